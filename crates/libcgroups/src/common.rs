@@ -1,4 +1,5 @@
 use std::{
+    collections::HashMap,
     fmt::{Debug, Display},
     fs::{self, File},
     io::{BufRead, BufReader, Write},
@@ -8,18 +9,23 @@ use std::{
 
 use anyhow::{bail, Context, Result};
 use nix::{
-    sys::statfs::{statfs, CGROUP2_SUPER_MAGIC, TMPFS_MAGIC},
-    unistd::Pid,
+    errno::Errno,
+    sys::{
+        signal::{kill, Signal},
+        statfs::{statfs, CGROUP2_SUPER_MAGIC, TMPFS_MAGIC},
+    },
+    unistd::{Gid, Pid, Uid},
 };
 use oci_spec::runtime::{
     LinuxDevice, LinuxDeviceBuilder, LinuxDeviceCgroup, LinuxDeviceCgroupBuilder, LinuxDeviceType,
-    LinuxResources,
+    LinuxRdma, LinuxResources,
 };
 
 use super::systemd;
 use super::v1;
 use super::v2;
 
+use super::oom::OomWatcher;
 use super::stats::Stats;
 
 pub const CGROUP_PROCS: &str = "cgroup.procs";
@@ -32,17 +38,88 @@ pub trait CgroupManager {
     /// Applies resource restrictions to the cgroup
     fn apply(&self, controller_opt: &ControllerOpt) -> Result<()>;
 
+    /// Adds the init process of a new container to the cgroup and applies the
+    /// resource restrictions. Managers which can only set some restrictions
+    /// when the cgroup is created, like the systemd manager, override this.
+    fn add_init_task(&self, pid: Pid, controller_opt: Option<&ControllerOpt>) -> Result<()> {
+        self.add_task(pid)?;
+        if let Some(controller_opt) = controller_opt {
+            self.apply(controller_opt)?;
+        }
+
+        Ok(())
+    }
+
     /// Removes the cgroup
     fn remove(&self) -> Result<()>;
 
     // Sets the freezer cgroup to the specified state
     fn freeze(&self, state: FreezerState) -> Result<()>;
 
+    /// Sets the freezer state of the cgroup and waits at most for the timeout
+    /// until the cgroup has reached the state
+    fn freeze_with_timeout(&self, state: FreezerState, timeout: Duration) -> Result<()>;
+
     /// Retrieve statistics for the cgroup
     fn stats(&self) -> Result<Stats>;
 
     // Gets the PIDs inside the cgroup
     fn get_all_pids(&self) -> Result<Vec<Pid>>;
+
+    /// Creates a watcher that reports when processes of the cgroup get killed
+    /// by the OOM killer
+    fn oom_watcher(&self) -> Result<OomWatcher>;
+
+    /// Returns the path of the perf_event cgroup, which can be used to scope
+    /// perf profiling to the processes of the cgroup
+    fn perf_event_path(&self) -> Result<PathBuf>;
+
+    /// Creates the cgroup without adding any task to it and returns the path of
+    /// the cgroup directory, if processes can be spawned directly into the cgroup
+    /// with clone3(CLONE_INTO_CGROUP). This is only possible on cgroup v2.
+    fn prepare_clone_into_cgroup(&self) -> Result<Option<PathBuf>> {
+        Ok(None)
+    }
+
+    /// Changes the owner of the cgroup directory and of the interface files that
+    /// are safe to delegate, so that the user is able to manage sub-cgroups
+    fn delegate(&self, _uid: Uid, _gid: Gid) -> Result<()> {
+        bail!("cgroup delegation is not supported by this cgroup manager")
+    }
+
+    /// Sends the signal to all processes inside the cgroup
+    fn kill_all(&self, signal: Signal) -> Result<()> {
+        signal_all_pids(self, signal)
+    }
+}
+
+/// Sends the signal to all processes of the cgroup. The cgroup is frozen in
+/// the meantime, so that no new processes can be forked which would escape
+/// the signal.
+pub fn signal_all_pids<C: CgroupManager + ?Sized>(manager: &C, signal: Signal) -> Result<()> {
+    if let Err(err) = manager.freeze(FreezerState::Frozen) {
+        log::warn!(
+            "failed to freeze cgroup before signaling processes: {:?}",
+            err
+        );
+    }
+
+    let result = manager.get_all_pids().and_then(|pids| {
+        for pid in pids {
+            match kill(pid, signal) {
+                // the process may have exited in the meantime
+                Ok(()) | Err(Errno::ESRCH) => {}
+                Err(err) => bail!("failed to send {} to {}: {}", signal, pid, err),
+            }
+        }
+        Ok(())
+    });
+
+    if let Err(err) = manager.freeze(FreezerState::Thawed) {
+        log::warn!("failed to thaw cgroup after signaling processes: {:?}", err);
+    }
+
+    result
 }
 
 #[derive(Debug)]
@@ -75,6 +152,29 @@ pub enum FreezerState {
     Thawed,
 }
 
+/// Time to wait for a cgroup to reach the requested freezer state
+pub const DEFAULT_FREEZER_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Returned if a cgroup did not reach the requested freezer state in time
+#[derive(Debug)]
+pub struct FreezerTimeoutError {
+    pub state: FreezerState,
+    pub timeout: Duration,
+}
+
+impl std::error::Error for FreezerTimeoutError {}
+
+impl Display for FreezerTimeoutError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "timeout of {} ms reached waiting for the cgroup to reach state {:?}",
+            self.timeout.as_millis(),
+            self.state
+        )
+    }
+}
+
 /// ControllerOpt is given all cgroup controller for applying cgroup configuration.
 #[derive(Clone, Debug)]
 pub struct ControllerOpt<'a> {
@@ -86,6 +186,12 @@ pub struct ControllerOpt<'a> {
     pub oom_score_adj: Option<i32>,
     /// FreezerState is given to freezer contoller for suspending process.
     pub freezer_state: Option<FreezerState>,
+    /// Annotations of the container, which can contain additional settings for
+    /// the cgroup manager (e.g. systemd unit properties).
+    pub annotations: Option<&'a HashMap<String, String>>,
+    /// Fail if resource restrictions cannot be applied because a controller is
+    /// not available. Otherwise these restrictions are skipped with a warning.
+    pub strict_resources: bool,
 }
 
 #[inline]
@@ -167,6 +273,46 @@ pub fn get_cgroup_setup() -> Result<CgroupSetup> {
     bail!("failed to detect cgroup setup");
 }
 
+/// Describes where the cgroup controllers are located on this system
+#[derive(Debug)]
+pub struct CgroupFeatures {
+    pub setup: CgroupSetup,
+    /// Cgroup v1 subsystems and their mount points
+    pub v1_controllers: HashMap<v1::ControllerType, PathBuf>,
+    /// Mount point of the unified hierarchy, if there is one
+    pub unified_mount: Option<PathBuf>,
+    /// Controllers which are attached to the unified hierarchy
+    pub v2_controllers: Vec<v2::controller_type::ControllerType>,
+}
+
+/// Detects the cgroup setup of the system and which controllers are attached
+/// to which hierarchy. On hybrid systems controllers can either be attached to
+/// a cgroup v1 hierarchy or to the unified hierarchy.
+pub fn get_cgroup_features() -> Result<CgroupFeatures> {
+    let setup = get_cgroup_setup()?;
+    let v1_controllers = match setup {
+        CgroupSetup::Legacy | CgroupSetup::Hybrid => v1::util::list_supported_mount_points()?,
+        CgroupSetup::Unified => HashMap::new(),
+    };
+
+    let unified_mount = match setup {
+        CgroupSetup::Hybrid | CgroupSetup::Unified => Some(v2::util::get_unified_mount_point()?),
+        CgroupSetup::Legacy => None,
+    };
+
+    let v2_controllers = match &unified_mount {
+        Some(mount) => v2::util::get_available_controllers(mount)?,
+        None => Vec::new(),
+    };
+
+    Ok(CgroupFeatures {
+        setup,
+        v1_controllers,
+        unified_mount,
+        v2_controllers,
+    })
+}
+
 pub fn create_cgroup_manager<P: Into<PathBuf>>(
     cgroup_path: P,
     systemd_cgroup: bool,
@@ -175,10 +321,18 @@ pub fn create_cgroup_manager<P: Into<PathBuf>>(
     let cgroup_setup = get_cgroup_setup()?;
 
     match cgroup_setup {
-        CgroupSetup::Legacy | CgroupSetup::Hybrid => {
+        CgroupSetup::Legacy => {
             log::info!("cgroup manager V1 will be used");
             Ok(Box::new(v1::manager::Manager::new(cgroup_path.into())?))
         }
+        CgroupSetup::Hybrid => {
+            log::info!("cgroup manager V1 with unified hierarchy will be used");
+            let unified_root = v2::util::get_unified_mount_point()?;
+            Ok(Box::new(v1::manager::Manager::hybrid(
+                cgroup_path.into(),
+                unified_root,
+            )?))
+        }
         CgroupSetup::Unified => {
             if systemd_cgroup {
                 if !systemd::booted() {
@@ -199,14 +353,29 @@ pub fn create_cgroup_manager<P: Into<PathBuf>>(
                 )?));
             }
             log::info!("cgroup manager V2 will be used");
+            let cgroup_path = resolve_cgroup_path(&cgroup_path.into(), systemd_cgroup)?;
             Ok(Box::new(v2::manager::Manager::new(
                 DEFAULT_CGROUP_ROOT.into(),
-                cgroup_path.into(),
+                cgroup_path,
             )?))
         }
     }
 }
 
+/// Resolves the cgroup path of a container to the path which is used by the cgroup
+/// manager. Relative paths of the cgroup v2 manager depend on the cgroup of the calling
+/// process, so the resolved path should be stored and used by later commands.
+pub fn resolve_cgroup_path(cgroup_path: &Path, systemd_cgroup: bool) -> Result<PathBuf> {
+    if systemd_cgroup || cgroup_path.is_absolute() {
+        return Ok(cgroup_path.to_owned());
+    }
+
+    match get_cgroup_setup()? {
+        CgroupSetup::Unified => v2::util::resolve_cgroup_path(cgroup_path),
+        CgroupSetup::Legacy | CgroupSetup::Hybrid => Ok(cgroup_path.to_owned()),
+    }
+}
+
 pub fn get_all_pids(path: &Path) -> Result<Vec<Pid>> {
     log::debug!("scan pids in folder: {:?}", path);
     let mut result = vec![];
@@ -239,6 +408,85 @@ where
     Ok(())
 }
 
+/// Weights of the nice levels -20 to 19 as used by the kernel scheduler
+/// (sched_prio_to_weight). The weight of nice 0 equals the default of 1024 cpu shares.
+const NICE_TO_SHARES: [u64; 40] = [
+    88761, 71755, 56483, 46273, 36291, 29154, 23254, 18705, 14949, 11916, 9548, 7620, 6100, 4904,
+    3906, 3121, 2501, 1991, 1586, 1277, 1024, 820, 655, 526, 423, 335, 272, 215, 172, 137, 110, 87,
+    70, 56, 45, 36, 29, 23, 18, 15,
+];
+const MIN_NICE: i32 = -20;
+const MAX_NICE: i32 = 19;
+const MIN_CPU_WEIGHT: u64 = 1;
+const MAX_CPU_WEIGHT: u64 = 10000;
+
+/// Converts cgroup v1 cpu shares to a cgroup v2 cpu weight. The kernel converts
+/// a cpu weight to shares as weight * 1024 / 100, so that the default weight of 100
+/// corresponds to the default of 1024 shares. This is the inverse of that conversion.
+/// A value of 0 means that no shares have been specified and is returned unchanged.
+/// # Example
+/// ```
+/// use libcgroups::common::convert_shares_to_weight;
+///
+/// assert_eq!(convert_shares_to_weight(1024), 100);
+/// ```
+pub fn convert_shares_to_weight(shares: u64) -> u64 {
+    if shares == 0 {
+        return 0;
+    }
+
+    let weight = (shares * 100 + 512) / 1024;
+    weight.clamp(MIN_CPU_WEIGHT, MAX_CPU_WEIGHT)
+}
+
+/// Converts a cgroup v2 cpu weight to cgroup v1 cpu shares
+pub fn convert_weight_to_shares(weight: u64) -> u64 {
+    weight.clamp(MIN_CPU_WEIGHT, MAX_CPU_WEIGHT) * 1024 / 100
+}
+
+/// Converts a nice value to the cpu weight which the kernel uses for it, e.g.
+/// when writing cpu.weight.nice
+pub fn convert_nice_to_weight(nice: i32) -> Result<u64> {
+    if !(MIN_NICE..=MAX_NICE).contains(&nice) {
+        bail!(
+            "nice value {} is out of range ({} to {})",
+            nice,
+            MIN_NICE,
+            MAX_NICE
+        );
+    }
+
+    Ok(convert_shares_to_weight(
+        NICE_TO_SHARES[(nice - MIN_NICE) as usize],
+    ))
+}
+
+/// Converts a cpu weight to the nice value with the closest scheduler weight,
+/// which is what the kernel reports in cpu.weight.nice
+pub fn convert_weight_to_nice(weight: u64) -> i32 {
+    let shares = convert_weight_to_shares(weight);
+    let closest = NICE_TO_SHARES
+        .iter()
+        .enumerate()
+        .min_by_key(|(_, &s)| (s as i64 - shares as i64).abs())
+        .map(|(i, _)| i)
+        .unwrap_or_default();
+
+    closest as i32 + MIN_NICE
+}
+
+/// Returns major and minor version of the running kernel
+pub(crate) fn kernel_version() -> Option<(u32, u32)> {
+    parse_kernel_version(nix::sys::utsname::uname().release())
+}
+
+fn parse_kernel_version(release: &str) -> Option<(u32, u32)> {
+    let mut parts = release.split(|c: char| !c.is_ascii_digit());
+    let major = parts.next()?.parse().ok()?;
+    let minor = parts.next()?.parse().ok()?;
+    Some((major, minor))
+}
+
 pub(crate) trait PathBufExt {
     fn join_safely<P: AsRef<Path>>(&self, path: P) -> Result<PathBuf>;
 }
@@ -246,6 +494,14 @@ pub(crate) trait PathBufExt {
 impl PathBufExt for PathBuf {
     fn join_safely<P: AsRef<Path>>(&self, path: P) -> Result<PathBuf> {
         let path = path.as_ref();
+        // the joined path must not escape from the cgroup root
+        if path
+            .components()
+            .any(|c| matches!(c, std::path::Component::ParentDir))
+        {
+            bail!("cgroup path {} must not contain '..'", path.display());
+        }
+
         if path.is_relative() {
             return Ok(self.join(path));
         }
@@ -257,6 +513,33 @@ impl PathBufExt for PathBuf {
     }
 }
 
+/// Returns the device rules of a container. Starting from a state in which all
+/// devices are denied, the default rules are applied before the rules of the
+/// spec, so that the spec can restrict the default devices. Rules which deny
+/// all devices at the start of the spec rules only reset to the initial state
+/// and are therefore dropped, as they would discard the default rules.
+pub(crate) fn container_device_rules(
+    devices: &Option<Vec<LinuxDeviceCgroup>>,
+) -> Vec<LinuxDeviceCgroup> {
+    let deny_all = LinuxDeviceCgroupBuilder::default()
+        .allow(false)
+        .typ(LinuxDeviceType::A)
+        .access("rwm")
+        .build()
+        .unwrap();
+    let is_deny_all =
+        |d: &&LinuxDeviceCgroup| !d.allow() && d.typ().unwrap_or_default() == LinuxDeviceType::A;
+
+    let mut rules = vec![deny_all];
+    rules.extend(default_devices().iter().map(LinuxDeviceCgroup::from));
+    rules.extend(default_allow_devices());
+    if let Some(devices) = devices {
+        rules.extend(devices.iter().skip_while(is_deny_all).cloned());
+    }
+
+    rules
+}
+
 pub(crate) fn default_allow_devices() -> Vec<LinuxDeviceCgroup> {
     vec![
         LinuxDeviceCgroupBuilder::default()
@@ -361,6 +644,33 @@ pub(crate) fn default_devices() -> Vec<LinuxDevice> {
     ]
 }
 
+/// Writes the rdma limits of each device to rdma.max. The format is the same for
+/// cgroup v1 and v2, e.g. 'mlx4_0 hca_handle=2 hca_object=2000'. Limits which are
+/// not specified are left untouched.
+pub(crate) fn apply_rdma_limits<'a, I>(path: &Path, rdma: I) -> Result<()>
+where
+    I: IntoIterator<Item = (&'a String, &'a LinuxRdma)>,
+{
+    for (device, limits) in rdma {
+        let mut entry = device.to_owned();
+        if let Some(hca_handles) = limits.hca_handles() {
+            entry.push_str(&format!(" hca_handle={}", hca_handles));
+        }
+        if let Some(hca_objects) = limits.hca_objects() {
+            entry.push_str(&format!(" hca_object={}", hca_objects));
+        }
+
+        if entry.len() == device.len() {
+            continue;
+        }
+
+        write_cgroup_file_str(path.join("rdma.max"), &entry)
+            .with_context(|| format!("failed to set rdma limits of {}", device))?;
+    }
+
+    Ok(())
+}
+
 /// Attempts to delete the path the requested number of times.
 pub(crate) fn delete_with_retry<P: AsRef<Path>, L: Into<Option<Duration>>>(
     path: P,
@@ -387,3 +697,106 @@ pub(crate) fn delete_with_retry<P: AsRef<Path>, L: Into<Option<Duration>>>(
 
     bail!("could not delete {:?}", path)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_kernel_version() {
+        assert_eq!(parse_kernel_version("5.15.0-91-generic"), Some((5, 15)));
+        assert_eq!(parse_kernel_version("4.19.0"), Some((4, 19)));
+        assert_eq!(parse_kernel_version("6.1"), Some((6, 1)));
+        assert_eq!(parse_kernel_version("invalid"), None);
+    }
+
+    #[test]
+    fn test_join_safely() -> Result<()> {
+        let root = PathBuf::from("/sys/fs/cgroup");
+        assert_eq!(
+            root.join_safely("/youki/test")?,
+            PathBuf::from("/sys/fs/cgroup/youki/test")
+        );
+        assert_eq!(
+            root.join_safely("youki/test")?,
+            PathBuf::from("/sys/fs/cgroup/youki/test")
+        );
+        assert!(root.join_safely("/youki/../../etc").is_err());
+        assert!(root.join_safely("../youki").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_resolve_cgroup_path_unchanged() -> Result<()> {
+        assert_eq!(
+            resolve_cgroup_path(Path::new("/youki/test"), false)?,
+            PathBuf::from("/youki/test")
+        );
+        assert_eq!(
+            resolve_cgroup_path(Path::new("system.slice:youki:test"), true)?,
+            PathBuf::from("system.slice:youki:test")
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_convert_shares_to_weight() {
+        assert_eq!(convert_shares_to_weight(0), 0);
+        assert_eq!(convert_shares_to_weight(2), 1);
+        assert_eq!(convert_shares_to_weight(1024), 100);
+        assert_eq!(convert_shares_to_weight(22000), 2148);
+        assert_eq!(convert_shares_to_weight(262144), 10000);
+    }
+
+    #[test]
+    fn test_convert_weight_to_shares() {
+        assert_eq!(convert_weight_to_shares(100), 1024);
+        assert_eq!(
+            convert_shares_to_weight(convert_weight_to_shares(2148)),
+            2148
+        );
+    }
+
+    #[test]
+    fn test_convert_nice() -> Result<()> {
+        assert_eq!(convert_nice_to_weight(0)?, 100);
+        assert_eq!(convert_nice_to_weight(-20)?, 8668);
+        assert_eq!(convert_nice_to_weight(19)?, 1);
+        assert!(convert_nice_to_weight(20).is_err());
+
+        assert_eq!(convert_weight_to_nice(100), 0);
+        assert_eq!(convert_weight_to_nice(convert_nice_to_weight(-10)?), -10);
+        assert_eq!(convert_weight_to_nice(1), 19);
+        Ok(())
+    }
+
+    #[test]
+    fn test_container_device_rules() -> Result<()> {
+        let deny_all = LinuxDeviceCgroupBuilder::default()
+            .allow(false)
+            .access("rwm")
+            .build()?;
+        let deny_null = LinuxDeviceCgroupBuilder::default()
+            .allow(false)
+            .typ(LinuxDeviceType::C)
+            .major(1)
+            .minor(3)
+            .access("rwm")
+            .build()?;
+        let defaults = default_devices().len() + default_allow_devices().len();
+
+        let rules = container_device_rules(&None);
+        assert_eq!(rules.len(), defaults + 1);
+        assert!(!rules[0].allow());
+        assert_eq!(rules[0].typ(), Some(LinuxDeviceType::A));
+
+        // the leading deny all rule would discard the default rules
+        let rules = container_device_rules(&Some(vec![deny_all.clone(), deny_null.clone()]));
+        assert_eq!(rules.len(), defaults + 2);
+        assert_eq!(rules.last(), Some(&deny_null));
+
+        let rules = container_device_rules(&Some(vec![deny_null.clone(), deny_all.clone()]));
+        assert_eq!(rules[defaults + 1..], [deny_null, deny_all]);
+        Ok(())
+    }
+}
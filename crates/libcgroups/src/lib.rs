@@ -7,6 +7,7 @@ extern crate quickcheck;
 mod test;
 
 pub mod common;
+pub mod oom;
 pub mod stats;
 pub mod systemd;
 pub mod test_manager;
@@ -0,0 +1,267 @@
+//! Notifications about processes of a cgroup being killed by the OOM killer.
+//! On cgroup v1 the kernel notifies an eventfd that has been registered through
+//! cgroup.event_control, while on cgroup v2 the oom_kill and oom_group_kill
+//! counters in memory.events are watched with inotify.
+use std::{
+    fs::File,
+    os::unix::io::{AsRawFd, RawFd},
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Context, Result};
+use nix::{
+    sys::{
+        eventfd::{eventfd, EfdFlags},
+        inotify::{AddWatchFlags, InitFlags, Inotify},
+    },
+    unistd,
+};
+
+use crate::{common, stats};
+
+const CGROUP_EVENT_CONTROL: &str = "cgroup.event_control";
+const MEMORY_OOM_CONTROL: &str = "memory.oom_control";
+const MEMORY_EVENTS: &str = "memory.events";
+
+/// Processes of a cgroup which have been killed by the OOM killer
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct OomEvent {
+    /// Number of processes that have been killed
+    pub kills: u64,
+    /// Indicates that all processes of the cgroup have been killed together,
+    /// because memory.oom.group is set. Only reported on cgroup v2.
+    pub group_kill: bool,
+}
+
+pub struct OomWatcher {
+    kind: WatcherKind,
+}
+
+enum WatcherKind {
+    V1 {
+        cgroup_path: PathBuf,
+        event_fd: RawFd,
+        // needs to stay open for as long as the eventfd is registered
+        _oom_control: File,
+    },
+    V2 {
+        events_path: PathBuf,
+        inotify: Inotify,
+        oom_kills: u64,
+        oom_group_kills: u64,
+    },
+}
+
+impl OomWatcher {
+    /// Watches the cgroup v1 memory cgroup at the specified path
+    pub fn v1(cgroup_path: &Path) -> Result<Self> {
+        let oom_control = File::open(cgroup_path.join(MEMORY_OOM_CONTROL)).with_context(|| {
+            format!("failed to open {:?}", cgroup_path.join(MEMORY_OOM_CONTROL))
+        })?;
+        let event_fd = eventfd(0, EfdFlags::EFD_CLOEXEC).context("failed to create eventfd")?;
+
+        if let Err(err) = common::write_cgroup_file_str(
+            cgroup_path.join(CGROUP_EVENT_CONTROL),
+            &format!("{} {}", event_fd, oom_control.as_raw_fd()),
+        ) {
+            let _ = unistd::close(event_fd);
+            return Err(err.context("failed to register for oom notifications"));
+        }
+
+        Ok(Self {
+            kind: WatcherKind::V1 {
+                cgroup_path: cgroup_path.to_owned(),
+                event_fd,
+                _oom_control: oom_control,
+            },
+        })
+    }
+
+    /// Watches the cgroup v2 cgroup at the specified path
+    pub fn v2(cgroup_path: &Path) -> Result<Self> {
+        let events_path = cgroup_path.join(MEMORY_EVENTS);
+        // only oom kills which happen from now on should be reported
+        let (oom_kills, oom_group_kills) = Self::read_oom_kills(&events_path)?;
+        let inotify = Inotify::init(InitFlags::IN_CLOEXEC).context("failed to init inotify")?;
+        if let Err(err) = inotify.add_watch(&events_path, AddWatchFlags::IN_MODIFY) {
+            let _ = unistd::close(inotify.as_raw_fd());
+            return Err(err).with_context(|| format!("failed to watch {:?}", events_path));
+        }
+
+        Ok(Self {
+            kind: WatcherKind::V2 {
+                events_path,
+                inotify,
+                oom_kills,
+                oom_group_kills,
+            },
+        })
+    }
+
+    /// Blocks until processes of the cgroup have been killed by the OOM killer and
+    /// returns the oom kills. Returns None once the cgroup has been removed.
+    pub fn wait(&mut self) -> Result<Option<OomEvent>> {
+        match &mut self.kind {
+            WatcherKind::V1 {
+                cgroup_path,
+                event_fd,
+                ..
+            } => {
+                let mut buf = [0; 8];
+                unistd::read(*event_fd, &mut buf).context("failed to read from eventfd")?;
+                // the eventfd is also notified when the cgroup is removed
+                if !cgroup_path.exists() {
+                    return Ok(None);
+                }
+
+                Ok(Some(OomEvent {
+                    kills: u64::from_ne_bytes(buf),
+                    group_kill: false,
+                }))
+            }
+            WatcherKind::V2 {
+                events_path,
+                inotify,
+                oom_kills,
+                oom_group_kills,
+            } => loop {
+                let (kills, group_kills) = match Self::read_oom_kills(events_path) {
+                    Ok(current) => current,
+                    Err(_) if !events_path.exists() => return Ok(None),
+                    Err(err) => return Err(err),
+                };
+
+                if kills > *oom_kills || group_kills > *oom_group_kills {
+                    let event = OomEvent {
+                        kills: kills.saturating_sub(*oom_kills),
+                        group_kill: group_kills > *oom_group_kills,
+                    };
+                    *oom_kills = kills;
+                    *oom_group_kills = group_kills;
+                    return Ok(Some(event));
+                }
+
+                let events = inotify
+                    .read_events()
+                    .context("failed to read inotify events")?;
+                if events
+                    .iter()
+                    .any(|e| e.mask.contains(AddWatchFlags::IN_IGNORED))
+                {
+                    return Ok(None);
+                }
+            },
+        }
+    }
+
+    /// Returns the number of processes of the cgroup, which have been killed by
+    /// the OOM killer since the cgroup has been created. Requires Linux 4.13 on
+    /// cgroup v1.
+    pub fn total_kills(&self) -> Result<u64> {
+        match &self.kind {
+            WatcherKind::V1 { cgroup_path, .. } => {
+                let oom_control =
+                    stats::parse_flat_keyed_data(&cgroup_path.join(MEMORY_OOM_CONTROL))?;
+                Ok(oom_control.get("oom_kill").copied().unwrap_or_default())
+            }
+            WatcherKind::V2 { events_path, .. } => Ok(Self::read_oom_kills(events_path)?.0),
+        }
+    }
+
+    /// Returns the number of processes killed by the OOM killer and the number of
+    /// times the whole cgroup has been killed because of memory.oom.group
+    fn read_oom_kills(events_path: &Path) -> Result<(u64, u64)> {
+        let events = stats::parse_flat_keyed_data(events_path)?;
+        Ok((
+            events.get("oom_kill").copied().unwrap_or_default(),
+            // oom_group_kill is only reported since Linux 5.12
+            events.get("oom_group_kill").copied().unwrap_or_default(),
+        ))
+    }
+}
+
+impl Drop for OomWatcher {
+    fn drop(&mut self) {
+        let fd = match &self.kind {
+            WatcherKind::V1 { event_fd, .. } => *event_fd,
+            WatcherKind::V2 { inotify, .. } => inotify.as_raw_fd(),
+        };
+
+        let _ = unistd::close(fd);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test::{create_temp_dir, set_fixture};
+    use std::{thread, time::Duration};
+
+    #[test]
+    fn test_v2_total_kills() -> Result<()> {
+        let tmp = create_temp_dir("test_v2_total_kills")?;
+        set_fixture(
+            &tmp,
+            MEMORY_EVENTS,
+            "low 0\nhigh 0\nmax 2\noom 1\noom_kill 2\n",
+        )?;
+        let watcher = OomWatcher::v2(&tmp)?;
+        assert_eq!(watcher.total_kills()?, 2);
+        Ok(())
+    }
+
+    #[test]
+    fn test_v2_oom_kill_reported() -> Result<()> {
+        let tmp = create_temp_dir("test_v2_oom_kill_reported")?;
+        set_fixture(
+            &tmp,
+            MEMORY_EVENTS,
+            "low 0\nhigh 0\nmax 2\noom 1\noom_kill 1\n",
+        )?;
+        let mut watcher = OomWatcher::v2(&tmp)?;
+
+        let events_path = tmp.join(MEMORY_EVENTS);
+        let handle = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(50));
+            std::fs::write(events_path, "low 0\nhigh 0\nmax 5\noom 3\noom_kill 3\n")
+        });
+
+        assert_eq!(
+            watcher.wait()?,
+            Some(OomEvent {
+                kills: 2,
+                group_kill: false
+            })
+        );
+        handle.join().unwrap()?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_v2_oom_group_kill_reported() -> Result<()> {
+        let tmp = create_temp_dir("test_v2_oom_group_kill_reported")?;
+        let events_path = set_fixture(&tmp, MEMORY_EVENTS, "oom_kill 0\noom_group_kill 0\n")?;
+        let mut watcher = OomWatcher::v2(&tmp)?;
+        std::fs::write(events_path, "oom_kill 3\noom_group_kill 1\n")?;
+
+        assert_eq!(
+            watcher.wait()?,
+            Some(OomEvent {
+                kills: 3,
+                group_kill: true
+            })
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_v2_cgroup_removed() -> Result<()> {
+        let tmp = create_temp_dir("test_v2_cgroup_removed")?;
+        let events_path = set_fixture(&tmp, MEMORY_EVENTS, "oom_kill 0\n")?;
+        let mut watcher = OomWatcher::v2(&tmp)?;
+        std::fs::remove_file(events_path)?;
+
+        assert_eq!(watcher.wait()?, None);
+        Ok(())
+    }
+}
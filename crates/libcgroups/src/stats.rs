@@ -1,6 +1,19 @@
 use anyhow::{bail, Context, Result};
+use nix::{
+    dir::Dir,
+    fcntl::{self, OFlag},
+    sys::stat::Mode,
+    unistd::Pid,
+};
 use serde::Serialize;
-use std::{collections::HashMap, fmt::Display, fs, path::Path};
+use std::{
+    collections::HashMap,
+    fmt::Display,
+    fs::{self, File},
+    io::Read,
+    os::unix::io::{AsRawFd, FromRawFd},
+    path::{Path, PathBuf},
+};
 
 use super::common;
 
@@ -10,6 +23,14 @@ pub trait StatsProvider {
     fn stats(cgroup_path: &Path) -> Result<Self::Stats>;
 }
 
+/// Collects statistics from cgroup files that have been read in a batch
+pub trait BatchedStatsProvider: StatsProvider {
+    /// Names of the files that are needed to collect the statistics
+    fn stats_files() -> Result<Vec<String>>;
+
+    fn stats_from_files(files: &CgroupFiles) -> Result<Self::Stats>;
+}
+
 /// Reports the statistics for a cgroup
 #[derive(Debug, Serialize)]
 pub struct Stats {
@@ -23,6 +44,12 @@ pub struct Stats {
     pub blkio: BlkioStats,
     /// Memory statistics for the cgroup
     pub memory: MemoryStats,
+    /// Usage of miscellaneous resources, e.g. sgx_epc, keyed by resource name (cgroup v2 only)
+    pub misc: HashMap<String, u64>,
+    /// Rdma statistics for the cgroup keyed by device name
+    pub rdma: HashMap<String, RdmaStats>,
+    /// Network statistics of the network namespace of the cgroup keyed by interface name
+    pub network: HashMap<String, NetworkStats>,
 }
 
 impl Default for Stats {
@@ -33,6 +60,9 @@ impl Default for Stats {
             hugetlb: HashMap::new(),
             blkio: BlkioStats::default(),
             memory: MemoryStats::default(),
+            misc: HashMap::new(),
+            rdma: HashMap::new(),
+            network: HashMap::new(),
         }
     }
 }
@@ -44,6 +74,8 @@ pub struct CpuStats {
     pub usage: CpuUsage,
     /// Cpu Throttling statistics for the cgroup
     pub throttling: CpuThrottling,
+    /// Cpu pressure stall information for the cgroup (cgroup v2 only)
+    pub psi: Option<PsiStats>,
 }
 
 impl Default for CpuStats {
@@ -51,6 +83,7 @@ impl Default for CpuStats {
         Self {
             usage: CpuUsage::default(),
             throttling: CpuThrottling::default(),
+            psi: None,
         }
     }
 }
@@ -123,6 +156,8 @@ pub struct MemoryStats {
     pub hierarchy: bool,
     /// Various memory statistics
     pub stats: HashMap<String, u64>,
+    /// Memory pressure stall information for the cgroup (cgroup v2 only)
+    pub psi: Option<PsiStats>,
 }
 
 impl Default for MemoryStats {
@@ -135,6 +170,7 @@ impl Default for MemoryStats {
             cache: 0,
             hierarchy: false,
             stats: HashMap::default(),
+            psi: None,
         }
     }
 }
@@ -181,8 +217,38 @@ impl Default for PidStats {
     }
 }
 
+/// Reports rdma resource usage of a device
+#[derive(Debug, Default, PartialEq, Eq, Serialize)]
+pub struct RdmaStats {
+    /// Number of used hca handles
+    pub hca_handles: u32,
+    /// Number of used hca objects
+    pub hca_objects: u32,
+}
+
+/// Reports the traffic of a network interface
+#[derive(Debug, Default, PartialEq, Eq, Serialize)]
+pub struct NetworkStats {
+    /// Number of received bytes
+    pub rx_bytes: u64,
+    /// Number of received packets
+    pub rx_packets: u64,
+    /// Number of receive errors
+    pub rx_errors: u64,
+    /// Number of dropped incoming packets
+    pub rx_dropped: u64,
+    /// Number of transmitted bytes
+    pub tx_bytes: u64,
+    /// Number of transmitted packets
+    pub tx_packets: u64,
+    /// Number of transmit errors
+    pub tx_errors: u64,
+    /// Number of dropped outgoing packets
+    pub tx_dropped: u64,
+}
+
 /// Reports block io stats for a cgroup
-#[derive(Debug, PartialEq, Eq, Serialize)]
+#[derive(Debug, PartialEq, Serialize)]
 pub struct BlkioStats {
     // Number of bytes transfered to/from a device by the cgroup
     pub service_bytes: Vec<BlkioDeviceStat>,
@@ -200,6 +266,8 @@ pub struct BlkioStats {
     pub queued: Vec<BlkioDeviceStat>,
     // Number of requests merged into requests for I/O operations
     pub merged: Vec<BlkioDeviceStat>,
+    // Io pressure stall information for the cgroup (cgroup v2 only)
+    pub psi: Option<PsiStats>,
 }
 
 impl Default for BlkioStats {
@@ -213,6 +281,7 @@ impl Default for BlkioStats {
             wait_time: Vec::new(),
             queued: Vec::new(),
             merged: Vec::new(),
+            psi: None,
         }
     }
 }
@@ -265,6 +334,229 @@ impl Default for HugeTlbStats {
     }
 }
 
+/// Reports pressure stall information (PSI) for a resource
+/// See https://www.kernel.org/doc/html/latest/accounting/psi.html
+#[derive(Debug, PartialEq, Serialize)]
+pub struct PsiStats {
+    /// Time in which at least some tasks were stalled on the resource
+    pub some: PsiData,
+    /// Time in which all non-idle tasks were stalled on the resource
+    pub full: PsiData,
+}
+
+impl Default for PsiStats {
+    fn default() -> Self {
+        Self {
+            some: PsiData::default(),
+            full: PsiData::default(),
+        }
+    }
+}
+
+/// Reports the stall time for one type of pressure
+#[derive(Debug, PartialEq, Serialize)]
+pub struct PsiData {
+    /// Share of time stalled in percent over the last 10 seconds
+    pub avg10: f64,
+    /// Share of time stalled in percent over the last 60 seconds
+    pub avg60: f64,
+    /// Share of time stalled in percent over the last 300 seconds
+    pub avg300: f64,
+    /// Total stall time in microseconds
+    pub total: u64,
+}
+
+impl Default for PsiData {
+    fn default() -> Self {
+        Self {
+            avg10: 0.0,
+            avg60: 0.0,
+            avg300: 0.0,
+            total: 0,
+        }
+    }
+}
+
+/// Collects the statistics of the cgroup at cgroup_path, which is relative to the
+/// root of the cgroup hierarchy. The cgroup setup of the system is detected
+/// automatically. Network statistics are read from the network namespace of the
+/// processes in the cgroup.
+/// # Example
+/// ```no_run
+/// use libcgroups::stats::get_stats;
+///
+/// let stats = get_stats("/youki/container").unwrap();
+/// println!("memory usage: {}", stats.memory.memory.usage);
+/// ```
+pub fn get_stats<P: Into<PathBuf>>(cgroup_path: P) -> Result<Stats> {
+    let cgroup_path = cgroup_path.into();
+    let manager = common::create_cgroup_manager(&cgroup_path, false, "")
+        .with_context(|| format!("failed to create cgroup manager for {:?}", cgroup_path))?;
+    let mut stats = manager
+        .stats()
+        .with_context(|| format!("failed to get stats of cgroup {:?}", cgroup_path))?;
+
+    // all processes of a container share the same network namespace
+    if let Some(pid) = manager.get_all_pids()?.first() {
+        stats.network = network_stats(*pid)?;
+    }
+
+    Ok(stats)
+}
+
+/// Returns the traffic of the network interfaces in the network namespace of
+/// the process
+pub fn network_stats(pid: Pid) -> Result<HashMap<String, NetworkStats>> {
+    parse_net_dev(&Path::new("/proc").join(pid.to_string()).join("net/dev"))
+}
+
+fn parse_net_dev(net_dev: &Path) -> Result<HashMap<String, NetworkStats>> {
+    let content =
+        fs::read_to_string(net_dev).with_context(|| format!("failed to read {:?}", net_dev))?;
+    let mut stats = HashMap::new();
+    // the first two lines are headers
+    for line in content.lines().skip(2) {
+        let (interface, values) = line
+            .split_once(':')
+            .with_context(|| format!("invalid entry {} in {:?}", line, net_dev))?;
+        let values = values
+            .split_ascii_whitespace()
+            .map(parse_value)
+            .collect::<Result<Vec<u64>>>()?;
+        if values.len() < 16 {
+            bail!("invalid entry {} in {:?}", line, net_dev);
+        }
+
+        stats.insert(
+            interface.trim().to_owned(),
+            NetworkStats {
+                rx_bytes: values[0],
+                rx_packets: values[1],
+                rx_errors: values[2],
+                rx_dropped: values[3],
+                tx_bytes: values[8],
+                tx_packets: values[9],
+                tx_errors: values[10],
+                tx_dropped: values[11],
+            },
+        );
+    }
+
+    Ok(stats)
+}
+
+/// Contents of the interface files of a cgroup, which have been read in a single
+/// pass. The files are opened relative to a file descriptor of the cgroup directory,
+/// so that the path does not need to be resolved again for every file. This keeps
+/// the overhead of collecting statistics low, even if this is done periodically
+/// for a large number of containers.
+#[derive(Debug, Default)]
+pub struct CgroupFiles {
+    path: PathBuf,
+    contents: HashMap<String, String>,
+}
+
+impl CgroupFiles {
+    /// Reads the files of the cgroup with the given names. Files that cannot be
+    /// read are left out, which is the case for write only files or pressure
+    /// files if psi is disabled.
+    pub fn read<S: AsRef<str>>(cgroup_path: &Path, names: &[S]) -> Result<Self> {
+        let dir = Dir::open(
+            cgroup_path,
+            OFlag::O_RDONLY | OFlag::O_DIRECTORY | OFlag::O_CLOEXEC,
+            Mode::empty(),
+        )
+        .with_context(|| format!("failed to open {:?}", cgroup_path))?;
+
+        let mut contents = HashMap::with_capacity(names.len());
+        for name in names {
+            let name = name.as_ref();
+            match Self::read_at(&dir, name) {
+                Ok(content) => {
+                    contents.insert(name.to_owned(), content);
+                }
+                Err(err) => log::debug!("skipping {:?}: {}", cgroup_path.join(name), err),
+            }
+        }
+
+        Ok(Self {
+            path: cgroup_path.to_owned(),
+            contents,
+        })
+    }
+
+    fn read_at(dir: &Dir, name: &str) -> std::io::Result<String> {
+        let fd = fcntl::openat(
+            dir.as_raw_fd(),
+            name,
+            OFlag::O_RDONLY | OFlag::O_CLOEXEC,
+            Mode::empty(),
+        )?;
+        // the file takes ownership of the fd and closes it when dropped
+        let mut file = unsafe { File::from_raw_fd(fd) };
+        let mut content = String::new();
+        file.read_to_string(&mut content)?;
+        Ok(content)
+    }
+
+    /// Returns the path of the cgroup
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Returns if the file has been read
+    pub fn contains(&self, name: &str) -> bool {
+        self.contents.contains_key(name)
+    }
+
+    /// Returns the content of the file
+    pub fn content(&self, name: &str) -> Result<&str> {
+        match self.contents.get(name) {
+            Some(content) => Ok(content),
+            None => bail!("failed to read {:?}", self.path.join(name)),
+        }
+    }
+
+    /// Parses a single valued file to an u64
+    pub fn single_value(&self, name: &str) -> Result<u64> {
+        parse_single_value_str(self.content(name)?, &self.path.join(name))
+    }
+
+    /// Parses a file that is structed according to the flat keyed format
+    pub fn flat_keyed_data(&self, name: &str) -> Result<HashMap<String, u64>> {
+        parse_flat_keyed_str(self.content(name)?, &self.path.join(name))
+    }
+
+    /// Parses a file that is structed according to the nested keyed format
+    pub fn nested_keyed_data(&self, name: &str) -> Result<HashMap<String, Vec<String>>> {
+        parse_nested_keyed_str(self.content(name)?, &self.path.join(name))
+    }
+
+    /// Parses a pressure file. Returns None if the kernel does not provide
+    /// pressure stall information
+    pub fn psi_stats(&self, name: &str) -> Result<Option<PsiStats>> {
+        match self.contents.get(name) {
+            Some(content) => parse_psi_str(content, &self.path.join(name)).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    /// Returns cgroup pid statistics
+    pub fn pid_stats(&self) -> Result<PidStats> {
+        parse_pid_stats(self.content("pids.current")?, self.content("pids.max")?)
+    }
+
+    /// Returns the rdma resource usage per device. Returns no statistics if the
+    /// rdma controller is not available
+    pub fn rdma_stats(&self) -> Result<HashMap<String, RdmaStats>> {
+        if !self.contains("rdma.current") {
+            return Ok(HashMap::new());
+        }
+
+        parse_rdma_stats(self.nested_keyed_data("rdma.current")?)
+    }
+}
+
 /// Reports which hugepage sizes are supported by the system
 pub fn supported_page_sizes() -> Result<Vec<String>> {
     let mut sizes = Vec::new();
@@ -329,6 +621,10 @@ pub fn parse_value(value: &str) -> Result<u64> {
 /// ```
 pub fn parse_single_value(file_path: &Path) -> Result<u64> {
     let value = common::read_cgroup_file(file_path)?;
+    parse_single_value_str(&value, file_path)
+}
+
+fn parse_single_value_str(value: &str, file_path: &Path) -> Result<u64> {
     let value = value.trim();
     if value == "max" {
         return Ok(u64::MAX);
@@ -345,8 +641,12 @@ pub fn parse_single_value(file_path: &Path) -> Result<u64> {
 
 /// Parses a file that is structed according to the flat keyed format
 pub fn parse_flat_keyed_data(file_path: &Path) -> Result<HashMap<String, u64>> {
-    let mut stats = HashMap::new();
     let keyed_data = common::read_cgroup_file(file_path)?;
+    parse_flat_keyed_str(&keyed_data, file_path)
+}
+
+fn parse_flat_keyed_str(keyed_data: &str, file_path: &Path) -> Result<HashMap<String, u64>> {
+    let mut stats = HashMap::new();
     for entry in keyed_data.lines() {
         let entry_fields: Vec<&str> = entry.split_ascii_whitespace().collect();
         if entry_fields.len() != 2 {
@@ -373,8 +673,15 @@ pub fn parse_flat_keyed_data(file_path: &Path) -> Result<HashMap<String, u64>> {
 
 /// Parses a file that is structed according to the nested keyed format
 pub fn parse_nested_keyed_data(file_path: &Path) -> Result<HashMap<String, Vec<String>>> {
-    let mut stats: HashMap<String, Vec<String>> = HashMap::new();
     let keyed_data = common::read_cgroup_file(file_path)?;
+    parse_nested_keyed_str(&keyed_data, file_path)
+}
+
+fn parse_nested_keyed_str(
+    keyed_data: &str,
+    file_path: &Path,
+) -> Result<HashMap<String, Vec<String>>> {
+    let mut stats: HashMap<String, Vec<String>> = HashMap::new();
     for entry in keyed_data.lines() {
         let entry_fields: Vec<&str> = entry.split_ascii_whitespace().collect();
         if entry_fields.len() < 2 || !entry_fields[1..].iter().all(|p| p.contains('=')) {
@@ -411,18 +718,68 @@ pub fn parse_device_number(device: &str) -> Result<(u64, u64)> {
     Ok((numbers[0].parse()?, numbers[1].parse()?))
 }
 
+/// Parses a pressure file (e.g. cpu.pressure). Returns None if the kernel
+/// does not provide pressure stall information
+pub fn psi_stats(psi_file: &Path) -> Result<Option<PsiStats>> {
+    if !psi_file.exists() {
+        return Ok(None);
+    }
+
+    let content = match common::read_cgroup_file(psi_file) {
+        Ok(content) => content,
+        Err(err) => {
+            // the file exists but cannot be read if psi is disabled on the kernel command line
+            log::debug!("pressure stall information is not available: {:?}", err);
+            return Ok(None);
+        }
+    };
+
+    parse_psi_str(&content, psi_file).map(Some)
+}
+
+fn parse_psi_str(content: &str, psi_file: &Path) -> Result<PsiStats> {
+    let mut stats = PsiStats::default();
+    for line in content.lines() {
+        let mut fields = line.split_ascii_whitespace();
+        let data = match fields.next() {
+            Some("some") => &mut stats.some,
+            Some("full") => &mut stats.full,
+            _ => continue,
+        };
+
+        for field in fields {
+            let (key, value) = field
+                .split_once('=')
+                .with_context(|| format!("invalid entry {} in {}", field, psi_file.display()))?;
+            let parse_err = || format!("failed to parse {} from {}", field, psi_file.display());
+            match key {
+                "avg10" => data.avg10 = value.parse().with_context(parse_err)?,
+                "avg60" => data.avg60 = value.parse().with_context(parse_err)?,
+                "avg300" => data.avg300 = value.parse().with_context(parse_err)?,
+                "total" => data.total = value.parse().with_context(parse_err)?,
+                _ => continue,
+            }
+        }
+    }
+
+    Ok(stats)
+}
+
 /// Returns cgroup pid statistics
 pub fn pid_stats(cgroup_path: &Path) -> Result<PidStats> {
-    let mut stats = PidStats::default();
-
     let current = common::read_cgroup_file(cgroup_path.join("pids.current"))?;
+    let limit = common::read_cgroup_file(cgroup_path.join("pids.max"))?;
+    parse_pid_stats(&current, &limit)
+}
+
+fn parse_pid_stats(current: &str, limit: &str) -> Result<PidStats> {
+    let mut stats = PidStats::default();
     stats.current = current
         .trim()
         .parse()
         .context("failed to parse current pids")?;
 
-    let limit =
-        common::read_cgroup_file(cgroup_path.join("pids.max")).map(|l| l.trim().to_owned())?;
+    let limit = limit.trim();
     if limit != "max" {
         stats.limit = limit.parse().context("failed to parse pids limit")?;
     }
@@ -430,12 +787,90 @@ pub fn pid_stats(cgroup_path: &Path) -> Result<PidStats> {
     Ok(stats)
 }
 
+/// Returns the rdma resource usage per device. Returns no statistics if the rdma
+/// controller is not available
+pub fn rdma_stats(cgroup_path: &Path) -> Result<HashMap<String, RdmaStats>> {
+    let current = cgroup_path.join("rdma.current");
+    if !current.exists() {
+        return Ok(HashMap::new());
+    }
+
+    parse_rdma_stats(parse_nested_keyed_data(&current)?)
+}
+
+fn parse_rdma_stats(
+    keyed_data: HashMap<String, Vec<String>>,
+) -> Result<HashMap<String, RdmaStats>> {
+    let mut stats = HashMap::new();
+    // each line has the format 'mlx4_0 hca_handle=2 hca_object=2000'
+    for (device, values) in keyed_data {
+        let mut device_stats = RdmaStats::default();
+        for value in values {
+            let (key, value) = value.split_once('=').with_context(|| {
+                format!(
+                    "rdma value {} of device {} does not conform to 'key=value'",
+                    value, device
+                )
+            })?;
+            let value = value
+                .parse()
+                .with_context(|| format!("failed to parse {} of device {}", key, device))?;
+            match key {
+                "hca_handle" => device_stats.hca_handles = value,
+                "hca_object" => device_stats.hca_objects = value,
+                _ => continue,
+            }
+        }
+
+        stats.insert(device, device_stats);
+    }
+
+    Ok(stats)
+}
+
 #[cfg(test)]
 mod tests {
     use crate::test::{create_temp_dir, set_fixture};
 
     use super::*;
 
+    #[test]
+    fn test_cgroup_files() -> Result<()> {
+        let tmp = create_temp_dir("test_cgroup_files")?;
+        set_fixture(&tmp, "memory.current", "1024\n")?;
+        set_fixture(&tmp, "memory.stat", "anon 10\nfile 20\n")?;
+        set_fixture(&tmp, "pids.current", "5\n")?;
+        set_fixture(&tmp, "pids.max", "max\n")?;
+        set_fixture(&tmp, "cpu.stat", "usage_usec 100\n")?;
+        fs::create_dir(tmp.join("memory.child"))?;
+
+        let files = CgroupFiles::read(
+            &tmp,
+            &[
+                "memory.current",
+                "memory.stat",
+                "memory.max",
+                "memory.pressure",
+                "memory.child",
+                "pids.current",
+                "pids.max",
+            ],
+        )?;
+        assert_eq!(files.single_value("memory.current")?, 1024);
+        assert_eq!(
+            files.flat_keyed_data("memory.stat")?,
+            HashMap::from([("anon".to_owned(), 10), ("file".to_owned(), 20)])
+        );
+        assert_eq!(files.pid_stats()?.current, 5);
+        assert_eq!(files.pid_stats()?.limit, 0);
+        assert!(!files.contains("cpu.stat"));
+        assert!(!files.contains("memory.child"));
+        assert!(!files.contains("memory.cur"));
+        assert!(files.content("memory.max").is_err());
+        assert!(files.psi_stats("memory.pressure")?.is_none());
+        Ok(())
+    }
+
     #[test]
     fn test_supported_page_sizes_gigabyte() {
         let page_size = extract_page_size("hugepages-1048576kB").unwrap();
@@ -612,6 +1047,58 @@ mod tests {
         assert!(result.is_err());
     }
 
+    #[test]
+    fn test_parse_psi_stats() {
+        let tmp = create_temp_dir("test_parse_psi_stats").unwrap();
+        let file_content = [
+            "some avg10=1.50 avg60=0.25 avg300=0.00 total=123456",
+            "full avg10=0.00 avg60=0.10 avg300=0.01 total=789",
+        ]
+        .join("\n");
+        let file_path = set_fixture(&tmp, "memory.pressure", &file_content).unwrap();
+
+        let actual = psi_stats(&file_path).unwrap();
+        let expected = PsiStats {
+            some: PsiData {
+                avg10: 1.5,
+                avg60: 0.25,
+                avg300: 0.0,
+                total: 123456,
+            },
+            full: PsiData {
+                avg10: 0.0,
+                avg60: 0.1,
+                avg300: 0.01,
+                total: 789,
+            },
+        };
+        assert_eq!(actual, Some(expected));
+    }
+
+    #[test]
+    fn test_parse_psi_stats_without_full() {
+        let tmp = create_temp_dir("test_parse_psi_stats_without_full").unwrap();
+        let file_content = "some avg10=0.00 avg60=0.00 avg300=0.00 total=42";
+        let file_path = set_fixture(&tmp, "cpu.pressure", file_content).unwrap();
+
+        let actual = psi_stats(&file_path).unwrap().unwrap();
+        assert_eq!(actual.some.total, 42);
+        assert_eq!(actual.full, PsiData::default());
+    }
+
+    #[test]
+    fn test_parse_psi_stats_missing_file() {
+        let tmp = create_temp_dir("test_parse_psi_stats_missing_file").unwrap();
+        assert_eq!(psi_stats(&tmp.join("io.pressure")).unwrap(), None);
+    }
+
+    #[test]
+    fn test_parse_invalid_psi_stats() {
+        let tmp = create_temp_dir("test_parse_invalid_psi_stats").unwrap();
+        let file_path = set_fixture(&tmp, "io.pressure", "some avg10=abc total=1").unwrap();
+        assert!(psi_stats(&file_path).is_err());
+    }
+
     #[test]
     fn test_parse_device_number() {
         let (major, minor) = parse_device_number("8:0").unwrap();
@@ -623,4 +1110,65 @@ mod tests {
         let result = parse_device_number("a:b");
         assert!(result.is_err());
     }
+
+    #[test]
+    fn test_rdma_stats() {
+        let tmp = create_temp_dir("test_rdma_stats").expect("create temp dir");
+        set_fixture(
+            &tmp,
+            "rdma.current",
+            "mlx4_0 hca_handle=2 hca_object=2000\nocrdma1 hca_handle=3 hca_object=0\n",
+        )
+        .expect("create rdma.current file");
+
+        let actual = rdma_stats(&tmp).expect("get rdma stats");
+
+        let mut expected = HashMap::new();
+        expected.insert(
+            "mlx4_0".to_owned(),
+            RdmaStats {
+                hca_handles: 2,
+                hca_objects: 2000,
+            },
+        );
+        expected.insert(
+            "ocrdma1".to_owned(),
+            RdmaStats {
+                hca_handles: 3,
+                hca_objects: 0,
+            },
+        );
+        assert_eq!(actual, expected);
+    }
+
+    #[test]
+    fn test_parse_net_dev() -> Result<()> {
+        let tmp = create_temp_dir("test_parse_net_dev")?;
+        let net_dev = set_fixture(
+            &tmp,
+            "dev",
+            "Inter-|   Receive                                                |  Transmit
+ face |bytes    packets errs drop fifo frame compressed multicast|bytes    packets errs drop fifo colls carrier compressed
+    lo:     100       2    0    0    0     0          0         0      100       2    0    0    0     0       0          0
+  eth0: 5000      40    1    2    0     0          0         0     3000      30    3    4    0     0       0          0
+",
+        )?;
+
+        let stats = parse_net_dev(&net_dev)?;
+        assert_eq!(stats.len(), 2);
+        assert_eq!(
+            stats["eth0"],
+            NetworkStats {
+                rx_bytes: 5000,
+                rx_packets: 40,
+                rx_errors: 1,
+                rx_dropped: 2,
+                tx_bytes: 3000,
+                tx_packets: 30,
+                tx_errors: 3,
+                tx_dropped: 4,
+            }
+        );
+        Ok(())
+    }
 }
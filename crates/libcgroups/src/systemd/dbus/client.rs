@@ -1,8 +1,11 @@
 use crate::systemd::dbus::systemd_api::OrgFreedesktopSystemd1Manager;
 use anyhow::{Context, Result};
 use dbus::arg::{RefArg, Variant};
+use dbus::blocking::stdintf::org_freedesktop_dbus::Properties;
 use dbus::blocking::{Connection, Proxy};
+use dbus::channel::Channel;
 use std::collections::HashMap;
+use std::env;
 use std::path::PathBuf;
 use std::time::Duration;
 
@@ -15,10 +18,17 @@ pub trait SystemdClient {
         pid: u32,
         parent: &str,
         unit_name: &str,
+        properties: &HashMap<&str, Box<dyn RefArg>>,
     ) -> Result<()>;
 
     fn stop_transient_unit(&self, unit_name: &str) -> Result<()>;
 
+    fn reset_failed_unit(&self, unit_name: &str) -> Result<()>;
+
+    fn unit_active_state(&self, unit_name: &str) -> Result<Option<String>>;
+
+    fn attach_process(&self, unit_name: &str, pid: u32) -> Result<()>;
+
     fn set_unit_properties(
         &self,
         unit_name: &str,
@@ -31,6 +41,8 @@ pub trait SystemdClient {
 }
 
 /// Client is a wrapper providing higher level API and abatraction around dbus.
+/// It uses the blocking connection of the dbus crate together with the bindings
+/// generated in systemd_api, a native client based on zbus is not implemented.
 /// For more information see https://www.freedesktop.org/wiki/Software/systemd/dbus/
 pub struct Client {
     conn: Connection,
@@ -44,9 +56,26 @@ impl Client {
         Ok(Client { conn, system: true })
     }
 
-    /// Uses the session bus to communicate with systemd
+    /// Uses the session bus to communicate with systemd. This is the per-user
+    /// systemd instance which is used to manage the cgroups of rootless
+    /// containers. If no session bus address is set in the environment, the
+    /// default socket in the user's runtime directory is used.
     pub fn new_session() -> Result<Self> {
-        let conn = Connection::new_session()?;
+        let conn = if env::var_os("DBUS_SESSION_BUS_ADDRESS").is_some() {
+            Connection::new_session()?
+        } else {
+            let runtime_dir = env::var("XDG_RUNTIME_DIR")
+                .unwrap_or_else(|_| format!("/run/user/{}", nix::unistd::geteuid()));
+            let address = format!("unix:path={}/bus", runtime_dir);
+            log::debug!("session bus address not set, connecting to {}", address);
+            let mut channel = Channel::open_private(&address)
+                .with_context(|| format!("failed to connect to session bus at {}", address))?;
+            channel
+                .register()
+                .context("failed to register with the session bus")?;
+            Connection::from(channel)
+        };
+
         Ok(Client {
             conn,
             system: false,
@@ -68,7 +97,9 @@ impl SystemdClient for Client {
     }
 
     /// start_transient_unit is a higher level API for starting a unit
-    /// for a specific container under systemd.
+    /// for a specific container under systemd. The given properties are set
+    /// in addition to the default ones, some properties can only be set when
+    /// the unit is created.
     /// See https://www.freedesktop.org/wiki/Software/systemd/dbus for more details.
     fn start_transient_unit(
         &self,
@@ -76,6 +107,7 @@ impl SystemdClient for Client {
         pid: u32,
         parent: &str,
         unit_name: &str,
+        properties: &HashMap<&str, Box<dyn RefArg>>,
     ) -> Result<()> {
         // To view and introspect the methods under the 'org.freedesktop.systemd1' destination
         // and object path under it use the following command:
@@ -89,8 +121,9 @@ impl SystemdClient for Client {
         // - TasksAccounting=true
         // see https://github.com/opencontainers/runc/blob/6023d635d725a74c6eaa11ab7f3c870c073badd2/docs/systemd.md#systemd-cgroup-driver
         // for more details.
-        let mut properties: Vec<(&str, Variant<Box<dyn RefArg>>)> = Vec::with_capacity(6);
-        properties.push((
+        let mut unit_properties: Vec<(&str, Variant<Box<dyn RefArg>>)> =
+            Vec::with_capacity(8 + properties.len());
+        unit_properties.push((
             "Description",
             Variant(Box::new(format!("youki container {}", container_name))),
         ));
@@ -98,24 +131,33 @@ impl SystemdClient for Client {
         // if we create a slice, the parent is defined via a Wants=
         // otherwise, we use Slice=
         if unit_name.ends_with("slice") {
-            properties.push(("Wants", Variant(Box::new(parent.to_owned()))));
+            unit_properties.push(("Wants", Variant(Box::new(parent.to_owned()))));
         } else {
-            properties.push(("Slice", Variant(Box::new(parent.to_owned()))));
-            properties.push(("Delegate", Variant(Box::new(true))));
+            unit_properties.push(("Slice", Variant(Box::new(parent.to_owned()))));
+            unit_properties.push(("Delegate", Variant(Box::new(true))));
         }
 
-        properties.push(("MemoryAccounting", Variant(Box::new(true))));
-        properties.push(("CPUAccounting", Variant(Box::new(true))));
-        properties.push(("IOAccounting", Variant(Box::new(true))));
-        properties.push(("TasksAccounting", Variant(Box::new(true))));
+        unit_properties.push(("MemoryAccounting", Variant(Box::new(true))));
+        unit_properties.push(("CPUAccounting", Variant(Box::new(true))));
+        unit_properties.push(("IOAccounting", Variant(Box::new(true))));
+        unit_properties.push(("TasksAccounting", Variant(Box::new(true))));
 
-        properties.push(("DefaultDependencies", Variant(Box::new(false))));
-        properties.push(("PIDs", Variant(Box::new(vec![pid]))));
+        unit_properties.push(("DefaultDependencies", Variant(Box::new(false))));
+        unit_properties.push(("PIDs", Variant(Box::new(vec![pid]))));
 
-        log::debug!("START UNIT: {:?}", properties);
+        // the given properties take precedence over the defaults, e.g. if they
+        // have been requested through annotations
+        unit_properties.retain(|(name, _)| !properties.contains_key(name));
+        unit_properties.extend(
+            properties
+                .iter()
+                .map(|(name, value)| (*name, Variant(value.box_clone()))),
+        );
+
+        log::debug!("START UNIT: {:?}", unit_properties);
 
         proxy
-            .start_transient_unit(unit_name, "replace", properties, vec![])
+            .start_transient_unit(unit_name, "replace", unit_properties, vec![])
             .with_context(|| {
                 format!(
                     "failed to start transient unit {}, parent is {}",
@@ -134,6 +176,54 @@ impl SystemdClient for Client {
         Ok(())
     }
 
+    /// Clears the failed state of a unit, so that a unit with the same name
+    /// can be created again and does not linger in the unit list
+    fn reset_failed_unit(&self, unit_name: &str) -> Result<()> {
+        let proxy = self.create_proxy();
+
+        proxy
+            .reset_failed_unit(unit_name)
+            .with_context(|| format!("failed to reset failed unit {}", unit_name))?;
+        Ok(())
+    }
+
+    /// Returns the active state of the unit, e.g. active or failed, or None if
+    /// the unit is not loaded
+    fn unit_active_state(&self, unit_name: &str) -> Result<Option<String>> {
+        let proxy = self.create_proxy();
+
+        let unit_path = match proxy.get_unit(unit_name) {
+            Ok(unit_path) => unit_path,
+            Err(err) if err.name() == Some("org.freedesktop.systemd1.NoSuchUnit") => {
+                return Ok(None)
+            }
+            Err(err) => {
+                return Err(err).with_context(|| format!("failed to get unit {}", unit_name))
+            }
+        };
+
+        let state: String = self
+            .conn
+            .with_proxy(
+                "org.freedesktop.systemd1",
+                unit_path,
+                Duration::from_millis(5000),
+            )
+            .get("org.freedesktop.systemd1.Unit", "ActiveState")
+            .with_context(|| format!("failed to get active state of unit {}", unit_name))?;
+        Ok(Some(state))
+    }
+
+    /// Moves the process into the cgroup of an already running unit
+    fn attach_process(&self, unit_name: &str, pid: u32) -> Result<()> {
+        let proxy = self.create_proxy();
+
+        proxy
+            .attach_processes_to_unit(unit_name, "/", vec![pid])
+            .with_context(|| format!("failed to attach process {} to unit {}", pid, unit_name))?;
+        Ok(())
+    }
+
     fn set_unit_properties(
         &self,
         unit_name: &str,
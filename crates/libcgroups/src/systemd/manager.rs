@@ -6,11 +6,15 @@ use std::{
     fs::{self},
     os::unix::fs::PermissionsExt,
     path::Component::RootDir,
+    time::Duration,
 };
 
 use anyhow::{anyhow, bail, Context, Result};
 use dbus::arg::RefArg;
-use nix::unistd::Pid;
+use nix::{
+    sys::signal::Signal,
+    unistd::{Gid, Pid, Uid},
+};
 use std::path::{Path, PathBuf};
 
 use super::{
@@ -19,10 +23,13 @@ use super::{
     cpu::Cpu,
     cpuset::CpuSet,
     dbus::client::{Client, SystemdClient},
-    memory::Memory,
+    memory::{self, Memory},
     pids::Pids,
+    properties,
 };
+use crate::oom::OomWatcher;
 use crate::stats::Stats;
+use crate::v2;
 use crate::{
     common::{self, CgroupManager, ControllerOpt, FreezerState, PathBufExt},
     systemd::unified::Unified,
@@ -270,6 +277,7 @@ impl Manager {
         for controller in fs::read_to_string(&controllers_path)?.split_whitespace() {
             match controller {
                 "cpu" => controllers.push(ControllerType::Cpu),
+                "cpuset" => controllers.push(ControllerType::CpuSet),
                 "io" => controllers.push(ControllerType::Io),
                 "memory" => controllers.push(ControllerType::Memory),
                 "pids" => controllers.push(ControllerType::Tasks),
@@ -280,22 +288,63 @@ impl Manager {
         Ok(controllers)
     }
 
-    fn write_controllers(path: &Path, controllers: &[String]) -> Result<()> {
-        for controller in controllers {
-            common::write_cgroup_file_str(path.join(CGROUP_SUBTREE_CONTROL), controller)?;
+    fn ensure_delegated(controller: &ControllerType, delegated: &[ControllerType]) -> Result<()> {
+        if !delegated.contains(controller) {
+            bail!(
+                "resource restrictions for the {} controller were requested, but the controller \
+                is not delegated to the systemd user instance",
+                controller
+            );
         }
 
         Ok(())
     }
-}
 
-impl CgroupManager for Manager {
-    fn add_task(&self, pid: Pid) -> Result<()> {
-        // Dont attach any pid to the cgroup if -1 is specified as a pid
-        if pid.as_raw() == -1 {
-            return Ok(());
+    /// Returns a manager for the cgroup of the unit, which is used for the
+    /// operations that are not handled through systemd
+    fn fs_manager(&self) -> Result<v2::manager::Manager> {
+        v2::manager::Manager::new(self.root_path.clone(), self.cgroups_path.clone())
+    }
+
+    /// Returns the unified properties whose controller is delegated to the systemd
+    /// user instance. Properties of controllers that are not delegated result in
+    /// an error in strict mode and are skipped otherwise.
+    fn delegated_unified(
+        unified: &HashMap<String, String>,
+        delegated: &[ControllerType],
+        strict: bool,
+    ) -> Result<HashMap<String, String>> {
+        let mut filtered = HashMap::with_capacity(unified.len());
+        for (key, value) in unified {
+            let controller = match key.split_once('.').map(|(prefix, _)| prefix) {
+                Some("cpu") => ControllerType::Cpu,
+                Some("cpuset") => ControllerType::CpuSet,
+                Some("io") => ControllerType::Io,
+                Some("memory") => ControllerType::Memory,
+                Some("pids") => ControllerType::Tasks,
+                // unknown properties are reported when the unified restrictions are applied
+                _ => {
+                    filtered.insert(key.clone(), value.clone());
+                    continue;
+                }
+            };
+
+            if let Err(err) = Self::ensure_delegated(&controller, delegated) {
+                if strict {
+                    return Err(err.context(format!("failed to apply {}", key)));
+                }
+
+                log::warn!("skipping unified resource restriction {}: {}", key, err);
+                continue;
+            }
+
+            filtered.insert(key.clone(), value.clone());
         }
 
+        Ok(filtered)
+    }
+
+    fn start_unit(&self, pid: Pid, properties: &HashMap<&str, Box<dyn RefArg>>) -> Result<()> {
         log::debug!("Starting {:?}", self.unit_name);
         self.client
             .start_transient_unit(
@@ -303,25 +352,40 @@ impl CgroupManager for Manager {
                 pid.as_raw() as u32,
                 &self.destructured_path.parent,
                 &self.unit_name,
+                properties,
             )
             .with_context(|| {
                 format!(
                     "failed to create unit {} for container {}",
                     self.unit_name, self.container_name
                 )
-            })?;
-
-        Ok(())
+            })
     }
 
-    fn apply(&self, controller_opt: &ControllerOpt) -> Result<()> {
-        let mut properties: HashMap<&str, Box<dyn RefArg>> = HashMap::new();
+    fn resource_properties<'a>(
+        &self,
+        controller_opt: &ControllerOpt,
+    ) -> Result<HashMap<&'a str, Box<dyn RefArg>>> {
+        let mut properties: HashMap<&'a str, Box<dyn RefArg>> = HashMap::new();
         let systemd_version = self
             .client
             .systemd_version()
             .context("could not retrieve systemd version")?;
 
+        // The systemd user instance can only apply restrictions for controllers
+        // that have been delegated to it, so this needs to be verified for rootless
+        // containers. Otherwise the restrictions would silently be ignored.
+        let delegated = if self.client.is_system() {
+            None
+        } else {
+            Some(
+                self.get_available_controllers(&self.cgroups_path)
+                    .context("failed to get controllers delegated to systemd user instance")?,
+            )
+        };
+
         for controller in CONTROLLER_TYPES {
+            let previous: Vec<&str> = properties.keys().copied().collect();
             match controller {
                 ControllerType::Cpu => {
                     Cpu::apply(controller_opt, systemd_version, &mut properties)?
@@ -339,9 +403,99 @@ impl CgroupManager for Manager {
                 }
                 _ => {}
             };
+
+            if let Some(delegated) = &delegated {
+                if properties.len() != previous.len() {
+                    if let Err(err) = Self::ensure_delegated(controller, delegated) {
+                        if controller_opt.strict_resources {
+                            return Err(err);
+                        }
+
+                        log::warn!("skipping resource restrictions: {}", err);
+                        properties.retain(|name, _| previous.contains(name));
+                    }
+                }
+            }
         }
 
-        Unified::apply(controller_opt, systemd_version, &mut properties)?;
+        // unified properties are subject to the same delegation requirements as
+        // the controller specific restrictions
+        let unified_resources;
+        let unified_opt;
+        let unified_controller_opt = match (&delegated, controller_opt.resources.unified()) {
+            (Some(delegated), Some(unified)) => {
+                let unified =
+                    Self::delegated_unified(unified, delegated, controller_opt.strict_resources)?;
+                let mut resources = controller_opt.resources.clone();
+                resources.set_unified(Some(unified));
+                unified_resources = resources;
+                unified_opt = ControllerOpt {
+                    resources: &unified_resources,
+                    ..controller_opt.clone()
+                };
+                &unified_opt
+            }
+            _ => controller_opt,
+        };
+        Unified::apply(unified_controller_opt, systemd_version, &mut properties)?;
+
+        Ok(properties)
+    }
+
+    fn write_controllers(path: &Path, controllers: &[String]) -> Result<()> {
+        for controller in controllers {
+            common::write_cgroup_file_str(path.join(CGROUP_SUBTREE_CONTROL), controller)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl CgroupManager for Manager {
+    fn add_task(&self, pid: Pid) -> Result<()> {
+        // Dont attach any pid to the cgroup if -1 is specified as a pid
+        if pid.as_raw() == -1 {
+            return Ok(());
+        }
+
+        // the unit already exists if a process is executed in a running container
+        match self.client.unit_active_state(&self.unit_name)?.as_deref() {
+            Some("active" | "activating" | "reloading") => {
+                log::debug!("Attaching {} to {:?}", pid, self.unit_name);
+                self.client
+                    .attach_process(&self.unit_name, pid.as_raw() as u32)
+                    .with_context(|| {
+                        format!(
+                            "failed to add task {} to unit {} of container {}",
+                            pid, self.unit_name, self.container_name
+                        )
+                    })
+            }
+            Some(state) => bail!(
+                "cannot add task {} to unit {} of container {}, the unit is {}",
+                pid,
+                self.unit_name,
+                self.container_name,
+                state
+            ),
+            None => self.start_unit(pid, &HashMap::new()),
+        }
+    }
+
+    fn apply(&self, controller_opt: &ControllerOpt) -> Result<()> {
+        // properties from annotations may only be settable when the unit is
+        // created, so they are not applied to the running unit
+        let mut properties = self.resource_properties(controller_opt)?;
+        // the oom policy of a unit can not be changed once it has been started
+        if properties.remove(memory::OOM_POLICY).is_some() {
+            if controller_opt.strict_resources {
+                bail!("memory.oom.group can only be set when the container is created");
+            }
+
+            log::warn!(
+                "memory.oom.group can only be set when the container is created, ignoring it"
+            );
+        }
         log::debug!("{:?}", properties);
 
         self.client
@@ -351,6 +505,53 @@ impl CgroupManager for Manager {
         Ok(())
     }
 
+    fn add_init_task(&self, pid: Pid, controller_opt: Option<&ControllerOpt>) -> Result<()> {
+        // Dont attach any pid to the cgroup if -1 is specified as a pid
+        if pid.as_raw() == -1 {
+            return Ok(());
+        }
+
+        match self.client.unit_active_state(&self.unit_name)?.as_deref() {
+            // a unit which is left over from a previous container with the same
+            // name would prevent the new unit from being started
+            Some(state @ ("failed" | "inactive")) => {
+                log::debug!("resetting {} unit {:?}", state, self.unit_name);
+                self.client
+                    .reset_failed_unit(&self.unit_name)
+                    .with_context(|| {
+                        format!(
+                            "failed to reset unit {} of container {}",
+                            self.unit_name, self.container_name
+                        )
+                    })?;
+            }
+            Some(state) => bail!(
+                "unit {} of container {} already exists and is {}",
+                self.unit_name,
+                self.container_name,
+                state
+            ),
+            None => {}
+        }
+
+        let controller_opt = match controller_opt {
+            Some(controller_opt) => controller_opt,
+            None => return self.start_unit(pid, &HashMap::new()),
+        };
+        let annotated_properties = match controller_opt.annotations {
+            Some(annotations) => properties::from_annotations(annotations)
+                .context("failed to get systemd properties from annotations")?,
+            None => Vec::new(),
+        };
+        let mut properties = self.resource_properties(controller_opt)?;
+        // properties from annotations take precedence, as they have been set explicitly
+        for (name, value) in &annotated_properties {
+            properties.insert(name.as_str(), value.box_clone());
+        }
+
+        self.start_unit(pid, &properties)
+    }
+
     fn remove(&self) -> Result<()> {
         log::debug!("remove {}", self.unit_name);
         self.client
@@ -359,20 +560,48 @@ impl CgroupManager for Manager {
                 format!("could not remove control group {}", self.destructured_path)
             })?;
 
+        // a unit whose processes have been killed ends up in the failed state
+        // and would prevent a new container with the same name from starting
+        if let Err(err) = self.client.reset_failed_unit(&self.unit_name) {
+            log::debug!("could not reset failed unit {}: {:?}", self.unit_name, err);
+        }
+
         Ok(())
     }
 
     fn freeze(&self, state: FreezerState) -> Result<()> {
-        todo!();
+        // systemd does not track the freezer state of transient units, so the
+        // cgroup is frozen directly through the fs like for the v2 manager
+        self.fs_manager()?.freeze(state)
+    }
+
+    fn freeze_with_timeout(&self, state: FreezerState, timeout: Duration) -> Result<()> {
+        self.fs_manager()?.freeze_with_timeout(state, timeout)
     }
 
     fn stats(&self) -> Result<Stats> {
-        Ok(Stats::default())
+        self.fs_manager()?.stats()
     }
 
     fn get_all_pids(&self) -> Result<Vec<Pid>> {
         common::get_all_pids(&self.full_path)
     }
+
+    fn oom_watcher(&self) -> Result<OomWatcher> {
+        self.fs_manager()?.oom_watcher()
+    }
+
+    fn perf_event_path(&self) -> Result<PathBuf> {
+        self.fs_manager()?.perf_event_path()
+    }
+
+    fn delegate(&self, uid: Uid, gid: Gid) -> Result<()> {
+        self.fs_manager()?.delegate(uid, gid)
+    }
+
+    fn kill_all(&self, signal: Signal) -> Result<()> {
+        self.fs_manager()?.kill_all(signal)
+    }
 }
 
 #[cfg(test)]
@@ -394,6 +623,7 @@ mod tests {
             pid: u32,
             parent: &str,
             unit_name: &str,
+            properties: &HashMap<&str, Box<dyn RefArg>>,
         ) -> Result<()> {
             Ok(())
         }
@@ -402,6 +632,18 @@ mod tests {
             Ok(())
         }
 
+        fn reset_failed_unit(&self, unit_name: &str) -> Result<()> {
+            Ok(())
+        }
+
+        fn unit_active_state(&self, unit_name: &str) -> Result<Option<String>> {
+            Ok(None)
+        }
+
+        fn attach_process(&self, unit_name: &str, pid: u32) -> Result<()> {
+            Ok(())
+        }
+
         fn set_unit_properties(
             &self,
             unit_name: &str,
@@ -457,6 +699,37 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn ensure_delegated_works() -> Result<()> {
+        let delegated = vec![ControllerType::Memory, ControllerType::Tasks];
+
+        assert!(Manager::ensure_delegated(&ControllerType::Memory, &delegated).is_ok());
+        assert!(Manager::ensure_delegated(&ControllerType::Tasks, &delegated).is_ok());
+        assert!(Manager::ensure_delegated(&ControllerType::Cpu, &delegated).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn delegated_unified_works() -> Result<()> {
+        let delegated = vec![ControllerType::Memory];
+        let unified: HashMap<String, String> = [("memory.oom.group", "1"), ("cpu.weight", "100")]
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect();
+
+        let filtered = Manager::delegated_unified(&unified, &delegated, false)?;
+        assert_eq!(filtered.len(), 1);
+        assert_eq!(
+            filtered.get("memory.oom.group").map(String::as_str),
+            Some("1")
+        );
+
+        assert!(Manager::delegated_unified(&unified, &delegated, true).is_err());
+
+        Ok(())
+    }
+
     #[test]
     fn get_cgroups_path_works_with_scope() -> Result<()> {
         let cgroups_path = Path::new(":docker:foo")
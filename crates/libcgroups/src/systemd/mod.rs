@@ -8,6 +8,7 @@ mod dbus;
 pub mod manager;
 mod memory;
 mod pids;
+pub mod properties;
 mod unified;
 
 /// Checks if the system was booted with systemd
@@ -51,6 +51,8 @@ mod tests {
         let options = ControllerOpt {
             resources,
             disable_oom_killer: false,
+            annotations: None,
+            strict_resources: false,
             oom_score_adj: None,
             freezer_state: None,
         };
@@ -0,0 +1,213 @@
+//! Arbitrary properties of the transient unit can be set through annotations of
+//! the form org.systemd.property.<Name>=<value>, e.g. for TasksMax or timeouts
+//! which are not part of the runtime spec. They are set when the unit is started,
+//! as some of them can not be changed afterwards. This works the same way as in runc.
+use std::collections::HashMap;
+
+use anyhow::{bail, Context, Result};
+use dbus::arg::RefArg;
+
+pub const ANNOTATION_PREFIX: &str = "org.systemd.property.";
+
+/// Returns the unit properties which have been requested through annotations.
+/// Properties whose name ends with Sec are converted to the corresponding USec
+/// property, as systemd only accepts microseconds over dbus. Setting both, e.g.
+/// TimeoutStopSec and TimeoutStopUSec, is rejected.
+pub(crate) fn from_annotations(
+    annotations: &HashMap<String, String>,
+) -> Result<Vec<(String, Box<dyn RefArg>)>> {
+    let mut properties = Vec::new();
+    // annotation from which each property has been set
+    let mut sources: HashMap<String, &str> = HashMap::new();
+    let mut keys: Vec<&String> = annotations.keys().collect();
+    keys.sort();
+    for key in keys {
+        let value = &annotations[key];
+        let name = match key.strip_prefix(ANNOTATION_PREFIX) {
+            Some(name) => name,
+            None => continue,
+        };
+
+        if !is_valid_name(name) {
+            bail!(
+                "invalid systemd property name {} in annotation {}",
+                name,
+                key
+            );
+        }
+
+        let property: (String, Box<dyn RefArg>) = match name.strip_suffix("Sec") {
+            Some(base) if !name.ends_with("USec") => (
+                format!("{}USec", base),
+                Box::new(
+                    parse_seconds(value)
+                        .with_context(|| format!("invalid value for {}: {}", name, value))?,
+                ),
+            ),
+            _ => (
+                name.to_owned(),
+                parse_value(value)
+                    .with_context(|| format!("invalid value for {}: {}", name, value))?,
+            ),
+        };
+
+        if let Some(other) = sources.insert(property.0.clone(), key) {
+            bail!(
+                "annotations {} and {} both set systemd property {}",
+                other,
+                key,
+                property.0
+            );
+        }
+
+        log::debug!("setting systemd property {} from annotation", property.0);
+        properties.push(property);
+    }
+
+    Ok(properties)
+}
+
+// Property names start with an upper case letter and contain letters and digits only
+fn is_valid_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    match chars.next() {
+        Some(c) if c.is_ascii_uppercase() => chars.all(|c| c.is_ascii_alphanumeric()),
+        _ => false,
+    }
+}
+
+fn parse_seconds(value: &str) -> Result<u64> {
+    let value = strip_type(value, &["uint64", "int64", "uint32", "int32", "double"]);
+    if value == "infinity" {
+        return Ok(u64::MAX);
+    }
+
+    let seconds: f64 = value.parse()?;
+    if seconds < 0.0 {
+        bail!("duration must not be negative");
+    }
+
+    Ok((seconds * 1_000_000.0) as u64)
+}
+
+fn strip_type<'a>(value: &'a str, types: &[&str]) -> &'a str {
+    let value = value.trim();
+    match value.split_once(char::is_whitespace) {
+        Some((typ, rest)) if types.contains(&typ) => rest.trim(),
+        _ => value,
+    }
+}
+
+// Values can either be annotated with their dbus type, e.g. 'uint64 100', or
+// the type is inferred from the value
+fn parse_value(value: &str) -> Result<Box<dyn RefArg>> {
+    let value = value.trim();
+    if let Some((typ, rest)) = value.split_once(char::is_whitespace) {
+        let rest = rest.trim();
+        let parsed: Option<Box<dyn RefArg>> = match typ {
+            "uint64" => Some(Box::new(rest.parse::<u64>()?)),
+            "int64" => Some(Box::new(rest.parse::<i64>()?)),
+            "uint32" => Some(Box::new(rest.parse::<u32>()?)),
+            "int32" => Some(Box::new(rest.parse::<i32>()?)),
+            "double" => Some(Box::new(rest.parse::<f64>()?)),
+            "boolean" => Some(Box::new(rest.parse::<bool>()?)),
+            "string" => Some(Box::new(unquote(rest).to_owned())),
+            _ => None,
+        };
+
+        if let Some(parsed) = parsed {
+            return Ok(parsed);
+        }
+    }
+
+    if let Ok(value) = value.parse::<bool>() {
+        return Ok(Box::new(value));
+    }
+    if let Ok(value) = value.parse::<u64>() {
+        return Ok(Box::new(value));
+    }
+    if let Ok(value) = value.parse::<i64>() {
+        return Ok(Box::new(value));
+    }
+    if value.len() >= 2
+        && ((value.starts_with('"') && value.ends_with('"'))
+            || (value.starts_with('\'') && value.ends_with('\'')))
+    {
+        return Ok(Box::new(unquote(value).to_owned()));
+    }
+
+    bail!("cannot determine the type of the value, strings have to be quoted")
+}
+
+fn unquote(value: &str) -> &str {
+    value
+        .strip_prefix('"')
+        .and_then(|v| v.strip_suffix('"'))
+        .or_else(|| value.strip_prefix('\'').and_then(|v| v.strip_suffix('\'')))
+        .unwrap_or(value)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use dbus::arg::ArgType;
+
+    fn annotations(entries: &[(&str, &str)]) -> HashMap<String, String> {
+        entries
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn test_from_annotations() -> Result<()> {
+        let annotations = annotations(&[
+            ("org.systemd.property.TasksMax", "uint64 100"),
+            ("org.systemd.property.CollectMode", "'inactive-or-failed'"),
+            ("org.opencontainers.image.ref.name", "ignored"),
+        ]);
+
+        let properties: HashMap<String, Box<dyn RefArg>> =
+            from_annotations(&annotations)?.into_iter().collect();
+        assert_eq!(properties.len(), 2);
+        assert_eq!(properties["TasksMax"].arg_type(), ArgType::UInt64);
+        assert_eq!(properties["TasksMax"].as_u64(), Some(100));
+        assert_eq!(
+            properties["CollectMode"].as_str(),
+            Some("inactive-or-failed")
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_sec_converted_to_usec() -> Result<()> {
+        let annotations = annotations(&[
+            ("org.systemd.property.CPUQuotaPeriodSec", "0.5"),
+            ("org.systemd.property.RuntimeMaxUSec", "uint64 1000"),
+        ]);
+
+        let properties: HashMap<String, Box<dyn RefArg>> =
+            from_annotations(&annotations)?.into_iter().collect();
+        assert_eq!(properties["CPUQuotaPeriodUSec"].as_u64(), Some(500_000));
+        assert_eq!(properties["RuntimeMaxUSec"].as_u64(), Some(1000));
+        Ok(())
+    }
+
+    #[test]
+    fn test_invalid_annotations() {
+        assert!(
+            from_annotations(&annotations(&[("org.systemd.property.tasks-max", "1")])).is_err()
+        );
+        assert!(
+            from_annotations(&annotations(&[("org.systemd.property.Slice", "unquoted")])).is_err()
+        );
+        assert!(
+            from_annotations(&annotations(&[("org.systemd.property.TimeoutSec", "-1")])).is_err()
+        );
+        assert!(from_annotations(&annotations(&[
+            ("org.systemd.property.TimeoutStopSec", "1"),
+            ("org.systemd.property.TimeoutStopUSec", "uint64 1000"),
+        ]))
+        .is_err());
+    }
+}
@@ -4,7 +4,7 @@ use std::collections::HashMap;
 
 use super::{
     controller::Controller,
-    cpu::{self, convert_shares_to_cgroup2},
+    cpu,
     cpuset::{self, to_bitmask},
     memory, pids,
 };
@@ -37,11 +37,27 @@ impl Unified {
         for (key, value) in unified {
             match key.as_str() {
                 "cpu.weight" => {
-                    let shares = value
+                    let weight = value
+                        .trim()
                         .parse::<u64>()
                         .with_context(|| format!("failed to parse cpu weight: {}", value))?;
-                    properties.insert(cpu::CPU_WEIGHT, Box::new(convert_shares_to_cgroup2(shares)));
+                    if !(1..=10000).contains(&weight) {
+                        bail!("cpu weight {} is out of range (1 to 10000)", weight);
+                    }
+                    properties.insert(cpu::CPU_WEIGHT, Box::new(weight));
                 }
+                "cpu.idle" => match value.trim() {
+                    "0" => {}
+                    "1" => {
+                        // systemd represents an idle cgroup as CPUWeight=idle, which
+                        // is transferred as a weight of 0
+                        if systemd_version < 252 {
+                            bail!("setting cpu.idle requires systemd version 252 or later");
+                        }
+                        properties.insert(cpu::CPU_WEIGHT, Box::new(0u64));
+                    }
+                    other => bail!("invalid value {} for cpu.idle, expected 0 or 1", other),
+                },
                 "cpu.max" => {
                     let parts: Vec<&str> = value.split_whitespace().collect();
                     if parts.is_empty() || parts.len() > 2 {
@@ -92,6 +108,23 @@ impl Unified {
                     };
                     properties.insert(systemd_memory, Box::new(value));
                 }
+                "memory.oom.group" => {
+                    // systemd enables memory.oom.group for units that are
+                    // completely stopped once one of their processes is oom killed
+                    if systemd_version < 253 {
+                        bail!("setting memory.oom.group requires systemd version 253 or later");
+                    }
+
+                    let policy = match value.trim() {
+                        "0" => "continue",
+                        "1" => "kill",
+                        other => bail!(
+                            "invalid value {} for memory.oom.group, expected 0 or 1",
+                            other
+                        ),
+                    };
+                    properties.insert(memory::OOM_POLICY, Box::new(policy.to_owned()));
+                }
                 "pids.max" => {
                     let pids = value.trim().parse::<i64>()?;
                     properties.insert(pids::TASKS_MAX, Box::new(pids as u64));
@@ -115,7 +148,7 @@ mod tests {
     fn test_set() -> Result<()> {
         // arrange
         let unified: HashMap<String, String> = [
-            ("cpu.weight", "22000"),
+            ("cpu.weight", "840"),
             ("cpuset.cpus", "0-3"),
             ("cpuset.mems", "0-3"),
             ("memory.min", "100000"),
@@ -207,4 +240,23 @@ mod tests {
 
         Ok(())
     }
+
+    #[test]
+    fn test_oom_group() -> Result<()> {
+        // arrange
+        let unified: HashMap<String, String> = [("memory.oom.group", "1")]
+            .into_iter()
+            .map(|(k, v)| (k.to_owned(), v.to_owned()))
+            .collect();
+        let mut actual: HashMap<&str, Box<dyn RefArg>> = HashMap::new();
+
+        // act
+        Unified::apply(&unified, 253, &mut actual).context("apply unified")?;
+
+        // assert
+        assert_eq!(actual[memory::OOM_POLICY].as_str(), Some("kill"));
+        assert!(Unified::apply(&unified, 252, &mut HashMap::new()).is_err());
+
+        Ok(())
+    }
 }
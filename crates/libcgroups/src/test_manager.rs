@@ -1,10 +1,11 @@
-use std::cell::RefCell;
+use std::{cell::RefCell, path::PathBuf, time::Duration};
 
 use anyhow::Result;
 use nix::unistd::Pid;
 
 use crate::{
     common::{CgroupManager, ControllerOpt, FreezerState},
+    oom::OomWatcher,
     stats::Stats,
 };
 
@@ -43,6 +44,10 @@ impl CgroupManager for TestManager {
         unimplemented!()
     }
 
+    fn freeze_with_timeout(&self, _state: FreezerState, _timeout: Duration) -> Result<()> {
+        unimplemented!()
+    }
+
     fn stats(&self) -> anyhow::Result<Stats> {
         unimplemented!()
     }
@@ -50,6 +55,14 @@ impl CgroupManager for TestManager {
     fn get_all_pids(&self) -> Result<Vec<Pid>> {
         unimplemented!()
     }
+
+    fn oom_watcher(&self) -> Result<OomWatcher> {
+        unimplemented!()
+    }
+
+    fn perf_event_path(&self) -> Result<PathBuf> {
+        unimplemented!()
+    }
 }
 
 impl TestManager {
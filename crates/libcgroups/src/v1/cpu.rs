@@ -15,8 +15,13 @@ const CGROUP_CPU_QUOTA: &str = "cpu.cfs_quota_us";
 const CGROUP_CPU_PERIOD: &str = "cpu.cfs_period_us";
 const CGROUP_CPU_RT_RUNTIME: &str = "cpu.rt_runtime_us";
 const CGROUP_CPU_RT_PERIOD: &str = "cpu.rt_period_us";
+const CGROUP_CPU_BURST: &str = "cpu.cfs_burst_us";
 const CGROUP_CPU_STAT: &str = "cpu.stat";
 
+// The runtime spec version we are using has no burst field yet, so the burst
+// is taken from the same unified resource as on cgroup v2
+const UNIFIED_CPU_BURST: &str = "cpu.max.burst";
+
 pub struct Cpu {}
 
 impl Controller for Cpu {
@@ -29,6 +34,20 @@ impl Controller for Cpu {
             Self::apply(cgroup_root, cpu).context("failed to apply cpu resource restrictions")?;
         }
 
+        if let Some(burst) = controller_opt
+            .resources
+            .unified()
+            .as_ref()
+            .and_then(|u| u.get(UNIFIED_CPU_BURST))
+        {
+            let quota = controller_opt
+                .resources
+                .cpu()
+                .as_ref()
+                .and_then(|cpu| cpu.quota());
+            Self::apply_burst(cgroup_root, burst, quota).context("failed to apply cpu burst")?;
+        }
+
         Ok(())
     }
 
@@ -127,6 +146,26 @@ impl Cpu {
 
         Ok(())
     }
+
+    // The burst has to be written after the quota, as the kernel rejects a
+    // burst which is larger than the quota
+    fn apply_burst(root_path: &Path, burst: &str, quota: Option<i64>) -> Result<()> {
+        let burst = burst.trim();
+        let value: u64 = burst
+            .parse()
+            .with_context(|| format!("invalid cpu burst value {}", burst))?;
+        if let Some(quota) = quota {
+            if quota > 0 && value > quota as u64 {
+                bail!(
+                    "cpu burst ({}) must not be larger than the cpu quota ({})",
+                    value,
+                    quota
+                );
+            }
+        }
+
+        common::write_cgroup_file(root_path.join(CGROUP_CPU_BURST), value)
+    }
 }
 
 #[cfg(test)]
@@ -169,6 +208,21 @@ mod tests {
         assert_eq!(content, QUOTA.to_string());
     }
 
+    #[test]
+    fn test_set_burst() {
+        // arrange
+        let (tmp, burst) = setup("test_set_burst", CGROUP_CPU_BURST);
+
+        // act
+        Cpu::apply_burst(&tmp, "50000", Some(100000)).expect("apply cpu burst");
+
+        // assert
+        let content = fs::read_to_string(burst)
+            .unwrap_or_else(|_| panic!("read {} file content", CGROUP_CPU_BURST));
+        assert_eq!(content, "50000");
+        assert!(Cpu::apply_burst(&tmp, "200000", Some(100000)).is_err());
+    }
+
     #[test]
     fn test_set_period() {
         // arrange
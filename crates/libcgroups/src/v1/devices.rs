@@ -1,10 +1,13 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::ErrorKind;
 use std::path::Path;
 
-use anyhow::Result;
+use anyhow::{Context, Result};
 
 use super::controller::Controller;
-use crate::common::{self, default_allow_devices, default_devices, ControllerOpt};
-use oci_spec::runtime::LinuxDeviceCgroup;
+use crate::common::{self, container_device_rules, ControllerOpt};
+use oci_spec::runtime::{LinuxDeviceCgroup, LinuxDeviceType};
 
 pub struct Devices {}
 
@@ -14,19 +17,38 @@ impl Controller for Devices {
     fn apply(controller_opt: &ControllerOpt, cgroup_root: &Path) -> Result<()> {
         log::debug!("Apply Devices cgroup config");
 
-        if let Some(devices) = controller_opt.resources.devices().as_ref() {
-            for d in devices {
-                Self::apply_device(d, cgroup_root)?;
-            }
-        }
+        let rules = container_device_rules(controller_opt.resources.devices());
+        // denying all devices first also discards the rules which have been
+        // applied before, e.g. on update of a container. In between, the
+        // processes of a running container can not access any device, so only
+        // the changed exceptions are written as long as the default policy
+        // stays the same, like runc does.
+        let current = read_device_list(cgroup_root)?;
+        let transition = match (current, expected_device_list(&rules)) {
+            (Some(current), Some(expected)) => device_list_transition(&current, &expected),
+            _ => None,
+        };
 
-        for d in [
-            default_devices().iter().map(|d| d.into()).collect(),
-            default_allow_devices(),
-        ]
-        .concat()
-        {
-            Self::apply_device(&d, cgroup_root)?;
+        match transition {
+            Some(entries) => {
+                if entries.is_empty() {
+                    log::debug!("device rules are unchanged");
+                }
+
+                for (allow, entry) in entries {
+                    let file = if allow {
+                        "devices.allow"
+                    } else {
+                        "devices.deny"
+                    };
+                    common::write_cgroup_file_str(cgroup_root.join(file), &entry)?;
+                }
+            }
+            None => {
+                for d in &rules {
+                    Self::apply_device(d, cgroup_root)?;
+                }
+            }
         }
 
         Ok(())
@@ -51,12 +73,139 @@ impl Devices {
     }
 }
 
+/// Devices of a device list entry, i.e. the type and the major and minor
+/// number, which are None for all numbers
+type DeviceKey = (char, Option<i64>, Option<i64>);
+
+/// The only entry of devices.list if all devices are allowed
+const ALL_DEVICES: DeviceKey = ('a', None, None);
+
+/// Returns the entries of devices.list, which result from applying the rules
+/// after a rule which denies all devices. The kernel only lists the exceptions
+/// if all other devices are denied and lists all devices otherwise, so None is
+/// returned if the rules deny devices after allowing all of them or contain
+/// types which can not be compared.
+fn expected_device_list(rules: &[LinuxDeviceCgroup]) -> Option<BTreeMap<DeviceKey, String>> {
+    let mut exceptions: BTreeMap<DeviceKey, String> = BTreeMap::new();
+    for rule in rules {
+        let typ = match rule.typ().unwrap_or_default() {
+            LinuxDeviceType::A => {
+                exceptions.clear();
+                if rule.allow() {
+                    exceptions.insert(ALL_DEVICES, "rwm".to_owned());
+                }
+                continue;
+            }
+            _ if exceptions.contains_key(&ALL_DEVICES) => return None,
+            LinuxDeviceType::C => 'c',
+            LinuxDeviceType::B => 'b',
+            _ => return None,
+        };
+        let access = rule.access().as_deref().unwrap_or_default();
+        let key = (typ, rule.major(), rule.minor());
+        let current = exceptions.remove(&key).unwrap_or_default();
+        // like the kernel, exceptions are only merged with exceptions of the
+        // exact same devices
+        let access: String = "rwm"
+            .chars()
+            .filter(|c| match rule.allow() {
+                true => current.contains(*c) || access.contains(*c),
+                false => current.contains(*c) && !access.contains(*c),
+            })
+            .collect();
+        if !access.is_empty() {
+            exceptions.insert(key, access);
+        }
+    }
+
+    Some(exceptions)
+}
+
+/// Returns the entries which need to be written to devices.allow (true) or
+/// devices.deny (false) to get from the current to the expected exceptions.
+/// None is returned if the default policy changes, which requires all rules
+/// to be applied again.
+fn device_list_transition(
+    current: &BTreeMap<DeviceKey, String>,
+    expected: &BTreeMap<DeviceKey, String>,
+) -> Option<Vec<(bool, String)>> {
+    if current.contains_key(&ALL_DEVICES) || expected.contains_key(&ALL_DEVICES) {
+        return (current == expected).then(Vec::new);
+    }
+
+    let difference = |access: &str, other: Option<&String>| -> String {
+        let other = other.map(String::as_str).unwrap_or_default();
+        access.chars().filter(|c| !other.contains(*c)).collect()
+    };
+
+    let mut entries = Vec::new();
+    for (key, access) in current {
+        let removed = difference(access, expected.get(key));
+        if !removed.is_empty() {
+            entries.push((false, device_entry(key, &removed)));
+        }
+    }
+    for (key, access) in expected {
+        let added = difference(access, current.get(key));
+        if !added.is_empty() {
+            entries.push((true, device_entry(key, &added)));
+        }
+    }
+
+    Some(entries)
+}
+
+fn device_entry((typ, major, minor): &DeviceKey, access: &str) -> String {
+    let number = |n: &Option<i64>| n.map_or("*".to_owned(), |n| n.to_string());
+    format!("{} {}:{} {}", typ, number(major), number(minor), access)
+}
+
+/// Reads the exceptions in devices.list, which is not available if the devices
+/// controller has not been set up yet
+fn read_device_list(cgroup_root: &Path) -> Result<Option<BTreeMap<DeviceKey, String>>> {
+    let path = cgroup_root.join("devices.list");
+    let content = match fs::read_to_string(&path) {
+        Ok(content) => content,
+        Err(err) if err.kind() == ErrorKind::NotFound => return Ok(None),
+        Err(err) => return Err(err).with_context(|| format!("failed to read {:?}", path)),
+    };
+
+    let mut exceptions = BTreeMap::new();
+    for line in content.lines() {
+        let parse_number = |number: &str| match number {
+            "*" => Ok(None),
+            number => number.parse::<i64>().map(Some),
+        };
+        let entry = match line.split_whitespace().collect::<Vec<_>>()[..] {
+            [typ, numbers, access] => numbers.split_once(':').and_then(|(major, minor)| {
+                let typ = typ.chars().next()?;
+                let major = parse_number(major).ok()?;
+                let minor = parse_number(minor).ok()?;
+                Some(((typ, major, minor), access.to_owned()))
+            }),
+            _ => None,
+        };
+        match entry {
+            Some((key, access)) => {
+                exceptions.insert(key, access);
+            }
+            None => {
+                log::debug!("unexpected entry in {:?}: {}", path, line);
+                return Ok(None);
+            }
+        }
+    }
+
+    Ok(Some(exceptions))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::common::default_allow_devices;
     use crate::test::create_temp_dir;
     use crate::test::set_fixture;
-    use oci_spec::runtime::{LinuxDeviceCgroupBuilder, LinuxDeviceType};
+    use oci_spec::runtime::{LinuxDeviceCgroupBuilder, LinuxResources};
     use std::fs::read_to_string;
 
     #[test]
@@ -138,6 +287,130 @@ mod tests {
         });
     }
 
+    #[test]
+    fn test_expected_device_list() {
+        let rule = |allow: bool, typ: LinuxDeviceType, major: Option<i64>, access: &str| {
+            let mut builder = LinuxDeviceCgroupBuilder::default()
+                .allow(allow)
+                .typ(typ)
+                .access(access);
+            if let Some(major) = major {
+                builder = builder.major(major).minor(3);
+            }
+            builder.build().unwrap()
+        };
+
+        let rules = vec![
+            rule(false, LinuxDeviceType::A, None, "rwm"),
+            rule(true, LinuxDeviceType::C, Some(1), "r"),
+            rule(true, LinuxDeviceType::C, Some(1), "wm"),
+            rule(true, LinuxDeviceType::B, None, "m"),
+            rule(false, LinuxDeviceType::C, Some(1), "w"),
+        ];
+        let want: BTreeMap<DeviceKey, String> = vec![
+            (('c', Some(1), Some(3)), "rm".to_owned()),
+            (('b', None, None), "m".to_owned()),
+        ]
+        .into_iter()
+        .collect();
+        assert_eq!(expected_device_list(&rules), Some(want));
+
+        // the kernel only lists all devices if all devices are allowed
+        let rules = vec![
+            rule(false, LinuxDeviceType::A, None, "rwm"),
+            rule(true, LinuxDeviceType::A, None, "rwm"),
+        ];
+        let want: BTreeMap<DeviceKey, String> =
+            vec![(ALL_DEVICES, "rwm".to_owned())].into_iter().collect();
+        assert_eq!(expected_device_list(&rules), Some(want));
+
+        // exceptions of allowed devices are not listed at all
+        let rules = vec![
+            rule(true, LinuxDeviceType::A, None, "rwm"),
+            rule(false, LinuxDeviceType::C, Some(1), "w"),
+        ];
+        assert_eq!(expected_device_list(&rules), None);
+    }
+
+    #[test]
+    fn test_apply_unchanged_devices() {
+        let tmp = create_temp_dir("test_apply_unchanged_devices")
+            .expect("create temp directory for test");
+        let resources = LinuxResources::default();
+        let controller_opt = ControllerOpt {
+            resources: &resources,
+            freezer_state: None,
+            oom_score_adj: None,
+            disable_oom_killer: false,
+            annotations: None,
+            strict_resources: false,
+        };
+
+        let list: String = expected_device_list(&container_device_rules(&None))
+            .expect("device list of default rules")
+            .iter()
+            .map(|(key, access)| device_entry(key, access) + "\n")
+            .collect();
+        set_fixture(&tmp, "devices.list", &list).expect("create devices list");
+        set_fixture(&tmp, "devices.allow", "").expect("create allowed devices list");
+        set_fixture(&tmp, "devices.deny", "").expect("create denied devices list");
+
+        Devices::apply(&controller_opt, &tmp).expect("apply devices");
+        assert_eq!(read_to_string(tmp.join("devices.deny")).unwrap(), "");
+        assert_eq!(read_to_string(tmp.join("devices.allow")).unwrap(), "");
+
+        // the rules are applied again if they differ from the current ones
+        set_fixture(&tmp, "devices.list", "a *:* rwm\n").expect("create devices list");
+        Devices::apply(&controller_opt, &tmp).expect("apply devices");
+        assert_eq!(
+            read_to_string(tmp.join("devices.deny")).unwrap(),
+            "a *:* rwm"
+        );
+    }
+
+    #[test]
+    fn test_apply_changed_devices() {
+        let tmp =
+            create_temp_dir("test_apply_changed_devices").expect("create temp directory for test");
+        let resources = LinuxResources::default();
+        let controller_opt = ControllerOpt {
+            resources: &resources,
+            freezer_state: None,
+            oom_score_adj: None,
+            disable_oom_killer: false,
+            annotations: None,
+            strict_resources: false,
+        };
+
+        // access to /dev/null has been restricted and another device has been
+        // allowed since the rules have been applied
+        let mut current = expected_device_list(&container_device_rules(&None))
+            .expect("device list of default rules");
+        assert_eq!(
+            current.insert(('c', Some(1), Some(3)), "r".to_owned()),
+            Some("rwm".to_owned())
+        );
+        current.insert(('c', Some(10), Some(200)), "rwm".to_owned());
+        let list: String = current
+            .iter()
+            .map(|(key, access)| device_entry(key, access) + "\n")
+            .collect();
+        set_fixture(&tmp, "devices.list", &list).expect("create devices list");
+        set_fixture(&tmp, "devices.allow", "").expect("create allowed devices list");
+        set_fixture(&tmp, "devices.deny", "").expect("create denied devices list");
+
+        // only the changed exceptions are written, instead of denying all devices
+        Devices::apply(&controller_opt, &tmp).expect("apply devices");
+        assert_eq!(
+            read_to_string(tmp.join("devices.deny")).unwrap(),
+            "c 10:200 rwm"
+        );
+        assert_eq!(
+            read_to_string(tmp.join("devices.allow")).unwrap(),
+            "c 1:3 wm"
+        );
+    }
+
     quickcheck! {
         fn property_test_apply_device(device: LinuxDeviceCgroup) -> bool {
             let tmp = create_temp_dir("property_test_apply_device").expect("create temp directory for test");
@@ -2,19 +2,21 @@ use std::io::prelude::*;
 use std::{
     fs::{create_dir_all, OpenOptions},
     path::Path,
-    thread, time,
+    thread,
+    time::{Duration, Instant},
 };
 
 use anyhow::{Result, *};
 
 use super::Controller;
 use crate::common;
-use crate::common::{ControllerOpt, FreezerState};
+use crate::common::{ControllerOpt, FreezerState, FreezerTimeoutError, DEFAULT_FREEZER_TIMEOUT};
 
 const CGROUP_FREEZER_STATE: &str = "freezer.state";
 const FREEZER_STATE_THAWED: &str = "THAWED";
 const FREEZER_STATE_FROZEN: &str = "FROZEN";
 const FREEZER_STATE_FREEZING: &str = "FREEZING";
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
 
 pub struct Freezer {}
 
@@ -39,6 +41,14 @@ impl Controller for Freezer {
 
 impl Freezer {
     fn apply(freezer_state: &FreezerState, cgroup_root: &Path) -> Result<()> {
+        Self::freeze_with_timeout(*freezer_state, cgroup_root, DEFAULT_FREEZER_TIMEOUT)
+    }
+
+    pub(crate) fn freeze_with_timeout(
+        freezer_state: FreezerState,
+        cgroup_root: &Path,
+        timeout: Duration,
+    ) -> Result<()> {
         match freezer_state {
             FreezerState::Undefined => {}
             FreezerState::Thawed => {
@@ -46,50 +56,12 @@ impl Freezer {
                     cgroup_root.join(CGROUP_FREEZER_STATE),
                     FREEZER_STATE_THAWED,
                 )?;
+                Self::wait_for_state(cgroup_root, FREEZER_STATE_THAWED, timeout)
+                    .and_then(|reached| Self::ensure_reached(reached, freezer_state, timeout))?;
             }
             FreezerState::Frozen => {
-                let r = || -> Result<()> {
-                    // We should do our best to retry if FREEZING is seen until it becomes FROZEN.
-                    // Add sleep between retries occasionally helped when system is extremely slow.
-                    // see:
-                    // https://github.com/opencontainers/runc/blob/b9ee9c6314599f1b4a7f497e1f1f856fe433d3b7/libcontainer/cgroups/fs/freezer.go#L42
-                    for i in 0..1000 {
-                        if i % 50 == 49 {
-                            let _ = common::write_cgroup_file(
-                                cgroup_root.join(CGROUP_FREEZER_STATE),
-                                FREEZER_STATE_THAWED,
-                            );
-                            thread::sleep(time::Duration::from_millis(10));
-                        }
-
-                        common::write_cgroup_file(
-                            cgroup_root.join(CGROUP_FREEZER_STATE),
-                            FREEZER_STATE_FROZEN,
-                        )?;
-
-                        if i % 25 == 24 {
-                            thread::sleep(time::Duration::from_millis(10));
-                        }
-
-                        let r = Self::read_freezer_state(cgroup_root)?;
-                        match r.trim() {
-                            FREEZER_STATE_FREEZING => {
-                                continue;
-                            }
-                            FREEZER_STATE_FROZEN => {
-                                if i > 1 {
-                                    log::debug!("frozen after {} retries", i)
-                                }
-                                return Ok(());
-                            }
-                            _ => {
-                                // should not reach here.
-                                bail!("unexpected state {} while freezing", r.trim());
-                            }
-                        }
-                    }
-                    bail!("unbale to freeze");
-                }();
+                let r = Self::freeze(cgroup_root, timeout)
+                    .and_then(|reached| Self::ensure_reached(reached, freezer_state, timeout));
 
                 if r.is_err() {
                     // Freezing failed, and it is bad and dangerous to leave the cgroup in FROZEN or
@@ -105,6 +77,78 @@ impl Freezer {
         Ok(())
     }
 
+    // Returns true if the cgroup has been frozen before the timeout expired
+    fn freeze(cgroup_root: &Path, timeout: Duration) -> Result<bool> {
+        // We should do our best to retry if FREEZING is seen until it becomes FROZEN.
+        // Add sleep between retries occasionally helped when system is extremely slow.
+        // see:
+        // https://github.com/opencontainers/runc/blob/b9ee9c6314599f1b4a7f497e1f1f856fe433d3b7/libcontainer/cgroups/fs/freezer.go#L42
+        let start = Instant::now();
+        let mut i = 0;
+        while start.elapsed() < timeout {
+            if i % 50 == 49 {
+                let _ = common::write_cgroup_file(
+                    cgroup_root.join(CGROUP_FREEZER_STATE),
+                    FREEZER_STATE_THAWED,
+                );
+                thread::sleep(POLL_INTERVAL);
+            }
+
+            common::write_cgroup_file(
+                cgroup_root.join(CGROUP_FREEZER_STATE),
+                FREEZER_STATE_FROZEN,
+            )?;
+
+            if i % 25 == 24 {
+                thread::sleep(POLL_INTERVAL);
+            }
+
+            let r = Self::read_freezer_state(cgroup_root)?;
+            match r.trim() {
+                FREEZER_STATE_FREEZING => {
+                    i += 1;
+                    continue;
+                }
+                FREEZER_STATE_FROZEN => {
+                    if i > 1 {
+                        log::debug!("frozen after {} retries", i)
+                    }
+                    return Ok(true);
+                }
+                _ => {
+                    // should not reach here.
+                    bail!("unexpected state {} while freezing", r.trim());
+                }
+            }
+        }
+
+        Ok(false)
+    }
+
+    // Returns true if the state has been reached before the timeout expired
+    fn wait_for_state(cgroup_root: &Path, expected: &str, timeout: Duration) -> Result<bool> {
+        let start = Instant::now();
+        loop {
+            if Self::read_freezer_state(cgroup_root)?.trim() == expected {
+                return Ok(true);
+            }
+
+            if start.elapsed() >= timeout {
+                return Ok(false);
+            }
+
+            thread::sleep(POLL_INTERVAL);
+        }
+    }
+
+    fn ensure_reached(reached: bool, state: FreezerState, timeout: Duration) -> Result<()> {
+        if reached {
+            Ok(())
+        } else {
+            Err(FreezerTimeoutError { state, timeout }.into())
+        }
+    }
+
     fn read_freezer_state(cgroup_root: &Path) -> Result<String> {
         let path = cgroup_root.join(CGROUP_FREEZER_STATE);
         let mut content = String::new();
@@ -184,6 +228,8 @@ mod tests {
                 freezer_state: Some(state),
                 oom_score_adj: None,
                 disable_oom_killer: false,
+                annotations: None,
+                strict_resources: false,
             };
 
             let pid = Pid::from_raw(1000);
@@ -211,6 +257,8 @@ mod tests {
                 freezer_state: Some(state),
                 oom_score_adj: None,
                 disable_oom_killer: false,
+                annotations: None,
+                strict_resources: false,
             };
 
             let pid = Pid::from_raw(1001);
@@ -239,6 +287,8 @@ mod tests {
                 freezer_state: Some(state),
                 oom_score_adj: None,
                 disable_oom_killer: false,
+                annotations: None,
+                strict_resources: false,
             };
 
             let pid = Pid::from_raw(1002);
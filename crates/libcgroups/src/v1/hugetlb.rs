@@ -71,6 +71,16 @@ impl HugeTlb {
             root_path.join(format!("hugetlb.{}.limit_in_bytes", hugetlb.page_size())),
             hugetlb.limit(),
         )?;
+
+        // reservation accounting is only available since Linux 5.7
+        let rsvd_file = root_path.join(format!(
+            "hugetlb.{}.rsvd.limit_in_bytes",
+            hugetlb.page_size()
+        ));
+        if rsvd_file.exists() {
+            common::write_cgroup_file(rsvd_file, hugetlb.limit())?;
+        }
+
         Ok(())
     }
 
@@ -3,8 +3,8 @@ use std::path::Path;
 use std::time::Duration;
 use std::{collections::HashMap, path::PathBuf};
 
-use anyhow::bail;
 use anyhow::Result;
+use anyhow::{bail, Context};
 use nix::unistd::Pid;
 
 use procfs::process::Process;
@@ -14,14 +14,20 @@ use super::{
     blkio::Blkio, controller_type::CONTROLLERS, cpu::Cpu, cpuacct::CpuAcct, cpuset::CpuSet,
     devices::Devices, freezer::Freezer, hugetlb::HugeTlb, memory::Memory,
     network_classifier::NetworkClassifier, network_priority::NetworkPriority,
-    perf_event::PerfEvent, pids::Pids, util, Controller,
+    perf_event::PerfEvent, pids::Pids, rdma::Rdma, util, Controller,
 };
 
 use crate::common::{self, CgroupManager, ControllerOpt, FreezerState, PathBufExt, CGROUP_PROCS};
+use crate::oom::OomWatcher;
 use crate::stats::{Stats, StatsProvider};
+use crate::v2;
 
 pub struct Manager {
     subsystems: HashMap<CtrlType, PathBuf>,
+    /// cgroup in the unified hierarchy, which is only present on hybrid systems
+    unified: Option<v2::manager::Manager>,
+    /// controllers which are attached to the unified hierarchy instead of a v1 hierarchy
+    unified_controllers: Vec<v2::controller_type::ControllerType>,
 }
 
 impl Manager {
@@ -36,7 +42,11 @@ impl Manager {
             }
         }
 
-        Ok(Manager { subsystems })
+        Ok(Manager {
+            subsystems,
+            unified: None,
+            unified_controllers: Vec::new(),
+        })
     }
 
     fn get_subsystem_path(cgroup_path: &Path, subsystem: &CtrlType) -> Result<PathBuf> {
@@ -58,6 +68,57 @@ impl Manager {
         Ok(p)
     }
 
+    /// Constructs a new cgroup manager for a hybrid system. In addition to the cgroup v1
+    /// subsystems, the process is also placed into the unified hierarchy mounted at
+    /// unified_root and resources of controllers which are attached to the unified
+    /// hierarchy are applied there.
+    pub fn hybrid(cgroup_path: PathBuf, unified_root: PathBuf) -> Result<Self> {
+        let mut manager = Self::new(cgroup_path.clone())?;
+        let unified_path = if cgroup_path.as_os_str().is_empty() {
+            v2::util::current_cgroup()?
+        } else {
+            cgroup_path
+        };
+
+        manager.unified_controllers = v2::util::get_available_controllers(&unified_root)
+            .context("failed to get controllers of the unified hierarchy")?;
+        manager.unified = Some(v2::manager::Manager::new(unified_root, unified_path)?);
+        Ok(manager)
+    }
+
+    /// Returns the controller of the unified hierarchy which provides the same resource
+    /// control as the cgroup v1 subsystem
+    fn unified_equivalent(subsystem: &CtrlType) -> Option<v2::controller_type::ControllerType> {
+        use v2::controller_type::ControllerType as V2CtrlType;
+
+        match subsystem {
+            CtrlType::Cpu | CtrlType::CpuAcct => Some(V2CtrlType::Cpu),
+            CtrlType::CpuSet => Some(V2CtrlType::CpuSet),
+            CtrlType::HugeTlb => Some(V2CtrlType::HugeTlb),
+            CtrlType::Pids => Some(V2CtrlType::Pids),
+            CtrlType::Memory => Some(V2CtrlType::Memory),
+            CtrlType::Blkio => Some(V2CtrlType::Io),
+            CtrlType::Rdma => Some(V2CtrlType::Rdma),
+            _ => None,
+        }
+    }
+
+    /// Returns the controllers of the unified hierarchy which are responsible for
+    /// subsystems that are not available as cgroup v1 hierarchy, as well as the
+    /// controllers which only exist on the unified hierarchy
+    fn routed_controllers(&self) -> Vec<v2::controller_type::ControllerType> {
+        self.unified_controllers
+            .iter()
+            .filter(|&controller| {
+                CONTROLLERS
+                    .iter()
+                    .filter(|s| Self::unified_equivalent(s).as_ref() == Some(controller))
+                    .all(|s| !self.subsystems.contains_key(s))
+            })
+            .copied()
+            .collect()
+    }
+
     fn get_required_controllers(
         &self,
         controller_opt: &ControllerOpt,
@@ -82,13 +143,21 @@ impl Manager {
                     NetworkClassifier::needs_to_handle(controller_opt).is_some()
                 }
                 CtrlType::Freezer => Freezer::needs_to_handle(controller_opt).is_some(),
+                CtrlType::Rdma => Rdma::needs_to_handle(controller_opt).is_some(),
             };
 
             if required {
                 if let Some(subsystem_path) = self.subsystems.get(controller) {
                     required_controllers.insert(controller, subsystem_path);
-                } else {
+                } else if Self::unified_equivalent(controller)
+                    .map(|c| self.unified_controllers.contains(&c))
+                    .unwrap_or_default()
+                {
+                    log::debug!("cgroup {} is handled by the unified hierarchy", controller);
+                } else if controller_opt.strict_resources {
                     bail!("cgroup {} is required to fullfill the request, but is not supported by this system", controller);
+                } else {
+                    log::warn!("cgroup {} is required to fullfill the request, but is not supported by this system. Skipping resource restrictions", controller);
                 }
             }
         }
@@ -106,6 +175,22 @@ impl CgroupManager for Manager {
             bail!("subsystem does not exist")
         }
     }
+
+    fn oom_watcher(&self) -> Result<OomWatcher> {
+        let memory = self
+            .subsystems
+            .get(&CtrlType::Memory)
+            .context("memory subsystem is not available")?;
+        OomWatcher::v1(memory)
+    }
+
+    fn perf_event_path(&self) -> Result<PathBuf> {
+        self.subsystems
+            .get(&CtrlType::PerfEvent)
+            .cloned()
+            .context("perf_event subsystem is not available")
+    }
+
     fn add_task(&self, pid: Pid) -> Result<()> {
         for subsys in &self.subsystems {
             match subsys.0 {
@@ -121,9 +206,16 @@ impl CgroupManager for Manager {
                 CtrlType::NetworkPriority => NetworkPriority::add_task(pid, subsys.1)?,
                 CtrlType::NetworkClassifier => NetworkClassifier::add_task(pid, subsys.1)?,
                 CtrlType::Freezer => Freezer::add_task(pid, subsys.1)?,
+                CtrlType::Rdma => Rdma::add_task(pid, subsys.1)?,
             }
         }
 
+        if let Some(unified) = &self.unified {
+            unified
+                .add_task(pid)
+                .context("failed to add task to the unified hierarchy")?;
+        }
+
         Ok(())
     }
 
@@ -142,9 +234,15 @@ impl CgroupManager for Manager {
                 CtrlType::NetworkPriority => NetworkPriority::apply(controller_opt, subsys.1)?,
                 CtrlType::NetworkClassifier => NetworkClassifier::apply(controller_opt, subsys.1)?,
                 CtrlType::Freezer => Freezer::apply(controller_opt, subsys.1)?,
+                CtrlType::Rdma => Rdma::apply(controller_opt, subsys.1)?,
             }
         }
 
+        if let Some(unified) = &self.unified {
+            unified.apply_controllers(controller_opt, &self.routed_controllers())?;
+            unified.apply_unified(controller_opt)?;
+        }
+
         Ok(())
     }
 
@@ -164,6 +262,10 @@ impl CgroupManager for Manager {
             }
         }
 
+        if let Some(unified) = &self.unified {
+            unified.remove()?;
+        }
+
         Ok(())
     }
 
@@ -173,11 +275,22 @@ impl CgroupManager for Manager {
             freezer_state: Some(state),
             oom_score_adj: None,
             disable_oom_killer: false,
+            annotations: None,
+            strict_resources: false,
         };
-        Freezer::apply(
-            &controller_opt,
-            self.subsystems.get(&CtrlType::Freezer).unwrap(),
-        )
+        let freezer = self
+            .subsystems
+            .get(&CtrlType::Freezer)
+            .context("freezer subsystem is not available")?;
+        Freezer::apply(&controller_opt, freezer)
+    }
+
+    fn freeze_with_timeout(&self, state: FreezerState, timeout: Duration) -> Result<()> {
+        let freezer = self
+            .subsystems
+            .get(&CtrlType::Freezer)
+            .context("freezer subsystem is not available")?;
+        Freezer::freeze_with_timeout(state, freezer, timeout)
     }
 
     fn stats(&self) -> Result<Stats> {
@@ -191,10 +304,96 @@ impl CgroupManager for Manager {
                 CtrlType::HugeTlb => stats.hugetlb = HugeTlb::stats(subsystem.1)?,
                 CtrlType::Blkio => stats.blkio = Blkio::stats(subsystem.1)?,
                 CtrlType::Memory => stats.memory = Memory::stats(subsystem.1)?,
+                CtrlType::Rdma => stats.rdma = Rdma::stats(subsystem.1)?,
                 _ => continue,
             }
         }
 
+        if let Some(unified) = &self.unified {
+            unified.controller_stats(&mut stats, &self.routed_controllers())?;
+        }
+
         Ok(stats)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use oci_spec::runtime::{LinuxMemoryBuilder, LinuxResourcesBuilder};
+
+    fn manager(unified_controllers: Vec<v2::controller_type::ControllerType>) -> Manager {
+        Manager {
+            subsystems: HashMap::new(),
+            unified: None,
+            unified_controllers,
+        }
+    }
+
+    #[test]
+    fn test_required_controller_missing() {
+        let resources = LinuxResourcesBuilder::default()
+            .memory(LinuxMemoryBuilder::default().limit(1024).build().unwrap())
+            .build()
+            .unwrap();
+        let controller_opt = ControllerOpt {
+            resources: &resources,
+            freezer_state: None,
+            oom_score_adj: None,
+            disable_oom_killer: false,
+            annotations: None,
+            strict_resources: true,
+        };
+
+        assert!(manager(vec![])
+            .get_required_controllers(&controller_opt)
+            .is_err());
+    }
+
+    #[test]
+    fn test_required_controller_missing_skipped() -> Result<()> {
+        let resources = LinuxResourcesBuilder::default()
+            .memory(LinuxMemoryBuilder::default().limit(1024).build().unwrap())
+            .build()
+            .unwrap();
+        let controller_opt = ControllerOpt {
+            resources: &resources,
+            freezer_state: None,
+            oom_score_adj: None,
+            disable_oom_killer: false,
+            annotations: None,
+            strict_resources: false,
+        };
+
+        assert!(manager(vec![])
+            .get_required_controllers(&controller_opt)?
+            .is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_required_controller_on_unified_hierarchy() -> Result<()> {
+        let resources = LinuxResourcesBuilder::default()
+            .memory(LinuxMemoryBuilder::default().limit(1024).build().unwrap())
+            .build()
+            .unwrap();
+        let controller_opt = ControllerOpt {
+            resources: &resources,
+            freezer_state: None,
+            oom_score_adj: None,
+            disable_oom_killer: false,
+            annotations: None,
+            strict_resources: false,
+        };
+
+        let manager = manager(vec![v2::controller_type::ControllerType::Memory]);
+        assert!(manager
+            .get_required_controllers(&controller_opt)?
+            .is_empty());
+        assert_eq!(
+            manager.routed_controllers(),
+            vec![v2::controller_type::ControllerType::Memory]
+        );
+        Ok(())
+    }
+}
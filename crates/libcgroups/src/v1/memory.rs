@@ -30,6 +30,9 @@ const MEMORY_USE_HIERARCHY: &str = "memory.use_hierarchy";
 const MEMORY_PREFIX: &str = "memory";
 // Prefix for memory and swap cgroup files
 const MEMORY_AND_SWAP_PREFIX: &str = "memory.memsw";
+// Kernel version since which kernel memory is always accounted together with
+// user memory and separate kernel memory limits are deprecated
+const KERNEL_MEMORY_DEPRECATED_SINCE: (u32, u32) = (5, 4);
 // Prefix for kernel memory cgroup files
 const MEMORY_KERNEL_PREFIX: &str = "memory.kmem";
 // Prefix for kernel tcp memory cgroup files
@@ -54,7 +57,7 @@ impl Controller for Memory {
         if let Some(memory) = &controller_opt.resources.memory() {
             let reservation = memory.reservation().unwrap_or(0);
 
-            Self::apply(memory, cgroup_root)?;
+            Self::apply(memory, cgroup_root, controller_opt.strict_resources)?;
 
             if reservation != 0 {
                 common::write_cgroup_file(
@@ -84,18 +87,7 @@ impl Controller for Memory {
                 }
             }
 
-            // NOTE: Seems as though kernel and kernelTCP are both deprecated
-            // neither are implemented by runc. Tests pass without this, but
-            // kept in per the spec.
-            if let Some(kmem) = memory.kernel() {
-                common::write_cgroup_file(cgroup_root.join(CGROUP_KERNEL_MEMORY_LIMIT), kmem)?;
-            }
-            if let Some(tcp_mem) = memory.kernel_tcp() {
-                common::write_cgroup_file(
-                    cgroup_root.join(CGROUP_KERNEL_TCP_MEMORY_LIMIT),
-                    tcp_mem,
-                )?;
-            }
+            Self::set_kernel_memory(memory, cgroup_root, common::kernel_version())?;
         }
 
         Ok(())
@@ -116,9 +108,16 @@ impl StatsProvider for Memory {
 
     fn stats(cgroup_path: &Path) -> Result<Self::Stats> {
         let memory = Self::get_memory_data(cgroup_path, MEMORY_PREFIX)?;
-        let memswap = Self::get_memory_data(cgroup_path, MEMORY_AND_SWAP_PREFIX)?;
-        let kernel = Self::get_memory_data(cgroup_path, MEMORY_KERNEL_PREFIX)?;
-        let kernel_tcp = Self::get_memory_data(cgroup_path, MEMORY_KERNEL_TCP_PREFIX)?;
+        // there are no swap statistics if swap accounting is disabled
+        let memswap = if Self::swap_accounting_enabled(cgroup_path) {
+            Self::get_memory_data(cgroup_path, MEMORY_AND_SWAP_PREFIX)?
+        } else {
+            MemoryData::default()
+        };
+        // the kmem files are missing if the kernel has been built without kernel
+        // memory accounting or removed them (Linux 6.1 and later)
+        let kernel = Self::get_optional_memory_data(cgroup_path, MEMORY_KERNEL_PREFIX)?;
+        let kernel_tcp = Self::get_optional_memory_data(cgroup_path, MEMORY_KERNEL_TCP_PREFIX)?;
         let hierarchy = Self::hierarchy_enabled(cgroup_path)?;
         let stats = Self::get_stat_data(cgroup_path)?;
 
@@ -130,6 +129,7 @@ impl StatsProvider for Memory {
             cache: stats["cache"],
             hierarchy,
             stats,
+            psi: None,
         })
     }
 }
@@ -154,6 +154,19 @@ impl Memory {
         Ok(memory_data)
     }
 
+    fn get_optional_memory_data(cgroup_path: &Path, file_prefix: &str) -> Result<MemoryData> {
+        let usage = cgroup_path.join(format!("{}{}", file_prefix, MEMORY_USAGE_IN_BYTES));
+        if !usage.exists() {
+            return Ok(MemoryData::default());
+        }
+
+        Self::get_memory_data(cgroup_path, file_prefix)
+    }
+
+    fn swap_accounting_enabled(cgroup_path: &Path) -> bool {
+        cgroup_path.join(CGROUP_MEMORY_SWAP_LIMIT).exists()
+    }
+
     fn hierarchy_enabled(cgroup_path: &Path) -> Result<bool> {
         let hierarchy_path = cgroup_path.join(MEMORY_USE_HIERARCHY);
         let hierarchy = common::read_cgroup_file(hierarchy_path)?;
@@ -263,20 +276,84 @@ impl Memory {
         }
     }
 
-    fn set_swap(swap: i64, cgroup_root: &Path) -> Result<()> {
+    fn set_swap(swap: i64, cgroup_root: &Path, strict: bool) -> Result<()> {
         if swap == 0 {
             return Ok(());
         }
 
-        common::write_cgroup_file(cgroup_root.join(CGROUP_MEMORY_SWAP_LIMIT), swap)?;
+        let swap_limit = cgroup_root.join(CGROUP_MEMORY_SWAP_LIMIT);
+        // the memsw files do not exist if swap accounting has been disabled
+        // with swapaccount=0 on the kernel command line
+        if !swap_limit.exists() {
+            if swap == -1 {
+                log::debug!("swap accounting is disabled, skipping unlimited swap");
+            } else if strict {
+                bail!(
+                    "swap accounting is disabled, swap limit of {} can not be applied",
+                    swap
+                );
+            } else {
+                log::warn!(
+                    "swap accounting is disabled, swap limit of {} will be ignored",
+                    swap
+                );
+            }
+            return Ok(());
+        }
+
+        common::write_cgroup_file(swap_limit, swap)?;
+        Ok(())
+    }
+
+    fn kernel_memory_deprecated(kernel_version: Option<(u32, u32)>) -> bool {
+        matches!(kernel_version, Some(version) if version >= KERNEL_MEMORY_DEPRECATED_SINCE)
+    }
+
+    fn set_kernel_memory(
+        memory: &LinuxMemory,
+        cgroup_root: &Path,
+        kernel_version: Option<(u32, u32)>,
+    ) -> Result<()> {
+        if let Some(kmem) = memory.kernel() {
+            if Self::kernel_memory_deprecated(kernel_version) {
+                log::warn!(
+                    "kernel memory limits are deprecated since Linux 5.4 as kernel memory \
+                    is always accounted, kernel memory limit of {} will be ignored",
+                    kmem
+                );
+            } else {
+                Self::set_kernel_limit(&cgroup_root.join(CGROUP_KERNEL_MEMORY_LIMIT), kmem)?;
+            }
+        }
+
+        if let Some(tcp_mem) = memory.kernel_tcp() {
+            Self::set_kernel_limit(&cgroup_root.join(CGROUP_KERNEL_TCP_MEMORY_LIMIT), tcp_mem)?;
+        }
+
         Ok(())
     }
 
+    fn set_kernel_limit(path: &Path, limit: i64) -> Result<()> {
+        // the kmem files do not exist if the kernel has been built without
+        // kernel memory accounting
+        if !path.exists() {
+            log::warn!(
+                "kernel memory accounting is not supported, limit of {} for {:?} will be ignored",
+                limit,
+                path
+            );
+            return Ok(());
+        }
+
+        common::write_cgroup_file(path, limit)
+    }
+
     fn set_memory_and_swap(
         limit: i64,
         swap: i64,
         is_updated: bool,
         cgroup_root: &Path,
+        strict: bool,
     ) -> Result<()> {
         // According to runc we need to change the write sequence of
         // limit and swap so it won't fail, because the new and old
@@ -284,36 +361,36 @@ impl Memory {
         // see:
         // https://github.com/opencontainers/runc/blob/3f6594675675d4e88901c782462f56497260b1d2/libcontainer/cgroups/fs/memory.go#L89
         if is_updated {
-            Self::set_swap(swap, cgroup_root)?;
+            Self::set_swap(swap, cgroup_root, strict)?;
             Self::set_memory(limit, cgroup_root)?;
         }
         Self::set_memory(limit, cgroup_root)?;
-        Self::set_swap(swap, cgroup_root)?;
+        Self::set_swap(swap, cgroup_root, strict)?;
         Ok(())
     }
 
-    fn apply(resource: &LinuxMemory, cgroup_root: &Path) -> Result<()> {
+    fn apply(resource: &LinuxMemory, cgroup_root: &Path, strict: bool) -> Result<()> {
         match resource.limit() {
             Some(limit) => {
                 let current_limit = Self::get_memory_limit(cgroup_root)?;
                 match resource.swap() {
                     Some(swap) => {
                         let is_updated = swap == -1 || current_limit < swap;
-                        Self::set_memory_and_swap(limit, swap, is_updated, cgroup_root)?;
+                        Self::set_memory_and_swap(limit, swap, is_updated, cgroup_root, strict)?;
                     }
                     None => {
                         if limit == -1 {
-                            Self::set_memory_and_swap(limit, -1, true, cgroup_root)?;
+                            Self::set_memory_and_swap(limit, -1, true, cgroup_root, strict)?;
                         } else {
                             let is_updated = current_limit < 0;
-                            Self::set_memory_and_swap(limit, 0, is_updated, cgroup_root)?;
+                            Self::set_memory_and_swap(limit, 0, is_updated, cgroup_root, strict)?;
                         }
                     }
                 }
             }
             None => match resource.swap() {
-                Some(swap) => Self::set_memory_and_swap(0, swap, false, cgroup_root)?,
-                None => Self::set_memory_and_swap(0, 0, false, cgroup_root)?,
+                Some(swap) => Self::set_memory_and_swap(0, swap, false, cgroup_root, strict)?,
+                None => Self::set_memory_and_swap(0, 0, false, cgroup_root, strict)?,
             },
         }
         Ok(())
@@ -358,12 +435,24 @@ mod tests {
         let limit = 512;
         let tmp = create_temp_dir("test_set_swap").expect("create temp directory for test");
         set_fixture(&tmp, CGROUP_MEMORY_SWAP_LIMIT, "0").expect("Set fixure for swap limit");
-        Memory::set_swap(limit, &tmp).expect("Set swap limit");
+        Memory::set_swap(limit, &tmp, false).expect("Set swap limit");
         let content =
             std::fs::read_to_string(tmp.join(CGROUP_MEMORY_SWAP_LIMIT)).expect("Read to string");
         assert_eq!(limit.to_string(), content)
     }
 
+    #[test]
+    fn test_set_swap_without_swap_accounting() {
+        let tmp = create_temp_dir("test_set_swap_without_swap_accounting")
+            .expect("create temp directory for test");
+
+        Memory::set_swap(1024, &tmp, false).expect("swap limit is ignored");
+        assert!(!tmp.join(CGROUP_MEMORY_SWAP_LIMIT).exists());
+
+        assert!(Memory::set_swap(1024, &tmp, true).is_err());
+        Memory::set_swap(-1, &tmp, true).expect("unlimited swap is skipped");
+    }
+
     #[test]
     fn test_set_memory_and_swap() {
         let tmp =
@@ -377,7 +466,7 @@ mod tests {
         {
             let limit = -1;
             let linux_memory = LinuxMemoryBuilder::default().limit(limit).build().unwrap();
-            Memory::apply(&linux_memory, &tmp).expect("Set memory and swap");
+            Memory::apply(&linux_memory, &tmp, false).expect("Set memory and swap");
 
             let limit_content =
                 std::fs::read_to_string(tmp.join(CGROUP_MEMORY_LIMIT)).expect("Read to string");
@@ -398,7 +487,7 @@ mod tests {
                 .swap(swap)
                 .build()
                 .unwrap();
-            Memory::apply(&linux_memory, &tmp).expect("Set memory and swap");
+            Memory::apply(&linux_memory, &tmp, false).expect("Set memory and swap");
 
             let limit_content =
                 std::fs::read_to_string(tmp.join(CGROUP_MEMORY_LIMIT)).expect("Read to string");
@@ -410,6 +499,57 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_set_kernel_memory() {
+        let tmp =
+            create_temp_dir("test_set_kernel_memory").expect("create temp directory for test");
+        set_fixture(&tmp, CGROUP_KERNEL_MEMORY_LIMIT, "0").expect("set fixture for kernel memory");
+        set_fixture(&tmp, CGROUP_KERNEL_TCP_MEMORY_LIMIT, "0")
+            .expect("set fixture for kernel tcp memory");
+        let memory = LinuxMemoryBuilder::default()
+            .kernel(1024)
+            .kernel_tcp(512)
+            .build()
+            .unwrap();
+
+        Memory::set_kernel_memory(&memory, &tmp, Some((4, 19))).expect("set kernel memory");
+        let kernel_content =
+            std::fs::read_to_string(tmp.join(CGROUP_KERNEL_MEMORY_LIMIT)).expect("read to string");
+        assert_eq!(kernel_content, "1024");
+        let tcp_content = std::fs::read_to_string(tmp.join(CGROUP_KERNEL_TCP_MEMORY_LIMIT))
+            .expect("read to string");
+        assert_eq!(tcp_content, "512");
+    }
+
+    #[test]
+    fn test_set_kernel_memory_deprecated() {
+        let tmp = create_temp_dir("test_set_kernel_memory_deprecated")
+            .expect("create temp directory for test");
+        set_fixture(&tmp, CGROUP_KERNEL_MEMORY_LIMIT, "0").expect("set fixture for kernel memory");
+        let memory = LinuxMemoryBuilder::default().kernel(1024).build().unwrap();
+
+        Memory::set_kernel_memory(&memory, &tmp, Some((5, 4))).expect("kernel memory is ignored");
+        let kernel_content =
+            std::fs::read_to_string(tmp.join(CGROUP_KERNEL_MEMORY_LIMIT)).expect("read to string");
+        assert_eq!(kernel_content, "0");
+    }
+
+    #[test]
+    fn test_set_kernel_memory_not_supported() {
+        let tmp = create_temp_dir("test_set_kernel_memory_not_supported")
+            .expect("create temp directory for test");
+        let memory = LinuxMemoryBuilder::default()
+            .kernel(1024)
+            .kernel_tcp(512)
+            .build()
+            .unwrap();
+
+        Memory::set_kernel_memory(&memory, &tmp, Some((4, 19)))
+            .expect("missing kernel memory files are ignored");
+        assert!(!tmp.join(CGROUP_KERNEL_MEMORY_LIMIT).exists());
+        assert!(!tmp.join(CGROUP_KERNEL_TCP_MEMORY_LIMIT).exists());
+    }
+
     quickcheck! {
             fn property_test_set_memory(linux_memory: LinuxMemory, disable_oom_killer: bool) -> bool {
                 let tmp =
@@ -434,6 +574,8 @@ mod tests {
                 let controller_opt = ControllerOpt {
                     resources: &linux_resources,
                     disable_oom_killer,
+                    annotations: None,
+                    strict_resources: false,
                     oom_score_adj: None,
                     freezer_state: None,
                 };
@@ -467,6 +609,9 @@ mod tests {
                 // check kernel memory limit
                 let kernel_content = std::fs::read_to_string(tmp.join(CGROUP_KERNEL_MEMORY_LIMIT)).expect("read kernel memory limit");
                 let kernel_check = match memory_limits.kernel() {
+                    Some(_) if Memory::kernel_memory_deprecated(common::kernel_version()) => {
+                        kernel_content == "0"
+                    }
                     Some(kernel) => {
                         kernel_content == kernel.to_string()
                     }
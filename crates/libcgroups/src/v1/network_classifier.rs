@@ -23,7 +23,13 @@ impl Controller for NetworkClassifier {
     }
 
     fn needs_to_handle<'a>(controller_opt: &'a ControllerOpt) -> Option<&'a Self::Resource> {
-        controller_opt.resources.network().as_ref()
+        // only require the subsystem if a class id has been requested, the network
+        // resources may contain interface priorities only
+        controller_opt
+            .resources
+            .network()
+            .as_ref()
+            .filter(|network| network.class_id().is_some())
     }
 }
 
@@ -41,7 +47,7 @@ impl NetworkClassifier {
 mod tests {
     use super::*;
     use crate::test::{create_temp_dir, set_fixture};
-    use oci_spec::runtime::LinuxNetworkBuilder;
+    use oci_spec::runtime::{LinuxNetworkBuilder, LinuxResourcesBuilder};
 
     #[test]
     fn test_apply_network_classifier() {
@@ -62,4 +68,26 @@ mod tests {
             std::fs::read_to_string(tmp.join("net_cls.classid")).expect("Read classID contents");
         assert_eq!(id.to_string(), content);
     }
+
+    #[test]
+    fn test_needs_to_handle_without_class_id() {
+        let network = LinuxNetworkBuilder::default()
+            .priorities(vec![])
+            .build()
+            .unwrap();
+        let resources = LinuxResourcesBuilder::default()
+            .network(network)
+            .build()
+            .unwrap();
+        let controller_opt = ControllerOpt {
+            resources: &resources,
+            freezer_state: None,
+            oom_score_adj: None,
+            disable_oom_killer: false,
+            annotations: None,
+            strict_resources: false,
+        };
+
+        assert!(NetworkClassifier::needs_to_handle(&controller_opt).is_none());
+    }
 }
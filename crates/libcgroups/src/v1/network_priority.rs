@@ -23,15 +23,33 @@ impl Controller for NetworkPriority {
     }
 
     fn needs_to_handle<'a>(controller_opt: &'a ControllerOpt) -> Option<&'a Self::Resource> {
-        controller_opt.resources.network().as_ref()
+        controller_opt
+            .resources
+            .network()
+            .as_ref()
+            .filter(|network| {
+                network
+                    .priorities()
+                    .as_ref()
+                    .map(|p| !p.is_empty())
+                    .unwrap_or_default()
+            })
     }
 }
 
 impl NetworkPriority {
     fn apply(root_path: &Path, network: &LinuxNetwork) -> Result<()> {
         if let Some(ni_priorities) = network.priorities() {
-            let priorities: String = ni_priorities.iter().map(|p| p.to_string()).collect();
-            common::write_cgroup_file_str(root_path.join("net_prio.ifpriomap"), priorities.trim())?;
+            // the kernel only parses a single 'interface priority' entry per write
+            for priority in ni_priorities {
+                common::write_cgroup_file_str(
+                    root_path.join("net_prio.ifpriomap"),
+                    &format!("{} {}", priority.name(), priority.priority()),
+                )
+                .with_context(|| {
+                    format!("failed to set priority of interface {}", priority.name())
+                })?;
+            }
         }
 
         Ok(())
@@ -61,7 +79,6 @@ mod tests {
                 .build()
                 .unwrap(),
         ];
-        let priorities_string = priorities.iter().map(|p| p.to_string()).collect::<String>();
         let network = LinuxNetworkBuilder::default()
             .priorities(priorities)
             .build()
@@ -69,8 +86,9 @@ mod tests {
 
         NetworkPriority::apply(&tmp, &network).expect("apply network priorities");
 
+        // every entry is written separately, so the file contains the last one
         let content =
             std::fs::read_to_string(tmp.join("net_prio.ifpriomap")).expect("Read classID contents");
-        assert_eq!(priorities_string.trim(), content);
+        assert_eq!(content, "b 2");
     }
 }
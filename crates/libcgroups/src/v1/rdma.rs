@@ -0,0 +1,77 @@
+use std::{collections::HashMap, path::Path};
+
+use anyhow::{Context, Result};
+
+use super::Controller;
+use crate::{
+    common::{self, ControllerOpt},
+    stats::{self, RdmaStats, StatsProvider},
+};
+use oci_spec::runtime::LinuxRdma;
+
+pub struct Rdma {}
+
+impl Controller for Rdma {
+    type Resource = HashMap<String, LinuxRdma>;
+
+    fn apply(controller_opt: &ControllerOpt, cgroup_root: &Path) -> Result<()> {
+        log::debug!("Apply rdma cgroup config");
+
+        if let Some(rdma) = Self::needs_to_handle(controller_opt) {
+            common::apply_rdma_limits(cgroup_root, rdma)
+                .context("failed to apply rdma resource restrictions")?;
+        }
+
+        Ok(())
+    }
+
+    fn needs_to_handle<'a>(controller_opt: &'a ControllerOpt) -> Option<&'a Self::Resource> {
+        controller_opt.resources.rdma().as_ref()
+    }
+}
+
+impl StatsProvider for Rdma {
+    type Stats = HashMap<String, RdmaStats>;
+
+    fn stats(cgroup_path: &Path) -> Result<Self::Stats> {
+        stats::rdma_stats(cgroup_path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test::{create_temp_dir, set_fixture};
+    use oci_spec::runtime::{LinuxRdmaBuilder, LinuxResourcesBuilder};
+    use std::fs;
+
+    #[test]
+    fn test_apply_rdma() {
+        let tmp = create_temp_dir("test_apply_rdma").expect("create temp directory for test");
+        let max = set_fixture(&tmp, "rdma.max", "").expect("set fixture for rdma.max");
+
+        let mut rdma = HashMap::new();
+        rdma.insert(
+            "mlx4_0".to_owned(),
+            LinuxRdmaBuilder::default()
+                .hca_handles(2u32)
+                .hca_objects(2000u32)
+                .build()
+                .unwrap(),
+        );
+        let resources = LinuxResourcesBuilder::default().rdma(rdma).build().unwrap();
+        let controller_opt = ControllerOpt {
+            resources: &resources,
+            freezer_state: None,
+            oom_score_adj: None,
+            disable_oom_killer: false,
+            annotations: None,
+            strict_resources: false,
+        };
+
+        <Rdma as Controller>::apply(&controller_opt, &tmp).expect("apply rdma");
+
+        let content = fs::read_to_string(max).expect("read rdma.max");
+        assert_eq!(content, "mlx4_0 hca_handle=2 hca_object=2000");
+    }
+}
@@ -1,13 +1,15 @@
 use std::fmt::Display;
 
-#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
 pub enum ControllerType {
     Cpu,
     CpuSet,
     Io,
     Memory,
     HugeTlb,
+    Misc,
     Pids,
+    Rdma,
 }
 
 impl Display for ControllerType {
@@ -18,7 +20,9 @@ impl Display for ControllerType {
             Self::Io => "io",
             Self::Memory => "memory",
             Self::HugeTlb => "hugetlb",
+            Self::Misc => "misc",
             Self::Pids => "pids",
+            Self::Rdma => "rdma",
         };
 
         write!(f, "{}", print)
@@ -31,7 +35,9 @@ pub const CONTROLLER_TYPES: &[ControllerType] = &[
     ControllerType::HugeTlb,
     ControllerType::Io,
     ControllerType::Memory,
+    ControllerType::Misc,
     ControllerType::Pids,
+    ControllerType::Rdma,
 ];
 
 #[derive(Clone, Copy, PartialEq, Eq, Hash)]
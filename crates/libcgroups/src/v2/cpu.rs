@@ -3,7 +3,7 @@ use std::path::Path;
 
 use crate::{
     common::{self, ControllerOpt},
-    stats::{CpuUsage, StatsProvider},
+    stats::{BatchedStatsProvider, CgroupFiles, CpuStats, StatsProvider},
 };
 
 use oci_spec::runtime::LinuxCpu;
@@ -12,30 +12,92 @@ use super::controller::Controller;
 
 const CGROUP_CPU_WEIGHT: &str = "cpu.weight";
 const CGROUP_CPU_MAX: &str = "cpu.max";
+const CGROUP_CPU_BURST: &str = "cpu.max.burst";
+const CGROUP_CPU_IDLE: &str = "cpu.idle";
 const DEFAULT_PERIOD: &str = "100000";
 const UNRESTRICTED_QUOTA: &str = "max";
 
 const CPU_STAT: &str = "cpu.stat";
+const CPU_PSI: &str = "cpu.pressure";
+
+/// Unified keys which are handled by the cpu controller instead of being
+/// passed through verbatim
+pub const UNIFIED_KEYS: &[&str] = &[CGROUP_CPU_BURST, CGROUP_CPU_IDLE];
 
 pub struct Cpu {}
 
 impl Controller for Cpu {
     fn apply(controller_opt: &ControllerOpt, path: &Path) -> Result<()> {
+        let idle = match controller_opt
+            .resources
+            .unified()
+            .as_ref()
+            .and_then(|u| u.get(CGROUP_CPU_IDLE))
+        {
+            Some(idle) => Some(Self::parse_idle(idle)?),
+            None => None,
+        };
+
+        // the kernel rejects changes of the weight while the cgroup is idle
+        if idle == Some(false) {
+            common::write_cgroup_file(path.join(CGROUP_CPU_IDLE), 0)?;
+        }
+
         if let Some(cpu) = &controller_opt.resources.cpu() {
             Self::apply(path, cpu).context("failed to apply cpu resource restrictions")?;
         }
 
+        if idle == Some(true) {
+            if controller_opt
+                .resources
+                .cpu()
+                .as_ref()
+                .and_then(|cpu| cpu.shares())
+                .is_some()
+            {
+                log::warn!("cpu shares have no effect as the cgroup is marked as idle");
+            }
+
+            common::write_cgroup_file(path.join(CGROUP_CPU_IDLE), 1)?;
+        }
+
+        // the runtime spec version we are using has no burst field yet, so the
+        // burst can only be requested through the unified resources
+        if let Some(burst) = controller_opt
+            .resources
+            .unified()
+            .as_ref()
+            .and_then(|u| u.get(CGROUP_CPU_BURST))
+        {
+            let quota = controller_opt
+                .resources
+                .cpu()
+                .as_ref()
+                .and_then(|cpu| cpu.quota());
+            Self::apply_burst(path, burst, quota).context("failed to apply cpu burst")?;
+        }
+
         Ok(())
     }
 }
 
 impl StatsProvider for Cpu {
-    type Stats = CpuUsage;
+    type Stats = CpuStats;
 
     fn stats(cgroup_path: &Path) -> Result<Self::Stats> {
-        let mut stats = CpuUsage::default();
+        Self::stats_from_files(&CgroupFiles::read(cgroup_path, &Self::stats_files()?)?)
+    }
+}
 
-        let stat_content = common::read_cgroup_file(cgroup_path.join(CPU_STAT))?;
+impl BatchedStatsProvider for Cpu {
+    fn stats_files() -> Result<Vec<String>> {
+        Ok(vec![CPU_STAT.to_owned(), CPU_PSI.to_owned()])
+    }
+
+    fn stats_from_files(files: &CgroupFiles) -> Result<Self::Stats> {
+        let mut stats = CpuStats::default();
+
+        let stat_content = files.content(CPU_STAT)?;
         for entry in stat_content.lines() {
             let parts: Vec<&str> = entry.split_ascii_whitespace().collect();
             if parts.len() != 2 {
@@ -44,13 +106,14 @@ impl StatsProvider for Cpu {
 
             let value = parts[1].parse()?;
             match parts[0] {
-                "usage_usec" => stats.usage_total = value,
-                "user_usec" => stats.usage_user = value,
-                "system_usec" => stats.usage_kernel = value,
+                "usage_usec" => stats.usage.usage_total = value,
+                "user_usec" => stats.usage.usage_user = value,
+                "system_usec" => stats.usage.usage_kernel = value,
                 _ => continue,
             }
         }
 
+        stats.psi = files.psi_stats(CPU_PSI)?;
         Ok(stats)
     }
 }
@@ -61,11 +124,11 @@ impl Cpu {
             bail!("realtime is not supported on cgroup v2 yet");
         }
 
-        if let Some(mut shares) = cpu.shares() {
-            shares = Self::convert_shares_to_cgroup2(shares);
-            if shares != 0 {
+        if let Some(shares) = cpu.shares() {
+            let weight = common::convert_shares_to_weight(shares);
+            if weight != 0 {
                 // will result in Erno 34 (numerical result out of range) otherwise
-                common::write_cgroup_file(path.join(CGROUP_CPU_WEIGHT), shares)?;
+                common::write_cgroup_file(path.join(CGROUP_CPU_WEIGHT), weight)?;
             }
         }
 
@@ -94,12 +157,38 @@ impl Cpu {
         Ok(())
     }
 
-    fn convert_shares_to_cgroup2(shares: u64) -> u64 {
-        if shares == 0 {
-            return 0;
+    // The burst has to be written after cpu.max, as the kernel rejects a burst
+    // which is larger than the quota
+    fn apply_burst(path: &Path, burst: &str, quota: Option<i64>) -> Result<()> {
+        let burst = burst.trim();
+        let value: u64 = burst
+            .parse()
+            .with_context(|| format!("invalid cpu burst value {}", burst))?;
+        if let Some(quota) = quota {
+            if quota > 0 && value > quota as u64 {
+                bail!(
+                    "cpu burst ({}) must not be larger than the cpu quota ({})",
+                    value,
+                    quota
+                );
+            }
         }
 
-        1 + ((shares - 2) * 9999) / 262142
+        common::write_cgroup_file(path.join(CGROUP_CPU_BURST), value)
+    }
+
+    // cpu.idle (since Linux 5.15) marks the cgroup as SCHED_IDLE, which gives it
+    // the lowest possible priority
+    fn parse_idle(idle: &str) -> Result<bool> {
+        match idle.trim() {
+            "0" => Ok(false),
+            "1" => Ok(true),
+            other => bail!(
+                "invalid value {} for {}, expected 0 or 1",
+                other,
+                CGROUP_CPU_IDLE
+            ),
+        }
     }
 
     fn is_realtime_requested(cpu: &LinuxCpu) -> bool {
@@ -118,9 +207,10 @@ impl Cpu {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::stats::CpuUsage;
     use crate::test::{create_temp_dir, set_fixture, setup};
-    use oci_spec::runtime::LinuxCpuBuilder;
-    use std::fs;
+    use oci_spec::runtime::{LinuxCpuBuilder, LinuxResourcesBuilder};
+    use std::{collections::HashMap, fs};
 
     #[test]
     fn test_set_shares() {
@@ -136,7 +226,7 @@ mod tests {
         // assert
         let content = fs::read_to_string(weight)
             .unwrap_or_else(|_| panic!("read {} file content", CGROUP_CPU_WEIGHT));
-        assert_eq!(content, 840.to_string());
+        assert_eq!(content, 2148.to_string());
     }
 
     #[test]
@@ -268,13 +358,77 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_set_burst() {
+        // arrange
+        let (tmp, burst) = setup("test_set_burst", CGROUP_CPU_BURST);
+
+        // act
+        Cpu::apply_burst(&tmp, "50000", Some(100000)).expect("apply cpu burst");
+
+        // assert
+        let content = fs::read_to_string(burst)
+            .unwrap_or_else(|_| panic!("read {} file content", CGROUP_CPU_BURST));
+        assert_eq!(content, "50000");
+    }
+
+    #[test]
+    fn test_burst_larger_than_quota() {
+        // arrange
+        let (tmp, _) = setup("test_burst_larger_than_quota", CGROUP_CPU_BURST);
+
+        // act
+        let result = Cpu::apply_burst(&tmp, "200000", Some(100000));
+
+        // assert
+        assert!(
+            result.is_err(),
+            "burst larger than quota should be rejected"
+        );
+    }
+
+    #[test]
+    fn test_set_idle() {
+        // arrange
+        let (tmp, idle) = setup("test_set_idle", CGROUP_CPU_IDLE);
+        let resources = LinuxResourcesBuilder::default()
+            .unified(HashMap::from([(
+                CGROUP_CPU_IDLE.to_owned(),
+                "1".to_owned(),
+            )]))
+            .build()
+            .unwrap();
+        let controller_opt = ControllerOpt {
+            resources: &resources,
+            freezer_state: None,
+            oom_score_adj: None,
+            disable_oom_killer: false,
+            annotations: None,
+            strict_resources: false,
+        };
+
+        // act
+        <Cpu as Controller>::apply(&controller_opt, &tmp).expect("apply cpu");
+
+        // assert
+        let content = fs::read_to_string(idle)
+            .unwrap_or_else(|_| panic!("read {} file content", CGROUP_CPU_IDLE));
+        assert_eq!(content, "1");
+    }
+
+    #[test]
+    fn test_invalid_idle() {
+        assert!(Cpu::parse_idle("2").is_err());
+        assert!(!Cpu::parse_idle("0").unwrap());
+    }
+
     #[test]
     fn test_stat_usage() {
         let tmp = create_temp_dir("test_stat_usage").expect("create temp directory for test");
         let content = ["usage_usec 7730", "user_usec 4387", "system_usec 3498"].join("\n");
         set_fixture(&tmp, CPU_STAT, &content).expect("create stat file");
 
-        let actual = Cpu::stats(&tmp).expect("get cgroup stats");
+        let actual = Cpu::stats(&tmp).expect("get cgroup stats").usage;
         let expected = CpuUsage {
             usage_total: 7730,
             usage_user: 4387,
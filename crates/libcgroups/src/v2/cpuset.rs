@@ -1,4 +1,4 @@
-use anyhow::{Context, Result};
+use anyhow::{bail, Context, Result};
 use std::path::Path;
 
 use crate::common::{self, ControllerOpt};
@@ -8,6 +8,11 @@ use super::controller::Controller;
 
 const CGROUP_CPUSET_CPUS: &str = "cpuset.cpus";
 const CGROUP_CPUSET_MEMS: &str = "cpuset.mems";
+const CGROUP_CPUSET_PARTITION: &str = "cpuset.cpus.partition";
+
+/// Unified keys which are handled by the cpuset controller instead of being
+/// passed through verbatim
+pub const UNIFIED_KEYS: &[&str] = &[CGROUP_CPUSET_PARTITION];
 
 pub struct CpuSet {}
 
@@ -18,6 +23,16 @@ impl Controller for CpuSet {
                 .context("failed to apply cpuset resource restrictions")?;
         }
 
+        if let Some(partition) = controller_opt
+            .resources
+            .unified()
+            .as_ref()
+            .and_then(|u| u.get(CGROUP_CPUSET_PARTITION))
+        {
+            Self::apply_partition(cgroup_path, partition)
+                .context("failed to apply cpuset partition")?;
+        }
+
         Ok(())
     }
 }
@@ -34,6 +49,26 @@ impl CpuSet {
 
         Ok(())
     }
+
+    // A partition can only be formed once the cpus have been set, so this needs to
+    // happen after the cpuset has been applied. The kernel accepts invalid partitions
+    // but reports them as such when the file is read back, e.g.
+    // "root invalid (Cpu list in cpuset.cpus not exclusive)"
+    fn apply_partition(path: &Path, partition: &str) -> Result<()> {
+        let partition = partition.trim();
+        if !matches!(partition, "member" | "root" | "isolated") {
+            bail!("invalid cpuset partition type {}", partition);
+        }
+
+        let partition_path = path.join(CGROUP_CPUSET_PARTITION);
+        common::write_cgroup_file_str(&partition_path, partition)?;
+        let state = common::read_cgroup_file(&partition_path)?;
+        if state.contains("invalid") {
+            bail!("cpuset partition could not be created: {}", state.trim());
+        }
+
+        Ok(())
+    }
 }
 
 #[cfg(test)]
@@ -62,6 +97,32 @@ mod tests {
         assert_eq!(content, "1-3");
     }
 
+    #[test]
+    fn test_set_partition() {
+        // arrange
+        let (tmp, partition) = setup("test_set_partition", CGROUP_CPUSET_PARTITION);
+
+        // act
+        CpuSet::apply_partition(&tmp, "root").expect("apply cpuset partition");
+
+        // assert
+        let content = fs::read_to_string(&partition)
+            .unwrap_or_else(|_| panic!("read {} file content", CGROUP_CPUSET_PARTITION));
+        assert_eq!(content, "root");
+    }
+
+    #[test]
+    fn test_set_invalid_partition_type() {
+        // arrange
+        let (tmp, _) = setup("test_set_invalid_partition_type", CGROUP_CPUSET_PARTITION);
+
+        // act
+        let result = CpuSet::apply_partition(&tmp, "exclusive");
+
+        // assert
+        assert!(result.is_err());
+    }
+
     #[test]
     fn test_set_mems() {
         // arrange
@@ -103,6 +103,25 @@ pub fn prog_attach(prog_fd: RawFd, cgroup_fd: RawFd) -> Result<()> {
     Ok(())
 }
 
+/// Atomically replaces the attached program old_prog_fd with prog_fd, so that
+/// there is no point in time where both or none of the programs are attached.
+/// Requires BPF_F_REPLACE, which is available since Linux 5.6.
+pub fn prog_replace(prog_fd: RawFd, old_prog_fd: RawFd, cgroup_fd: RawFd) -> Result<()> {
+    let mut opts = unsafe { std::mem::zeroed::<libbpf_sys::bpf_prog_attach_opts>() };
+    opts.sz = std::mem::size_of::<libbpf_sys::bpf_prog_attach_opts>() as _;
+    opts.flags = libbpf_sys::BPF_F_ALLOW_MULTI | libbpf_sys::BPF_F_REPLACE;
+    opts.replace_prog_fd = old_prog_fd;
+
+    let ret = unsafe {
+        libbpf_sys::bpf_prog_attach_xattr(prog_fd, cgroup_fd, libbpf_sys::BPF_CGROUP_DEVICE, &opts)
+    };
+
+    if ret != 0 {
+        return Err(errno::errno().into());
+    }
+    Ok(())
+}
+
 pub fn bump_memlock_rlimit() -> Result<()> {
     let rlimit = libc::rlimit {
         rlim_cur: 128 << 20,
@@ -1,4 +1,4 @@
-use std::os::unix::io::AsRawFd;
+use std::os::unix::io::{AsRawFd, RawFd};
 use std::path::Path;
 
 use anyhow::Result;
@@ -8,7 +8,7 @@ use nix::fcntl::OFlag;
 use nix::sys::stat::Mode;
 use oci_spec::runtime::LinuxDeviceCgroup;
 
-use crate::common::{default_allow_devices, default_devices, ControllerOpt};
+use crate::common::{container_device_rules, ControllerOpt};
 use crate::v2::controller::Controller;
 
 const LICENSE: &str = "Apache";
@@ -32,24 +32,9 @@ impl Devices {
     ) -> Result<()> {
         log::debug!("Apply Devices cgroup config");
 
-        // FIXME: should we start as "deny all"?
         let mut emulator = emulator::Emulator::with_default_allow(false);
-
-        // FIXME: apply user-defined and default rules in which order?
-        if let Some(devices) = linux_devices {
-            for d in devices {
-                log::debug!("apply user defined rule: {:?}", d);
-                emulator.add_rule(d)?;
-            }
-        }
-
-        for d in [
-            default_devices().iter().map(|d| d.into()).collect(),
-            default_allow_devices(),
-        ]
-        .concat()
-        {
-            log::debug!("apply default rule: {:?}", d);
+        for d in container_device_rules(linux_devices) {
+            log::debug!("apply device rule: {:?}", d);
             emulator.add_rule(&d)?;
         }
 
@@ -60,19 +45,6 @@ impl Devices {
         bpf::bump_memlock_rlimit()?;
         let prog_fd = bpf::prog_load(LICENSE, prog.bytecodes())?;
 
-        // FIXME: simple way to attach BPF program
-        //  1. get list of existing attached programs
-        //  2. attach this program (not use BPF_F_REPLACE, see below)
-        //  3. detach all programs of 1
-        //
-        // runc will use BPF_F_REPLACE to replace currently attached progam if:
-        //   1. BPF_F_REPLACE is supported by kernel
-        //   2. there is exactly one attached program
-        // https://github.com/opencontainers/runc/blob/8e6871a3b14bb74e0ef358aca3b9f8f9cb80f041/libcontainer/cgroups/ebpf/ebpf_linux.go#L165
-        //
-        // IMHO, this is too complicated, and in most cases, we just attach program once without
-        // already attached programs.
-
         let fd = nix::dir::Dir::open(
             cgroup_root.as_os_str(),
             OFlag::O_RDONLY | OFlag::O_DIRECTORY,
@@ -80,13 +52,43 @@ impl Devices {
         )?;
 
         let old_progs = bpf::prog_query(fd.as_raw_fd())?;
-        bpf::prog_attach(prog_fd, fd.as_raw_fd())?;
+        let result = Self::attach_program(prog_fd, &old_progs, fd.as_raw_fd());
+
+        // the kernel keeps its own reference to attached programs
+        let _ = nix::unistd::close(prog_fd);
+        for old_prog in &old_progs {
+            let _ = nix::unistd::close(old_prog.fd);
+        }
+
+        result
+    }
+
+    // Like runc, the currently attached program is replaced atomically with
+    // BPF_F_REPLACE if there is exactly one, e.g. on update of a container.
+    // Otherwise the new program is attached before all existing ones are
+    // detached, so that the device restrictions are never lifted.
+    // https://github.com/opencontainers/runc/blob/8e6871a3b14bb74e0ef358aca3b9f8f9cb80f041/libcontainer/cgroups/ebpf/ebpf_linux.go#L165
+    fn attach_program(
+        prog_fd: RawFd,
+        old_progs: &[bpf::ProgramInfo],
+        cgroup_fd: RawFd,
+    ) -> Result<()> {
+        if let [old_prog] = old_progs {
+            match bpf::prog_replace(prog_fd, old_prog.fd, cgroup_fd) {
+                Ok(()) => return Ok(()),
+                Err(err) => log::debug!(
+                    "failed to replace bpf program {}, falling back to attach and detach: {}",
+                    old_prog.id,
+                    err
+                ),
+            }
+        }
+
+        bpf::prog_attach(prog_fd, cgroup_fd)?;
         for old_prog in old_progs {
-            bpf::prog_detach2(old_prog.fd, fd.as_raw_fd())?;
+            bpf::prog_detach2(old_prog.fd, cgroup_fd)?;
         }
 
         Ok(())
     }
 }
-
-// FIXME: add tests, but how to?
@@ -54,4 +54,60 @@ impl Emulator {
     }
 }
 
-// FIXME: add some tests
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use oci_spec::runtime::LinuxDeviceCgroupBuilder;
+
+    fn rule(typ: LinuxDeviceType, allow: bool, access: Option<&str>) -> LinuxDeviceCgroup {
+        let mut builder = LinuxDeviceCgroupBuilder::default()
+            .typ(typ)
+            .allow(allow)
+            .major(1)
+            .minor(3);
+        if let Some(access) = access {
+            builder = builder.access(access);
+        }
+        builder.build().unwrap()
+    }
+
+    #[test]
+    fn test_add_rules() -> Result<()> {
+        let mut emulator = Emulator::with_default_allow(false);
+        let rules = vec![
+            rule(LinuxDeviceType::C, true, Some("rwm")),
+            rule(LinuxDeviceType::B, false, Some("r")),
+        ];
+        emulator.add_rules(&rules)?;
+
+        assert!(!emulator.default_allow);
+        assert_eq!(emulator.rules, rules);
+        Ok(())
+    }
+
+    #[test]
+    fn test_rule_without_access_is_discarded() -> Result<()> {
+        let mut emulator = Emulator::with_default_allow(false);
+        emulator.add_rule(&rule(LinuxDeviceType::C, true, None))?;
+
+        assert!(emulator.rules.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_all_rule_resets_rules() -> Result<()> {
+        let mut emulator = Emulator::with_default_allow(false);
+        emulator.add_rule(&rule(LinuxDeviceType::C, true, Some("rwm")))?;
+        emulator.add_rule(&rule(LinuxDeviceType::A, true, Some("rwm")))?;
+
+        assert!(emulator.default_allow);
+        assert!(emulator.rules.is_empty());
+
+        emulator.add_rule(&rule(LinuxDeviceType::C, false, Some("w")))?;
+        emulator.add_rule(&rule(LinuxDeviceType::A, false, None))?;
+
+        assert!(!emulator.default_allow);
+        assert!(emulator.rules.is_empty());
+        Ok(())
+    }
+}
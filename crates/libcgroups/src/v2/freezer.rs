@@ -1,18 +1,21 @@
 use anyhow::{bail, Context, Result};
 use std::{
     fs::OpenOptions,
-    io::{BufRead, BufReader, Read, Seek, SeekFrom, Write},
+    io::Write,
     path::Path,
-    str, thread,
-    time::Duration,
+    thread,
+    time::{Duration, Instant},
 };
 
-use crate::common::{ControllerOpt, FreezerState};
+use crate::common::{
+    self, ControllerOpt, FreezerState, FreezerTimeoutError, DEFAULT_FREEZER_TIMEOUT,
+};
 
 use super::controller::Controller;
 
 const CGROUP_FREEZE: &str = "cgroup.freeze";
 const CGROUP_EVENTS: &str = "cgroup.events";
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
 
 pub struct Freezer {}
 
@@ -28,6 +31,14 @@ impl Controller for Freezer {
 
 impl Freezer {
     fn apply(freezer_state: FreezerState, path: &Path) -> Result<()> {
+        Self::freeze_with_timeout(freezer_state, path, DEFAULT_FREEZER_TIMEOUT)
+    }
+
+    pub(crate) fn freeze_with_timeout(
+        freezer_state: FreezerState,
+        path: &Path,
+        timeout: Duration,
+    ) -> Result<()> {
         let state_str = match freezer_state {
             FreezerState::Undefined => return Ok(()),
             FreezerState::Frozen => "1",
@@ -48,74 +59,49 @@ impl Freezer {
             Ok(mut file) => file.write_all(state_str.as_bytes())?,
         };
 
-        // confirm that the cgroup did actually change states.
-        let actual_state = Self::read_freezer_state(path)?;
-        if !actual_state.eq(&freezer_state) {
-            bail!(
-                "expected \"cgroup.freeze\" to be in state {:?} but was in {:?}",
-                freezer_state,
-                actual_state
-            );
-        }
-
-        Ok(())
+        // confirm that the cgroup did actually change states. Writing to
+        // cgroup.freeze only requests the state change, whether it has been
+        // completed is reported by cgroup.events
+        Self::wait_for_state(path, freezer_state, timeout)
     }
 
-    fn read_freezer_state(path: &Path) -> Result<FreezerState> {
-        let mut buf = [0; 1];
-        OpenOptions::new()
-            .create(false)
-            .read(true)
-            .open(path.join(CGROUP_FREEZE))?
-            .read_exact(&mut buf)?;
-
-        let state = str::from_utf8(&buf)?;
-        match state {
-            "0" => Ok(FreezerState::Thawed),
-            "1" => Self::wait_frozen(path),
-            _ => bail!("unknown \"cgroup.freeze\" state: {}", state),
-        }
-    }
-
-    // wait_frozen polls cgroup.events until it sees "frozen 1" in it.
-    fn wait_frozen(path: &Path) -> Result<FreezerState> {
-        let f = OpenOptions::new()
-            .create(false)
-            .read(true)
-            .open(path.join(CGROUP_EVENTS))?;
-        let mut f = BufReader::new(f);
-
-        let wait_time = Duration::from_millis(10);
-        let max_iter = 1000;
+    fn wait_for_state(path: &Path, expected: FreezerState, timeout: Duration) -> Result<()> {
+        let start = Instant::now();
         let mut iter = 0;
-        let mut line = String::new();
-
         loop {
-            if iter == max_iter {
-                bail!(
-                    "timeout of {} ms reached waiting for the cgroup to freeze",
-                    wait_time.as_millis() * max_iter
-                );
-            }
-            line.clear();
-            let num_bytes = f.read_line(&mut line)?;
-            if num_bytes == 0 {
-                break;
+            let actual = Self::read_freezer_state(path)?;
+            if actual == expected {
+                if iter > 1 {
+                    log::debug!("reached state {:?} after {} retries", expected, iter);
+                }
+                return Ok(());
             }
-            if line.starts_with("frozen ") {
-                if line.starts_with("frozen 1") {
-                    if iter > 1 {
-                        log::debug!("frozen after {} retries", iter)
-                    }
-                    return Ok(FreezerState::Frozen);
+
+            if start.elapsed() >= timeout {
+                return Err(FreezerTimeoutError {
+                    state: expected,
+                    timeout,
                 }
-                iter += 1;
-                thread::sleep(wait_time);
-                f.seek(SeekFrom::Start(0))?;
+                .into());
             }
+
+            iter += 1;
+            thread::sleep(POLL_INTERVAL);
         }
+    }
+
+    fn read_freezer_state(path: &Path) -> Result<FreezerState> {
+        let events = common::read_cgroup_file(path.join(CGROUP_EVENTS))?;
+        let frozen = events
+            .lines()
+            .find_map(|l| l.strip_prefix("frozen "))
+            .with_context(|| format!("{} does not contain the frozen state", CGROUP_EVENTS))?;
 
-        Ok(FreezerState::Undefined)
+        match frozen.trim() {
+            "0" => Ok(FreezerState::Thawed),
+            "1" => Ok(FreezerState::Frozen),
+            state => bail!("unknown \"{}\" frozen state: {}", CGROUP_EVENTS, state),
+        }
     }
 }
 
@@ -154,6 +140,8 @@ mod tests {
 
         // set Thawed state.
         {
+            set_fixture(&tmp, CGROUP_EVENTS, "populated 0\nfrozen 0")
+                .expect("Set fixure for freezer state");
             let freezer_state = FreezerState::Thawed;
             Freezer::apply(freezer_state, &tmp).expect("Set freezer state");
 
@@ -189,4 +177,17 @@ mod tests {
             assert!(r.is_err());
         }
     }
+
+    #[test]
+    fn test_set_freezer_state_timeout() {
+        let tmp = create_temp_dir("test_set_freezer_state_timeout")
+            .expect("create temp directory for test");
+        set_fixture(&tmp, CGROUP_FREEZE, "").expect("Set fixure for freezer state");
+        set_fixture(&tmp, CGROUP_EVENTS, "populated 1\nfrozen 0")
+            .expect("Set fixure for freezer state");
+
+        let r = Freezer::freeze_with_timeout(FreezerState::Frozen, &tmp, Duration::from_millis(50));
+        let err = r.expect_err("cgroup never reports frozen");
+        assert!(err.downcast_ref::<FreezerTimeoutError>().is_some());
+    }
 }
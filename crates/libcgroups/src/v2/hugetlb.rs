@@ -4,7 +4,7 @@ use std::{collections::HashMap, path::Path};
 use super::controller::Controller;
 use crate::{
     common::{self, ControllerOpt},
-    stats::{parse_single_value, supported_page_sizes, HugeTlbStats, StatsProvider},
+    stats::{supported_page_sizes, BatchedStatsProvider, CgroupFiles, HugeTlbStats, StatsProvider},
 };
 
 use oci_spec::runtime::LinuxHugepageLimit;
@@ -28,13 +28,31 @@ impl StatsProvider for HugeTlb {
     type Stats = HashMap<String, HugeTlbStats>;
 
     fn stats(cgroup_path: &Path) -> Result<Self::Stats> {
+        Self::stats_from_files(&CgroupFiles::read(cgroup_path, &Self::stats_files()?)?)
+    }
+}
+
+impl BatchedStatsProvider for HugeTlb {
+    fn stats_files() -> Result<Vec<String>> {
+        Ok(supported_page_sizes()?
+            .iter()
+            .flat_map(|page_size| {
+                [
+                    format!("hugetlb.{}.current", page_size),
+                    format!("hugetlb.{}.events", page_size),
+                ]
+            })
+            .collect())
+    }
+
+    fn stats_from_files(files: &CgroupFiles) -> Result<Self::Stats> {
         let page_sizes = supported_page_sizes()?;
         let mut hugetlb_stats = HashMap::with_capacity(page_sizes.len());
 
         for page_size in page_sizes {
             hugetlb_stats.insert(
                 page_size.clone(),
-                Self::stats_for_page_size(cgroup_path, &page_size)?,
+                Self::stats_for_page_size(files, &page_size)?,
             );
         }
 
@@ -55,9 +73,18 @@ impl HugeTlb {
         }
 
         common::write_cgroup_file(
-            root_path.join(format!("hugetlb.{}.limit_in_bytes", hugetlb.page_size())),
+            root_path.join(format!("hugetlb.{}.max", hugetlb.page_size())),
             hugetlb.limit(),
         )?;
+
+        // reservation accounting is only available since Linux 5.7. If the kernel
+        // supports it, the reservations are limited as well, as otherwise pages
+        // which are reserved but not yet faulted in escape the limit
+        let rsvd_file = root_path.join(format!("hugetlb.{}.rsvd.max", hugetlb.page_size()));
+        if rsvd_file.exists() {
+            common::write_cgroup_file(rsvd_file, hugetlb.limit())?;
+        }
+
         Ok(())
     }
 
@@ -65,9 +92,9 @@ impl HugeTlb {
         (number != 0) && (number & (number - 1)) == 0
     }
 
-    fn stats_for_page_size(cgroup_path: &Path, page_size: &str) -> Result<HugeTlbStats> {
+    fn stats_for_page_size(files: &CgroupFiles, page_size: &str) -> Result<HugeTlbStats> {
         let events_file = format!("hugetlb.{}.events", page_size);
-        let events = common::read_cgroup_file(cgroup_path.join(&events_file))?;
+        let events = files.content(&events_file)?;
         let fail_count: u64 = events
             .lines()
             .find(|l| l.starts_with("max"))
@@ -77,7 +104,7 @@ impl HugeTlb {
             .unwrap_or_default();
 
         Ok(HugeTlbStats {
-            usage: parse_single_value(&cgroup_path.join(format!("hugetlb.{}.current", page_size)))?,
+            usage: files.single_value(&format!("hugetlb.{}.current", page_size))?,
             fail_count,
             ..Default::default()
         })
@@ -93,7 +120,7 @@ mod tests {
 
     #[test]
     fn test_set_hugetlb() {
-        let page_file_name = "hugetlb.2MB.limit_in_bytes";
+        let page_file_name = "hugetlb.2MB.max";
         let tmp = create_temp_dir("test_set_hugetlbv2").expect("create temp directory for test");
         set_fixture(&tmp, page_file_name, "0").expect("Set fixture for 2 MB page size");
 
@@ -107,6 +134,26 @@ mod tests {
         assert_eq!(hugetlb.limit().to_string(), content);
     }
 
+    #[test]
+    fn test_set_hugetlb_rsvd() {
+        let page_file_name = "hugetlb.2MB.max";
+        let rsvd_file_name = "hugetlb.2MB.rsvd.max";
+        let tmp =
+            create_temp_dir("test_set_hugetlbv2_rsvd").expect("create temp directory for test");
+        set_fixture(&tmp, page_file_name, "0").expect("Set fixture for 2 MB page size");
+        set_fixture(&tmp, rsvd_file_name, "0").expect("Set fixture for 2 MB reservations");
+
+        let hugetlb = LinuxHugepageLimitBuilder::default()
+            .page_size("2MB")
+            .limit(16384)
+            .build()
+            .unwrap();
+        HugeTlb::apply(&tmp, &hugetlb).expect("apply hugetlb");
+        let content =
+            read_to_string(tmp.join(rsvd_file_name)).expect("Read hugetlb rsvd file content");
+        assert_eq!(hugetlb.limit().to_string(), content);
+    }
+
     #[test]
     fn test_set_hugetlb_with_invalid_page_size() {
         let tmp = create_temp_dir("test_set_hugetlbv2_with_invalid_page_size")
@@ -127,7 +174,7 @@ mod tests {
 
     quickcheck! {
         fn property_test_set_hugetlb(hugetlb: LinuxHugepageLimit) -> bool {
-            let page_file_name = format!("hugetlb.{:?}.limit_in_bytes", hugetlb.page_size());
+            let page_file_name = format!("hugetlb.{:?}.max", hugetlb.page_size());
             let tmp = create_temp_dir("property_test_set_hugetlbv2").expect("create temp directory for test");
             set_fixture(&tmp, &page_file_name, "0").expect("Set fixture for page size");
             let result = HugeTlb::apply(&tmp, &hugetlb);
@@ -1,18 +1,25 @@
 use std::path::{Path, PathBuf};
 
 use anyhow::{bail, Context, Result};
+use nix::sys::stat::{self, SFlag};
 
 use crate::{
     common::{self, ControllerOpt},
-    stats::{self, BlkioDeviceStat, BlkioStats, StatsProvider},
+    stats::{self, BatchedStatsProvider, BlkioDeviceStat, BlkioStats, CgroupFiles, StatsProvider},
 };
 
-use super::controller::Controller;
+use super::{controller::Controller, translation};
 use oci_spec::runtime::LinuxBlockIo;
 
 const CGROUP_BFQ_IO_WEIGHT: &str = "io.bfq.weight";
 const CGROUP_IO_WEIGHT: &str = "io.weight";
+const CGROUP_IO_LATENCY: &str = "io.latency";
 const CGROUP_IO_STAT: &str = "io.stat";
+const CGROUP_IO_PSI: &str = "io.pressure";
+
+/// Unified keys which are handled by the io controller instead of being
+/// passed through verbatim
+pub const UNIFIED_KEYS: &[&str] = &[CGROUP_IO_LATENCY];
 
 pub struct Io {}
 
@@ -22,6 +29,17 @@ impl Controller for Io {
         if let Some(io) = &controller_opt.resources.block_io() {
             Self::apply(cgroup_root, io).context("failed to apply io resource restrictions")?;
         }
+
+        if let Some(latency) = controller_opt
+            .resources
+            .unified()
+            .as_ref()
+            .and_then(|u| u.get(CGROUP_IO_LATENCY))
+        {
+            Self::apply_latency(cgroup_root, latency)
+                .context("failed to apply io latency target")?;
+        }
+
         Ok(())
     }
 }
@@ -30,7 +48,17 @@ impl StatsProvider for Io {
     type Stats = BlkioStats;
 
     fn stats(cgroup_path: &Path) -> Result<Self::Stats> {
-        let keyed_data = stats::parse_nested_keyed_data(&cgroup_path.join(CGROUP_IO_STAT))?;
+        Self::stats_from_files(&CgroupFiles::read(cgroup_path, &Self::stats_files()?)?)
+    }
+}
+
+impl BatchedStatsProvider for Io {
+    fn stats_files() -> Result<Vec<String>> {
+        Ok(vec![CGROUP_IO_STAT.to_owned(), CGROUP_IO_PSI.to_owned()])
+    }
+
+    fn stats_from_files(files: &CgroupFiles) -> Result<Self::Stats> {
+        let keyed_data = files.nested_keyed_data(CGROUP_IO_STAT)?;
         let mut service_bytes = Vec::with_capacity(keyed_data.len());
         let mut serviced = Vec::with_capacity(keyed_data.len());
         for entry in keyed_data {
@@ -71,6 +99,7 @@ impl StatsProvider for Io {
         let stats = BlkioStats {
             service_bytes,
             serviced,
+            psi: files.psi_stats(CGROUP_IO_PSI)?,
             ..Default::default()
         };
 
@@ -87,10 +116,12 @@ impl Io {
     fn apply(root_path: &Path, blkio: &LinuxBlockIo) -> Result<()> {
         if let Some(weight_device) = blkio.weight_device() {
             for wd in weight_device {
-                common::write_cgroup_file(
-                    root_path.join(CGROUP_BFQ_IO_WEIGHT),
-                    &format!("{}:{} {}", wd.major(), wd.minor(), wd.weight().unwrap()),
-                )?;
+                if let Some(weight) = wd.weight() {
+                    common::write_cgroup_file(
+                        root_path.join(CGROUP_BFQ_IO_WEIGHT),
+                        &format!("{}:{} {}", wd.major(), wd.minor(), weight),
+                    )?;
+                }
             }
         }
         if let Some(leaf_weight) = blkio.leaf_weight() {
@@ -100,10 +131,17 @@ impl Io {
         }
         if let Some(io_weight) = blkio.weight() {
             if io_weight > 0 {
-                common::write_cgroup_file(
-                    root_path.join(CGROUP_IO_WEIGHT),
-                    format!("{}", io_weight),
-                )?;
+                // the bfq scheduler uses the same range as the runtime spec, so
+                // the weight can be used as is if bfq is available
+                let bfq_weight = root_path.join(CGROUP_BFQ_IO_WEIGHT);
+                if bfq_weight.exists() {
+                    common::write_cgroup_file(bfq_weight, io_weight)?;
+                } else {
+                    common::write_cgroup_file(
+                        root_path.join(CGROUP_IO_WEIGHT),
+                        translation::convert_blkio_weight(io_weight),
+                    )?;
+                }
             }
         }
 
@@ -145,7 +183,62 @@ impl Io {
 
         Ok(())
     }
+
+    // Each line of the unified value sets the latency target of one device,
+    // e.g. "8:0 target=75" or "/dev/sda target=75". Devices can be referenced
+    // by path, in which case they are resolved to their device number.
+    fn apply_latency(root_path: &Path, latency: &str) -> Result<()> {
+        for entry in latency.lines().map(str::trim).filter(|l| !l.is_empty()) {
+            let (device, target) = entry
+                .split_once(char::is_whitespace)
+                .map(|(device, target)| (device, target.trim()))
+                .filter(|(_, target)| {
+                    target
+                        .strip_prefix("target=")
+                        .map_or(false, |usec| usec.parse::<u64>().is_ok())
+                })
+                .with_context(|| {
+                    format!(
+                        "io latency {} does not conform to '<device> target=<usec>'",
+                        entry
+                    )
+                })?;
+            let (major, minor) = if device.starts_with('/') {
+                Self::device_number(Path::new(device))?
+            } else {
+                Self::parse_device_number(device).with_context(|| {
+                    format!(
+                        "device {} is neither a path nor a '<major>:<minor>' device number",
+                        device
+                    )
+                })?
+            };
+
+            common::write_cgroup_file_str(
+                root_path.join(CGROUP_IO_LATENCY),
+                &format!("{}:{} {}", major, minor, target),
+            )?;
+        }
+
+        Ok(())
+    }
+
+    fn device_number(device: &Path) -> Result<(u64, u64)> {
+        let stat =
+            stat::stat(device).with_context(|| format!("failed to stat device {:?}", device))?;
+        if SFlag::from_bits_truncate(stat.st_mode) & SFlag::S_IFMT != SFlag::S_IFBLK {
+            bail!("{:?} is not a block device", device);
+        }
+
+        Ok((stat::major(stat.st_rdev), stat::minor(stat.st_rdev)))
+    }
+
+    fn parse_device_number(device: &str) -> Option<(u64, u64)> {
+        let (major, minor) = device.split_once(':')?;
+        Some((major.parse().ok()?, minor.parse().ok()?))
+    }
 }
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -155,6 +248,7 @@ mod test {
         LinuxBlockIoBuilder, LinuxThrottleDeviceBuilder, LinuxWeightDeviceBuilder,
     };
     use std::fs;
+    use std::os::unix::fs::{FileTypeExt, MetadataExt};
 
     #[test]
     fn test_set_io_read_bps() {
@@ -267,9 +361,84 @@ mod test {
 
         Io::apply(&tmp, &blkio).expect("apply blkio");
         let content =
-            fs::read_to_string(throttle).unwrap_or_else(|_| panic!("read bfq_io_weight content"));
+            fs::read_to_string(throttle).unwrap_or_else(|_| panic!("read io_weight content"));
+
+        assert_eq!("910", content);
+    }
+
+    #[test]
+    fn test_set_ioweight_bfq() {
+        let (tmp, bfq_weight) = setup("test_set_io_weight_bfq", CGROUP_BFQ_IO_WEIGHT);
+        let io_weight = set_fixture(&tmp, CGROUP_IO_WEIGHT, "").expect("set io.weight fixture");
+        let blkio = LinuxBlockIoBuilder::default()
+            .weight(100u16)
+            .build()
+            .unwrap();
 
+        Io::apply(&tmp, &blkio).expect("apply blkio");
+        let content =
+            fs::read_to_string(bfq_weight).unwrap_or_else(|_| panic!("read bfq_io_weight content"));
         assert_eq!("100", content);
+        let content =
+            fs::read_to_string(io_weight).unwrap_or_else(|_| panic!("read io_weight content"));
+        assert_eq!("", content);
+    }
+
+    #[test]
+    fn test_set_io_latency() {
+        let (tmp, latency) = setup("test_set_io_latency", CGROUP_IO_LATENCY);
+
+        Io::apply_latency(&tmp, "8:0 target=75").expect("apply io latency");
+        let content = fs::read_to_string(latency).unwrap_or_else(|_| panic!("read latency"));
+
+        assert_eq!("8:0 target=75", content);
+    }
+
+    #[test]
+    fn test_set_io_latency_by_path() {
+        let (tmp, latency) = setup("test_set_io_latency_by_path", CGROUP_IO_LATENCY);
+        // any block device of the host will do
+        let device = match fs::read_dir("/dev").expect("read /dev").find_map(|entry| {
+            let path = entry.ok()?.path();
+            path.metadata()
+                .ok()?
+                .file_type()
+                .is_block_device()
+                .then(|| path)
+        }) {
+            Some(device) => device,
+            None => return,
+        };
+        let rdev = device.metadata().expect("get device metadata").rdev();
+
+        Io::apply_latency(&tmp, &format!("{} target=75", device.display()))
+            .expect("apply io latency");
+        let content = fs::read_to_string(latency).unwrap_or_else(|_| panic!("read latency"));
+
+        assert_eq!(
+            format!("{}:{} target=75", stat::major(rdev), stat::minor(rdev)),
+            content
+        );
+    }
+
+    #[test]
+    fn test_set_io_latency_invalid() {
+        let (tmp, _) = setup("test_set_io_latency_invalid", CGROUP_IO_LATENCY);
+
+        for latency in [
+            "/dev/null target=75",
+            "/does/not/exist target=75",
+            "sda target=75",
+            "8 target=75",
+            "8:0 75",
+            "8:0 target=x",
+        ] {
+            assert!(
+                Io::apply_latency(&tmp, latency).is_err(),
+                "{} should be rejected",
+                latency
+            );
+        }
     }
 
     #[test]
@@ -5,9 +5,13 @@ use std::{
     time::Duration,
 };
 
-use anyhow::Result;
+use anyhow::{bail, Context, Result};
 
-use nix::unistd::Pid;
+use nix::{
+    errno::Errno,
+    sys::signal::Signal,
+    unistd::{self, Gid, Pid, Uid},
+};
 
 #[cfg(feature = "cgroupsv2_devices")]
 use super::devices::Devices;
@@ -22,14 +26,28 @@ use super::{
     hugetlb::HugeTlb,
     io::Io,
     memory::Memory,
+    misc::Misc,
     pids::Pids,
+    rdma::Rdma,
+    translation,
     unified::Unified,
     util::{self, CGROUP_SUBTREE_CONTROL},
 };
 use crate::{
     common::{self, CgroupManager, ControllerOpt, FreezerState, PathBufExt, CGROUP_PROCS},
-    stats::{Stats, StatsProvider},
+    oom::OomWatcher,
+    stats::{BatchedStatsProvider, CgroupFiles, Stats},
 };
+
+const CGROUP_KILL: &str = "cgroup.kill";
+// cgroup which receives processes that prevent enabling controllers for the children
+const LEAF_CGROUP: &str = "leaf";
+const SUBTREE_CONTROL_RETRIES: u32 = 5;
+// lists the interface files which can be delegated to a less privileged user
+const CGROUP_DELEGATE: &str = "/sys/kernel/cgroup/delegate";
+// used if the kernel is too old to provide the list of delegatable files
+const DEFAULT_DELEGATE_FILES: &[&str] = &[CGROUP_PROCS, "cgroup.threads", CGROUP_SUBTREE_CONTROL];
+
 pub struct Manager {
     root_path: PathBuf,
     cgroup_path: PathBuf,
@@ -50,6 +68,14 @@ impl Manager {
     }
 
     fn create_unified_cgroup(&self, pid: Pid) -> Result<()> {
+        self.create_cgroup_dirs()?;
+        common::write_cgroup_file(&self.full_path.join(CGROUP_PROCS), pid)?;
+        Ok(())
+    }
+
+    /// Creates the cgroup and its parents and enables the available controllers
+    /// for all of them
+    fn create_cgroup_dirs(&self) -> Result<()> {
         let controllers: Vec<String> = util::get_available_controllers(&self.root_path)?
             .iter()
             .map(|c| format!("{}{}", "+", c.to_string()))
@@ -77,13 +103,216 @@ impl Manager {
             }
         }
 
-        common::write_cgroup_file(&self.full_path.join(CGROUP_PROCS), pid)?;
         Ok(())
     }
 
+    /// Applies the resource restrictions of the specified controllers only. This
+    /// is used on hybrid systems where only some controllers are attached to
+    /// the unified hierarchy.
+    pub(crate) fn apply_controllers(
+        &self,
+        controller_opt: &ControllerOpt,
+        controllers: &[ControllerType],
+    ) -> Result<()> {
+        let mut available = None;
+        for controller in controllers {
+            let result = match controller {
+                ControllerType::Cpu => Cpu::apply(controller_opt, &self.full_path),
+                ControllerType::CpuSet => CpuSet::apply(controller_opt, &self.full_path),
+                ControllerType::HugeTlb => HugeTlb::apply(controller_opt, &self.full_path),
+                ControllerType::Io => Io::apply(controller_opt, &self.full_path),
+                ControllerType::Memory => Memory::apply(controller_opt, &self.full_path),
+                ControllerType::Misc => Misc::apply(controller_opt, &self.full_path),
+                ControllerType::Pids => Pids::apply(controller_opt, &self.full_path),
+                ControllerType::Rdma => Rdma::apply(controller_opt, &self.full_path),
+            };
+
+            if let Err(err) = result {
+                if controller_opt.strict_resources {
+                    return Err(err);
+                }
+
+                // only failures caused by a missing controller are tolerated
+                if available.is_none() {
+                    available = Some(util::get_available_controllers(&self.root_path)?);
+                }
+                if let Some(available) = &available {
+                    if available.contains(controller) {
+                        return Err(err);
+                    }
+                }
+
+                log::warn!(
+                    "skipping {} resource restrictions, the controller is not available: {:?}",
+                    controller,
+                    err
+                );
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Applies the unified resource restrictions, which are not handled by one of
+    /// the controllers
+    pub(crate) fn apply_unified(&self, controller_opt: &ControllerOpt) -> Result<()> {
+        Unified::apply(
+            controller_opt,
+            &self.full_path,
+            util::get_available_controllers(&self.root_path)?,
+        )
+    }
+
+    /// Collects the statistics of the specified controllers only
+    pub(crate) fn controller_stats(
+        &self,
+        stats: &mut Stats,
+        controllers: &[ControllerType],
+    ) -> Result<()> {
+        // the files of all controllers are read in a single pass, which is
+        // considerably faster than opening them one after another by path
+        let mut names = Vec::new();
+        for controller in controllers {
+            names.extend(Self::stats_files(*controller)?);
+        }
+        let files = CgroupFiles::read(&self.full_path, &names)?;
+
+        for controller in controllers {
+            match controller {
+                ControllerType::Cpu => stats.cpu = Cpu::stats_from_files(&files)?,
+                ControllerType::HugeTlb => stats.hugetlb = HugeTlb::stats_from_files(&files)?,
+                ControllerType::Pids => stats.pids = Pids::stats_from_files(&files)?,
+                ControllerType::Memory => stats.memory = Memory::stats_from_files(&files)?,
+                ControllerType::Io => stats.blkio = Io::stats_from_files(&files)?,
+                ControllerType::Misc => stats.misc = Misc::stats_from_files(&files)?,
+                ControllerType::Rdma => stats.rdma = Rdma::stats_from_files(&files)?,
+                _ => continue,
+            }
+        }
+
+        Ok(())
+    }
+
+    fn stats_files(controller: ControllerType) -> Result<Vec<String>> {
+        match controller {
+            ControllerType::Cpu => Cpu::stats_files(),
+            ControllerType::HugeTlb => HugeTlb::stats_files(),
+            ControllerType::Pids => Pids::stats_files(),
+            ControllerType::Memory => Memory::stats_files(),
+            ControllerType::Io => Io::stats_files(),
+            ControllerType::Misc => Misc::stats_files(),
+            ControllerType::Rdma => Rdma::stats_files(),
+            ControllerType::CpuSet => Ok(Vec::new()),
+        }
+    }
+
     fn write_controllers(path: &Path, controllers: &[String]) -> Result<()> {
+        let mut failed = Vec::new();
         for controller in controllers {
-            common::write_cgroup_file_str(path.join(CGROUP_SUBTREE_CONTROL), controller)?;
+            if let Err(err) = Self::enable_controller(path, controller) {
+                failed.push((controller.trim_start_matches('+'), err));
+            }
+        }
+
+        if !failed.is_empty() {
+            let names: Vec<&str> = failed.iter().map(|(name, _)| *name).collect();
+            let errors: Vec<String> = failed
+                .iter()
+                .map(|(name, err)| format!("{}: {:#}", name, err))
+                .collect();
+            bail!(
+                "failed to enable controllers {} in {:?}: {}",
+                names.join(", "),
+                path,
+                errors.join("; ")
+            );
+        }
+
+        Ok(())
+    }
+
+    // Enabling a controller fails with EBUSY if the cgroup contains processes, as
+    // only leaf cgroups may contain processes if controllers are enabled for the
+    // children (no internal process constraint). This can happen if processes race
+    // into the cgroup or if we are running inside of a cgroup namespace.
+    fn enable_controller(path: &Path, controller: &str) -> Result<()> {
+        let mut attempts = 0;
+        loop {
+            match common::write_cgroup_file_str(path.join(CGROUP_SUBTREE_CONTROL), controller) {
+                Ok(()) => return Ok(()),
+                Err(err) if Self::is_busy(&err) && attempts < SUBTREE_CONTROL_RETRIES => {
+                    attempts += 1;
+                    log::debug!(
+                        "enabling {} in {:?} failed with EBUSY, retrying (attempt {})",
+                        controller,
+                        path,
+                        attempts
+                    );
+                    Self::move_processes_to_leaf(path)?;
+                    std::thread::sleep(Duration::from_millis(10 * attempts as u64));
+                }
+                Err(err) => return Err(err),
+            }
+        }
+    }
+
+    fn is_busy(err: &anyhow::Error) -> bool {
+        err.root_cause()
+            .downcast_ref::<std::io::Error>()
+            .and_then(|e| e.raw_os_error())
+            == Some(Errno::EBUSY as i32)
+    }
+
+    fn move_processes_to_leaf(path: &Path) -> Result<()> {
+        let procs = common::read_cgroup_file(path.join(CGROUP_PROCS))?;
+        if procs.trim().is_empty() {
+            return Ok(());
+        }
+
+        let leaf = path.join(LEAF_CGROUP);
+        if !leaf.exists() {
+            fs::create_dir(&leaf).with_context(|| format!("failed to create {:?}", leaf))?;
+        }
+
+        for pid in procs.lines() {
+            log::debug!("moving process {} from {:?} to {:?}", pid, path, leaf);
+            // the process may have exited in the meantime
+            if let Err(err) = common::write_cgroup_file_str(leaf.join(CGROUP_PROCS), pid) {
+                log::debug!("failed to move process {}: {:?}", pid, err);
+            }
+        }
+
+        Ok(())
+    }
+
+    fn delegate_files(delegate_list: &Path) -> Vec<String> {
+        match fs::read_to_string(delegate_list) {
+            Ok(files) => files
+                .lines()
+                .map(str::trim)
+                .filter(|f| !f.is_empty())
+                .map(|f| f.to_owned())
+                .collect(),
+            Err(_) => DEFAULT_DELEGATE_FILES
+                .iter()
+                .map(|f| f.to_string())
+                .collect(),
+        }
+    }
+
+    fn chown_cgroup(&self, delegate_list: &Path, uid: Uid, gid: Gid) -> Result<()> {
+        unistd::chown(&self.full_path, Some(uid), Some(gid))
+            .with_context(|| format!("failed to chown {:?}", self.full_path))?;
+
+        for file in Self::delegate_files(delegate_list) {
+            let path = self.full_path.join(&file);
+            // files of controllers which are not enabled do not exist
+            if !path.exists() {
+                continue;
+            }
+
+            unistd::chown(&path, Some(uid), Some(gid))
+                .with_context(|| format!("failed to chown {:?}", path))?;
         }
 
         Ok(())
@@ -97,27 +326,15 @@ impl CgroupManager for Manager {
     }
 
     fn apply(&self, controller_opt: &ControllerOpt) -> Result<()> {
-        for controller in CONTROLLER_TYPES {
-            match controller {
-                ControllerType::Cpu => Cpu::apply(controller_opt, &self.full_path)?,
-                ControllerType::CpuSet => CpuSet::apply(controller_opt, &self.full_path)?,
-                ControllerType::HugeTlb => HugeTlb::apply(controller_opt, &self.full_path)?,
-                ControllerType::Io => Io::apply(controller_opt, &self.full_path)?,
-                ControllerType::Memory => Memory::apply(controller_opt, &self.full_path)?,
-                ControllerType::Pids => Pids::apply(controller_opt, &self.full_path)?,
-            }
-        }
+        translation::translate(controller_opt.resources).log();
+        self.apply_controllers(controller_opt, CONTROLLER_TYPES)?;
 
         #[cfg(feature = "cgroupsv2_devices")]
-        Devices::apply(controller_opt, &self.cgroup_path)?;
+        Devices::apply(controller_opt, &self.full_path)?;
 
         for pseudoctlr in PSEUDO_CONTROLLER_TYPES {
             if let PseudoControllerType::Unified = pseudoctlr {
-                Unified::apply(
-                    controller_opt,
-                    &self.full_path,
-                    util::get_available_controllers(&self.root_path)?,
-                )?;
+                self.apply_unified(controller_opt)?;
             }
         }
 
@@ -147,28 +364,162 @@ impl CgroupManager for Manager {
             freezer_state: Some(state),
             oom_score_adj: None,
             disable_oom_killer: false,
+            annotations: None,
+            strict_resources: false,
         };
         Freezer::apply(&controller_opt, &self.full_path)
     }
 
+    fn freeze_with_timeout(&self, state: FreezerState, timeout: Duration) -> Result<()> {
+        Freezer::freeze_with_timeout(state, &self.full_path, timeout)
+    }
+
     fn stats(&self) -> Result<Stats> {
         let mut stats = Stats::default();
-
-        for subsystem in CONTROLLER_TYPES {
-            match subsystem {
-                ControllerType::Cpu => stats.cpu.usage = Cpu::stats(&self.full_path)?,
-                ControllerType::HugeTlb => stats.hugetlb = HugeTlb::stats(&self.full_path)?,
-                ControllerType::Pids => stats.pids = Pids::stats(&self.full_path)?,
-                ControllerType::Memory => stats.memory = Memory::stats(&self.full_path)?,
-                ControllerType::Io => stats.blkio = Io::stats(&self.full_path)?,
-                _ => continue,
-            }
-        }
-
+        self.controller_stats(&mut stats, CONTROLLER_TYPES)?;
         Ok(stats)
     }
 
     fn get_all_pids(&self) -> Result<Vec<Pid>> {
         common::get_all_pids(&self.full_path)
     }
+
+    fn oom_watcher(&self) -> Result<OomWatcher> {
+        OomWatcher::v2(&self.full_path)
+    }
+
+    fn prepare_clone_into_cgroup(&self) -> Result<Option<PathBuf>> {
+        self.create_cgroup_dirs()?;
+        Ok(Some(self.full_path.clone()))
+    }
+
+    fn perf_event_path(&self) -> Result<PathBuf> {
+        // perf_event is an implicit controller on cgroup v2, which means that every
+        // cgroup can be used for perf profiling and the controller is not listed in
+        // cgroup.controllers
+        Ok(self.full_path.clone())
+    }
+
+    fn delegate(&self, uid: Uid, gid: Gid) -> Result<()> {
+        self.chown_cgroup(Path::new(CGROUP_DELEGATE), uid, gid)
+    }
+
+    fn kill_all(&self, signal: Signal) -> Result<()> {
+        // cgroup.kill (since Linux 5.14) kills all processes atomically, but
+        // only supports SIGKILL
+        let kill_file = self.full_path.join(CGROUP_KILL);
+        if signal == Signal::SIGKILL && kill_file.exists() {
+            log::debug!("kill all processes through {:?}", kill_file);
+            return common::write_cgroup_file(kill_file, 1);
+        }
+
+        common::signal_all_pids(self, signal)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test::{create_temp_dir, set_fixture};
+
+    #[test]
+    fn test_kill_all_uses_cgroup_kill() -> Result<()> {
+        let tmp = create_temp_dir("test_kill_all_uses_cgroup_kill")?;
+        let kill_file = set_fixture(&tmp, CGROUP_KILL, "")?;
+        let manager = Manager::new(tmp.to_path_buf(), PathBuf::from("/"))?;
+
+        manager.kill_all(Signal::SIGKILL)?;
+        assert_eq!(fs::read_to_string(kill_file)?, "1");
+        Ok(())
+    }
+
+    #[test]
+    fn test_perf_event_path() -> Result<()> {
+        let tmp = create_temp_dir("test_perf_event_path")?;
+        let manager = Manager::new(tmp.to_path_buf(), PathBuf::from("/container"))?;
+
+        assert_eq!(manager.perf_event_path()?, tmp.join("container"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_write_controllers() -> Result<()> {
+        let tmp = create_temp_dir("test_write_controllers")?;
+        let subtree_control = set_fixture(&tmp, CGROUP_SUBTREE_CONTROL, "")?;
+
+        Manager::write_controllers(&tmp, &["+cpu".to_owned()])?;
+        assert_eq!(fs::read_to_string(subtree_control)?, "+cpu");
+        Ok(())
+    }
+
+    #[test]
+    fn test_write_controllers_lists_failed_controllers() -> Result<()> {
+        let tmp = create_temp_dir("test_write_controllers_lists_failed_controllers")?;
+
+        let err = Manager::write_controllers(&tmp, &["+cpu".to_owned(), "+memory".to_owned()])
+            .expect_err("subtree_control does not exist");
+        assert!(err.to_string().contains("cpu, memory"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_is_busy() {
+        let busy = anyhow::Error::from(std::io::Error::from_raw_os_error(Errno::EBUSY as i32))
+            .context("failed to write");
+        assert!(Manager::is_busy(&busy));
+
+        let other = anyhow::Error::from(std::io::Error::from_raw_os_error(Errno::EINVAL as i32));
+        assert!(!Manager::is_busy(&other));
+    }
+
+    #[test]
+    fn test_move_processes_to_leaf() -> Result<()> {
+        let tmp = create_temp_dir("test_move_processes_to_leaf")?;
+        set_fixture(&tmp, CGROUP_PROCS, "1000\n")?;
+        fs::create_dir(tmp.join(LEAF_CGROUP))?;
+        let leaf_procs = set_fixture(&tmp.join(LEAF_CGROUP), CGROUP_PROCS, "")?;
+
+        Manager::move_processes_to_leaf(&tmp)?;
+        assert_eq!(fs::read_to_string(leaf_procs)?, "1000");
+        Ok(())
+    }
+
+    #[test]
+    fn test_delegate_files() -> Result<()> {
+        let tmp = create_temp_dir("test_delegate_files")?;
+        let delegate = set_fixture(
+            &tmp,
+            "delegate",
+            "cgroup.procs\ncgroup.threads\ncgroup.subtree_control\nmemory.oom.group\n",
+        )?;
+
+        assert_eq!(
+            Manager::delegate_files(&delegate),
+            vec![
+                "cgroup.procs",
+                "cgroup.threads",
+                "cgroup.subtree_control",
+                "memory.oom.group"
+            ]
+        );
+        assert_eq!(
+            Manager::delegate_files(&tmp.join("missing")),
+            DEFAULT_DELEGATE_FILES
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_chown_cgroup() -> Result<()> {
+        let tmp = create_temp_dir("test_chown_cgroup")?;
+        let delegate = set_fixture(&tmp, "delegate", "cgroup.procs\nmemory.oom.group\n")?;
+        let cgroup = tmp.join("container");
+        fs::create_dir(&cgroup)?;
+        set_fixture(&cgroup, CGROUP_PROCS, "")?;
+        let manager = Manager::new(tmp.to_path_buf(), PathBuf::from("/container"))?;
+
+        // chowning to the current user is always permitted
+        manager.chown_cgroup(&delegate, unistd::getuid(), unistd::getgid())?;
+        Ok(())
+    }
 }
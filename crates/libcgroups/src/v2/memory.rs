@@ -1,29 +1,47 @@
 use anyhow::{bail, Context, Result};
-use std::path::Path;
+use std::{collections::HashMap, path::Path};
 
 use oci_spec::runtime::LinuxMemory;
 
 use crate::{
     common::{self, ControllerOpt},
-    stats::{self, MemoryData, MemoryStats, StatsProvider},
+    stats::{BatchedStatsProvider, CgroupFiles, MemoryData, MemoryStats, StatsProvider},
 };
 
-use super::controller::Controller;
+use super::{controller::Controller, translation};
 
 const CGROUP_MEMORY_SWAP: &str = "memory.swap.max";
 const CGROUP_MEMORY_MAX: &str = "memory.max";
 const CGROUP_MEMORY_LOW: &str = "memory.low";
+const CGROUP_MEMORY_MIN: &str = "memory.min";
+const CGROUP_MEMORY_HIGH: &str = "memory.high";
+const CGROUP_MEMORY_OOM_GROUP: &str = "memory.oom.group";
 const MEMORY_STAT: &str = "memory.stat";
+const MEMORY_PSI: &str = "memory.pressure";
+
+/// Unified keys which are handled by the memory controller instead of being
+/// passed through verbatim
+pub const UNIFIED_KEYS: &[&str] = &[
+    CGROUP_MEMORY_MIN,
+    CGROUP_MEMORY_LOW,
+    CGROUP_MEMORY_HIGH,
+    CGROUP_MEMORY_OOM_GROUP,
+];
 
 pub struct Memory {}
 
 impl Controller for Memory {
     fn apply(controller_opt: &ControllerOpt, cgroup_path: &Path) -> Result<()> {
         if let Some(memory) = &controller_opt.resources.memory() {
-            Self::apply(cgroup_path, memory)
+            Self::apply(cgroup_path, memory, controller_opt.strict_resources)
                 .context("failed to apply memory resource restrictions")?;
         }
 
+        if let Some(unified) = &controller_opt.resources.unified() {
+            Self::apply_unified(cgroup_path, unified)
+                .context("failed to apply memory protection and soft limits")?;
+        }
+
         Ok(())
     }
 }
@@ -32,11 +50,38 @@ impl StatsProvider for Memory {
     type Stats = MemoryStats;
 
     fn stats(cgroup_path: &Path) -> Result<Self::Stats> {
+        Self::stats_from_files(&CgroupFiles::read(cgroup_path, &Self::stats_files()?)?)
+    }
+}
+
+impl BatchedStatsProvider for Memory {
+    fn stats_files() -> Result<Vec<String>> {
+        let mut files: Vec<String> = ["memory", "memory.swap"]
+            .iter()
+            .flat_map(|prefix| {
+                ["current", "max", "events"]
+                    .iter()
+                    .map(move |name| format!("{}.{}", prefix, name))
+            })
+            .collect();
+        files.extend([MEMORY_STAT.to_owned(), MEMORY_PSI.to_owned()]);
+        Ok(files)
+    }
+
+    fn stats_from_files(files: &CgroupFiles) -> Result<Self::Stats> {
+        // there are no swap statistics if swap accounting is disabled
+        let memswap = if files.contains(CGROUP_MEMORY_SWAP) {
+            Self::get_memory_data(files, "memory.swap", "fail")?
+        } else {
+            MemoryData::default()
+        };
+
         let stats = MemoryStats {
-            memory: Self::get_memory_data(cgroup_path, "memory", "oom")?,
-            memswap: Self::get_memory_data(cgroup_path, "memory.swap", "fail")?,
+            memory: Self::get_memory_data(files, "memory", "oom")?,
+            memswap,
             hierarchy: true,
-            stats: stats::parse_flat_keyed_data(&cgroup_path.join(MEMORY_STAT))?,
+            stats: files.flat_keyed_data(MEMORY_STAT)?,
+            psi: files.psi_stats(MEMORY_PSI)?,
             ..Default::default()
         };
 
@@ -46,18 +91,13 @@ impl StatsProvider for Memory {
 
 impl Memory {
     fn get_memory_data(
-        cgroup_path: &Path,
+        files: &CgroupFiles,
         file_prefix: &str,
         fail_event: &str,
     ) -> Result<MemoryData> {
-        let usage =
-            stats::parse_single_value(&cgroup_path.join(format!("{}.{}", file_prefix, "current")))?;
-        let limit =
-            stats::parse_single_value(&cgroup_path.join(format!("{}.{}", file_prefix, "max")))?;
-
-        let events = stats::parse_flat_keyed_data(
-            &cgroup_path.join(format!("{}.{}", file_prefix, "events")),
-        )?;
+        let usage = files.single_value(&format!("{}.{}", file_prefix, "current"))?;
+        let limit = files.single_value(&format!("{}.{}", file_prefix, "max"))?;
+        let events = files.flat_keyed_data(&format!("{}.{}", file_prefix, "events"))?;
         let fail_count = if let Some((_, v)) = events.get_key_value(fail_event) {
             *v
         } else {
@@ -82,7 +122,32 @@ impl Memory {
         }
     }
 
-    fn apply(path: &Path, memory: &LinuxMemory) -> Result<()> {
+    // memory.swap.max does not exist if swap accounting has been disabled with
+    // swapaccount=0 on the kernel command line or if the kernel has been built
+    // without swap support. A swap limit is an error in strict mode then.
+    fn set_swap(path: &Path, val: i64, strict: bool) -> Result<()> {
+        let swap_max = path.join(CGROUP_MEMORY_SWAP);
+        if !swap_max.exists() {
+            if val == -1 {
+                log::debug!("swap accounting is disabled, skipping unlimited swap");
+            } else if val != 0 && strict {
+                bail!(
+                    "swap accounting is disabled, swap limit of {} can not be applied",
+                    val
+                );
+            } else if val != 0 {
+                log::warn!(
+                    "swap accounting is disabled, swap limit of {} will be ignored",
+                    val
+                );
+            }
+            return Ok(());
+        }
+
+        Memory::set(swap_max, val)
+    }
+
+    fn apply(path: &Path, memory: &LinuxMemory, strict: bool) -> Result<()> {
         // if nothing is set just exit right away
         if memory.reservation().is_none() && memory.limit().is_none() && memory.swap().is_none() {
             return Ok(());
@@ -97,29 +162,16 @@ impl Memory {
                     bail!("invalid swap value: {}", swap);
                 }
                 Some(swap) => {
-                    // -1 means max
-                    if swap == -1 || limit == -1 {
-                        Memory::set(path.join(CGROUP_MEMORY_SWAP), swap)?;
-                    } else {
-                        if swap < limit {
-                            bail!(
-                                "swap memory ({}) should be bigger than memory limit ({})",
-                                swap,
-                                limit
-                            );
-                        }
-
-                        // In cgroup v1 swap is memory+swap, but in cgroup v2 swap is
-                        // a separate value, so the swap value in the runtime spec needs
-                        // to be converted from the cgroup v1 value to the cgroup v2 value
-                        // by subtracting limit from swap
-                        Memory::set(path.join(CGROUP_MEMORY_SWAP), swap - limit)?;
-                    }
+                    Memory::set_swap(
+                        path,
+                        translation::convert_memsw_to_swap(limit, swap)?,
+                        strict,
+                    )?;
                     Memory::set(path.join(CGROUP_MEMORY_MAX), limit)?;
                 }
                 None => {
                     if limit == -1 {
-                        Memory::set(path.join(CGROUP_MEMORY_SWAP), -1)?;
+                        Memory::set_swap(path, -1, strict)?;
                     }
                     Memory::set(path.join(CGROUP_MEMORY_MAX), limit)?;
                 }
@@ -140,13 +192,44 @@ impl Memory {
 
         Ok(())
     }
+
+    // memory.min and memory.low protect memory from being reclaimed while
+    // memory.high throttles the cgroup before the hard limit is reached.
+    // These are applied after the OCI fields so that they take precedence
+    // over the value which was derived from the memory reservation.
+    // If memory.oom.group is set, the OOM killer kills all processes of the
+    // cgroup together instead of picking a single one, so that the container
+    // is not left behind in an inconsistent state
+    fn apply_unified(path: &Path, unified: &HashMap<String, String>) -> Result<()> {
+        for key in UNIFIED_KEYS {
+            if let Some(value) = unified.get(*key) {
+                let value = value.trim();
+                match *key {
+                    CGROUP_MEMORY_OOM_GROUP => {
+                        if value != "0" && value != "1" {
+                            bail!("invalid value {} for {}, expected 0 or 1", value, key);
+                        }
+                    }
+                    _ => {
+                        if value != "max" && value.parse::<u64>().is_err() {
+                            bail!("invalid value {} for {}", value, key);
+                        }
+                    }
+                }
+
+                common::write_cgroup_file_str(path.join(key), value)?;
+            }
+        }
+
+        Ok(())
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
     use crate::test::{create_temp_dir, set_fixture};
-    use oci_spec::runtime::LinuxMemoryBuilder;
+    use oci_spec::runtime::{LinuxMemoryBuilder, LinuxResourcesBuilder};
     use std::fs::read_to_string;
 
     #[test]
@@ -167,7 +250,7 @@ mod tests {
             .build()
             .unwrap();
 
-        Memory::apply(&tmp, &memory_limits).expect("apply memory limits");
+        Memory::apply(&tmp, &memory_limits, false).expect("apply memory limits");
 
         let limit_content = read_to_string(tmp.join(CGROUP_MEMORY_MAX)).expect("read memory limit");
         assert_eq!(limit_content, limit.to_string());
@@ -190,7 +273,7 @@ mod tests {
 
         let memory_limits = LinuxMemoryBuilder::default().limit(-1).build().unwrap();
 
-        Memory::apply(&tmp, &memory_limits).expect("apply memory limits");
+        Memory::apply(&tmp, &memory_limits, false).expect("apply memory limits");
 
         let limit_content = read_to_string(tmp.join(CGROUP_MEMORY_MAX)).expect("read memory limit");
         assert_eq!(limit_content, "max");
@@ -199,6 +282,27 @@ mod tests {
         assert_eq!(swap_content, "max");
     }
 
+    #[test]
+    fn test_set_memory_without_swap_accounting() {
+        let tmp = create_temp_dir("test_set_memory_without_swap_accounting_v2")
+            .expect("create temp directory for test");
+        set_fixture(&tmp, CGROUP_MEMORY_MAX, "0").expect("set fixture for memory limit");
+
+        let memory_limits = LinuxMemoryBuilder::default()
+            .limit(1024)
+            .swap(2048)
+            .build()
+            .unwrap();
+
+        Memory::apply(&tmp, &memory_limits, false).expect("apply memory limits");
+
+        let limit_content = read_to_string(tmp.join(CGROUP_MEMORY_MAX)).expect("read memory limit");
+        assert_eq!(limit_content, "1024");
+        assert!(!tmp.join(CGROUP_MEMORY_SWAP).exists());
+
+        assert!(Memory::apply(&tmp, &memory_limits, true).is_err());
+    }
+
     #[test]
     fn test_err_swap_no_memory() {
         let tmp =
@@ -209,7 +313,7 @@ mod tests {
 
         let memory_limits = LinuxMemoryBuilder::default().swap(512).build().unwrap();
 
-        let result = Memory::apply(&tmp, &memory_limits);
+        let result = Memory::apply(&tmp, &memory_limits, false);
 
         assert!(result.is_err());
     }
@@ -223,7 +327,7 @@ mod tests {
 
         let memory_limits = LinuxMemoryBuilder::default().limit(-2).build().unwrap();
 
-        let result = Memory::apply(&tmp, &memory_limits);
+        let result = Memory::apply(&tmp, &memory_limits, false);
 
         assert!(result.is_err());
     }
@@ -241,7 +345,7 @@ mod tests {
             .build()
             .unwrap();
 
-        let result = Memory::apply(&tmp, &memory_limits);
+        let result = Memory::apply(&tmp, &memory_limits, false);
 
         assert!(result.is_err());
     }
@@ -253,7 +357,7 @@ mod tests {
             set_fixture(&tmp, CGROUP_MEMORY_LOW, "0").expect("set fixture for memory reservation");
             set_fixture(&tmp, CGROUP_MEMORY_SWAP, "0").expect("set fixture for swap limit");
 
-            let result = Memory::apply(&tmp, &linux_memory);
+            let result = Memory::apply(&tmp, &linux_memory, false);
 
             // we need to check for expected errors first and foremost or we'll get false negatives
             // later
@@ -330,6 +434,76 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_set_memory_unified() {
+        let tmp =
+            create_temp_dir("test_set_memory_unified_v2").expect("create temp directory for test");
+        set_fixture(&tmp, CGROUP_MEMORY_MIN, "0").expect("set fixture for memory min");
+        set_fixture(&tmp, CGROUP_MEMORY_LOW, "0").expect("set fixture for memory low");
+        set_fixture(&tmp, CGROUP_MEMORY_HIGH, "max").expect("set fixture for memory high");
+
+        let mut unified = HashMap::new();
+        unified.insert(CGROUP_MEMORY_MIN.to_owned(), "1024".to_owned());
+        unified.insert(CGROUP_MEMORY_LOW.to_owned(), "2048".to_owned());
+        unified.insert(CGROUP_MEMORY_HIGH.to_owned(), "4096".to_owned());
+
+        Memory::apply_unified(&tmp, &unified).expect("apply unified memory settings");
+
+        let min = read_to_string(tmp.join(CGROUP_MEMORY_MIN)).expect("read memory min");
+        assert_eq!(min, "1024");
+        let low = read_to_string(tmp.join(CGROUP_MEMORY_LOW)).expect("read memory low");
+        assert_eq!(low, "2048");
+        let high = read_to_string(tmp.join(CGROUP_MEMORY_HIGH)).expect("read memory high");
+        assert_eq!(high, "4096");
+    }
+
+    #[test]
+    fn test_unified_overrides_reservation() {
+        let tmp = create_temp_dir("test_unified_overrides_reservation_v2")
+            .expect("create temp directory for test");
+        set_fixture(&tmp, CGROUP_MEMORY_LOW, "0").expect("set fixture for memory low");
+
+        let mut unified = HashMap::new();
+        unified.insert(CGROUP_MEMORY_LOW.to_owned(), "max".to_owned());
+        let resources = LinuxResourcesBuilder::default()
+            .memory(
+                LinuxMemoryBuilder::default()
+                    .reservation(512)
+                    .build()
+                    .unwrap(),
+            )
+            .unified(unified)
+            .build()
+            .unwrap();
+        let controller_opt = ControllerOpt {
+            resources: &resources,
+            freezer_state: None,
+            oom_score_adj: None,
+            disable_oom_killer: false,
+            annotations: None,
+            strict_resources: false,
+        };
+
+        <Memory as Controller>::apply(&controller_opt, &tmp).expect("apply memory");
+
+        let low = read_to_string(tmp.join(CGROUP_MEMORY_LOW)).expect("read memory low");
+        assert_eq!(low, "max");
+    }
+
+    #[test]
+    fn test_err_bad_unified_value() {
+        let tmp = create_temp_dir("test_err_bad_unified_value_v2")
+            .expect("create temp directory for test");
+        set_fixture(&tmp, CGROUP_MEMORY_HIGH, "max").expect("set fixture for memory high");
+
+        let mut unified = HashMap::new();
+        unified.insert(CGROUP_MEMORY_HIGH.to_owned(), "-1".to_owned());
+
+        let result = Memory::apply_unified(&tmp, &unified);
+
+        assert!(result.is_err());
+    }
+
     #[test]
     fn test_get_memory_data() {
         let tmp = create_temp_dir("test_stat_memory").expect("create test directory");
@@ -348,4 +522,25 @@ mod tests {
 
         assert_eq!(actual, expected);
     }
+
+    #[test]
+    fn test_set_oom_group() -> Result<()> {
+        let tmp = create_temp_dir("test_set_oom_group")?;
+        let oom_group = set_fixture(&tmp, CGROUP_MEMORY_OOM_GROUP, "0")?;
+        let unified = HashMap::from([(CGROUP_MEMORY_OOM_GROUP.to_owned(), "1".to_owned())]);
+
+        Memory::apply_unified(&tmp, &unified)?;
+        assert_eq!(read_to_string(oom_group)?, "1");
+        Ok(())
+    }
+
+    #[test]
+    fn test_set_invalid_oom_group() -> Result<()> {
+        let tmp = create_temp_dir("test_set_invalid_oom_group")?;
+        set_fixture(&tmp, CGROUP_MEMORY_OOM_GROUP, "0")?;
+        let unified = HashMap::from([(CGROUP_MEMORY_OOM_GROUP.to_owned(), "max".to_owned())]);
+
+        assert!(Memory::apply_unified(&tmp, &unified).is_err());
+        Ok(())
+    }
 }
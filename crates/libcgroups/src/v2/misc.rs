@@ -0,0 +1,140 @@
+use anyhow::{bail, Context, Result};
+use std::{collections::HashMap, path::Path};
+
+use crate::{
+    common::{self, ControllerOpt},
+    stats::{BatchedStatsProvider, CgroupFiles, StatsProvider},
+};
+
+use super::controller::Controller;
+
+const CGROUP_MISC_MAX: &str = "misc.max";
+const CGROUP_MISC_CURRENT: &str = "misc.current";
+
+/// Unified keys which are handled by the misc controller instead of being
+/// passed through verbatim
+pub const UNIFIED_KEYS: &[&str] = &[CGROUP_MISC_MAX];
+
+pub struct Misc {}
+
+impl Controller for Misc {
+    fn apply(controller_opt: &ControllerOpt, cgroup_path: &Path) -> Result<()> {
+        if let Some(limits) = controller_opt
+            .resources
+            .unified()
+            .as_ref()
+            .and_then(|u| u.get(CGROUP_MISC_MAX))
+        {
+            Self::apply(cgroup_path, limits)
+                .context("failed to apply misc resource restrictions")?;
+        }
+
+        Ok(())
+    }
+}
+
+impl StatsProvider for Misc {
+    type Stats = HashMap<String, u64>;
+
+    fn stats(cgroup_path: &Path) -> Result<Self::Stats> {
+        Self::stats_from_files(&CgroupFiles::read(cgroup_path, &Self::stats_files()?)?)
+    }
+}
+
+impl BatchedStatsProvider for Misc {
+    fn stats_files() -> Result<Vec<String>> {
+        Ok(vec![CGROUP_MISC_CURRENT.to_owned()])
+    }
+
+    fn stats_from_files(files: &CgroupFiles) -> Result<Self::Stats> {
+        // the misc controller is only available since Linux 5.13
+        if !files.contains(CGROUP_MISC_CURRENT) {
+            return Ok(HashMap::new());
+        }
+
+        files.flat_keyed_data(CGROUP_MISC_CURRENT)
+    }
+}
+
+impl Misc {
+    // The kernel only accepts a single 'resource limit' pair per write, so the
+    // value of the unified key may contain multiple newline separated entries,
+    // e.g. "sgx_epc 1048576\nsev max"
+    fn apply(path: &Path, limits: &str) -> Result<()> {
+        for entry in limits.lines().map(str::trim).filter(|l| !l.is_empty()) {
+            let fields: Vec<&str> = entry.split_ascii_whitespace().collect();
+            if fields.len() != 2 {
+                bail!("misc limit {} does not conform to 'resource limit'", entry);
+            }
+
+            if fields[1] != "max" && fields[1].parse::<u64>().is_err() {
+                bail!(
+                    "invalid limit {} for misc resource {}",
+                    fields[1],
+                    fields[0]
+                );
+            }
+
+            common::write_cgroup_file_str(path.join(CGROUP_MISC_MAX), entry)?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+    use crate::test::{create_temp_dir, set_fixture, setup};
+
+    #[test]
+    fn test_set_misc_limit() {
+        // arrange
+        let (tmp, max) = setup("test_set_misc_limit", CGROUP_MISC_MAX);
+
+        // act
+        Misc::apply(&tmp, "sgx_epc 1048576").expect("apply misc");
+
+        // assert
+        let content = fs::read_to_string(max)
+            .unwrap_or_else(|_| panic!("read {} file content", CGROUP_MISC_MAX));
+        assert_eq!(content, "sgx_epc 1048576");
+    }
+
+    #[test]
+    fn test_set_invalid_misc_limit() {
+        // arrange
+        let (tmp, _) = setup("test_set_invalid_misc_limit", CGROUP_MISC_MAX);
+
+        // act
+        let result = Misc::apply(&tmp, "sgx_epc lots");
+
+        // assert
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_stat_misc() {
+        let tmp = create_temp_dir("test_stat_misc").expect("create temp directory for test");
+        set_fixture(&tmp, CGROUP_MISC_CURRENT, "sgx_epc 4096\nsev 0\n")
+            .expect("create misc.current file");
+
+        let actual = Misc::stats(&tmp).expect("get cgroup stats");
+
+        let mut expected = HashMap::new();
+        expected.insert("sgx_epc".to_owned(), 4096);
+        expected.insert("sev".to_owned(), 0);
+        assert_eq!(actual, expected);
+    }
+
+    #[test]
+    fn test_stat_misc_unsupported() {
+        let tmp =
+            create_temp_dir("test_stat_misc_unsupported").expect("create temp directory for test");
+
+        let actual = Misc::stats(&tmp).expect("get cgroup stats");
+        assert!(actual.is_empty());
+    }
+}
@@ -4,7 +4,7 @@ use anyhow::{Context, Result};
 
 use crate::{
     common::{self, ControllerOpt},
-    stats::{self, PidStats, StatsProvider},
+    stats::{BatchedStatsProvider, CgroupFiles, PidStats, StatsProvider},
 };
 
 use super::controller::Controller;
@@ -26,7 +26,17 @@ impl StatsProvider for Pids {
     type Stats = PidStats;
 
     fn stats(cgroup_path: &Path) -> Result<Self::Stats> {
-        stats::pid_stats(cgroup_path)
+        Self::stats_from_files(&CgroupFiles::read(cgroup_path, &Self::stats_files()?)?)
+    }
+}
+
+impl BatchedStatsProvider for Pids {
+    fn stats_files() -> Result<Vec<String>> {
+        Ok(vec!["pids.current".to_owned(), "pids.max".to_owned()])
+    }
+
+    fn stats_from_files(files: &CgroupFiles) -> Result<Self::Stats> {
+        files.pid_stats()
     }
 }
 
@@ -0,0 +1,41 @@
+use std::{collections::HashMap, path::Path};
+
+use anyhow::{Context, Result};
+
+use crate::{
+    common::{self, ControllerOpt},
+    stats::{BatchedStatsProvider, CgroupFiles, RdmaStats, StatsProvider},
+};
+
+use super::controller::Controller;
+
+pub struct Rdma {}
+
+impl Controller for Rdma {
+    fn apply(controller_opt: &ControllerOpt, cgroup_path: &Path) -> Result<()> {
+        if let Some(rdma) = &controller_opt.resources.rdma() {
+            common::apply_rdma_limits(cgroup_path, rdma)
+                .context("failed to apply rdma resource restrictions")?;
+        }
+
+        Ok(())
+    }
+}
+
+impl StatsProvider for Rdma {
+    type Stats = HashMap<String, RdmaStats>;
+
+    fn stats(cgroup_path: &Path) -> Result<Self::Stats> {
+        Self::stats_from_files(&CgroupFiles::read(cgroup_path, &Self::stats_files()?)?)
+    }
+}
+
+impl BatchedStatsProvider for Rdma {
+    fn stats_files() -> Result<Vec<String>> {
+        Ok(vec!["rdma.current".to_owned()])
+    }
+
+    fn stats_from_files(files: &CgroupFiles) -> Result<Self::Stats> {
+        files.rdma_stats()
+    }
+}
@@ -0,0 +1,248 @@
+//! The resource restrictions of the runtime spec were designed for cgroup v1. Some
+//! of them have no direct equivalent on cgroup v2 and are either converted to the
+//! closest cgroup v2 interface file or cannot be applied at all. This module contains
+//! these conversions and reports which resources are affected by them.
+use anyhow::{bail, Result};
+use oci_spec::runtime::LinuxResources;
+
+/// Describes how a cgroup v1 resource restriction is handled on cgroup v2
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Translation {
+    /// Name of the resource in the runtime spec
+    pub resource: &'static str,
+    /// Cgroup v2 interface file which is used instead, if there is one
+    pub target: Option<&'static str>,
+    /// Explains how the value is translated or why it is dropped
+    pub reason: &'static str,
+}
+
+/// Lists the resource restrictions which are approximated or dropped on cgroup v2
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct TranslationReport {
+    /// Resources which are converted to the closest cgroup v2 equivalent
+    pub approximated: Vec<Translation>,
+    /// Resources which cannot be applied on cgroup v2
+    pub dropped: Vec<Translation>,
+}
+
+impl TranslationReport {
+    fn add_approximated(
+        &mut self,
+        resource: &'static str,
+        target: &'static str,
+        reason: &'static str,
+    ) {
+        self.approximated.push(Translation {
+            resource,
+            target: Some(target),
+            reason,
+        });
+    }
+
+    fn add_dropped(&mut self, resource: &'static str, reason: &'static str) {
+        self.dropped.push(Translation {
+            resource,
+            target: None,
+            reason,
+        });
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.approximated.is_empty() && self.dropped.is_empty()
+    }
+
+    /// Logs the approximated resources as debug and the dropped ones as warning
+    pub fn log(&self) {
+        for translation in &self.approximated {
+            log::debug!(
+                "{} is approximated by {}: {}",
+                translation.resource,
+                translation.target.unwrap_or_default(),
+                translation.reason
+            );
+        }
+
+        for translation in &self.dropped {
+            log::warn!(
+                "{} is not supported on cgroup v2 and will be ignored: {}",
+                translation.resource,
+                translation.reason
+            );
+        }
+    }
+}
+
+/// Determines which of the requested resource restrictions have no exact
+/// equivalent on cgroup v2
+pub fn translate(resources: &LinuxResources) -> TranslationReport {
+    let mut report = TranslationReport::default();
+
+    if let Some(cpu) = resources.cpu() {
+        if cpu.shares().unwrap_or_default() != 0 {
+            report.add_approximated(
+                "cpu.shares",
+                "cpu.weight",
+                "shares are converted to the weight range [1, 10000]",
+            );
+        }
+    }
+
+    if let Some(memory) = resources.memory() {
+        if memory.swap().is_some() {
+            report.add_approximated(
+                "memory.swap",
+                "memory.swap.max",
+                "the memory+swap limit is converted to a swap only limit",
+            );
+        }
+        if memory.reservation().is_some() {
+            report.add_approximated(
+                "memory.reservation",
+                "memory.low",
+                "the soft limit is converted to best effort memory protection",
+            );
+        }
+        if memory.kernel().is_some() {
+            report.add_dropped(
+                "memory.kernel",
+                "kernel memory is accounted to the memory limit",
+            );
+        }
+        if memory.kernel_tcp().is_some() {
+            report.add_dropped(
+                "memory.kernelTCP",
+                "kernel tcp memory is accounted to the memory limit",
+            );
+        }
+        if memory.swappiness().is_some() {
+            report.add_dropped("memory.swappiness", "there is no swappiness per cgroup");
+        }
+        if memory.disable_oom_killer().unwrap_or_default() {
+            report.add_dropped(
+                "memory.disableOOMKiller",
+                "the oom killer cannot be disabled per cgroup",
+            );
+        }
+    }
+
+    if let Some(blkio) = resources.block_io() {
+        if blkio.weight().unwrap_or_default() != 0 {
+            report.add_approximated(
+                "blockIO.weight",
+                "io.weight",
+                "the weight is converted to the range [1, 10000] unless the bfq scheduler is used",
+            );
+        }
+        if blkio
+            .weight_device()
+            .as_ref()
+            .map(|w| !w.is_empty())
+            .unwrap_or_default()
+        {
+            report.add_approximated(
+                "blockIO.weightDevice",
+                "io.bfq.weight",
+                "device weights require the bfq scheduler",
+            );
+        }
+    }
+
+    if let Some(network) = resources.network() {
+        if network.class_id().is_some() {
+            report.add_dropped(
+                "network.classID",
+                "there is no net_cls controller, use eBPF programs instead",
+            );
+        }
+        if network
+            .priorities()
+            .as_ref()
+            .map(|p| !p.is_empty())
+            .unwrap_or_default()
+        {
+            report.add_dropped(
+                "network.priorities",
+                "there is no net_prio controller, use eBPF programs instead",
+            );
+        }
+    }
+
+    report
+}
+
+/// Converts the blkio weight of the runtime spec, which is in the range of
+/// [10, 1000], to the range of io.weight, which is [1, 10000]
+pub fn convert_blkio_weight(weight: u16) -> u64 {
+    if weight == 0 {
+        return 0;
+    }
+
+    1 + (weight.saturating_sub(10) as u64 * 9999) / 990
+}
+
+/// Converts the cgroup v1 memory+swap limit of the runtime spec to the cgroup v2
+/// swap limit, which does not include the memory. A value of -1 means unlimited.
+pub fn convert_memsw_to_swap(limit: i64, memsw: i64) -> Result<i64> {
+    if memsw == -1 || limit == -1 {
+        return Ok(memsw);
+    }
+
+    if memsw < limit {
+        bail!(
+            "swap memory ({}) should be bigger than memory limit ({})",
+            memsw,
+            limit
+        );
+    }
+
+    Ok(memsw - limit)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use oci_spec::runtime::{LinuxMemoryBuilder, LinuxResourcesBuilder};
+
+    #[test]
+    fn test_convert_blkio_weight() {
+        assert_eq!(convert_blkio_weight(0), 0);
+        assert_eq!(convert_blkio_weight(10), 1);
+        assert_eq!(convert_blkio_weight(1000), 10000);
+    }
+
+    #[test]
+    fn test_convert_memsw_to_swap() -> Result<()> {
+        assert_eq!(convert_memsw_to_swap(1024, 4096)?, 3072);
+        assert_eq!(convert_memsw_to_swap(-1, 4096)?, 4096);
+        assert_eq!(convert_memsw_to_swap(1024, -1)?, -1);
+        assert!(convert_memsw_to_swap(4096, 1024).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_translate_memory() {
+        let resources = LinuxResourcesBuilder::default()
+            .memory(
+                LinuxMemoryBuilder::default()
+                    .limit(1024)
+                    .swap(4096)
+                    .kernel(512)
+                    .build()
+                    .unwrap(),
+            )
+            .build()
+            .unwrap();
+
+        let report = translate(&resources);
+        assert_eq!(report.approximated.len(), 1);
+        assert_eq!(report.approximated[0].target, Some("memory.swap.max"));
+        assert_eq!(report.dropped.len(), 1);
+        assert_eq!(report.dropped[0].resource, "memory.kernel");
+    }
+
+    #[test]
+    fn test_translate_nothing() {
+        let report = translate(&LinuxResources::default());
+        assert!(report.is_empty());
+    }
+}
@@ -1,8 +1,9 @@
 use std::{collections::HashMap, path::Path};
 
-use anyhow::{Context, Result};
+use anyhow::{bail, Context, Result};
+use oci_spec::runtime::LinuxResources;
 
-use super::controller_type::ControllerType;
+use super::{controller_type::ControllerType, cpu, cpuset, io, memory, misc, util};
 use crate::common::{self, ControllerOpt};
 
 pub struct Unified {}
@@ -14,8 +15,14 @@ impl Unified {
         controllers: Vec<ControllerType>,
     ) -> Result<()> {
         if let Some(unified) = &controller_opt.resources.unified() {
-            Self::apply_impl(unified, cgroup_path, &controllers)
-                .context("failed to apply unified resource restrictions")?;
+            Self::check_conflicts(unified, controller_opt.resources);
+            Self::apply_impl(
+                unified,
+                cgroup_path,
+                &controllers,
+                controller_opt.strict_resources,
+            )
+            .context("failed to apply unified resource restrictions")?;
         }
 
         Ok(())
@@ -25,35 +32,151 @@ impl Unified {
         unified: &HashMap<String, String>,
         cgroup_path: &Path,
         controllers: &[ControllerType],
+        strict: bool,
     ) -> Result<()> {
-        {
-            log::debug!("Apply unified cgroup config");
-            for (cgroup_file, value) in unified {
-                common::write_cgroup_file_str(cgroup_path.join(cgroup_file), value).map_err(
-                    |e| {
-                        let (subsystem, _) = cgroup_file
-                            .split_once(".")
-                            .with_context(|| {
-                                format!("failed to split {} with {}", cgroup_file, ".")
-                            })
-                            .unwrap();
-                        let context = if !controllers.iter().any(|c| c.to_string() == subsystem) {
-                            format!(
-                                "failed to set {} to {}: subsystem {} is not available",
-                                cgroup_file, value, subsystem
-                            )
-                        } else {
-                            format!("failed to set {} to {}: {}", cgroup_file, value, e)
-                        };
-
-                        e.context(context)
-                    },
-                )?;
+        log::debug!("Apply unified cgroup config");
+        // controllers which have been enabled for this cgroup by the parent
+        let enabled = cgroup_path.join(util::CGROUP_CONTROLLERS);
+        let enabled = if enabled.exists() {
+            Some(common::read_cgroup_file(enabled)?)
+        } else {
+            None
+        };
+
+        for (cgroup_file, value) in unified {
+            let subsystem = Self::subsystem(cgroup_file)?;
+            // these have already been applied by the dedicated controllers
+            if Self::is_handled_by_controller(cgroup_file) {
+                continue;
+            }
+
+            if let Some(enabled) = &enabled {
+                if subsystem != "cgroup" && !enabled.split_whitespace().any(|c| c == subsystem) {
+                    if strict {
+                        bail!(
+                            "cannot set {}: subsystem {} is not enabled for the cgroup",
+                            cgroup_file,
+                            subsystem
+                        );
+                    }
+
+                    log::warn!(
+                        "skipping {}: subsystem {} is not enabled for the cgroup",
+                        cgroup_file,
+                        subsystem
+                    );
+                    continue;
+                }
+            }
+
+            let available = controllers.iter().any(|c| c.to_string() == subsystem);
+            if let Err(e) = common::write_cgroup_file_str(cgroup_path.join(cgroup_file), value) {
+                if !available && !strict && subsystem != "cgroup" {
+                    log::warn!(
+                        "skipping {}: subsystem {} is not available",
+                        cgroup_file,
+                        subsystem
+                    );
+                    continue;
+                }
+
+                let context = if !available {
+                    format!(
+                        "failed to set {} to {}: subsystem {} is not available",
+                        cgroup_file, value, subsystem
+                    )
+                } else {
+                    format!("failed to set {} to {}: {}", cgroup_file, value, e)
+                };
+
+                return Err(e.context(context));
             }
         }
 
         Ok(())
     }
+
+    // Unified keys are cgroup interface files, which are named <subsystem>.<file>
+    // and are located directly in the cgroup directory
+    fn subsystem(cgroup_file: &str) -> Result<&str> {
+        if cgroup_file.contains('/') || cgroup_file.starts_with('.') {
+            bail!("unified key {} is not a cgroup interface file", cgroup_file);
+        }
+
+        let (subsystem, _) = cgroup_file
+            .split_once('.')
+            .with_context(|| format!("unified key {} is missing the subsystem", cgroup_file))?;
+        Ok(subsystem)
+    }
+
+    fn is_handled_by_controller(cgroup_file: &str) -> bool {
+        [
+            cpu::UNIFIED_KEYS,
+            cpuset::UNIFIED_KEYS,
+            io::UNIFIED_KEYS,
+            memory::UNIFIED_KEYS,
+            misc::UNIFIED_KEYS,
+        ]
+        .iter()
+        .any(|keys| keys.contains(&cgroup_file))
+    }
+
+    // Unified keys are applied after the structured resources, so they take
+    // precedence if both refer to the same cgroup file
+    fn check_conflicts(unified: &HashMap<String, String>, resources: &LinuxResources) {
+        for (cgroup_file, field) in Self::structured_files(resources) {
+            if unified.contains_key(cgroup_file) {
+                log::warn!(
+                    "unified resource {} overrides the value of linux.resources.{}",
+                    cgroup_file,
+                    field
+                );
+            }
+        }
+    }
+
+    // Returns the cgroup files which are set from the structured resources
+    fn structured_files(resources: &LinuxResources) -> Vec<(&'static str, &'static str)> {
+        let mut files = Vec::new();
+        if let Some(cpu) = resources.cpu() {
+            if cpu.shares().is_some() {
+                files.push(("cpu.weight", "cpu.shares"));
+            }
+            if cpu.quota().is_some() || cpu.period().is_some() {
+                files.push(("cpu.max", "cpu.quota"));
+            }
+            if cpu.cpus().is_some() {
+                files.push(("cpuset.cpus", "cpu.cpus"));
+            }
+            if cpu.mems().is_some() {
+                files.push(("cpuset.mems", "cpu.mems"));
+            }
+        }
+
+        if let Some(memory) = resources.memory() {
+            if memory.limit().is_some() {
+                files.push(("memory.max", "memory.limit"));
+            }
+            if memory.swap().is_some() {
+                files.push(("memory.swap.max", "memory.swap"));
+            }
+            if memory.reservation().is_some() {
+                files.push(("memory.low", "memory.reservation"));
+            }
+        }
+
+        if resources.pids().is_some() {
+            files.push(("pids.max", "pids.limit"));
+        }
+
+        if let Some(block_io) = resources.block_io() {
+            if block_io.weight().is_some() {
+                files.push(("io.weight", "blockIO.weight"));
+            }
+        }
+
+        files
+    }
 }
 
 #[cfg(test)]
@@ -95,6 +218,8 @@ mod tests {
             freezer_state: None,
             oom_score_adj: None,
             disable_oom_killer: false,
+            annotations: None,
+            strict_resources: false,
         };
 
         // act
@@ -133,6 +258,8 @@ mod tests {
             freezer_state: None,
             oom_score_adj: None,
             disable_oom_killer: false,
+            annotations: None,
+            strict_resources: true,
         };
 
         // act
@@ -166,6 +293,8 @@ mod tests {
             resources: &resources,
             oom_score_adj: None,
             disable_oom_killer: false,
+            annotations: None,
+            strict_resources: false,
             freezer_state: None,
         };
 
@@ -179,4 +308,75 @@ mod tests {
         // assert
         assert!(result.is_err());
     }
+
+    #[test]
+    fn test_set_unified_subsystem_not_enabled() {
+        // arrange
+        let tmp = create_temp_dir("test_set_unified_subsystem_not_enabled").unwrap();
+        set_fixture(&tmp, "cgroup.controllers", "cpu memory pids").unwrap();
+        let hugetlb_limit_path = set_fixture(&tmp, "hugetlb.1GB.max", "").unwrap();
+
+        let mut unified = HashMap::new();
+        unified.insert("hugetlb.1GB.max".to_owned(), "72348034".to_owned());
+
+        // act
+        let result = Unified::apply_impl(&unified, &tmp, &[ControllerType::HugeTlb], true);
+
+        // assert
+        assert!(result.is_err());
+        let hugetlb_limit = fs::read_to_string(hugetlb_limit_path).expect("read hugetlb limit");
+        assert_eq!(hugetlb_limit, "");
+    }
+
+    #[test]
+    fn test_skip_unified_subsystem_not_enabled() {
+        // arrange
+        let tmp = create_temp_dir("test_skip_unified_subsystem_not_enabled").unwrap();
+        set_fixture(&tmp, "cgroup.controllers", "cpu memory pids").unwrap();
+        let hugetlb_limit_path = set_fixture(&tmp, "hugetlb.1GB.max", "").unwrap();
+        let cpu_weight_path = set_fixture(&tmp, "cpu.weight", "").unwrap();
+
+        let mut unified = HashMap::new();
+        unified.insert("hugetlb.1GB.max".to_owned(), "72348034".to_owned());
+        unified.insert("cpu.weight".to_owned(), "5000".to_owned());
+
+        // act
+        let result = Unified::apply_impl(&unified, &tmp, &[ControllerType::Cpu], false);
+
+        // assert
+        assert!(result.is_ok());
+        let hugetlb_limit = fs::read_to_string(hugetlb_limit_path).expect("read hugetlb limit");
+        assert_eq!(hugetlb_limit, "");
+        let cpu_weight = fs::read_to_string(cpu_weight_path).expect("read cpu weight");
+        assert_eq!(cpu_weight, "5000");
+    }
+
+    #[test]
+    fn test_set_unified_invalid_key() {
+        let tmp = create_temp_dir("test_set_unified_invalid_key").unwrap();
+
+        for key in ["../cpu.weight", "weight", "cpu/cpu.weight"] {
+            let mut unified = HashMap::new();
+            unified.insert(key.to_owned(), "100".to_owned());
+
+            let result = Unified::apply_impl(&unified, &tmp, &[ControllerType::Cpu], false);
+            assert!(result.is_err(), "{} should be rejected", key);
+        }
+    }
+
+    #[test]
+    fn test_structured_files() {
+        let resources = LinuxResourcesBuilder::default()
+            .memory(
+                oci_spec::runtime::LinuxMemoryBuilder::default()
+                    .limit(1024)
+                    .build()
+                    .unwrap(),
+            )
+            .build()
+            .unwrap();
+
+        let files = Unified::structured_files(&resources);
+        assert_eq!(files, vec![("memory.max", "memory.limit")]);
+    }
 }
@@ -1,6 +1,6 @@
-use std::path::{Path, PathBuf};
+use std::path::{Component, Path, PathBuf};
 
-use anyhow::{anyhow, bail, Result};
+use anyhow::{anyhow, bail, Context, Result};
 use procfs::process::Process;
 
 use crate::common;
@@ -36,10 +36,76 @@ pub fn get_available_controllers(root_path: &Path) -> Result<Vec<ControllerType>
             "hugetlb" => controllers.push(ControllerType::HugeTlb),
             "io" => controllers.push(ControllerType::Io),
             "memory" => controllers.push(ControllerType::Memory),
+            "misc" => controllers.push(ControllerType::Misc),
             "pids" => controllers.push(ControllerType::Pids),
+            "rdma" => controllers.push(ControllerType::Rdma),
             tpe => log::warn!("Controller {} is not yet implemented.", tpe),
         }
     }
 
     Ok(controllers)
 }
+
+/// Returns the cgroup of the current process. If the process is running inside of
+/// a cgroup namespace, the path is relative to the root of the namespace.
+pub fn current_cgroup() -> Result<PathBuf> {
+    let cgroup = Process::myself()?
+        .cgroups()?
+        .into_iter()
+        .find(|c| c.hierarchy == 0)
+        .context("current process is not part of a cgroup v2 hierarchy")?;
+
+    parse_current_cgroup(&cgroup.pathname)
+}
+
+fn parse_current_cgroup(pathname: &str) -> Result<PathBuf> {
+    let path = PathBuf::from(pathname);
+    // processes which have been moved to a cgroup outside of their cgroup
+    // namespace see a path like /../sibling, which cannot be resolved from
+    // within the namespace
+    if path.components().any(|c| c == Component::ParentDir) {
+        bail!(
+            "current cgroup {:?} is outside of the cgroup namespace",
+            path
+        );
+    }
+
+    Ok(path)
+}
+
+/// Resolves the cgroup path of a container. Absolute paths are relative to the
+/// cgroup root (or the root of the cgroup namespace). Relative paths are resolved
+/// against the parent of the current cgroup, as the current cgroup contains processes
+/// and can therefore not enable controllers for its children.
+pub fn resolve_cgroup_path(cgroup_path: &Path) -> Result<PathBuf> {
+    if cgroup_path.is_absolute() {
+        return Ok(cgroup_path.to_owned());
+    }
+
+    let current = current_cgroup().context("failed to determine current cgroup")?;
+    let parent = current.parent().unwrap_or(&current);
+    Ok(parent.join(cgroup_path))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_resolve_absolute_cgroup_path() -> Result<()> {
+        let path = resolve_cgroup_path(Path::new("/youki/container"))?;
+        assert_eq!(path, PathBuf::from("/youki/container"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_current_cgroup() -> Result<()> {
+        assert_eq!(parse_current_cgroup("/")?, PathBuf::from("/"));
+        assert_eq!(
+            parse_current_cgroup("/user.slice/session-1.scope")?,
+            PathBuf::from("/user.slice/session-1.scope")
+        );
+        assert!(parse_current_cgroup("/../sibling").is_err());
+        Ok(())
+    }
+}
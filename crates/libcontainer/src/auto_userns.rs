@@ -0,0 +1,415 @@
+//! Automatic allocation of id ranges for user namespaces, similar to
+//! `--userns=auto` of podman. Every container receives its own slice of the
+//! subordinate id ranges of the user, so that no two containers share ids.
+use crate::{
+    namespaces::Namespaces,
+    rootless::{lookup_subordinate_range, SUBGID_FILE, SUBUID_FILE},
+};
+use anyhow::{bail, Context, Result};
+use nix::{
+    fcntl::{flock, FlockArg},
+    unistd::User,
+};
+use oci_spec::runtime::{LinuxIdMapping, LinuxIdMappingBuilder, LinuxNamespaceType, Spec};
+use serde::{Deserialize, Serialize};
+use std::{
+    fs::{self, File},
+    io::ErrorKind,
+    os::unix::io::AsRawFd,
+    path::Path,
+};
+
+const ALLOCATION_FILE: &str = "userns_auto.json";
+const LOCK_FILE: &str = "userns_auto.lock";
+
+/// Number of ids allocated to a container by default
+pub const DEFAULT_SIZE: u32 = 65536;
+
+/// Configuration for the automatic allocation of id ranges
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AutoUserns {
+    /// Number of uids and gids allocated to the container
+    pub size: u32,
+    /// Range (start, count) of host uids to allocate from. Defaults to the
+    /// subordinate uid range of the user in /etc/subuid
+    pub uid_pool: Option<(u32, u32)>,
+    /// Range (start, count) of host gids to allocate from. Defaults to the
+    /// subordinate gid range of the user in /etc/subgid
+    pub gid_pool: Option<(u32, u32)>,
+}
+
+impl Default for AutoUserns {
+    fn default() -> Self {
+        Self {
+            size: DEFAULT_SIZE,
+            uid_pool: None,
+            gid_pool: None,
+        }
+    }
+}
+
+/// Id ranges which have been allocated to a container
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Allocation {
+    pub uid_start: u32,
+    pub gid_start: u32,
+    pub size: u32,
+}
+
+impl Allocation {
+    /// Maps container uid 0 onwards to the allocated uid range
+    pub fn uid_mappings(&self) -> Result<Vec<LinuxIdMapping>> {
+        Ok(vec![LinuxIdMappingBuilder::default()
+            .container_id(0u32)
+            .host_id(self.uid_start)
+            .size(self.size)
+            .build()?])
+    }
+
+    /// Maps container gid 0 onwards to the allocated gid range
+    pub fn gid_mappings(&self) -> Result<Vec<LinuxIdMapping>> {
+        Ok(vec![LinuxIdMappingBuilder::default()
+            .container_id(0u32)
+            .host_id(self.gid_start)
+            .size(self.size)
+            .build()?])
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, container_dir: P) -> Result<()> {
+        let file = File::create(container_dir.as_ref().join(ALLOCATION_FILE))?;
+        serde_json::to_writer(&file, self)?;
+        Ok(())
+    }
+
+    /// Releases the ids allocated to a container
+    pub fn release<P: AsRef<Path>>(container_dir: P) -> Result<()> {
+        let path = container_dir.as_ref().join(ALLOCATION_FILE);
+        match fs::remove_file(&path) {
+            Err(err) if err.kind() != ErrorKind::NotFound => {
+                Err(err).with_context(|| format!("failed to remove {:?}", path))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Loads the allocation of a container, if ids have been allocated for it
+    pub fn load<P: AsRef<Path>>(container_dir: P) -> Result<Option<Self>> {
+        let path = container_dir.as_ref().join(ALLOCATION_FILE);
+        if !path.exists() {
+            return Ok(None);
+        }
+
+        let file = File::open(&path).with_context(|| format!("failed to open {:?}", path))?;
+        Ok(Some(serde_json::from_reader(&file).with_context(|| {
+            format!("failed to parse allocation {:?}", path)
+        })?))
+    }
+}
+
+impl AutoUserns {
+    /// Sets the id mappings of the spec to automatically allocated ranges if
+    /// the spec requests a new user namespace without specifying any mappings.
+    /// The allocation is recorded in the container directory and is released
+    /// when the container is deleted.
+    pub fn apply(
+        &self,
+        spec: &mut Spec,
+        root_path: &Path,
+        container_dir: &Path,
+    ) -> Result<Option<Allocation>> {
+        let mut linux = spec.linux().clone().context("no linux in spec")?;
+        let namespaces = Namespaces::from(linux.namespaces().as_ref());
+        match namespaces.get(LinuxNamespaceType::User) {
+            Some(ns) if ns.path().is_none() => {}
+            _ => bail!("automatic id allocation requires a new user namespace in the spec"),
+        }
+
+        if linux.uid_mappings().is_some() || linux.gid_mappings().is_some() {
+            tracing::debug!("spec already contains id mappings, skipping automatic allocation");
+            return Ok(None);
+        }
+
+        let allocation = self.allocate(root_path, container_dir)?;
+        tracing::debug!("allocated id ranges {:?}", allocation);
+        let mappings = allocation
+            .uid_mappings()
+            .and_then(|uid_mappings| Ok((uid_mappings, allocation.gid_mappings()?)));
+        let (uid_mappings, gid_mappings) = match mappings {
+            Ok(mappings) => mappings,
+            Err(err) => {
+                Allocation::release(container_dir)?;
+                return Err(err);
+            }
+        };
+        linux
+            .set_uid_mappings(Some(uid_mappings))
+            .set_gid_mappings(Some(gid_mappings));
+        spec.set_linux(Some(linux));
+
+        Ok(Some(allocation))
+    }
+
+    /// Allocates id ranges that are not used by any other container under
+    /// root_path and records the allocation in the container directory
+    pub fn allocate(&self, root_path: &Path, container_dir: &Path) -> Result<Allocation> {
+        if self.size == 0 {
+            bail!("the number of ids to allocate must be greater than zero");
+        }
+
+        let (uid_pool, gid_pool) = self.pools()?;
+
+        // serialize allocations of concurrently created containers. The lock
+        // is released when the file is closed.
+        let lock_path = root_path.join(LOCK_FILE);
+        let lock = File::create(&lock_path)
+            .with_context(|| format!("failed to create lock file {:?}", lock_path))?;
+        flock(lock.as_raw_fd(), FlockArg::LockExclusive)
+            .with_context(|| format!("failed to lock {:?}", lock_path))?;
+
+        let allocations = existing_allocations(root_path)?;
+        let uid_start = find_free_range(
+            uid_pool,
+            allocations.iter().map(|a| (a.uid_start, a.size)),
+            self.size,
+        )
+        .with_context(|| format!("no free range of {} uids in {:?}", self.size, uid_pool))?;
+        let gid_start = find_free_range(
+            gid_pool,
+            allocations.iter().map(|a| (a.gid_start, a.size)),
+            self.size,
+        )
+        .with_context(|| format!("no free range of {} gids in {:?}", self.size, gid_pool))?;
+
+        let allocation = Allocation {
+            uid_start,
+            gid_start,
+            size: self.size,
+        };
+        if let Err(err) = allocation.save(container_dir) {
+            Allocation::release(container_dir)?;
+            return Err(err.context("failed to record id allocation"));
+        }
+
+        Ok(allocation)
+    }
+
+    fn pools(&self) -> Result<((u32, u32), (u32, u32))> {
+        if let (Some(uid_pool), Some(gid_pool)) = (self.uid_pool, self.gid_pool) {
+            return Ok((uid_pool, gid_pool));
+        }
+
+        let uid = nix::unistd::geteuid();
+        let user_name = User::from_uid(uid)
+            .with_context(|| format!("failed to look up user {}", uid))?
+            .map(|u| u.name);
+
+        let uid_pool = match self.uid_pool {
+            Some(pool) => pool,
+            None => lookup_subordinate_range(
+                Path::new(SUBUID_FILE),
+                user_name.as_deref(),
+                uid.as_raw(),
+            )?
+            .with_context(|| {
+                format!(
+                    "no subordinate uid range for user {} in {}",
+                    uid, SUBUID_FILE
+                )
+            })?,
+        };
+        let gid_pool = match self.gid_pool {
+            Some(pool) => pool,
+            None => lookup_subordinate_range(
+                Path::new(SUBGID_FILE),
+                user_name.as_deref(),
+                uid.as_raw(),
+            )?
+            .with_context(|| {
+                format!(
+                    "no subordinate gid range for user {} in {}",
+                    uid, SUBGID_FILE
+                )
+            })?,
+        };
+
+        Ok((uid_pool, gid_pool))
+    }
+}
+
+fn existing_allocations(root_path: &Path) -> Result<Vec<Allocation>> {
+    let mut allocations = Vec::new();
+    for entry in
+        fs::read_dir(root_path).with_context(|| format!("failed to read {:?}", root_path))?
+    {
+        let path = entry?.path();
+        if !path.is_dir() {
+            continue;
+        }
+
+        match Allocation::load(&path) {
+            Ok(Some(allocation)) => allocations.push(allocation),
+            Ok(None) => {}
+            Err(err) => tracing::warn!("ignoring id allocation of {:?}: {:?}", path, err),
+        }
+    }
+
+    Ok(allocations)
+}
+
+/// Finds the lowest start of a range with size ids inside of pool that does
+/// not overlap with any of the used ranges
+fn find_free_range<I>(pool: (u32, u32), used: I, size: u32) -> Option<u32>
+where
+    I: Iterator<Item = (u32, u32)>,
+{
+    let mut used: Vec<(u64, u64)> = used
+        .map(|(start, count)| (u64::from(start), u64::from(start) + u64::from(count)))
+        .collect();
+    used.sort_unstable();
+
+    let size = u64::from(size);
+    let pool_end = u64::from(pool.0) + u64::from(pool.1);
+    let mut candidate = u64::from(pool.0);
+    for (start, end) in used {
+        if candidate + size <= start {
+            break;
+        }
+
+        candidate = candidate.max(end);
+    }
+
+    if candidate + size <= pool_end {
+        Some(candidate as u32)
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::create_temp_dir;
+    use oci_spec::runtime::{LinuxBuilder, LinuxNamespaceBuilder, SpecBuilder};
+
+    #[test]
+    fn test_find_free_range() {
+        let pool = (100000, 65536 * 3);
+        assert_eq!(
+            find_free_range(pool, vec![].into_iter(), 65536),
+            Some(100000)
+        );
+        assert_eq!(
+            find_free_range(pool, vec![(100000, 65536)].into_iter(), 65536),
+            Some(165536)
+        );
+        assert_eq!(
+            find_free_range(
+                pool,
+                vec![(165536, 65536), (100000, 65536)].into_iter(),
+                65536
+            ),
+            Some(231072)
+        );
+        // gaps that are too small are skipped
+        assert_eq!(
+            find_free_range(pool, vec![(100000, 10), (100020, 65536)].into_iter(), 65536),
+            Some(165556)
+        );
+        assert_eq!(
+            find_free_range(pool, vec![(100000, 10), (100020, 65536)].into_iter(), 10),
+            Some(100010)
+        );
+        // ranges outside of the pool are ignored
+        assert_eq!(
+            find_free_range(pool, vec![(0, 65536)].into_iter(), 65536),
+            Some(100000)
+        );
+        assert_eq!(
+            find_free_range(
+                pool,
+                vec![(100000, 65536), (165536, 65536), (231072, 65536)].into_iter(),
+                65536
+            ),
+            None
+        );
+    }
+
+    #[test]
+    fn test_allocate() -> Result<()> {
+        let root = create_temp_dir("test_auto_userns_allocate")?;
+        let auto_userns = AutoUserns {
+            size: 65536,
+            uid_pool: Some((100000, 65536 * 2)),
+            gid_pool: Some((200000, 65536 * 2)),
+        };
+
+        let first_dir = root.join("first");
+        fs::create_dir_all(&first_dir)?;
+        let first = auto_userns.allocate(&root, &first_dir)?;
+        assert_eq!(
+            first,
+            Allocation {
+                uid_start: 100000,
+                gid_start: 200000,
+                size: 65536
+            }
+        );
+        assert_eq!(Allocation::load(&first_dir)?, Some(first));
+
+        let second_dir = root.join("second");
+        fs::create_dir_all(&second_dir)?;
+        let second = auto_userns.allocate(&root, &second_dir)?;
+        assert_eq!(second.uid_start, 165536);
+        assert_eq!(second.gid_start, 265536);
+
+        let third_dir = root.join("third");
+        fs::create_dir_all(&third_dir)?;
+        assert!(auto_userns.allocate(&root, &third_dir).is_err());
+
+        // deleting a container releases its ids
+        fs::remove_dir_all(&first_dir)?;
+        assert_eq!(auto_userns.allocate(&root, &third_dir)?, first);
+
+        Allocation::release(&second_dir)?;
+        assert_eq!(Allocation::load(&second_dir)?, None);
+        assert_eq!(auto_userns.allocate(&root, &second_dir)?, second);
+        Ok(())
+    }
+
+    #[test]
+    fn test_apply() -> Result<()> {
+        let root = create_temp_dir("test_auto_userns_apply")?;
+        let container_dir = root.join("container");
+        fs::create_dir_all(&container_dir)?;
+        let auto_userns = AutoUserns {
+            size: 1000,
+            uid_pool: Some((100000, 65536)),
+            gid_pool: Some((100000, 65536)),
+        };
+
+        let user_ns = LinuxNamespaceBuilder::default()
+            .typ(LinuxNamespaceType::User)
+            .build()?;
+        let mut spec = SpecBuilder::default()
+            .linux(LinuxBuilder::default().namespaces(vec![user_ns]).build()?)
+            .build()?;
+
+        let allocation = auto_userns
+            .apply(&mut spec, &root, &container_dir)?
+            .expect("ids should be allocated");
+        let linux = spec.linux().as_ref().unwrap();
+        assert_eq!(linux.uid_mappings(), &Some(allocation.uid_mappings()?));
+        assert_eq!(linux.gid_mappings(), &Some(allocation.gid_mappings()?));
+
+        // mappings of the spec take precedence
+        assert!(auto_userns
+            .apply(&mut spec, &root, &container_dir)?
+            .is_none());
+
+        let mut spec = SpecBuilder::default()
+            .linux(LinuxBuilder::default().namespaces(vec![]).build()?)
+            .build()?;
+        assert!(auto_userns.apply(&mut spec, &root, &container_dir).is_err());
+        Ok(())
+    }
+}
@@ -125,14 +125,14 @@ impl CapabilityExt for SpecCapability {
 /// effective capability set is set of capabilities used by kernel to perform checks
 /// see https://man7.org/linux/man-pages/man7/capabilities.7.html for more information
 pub fn reset_effective<S: Syscall + ?Sized>(syscall: &S) -> Result<()> {
-    log::debug!("reset all caps");
+    tracing::debug!("reset all caps");
     syscall.set_capability(CapSet::Effective, &caps::all())?;
     Ok(())
 }
 
 /// Drop any extra granted capabilities, and reset to defaults which are in oci specification
 pub fn drop_privileges<S: Syscall + ?Sized>(cs: &LinuxCapabilities, syscall: &S) -> Result<()> {
-    log::debug!("dropping bounding capabilities to {:?}", cs.bounding());
+    tracing::debug!("dropping bounding capabilities to {:?}", cs.bounding());
     if let Some(bounding) = cs.bounding() {
         syscall.set_capability(CapSet::Bounding, &to_set(bounding))?;
     }
@@ -152,7 +152,7 @@ pub fn drop_privileges<S: Syscall + ?Sized>(cs: &LinuxCapabilities, syscall: &S)
     if let Some(ambient) = cs.ambient() {
         // check specifically for ambient, as those might not always be available
         if let Err(e) = syscall.set_capability(CapSet::Ambient, &to_set(ambient)) {
-            log::error!("failed to set ambient capabilities: {}", e);
+            tracing::error!("failed to set ambient capabilities: {}", e);
         }
     }
 
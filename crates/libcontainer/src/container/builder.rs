@@ -1,5 +1,11 @@
-use crate::syscall::Syscall;
-use std::path::PathBuf;
+use crate::{
+    executor::{DefaultExecutor, Executor},
+    rootless::RootlessMode,
+    syscall::Syscall,
+};
+use anyhow::{bail, Result};
+use nix::{fcntl, unistd::Pid};
+use std::{env, path::PathBuf};
 
 use super::{init_builder::InitContainerBuilder, tenant_builder::TenantContainerBuilder};
 pub struct ContainerBuilder<'a> {
@@ -16,6 +22,21 @@ pub struct ContainerBuilder<'a> {
     pub(super) console_socket: Option<PathBuf>,
     /// File descriptors to be passed into the container process
     pub(super) preserve_fds: i32,
+    /// Determines if rootless semantics are applied to the container
+    pub(super) rootless_mode: RootlessMode,
+    /// Create the working directory of the container process if it does
+    /// not exist
+    pub(super) create_cwd: bool,
+    /// Detach the container processes from the session of the caller
+    pub(super) detach: bool,
+    /// Names of variables of the environment of the caller which are
+    /// inherited by the container process
+    pub(super) inherited_env: Vec<String>,
+    /// Executes the payload of the container process
+    pub(super) executor: Box<dyn Executor>,
+    /// Allows to create containers although the runtime is not executed
+    /// from a sealed copy of its binary
+    pub(super) allow_unsealed_binary: bool,
 }
 
 /// Builder that can be used to configure the common properties of
@@ -56,6 +77,12 @@ impl<'a> ContainerBuilder<'a> {
             pid_file: None,
             console_socket: None,
             preserve_fds: 0,
+            rootless_mode: RootlessMode::default(),
+            create_cwd: true,
+            detach: false,
+            inherited_env: Vec::new(),
+            executor: Box::new(DefaultExecutor),
+            allow_unsealed_binary: false,
         }
     }
 
@@ -155,4 +182,198 @@ impl<'a> ContainerBuilder<'a> {
         self.preserve_fds = preserved_fds;
         self
     }
+
+    /// Sets if rootless semantics should be applied to the container. By
+    /// default this is determined automatically.
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use libcontainer::container::builder::ContainerBuilder;
+    /// # use libcontainer::rootless::RootlessMode;
+    /// # use libcontainer::syscall::syscall::create_syscall;
+    ///
+    /// ContainerBuilder::new("74f1a4cb3801".to_owned(), create_syscall().as_ref())
+    /// .with_rootless_mode(RootlessMode::Enabled);
+    /// ```
+    pub fn with_rootless_mode(mut self, mode: RootlessMode) -> Self {
+        self.rootless_mode = mode;
+        self
+    }
+
+    /// Sets if the working directory of the container process should be
+    /// created if it does not exist. Enabled by default.
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use libcontainer::container::builder::ContainerBuilder;
+    /// # use libcontainer::syscall::syscall::create_syscall;
+    ///
+    /// ContainerBuilder::new("74f1a4cb3801".to_owned(), create_syscall().as_ref())
+    /// .with_create_cwd(false);
+    /// ```
+    pub fn with_create_cwd(mut self, create_cwd: bool) -> Self {
+        self.create_cwd = create_cwd;
+        self
+    }
+
+    /// Sets if the container processes should be detached from the session
+    /// and the controlling terminal of the caller, so that the container
+    /// keeps running independently once the caller has exited. Disabled by
+    /// default.
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use libcontainer::container::builder::ContainerBuilder;
+    /// # use libcontainer::syscall::syscall::create_syscall;
+    ///
+    /// ContainerBuilder::new("74f1a4cb3801".to_owned(), create_syscall().as_ref())
+    /// .with_detach(true);
+    /// ```
+    pub fn with_detach(mut self, detach: bool) -> Self {
+        self.detach = detach;
+        self
+    }
+
+    /// Sets the names of variables of the environment of the caller which
+    /// are inherited by the container process, unless the spec sets them.
+    /// By default the environment is built from the spec only.
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use libcontainer::container::builder::ContainerBuilder;
+    /// # use libcontainer::syscall::syscall::create_syscall;
+    ///
+    /// ContainerBuilder::new("74f1a4cb3801".to_owned(), create_syscall().as_ref())
+    /// .with_inherited_env(vec!["TZ".to_owned()]);
+    /// ```
+    pub fn with_inherited_env(mut self, names: Vec<String>) -> Self {
+        self.inherited_env = names;
+        self
+    }
+
+    /// Sets the executor which executes the payload once the container
+    /// process has been set up. By default the program of `process.args` is
+    /// executed.
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use libcontainer::container::builder::ContainerBuilder;
+    /// # use libcontainer::executor::DefaultExecutor;
+    /// # use libcontainer::syscall::syscall::create_syscall;
+    ///
+    /// ContainerBuilder::new("74f1a4cb3801".to_owned(), create_syscall().as_ref())
+    /// .with_executor(DefaultExecutor);
+    /// ```
+    pub fn with_executor<E: Executor + 'static>(mut self, executor: E) -> Self {
+        self.executor = Box::new(executor);
+        self
+    }
+
+    /// Allows to create containers although the runtime is not executed from
+    /// a sealed copy of its binary. Otherwise a malicious container could
+    /// overwrite the binary through /proc/self/exe (CVE-2019-5736), which is
+    /// why this is disabled by default. The binary can be sealed on startup
+    /// with `pentacle::ensure_sealed`.
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use libcontainer::container::builder::ContainerBuilder;
+    /// # use libcontainer::syscall::syscall::create_syscall;
+    ///
+    /// ContainerBuilder::new("74f1a4cb3801".to_owned(), create_syscall().as_ref())
+    /// .with_allow_unsealed_binary(true);
+    /// ```
+    pub fn with_allow_unsealed_binary(mut self, allow: bool) -> Self {
+        self.allow_unsealed_binary = allow;
+        self
+    }
+
+    /// Ensures that the file descriptors to be preserved, which follow the
+    /// sockets of socket activation, are open. Otherwise files opened during
+    /// the creation of the container could take their place and leak into the
+    /// container.
+    pub(super) fn validate_preserved_fds(&self, listen_fds: i32) -> Result<()> {
+        if self.preserve_fds < 0 {
+            bail!(
+                "number of preserved fds must not be negative: {}",
+                self.preserve_fds
+            );
+        }
+
+        for fd in 3..3 + listen_fds + self.preserve_fds {
+            if fcntl::fcntl(fd, fcntl::F_GETFD).is_err() {
+                bail!("fd {} should be preserved but is not open", fd);
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Returns the number of sockets which have been passed to youki by systemd
+/// socket activation (see sd_listen_fds(3)). They start at fd 3 and are passed
+/// on to the container process.
+pub(super) fn listen_fds() -> i32 {
+    parse_listen_fds(
+        env::var("LISTEN_FDS").ok().as_deref(),
+        env::var("LISTEN_PID").ok().as_deref(),
+        Pid::this(),
+    )
+}
+
+fn parse_listen_fds(listen_fds: Option<&str>, listen_pid: Option<&str>, pid: Pid) -> i32 {
+    let listen_fds = match listen_fds {
+        Some(listen_fds) => listen_fds,
+        None => return 0,
+    };
+
+    // the sockets are meant for another process if the pid does not match,
+    // e.g. because they have been inherited by accident
+    if let Some(listen_pid) = listen_pid {
+        if listen_pid.parse::<i32>().ok() != Some(pid.as_raw()) {
+            tracing::debug!("ignoring LISTEN_FDS for process {}", listen_pid);
+            return 0;
+        }
+    }
+
+    match listen_fds.parse::<i32>() {
+        Ok(listen_fds) if listen_fds >= 0 => listen_fds,
+        _ => {
+            tracing::warn!(
+                "LISTEN_FDS entered is not a fd. Ignore the value: {:?}",
+                listen_fds
+            );
+            0
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::syscall::syscall::create_syscall;
+
+    #[test]
+    fn test_validate_preserved_fds() {
+        let syscall = create_syscall();
+        let builder = |preserve_fds| {
+            ContainerBuilder::new("74f1a4cb3801".to_owned(), syscall.as_ref())
+                .with_preserved_fds(preserve_fds)
+        };
+
+        assert!(builder(0).validate_preserved_fds(0).is_ok());
+        assert!(builder(-1).validate_preserved_fds(0).is_err());
+        assert!(builder(1 << 20).validate_preserved_fds(0).is_err());
+    }
+
+    #[test]
+    fn test_parse_listen_fds() {
+        let pid = Pid::from_raw(42);
+        assert_eq!(parse_listen_fds(None, None, pid), 0);
+        assert_eq!(parse_listen_fds(Some("2"), None, pid), 2);
+        assert_eq!(parse_listen_fds(Some("2"), Some("42"), pid), 2);
+        assert_eq!(parse_listen_fds(Some("2"), Some("43"), pid), 0);
+        assert_eq!(parse_listen_fds(Some("two"), Some("42"), pid), 0);
+        assert_eq!(parse_listen_fds(Some("-1"), None, pid), 0);
+    }
 }
@@ -1,15 +1,21 @@
 use super::{Container, ContainerStatus};
 use crate::{
+    executor::Executor,
     hooks,
+    log_driver::{self, LogOptions, LogPipes},
     notify_socket::NotifyListener,
-    process::{self, args::ContainerArgs},
+    pidfd,
+    process::{self, args::ContainerArgs, container_init_process, container_monitor_process},
+    rootfs::IdmappedMounts,
     rootless::Rootless,
+    spec_extensions::SpecExtensions,
     syscall::Syscall,
     utils,
 };
 use anyhow::{bail, Context, Result};
+use nix::unistd::{self, Pid};
 use oci_spec::runtime::Spec;
-use std::{fs, io::Write, os::unix::prelude::RawFd, path::PathBuf};
+use std::{fs, os::unix::prelude::RawFd, path::PathBuf};
 
 pub(super) struct ContainerBuilderImpl<'a> {
     /// Flag indicating if an init or a tenant container should be created
@@ -30,29 +36,76 @@ pub(super) struct ContainerBuilderImpl<'a> {
     /// Socket to communicate the file descriptor of the ptty
     pub console_socket: Option<RawFd>,
     /// Options for rootless containers
-    pub rootless: Option<Rootless<'a>>,
+    pub rootless: Option<Rootless>,
     /// Path to the Unix Domain Socket to communicate container start
     pub notify_path: PathBuf,
     /// Container state
     pub container: Option<Container>,
     /// File descriptos preserved/passed to the container init process.
     pub preserve_fds: i32,
+    /// Sockets passed by systemd socket activation, which precede the
+    /// preserved fds
+    pub listen_fds: i32,
+    /// Fail if resource restrictions cannot be applied because a controller
+    /// is not available
+    pub strict_resources: bool,
+    /// Settings of the spec which cannot be represented by oci-spec
+    pub spec_extensions: SpecExtensions,
+    /// Create the working directory of the container process if it does
+    /// not exist
+    pub create_cwd: bool,
+    /// Detach the container processes from the session of the caller
+    pub detach: bool,
+    /// Create the container although the runtime is not executed from a
+    /// sealed copy of its binary
+    pub allow_unsealed_binary: bool,
+    /// Keep the session keyring of the caller instead of creating a new one
+    pub no_new_keyring: bool,
+    /// Use MS_MOVE and chroot instead of pivot_root to change the root
+    pub no_pivot: bool,
+    /// Create missing sources of bind mounts
+    pub create_missing_bind_sources: bool,
+    /// Executes the payload of the container process
+    pub executor: Box<dyn Executor>,
+    /// Captures stdout and stderr of the container process in a log file
+    pub log_options: Option<LogOptions>,
+    /// Record the exit status of the init process with a monitor process
+    pub exit_monitor: bool,
 }
 
 impl<'a> ContainerBuilderImpl<'a> {
-    pub(super) fn create(&mut self) -> Result<()> {
-        if let Err(outer) = self.run_container().context("failed to create container") {
-            if let Err(inner) = self.cleanup_container() {
-                return Err(outer.context(inner));
-            }
+    #[tracing::instrument(level = "debug", skip_all)]
+    /// Creates the container process and returns its pid
+    pub(super) fn create(&mut self) -> Result<Pid> {
+        match self.run_container().context("failed to create container") {
+            Ok(pid) => Ok(pid),
+            Err(outer) => {
+                if let Err(inner) = self.cleanup_container() {
+                    return Err(outer.context(inner));
+                }
 
-            return Err(outer);
+                Err(outer)
+            }
         }
-
-        Ok(())
     }
 
-    fn run_container(&mut self) -> Result<()> {
+    fn run_container(&mut self) -> Result<Pid> {
+        // The container processes are forked from the runtime, so a malicious
+        // container could overwrite the runtime binary through /proc/self/exe
+        // (CVE-2019-5736) unless it is executed from a sealed memfd. youki
+        // takes care of this on startup, other users of the library have to
+        // call pentacle::ensure_sealed themselves or explicitly opt out.
+        if !pentacle::is_sealed() {
+            if !self.allow_unsealed_binary {
+                bail!(
+                    "the runtime is not executed from a sealed copy of its binary, \
+                    which allows containers to overwrite it (CVE-2019-5736)"
+                );
+            }
+
+            tracing::warn!("unsealed runtime binary has been allowed, containers may overwrite it");
+        }
+
         let linux = self.spec.linux().as_ref().context("no linux in spec")?;
         let cgroups_path = utils::get_cgroup_path(linux.cgroups_path(), &self.container_id);
         let cmanager = libcgroups::common::create_cgroup_manager(
@@ -60,8 +113,13 @@ impl<'a> ContainerBuilderImpl<'a> {
             self.use_systemd || self.rootless.is_some(),
             &self.container_id,
         )?;
-        let process = self.spec.process().as_ref().context("No process in spec")?;
-
+        let perf_event_cgroup = match cmanager.perf_event_path() {
+            Ok(path) => Some(path),
+            Err(err) => {
+                tracing::debug!("perf_event cgroup is not available: {:?}", err);
+                None
+            }
+        };
         if self.init {
             if let Some(hooks) = self.spec.hooks() {
                 hooks::run_hooks(hooks.create_runtime().as_ref(), self.container.as_ref())?
@@ -74,19 +132,13 @@ impl<'a> ContainerBuilderImpl<'a> {
         // namespace.
         let notify_socket: NotifyListener = NotifyListener::new(&self.notify_path)?;
 
-        // If Out-of-memory score adjustment is set in specification.  set the score
-        // value for the current process check
-        // https://dev.to/rrampage/surviving-the-linux-oom-killer-2ki9 for some more
-        // information.
-        //
-        // This has to be done before !dumpable because /proc/self/oom_score_adj
-        // is not writeable unless you're an privileged user (if !dumpable is
-        // set). All children inherit their parent's oom_score_adj value on
-        // fork(2) so this will always be propagated properly.
-        if let Some(oom_score_adj) = process.oom_score_adj() {
-            log::debug!("Set OOM score to {}", oom_score_adj);
-            let mut f = fs::File::create("/proc/self/oom_score_adj")?;
-            f.write_all(oom_score_adj.to_string().as_bytes())?;
+        // The oom score adjustment is inherited by the container processes on
+        // fork. It is set by the runtime, as decreasing it requires privileges
+        // which the container processes lack once they entered the user namespace.
+        // This has to be done before the process is made non-dumpable, as
+        // /proc/self/oom_score_adj is not writable by unprivileged users then.
+        if let Some(oom_score_adj) = self.spec.process().as_ref().and_then(|p| p.oom_score_adj()) {
+            container_init_process::set_oom_score_adj(oom_score_adj)?;
         }
 
         // Make the process non-dumpable, to avoid various race conditions that
@@ -101,6 +153,22 @@ impl<'a> ContainerBuilderImpl<'a> {
             prctl::set_dumpable(false).unwrap();
         }
 
+        let log_pipes = match (&self.log_options, &self.container) {
+            (Some(options), Some(container)) => Some(
+                log_driver::start(&container.root, options.clone())
+                    .context("failed to start log driver")?,
+            ),
+            _ => None,
+        };
+
+        // The id mapping of a mount can only be attached by the runtime, the
+        // init process lacks the privileges once it joined the user namespace
+        let idmapped_mounts = if self.init {
+            IdmappedMounts::new(self.spec).context("failed to create idmapped mounts")?
+        } else {
+            None
+        };
+
         // This intermediate_args will be passed to the container intermediate process,
         // therefore we will have to move all the variable by value. Since self
         // is a shared reference, we have to clone these variables here.
@@ -112,29 +180,74 @@ impl<'a> ContainerBuilderImpl<'a> {
             console_socket: self.console_socket,
             notify_socket,
             preserve_fds: self.preserve_fds,
+            listen_fds: self.listen_fds,
             container: &self.container,
             rootless: &self.rootless,
             cgroup_manager: cmanager,
+            strict_resources: self.strict_resources,
+            spec_extensions: &self.spec_extensions,
+            create_cwd: self.create_cwd,
+            detach: self.detach,
+            no_new_keyring: self.no_new_keyring,
+            no_pivot: self.no_pivot,
+            create_missing_bind_sources: self.create_missing_bind_sources,
+            executor: self.executor.as_ref(),
+            log_pipes,
+            idmapped_mounts: idmapped_mounts.as_ref(),
         };
 
-        let init_pid = process::container_main_process::container_main_process(&container_args)?;
+        let create_container =
+            || process::container_main_process::container_main_process(&container_args);
+        // the monitor has to wait until the state of the created container has
+        // been saved, so it is kept until the end of this function
+        let (_monitor, init_pid) = match &self.container {
+            Some(container) if self.exit_monitor => {
+                let console_socket = self.console_socket;
+                match container_monitor_process::fork_monitor(&container.root, || {
+                    let init_pid = create_container();
+                    // the monitor must not keep the console socket connected
+                    if let Some(console_socket) = console_socket {
+                        unistd::close(console_socket)?;
+                    }
+                    close_container_fds(log_pipes, idmapped_mounts.as_ref())?;
+                    init_pid
+                }) {
+                    Ok((monitor, init_pid)) => (Some(monitor), Ok(init_pid)),
+                    Err(err) => (None, Err(err)),
+                }
+            }
+            _ => (None, create_container()),
+        };
+        // the container process has its own copy of the pipes
+        close_container_fds(log_pipes, idmapped_mounts.as_ref())?;
+        let init_pid = init_pid?;
 
-        // if file to write the pid to is specified, write pid of the child
+        // if file to write the pid to is specified, write pid of the child.
+        // Higher level runtimes may poll for the pid file, so it must never
+        // be observed partially written.
         if let Some(pid_file) = &self.pid_file {
-            fs::write(&pid_file, format!("{}", init_pid)).context("failed to write pid file")?;
+            utils::write_file_atomically(pid_file, format!("{}", init_pid))
+                .context("failed to write pid file")?;
         }
 
         if let Some(container) = &mut self.container {
+            // the start time identifies the init process, even if its pid is
+            // reused after it has exited
+            let start_time = pidfd::start_time(init_pid)
+                .context("failed to determine start time of the init process")?;
+
             // update status and pid of the container process
             container
                 .set_status(ContainerStatus::Created)
                 .set_creator(nix::unistd::geteuid().as_raw())
                 .set_pid(init_pid.as_raw())
+                .set_init_process_start(Some(start_time))
+                .set_perf_event_cgroup(perf_event_cgroup)
                 .save()
                 .context("Failed to save container state")?;
         }
 
-        Ok(())
+        Ok(init_pid)
     }
 
     fn cleanup_container(&self) -> Result<()> {
@@ -168,3 +281,17 @@ impl<'a> ContainerBuilderImpl<'a> {
         Ok(())
     }
 }
+
+fn close_container_fds(
+    log_pipes: Option<LogPipes>,
+    idmapped_mounts: Option<&IdmappedMounts>,
+) -> Result<()> {
+    if let Some(log_pipes) = log_pipes {
+        log_pipes.close()?;
+    }
+    if let Some(idmapped_mounts) = idmapped_mounts {
+        idmapped_mounts.close()?;
+    }
+
+    Ok(())
+}
@@ -3,14 +3,22 @@ use std::ffi::OsString;
 use std::fs;
 use std::path::{Path, PathBuf};
 
-use anyhow::Result;
+use anyhow::{Context, Result};
 use chrono::DateTime;
-use nix::unistd::Pid;
+use libcgroups::common::CgroupManager;
+use nix::{
+    errno::Errno,
+    sys::signal::{self, Signal},
+    unistd::Pid,
+};
+use std::time::Duration;
 
 use chrono::Utc;
 use oci_spec::runtime::Spec;
 use procfs::process::Process;
 
+use crate::config::YoukiConfig;
+use crate::pidfd::{self, PidFd};
 use crate::syscall::syscall::create_syscall;
 
 use crate::container::{ContainerStatus, State};
@@ -77,6 +85,10 @@ impl Container {
         self.state.status.can_resume()
     }
 
+    pub fn can_update(&self) -> bool {
+        self.state.status.can_update()
+    }
+
     pub fn bundle(&self) -> &PathBuf {
         &self.state.bundle
     }
@@ -95,6 +107,42 @@ impl Container {
         self
     }
 
+    pub fn init_process_start(&self) -> Option<u64> {
+        self.state.init_process_start
+    }
+
+    pub fn set_init_process_start(&mut self, start_time: Option<u64>) -> &mut Self {
+        self.state.init_process_start = start_time;
+        self
+    }
+
+    pub fn listen_fds(&self) -> Option<i32> {
+        self.state.listen_fds
+    }
+
+    pub fn set_listen_fds(&mut self, listen_fds: Option<i32>) -> &mut Self {
+        self.state.listen_fds = listen_fds;
+        self
+    }
+
+    pub fn exit_code(&self) -> Option<i32> {
+        self.state.exit_code
+    }
+
+    pub fn set_exit_code(&mut self, exit_code: Option<i32>) -> &mut Self {
+        self.state.exit_code = exit_code;
+        self
+    }
+
+    pub fn oom_killed(&self) -> Option<bool> {
+        self.state.oom_killed
+    }
+
+    pub fn set_oom_killed(&mut self, oom_killed: Option<bool>) -> &mut Self {
+        self.state.oom_killed = oom_killed;
+        self
+    }
+
     pub fn created(&self) -> Option<DateTime<Utc>> {
         self.state.created
     }
@@ -125,6 +173,15 @@ impl Container {
         self
     }
 
+    pub fn perf_event_cgroup(&self) -> Option<&PathBuf> {
+        self.state.perf_event_cgroup.as_ref()
+    }
+
+    pub fn set_perf_event_cgroup(&mut self, path: Option<PathBuf>) -> &mut Self {
+        self.state.perf_event_cgroup = path;
+        self
+    }
+
     pub fn status(&self) -> ContainerStatus {
         self.state.status
     }
@@ -152,6 +209,9 @@ impl Container {
 
                     match proc.stat.state()? {
                         ProcState::Zombie | ProcState::Dead => ContainerStatus::Stopped,
+                        // the init process has exited and its pid has been
+                        // reused by an unrelated process
+                        _ if self.is_pid_reused(proc.stat.starttime) => ContainerStatus::Stopped,
                         _ => match self.status() {
                             ContainerStatus::Creating
                             | ContainerStatus::Created
@@ -170,6 +230,59 @@ impl Container {
         Ok(())
     }
 
+    fn is_pid_reused(&self, start_time: u64) -> bool {
+        matches!(self.init_process_start(), Some(expected) if expected != start_time)
+    }
+
+    /// Opens a pidfd for the init process of the container. Returns None if
+    /// the init process has exited, even if its pid has been reused.
+    pub fn init_pidfd(&self) -> Result<Option<PidFd>> {
+        let pid = match self.pid() {
+            Some(pid) => pid,
+            None => return Ok(None),
+        };
+
+        let pidfd = match PidFd::open(pid) {
+            Ok(pidfd) => pidfd,
+            Err(Errno::ESRCH) => return Ok(None),
+            Err(err) => return Err(err).context("failed to open pidfd of the init process"),
+        };
+
+        // The pidfd refers to the process which had the pid at the time it
+        // was opened. If that process has the start time of the init process,
+        // it is the init process.
+        match pidfd::start_time(pid) {
+            Ok(start_time) if !self.is_pid_reused(start_time) => Ok(Some(pidfd)),
+            _ => Ok(None),
+        }
+    }
+
+    /// Sends a signal to the init process of the container. Falls back to
+    /// kill(2) if pidfds are not supported by the kernel.
+    pub(crate) fn signal_init(&self, signal: Signal) -> Result<()> {
+        let pid = self.pid().context("container has no init process")?;
+        let result = match self.init_pidfd() {
+            Ok(Some(pidfd)) => pidfd.send_signal(signal),
+            Ok(None) => Err(Errno::ESRCH),
+            Err(err) if err.downcast_ref() == Some(&Errno::ENOSYS) => signal::kill(pid, signal),
+            Err(err) => return Err(err),
+        };
+
+        result.with_context(|| format!("failed to send {} to the init process {}", signal, pid))
+    }
+
+    /// Waits until the init process of the container has exited. Returns
+    /// false if it is still running after the timeout has expired.
+    pub(crate) fn wait_for_init_exit(&self, timeout: Duration) -> Result<bool> {
+        match self.init_pidfd() {
+            Ok(Some(pidfd)) => pidfd.wait_for_exit(timeout),
+            Ok(None) => Ok(true),
+            // the exit can not be waited for without pidfds
+            Err(err) if err.downcast_ref() == Some(&Errno::ENOSYS) => Ok(false),
+            Err(err) => Err(err),
+        }
+    }
+
     pub fn refresh_state(&mut self) -> Result<&mut Self> {
         let state = State::load(&self.root)?;
         self.state = state;
@@ -188,7 +301,7 @@ impl Container {
     }
 
     pub fn save(&self) -> Result<()> {
-        log::debug!("Save container status: {:?} in {:?}", self, self.root);
+        tracing::debug!("Save container status: {:?} in {:?}", self, self.root);
         self.state.save(&self.root)
     }
 
@@ -196,6 +309,23 @@ impl Container {
         let spec = Spec::load(self.root.join("config.json"))?;
         Ok(spec)
     }
+
+    /// Returns the path of the cgroup of the container, which has been resolved when
+    /// the container was created
+    pub fn cgroup_path(&self) -> Result<PathBuf> {
+        let config = YoukiConfig::load(&self.root)
+            .with_context(|| format!("failed to load youki config for container {}", self.id()))?;
+        Ok(config.cgroup_path)
+    }
+
+    /// Creates a manager for the cgroup of the container
+    pub(crate) fn cgroup_manager(&self) -> Result<Box<dyn CgroupManager>> {
+        let use_systemd = self
+            .systemd()
+            .context("could not determine cgroup manager")?;
+
+        libcgroups::common::create_cgroup_manager(self.cgroup_path()?, use_systemd, self.id())
+    }
 }
 
 #[cfg(test)]
@@ -237,6 +367,22 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_get_set_exit_status() {
+        let mut container = Container::default();
+        assert_eq!(container.exit_code(), None);
+        assert_eq!(container.oom_killed(), None);
+
+        container
+            .set_exit_code(Some(137))
+            .set_oom_killed(Some(true));
+        assert_eq!(container.exit_code(), Some(137));
+        assert_eq!(container.oom_killed(), Some(true));
+        let state = serde_json::to_value(&container.state).unwrap();
+        assert_eq!(state["exitCode"], 137);
+        assert_eq!(state["oomKilled"], true);
+    }
+
     #[test]
     fn test_set_annotations() {
         let mut container = Container::default();
@@ -5,7 +5,10 @@ use crate::utils;
 use anyhow::{bail, Context, Result};
 use libcgroups;
 use nix::sys::signal;
-use std::fs;
+use std::{fs, time::Duration};
+
+// time to wait for the init process to exit after it has been killed
+const KILL_TIMEOUT: Duration = Duration::from_secs(10);
 
 impl Container {
     /// Deletes the container
@@ -30,20 +33,24 @@ impl Container {
             .context("failed to refresh container status")?;
         if self.can_kill() && force {
             let sig = signal::Signal::SIGKILL;
-            log::debug!("kill signal {} to {}", sig, self.pid().unwrap());
-            signal::kill(self.pid().unwrap(), sig)?;
+            tracing::debug!("kill signal {} to {}", sig, self.pid().unwrap());
+            self.signal_init(sig)?;
+            // the cgroup can only be removed once all processes have exited
+            if !self.wait_for_init_exit(KILL_TIMEOUT)? {
+                tracing::warn!("init process of {} has not exited yet", self.id());
+            }
             self.set_status(ContainerStatus::Stopped).save()?;
         }
-        log::debug!("container status: {:?}", self.status());
+        tracing::debug!("container status: {:?}", self.status());
         if self.can_delete() {
             if self.root.exists() {
                 let config = YoukiConfig::load(&self.root).with_context(|| {
                     format!("failed to load runtime spec for container {}", self.id())
                 })?;
-                log::debug!("config: {:?}", config);
+                tracing::debug!("config: {:?}", config);
 
                 // remove the directory storing container state
-                log::debug!("remove dir {:?}", self.root);
+                tracing::debug!("remove dir {:?}", self.root);
                 fs::remove_dir_all(&self.root).with_context(|| {
                     format!("failed to remove container dir {}", self.root.display())
                 })?;
@@ -1,61 +1,272 @@
-use std::{thread, time::Duration};
-
-use crate::utils;
+use std::{collections::VecDeque, sync::mpsc, thread, time::Duration};
 
 use super::{Container, ContainerStatus};
 use anyhow::{bail, Context, Result};
+use chrono::{DateTime, Utc};
+use libcgroups::{oom::OomEvent, stats::Stats};
+use serde::Serialize;
+use serde_json::json;
+
+/// Interval in which stats are collected if no interval has been specified
+const DEFAULT_EVENTS_INTERVAL: Duration = Duration::from_secs(5);
+/// Number of samples that are kept in the stats history of the events command
+const EVENTS_HISTORY_CAPACITY: usize = 16;
+
+/// Stats of a container at a specific point in time
+#[derive(Debug, Serialize)]
+pub struct StatsSample {
+    pub timestamp: DateTime<Utc>,
+    pub stats: Stats,
+}
+
+/// Ring buffer which holds the most recent stats samples of a container. Once
+/// the capacity is reached the oldest sample is dropped.
+#[derive(Debug)]
+pub struct StatsHistory {
+    capacity: usize,
+    samples: VecDeque<StatsSample>,
+}
+
+impl StatsHistory {
+    pub fn new(capacity: usize) -> Self {
+        let capacity = capacity.max(1);
+        Self {
+            capacity,
+            samples: VecDeque::with_capacity(capacity),
+        }
+    }
+
+    pub fn push(&mut self, sample: StatsSample) {
+        if self.samples.len() == self.capacity {
+            self.samples.pop_front();
+        }
+        self.samples.push_back(sample);
+    }
+
+    /// Returns the most recent sample
+    pub fn latest(&self) -> Option<&StatsSample> {
+        self.samples.back()
+    }
+
+    /// Iterates over the samples from the oldest to the most recent one
+    pub fn iter(&self) -> impl Iterator<Item = &StatsSample> {
+        self.samples.iter()
+    }
+
+    pub fn len(&self) -> usize {
+        self.samples.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.samples.is_empty()
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+}
 
 impl Container {
-    /// Displays container events
+    /// Displays container events. If stats is set and no interval is specified,
+    /// the stats are printed once. Otherwise stats are sampled in the given
+    /// interval (default: 5s) and streamed as JSON lines. Without stats, oom
+    /// events are reported as well if the cgroup of the container can be watched.
     ///
     /// # Example
     ///
     /// ```no_run
     /// use libcontainer::container::builder::ContainerBuilder;
     /// use libcontainer::syscall::syscall::create_syscall;;
+    /// use std::time::Duration;
     ///
     /// # fn main() -> anyhow::Result<()> {
     /// let mut container = ContainerBuilder::new("74f1a4cb3801".to_owned(), create_syscall().as_ref())
     /// .as_init("/var/run/docker/bundle")
     /// .build()?;
     ///
-    /// container.events(5000, false)?;
+    /// container.events(Some(Duration::from_secs(5)), false)?;
     /// # Ok(())
     /// # }
     /// ```
-    pub fn events(&mut self, interval: u32, stats: bool) -> Result<()> {
+    pub fn events(&mut self, interval: Option<Duration>, stats: bool) -> Result<()> {
         self.refresh_status()
             .context("failed to refresh container status")?;
         if !self.state.status.eq(&ContainerStatus::Running) {
             bail!("{} is not in running state", self.id());
         }
 
-        let cgroups_path = utils::get_cgroup_path(
-            self.spec()?
-                .linux()
-                .as_ref()
-                .context("no linux in spec")?
-                .cgroups_path(),
-            self.id(),
-        );
-        let use_systemd = self
-            .systemd()
-            .context("Could not determine cgroup manager")?;
-
-        let cgroup_manager =
-            libcgroups::common::create_cgroup_manager(cgroups_path, use_systemd, self.id())?;
-        match stats {
-            true => {
-                let stats = cgroup_manager.stats()?;
-                println!("{}", serde_json::to_string_pretty(&stats)?);
+        if stats && interval.is_none() {
+            let stats = self.cgroup_manager()?.stats()?;
+            println!("{}", serde_json::to_string_pretty(&stats)?);
+            return Ok(());
+        }
+
+        // errors of the oom watcher are passed back, so that they end the events
+        let (oom_error_sender, oom_error_receiver) = mpsc::channel();
+        if !stats {
+            match self.cgroup_manager()?.oom_watcher() {
+                Ok(mut oom_watcher) => {
+                    let id = self.id().to_owned();
+                    thread::spawn(move || loop {
+                        match oom_watcher.wait() {
+                            Ok(Some(event)) => println!(
+                                "{}",
+                                json!({
+                                    "type": "oom",
+                                    "id": id,
+                                    "data": { "kills": event.kills, "groupKill": event.group_kill },
+                                })
+                            ),
+                            Ok(None) => break,
+                            Err(err) => {
+                                let _ = oom_error_sender.send(err);
+                                break;
+                            }
+                        }
+                    });
+                }
+                Err(err) => tracing::warn!(
+                    "failed to watch for oom events, only stats are reported: {:?}",
+                    err
+                ),
             }
-            false => loop {
-                let stats = cgroup_manager.stats()?;
-                println!("{}", serde_json::to_string_pretty(&stats)?);
-                thread::sleep(Duration::from_secs(interval as u64));
+        }
+
+        let id = self.id().to_owned();
+        let mut history = StatsHistory::new(EVENTS_HISTORY_CAPACITY);
+        let mut oom_error = None;
+        self.sample_stats(
+            interval.unwrap_or(DEFAULT_EVENTS_INTERVAL),
+            &mut history,
+            |history| {
+                if let Ok(err) = oom_error_receiver.try_recv() {
+                    oom_error = Some(err);
+                    return false;
+                }
+
+                if let Some(sample) = history.latest() {
+                    println!(
+                        "{}",
+                        json!({
+                            "type": "stats",
+                            "id": id,
+                            "timestamp": sample.timestamp,
+                            "data": sample.stats,
+                        })
+                    );
+                }
+                true
             },
+        )?;
+
+        match oom_error {
+            Some(err) => Err(err.context("failed to wait for oom events")),
+            None => Ok(()),
+        }
+    }
+
+    /// Collects the stats of the container in the given interval into the history
+    /// and calls the callback after every sample. Sampling stops once the callback
+    /// returns false or the container is no longer running.
+    pub fn sample_stats<F: FnMut(&StatsHistory) -> bool>(
+        &mut self,
+        interval: Duration,
+        history: &mut StatsHistory,
+        mut callback: F,
+    ) -> Result<()> {
+        let cgroup_manager = self.cgroup_manager()?;
+        loop {
+            self.refresh_status()
+                .context("failed to refresh container status")?;
+            if self.status() != ContainerStatus::Running {
+                return Ok(());
+            }
+
+            let stats = cgroup_manager
+                .stats()
+                .context("failed to collect container stats")?;
+            history.push(StatsSample {
+                timestamp: Utc::now(),
+                stats,
+            });
+
+            if !callback(history) {
+                return Ok(());
+            }
+
+            thread::sleep(interval);
+        }
+    }
+
+    /// Calls the callback every time processes of the container are killed by the
+    /// OOM killer. The event reports if the whole container has been killed because
+    /// memory.oom.group is set. Returns once the cgroup of the container has been removed.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// use libcontainer::container::builder::ContainerBuilder;
+    /// use libcontainer::syscall::syscall::create_syscall;;
+    ///
+    /// # fn main() -> anyhow::Result<()> {
+    /// let container = ContainerBuilder::new("74f1a4cb3801".to_owned(), create_syscall().as_ref())
+    /// .as_init("/var/run/docker/bundle")
+    /// .build()?;
+    ///
+    /// container.watch_oom(|event| println!("{} processes were oom killed", event.kills))?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn watch_oom<F: FnMut(OomEvent)>(&self, mut callback: F) -> Result<()> {
+        let mut watcher = self
+            .cgroup_manager()?
+            .oom_watcher()
+            .context("failed to watch for oom events")?;
+        while let Some(event) = watcher.wait()? {
+            callback(event);
         }
 
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample() -> StatsSample {
+        StatsSample {
+            timestamp: Utc::now(),
+            stats: Stats::default(),
+        }
+    }
+
+    #[test]
+    fn test_stats_history_drops_oldest_sample() {
+        let mut history = StatsHistory::new(2);
+        assert!(history.is_empty());
+
+        let first = sample();
+        let first_timestamp = first.timestamp;
+        history.push(first);
+        history.push(sample());
+        assert_eq!(history.len(), 2);
+        assert_eq!(history.iter().next().unwrap().timestamp, first_timestamp);
+
+        let last = sample();
+        let last_timestamp = last.timestamp;
+        history.push(last);
+        assert_eq!(history.len(), 2);
+        assert_eq!(history.capacity(), 2);
+        assert!(history.iter().all(|s| s.timestamp != first_timestamp));
+        assert_eq!(history.latest().unwrap().timestamp, last_timestamp);
+    }
+
+    #[test]
+    fn test_stats_history_minimum_capacity() {
+        let mut history = StatsHistory::new(0);
+        history.push(sample());
+        history.push(sample());
+        assert_eq!(history.len(), 1);
+    }
+}
@@ -4,7 +4,8 @@ use anyhow::{bail, Context, Result};
 use nix::sys::signal::{self};
 
 impl Container {
-    /// Sends the specified signal to the container init process
+    /// Sends the specified signal to the container init process, or to every
+    /// process in the container's cgroup if `all` is set
     ///
     /// # Example
     ///
@@ -18,17 +19,22 @@ impl Container {
     /// .as_init("/var/run/docker/bundle")
     /// .build()?;
     ///
-    /// container.kill(Signal::SIGKILL)?;
+    /// container.kill(Signal::SIGKILL, false)?;
     /// # Ok(())
     /// # }
     /// ```
-    pub fn kill<S: Into<Signal>>(&mut self, signal: S) -> Result<()> {
+    pub fn kill<S: Into<Signal>>(&mut self, signal: S, all: bool) -> Result<()> {
         let signal = signal.into().into_raw();
         self.refresh_status()
             .context("failed to refresh container status")?;
         if self.can_kill() {
-            log::debug!("kill signal {} to {}", signal, self.pid().unwrap());
-            signal::kill(self.pid().unwrap(), signal)?;
+            if all {
+                tracing::debug!("kill signal {} to all processes of {}", signal, self.id());
+                self.kill_all(signal)?;
+            } else {
+                tracing::debug!("kill signal {} to {}", signal, self.pid().unwrap());
+                self.signal_init(signal)?;
+            }
             self.set_status(ContainerStatus::Stopped).save()?;
             std::process::exit(0)
         } else {
@@ -39,4 +45,10 @@ impl Container {
             )
         }
     }
+
+    pub(super) fn kill_all(&self, signal: signal::Signal) -> Result<()> {
+        self.cgroup_manager()?
+            .kill_all(signal)
+            .with_context(|| format!("failed to kill all processes of {}", self.id()))
+    }
 }
@@ -1,8 +1,7 @@
-use crate::utils;
-
 use super::{Container, ContainerStatus};
 use anyhow::{bail, Context, Result};
-use libcgroups::common::FreezerState;
+use libcgroups::common::{FreezerState, DEFAULT_FREEZER_TIMEOUT};
+use std::time::Duration;
 
 impl Container {
     /// Suspends all processes within the container
@@ -23,6 +22,12 @@ impl Container {
     /// # }
     /// ```
     pub fn pause(&mut self) -> Result<()> {
+        self.pause_with_timeout(DEFAULT_FREEZER_TIMEOUT)
+    }
+
+    /// Suspends all processes within the container and waits at most for the
+    /// timeout until they have been frozen
+    pub fn pause_with_timeout(&mut self, timeout: Duration) -> Result<()> {
         self.refresh_status()
             .context("failed to refresh container status")?;
 
@@ -34,26 +39,38 @@ impl Container {
             );
         }
 
-        let spec = self.spec()?;
-        let cgroups_path = utils::get_cgroup_path(
-            spec.linux()
-                .as_ref()
-                .context("no linux in spec")?
-                .cgroups_path(),
-            self.id(),
-        );
-
-        let use_systemd = self
-            .systemd()
-            .context("container state does not contain cgroup manager")?;
-        let cmanager =
-            libcgroups::common::create_cgroup_manager(cgroups_path, use_systemd, self.id())?;
-        cmanager.freeze(FreezerState::Frozen)?;
-
-        log::debug!("saving paused status");
+        let cmanager = self.cgroup_manager()?;
+        if let Err(err) = cmanager.freeze_with_timeout(FreezerState::Frozen, timeout) {
+            // some processes may already have been frozen, which must not be
+            // left behind in this state as the container is still considered running
+            if let Err(thaw_err) = cmanager.freeze_with_timeout(FreezerState::Thawed, timeout) {
+                tracing::warn!(
+                    "failed to thaw container after failed pause: {:?}",
+                    thaw_err
+                );
+            }
+            return Err(err.context(format!("failed to freeze container {}", self.id())));
+        }
+
+        tracing::debug!("saving paused status");
         self.set_status(ContainerStatus::Paused).save()?;
 
-        log::debug!("container {} paused", self.id());
+        tracing::debug!("container {} paused", self.id());
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pause_stopped_container() {
+        // a container without a pid is considered to be stopped
+        let mut container = Container::default();
+        container.set_status(ContainerStatus::Running);
+
+        assert!(container.pause().is_err());
+        assert_eq!(container.status(), ContainerStatus::Stopped);
+    }
+}
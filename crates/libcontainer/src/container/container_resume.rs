@@ -1,9 +1,8 @@
-use crate::utils;
-
 use super::{Container, ContainerStatus};
 
 use anyhow::{bail, Context, Result};
-use libcgroups::common::FreezerState;
+use libcgroups::common::{FreezerState, DEFAULT_FREEZER_TIMEOUT};
+use std::time::Duration;
 
 impl Container {
     /// Resumes all processes within the container
@@ -24,6 +23,12 @@ impl Container {
     /// # }
     /// ```
     pub fn resume(&mut self) -> Result<()> {
+        self.resume_with_timeout(DEFAULT_FREEZER_TIMEOUT)
+    }
+
+    /// Resumes all processes within the container and waits at most for the
+    /// timeout until they have been thawed
+    pub fn resume_with_timeout(&mut self, timeout: Duration) -> Result<()> {
         self.refresh_status()
             .context("failed to refresh container status")?;
         // check if container can be resumed :
@@ -36,28 +41,30 @@ impl Container {
             );
         }
 
-        let spec = self.spec()?;
-        let cgroups_path = utils::get_cgroup_path(
-            spec.linux()
-                .as_ref()
-                .context("no linux in spec")?
-                .cgroups_path(),
-            self.id(),
-        );
-
-        // create cgroup manager structure from the config at the path
-        let use_systemd = self
-            .systemd()
-            .context("container state does not contain cgroup manager")?;
-        let cmanager =
-            libcgroups::common::create_cgroup_manager(cgroups_path, use_systemd, self.id())?;
         // resume the frozen container
-        cmanager.freeze(FreezerState::Thawed)?;
+        self.cgroup_manager()?
+            .freeze_with_timeout(FreezerState::Thawed, timeout)
+            .with_context(|| format!("failed to thaw container {}", self.id()))?;
 
-        log::debug!("saving running status");
+        tracing::debug!("saving running status");
         self.set_status(ContainerStatus::Running).save()?;
 
-        log::debug!("container {} resumed", self.id());
+        tracing::debug!("container {} resumed", self.id());
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_resume_stopped_container() {
+        // a container without a pid is considered to be stopped
+        let mut container = Container::default();
+        container.set_status(ContainerStatus::Paused);
+
+        assert!(container.resume().is_err());
+        assert_eq!(container.status(), ContainerStatus::Stopped);
+    }
+}
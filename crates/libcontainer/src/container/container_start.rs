@@ -26,6 +26,7 @@ impl Container {
     /// # Ok(())
     /// # }
     /// ```
+    #[tracing::instrument(level = "debug", skip_all, fields(id = %self.id()))]
     pub fn start(&mut self) -> Result<()> {
         self.refresh_status()
             .context("failed to refresh container status")?;
@@ -36,7 +37,7 @@ impl Container {
                 self.id(),
                 self.status()
             );
-            log::error!("{}", err_msg);
+            tracing::error!("{}", err_msg);
             bail!(err_msg);
         }
 
@@ -54,9 +55,15 @@ impl Container {
 
         let mut notify_socket = NotifySocket::new(&self.root.join(NOTIFY_FILE));
         notify_socket.notify_container_start()?;
-        self.set_status(ContainerStatus::Running)
-            .save()
-            .with_context(|| format!("could not save state for container {}", self.id()))?;
+        // the init process may have exited right away, in which case its exit
+        // status has already been recorded by the monitor process
+        self.refresh_state()
+            .context("failed to load container state")?;
+        if self.status() != ContainerStatus::Stopped {
+            self.set_status(ContainerStatus::Running)
+                .save()
+                .with_context(|| format!("could not save state for container {}", self.id()))?;
+        }
 
         // Run post start hooks. It runs after the container process is started.
         // It is called in the runtime namespace.
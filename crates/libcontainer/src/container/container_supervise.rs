@@ -0,0 +1,324 @@
+//! Supervision of a container by a runtime process which stays in the
+//! foreground (e.g. `youki run`). The supervising process becomes the child
+//! subreaper of the container, so that the init process is re-parented to it
+//! once the intermediate process has exited and its exit status can be
+//! collected.
+use super::{Container, ContainerStatus, State};
+use crate::{tty, tty_proxy::TtyProxy};
+use anyhow::{bail, Context, Result};
+use nix::{
+    errno::Errno,
+    sys::{
+        signal::{self, SigSet, Signal},
+        signalfd::{SfdFlags, SignalFd},
+        wait::{self, WaitPidFlag, WaitStatus},
+    },
+    unistd::Pid,
+};
+use std::os::unix::io::{AsRawFd, RawFd};
+
+/// Signals which are relayed to the init process of the container
+const FORWARDED_SIGNALS: &[Signal] = &[
+    Signal::SIGHUP,
+    Signal::SIGINT,
+    Signal::SIGQUIT,
+    Signal::SIGTERM,
+    Signal::SIGUSR1,
+    Signal::SIGUSR2,
+];
+
+/// Signals which are not forwarded, even if all signals are. They can not be
+/// caught, are handled by the supervisor itself or are caused by faults of the
+/// supervisor, which must not be blocked.
+const UNFORWARDED_SIGNALS: &[Signal] = &[
+    Signal::SIGKILL,
+    Signal::SIGSTOP,
+    Signal::SIGCHLD,
+    Signal::SIGWINCH,
+    Signal::SIGPIPE,
+    Signal::SIGSEGV,
+    Signal::SIGBUS,
+    Signal::SIGFPE,
+    Signal::SIGILL,
+    Signal::SIGTRAP,
+    Signal::SIGSYS,
+];
+
+/// Exit status of a process which has been killed by a signal, as reported
+/// by shells
+const SIGNAL_EXIT_OFFSET: i32 = 128;
+
+/// Marks the calling process as child subreaper (see PR_SET_CHILD_SUBREAPER
+/// in prctl(2)). Orphaned descendants, like the init process of a container
+/// after the intermediate process has exited, are re-parented to it instead
+/// of the init process of the host. This has to be called before the
+/// container is created.
+pub fn set_child_subreaper() -> Result<()> {
+    prctl::set_child_subreaper(true)
+        .map_err(Errno::from_i32)
+        .context("failed to become child subreaper")
+}
+
+/// Options of the supervision of a container, see [Container::supervise_with]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct SuperviseOptions {
+    console: Option<RawFd>,
+    forward_all_signals: bool,
+    signal_all: bool,
+}
+
+impl SuperviseOptions {
+    /// Relays the stdio of the calling process to and from the pty master of
+    /// the container (see [TtyProxy]). The window size of the terminal of the
+    /// calling process is applied to the pty as well, initially and whenever
+    /// the terminal is resized (SIGWINCH).
+    pub fn with_console(mut self, console: Option<RawFd>) -> Self {
+        self.console = console;
+        self
+    }
+
+    /// Forwards all signals which can be caught, instead of only those which
+    /// usually terminate the container (SIGHUP, SIGINT, SIGQUIT, SIGTERM) and
+    /// those reserved for applications (SIGUSR1, SIGUSR2)
+    pub fn with_forward_all_signals(mut self, forward_all_signals: bool) -> Self {
+        self.forward_all_signals = forward_all_signals;
+        self
+    }
+
+    /// Sends the forwarded signals to all processes in the cgroup of the
+    /// container instead of only its init process
+    pub fn with_signal_all(mut self, signal_all: bool) -> Self {
+        self.signal_all = signal_all;
+        self
+    }
+}
+
+impl Container {
+    /// Waits until the init process of the container has exited. Signals
+    /// received by the calling process in the meantime are forwarded to the
+    /// init process and exited descendants which have been re-parented to the
+    /// calling process are reaped. Returns the exit code of the init process.
+    ///
+    /// The calling process has to be the child subreaper of the container
+    /// (see [set_child_subreaper]), otherwise the init process can not be
+    /// waited for.
+    pub fn supervise(&mut self) -> Result<i32> {
+        self.supervise_with(SuperviseOptions::default())
+    }
+
+    /// Like [Container::supervise], but the stdio of the calling process is
+    /// relayed to and from the pty master of the container (see
+    /// [TtyProxy]). The window size of the terminal of the calling process is
+    /// applied to the pty as well, initially and whenever the terminal is
+    /// resized (SIGWINCH).
+    pub fn supervise_with_console(&mut self, console: Option<RawFd>) -> Result<i32> {
+        self.supervise_with(SuperviseOptions::default().with_console(console))
+    }
+
+    /// Like [Container::supervise], but with the given options
+    pub fn supervise_with(&mut self, options: SuperviseOptions) -> Result<i32> {
+        let init_pid = match self.pid() {
+            Some(pid) => pid,
+            None => bail!("container {} has no init process", self.id()),
+        };
+        let exit_code = self.supervise_process(init_pid, options)?;
+
+        // the container may have been deleted in the meantime, otherwise its
+        // exit status is kept for inspection until it is deleted
+        if State::file_path(&self.root).exists() {
+            self.save_exit_status(exit_code)?;
+        }
+
+        Ok(exit_code)
+    }
+
+    /// Marks the container as stopped and records the exit code of its init
+    /// process and whether it has been killed by the OOM killer
+    pub(crate) fn save_exit_status(&mut self, exit_code: i32) -> Result<()> {
+        self.refresh_state()
+            .context("failed to load container state")?;
+        let oom_killed = self.count_oom_kills().map(|kills| kills > 0);
+        self.set_status(ContainerStatus::Stopped)
+            .set_exit_code(Some(exit_code))
+            .set_oom_killed(oom_killed)
+            .save()
+            .context("failed to save exit status of the container")?;
+
+        Ok(())
+    }
+
+    /// Waits until a process, which has joined the container (see
+    /// [TenantContainerBuilder](super::tenant_builder::TenantContainerBuilder)),
+    /// has exited and returns its exit code. Like for the init process, the
+    /// calling process has to be its child subreaper.
+    pub fn supervise_tenant(&self, pid: Pid, options: SuperviseOptions) -> Result<i32> {
+        self.supervise_process(pid, options)
+    }
+
+    fn supervise_process(&self, pid: Pid, options: SuperviseOptions) -> Result<i32> {
+        let console = options.console;
+
+        let mut mask = forwarded_signals(options.forward_all_signals);
+        mask.add(Signal::SIGCHLD);
+        if console.is_some() {
+            mask.add(Signal::SIGWINCH);
+        }
+        // signals have to be blocked to be received through the signalfd
+        mask.thread_block()
+            .context("failed to block signals of the supervisor")?;
+        let mut signal_fd = SignalFd::with_flags(&mask, SfdFlags::SFD_CLOEXEC)
+            .context("failed to create signalfd")?;
+        let mut proxy = console
+            .map(|console| TtyProxy::new(console, signal_fd.as_raw_fd()))
+            .transpose()
+            .context("failed to relay to the console of the container")?;
+
+        // the process may have already exited before the signals have
+        // been blocked, which does not generate another SIGCHLD
+        let mut exit_code = reap_children(pid)?;
+        if let Some(console) = console {
+            resize_console(console);
+        }
+        while exit_code.is_none() {
+            if let Some(proxy) = &mut proxy {
+                proxy.wait_for_signal()?;
+            }
+            let info = match signal_fd.read_signal() {
+                Ok(Some(info)) => info,
+                Ok(None) | Err(Errno::EINTR) => continue,
+                Err(err) => return Err(err).context("failed to read from signalfd"),
+            };
+
+            let sig = Signal::try_from(info.ssi_signo as i32).context("received unknown signal")?;
+            if sig == Signal::SIGCHLD {
+                exit_code = reap_children(pid)?;
+                continue;
+            }
+            if let (Signal::SIGWINCH, Some(console)) = (sig, console) {
+                resize_console(console);
+                continue;
+            }
+
+            if options.signal_all {
+                tracing::debug!("forwarding {} to all processes of the container", sig);
+                // the container is still supervised, even if some of its
+                // processes could not be signaled
+                if let Err(err) = self.kill_all(sig) {
+                    tracing::warn!("failed to forward {} to the container: {:#}", sig, err);
+                }
+                continue;
+            }
+
+            tracing::debug!("forwarding {} to process {}", sig, pid);
+            match signal::kill(pid, sig) {
+                Ok(()) | Err(Errno::ESRCH) => {}
+                Err(err) => return Err(err).with_context(|| format!("failed to forward {}", sig)),
+            }
+        }
+
+        if let Some(mut proxy) = proxy {
+            proxy.flush()?;
+        }
+        mask.thread_unblock()
+            .context("failed to unblock signals of the supervisor")?;
+        Ok(exit_code.unwrap_or_default())
+    }
+
+    /// Returns the number of processes of the container, which have been
+    /// killed by the OOM killer, if it can be determined
+    fn count_oom_kills(&self) -> Option<u64> {
+        let kills = self
+            .cgroup_manager()
+            .and_then(|cmanager| cmanager.oom_watcher())
+            .and_then(|watcher| watcher.total_kills());
+        match kills {
+            Ok(kills) => Some(kills),
+            Err(err) => {
+                tracing::debug!("failed to count oom kills of the container: {:#}", err);
+                None
+            }
+        }
+    }
+}
+
+/// Reaps all exited children of the calling process. Returns the exit code of
+/// the supervised process if it is one of them.
+fn reap_children(pid: Pid) -> Result<Option<i32>> {
+    let mut exit_code = None;
+    loop {
+        let status = match wait::waitpid(None, Some(WaitPidFlag::WNOHANG)) {
+            Ok(WaitStatus::StillAlive) | Err(Errno::ECHILD) => return Ok(exit_code),
+            Ok(status) => status,
+            Err(Errno::EINTR) => continue,
+            Err(err) => return Err(err).context("failed to wait for children"),
+        };
+
+        if status.pid() == Some(pid) {
+            tracing::debug!("supervised process exited: {:?}", status);
+            exit_code = exit_code_of(status);
+        } else {
+            tracing::debug!("reaped re-parented process: {:?}", status);
+        }
+    }
+}
+
+fn forwarded_signals(all: bool) -> SigSet {
+    let mut signals = SigSet::empty();
+    if all {
+        Signal::iterator()
+            .filter(|sig| !UNFORWARDED_SIGNALS.contains(sig))
+            .for_each(|sig| signals.add(sig));
+    } else {
+        FORWARDED_SIGNALS.iter().for_each(|&sig| signals.add(sig));
+    }
+
+    signals
+}
+
+// The calling process may not have a terminal, e.g. if its stdin has been
+// redirected, in which case the console keeps its size
+fn resize_console(console: RawFd) {
+    if let Err(err) = tty::copy_window_size(libc::STDIN_FILENO, console) {
+        tracing::debug!("failed to resize console: {:#}", err);
+    }
+}
+
+pub(crate) fn exit_code_of(status: WaitStatus) -> Option<i32> {
+    match status {
+        WaitStatus::Exited(_, code) => Some(code),
+        WaitStatus::Signaled(_, sig, _) => Some(SIGNAL_EXIT_OFFSET + sig as i32),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_forwarded_signals() {
+        let signals = forwarded_signals(false);
+        assert!(signals.contains(Signal::SIGTERM));
+        assert!(!signals.contains(Signal::SIGCONT));
+
+        let signals = forwarded_signals(true);
+        assert!(signals.contains(Signal::SIGTERM));
+        assert!(signals.contains(Signal::SIGCONT));
+        assert!(!signals.contains(Signal::SIGCHLD));
+        assert!(!signals.contains(Signal::SIGKILL));
+    }
+
+    #[test]
+    fn test_exit_code_of() {
+        let pid = Pid::from_raw(42);
+        assert_eq!(exit_code_of(WaitStatus::Exited(pid, 3)), Some(3));
+        assert_eq!(
+            exit_code_of(WaitStatus::Signaled(pid, Signal::SIGKILL, false)),
+            Some(137)
+        );
+        assert_eq!(
+            exit_code_of(WaitStatus::Stopped(pid, Signal::SIGSTOP)),
+            None
+        );
+    }
+}
@@ -0,0 +1,304 @@
+use super::Container;
+use anyhow::{bail, Context, Result};
+use libcgroups::common::ControllerOpt;
+use oci_spec::runtime::{LinuxBlockIo, LinuxCpu, LinuxMemory, LinuxResources};
+
+impl Container {
+    /// Updates the cpu, memory, pids, block io, hugetlb and unified restrictions
+    /// of the container. Only restrictions that are set in the passed resources are changed,
+    /// all others keep their current value. The new resources are recorded in the
+    /// spec of the container.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// use libcontainer::container::builder::ContainerBuilder;
+    /// use libcontainer::syscall::syscall::create_syscall;
+    /// use oci_spec::runtime::{LinuxPidsBuilder, LinuxResourcesBuilder};
+    ///
+    /// # fn main() -> anyhow::Result<()> {
+    /// let mut container = ContainerBuilder::new("74f1a4cb3801".to_owned(), create_syscall().as_ref())
+    /// .as_init("/var/run/docker/bundle")
+    /// .build()?;
+    ///
+    /// let resources = LinuxResourcesBuilder::default()
+    ///     .pids(LinuxPidsBuilder::default().limit(100).build()?)
+    ///     .build()?;
+    /// container.update_resources(&resources)?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn update_resources(&mut self, resources: &LinuxResources) -> Result<()> {
+        self.refresh_status()
+            .context("failed to refresh container status")?;
+
+        if !self.can_update() {
+            bail!(
+                "{} could not be updated because it was {:?}",
+                self.id(),
+                self.status()
+            );
+        }
+
+        let mut spec = self.spec()?;
+        let mut linux = spec.linux().clone().context("no linux in spec")?;
+        let mut merged = linux.resources().clone().unwrap_or_default();
+        merge_resources(&mut merged, resources);
+
+        // all resources need to be applied, as e.g. the device rules of cgroup v2
+        // would otherwise be replaced by the default rules. The remaining settings
+        // are taken from the spec, so that they are not reset by the update.
+        let disable_oom_killer = merged
+            .memory()
+            .as_ref()
+            .and_then(|memory| memory.disable_oom_killer())
+            .unwrap_or(false);
+        let cmanager = self.cgroup_manager()?;
+        cmanager
+            .apply(&ControllerOpt {
+                resources: &merged,
+                freezer_state: None,
+                oom_score_adj: None,
+                disable_oom_killer,
+                annotations: spec.annotations().as_ref(),
+                strict_resources: false,
+            })
+            .context("failed to apply updated resources")?;
+
+        tracing::debug!("saving updated resources");
+        linux.set_resources(Some(merged));
+        spec.set_linux(Some(linux));
+        spec.save(self.root.join("config.json"))
+            .context("failed to save updated spec")?;
+
+        tracing::debug!("container {} updated", self.id());
+        Ok(())
+    }
+}
+
+fn merge_resources(current: &mut LinuxResources, update: &LinuxResources) {
+    if let Some(cpu) = update.cpu() {
+        let mut merged = current.cpu().clone().unwrap_or_default();
+        merge_cpu(&mut merged, cpu);
+        current.set_cpu(Some(merged));
+    }
+
+    if let Some(memory) = update.memory() {
+        let mut merged = current.memory().clone().unwrap_or_default();
+        merge_memory(&mut merged, memory);
+        current.set_memory(Some(merged));
+    }
+
+    if let Some(block_io) = update.block_io() {
+        let mut merged = current.block_io().clone().unwrap_or_default();
+        merge_block_io(&mut merged, block_io);
+        current.set_block_io(Some(merged));
+    }
+
+    // the rules are applied after the existing ones, so that devices can be
+    // added or removed with rules which allow or deny them
+    if let Some(devices) = update.devices() {
+        let mut merged = current.devices().clone().unwrap_or_default();
+        merged.extend(devices.iter().cloned());
+        current.set_devices(Some(merged));
+    }
+
+    if update.pids().is_some() {
+        current.set_pids(update.pids().clone());
+    }
+
+    if update.hugepage_limits().is_some() {
+        current.set_hugepage_limits(update.hugepage_limits().clone());
+    }
+
+    if let Some(unified) = update.unified() {
+        let mut merged = current.unified().clone().unwrap_or_default();
+        merged.extend(unified.iter().map(|(k, v)| (k.clone(), v.clone())));
+        current.set_unified(Some(merged));
+    }
+}
+
+fn merge_cpu(current: &mut LinuxCpu, update: &LinuxCpu) {
+    if let Some(shares) = update.shares() {
+        current.set_shares(Some(shares));
+    }
+    if let Some(quota) = update.quota() {
+        current.set_quota(Some(quota));
+    }
+    if let Some(period) = update.period() {
+        current.set_period(Some(period));
+    }
+    if let Some(realtime_runtime) = update.realtime_runtime() {
+        current.set_realtime_runtime(Some(realtime_runtime));
+    }
+    if let Some(realtime_period) = update.realtime_period() {
+        current.set_realtime_period(Some(realtime_period));
+    }
+    if let Some(cpus) = update.cpus() {
+        current.set_cpus(Some(cpus.clone()));
+    }
+    if let Some(mems) = update.mems() {
+        current.set_mems(Some(mems.clone()));
+    }
+}
+
+fn merge_memory(current: &mut LinuxMemory, update: &LinuxMemory) {
+    if let Some(limit) = update.limit() {
+        current.set_limit(Some(limit));
+    }
+    if let Some(reservation) = update.reservation() {
+        current.set_reservation(Some(reservation));
+    }
+    if let Some(swap) = update.swap() {
+        current.set_swap(Some(swap));
+    }
+    if let Some(kernel) = update.kernel() {
+        current.set_kernel(Some(kernel));
+    }
+    if let Some(kernel_tcp) = update.kernel_tcp() {
+        current.set_kernel_tcp(Some(kernel_tcp));
+    }
+    if let Some(swappiness) = update.swappiness() {
+        current.set_swappiness(Some(swappiness));
+    }
+}
+
+fn merge_block_io(current: &mut LinuxBlockIo, update: &LinuxBlockIo) {
+    if let Some(weight) = update.weight() {
+        current.set_weight(Some(weight));
+    }
+    if let Some(leaf_weight) = update.leaf_weight() {
+        current.set_leaf_weight(Some(leaf_weight));
+    }
+    if let Some(weight_device) = update.weight_device() {
+        current.set_weight_device(Some(weight_device.clone()));
+    }
+    if let Some(throttle_read_bps_device) = update.throttle_read_bps_device() {
+        current.set_throttle_read_bps_device(Some(throttle_read_bps_device.clone()));
+    }
+    if let Some(throttle_write_bps_device) = update.throttle_write_bps_device() {
+        current.set_throttle_write_bps_device(Some(throttle_write_bps_device.clone()));
+    }
+    if let Some(throttle_read_iops_device) = update.throttle_read_iops_device() {
+        current.set_throttle_read_iops_device(Some(throttle_read_iops_device.clone()));
+    }
+    if let Some(throttle_write_iops_device) = update.throttle_write_iops_device() {
+        current.set_throttle_write_iops_device(Some(throttle_write_iops_device.clone()));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use oci_spec::runtime::{
+        LinuxBlockIoBuilder, LinuxCpuBuilder, LinuxDeviceCgroupBuilder, LinuxDeviceType,
+        LinuxMemoryBuilder, LinuxPidsBuilder, LinuxResourcesBuilder,
+    };
+
+    #[test]
+    fn test_merge_keeps_unchanged_values() -> Result<()> {
+        let mut current = LinuxResourcesBuilder::default()
+            .cpu(
+                LinuxCpuBuilder::default()
+                    .shares(1024u64)
+                    .quota(50000i64)
+                    .build()?,
+            )
+            .memory(
+                LinuxMemoryBuilder::default()
+                    .limit(1024 * 1024 * 1024)
+                    .swap(2 * 1024 * 1024 * 1024)
+                    .build()?,
+            )
+            .pids(LinuxPidsBuilder::default().limit(100).build()?)
+            .build()?;
+        let update = LinuxResourcesBuilder::default()
+            .cpu(LinuxCpuBuilder::default().quota(100000i64).build()?)
+            .memory(
+                LinuxMemoryBuilder::default()
+                    .limit(512 * 1024 * 1024)
+                    .build()?,
+            )
+            .build()?;
+
+        merge_resources(&mut current, &update);
+
+        let cpu = current.cpu().as_ref().unwrap();
+        assert_eq!(cpu.shares(), Some(1024));
+        assert_eq!(cpu.quota(), Some(100000));
+        let memory = current.memory().as_ref().unwrap();
+        assert_eq!(memory.limit(), Some(512 * 1024 * 1024));
+        assert_eq!(memory.swap(), Some(2 * 1024 * 1024 * 1024));
+        assert_eq!(current.pids().as_ref().unwrap().limit(), 100);
+        Ok(())
+    }
+
+    #[test]
+    fn test_merge_adds_missing_controllers() -> Result<()> {
+        let mut current = LinuxResources::default();
+        let update = LinuxResourcesBuilder::default()
+            .block_io(LinuxBlockIoBuilder::default().weight(500u16).build()?)
+            .pids(LinuxPidsBuilder::default().limit(10).build()?)
+            .build()?;
+
+        merge_resources(&mut current, &update);
+
+        assert_eq!(current.block_io().as_ref().unwrap().weight(), Some(500));
+        assert_eq!(current.pids().as_ref().unwrap().limit(), 10);
+        Ok(())
+    }
+
+    #[test]
+    fn test_merge_unified() -> Result<()> {
+        let unified = |entries: &[(&str, &str)]| {
+            entries
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect::<std::collections::HashMap<_, _>>()
+        };
+        let mut current = LinuxResourcesBuilder::default()
+            .unified(unified(&[
+                ("cpu.max.burst", "1000"),
+                ("memory.oom.group", "1"),
+            ]))
+            .build()?;
+        let update = LinuxResourcesBuilder::default()
+            .unified(unified(&[("cpu.max.burst", "2000")]))
+            .build()?;
+
+        merge_resources(&mut current, &update);
+
+        assert_eq!(
+            current.unified().as_ref().unwrap(),
+            &unified(&[("cpu.max.burst", "2000"), ("memory.oom.group", "1")])
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_merge_appends_device_rules() -> Result<()> {
+        let rule = |allow: bool, minor: i64| {
+            LinuxDeviceCgroupBuilder::default()
+                .allow(allow)
+                .typ(LinuxDeviceType::C)
+                .major(10)
+                .minor(minor)
+                .access("rwm")
+                .build()
+        };
+        let mut current = LinuxResourcesBuilder::default()
+            .devices(vec![rule(true, 200)?])
+            .build()?;
+        let update = LinuxResourcesBuilder::default()
+            .devices(vec![rule(false, 200)?, rule(true, 229)?])
+            .build()?;
+
+        merge_resources(&mut current, &update);
+
+        assert_eq!(
+            current.devices().as_ref().unwrap(),
+            &vec![rule(true, 200)?, rule(false, 200)?, rule(true, 229)?]
+        );
+        Ok(())
+    }
+}
@@ -1,16 +1,31 @@
 use anyhow::{bail, Context, Result};
-use nix::unistd;
-use oci_spec::runtime::Spec;
+use nix::unistd::{self, Pid};
+use oci_spec::runtime::{LinuxNamespaceBuilder, LinuxNamespaceType, Spec};
 use rootless::Rootless;
 use std::{
-    fs,
-    path::{Path, PathBuf},
+    fmt, fs,
+    os::unix::fs::MetadataExt,
+    path::{Component, Path, PathBuf},
 };
 
-use crate::{apparmor, config::YoukiConfig, notify_socket::NOTIFY_FILE, rootless, tty, utils};
+use crate::{
+    apparmor,
+    auto_userns::{Allocation, AutoUserns},
+    config::YoukiConfig,
+    environment,
+    log_driver::LogOptions,
+    namespaces,
+    notify_socket::NOTIFY_FILE,
+    process::container_init_process,
+    rootless,
+    spec_extensions::{self, SpecExtensions},
+    sysctl, tty, user_lookup, utils,
+};
 
 use super::{
-    builder::ContainerBuilder, builder_impl::ContainerBuilderImpl, Container, ContainerStatus,
+    builder::{self, ContainerBuilder},
+    builder_impl::ContainerBuilderImpl,
+    Container, ContainerStatus,
 };
 
 // Builder that can be used to configure the properties of a new container
@@ -18,6 +33,15 @@ pub struct InitContainerBuilder<'a> {
     base: ContainerBuilder<'a>,
     bundle: PathBuf,
     use_systemd: bool,
+    auto_userns: Option<AutoUserns>,
+    strict_resources: bool,
+    namespaces_from: Option<String>,
+    no_new_keyring: bool,
+    no_pivot: bool,
+    create_missing_bind_sources: bool,
+    readonly_rootfs: Option<bool>,
+    log_options: Option<LogOptions>,
+    exit_monitor: bool,
 }
 
 impl<'a> InitContainerBuilder<'a> {
@@ -28,6 +52,15 @@ impl<'a> InitContainerBuilder<'a> {
             base: builder,
             bundle,
             use_systemd: true,
+            auto_userns: None,
+            strict_resources: false,
+            namespaces_from: None,
+            no_new_keyring: false,
+            no_pivot: false,
+            create_missing_bind_sources: false,
+            readonly_rootfs: None,
+            log_options: None,
+            exit_monitor: false,
         }
     }
 
@@ -37,20 +70,173 @@ impl<'a> InitContainerBuilder<'a> {
         self
     }
 
+    /// Sets if the creation of the container should fail if resource restrictions
+    /// cannot be applied because a controller is not available. By default these
+    /// restrictions are skipped with a warning.
+    pub fn with_strict_resources(mut self, strict: bool) -> Self {
+        self.strict_resources = strict;
+        self
+    }
+
+    /// Joins the network, ipc, pid and uts namespaces of another container,
+    /// e.g. to share the namespaces of a pod sandbox
+    pub fn with_namespaces_from<S: Into<String>>(mut self, container_id: Option<S>) -> Self {
+        self.namespaces_from = container_id.map(|id| id.into());
+        self
+    }
+
+    /// Sets if the container should keep the session keyring of the caller.
+    /// By default a new session keyring named after the container is created,
+    /// which may be unwanted or blocked by a seccomp profile.
+    pub fn with_no_new_keyring(mut self, no_new_keyring: bool) -> Self {
+        self.no_new_keyring = no_new_keyring;
+        self
+    }
+
+    /// Sets if the root of the container should be changed by moving the
+    /// root filesystem on top of the current root and chroot into it, for
+    /// environments like ramdisks where pivot_root fails. This is less secure
+    /// than pivot_root, which is used by default.
+    pub fn with_no_pivot(mut self, no_pivot: bool) -> Self {
+        self.no_pivot = no_pivot;
+        self
+    }
+
+    /// Sets if missing sources of bind mounts should be created instead of
+    /// failing. The source is created as an empty file if the destination is
+    /// a file in the rootfs, e.g. /etc/resolv.conf, and as directory otherwise.
+    pub fn with_create_missing_bind_sources(mut self, create: bool) -> Self {
+        self.create_missing_bind_sources = create;
+        self
+    }
+
+    /// Sets if the root filesystem of the container is mounted read only,
+    /// which overrides root.readonly of the spec
+    pub fn with_readonly_rootfs(mut self, readonly: bool) -> Self {
+        self.readonly_rootfs = Some(readonly);
+        self
+    }
+
+    /// Allocates unused id ranges for the user namespace of the container
+    /// if the spec does not contain any id mappings
+    pub fn with_auto_userns(mut self, auto_userns: Option<AutoUserns>) -> Self {
+        self.auto_userns = auto_userns;
+        self
+    }
+
+    /// Captures stdout and stderr of the container process in a log file in
+    /// the state directory (see [crate::log_driver]). Containers with a
+    /// terminal are rejected, as their output goes to the console.
+    pub fn with_log_driver(mut self, options: Option<LogOptions>) -> Self {
+        self.log_options = options;
+        self
+    }
+
+    /// Sets if the exit status of the container should be recorded by a
+    /// monitor process (see [crate::process::container_monitor_process]),
+    /// which is needed if nobody supervises the container, e.g. because it
+    /// runs detached. The monitor becomes the parent of the init process
+    /// instead of the caller, so this is disabled by default.
+    pub fn with_exit_monitor(mut self, exit_monitor: bool) -> Self {
+        self.exit_monitor = exit_monitor;
+        self
+    }
+
     /// Creates a new container
+    #[tracing::instrument(level = "debug", skip_all, name = "create", fields(id = %self.base.container_id))]
     pub fn build(self) -> Result<Container> {
-        let spec = self.load_spec()?;
+        let listen_fds = builder::listen_fds();
+        self.base
+            .validate_preserved_fds(listen_fds)
+            .context("invalid preserved fds")?;
+        let (mut spec, spec_extensions) = self.load_spec()?;
+        if let Some(readonly) = self.readonly_rootfs {
+            set_readonly_rootfs(&mut spec, readonly)?;
+        }
+        self.validate_cgroups_path(&spec)?;
+        if let Some(container_id) = &self.namespaces_from {
+            self.join_namespaces_of(&mut spec, container_id)
+                .with_context(|| {
+                    format!("failed to join namespaces of container {}", container_id)
+                })?;
+        }
+        if self.log_options.is_some()
+            && spec
+                .process()
+                .as_ref()
+                .and_then(|process| process.terminal())
+                == Some(true)
+        {
+            bail!("the log driver can not be used for containers with a terminal");
+        }
+        namespaces::validate_namespaces(&spec).context("invalid namespace configuration")?;
+        sysctl::validate(&spec).context("invalid sysctl configuration")?;
+        spec_extensions
+            .validate(&spec)
+            .context("invalid runtime spec")?;
+        let rootfs = spec
+            .root()
+            .as_ref()
+            .context("no root in spec")?
+            .path()
+            .clone();
+        user_lookup::resolve_spec_user(&mut spec, &rootfs)
+            .context("failed to resolve user of the container process")?;
+        environment::prepare_process_env(&mut spec, &rootfs, &self.base.inherited_env)
+            .context("failed to prepare environment of the container process")?;
         let container_dir = self.create_container_dir()?;
+        let allocated = match &self.auto_userns {
+            Some(auto_userns) => auto_userns
+                .apply(&mut spec, &self.base.root_path, &container_dir)
+                .context("failed to allocate id ranges for the user namespace")?
+                .is_some(),
+            None => false,
+        };
+
+        // the ids stay allocated as long as the container directory exists,
+        // which is not removed if the setup fails before the container process
+        // is created
+        let mut container =
+            match self.create_container(&container_dir, &spec, spec_extensions, listen_fds) {
+                Ok(container) => container,
+                Err(err) => {
+                    if allocated {
+                        if let Err(err) = Allocation::release(&container_dir) {
+                            tracing::warn!("failed to release id allocation: {:?}", err);
+                        }
+                    }
+                    return Err(err);
+                }
+            };
+        container.refresh_state()?;
+
+        Ok(container)
+    }
 
-        let mut container = self.create_container_state(&container_dir)?;
+    fn create_container(
+        self,
+        container_dir: &Path,
+        spec: &Spec,
+        spec_extensions: SpecExtensions,
+        listen_fds: i32,
+    ) -> Result<Container> {
+        let mut container = self.create_container_state(container_dir)?;
         container
             .set_systemd(self.use_systemd)
-            .set_annotations(spec.annotations().clone());
+            .set_annotations(spec.annotations().clone())
+            .set_listen_fds(Some(listen_fds).filter(|&n| n > 0));
 
-        let config = YoukiConfig::from_spec(&spec, container.id())?;
-        config.save(&container_dir)?;
+        let rootless = Rootless::new(spec, self.base.rootless_mode)?;
+        let mut config = YoukiConfig::from_spec(spec, container.id())?;
+        // relative cgroup paths depend on the cgroup of the calling process, later
+        // commands have to use the path which has been resolved on creation
+        config.cgroup_path = libcgroups::common::resolve_cgroup_path(
+            &config.cgroup_path,
+            self.use_systemd || rootless.is_some(),
+        )?;
+        config.save(container_dir)?;
 
-        unistd::chdir(&container_dir)?;
+        unistd::chdir(container_dir)?;
         let notify_path = container_dir.join(NOTIFY_FILE);
         // convert path of root file system of the container to absolute path
         let rootfs = fs::canonicalize(&spec.root().as_ref().context("no root in spec")?.path())?;
@@ -59,7 +245,7 @@ impl<'a> InitContainerBuilder<'a> {
         // get file descriptors of console socket
         let csocketfd = if let Some(console_socket) = &self.base.console_socket {
             Some(tty::setup_console_socket(
-                &container_dir,
+                container_dir,
                 console_socket,
                 "console-socket",
             )?)
@@ -67,7 +253,6 @@ impl<'a> InitContainerBuilder<'a> {
             None
         };
 
-        let rootless = Rootless::new(&spec)?;
         let mut builder_impl = ContainerBuilderImpl {
             init: true,
             syscall: self.base.syscall,
@@ -75,23 +260,34 @@ impl<'a> InitContainerBuilder<'a> {
             pid_file: self.base.pid_file,
             console_socket: csocketfd,
             use_systemd: self.use_systemd,
-            spec: &spec,
+            spec,
             rootfs,
             rootless,
             notify_path,
             container: Some(container.clone()),
             preserve_fds: self.base.preserve_fds,
+            listen_fds,
+            strict_resources: self.strict_resources,
+            spec_extensions,
+            create_cwd: self.base.create_cwd,
+            detach: self.base.detach,
+            allow_unsealed_binary: self.base.allow_unsealed_binary,
+            executor: self.base.executor,
+            no_new_keyring: self.no_new_keyring,
+            no_pivot: self.no_pivot,
+            create_missing_bind_sources: self.create_missing_bind_sources,
+            log_options: self.log_options,
+            exit_monitor: self.exit_monitor,
         };
 
         builder_impl.create()?;
-        container.refresh_state()?;
 
         Ok(container)
     }
 
     fn create_container_dir(&self) -> Result<PathBuf> {
         let container_dir = self.base.root_path.join(&self.base.container_id);
-        log::debug!("container directory will be {:?}", container_dir);
+        tracing::debug!("container directory will be {:?}", container_dir);
 
         if container_dir.exists() {
             bail!("container {} already exists", self.base.container_id);
@@ -101,13 +297,13 @@ impl<'a> InitContainerBuilder<'a> {
         Ok(container_dir)
     }
 
-    fn load_spec(&self) -> Result<Spec> {
+    fn load_spec(&self) -> Result<(Spec, SpecExtensions)> {
         let source_spec_path = self.bundle.join("config.json");
-        let mut spec = Spec::load(&source_spec_path)?;
+        let (mut spec, extensions) = spec_extensions::load_spec(&source_spec_path)?;
         Self::validate_spec(&spec).context("failed to validate runtime spec")?;
 
         spec.canonicalize_rootfs(&self.bundle)?;
-        Ok(spec)
+        Ok((spec, extensions))
     }
 
     fn validate_spec(spec: &Spec) -> Result<()> {
@@ -119,6 +315,10 @@ impl<'a> InitContainerBuilder<'a> {
         }
 
         if let Some(process) = spec.process() {
+            if let Some(oom_score_adj) = process.oom_score_adj() {
+                container_init_process::validate_oom_score_adj(oom_score_adj)?;
+            }
+
             if let Some(profile) = process.apparmor_profile() {
                 if !apparmor::is_enabled()? {
                     bail!(
@@ -133,6 +333,59 @@ impl<'a> InitContainerBuilder<'a> {
         Ok(())
     }
 
+    fn join_namespaces_of(&self, spec: &mut Spec, container_id: &str) -> Result<()> {
+        let container_root = self.base.root_path.join(container_id);
+        if !container_root.exists() {
+            bail!("container {} does not exist", container_id);
+        }
+
+        let container = Container::load(container_root)?;
+        let pid = match (container.status(), container.pid()) {
+            (ContainerStatus::Stopped, _) | (_, None) => {
+                bail!("container {} is not running", container_id)
+            }
+            (_, Some(pid)) => pid,
+        };
+
+        let join_userns = has_own_user_namespace(pid)
+            .with_context(|| format!("failed to inspect user namespace of {}", container_id))?;
+        share_namespaces(spec, pid, join_userns)
+    }
+
+    /// Ensures that the cgroup of the new container stays below the cgroup root
+    /// and is not shared with another container that is still alive
+    fn validate_cgroups_path(&self, spec: &Spec) -> Result<()> {
+        let linux = spec.linux().as_ref().context("no linux in spec")?;
+        let cgroups_path = utils::get_cgroup_path(linux.cgroups_path(), &self.base.container_id);
+        if cgroups_path
+            .components()
+            .any(|c| matches!(c, Component::ParentDir))
+        {
+            return Err(CgroupPathError::Invalid {
+                path: cgroups_path,
+                reason: "must not contain '..'".to_owned(),
+            }
+            .into());
+        }
+
+        let resolved_path =
+            libcgroups::common::resolve_cgroup_path(&cgroups_path, self.use_systemd)?;
+        let conflicts = find_cgroup_path_conflicts(
+            &self.base.root_path,
+            &resolved_path,
+            &self.base.container_id,
+        )?;
+        if !conflicts.is_empty() {
+            return Err(CgroupPathError::Conflict {
+                path: cgroups_path,
+                containers: conflicts,
+            }
+            .into());
+        }
+
+        Ok(())
+    }
+
     fn create_container_state(&self, container_dir: &Path) -> Result<Container> {
         let container = Container::new(
             &self.base.container_id,
@@ -145,3 +398,284 @@ impl<'a> InitContainerBuilder<'a> {
         Ok(container)
     }
 }
+
+/// Namespaces which are joined if the namespaces of another container are shared.
+/// The user namespace comes first, as it owns the other namespaces of the container.
+const SHARED_NAMESPACES: &[(LinuxNamespaceType, &str)] = &[
+    (LinuxNamespaceType::User, "user"),
+    (LinuxNamespaceType::Network, "net"),
+    (LinuxNamespaceType::Ipc, "ipc"),
+    (LinuxNamespaceType::Pid, "pid"),
+    (LinuxNamespaceType::Uts, "uts"),
+];
+
+/// Returns true if the process does not run in the user namespace of the runtime
+fn has_own_user_namespace(pid: Pid) -> Result<bool> {
+    let own = fs::metadata("/proc/self/ns/user")?;
+    let other = fs::metadata(format!("/proc/{}/ns/user", pid))?;
+    Ok((own.dev(), own.ino()) != (other.dev(), other.ino()))
+}
+
+/// Replaces the shared namespaces of the spec with the namespaces of the process.
+/// A process without its own user namespace runs in the user namespace of the
+/// runtime, which is kept instead of creating a new one.
+fn share_namespaces(spec: &mut Spec, pid: Pid, join_userns: bool) -> Result<()> {
+    let mut linux = spec.linux().clone().context("no linux in spec")?;
+    let mut namespaces = linux.namespaces().clone().unwrap_or_default();
+    for &(typ, name) in SHARED_NAMESPACES {
+        if typ == LinuxNamespaceType::User && !join_userns {
+            namespaces.retain(|ns| ns.typ() != typ);
+            continue;
+        }
+
+        let shared = LinuxNamespaceBuilder::default()
+            .typ(typ)
+            .path(format!("/proc/{}/ns/{}", pid, name))
+            .build()?;
+        match namespaces.iter_mut().find(|ns| ns.typ() == typ) {
+            Some(ns) => *ns = shared,
+            None => namespaces.push(shared),
+        }
+    }
+
+    linux.set_namespaces(Some(namespaces));
+    spec.set_linux(Some(linux));
+    Ok(())
+}
+
+/// Error returned if the cgroups path of a new container cannot be used
+#[derive(Debug)]
+pub enum CgroupPathError {
+    /// The path would escape the cgroup root
+    Invalid { path: PathBuf, reason: String },
+    /// The path is already used by containers which are still alive
+    Conflict {
+        path: PathBuf,
+        containers: Vec<String>,
+    },
+}
+
+impl std::error::Error for CgroupPathError {}
+
+impl fmt::Display for CgroupPathError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Invalid { path, reason } => {
+                write!(f, "invalid cgroups path {:?}: {}", path, reason)
+            }
+            Self::Conflict { path, containers } => write!(
+                f,
+                "cgroups path {:?} is already used by container(s) {}",
+                path,
+                containers.join(", ")
+            ),
+        }
+    }
+}
+
+/// Returns the ids of all containers below the root path which are not stopped
+/// and use the same cgroup. Containers whose state cannot be read are ignored.
+fn find_cgroup_path_conflicts(
+    root_path: &Path,
+    cgroups_path: &Path,
+    container_id: &str,
+) -> Result<Vec<String>> {
+    if !root_path.exists() {
+        return Ok(Vec::new());
+    }
+
+    let normalize = |path: &Path| path.strip_prefix("/").unwrap_or(path).to_path_buf();
+    let cgroups_path = normalize(cgroups_path);
+    let mut conflicts = Vec::new();
+    for entry in fs::read_dir(root_path)
+        .with_context(|| format!("failed to read directory {:?}", root_path))?
+    {
+        let container_root = entry?.path();
+        if !container_root.is_dir() {
+            continue;
+        }
+
+        let container = match Container::load(container_root) {
+            Ok(container) => container,
+            Err(err) => {
+                tracing::debug!("skipping container while checking cgroups path: {:?}", err);
+                continue;
+            }
+        };
+
+        if container.id() == container_id || container.status() == ContainerStatus::Stopped {
+            continue;
+        }
+
+        // the cgroup of a container is recorded in its youki config
+        let other_path = match YoukiConfig::load(container.root.as_path()) {
+            Ok(config) => config.cgroup_path,
+            Err(_) => continue,
+        };
+
+        if normalize(&other_path) == cgroups_path {
+            conflicts.push(container.id().to_owned());
+        }
+    }
+
+    conflicts.sort();
+    Ok(conflicts)
+}
+
+fn set_readonly_rootfs(spec: &mut Spec, readonly: bool) -> Result<()> {
+    let mut root = spec.root().clone().context("no root in spec")?;
+    root.set_readonly(Some(readonly));
+    spec.set_root(Some(root));
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::create_temp_dir;
+    use anyhow::Result;
+    use oci_spec::runtime::{Linux, LinuxNamespace};
+    use serial_test::serial;
+
+    fn create_container(
+        root_path: &Path,
+        id: &str,
+        status: ContainerStatus,
+        cgroups_path: Option<&str>,
+    ) -> Result<()> {
+        let container_root = root_path.join(id);
+        fs::create_dir_all(&container_root)?;
+        let pid = match status {
+            ContainerStatus::Stopped => None,
+            _ => Some(std::process::id() as i32),
+        };
+        Container::new(id, status, pid, &PathBuf::from("."), &container_root)?.save()?;
+
+        let mut linux = Linux::default();
+        linux.set_cgroups_path(cgroups_path.map(PathBuf::from));
+        let mut spec = Spec::default();
+        spec.set_linux(Some(linux));
+        YoukiConfig::from_spec(&spec, id)?.save(&container_root)?;
+        Ok(())
+    }
+
+    #[test]
+    #[serial]
+    fn test_cgroup_path_conflicts() -> Result<()> {
+        let tmp = create_temp_dir("test_cgroup_path_conflicts")?;
+        create_container(
+            &tmp,
+            "running",
+            ContainerStatus::Running,
+            Some("/youki/shared"),
+        )?;
+        create_container(
+            &tmp,
+            "stopped",
+            ContainerStatus::Stopped,
+            Some("/youki/shared"),
+        )?;
+        create_container(&tmp, "default", ContainerStatus::Created, None)?;
+
+        assert_eq!(
+            find_cgroup_path_conflicts(&tmp, Path::new("youki/shared"), "new")?,
+            vec!["running".to_owned()]
+        );
+        assert_eq!(
+            find_cgroup_path_conflicts(&tmp, Path::new("/default"), "new")?,
+            vec!["default".to_owned()]
+        );
+        assert!(find_cgroup_path_conflicts(&tmp, Path::new("/youki/other"), "new")?.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_share_namespaces() -> Result<()> {
+        let mut spec = Spec::default();
+        share_namespaces(&mut spec, Pid::from_raw(42), true)?;
+
+        let namespaces = spec.linux().as_ref().unwrap().namespaces().clone().unwrap();
+        for &(typ, name) in SHARED_NAMESPACES {
+            let shared: Vec<_> = namespaces.iter().filter(|ns| ns.typ() == typ).collect();
+            assert_eq!(shared.len(), 1);
+            assert_eq!(
+                shared[0].path(),
+                &Some(PathBuf::from(format!("/proc/42/ns/{}", name)))
+            );
+        }
+
+        let mount = namespaces
+            .iter()
+            .find(|ns| ns.typ() == LinuxNamespaceType::Mount)
+            .expect("mount namespace is kept");
+        assert_eq!(mount.path(), &None);
+        Ok(())
+    }
+
+    #[test]
+    fn test_share_namespaces_with_userns() -> Result<()> {
+        // a spec which creates a new user namespace
+        let mut spec = Spec::default();
+        let mut linux = spec.linux().clone().unwrap();
+        let mut namespaces = linux.namespaces().clone().unwrap();
+        namespaces.push(
+            LinuxNamespaceBuilder::default()
+                .typ(LinuxNamespaceType::User)
+                .build()?,
+        );
+        linux.set_namespaces(Some(namespaces));
+        spec.set_linux(Some(linux));
+
+        let user_namespaces = |spec: &Spec| -> Vec<LinuxNamespace> {
+            spec.linux()
+                .as_ref()
+                .unwrap()
+                .namespaces()
+                .iter()
+                .flatten()
+                .filter(|ns| ns.typ() == LinuxNamespaceType::User)
+                .cloned()
+                .collect()
+        };
+
+        // the user namespace of the other container is joined, so that its
+        // namespaces can be joined as well
+        let mut joined = spec.clone();
+        share_namespaces(&mut joined, Pid::from_raw(42), true)?;
+        let user = user_namespaces(&joined);
+        assert_eq!(user.len(), 1);
+        assert_eq!(user[0].path(), &Some(PathBuf::from("/proc/42/ns/user")));
+        namespaces::validate_namespaces(&joined)?;
+
+        // the other container runs in the user namespace of the runtime
+        let mut kept = spec.clone();
+        share_namespaces(&mut kept, Pid::from_raw(42), false)?;
+        assert!(user_namespaces(&kept).is_empty());
+        namespaces::validate_namespaces(&kept)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_set_readonly_rootfs() -> Result<()> {
+        let mut spec = Spec::default();
+        set_readonly_rootfs(&mut spec, true)?;
+        assert_eq!(spec.root().as_ref().unwrap().readonly(), &Some(true));
+        set_readonly_rootfs(&mut spec, false)?;
+        assert_eq!(spec.root().as_ref().unwrap().readonly(), &Some(false));
+
+        spec.set_root(None);
+        assert!(set_readonly_rootfs(&mut spec, true).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_cgroup_path_conflicts_missing_root() -> Result<()> {
+        let conflicts = find_cgroup_path_conflicts(
+            Path::new("/does/not/exist"),
+            Path::new("/youki/shared"),
+            "new",
+        )?;
+        assert!(conflicts.is_empty());
+        Ok(())
+    }
+}
@@ -14,8 +14,13 @@ mod container_kill;
 mod container_pause;
 mod container_resume;
 mod container_start;
+mod container_supervise;
+mod container_update;
 pub mod init_builder;
 pub mod state;
 pub mod tenant_builder;
 pub use container::Container;
+pub use container_events::{StatsHistory, StatsSample};
+pub(crate) use container_supervise::exit_code_of;
+pub use container_supervise::{set_child_subreaper, SuperviseOptions};
 pub use state::{ContainerProcessState, ContainerStatus, State};
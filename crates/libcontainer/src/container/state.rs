@@ -55,6 +55,14 @@ impl ContainerStatus {
     pub fn can_resume(&self) -> bool {
         matches!(self, ContainerStatus::Paused)
     }
+
+    pub fn can_update(&self) -> bool {
+        use ContainerStatus::*;
+        match self {
+            Creating | Stopped => false,
+            Created | Running | Paused => true,
+        }
+    }
 }
 
 impl Display for ContainerStatus {
@@ -97,6 +105,25 @@ pub struct State {
     pub creator: Option<u32>,
     // Specifies if systemd should be used to manage cgroups
     pub use_systemd: Option<bool>,
+    // Path of the perf_event cgroup of the container, which can be used to
+    // scope perf profiling to the container
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub perf_event_cgroup: Option<PathBuf>,
+    // Start time of the container process in clock ticks after boot, which is
+    // used to detect if its pid has been reused
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub init_process_start: Option<u64>,
+    // Number of sockets passed to the container by systemd socket activation
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub listen_fds: Option<i32>,
+    // Exit code of the container process, with 128 + signal number if it has
+    // been killed by a signal. Only known if the container has been
+    // supervised until its exit, e.g. by youki run.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub exit_code: Option<i32>,
+    // Indicates that the OOM killer has killed processes of the container
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub oom_killed: Option<bool>,
 }
 
 impl State {
@@ -118,6 +145,11 @@ impl State {
             created: None,
             creator: None,
             use_systemd: None,
+            perf_event_cgroup: None,
+            init_process_start: None,
+            listen_fds: None,
+            exit_code: None,
+            oom_killed: None,
         }
     }
 
@@ -188,6 +220,7 @@ mod tests {
         assert!(!cstatus.can_kill());
         assert!(!cstatus.can_pause());
         assert!(!cstatus.can_resume());
+        assert!(!cstatus.can_update());
     }
 
     #[test]
@@ -198,6 +231,7 @@ mod tests {
         assert!(cstatus.can_kill());
         assert!(!cstatus.can_pause());
         assert!(!cstatus.can_resume());
+        assert!(cstatus.can_update());
     }
 
     #[test]
@@ -208,6 +242,7 @@ mod tests {
         assert!(cstatus.can_kill());
         assert!(cstatus.can_pause());
         assert!(!cstatus.can_resume());
+        assert!(cstatus.can_update());
     }
 
     #[test]
@@ -218,6 +253,7 @@ mod tests {
         assert!(!cstatus.can_kill());
         assert!(!cstatus.can_pause());
         assert!(!cstatus.can_resume());
+        assert!(!cstatus.can_update());
     }
 
     #[test]
@@ -228,5 +264,6 @@ mod tests {
         assert!(cstatus.can_kill());
         assert!(!cstatus.can_pause());
         assert!(cstatus.can_resume());
+        assert!(cstatus.can_update());
     }
 }
@@ -1,10 +1,10 @@
 use anyhow::{bail, Context, Result};
 use caps::Capability;
-use nix::unistd;
+use nix::unistd::{self, Pid};
 use oci_spec::runtime::{
     Capabilities as SpecCapabilities, Capability as SpecCapability, LinuxBuilder,
     LinuxCapabilities, LinuxCapabilitiesBuilder, LinuxNamespace, LinuxNamespaceBuilder,
-    LinuxNamespaceType, Process, ProcessBuilder, Spec,
+    LinuxNamespaceType, Process, ProcessBuilder, Spec, User,
 };
 use procfs::process::Namespace;
 
@@ -18,16 +18,54 @@ use std::{
 };
 
 use crate::{capabilities::CapabilityExt, container::builder_impl::ContainerBuilderImpl};
-use crate::{notify_socket::NotifySocket, rootless::Rootless, tty, utils};
+use crate::{
+    environment,
+    notify_socket::NotifySocket,
+    rootless::Rootless,
+    spec_extensions::{self, SpecExtensions},
+    time_namespace::TimeNamespace,
+    tty, user_lookup, utils,
+};
 
-use super::{builder::ContainerBuilder, Container};
+use super::{
+    builder::{self, ContainerBuilder},
+    Container,
+};
 
 const NAMESPACE_TYPES: &[&str] = &["ipc", "uts", "net", "pid", "mnt", "cgroup"];
 const TENANT_NOTIFY: &str = "tenant-notify-";
 const TENANT_TTY: &str = "tenant-tty-";
 
 /// Builder that can be used to configure the properties of a process
-/// that will join an existing container sandbox
+/// that will join an existing container sandbox. The process enters the
+/// namespaces and the cgroup of the container and is confined by its seccomp
+/// filter. It is either configured through the builder methods or given as
+/// process spec, e.g. by a shim which implements exec.
+///
+/// # Example
+///
+/// ```no_run
+/// # use libcontainer::container::{builder::ContainerBuilder, Container};
+/// # use libcontainer::container::{set_child_subreaper, SuperviseOptions};
+/// # use libcontainer::syscall::syscall::create_syscall;
+/// # use oci_spec::runtime::ProcessBuilder;
+///
+/// # fn main() -> anyhow::Result<()> {
+/// let process = ProcessBuilder::default()
+///     .args(vec!["ps".to_owned(), "aux".to_owned()])
+///     .build()?;
+/// set_child_subreaper()?;
+/// let pid = ContainerBuilder::new("74f1a4cb3801".to_owned(), create_syscall().as_ref())
+///     .with_root_path("/run/containers/youki")
+///     .as_tenant()
+///     .with_process_spec(Some(process))
+///     .build()?;
+///
+/// let container = Container::load("/run/containers/youki/74f1a4cb3801".into())?;
+/// let exit_code = container.supervise_tenant(pid, SuperviseOptions::default())?;
+/// std::process::exit(exit_code);
+/// # }
+/// ```
 pub struct TenantContainerBuilder<'a> {
     base: ContainerBuilder<'a>,
     env: HashMap<String, String>,
@@ -36,6 +74,11 @@ pub struct TenantContainerBuilder<'a> {
     no_new_privs: Option<bool>,
     capabilities: Vec<String>,
     process: Option<PathBuf>,
+    process_spec: Option<Process>,
+    terminal: bool,
+    uid: Option<u32>,
+    gid: Option<u32>,
+    additional_gids: Option<Vec<u32>>,
 }
 
 impl<'a> TenantContainerBuilder<'a> {
@@ -51,6 +94,11 @@ impl<'a> TenantContainerBuilder<'a> {
             no_new_privs: None,
             capabilities: Vec::new(),
             process: None,
+            process_spec: None,
+            terminal: false,
+            uid: None,
+            gid: None,
+            additional_gids: None,
         }
     }
 
@@ -87,8 +135,43 @@ impl<'a> TenantContainerBuilder<'a> {
         self
     }
 
-    /// Joins an existing container
-    pub fn build(self) -> Result<()> {
+    /// Sets the process, which is run as it is. The other settings of the
+    /// process, including a process.json, are ignored.
+    pub fn with_process_spec(mut self, process: Option<Process>) -> Self {
+        self.process_spec = process;
+        self
+    }
+
+    /// Allocates a pseudoterminal for the process, which is sent to the
+    /// console socket
+    pub fn with_terminal(mut self, terminal: bool) -> Self {
+        self.terminal = terminal;
+        self
+    }
+
+    /// Sets the user of the process. The process runs as the user of the
+    /// container init process by default, the gid defaults to 0 if only the
+    /// uid is set.
+    pub fn with_user(mut self, uid: Option<u32>, gid: Option<u32>) -> Self {
+        self.uid = uid;
+        self.gid = gid;
+        self
+    }
+
+    /// Sets the supplementary groups of the process, which are inherited from
+    /// the container init process by default
+    pub fn with_additional_gids(mut self, additional_gids: Option<Vec<u32>>) -> Self {
+        self.additional_gids = additional_gids;
+        self
+    }
+
+    /// Joins an existing container and returns the pid of the process
+    #[tracing::instrument(level = "debug", skip_all, name = "exec", fields(id = %self.base.container_id))]
+    pub fn build(self) -> Result<Pid> {
+        let listen_fds = builder::listen_fds();
+        self.base
+            .validate_preserved_fds(listen_fds)
+            .context("invalid preserved fds")?;
         let container_dir = self
             .lookup_container_dir()
             .context("failed to look up container dir")?;
@@ -98,22 +181,34 @@ impl<'a> TenantContainerBuilder<'a> {
         let mut spec = self
             .load_init_spec(&container_dir)
             .context("failed to load init spec")?;
-        self.adapt_spec_for_tenant(&mut spec, &container)
+        let mut spec_extensions = self
+            .adapt_spec_for_tenant(&mut spec, &container)
             .context("failed to adapt spec for tenant")?;
 
-        log::debug!("{:#?}", spec);
+        tracing::debug!("{:#?}", spec);
 
         unistd::chdir(&container_dir)?;
         let notify_path = Self::setup_notify_listener(&container_dir)?;
         // convert path of root file system of the container to absolute path
         let rootfs = fs::canonicalize(&spec.root().as_ref().context("no root in spec")?.path())?;
+        user_lookup::resolve_spec_user(&mut spec, &rootfs)
+            .context("failed to resolve user of the process")?;
+        environment::prepare_process_env(&mut spec, &rootfs, &self.base.inherited_env)
+            .context("failed to prepare environment of the process")?;
 
         // if socket file path is given in commandline options,
         // get file descriptors of console socket
         let csocketfd = self.setup_tty_socket(&container_dir)?;
 
         let use_systemd = self.should_use_systemd(&container);
-        let rootless = Rootless::new(&spec)?;
+        // the time namespace is not part of the spec, so it is joined if the
+        // container init process has its own one
+        spec_extensions.time_namespace = match container.pid() {
+            Some(pid) => TimeNamespace::of_process(pid.as_raw())
+                .context("failed to determine time namespace of the container")?,
+            None => None,
+        };
+        let rootless = Rootless::new(&spec, self.base.rootless_mode)?;
 
         let mut builder_impl = ContainerBuilderImpl {
             init: false,
@@ -128,13 +223,25 @@ impl<'a> TenantContainerBuilder<'a> {
             notify_path: notify_path.clone(),
             container: None,
             preserve_fds: self.base.preserve_fds,
+            listen_fds,
+            strict_resources: false,
+            spec_extensions,
+            create_cwd: self.base.create_cwd,
+            detach: self.base.detach,
+            allow_unsealed_binary: self.base.allow_unsealed_binary,
+            executor: self.base.executor,
+            no_new_keyring: false,
+            no_pivot: false,
+            create_missing_bind_sources: false,
+            log_options: None,
+            exit_monitor: false,
         };
 
-        builder_impl.create()?;
+        let pid = builder_impl.create()?;
 
         let mut notify_socket = NotifySocket::new(notify_path);
         notify_socket.notify_container_start()?;
-        Ok(())
+        Ok(pid)
     }
 
     fn lookup_container_dir(&self) -> Result<PathBuf> {
@@ -165,26 +272,57 @@ impl<'a> TenantContainerBuilder<'a> {
         Ok(container)
     }
 
-    fn adapt_spec_for_tenant(&self, spec: &mut Spec, container: &Container) -> Result<()> {
-        let process = if let Some(process) = &self.process {
+    /// Replaces the process and namespaces of the spec. Returns the settings
+    /// of the process which are not part of the spec.
+    fn adapt_spec_for_tenant(
+        &self,
+        spec: &mut Spec,
+        container: &Container,
+    ) -> Result<SpecExtensions> {
+        let (process, extensions) = if let Some(process) = &self.process_spec {
+            (process.clone(), SpecExtensions::default())
+        } else if let Some(process) = &self.process {
             self.get_process(process)?
         } else {
+            let init_process = spec.process().clone().context("no process in spec")?;
             let mut process_builder = ProcessBuilder::default()
                 .args(self.get_args()?)
-                .env(self.get_environment()?);
+                .env(self.get_environment()?)
+                .terminal(self.terminal)
+                .user(self.get_user(&init_process));
             if let Some(cwd) = self.get_working_dir()? {
                 process_builder = process_builder.cwd(cwd);
             }
 
-            if let Some(no_new_priv) = self.get_no_new_privileges() {
+            if let Some(no_new_priv) = self
+                .get_no_new_privileges()
+                .or_else(|| init_process.no_new_privileges())
+            {
                 process_builder = process_builder.no_new_privileges(no_new_priv);
             }
 
+            // the process is confined like the init process of the container
+            if let Some(profile) = init_process.apparmor_profile() {
+                process_builder = process_builder.apparmor_profile(profile.clone());
+            }
+            if let Some(label) = init_process.selinux_label() {
+                process_builder = process_builder.selinux_label(label.clone());
+            }
+            if let Some(rlimits) = init_process.rlimits() {
+                process_builder = process_builder.rlimits(rlimits.clone());
+            }
+
             if let Some(caps) = self.get_capabilities(spec)? {
                 process_builder = process_builder.capabilities(caps);
             }
 
-            process_builder.build()?
+            // like the other processes of the container, the process should be
+            // preferred by the oom killer in the same way as the init process
+            if let Some(oom_score_adj) = spec.process().as_ref().and_then(|p| p.oom_score_adj()) {
+                process_builder = process_builder.oom_score_adj(oom_score_adj);
+            }
+
+            (process_builder.build()?, SpecExtensions::default())
         };
 
         if container.pid().is_none() {
@@ -193,13 +331,23 @@ impl<'a> TenantContainerBuilder<'a> {
 
         let init_process = procfs::process::Process::new(container.pid().unwrap().as_raw())?;
         let ns = self.get_namespaces(init_process.namespaces()?)?;
-        let linux = LinuxBuilder::default().namespaces(ns).build()?;
+        // the process joins the cgroup of the container, which has been resolved
+        // when the container was created, and is subject to the same seccomp
+        // filter as the init process
+        let init_linux = spec.linux().clone().context("no linux in spec")?;
+        let mut linux_builder = LinuxBuilder::default()
+            .namespaces(ns)
+            .cgroups_path(container.cgroup_path()?);
+        if let Some(seccomp) = init_linux.seccomp() {
+            linux_builder = linux_builder.seccomp(seccomp.clone());
+        }
+        let linux = linux_builder.build()?;
 
         spec.set_process(Some(process)).set_linux(Some(linux));
-        Ok(())
+        Ok(extensions)
     }
 
-    fn get_process(&self, process: &Path) -> Result<Process> {
+    fn get_process(&self, process: &Path) -> Result<(Process, SpecExtensions)> {
         if !process.exists() {
             bail!(
                 "Process.json file does not exist at specified path {}",
@@ -208,8 +356,9 @@ impl<'a> TenantContainerBuilder<'a> {
         }
 
         let process = utils::open(process)?;
-        let process_spec = serde_json::from_reader(process)?;
-        Ok(process_spec)
+        let mut process_spec: serde_json::Value = serde_json::from_reader(process)?;
+        let extensions = spec_extensions::extract_process_extensions(&mut process_spec)?;
+        Ok((serde_json::from_value(process_spec)?, extensions))
     }
 
     fn get_working_dir(&self) -> Result<Option<PathBuf>> {
@@ -241,85 +390,119 @@ impl<'a> TenantContainerBuilder<'a> {
             .collect())
     }
 
+    fn get_user(&self, init_process: &Process) -> User {
+        let mut user = init_process.user().clone();
+        if let Some(uid) = self.uid {
+            user.set_uid(uid)
+                .set_gid(self.gid.unwrap_or_default())
+                .set_username(None);
+        }
+        if let Some(additional_gids) = &self.additional_gids {
+            user.set_additional_gids(Some(additional_gids.clone()));
+        }
+
+        user
+    }
+
     fn get_no_new_privileges(&self) -> Option<bool> {
         self.no_new_privs
     }
 
+    /// Returns the capabilities of the init process with the additional
+    /// capabilities
     fn get_capabilities(&self, spec: &Spec) -> Result<Option<LinuxCapabilities>> {
-        if !self.capabilities.is_empty() {
-            let mut caps: Vec<Capability> = Vec::with_capacity(self.capabilities.len());
-            for cap in &self.capabilities {
-                caps.push(Capability::from_str(cap)?);
-            }
-
-            let caps: SpecCapabilities =
-                caps.iter().map(|c| SpecCapability::from_cap(*c)).collect();
-
-            if let Some(spec_caps) = spec
+        if self.capabilities.is_empty() {
+            return Ok(spec
                 .process()
                 .as_ref()
                 .context("no process in spec")?
                 .capabilities()
-            {
-                let mut capabilities_builder = LinuxCapabilitiesBuilder::default();
-                capabilities_builder = match spec_caps.ambient() {
-                    Some(ambient) => {
-                        let ambient: SpecCapabilities = ambient.union(&caps).copied().collect();
-                        capabilities_builder.ambient(ambient)
-                    }
-                    None => capabilities_builder,
-                };
-                capabilities_builder = match spec_caps.bounding() {
-                    Some(bounding) => {
-                        let bounding: SpecCapabilities = bounding.union(&caps).copied().collect();
-                        capabilities_builder.bounding(bounding)
-                    }
-                    None => capabilities_builder,
-                };
-                capabilities_builder = match spec_caps.effective() {
-                    Some(effective) => {
-                        let effective: SpecCapabilities = effective.union(&caps).copied().collect();
-                        capabilities_builder.effective(effective)
-                    }
-                    None => capabilities_builder,
-                };
-                capabilities_builder = match spec_caps.inheritable() {
-                    Some(inheritable) => {
-                        let inheritable: SpecCapabilities =
-                            inheritable.union(&caps).copied().collect();
-                        capabilities_builder.inheritable(inheritable)
-                    }
-                    None => capabilities_builder,
-                };
-                capabilities_builder = match spec_caps.permitted() {
-                    Some(permitted) => {
-                        let permitted: SpecCapabilities = permitted.union(&caps).copied().collect();
-                        capabilities_builder.permitted(permitted)
-                    }
-                    None => capabilities_builder,
-                };
-
-                let c = capabilities_builder.build()?;
-                return Ok(Some(c));
-            }
+                .clone());
+        }
 
-            return Ok(Some(
-                LinuxCapabilitiesBuilder::default()
-                    .bounding(caps.clone())
-                    .effective(caps.clone())
-                    .inheritable(caps.clone())
-                    .permitted(caps.clone())
-                    .ambient(caps)
-                    .build()?,
-            ));
+        let mut caps: Vec<Capability> = Vec::with_capacity(self.capabilities.len());
+        for cap in &self.capabilities {
+            caps.push(Capability::from_str(cap)?);
         }
 
-        Ok(None)
+        let caps: SpecCapabilities = caps.iter().map(|c| SpecCapability::from_cap(*c)).collect();
+
+        if let Some(spec_caps) = spec
+            .process()
+            .as_ref()
+            .context("no process in spec")?
+            .capabilities()
+        {
+            let mut capabilities_builder = LinuxCapabilitiesBuilder::default();
+            capabilities_builder = match spec_caps.ambient() {
+                Some(ambient) => {
+                    let ambient: SpecCapabilities = ambient.union(&caps).copied().collect();
+                    capabilities_builder.ambient(ambient)
+                }
+                None => capabilities_builder,
+            };
+            capabilities_builder = match spec_caps.bounding() {
+                Some(bounding) => {
+                    let bounding: SpecCapabilities = bounding.union(&caps).copied().collect();
+                    capabilities_builder.bounding(bounding)
+                }
+                None => capabilities_builder,
+            };
+            capabilities_builder = match spec_caps.effective() {
+                Some(effective) => {
+                    let effective: SpecCapabilities = effective.union(&caps).copied().collect();
+                    capabilities_builder.effective(effective)
+                }
+                None => capabilities_builder,
+            };
+            capabilities_builder = match spec_caps.inheritable() {
+                Some(inheritable) => {
+                    let inheritable: SpecCapabilities = inheritable.union(&caps).copied().collect();
+                    capabilities_builder.inheritable(inheritable)
+                }
+                None => capabilities_builder,
+            };
+            capabilities_builder = match spec_caps.permitted() {
+                Some(permitted) => {
+                    let permitted: SpecCapabilities = permitted.union(&caps).copied().collect();
+                    capabilities_builder.permitted(permitted)
+                }
+                None => capabilities_builder,
+            };
+
+            let c = capabilities_builder.build()?;
+            return Ok(Some(c));
+        }
+
+        Ok(Some(
+            LinuxCapabilitiesBuilder::default()
+                .bounding(caps.clone())
+                .effective(caps.clone())
+                .inheritable(caps.clone())
+                .permitted(caps.clone())
+                .ambient(caps)
+                .build()?,
+        ))
     }
 
     fn get_namespaces(&self, init_namespaces: Vec<Namespace>) -> Result<Vec<LinuxNamespace>> {
         let mut tenant_namespaces = Vec::with_capacity(init_namespaces.len());
 
+        // the user namespace can only be joined if it is not the one of the
+        // caller, e.g. for rootless containers
+        let own_namespaces = procfs::process::Process::myself()?.namespaces()?;
+        let own_user_ns = own_namespaces.iter().find(|n| n.ns_type == "user");
+        if let Some(init_ns) = init_namespaces.iter().find(|n| n.ns_type == "user") {
+            if own_user_ns.map(|n| n.identifier) != Some(init_ns.identifier) {
+                tenant_namespaces.push(
+                    LinuxNamespaceBuilder::default()
+                        .typ(LinuxNamespaceType::User)
+                        .path(init_ns.path.clone())
+                        .build()?,
+                )
+            }
+        }
+
         for &ns_type in NAMESPACE_TYPES {
             if let Some(init_ns) = init_namespaces.iter().find(|n| n.ns_type == ns_type) {
                 let tenant_ns = LinuxNamespaceType::try_from(ns_type)?;
@@ -375,3 +558,57 @@ impl<'a> TenantContainerBuilder<'a> {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        config::YoukiConfig, container::ContainerStatus, syscall::syscall::create_syscall,
+        utils::create_temp_dir,
+    };
+    use oci_spec::runtime::{LinuxSeccompAction, LinuxSeccompBuilder};
+
+    #[test]
+    fn test_adapt_spec_with_process_spec() -> Result<()> {
+        let syscall = create_syscall();
+        let process = ProcessBuilder::default()
+            .args(vec!["ps".to_owned()])
+            .build()?;
+        let builder = ContainerBuilder::new("74f1a4cb3801".to_owned(), syscall.as_ref())
+            .as_tenant()
+            .with_process_spec(Some(process.clone()));
+        // the namespaces of the test process are joined
+        let tmp = create_temp_dir("test_adapt_spec_with_process_spec")?;
+        let container = Container::new(
+            "74f1a4cb3801",
+            ContainerStatus::Running,
+            Some(unistd::getpid().as_raw()),
+            Path::new("."),
+            &tmp,
+        )?;
+        let mut spec = Spec::default();
+        let mut config = YoukiConfig::from_spec(&spec, container.id())?;
+        config.cgroup_path = PathBuf::from("/youki/74f1a4cb3801");
+        config.save(&tmp)?;
+        let seccomp = LinuxSeccompBuilder::default()
+            .default_action(LinuxSeccompAction::ScmpActAllow)
+            .build()?;
+        let mut linux = spec.linux().clone().unwrap();
+        linux.set_seccomp(Some(seccomp.clone()));
+        spec.set_linux(Some(linux));
+
+        builder.adapt_spec_for_tenant(&mut spec, &container)?;
+        assert_eq!(spec.process().as_ref(), Some(&process));
+        let linux = spec.linux().as_ref().unwrap();
+        assert_eq!(linux.seccomp().as_ref(), Some(&seccomp));
+        assert_eq!(
+            linux.cgroups_path().as_deref(),
+            Some(Path::new("/youki/74f1a4cb3801"))
+        );
+        let namespaces = linux.namespaces().as_ref().unwrap();
+        assert!(namespaces
+            .iter()
+            .all(|ns| ns.typ() != LinuxNamespaceType::User && ns.path().is_some()));
+        Ok(())
+    }
+}
@@ -0,0 +1,151 @@
+//! CPU affinity of processes which are executed in a container as specified
+//! by `process.execCPUAffinity`. Without it the processes would inherit the
+//! affinity of youki, which may e.g. be pinned to a housekeeping cpu.
+use anyhow::{bail, Context, Result};
+use nix::{
+    sched::{self, CpuSet},
+    unistd::Pid,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+/// CPU affinity as specified by `process.execCPUAffinity`. Both values are
+/// cpu lists such as "0-3,7".
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ExecCpuAffinity {
+    /// Affinity of the runtime while it sets up the process, applied before
+    /// the process joins the cgroup of the container, so that it is narrowed
+    /// down by the cpuset of the cgroup
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub initial: Option<String>,
+    /// Affinity of the process when the payload is executed
+    #[serde(default, rename = "final", skip_serializing_if = "Option::is_none")]
+    pub final_: Option<String>,
+}
+
+impl ExecCpuAffinity {
+    /// Ensures that both cpu lists can be parsed
+    pub fn validate(&self) -> Result<()> {
+        if let Some(initial) = &self.initial {
+            parse_cpu_list(initial).context("invalid initial cpu affinity")?;
+        }
+        if let Some(final_) = &self.final_ {
+            parse_cpu_list(final_).context("invalid final cpu affinity")?;
+        }
+
+        Ok(())
+    }
+
+    /// Applies the initial cpu affinity to the calling process
+    pub fn apply_initial(&self) -> Result<()> {
+        match &self.initial {
+            Some(cpus) => set_affinity(cpus),
+            None => Ok(()),
+        }
+    }
+
+    /// Applies the final cpu affinity to the calling process
+    pub fn apply_final(&self) -> Result<()> {
+        match &self.final_ {
+            Some(cpus) => set_affinity(cpus),
+            None => Ok(()),
+        }
+    }
+}
+
+fn set_affinity(cpus: &str) -> Result<()> {
+    tracing::debug!("set cpu affinity to {}", cpus);
+    let cpu_set = parse_cpu_list(cpus)?;
+    sched::sched_setaffinity(Pid::from_raw(0), &cpu_set)
+        .with_context(|| format!("failed to set cpu affinity to {}", cpus))
+}
+
+/// Parses a cpu list (see cpuset(7)) into a cpu set
+fn parse_cpu_list(cpus: &str) -> Result<CpuSet> {
+    let mut cpu_set = CpuSet::new();
+    let mut empty = true;
+    for range in cpus.split(',').map(str::trim) {
+        let (start, end) = match range.split_once('-') {
+            Some((start, end)) => (parse_cpu(start)?, parse_cpu(end)?),
+            None => {
+                let cpu = parse_cpu(range)?;
+                (cpu, cpu)
+            }
+        };
+        if start > end {
+            bail!("invalid cpu range {}", range);
+        }
+
+        for cpu in start..=end {
+            cpu_set.set(cpu).with_context(|| {
+                format!("cpu {} exceeds the maximum of {}", cpu, CpuSet::count())
+            })?;
+            empty = false;
+        }
+    }
+
+    if empty {
+        bail!("cpu list {:?} is empty", cpus);
+    }
+
+    Ok(cpu_set)
+}
+
+fn parse_cpu(cpu: &str) -> Result<usize> {
+    cpu.trim()
+        .parse()
+        .with_context(|| format!("invalid cpu {:?}", cpu))
+}
+
+/// Removes the cpu affinity from the raw process config, as it cannot be
+/// represented by the spec, and validates it
+pub(crate) fn extract_exec_cpu_affinity(process: &mut Value) -> Result<Option<ExecCpuAffinity>> {
+    let affinity = match process
+        .as_object_mut()
+        .and_then(|process| process.remove("execCPUAffinity"))
+    {
+        Some(affinity) => affinity,
+        None => return Ok(None),
+    };
+
+    let affinity: ExecCpuAffinity =
+        serde_json::from_value(affinity).context("invalid process.execCPUAffinity")?;
+    affinity.validate()?;
+    Ok(Some(affinity))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_parse_cpu_list() -> Result<()> {
+        let cpu_set = parse_cpu_list("0-2, 5")?;
+        for cpu in 0..8 {
+            assert_eq!(cpu_set.is_set(cpu)?, [0, 1, 2, 5].contains(&cpu), "{}", cpu);
+        }
+
+        for invalid in ["", "a", "3-1", "1-", "0,,1", "100000"] {
+            assert!(parse_cpu_list(invalid).is_err(), "{}", invalid);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_extract_exec_cpu_affinity() -> Result<()> {
+        let mut process = json!({
+            "args": ["sh"],
+            "execCPUAffinity": { "initial": "0", "final": "1-3" },
+        });
+
+        let affinity = extract_exec_cpu_affinity(&mut process)?.expect("cpu affinity");
+        assert_eq!(affinity.initial.as_deref(), Some("0"));
+        assert_eq!(affinity.final_.as_deref(), Some("1-3"));
+        assert_eq!(process, json!({ "args": ["sh"] }));
+
+        let mut process = json!({ "execCPUAffinity": { "final": "1-" } });
+        assert!(extract_exec_cpu_affinity(&mut process).is_err());
+        Ok(())
+    }
+}
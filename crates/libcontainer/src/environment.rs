@@ -0,0 +1,116 @@
+//! Environment of the container process. It is built from `process.env`
+//! only, as the environment of youki may reveal information about the host.
+//! Variables which most programs rely on are added if they are missing.
+use anyhow::{Context, Result};
+use oci_spec::runtime::Spec;
+use std::{env, path::Path};
+
+use crate::user_lookup;
+
+/// PATH of the container process if the spec does not contain one
+pub const DEFAULT_PATH: &str = "/usr/local/sbin:/usr/local/bin:/usr/sbin:/usr/bin:/sbin:/bin";
+
+/// Completes the environment of the process in the spec. The variables of
+/// youki with the given names are inherited, unless the spec sets them.
+/// HOME is set to the home directory of the user and PATH to a default.
+pub fn prepare_process_env(spec: &mut Spec, rootfs: &Path, inherited: &[String]) -> Result<()> {
+    let mut process = match spec.process() {
+        Some(process) => process.clone(),
+        None => return Ok(()),
+    };
+    let mut process_env = process.env().clone().unwrap_or_default();
+
+    for name in inherited {
+        if !contains(&process_env, name) {
+            if let Ok(value) = env::var(name) {
+                process_env.push(format!("{}={}", name, value));
+            }
+        }
+    }
+
+    if !contains(&process_env, "PATH") {
+        process_env.push(format!("PATH={}", DEFAULT_PATH));
+    }
+
+    if !contains(&process_env, "HOME") {
+        let uid = process.user().uid();
+        let home = user_lookup::home_dir(rootfs, uid)
+            .with_context(|| format!("failed to look up home directory of user {}", uid))?
+            .unwrap_or_else(|| "/".to_owned());
+        process_env.push(format!("HOME={}", home));
+    }
+
+    process.set_env(Some(process_env));
+    spec.set_process(Some(process));
+    Ok(())
+}
+
+fn contains(process_env: &[String], name: &str) -> bool {
+    process_env.iter().any(|var| match var.split_once('=') {
+        Some((key, _)) => key == name,
+        None => var == name,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::create_temp_dir;
+    use oci_spec::runtime::{ProcessBuilder, UserBuilder};
+    use std::fs;
+
+    fn spec_with_env(process_env: Vec<String>) -> Result<Spec> {
+        let mut spec = Spec::default();
+        spec.set_process(Some(
+            ProcessBuilder::default()
+                .user(UserBuilder::default().uid(1000u32).build()?)
+                .env(process_env)
+                .build()?,
+        ));
+        Ok(spec)
+    }
+
+    fn process_env(spec: &Spec) -> Vec<String> {
+        spec.process().as_ref().unwrap().env().clone().unwrap()
+    }
+
+    #[test]
+    fn test_prepare_process_env() -> Result<()> {
+        let rootfs = create_temp_dir("test_prepare_process_env")?;
+        fs::create_dir_all(rootfs.join("etc"))?;
+        fs::write(
+            rootfs.join("etc/passwd"),
+            "app:x:1000:1000::/home/app:/bin/sh\n",
+        )?;
+
+        let mut spec = spec_with_env(vec!["TERM=xterm".to_owned()])?;
+        prepare_process_env(&mut spec, &rootfs, &[])?;
+        assert_eq!(
+            process_env(&spec),
+            vec![
+                "TERM=xterm".to_owned(),
+                format!("PATH={}", DEFAULT_PATH),
+                "HOME=/home/app".to_owned(),
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_prepare_process_env_keeps_spec_values() -> Result<()> {
+        let rootfs = create_temp_dir("test_prepare_process_env_keeps_spec_values")?;
+        let mut spec = spec_with_env(vec!["PATH=/bin".to_owned(), "HOME=/root".to_owned()])?;
+        prepare_process_env(&mut spec, &rootfs, &["PATH".to_owned()])?;
+        assert_eq!(process_env(&spec), vec!["PATH=/bin", "HOME=/root"]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_prepare_process_env_without_user() -> Result<()> {
+        let rootfs = create_temp_dir("test_prepare_process_env_without_user")?;
+        let mut spec = spec_with_env(vec!["PATH=/bin".to_owned()])?;
+        prepare_process_env(&mut spec, &rootfs, &[])?;
+        assert_eq!(process_env(&spec), vec!["PATH=/bin", "HOME=/"]);
+        Ok(())
+    }
+}
@@ -0,0 +1,63 @@
+//! Execution of the payload of the container. Once the container process has
+//! been set up, it is handed over to an executor. By default the program of
+//! `process.args` replaces the container process, but embedders may plug in
+//! other backends, e.g. to run a closure as pid 1 or to dispatch the payload
+//! to a WebAssembly runtime.
+use anyhow::{bail, Context, Result};
+use oci_spec::runtime::Spec;
+
+use crate::utils;
+
+/// Executes the payload of the container in the container process, after all
+/// namespaces, the cgroup, the root filesystem and the security settings have
+/// been set up
+pub trait Executor {
+    /// Executes the payload. Returns once the payload has finished, unless the
+    /// container process is replaced, after which the container process exits.
+    /// Errors are reported to the process which starts the container.
+    fn exec(&self, spec: &Spec) -> Result<()>;
+
+    /// Returns if the container process is replaced by the payload through
+    /// exec. Otherwise the start of the container is confirmed before the
+    /// executor is called, as the payload may run for an unlimited time.
+    fn replaces_process(&self) -> bool {
+        false
+    }
+}
+
+/// Executes the program of `process.args` like execvp(3)
+#[derive(Debug, Default, Clone, Copy)]
+pub struct DefaultExecutor;
+
+impl Executor for DefaultExecutor {
+    fn exec(&self, spec: &Spec) -> Result<()> {
+        let args = spec
+            .process()
+            .as_ref()
+            .and_then(|process| process.args().as_ref());
+        match args {
+            Some(args) if !args.is_empty() => utils::do_exec(&args[0], args)
+                .with_context(|| format!("failed to execute {}", args[0])),
+            _ => bail!("on non-Windows, at least one process arg entry is required"),
+        }
+    }
+
+    fn replaces_process(&self) -> bool {
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use oci_spec::runtime::ProcessBuilder;
+
+    #[test]
+    fn test_default_executor_without_args() -> Result<()> {
+        let mut spec = Spec::default();
+        spec.set_process(Some(ProcessBuilder::default().args(vec![]).build()?));
+        assert!(DefaultExecutor.exec(&spec).is_err());
+        assert!(DefaultExecutor.replaces_process());
+        Ok(())
+    }
+}
@@ -18,6 +18,7 @@ impl fmt::Display for HookTimeoutError {
     }
 }
 
+#[tracing::instrument(level = "debug", skip_all, name = "hooks")]
 pub fn run_hooks(hooks: Option<&Vec<Hook>>, container: Option<&Container>) -> Result<()> {
     if container.is_none() {
         bail!("container state is required to run hook");
@@ -35,7 +36,7 @@ pub fn run_hooks(hooks: Option<&Vec<Hook>>, container: Option<&Container>) -> Re
             // doesn't include arg0. So we have to make the split arg0 from the
             // rest of args.
             if let Some((arg0, args)) = hook.args().as_ref().and_then(|a| a.split_first()) {
-                log::debug!("run_hooks arg0: {:?}, args: {:?}", arg0, args);
+                tracing::debug!("run_hooks arg0: {:?}, args: {:?}", arg0, args);
                 hook_command.arg0(arg0).args(args)
             } else {
                 hook_command.arg0(&hook.path().display().to_string())
@@ -46,7 +47,7 @@ pub fn run_hooks(hooks: Option<&Vec<Hook>>, container: Option<&Container>) -> Re
             } else {
                 HashMap::new()
             };
-            log::debug!("run_hooks envs: {:?}", envs);
+            tracing::debug!("run_hooks envs: {:?}", envs);
 
             let mut hook_process = hook_command
                 .env_clear()
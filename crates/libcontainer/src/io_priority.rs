@@ -0,0 +1,152 @@
+//! I/O scheduling class and priority of the container process as specified by
+//! `process.ioPriority` (see ioprio_set(2)). The I/O priority is inherited by
+//! the payload.
+use anyhow::{bail, Context, Result};
+use nix::errno::Errno;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+// see include/uapi/linux/ioprio.h
+const IOPRIO_WHO_PROCESS: libc::c_int = 1;
+const IOPRIO_CLASS_SHIFT: u32 = 13;
+const IOPRIO_CLASS_RT: u32 = 1;
+const IOPRIO_CLASS_BE: u32 = 2;
+const IOPRIO_CLASS_IDLE: u32 = 3;
+// number of priority levels of the realtime and best effort classes
+const IOPRIO_LEVELS: i64 = 8;
+
+/// I/O scheduling class
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum IoPriorityClass {
+    #[serde(rename = "IOPRIO_CLASS_RT")]
+    Realtime,
+    #[serde(rename = "IOPRIO_CLASS_BE")]
+    BestEffort,
+    #[serde(rename = "IOPRIO_CLASS_IDLE")]
+    Idle,
+}
+
+impl IoPriorityClass {
+    fn value(&self) -> u32 {
+        match self {
+            Self::Realtime => IOPRIO_CLASS_RT,
+            Self::BestEffort => IOPRIO_CLASS_BE,
+            Self::Idle => IOPRIO_CLASS_IDLE,
+        }
+    }
+}
+
+/// I/O priority as specified by `process.ioPriority`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct IoPriority {
+    pub class: IoPriorityClass,
+    /// Priority level within the class, 0 is the highest and 7 the lowest
+    /// priority
+    #[serde(default)]
+    pub priority: i64,
+}
+
+impl IoPriority {
+    /// Ensures that the priority level is valid for the class
+    pub fn validate(&self) -> Result<()> {
+        if !(0..IOPRIO_LEVELS).contains(&self.priority) {
+            bail!(
+                "io priority {} of class {:?} is not in the range [0, {}]",
+                self.priority,
+                self.class,
+                IOPRIO_LEVELS - 1
+            );
+        }
+
+        Ok(())
+    }
+
+    fn ioprio(&self) -> libc::c_int {
+        // the idle class has no priority levels
+        let level = match self.class {
+            IoPriorityClass::Idle => 0,
+            _ => self.priority as u32,
+        };
+        ((self.class.value() << IOPRIO_CLASS_SHIFT) | level) as libc::c_int
+    }
+
+    /// Sets the I/O priority of the calling process. The realtime class
+    /// requires CAP_SYS_ADMIN, so this has to happen before the capabilities
+    /// are dropped.
+    pub fn apply(&self) -> Result<()> {
+        tracing::debug!("set io priority {:?}", self);
+        let result =
+            unsafe { libc::syscall(libc::SYS_ioprio_set, IOPRIO_WHO_PROCESS, 0, self.ioprio()) };
+        if result == -1 {
+            bail!(
+                "failed to set io priority {:?}: {}",
+                self.class,
+                Errno::last()
+            );
+        }
+
+        Ok(())
+    }
+}
+
+/// Removes the I/O priority from the raw process config, as it cannot be
+/// represented by the spec, and validates it
+pub(crate) fn extract_io_priority(process: &mut Value) -> Result<Option<IoPriority>> {
+    let io_priority = match process
+        .as_object_mut()
+        .and_then(|process| process.remove("ioPriority"))
+    {
+        Some(io_priority) => io_priority,
+        None => return Ok(None),
+    };
+
+    let io_priority: IoPriority =
+        serde_json::from_value(io_priority).context("invalid process.ioPriority")?;
+    io_priority.validate()?;
+    Ok(Some(io_priority))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_extract_io_priority() -> Result<()> {
+        let mut process = json!({
+            "args": ["sh"],
+            "ioPriority": { "class": "IOPRIO_CLASS_BE", "priority": 4 },
+        });
+
+        let io_priority = extract_io_priority(&mut process)?.expect("io priority");
+        assert_eq!(process, json!({ "args": ["sh"] }));
+        assert_eq!(io_priority.ioprio(), (2 << 13) | 4);
+
+        assert_eq!(extract_io_priority(&mut process)?, None);
+        Ok(())
+    }
+
+    #[test]
+    fn test_idle_class_ignores_priority() -> Result<()> {
+        let mut process = json!({ "ioPriority": { "class": "IOPRIO_CLASS_IDLE", "priority": 7 } });
+        let io_priority = extract_io_priority(&mut process)?.expect("io priority");
+        assert_eq!(io_priority.ioprio(), 3 << 13);
+        Ok(())
+    }
+
+    #[test]
+    fn test_invalid_io_priority() {
+        for io_priority in vec![
+            json!({ "class": "IOPRIO_CLASS_NONE" }),
+            json!({ "class": "IOPRIO_CLASS_RT", "priority": 8 }),
+            json!({ "class": "IOPRIO_CLASS_BE", "priority": -1 }),
+        ] {
+            let mut process = json!({ "ioPriority": io_priority.clone() });
+            assert!(
+                extract_io_priority(&mut process).is_err(),
+                "{}",
+                io_priority
+            );
+        }
+    }
+}
@@ -0,0 +1,122 @@
+//! Session keyring of the container (see keyrings(7)). Without a session
+//! keyring of its own, the container process would share the session keyring
+//! of youki and therefore have access to the keys of the caller.
+use anyhow::{bail, Context, Result};
+use nix::errno::Errno;
+use std::{ffi::CString, path::Path};
+
+use crate::utils;
+
+// see include/uapi/linux/keyctl.h
+const KEYCTL_JOIN_SESSION_KEYRING: libc::c_long = 1;
+const KEYCTL_SETPERM: libc::c_long = 5;
+const KEYCTL_DESCRIBE: libc::c_long = 6;
+const KEY_USR_SEARCH: u32 = 0x080000;
+
+const KEYCREATE_PATH: &str = "/proc/self/attr/keycreate";
+
+/// Joins a new session keyring with the given name, which is labeled with
+/// the SELinux label, if there is one. Kernels without support for keyrings
+/// are tolerated.
+pub fn join_session_keyring(name: &str, selinux_label: Option<&str>) -> Result<()> {
+    let selinux_label = selinux_label.filter(|label| !label.is_empty());
+    if let Some(label) = selinux_label {
+        set_keycreate_label(label)
+            .with_context(|| format!("failed to set label {} of the keyring", label))?;
+    }
+
+    let c_name = CString::new(name).context("invalid keyring name")?;
+    let result = unsafe {
+        libc::syscall(
+            libc::SYS_keyctl,
+            KEYCTL_JOIN_SESSION_KEYRING,
+            c_name.as_ptr(),
+        )
+    };
+    let keyring = match Errno::result(result) {
+        Ok(keyring) => keyring,
+        Err(Errno::ENOSYS) => {
+            tracing::warn!("keyrings are not supported by the kernel");
+            return Ok(());
+        }
+        Err(err) => {
+            return Err(err).with_context(|| format!("failed to join session keyring {}", name))
+        }
+    };
+
+    // the label only applies to the keyring of the container, keys created
+    // by the payload get the default label
+    if selinux_label.is_some() {
+        set_keycreate_label("").context("failed to reset keyring label")?;
+    }
+
+    // processes of the container which run as another user than the one which
+    // created the keyring must still be able to search it
+    set_keyring_permissions(keyring, KEY_USR_SEARCH)
+        .with_context(|| format!("failed to change permissions of session keyring {}", name))
+}
+
+fn set_keycreate_label(label: &str) -> Result<()> {
+    let path = Path::new(KEYCREATE_PATH);
+    utils::ensure_procfs(path)?;
+    utils::write_file(path, label)
+}
+
+/// Adds the permissions to those of the keyring
+fn set_keyring_permissions(keyring: libc::c_long, permissions: u32) -> Result<()> {
+    let mut description = vec![0u8; 512];
+    let len = unsafe {
+        libc::syscall(
+            libc::SYS_keyctl,
+            KEYCTL_DESCRIBE,
+            keyring,
+            description.as_mut_ptr(),
+            description.len(),
+        )
+    };
+    let len = Errno::result(len).context("failed to describe keyring")? as usize;
+    // the length includes the terminating nul byte
+    description.truncate(len.saturating_sub(1).min(description.len()));
+
+    let description = String::from_utf8_lossy(&description);
+    let current = parse_permissions(&description)?;
+    let result = unsafe {
+        libc::syscall(
+            libc::SYS_keyctl,
+            KEYCTL_SETPERM,
+            keyring,
+            current | permissions,
+        )
+    };
+    Errno::result(result)
+        .map(drop)
+        .context("failed to set permissions")
+}
+
+/// Parses the permissions of a key description, which has the form
+/// `type;uid;gid;perm;description` with the permissions in hex
+fn parse_permissions(description: &str) -> Result<u32> {
+    let permissions = match description.split(';').nth(3) {
+        Some(permissions) => permissions,
+        None => bail!("invalid key description {:?}", description),
+    };
+
+    u32::from_str_radix(permissions, 16)
+        .with_context(|| format!("invalid key permissions {:?}", permissions))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_permissions() -> Result<()> {
+        assert_eq!(
+            parse_permissions("keyring;0;0;3f1f0000;_ses.74f1a4cb3801")?,
+            0x3f1f0000
+        );
+        assert!(parse_permissions("keyring;0;0").is_err());
+        assert!(parse_permissions("keyring;0;0;xyz;_ses").is_err());
+        Ok(())
+    }
+}
@@ -1,16 +1,32 @@
 #![cfg_attr(coverage, feature(no_coverage))]
 pub mod apparmor;
+pub mod auto_userns;
 pub mod capabilities;
 pub mod config;
 pub mod container;
+pub mod cpu_affinity;
+pub mod environment;
+pub mod executor;
 pub mod hooks;
+pub mod io_priority;
+pub mod keyring;
+pub mod log_driver;
 pub mod namespaces;
 pub mod notify_socket;
+pub mod openat2;
+pub mod personality;
+pub mod pidfd;
 pub mod process;
 pub mod rootfs;
 pub mod rootless;
+pub mod scheduler;
 pub mod seccomp;
 pub mod signal;
+pub mod spec_extensions;
 pub mod syscall;
+pub mod sysctl;
+pub mod time_namespace;
 pub mod tty;
+pub mod tty_proxy;
+pub mod user_lookup;
 pub mod utils;
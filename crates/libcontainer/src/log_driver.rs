@@ -0,0 +1,335 @@
+//! Capture of the stdout and stderr of the container process in a log file in
+//! the state directory, for embedders without a logging shim of their own.
+//! Every line is written as a json record with the stream and a timestamp,
+//! like the json-file log driver of Docker:
+//!
+//! {"log":"hello\n","stream":"stdout","time":"2022-01-01T00:00:00.000000000Z"}
+//!
+//! The records are written by a separate process, which keeps running after
+//! youki has exited and exits once all processes of the container have closed
+//! their stdout and stderr.
+use anyhow::{Context, Result};
+use chrono::{SecondsFormat, Utc};
+use nix::{
+    errno::Errno,
+    fcntl::{self, OFlag},
+    poll::{poll, PollFd, PollFlags},
+    sys::stat::Mode,
+    unistd::{self, close, dup2},
+};
+use serde::Serialize;
+use std::{
+    fs::{self, File, OpenOptions},
+    io::Write,
+    os::unix::io::RawFd,
+    path::{Path, PathBuf},
+};
+
+use crate::process::fork;
+
+/// Name of the log file in the state directory of the container
+pub const LOG_FILE_NAME: &str = "container.log";
+
+/// Options of the log driver
+#[derive(Debug, Clone)]
+pub struct LogOptions {
+    max_size: Option<u64>,
+    max_files: usize,
+}
+
+impl Default for LogOptions {
+    fn default() -> Self {
+        Self {
+            max_size: None,
+            max_files: 1,
+        }
+    }
+}
+
+impl LogOptions {
+    /// Rotates the log file before it exceeds the size in bytes. The file
+    /// grows without limit by default.
+    pub fn with_max_size(mut self, max_size: Option<u64>) -> Self {
+        self.max_size = max_size;
+        self
+    }
+
+    /// Number of log files which are kept on rotation, including the current
+    /// one. Rotated files are suffixed with .1 (newest) up to .N-1 (oldest).
+    /// With a single file, it is truncated on rotation.
+    pub fn with_max_files(mut self, max_files: usize) -> Self {
+        self.max_files = max_files.max(1);
+        self
+    }
+}
+
+#[derive(Debug, Clone, Copy, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Stream {
+    Stdout,
+    Stderr,
+}
+
+#[derive(Serialize)]
+struct Record<'a> {
+    log: &'a str,
+    stream: Stream,
+    time: String,
+}
+
+/// Writes records to the log file and rotates it
+pub struct LogWriter {
+    path: PathBuf,
+    options: LogOptions,
+    file: File,
+    size: u64,
+}
+
+impl LogWriter {
+    pub fn new<P: Into<PathBuf>>(path: P, options: LogOptions) -> Result<Self> {
+        let path = path.into();
+        let file = open_log_file(&path)?;
+        let size = file.metadata()?.len();
+        Ok(Self {
+            path,
+            options,
+            file,
+            size,
+        })
+    }
+
+    /// Writes a line, including its newline if it has one, as a record
+    pub fn write_line(&mut self, stream: Stream, line: &[u8]) -> Result<()> {
+        let record = Record {
+            log: &String::from_utf8_lossy(line),
+            stream,
+            time: Utc::now().to_rfc3339_opts(SecondsFormat::Nanos, true),
+        };
+        let mut record = serde_json::to_vec(&record)?;
+        record.push(b'\n');
+
+        if let Some(max_size) = self.options.max_size {
+            if self.size > 0 && self.size + record.len() as u64 > max_size {
+                self.rotate()?;
+            }
+        }
+
+        self.file
+            .write_all(&record)
+            .with_context(|| format!("failed to write to log file {:?}", self.path))?;
+        self.size += record.len() as u64;
+        Ok(())
+    }
+
+    fn rotate(&mut self) -> Result<()> {
+        if self.options.max_files > 1 {
+            for i in (1..self.options.max_files - 1).rev() {
+                let from = rotated_path(&self.path, i);
+                if from.exists() {
+                    fs::rename(&from, rotated_path(&self.path, i + 1))
+                        .with_context(|| format!("failed to rotate log file {:?}", from))?;
+                }
+            }
+            fs::rename(&self.path, rotated_path(&self.path, 1))
+                .with_context(|| format!("failed to rotate log file {:?}", self.path))?;
+        } else {
+            fs::remove_file(&self.path)
+                .with_context(|| format!("failed to truncate log file {:?}", self.path))?;
+        }
+
+        self.file = open_log_file(&self.path)?;
+        self.size = 0;
+        Ok(())
+    }
+}
+
+/// Write ends of the pipes, which replace stdout and stderr of the container
+/// process
+#[derive(Debug, Clone, Copy)]
+pub struct LogPipes {
+    stdout: RawFd,
+    stderr: RawFd,
+}
+
+impl LogPipes {
+    /// Replaces stdout and stderr of the calling process with the pipes
+    pub fn redirect(&self) -> Result<()> {
+        dup2(self.stdout, libc::STDOUT_FILENO).context("failed to redirect stdout")?;
+        dup2(self.stderr, libc::STDERR_FILENO).context("failed to redirect stderr")?;
+        self.close()
+    }
+
+    /// Closes the pipes in the calling process, the log driver exits once
+    /// they have been closed in all processes
+    pub fn close(&self) -> Result<()> {
+        close(self.stdout).context("failed to close stdout pipe")?;
+        close(self.stderr).context("failed to close stderr pipe")?;
+        Ok(())
+    }
+}
+
+/// Starts the log driver process, which writes to the log file in the state
+/// directory. Returns the pipes which have to be passed to the container.
+pub fn start(state_dir: &Path, options: LogOptions) -> Result<LogPipes> {
+    let mut writer = LogWriter::new(state_dir.join(LOG_FILE_NAME), options)?;
+    let (stdout_read, stdout_write) =
+        unistd::pipe2(OFlag::O_CLOEXEC).context("failed to create stdout pipe")?;
+    let (stderr_read, stderr_write) =
+        unistd::pipe2(OFlag::O_CLOEXEC).context("failed to create stderr pipe")?;
+
+    fork::container_fork(|| {
+        close(stdout_write)?;
+        close(stderr_write)?;
+        detach()?;
+        relay_to_log(&mut writer, stdout_read, stderr_read)
+    })
+    .context("failed to start log driver")?;
+
+    close(stdout_read)?;
+    close(stderr_read)?;
+    Ok(LogPipes {
+        stdout: stdout_write,
+        stderr: stderr_write,
+    })
+}
+
+/// Detaches the log driver from the session and stdio of the caller, which
+/// may wait for their end
+fn detach() -> Result<()> {
+    unistd::setsid().context("failed to create session")?;
+    let dev_null = fcntl::open("/dev/null", OFlag::O_RDWR, Mode::empty())
+        .context("failed to open /dev/null")?;
+    for fd in [libc::STDIN_FILENO, libc::STDOUT_FILENO, libc::STDERR_FILENO] {
+        dup2(dev_null, fd)?;
+    }
+    close(dev_null)?;
+    Ok(())
+}
+
+/// Writes the lines of both streams until all writers have closed them
+fn relay_to_log(writer: &mut LogWriter, stdout: RawFd, stderr: RawFd) -> Result<()> {
+    let mut streams = vec![
+        (stdout, Stream::Stdout, Vec::new()),
+        (stderr, Stream::Stderr, Vec::new()),
+    ];
+    let mut buf = [0u8; 4096];
+    while !streams.is_empty() {
+        let mut fds: Vec<PollFd> = streams
+            .iter()
+            .map(|(fd, _, _)| PollFd::new(*fd, PollFlags::POLLIN))
+            .collect();
+        match poll(&mut fds, -1) {
+            Ok(_) => {}
+            Err(Errno::EINTR) => continue,
+            Err(err) => return Err(err).context("failed to wait for container output"),
+        }
+
+        let ready: Vec<bool> = fds
+            .iter()
+            .map(|fd| !fd.revents().unwrap_or_else(PollFlags::empty).is_empty())
+            .collect();
+        let mut closed = Vec::new();
+        for (i, (fd, stream, partial)) in streams.iter_mut().enumerate() {
+            if !ready[i] {
+                continue;
+            }
+
+            let n = match unistd::read(*fd, &mut buf) {
+                Ok(n) => n,
+                Err(Errno::EINTR | Errno::EAGAIN) => continue,
+                Err(err) => return Err(err).context("failed to read container output"),
+            };
+            if n == 0 {
+                // a last line without newline is written as it is
+                if !partial.is_empty() {
+                    writer.write_line(*stream, partial)?;
+                }
+                closed.push(i);
+                continue;
+            }
+
+            partial.extend_from_slice(&buf[..n]);
+            while let Some(end) = partial.iter().position(|&b| b == b'\n') {
+                let line: Vec<u8> = partial.drain(..=end).collect();
+                writer.write_line(*stream, &line)?;
+            }
+        }
+
+        for i in closed.into_iter().rev() {
+            let (fd, _, _) = streams.remove(i);
+            let _ = close(fd);
+        }
+    }
+
+    Ok(())
+}
+
+fn open_log_file(path: &Path) -> Result<File> {
+    OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .with_context(|| format!("failed to open log file {:?}", path))
+}
+
+fn rotated_path(path: &Path, i: usize) -> PathBuf {
+    let mut rotated = path.as_os_str().to_owned();
+    rotated.push(format!(".{}", i));
+    PathBuf::from(rotated)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::create_temp_dir;
+
+    fn read_records(path: &Path) -> Result<Vec<serde_json::Value>> {
+        fs::read_to_string(path)?
+            .lines()
+            .map(|line| Ok(serde_json::from_str(line)?))
+            .collect()
+    }
+
+    #[test]
+    fn test_write_line() -> Result<()> {
+        let tmp = create_temp_dir("test_write_line")?;
+        let path = tmp.join(LOG_FILE_NAME);
+        let mut writer = LogWriter::new(&path, LogOptions::default())?;
+        writer.write_line(Stream::Stdout, b"hello\n")?;
+        writer.write_line(Stream::Stderr, b"world")?;
+
+        let records = read_records(&path)?;
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0]["log"], "hello\n");
+        assert_eq!(records[0]["stream"], "stdout");
+        assert!(records[0]["time"].is_string());
+        assert_eq!(records[1]["log"], "world");
+        assert_eq!(records[1]["stream"], "stderr");
+        Ok(())
+    }
+
+    #[test]
+    fn test_rotate() -> Result<()> {
+        let tmp = create_temp_dir("test_rotate")?;
+        let path = tmp.join(LOG_FILE_NAME);
+        let options = LogOptions::default()
+            .with_max_size(Some(1))
+            .with_max_files(3);
+        let mut writer = LogWriter::new(&path, options)?;
+        for line in ["1\n", "2\n", "3\n", "4\n"] {
+            writer.write_line(Stream::Stdout, line.as_bytes())?;
+        }
+
+        assert_eq!(read_records(&path)?[0]["log"], "4\n");
+        assert_eq!(read_records(&rotated_path(&path, 1))?[0]["log"], "3\n");
+        assert_eq!(read_records(&rotated_path(&path, 2))?[0]["log"], "2\n");
+        assert!(!rotated_path(&path, 3).exists());
+
+        let mut writer = LogWriter::new(&path, LogOptions::default().with_max_size(Some(1)))?;
+        writer.write_line(Stream::Stdout, b"5\n")?;
+        let records = read_records(&path)?;
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0]["log"], "5\n");
+        Ok(())
+    }
+}
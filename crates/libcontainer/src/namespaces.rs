@@ -8,11 +8,26 @@
 //! Cgroup (Resource limits, execution priority etc.)
 
 use crate::syscall::{syscall::create_syscall, Syscall};
-use anyhow::{Context, Result};
+use anyhow::{bail, Context, Result};
 use nix::{fcntl, sched::CloneFlags, sys::stat, unistd};
-use oci_spec::runtime::{LinuxNamespace, LinuxNamespaceType};
+use oci_spec::runtime::{LinuxNamespace, LinuxNamespaceType, Spec};
 use std::collections;
 
+/// Order in which namespaces are entered. The user namespace comes first, as
+/// it determines the privileges over all other namespaces. The pid namespace
+/// only applies to children, so it is entered before the init process is
+/// forked. The mount namespace comes last, because the paths of the other
+/// namespaces may only be reachable through the mount namespace of the host.
+const NAMESPACE_ORDER: &[LinuxNamespaceType] = &[
+    LinuxNamespaceType::User,
+    LinuxNamespaceType::Pid,
+    LinuxNamespaceType::Network,
+    LinuxNamespaceType::Ipc,
+    LinuxNamespaceType::Uts,
+    LinuxNamespaceType::Cgroup,
+    LinuxNamespaceType::Mount,
+];
+
 /// Holds information about namespaces
 pub struct Namespaces {
     command: Box<dyn Syscall>,
@@ -48,21 +63,24 @@ impl From<Option<&Vec<LinuxNamespace>>> for Namespaces {
 }
 
 impl Namespaces {
+    /// Enters all namespaces accepted by the filter in dependency order
+    #[tracing::instrument(level = "debug", skip_all, name = "namespaces")]
     pub fn apply_namespaces<F: Fn(CloneFlags) -> bool>(&self, filter: F) -> Result<()> {
-        let to_enter: collections::HashMap<&CloneFlags, &LinuxNamespace> = self
-            .namespace_map
-            .iter()
-            .filter(|(k, _)| filter(**k))
-            .collect();
-        for (ns_type, ns) in to_enter {
+        let to_enter = self.ordered().filter(|ns| filter(get_clone_flag(ns.typ())));
+        for ns in to_enter {
             self.unshare_or_setns(ns)
-                .with_context(|| format!("Failed to enter {:?} namespace: {:?}", ns_type, ns))?;
+                .with_context(|| format!("Failed to enter {:?} namespace: {:?}", ns.typ(), ns))?;
         }
         Ok(())
     }
 
+    /// Iterates over the namespaces in the order they have to be entered
+    pub fn ordered(&self) -> impl Iterator<Item = &LinuxNamespace> {
+        NAMESPACE_ORDER.iter().filter_map(move |&typ| self.get(typ))
+    }
+
     pub fn unshare_or_setns(&self, namespace: &LinuxNamespace) -> Result<()> {
-        log::debug!("unshare or setns: {:?}", namespace);
+        tracing::debug!("unshare or setns: {:?}", namespace);
         if namespace.path().is_none() {
             self.command.unshare(get_clone_flag(namespace.typ()))?;
         } else {
@@ -83,6 +101,50 @@ impl Namespaces {
     }
 }
 
+/// Rejects namespace configurations which can not be applied
+pub fn validate_namespaces(spec: &Spec) -> Result<()> {
+    let namespaces = match spec.linux().as_ref().and_then(|l| l.namespaces().as_ref()) {
+        Some(namespaces) => namespaces,
+        None => return Ok(()),
+    };
+
+    for (i, ns) in namespaces.iter().enumerate() {
+        if namespaces[..i].iter().any(|other| other.typ() == ns.typ()) {
+            bail!("{:?} namespace is specified more than once", ns.typ());
+        }
+    }
+
+    let find = |typ: LinuxNamespaceType| namespaces.iter().find(|ns| ns.typ() == typ);
+    // namespaces are owned by the user namespace they have been created in.
+    // Once a new user namespace has been created, there are no privileges left
+    // to join namespaces that are owned by the user namespace of the host.
+    if let Some(user_ns) = find(LinuxNamespaceType::User) {
+        if user_ns.path().is_none() {
+            if let Some(joined) = namespaces
+                .iter()
+                .find(|ns| ns.typ() != LinuxNamespaceType::User && ns.path().is_some())
+            {
+                bail!(
+                    "cannot join existing {:?} namespace {:?} from a new user namespace, \
+                    the user namespace owning it has to be joined as well",
+                    joined.typ(),
+                    joined.path().as_ref().unwrap()
+                );
+            }
+        }
+    }
+
+    // the hostname is only set in a new uts namespace, a joined uts namespace
+    // keeps its hostname
+    if let Some(hostname) = spec.hostname() {
+        if !hostname.is_empty() && find(LinuxNamespaceType::Uts).is_none() {
+            bail!("cannot set hostname without a uts namespace");
+        }
+    }
+
+    Ok(())
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -127,20 +189,90 @@ mod tests {
             .apply_namespaces(|ns_type| { ns_type != CloneFlags::CLONE_NEWIPC })
             .is_ok());
 
-        let mut setns_args: Vec<_> = test_command
+        let setns_args: Vec<_> = test_command
             .get_setns_args()
             .into_iter()
             .map(|(_fd, cf)| cf)
             .collect();
-        setns_args.sort();
-        let mut expect = vec![CloneFlags::CLONE_NEWNS, CloneFlags::CLONE_NEWNET];
-        expect.sort();
-        assert_eq!(setns_args, expect);
-
-        let mut unshare_args = test_command.get_unshare_args();
-        unshare_args.sort();
-        let mut expect = vec![CloneFlags::CLONE_NEWUSER, CloneFlags::CLONE_NEWPID];
-        expect.sort();
-        assert_eq!(unshare_args, expect)
+        // mount namespace has to be entered last
+        assert_eq!(
+            setns_args,
+            vec![CloneFlags::CLONE_NEWNET, CloneFlags::CLONE_NEWNS]
+        );
+
+        // user namespace has to be entered first
+        let unshare_args = test_command.get_unshare_args();
+        assert_eq!(
+            unshare_args,
+            vec![CloneFlags::CLONE_NEWUSER, CloneFlags::CLONE_NEWPID]
+        )
+    }
+
+    #[test]
+    fn test_ordered_namespaces() {
+        let sample_linux_namespaces = gen_sample_linux_namespaces();
+        let namespaces = Namespaces::from(Some(&sample_linux_namespaces));
+        let order: Vec<_> = namespaces.ordered().map(|ns| ns.typ()).collect();
+        assert_eq!(
+            order,
+            vec![
+                LinuxNamespaceType::User,
+                LinuxNamespaceType::Pid,
+                LinuxNamespaceType::Network,
+                LinuxNamespaceType::Ipc,
+                LinuxNamespaceType::Mount,
+            ]
+        );
+    }
+
+    fn spec_with_namespaces(namespaces: Vec<LinuxNamespace>) -> Spec {
+        let mut spec = Spec::default();
+        let mut linux = spec.linux().clone().unwrap();
+        linux.set_namespaces(Some(namespaces));
+        spec.set_linux(Some(linux)).set_hostname(None);
+        spec
+    }
+
+    #[test]
+    fn test_validate_namespaces() {
+        assert!(validate_namespaces(&Spec::default()).is_ok());
+
+        let duplicated = spec_with_namespaces(vec![
+            LinuxNamespaceBuilder::default()
+                .typ(LinuxNamespaceType::Ipc)
+                .build()
+                .unwrap(),
+            LinuxNamespaceBuilder::default()
+                .typ(LinuxNamespaceType::Ipc)
+                .path("/proc/1/ns/ipc")
+                .build()
+                .unwrap(),
+        ]);
+        assert!(validate_namespaces(&duplicated).is_err());
+
+        let joined_from_new_userns = spec_with_namespaces(gen_sample_linux_namespaces());
+        assert!(validate_namespaces(&joined_from_new_userns).is_err());
+
+        let mut hostname_without_uts = spec_with_namespaces(vec![]);
+        hostname_without_uts.set_hostname(Some("youki".to_owned()));
+        assert!(validate_namespaces(&hostname_without_uts).is_err());
+
+        // the default spec sets a hostname, which is kept by a joined uts
+        // namespace
+        let mut hostname_with_joined_uts = Spec::default();
+        let mut linux = hostname_with_joined_uts.linux().clone().unwrap();
+        let mut namespaces = linux.namespaces().clone().unwrap();
+        namespaces.retain(|ns| ns.typ() != LinuxNamespaceType::Uts);
+        namespaces.push(
+            LinuxNamespaceBuilder::default()
+                .typ(LinuxNamespaceType::Uts)
+                .path("/proc/1/ns/uts")
+                .build()
+                .unwrap(),
+        );
+        linux.set_namespaces(Some(namespaces));
+        hostname_with_joined_uts.set_linux(Some(linux));
+        assert!(hostname_with_joined_uts.hostname().is_some());
+        assert!(validate_namespaces(&hostname_with_joined_uts).is_ok());
     }
 }
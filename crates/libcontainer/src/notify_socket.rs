@@ -1,13 +1,19 @@
 use anyhow::{bail, Context, Result};
 use nix::unistd::{self, close};
 use std::env;
-use std::io::prelude::*;
+use std::io::{prelude::*, ErrorKind};
+use std::net::Shutdown;
 use std::os::unix::io::AsRawFd;
 use std::os::unix::net::{UnixListener, UnixStream};
 use std::path::{Path, PathBuf};
+use std::time::Duration;
 
 pub const NOTIFY_FILE: &str = "notify.sock";
 
+/// Time to wait for the container process to execute its payload once it
+/// has been told to start
+pub const DEFAULT_START_TIMEOUT: Duration = Duration::from_secs(60);
+
 pub struct NotifyListener {
     socket: UnixListener,
 }
@@ -35,17 +41,21 @@ impl NotifyListener {
         Ok(Self { socket: stream })
     }
 
-    pub fn wait_for_container_start(&self) -> Result<()> {
-        match self.socket.accept() {
-            Ok((mut socket, _)) => {
-                let mut response = String::new();
-                socket.read_to_string(&mut response)?;
-                log::debug!("received: {}", response);
-            }
-            Err(e) => bail!("accept function failed: {:?}", e),
-        }
+    /// Waits until the container is told to start. The connection of the
+    /// returned request is closed on exec, which signals the successful
+    /// start of the payload to the other end.
+    pub fn wait_for_container_start(&self) -> Result<StartRequest> {
+        let (mut socket, _) = self
+            .socket
+            .accept()
+            .context("failed to accept start request")?;
+        let mut request = String::new();
+        socket
+            .read_to_string(&mut request)
+            .context("failed to read start request")?;
+        tracing::debug!("received: {}", request);
 
-        Ok(())
+        Ok(StartRequest { socket })
     }
 
     pub fn close(&mut self) -> Result<()> {
@@ -54,25 +64,75 @@ impl NotifyListener {
     }
 }
 
+/// Request to start the container, received by the container process
+pub struct StartRequest {
+    socket: UnixStream,
+}
+
+impl StartRequest {
+    /// Reports the error which prevented the payload from being executed to
+    /// the process which requested the start
+    pub fn report_error(&mut self, err: &anyhow::Error) {
+        if let Err(write_err) = self.socket.write_all(format!("{:#}", err).as_bytes()) {
+            tracing::warn!("failed to report start error: {}", write_err);
+        }
+    }
+}
+
 pub struct NotifySocket {
     path: PathBuf,
+    timeout: Duration,
 }
 
 impl NotifySocket {
     pub fn new<P: Into<PathBuf>>(socket_path: P) -> Self {
         Self {
             path: socket_path.into(),
+            timeout: DEFAULT_START_TIMEOUT,
         }
     }
 
+    /// Sets the time to wait for the container process to execute its
+    /// payload. The timeout must not be zero.
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Tells the container process to start and waits until it has executed
+    /// its payload. Fails with the error of the container process if the
+    /// payload could not be executed.
     pub fn notify_container_start(&mut self) -> Result<()> {
-        log::debug!("notify container start");
+        tracing::debug!("notify container start");
         let cwd = env::current_dir()?;
         unistd::chdir(self.path.parent().unwrap())?;
-        let mut stream = UnixStream::connect(&self.path.file_name().unwrap())?;
-        stream.write_all(b"start container")?;
-        log::debug!("notify finished");
+        let stream = UnixStream::connect(&self.path.file_name().unwrap());
         unistd::chdir(&cwd)?;
+        let mut stream = stream.context("failed to connect to the container process")?;
+
+        stream.write_all(b"start container")?;
+        stream
+            .shutdown(Shutdown::Write)
+            .context("failed to send start request")?;
+
+        // the container process only answers if it has failed, otherwise the
+        // connection is closed once the payload has been executed
+        stream.set_read_timeout(Some(self.timeout))?;
+        let mut response = String::new();
+        match stream.read_to_string(&mut response) {
+            Ok(_) => {}
+            Err(err) if matches!(err.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {
+                bail!("container process did not start within {:?}", self.timeout)
+            }
+            Err(err) => {
+                return Err(err).context("failed to wait for the container process to start")
+            }
+        }
+        if !response.is_empty() {
+            bail!("container process failed to start: {}", response);
+        }
+
+        tracing::debug!("notify finished");
         Ok(())
     }
 
@@ -81,3 +141,71 @@ impl NotifySocket {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::create_temp_dir;
+    use anyhow::anyhow;
+    use serial_test::serial;
+    use std::thread;
+
+    #[test]
+    #[serial]
+    fn test_start_error_is_reported() -> Result<()> {
+        let tmp = create_temp_dir("test_start_error_is_reported")?;
+        let path = tmp.join(NOTIFY_FILE);
+        let listener = NotifyListener::new(&path)?;
+
+        let init = thread::spawn(move || -> Result<()> {
+            let mut request = listener.wait_for_container_start()?;
+            request.report_error(&anyhow!("executable file not found"));
+            Ok(())
+        });
+
+        let err = NotifySocket::new(&path)
+            .notify_container_start()
+            .expect_err("start should fail");
+        init.join().unwrap()?;
+        assert!(err.to_string().contains("executable file not found"));
+        Ok(())
+    }
+
+    #[test]
+    #[serial]
+    fn test_start_succeeds_on_close() -> Result<()> {
+        let tmp = create_temp_dir("test_start_succeeds_on_close")?;
+        let path = tmp.join(NOTIFY_FILE);
+        let listener = NotifyListener::new(&path)?;
+
+        let init = thread::spawn(move || -> Result<()> {
+            drop(listener.wait_for_container_start()?);
+            Ok(())
+        });
+
+        NotifySocket::new(&path).notify_container_start()?;
+        init.join().unwrap()?;
+        Ok(())
+    }
+
+    #[test]
+    #[serial]
+    fn test_start_timeout() -> Result<()> {
+        let tmp = create_temp_dir("test_start_timeout")?;
+        let path = tmp.join(NOTIFY_FILE);
+        let listener = NotifyListener::new(&path)?;
+
+        let init = thread::spawn(move || -> Result<()> {
+            let _request = listener.wait_for_container_start()?;
+            thread::sleep(Duration::from_millis(500));
+            Ok(())
+        });
+
+        let result = NotifySocket::new(&path)
+            .with_timeout(Duration::from_millis(50))
+            .notify_container_start();
+        init.join().unwrap()?;
+        assert!(result.is_err());
+        Ok(())
+    }
+}
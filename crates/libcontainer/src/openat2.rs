@@ -0,0 +1,282 @@
+//! Path resolution with openat2(2), which can restrict how the components of
+//! a path are resolved. This prevents a rootfs from redirecting paths, which
+//! youki mounts over, to other locations through symlinks. Available since
+//! Linux 5.6.
+use anyhow::{bail, Context, Result};
+use nix::{
+    errno::Errno,
+    fcntl::{open, OFlag},
+    sys::stat::Mode,
+};
+use std::{
+    ffi::CString,
+    fs::{self, File},
+    mem,
+    os::unix::{
+        ffi::OsStrExt,
+        io::{AsRawFd, FromRawFd, RawFd},
+    },
+    path::{Component, Path, PathBuf},
+};
+
+use crate::utils;
+
+// see include/uapi/linux/openat2.h
+pub const RESOLVE_NO_XDEV: u64 = 0x01;
+pub const RESOLVE_NO_MAGICLINKS: u64 = 0x02;
+pub const RESOLVE_NO_SYMLINKS: u64 = 0x04;
+pub const RESOLVE_BENEATH: u64 = 0x08;
+pub const RESOLVE_IN_ROOT: u64 = 0x10;
+
+#[repr(C)]
+struct OpenHow {
+    flags: u64,
+    mode: u64,
+    resolve: u64,
+}
+
+/// Opens the path relative to the directory with the restrictions of the
+/// resolve flags. Fails with ENOSYS if the kernel does not support openat2.
+pub fn openat2(dirfd: RawFd, path: &Path, flags: OFlag, resolve: u64) -> nix::Result<File> {
+    let c_path = CString::new(path.as_os_str().as_bytes()).map_err(|_| Errno::EINVAL)?;
+    let how = OpenHow {
+        flags: (flags | OFlag::O_CLOEXEC).bits() as u64,
+        mode: 0,
+        resolve,
+    };
+    let fd = unsafe {
+        libc::syscall(
+            libc::SYS_openat2,
+            dirfd,
+            c_path.as_ptr(),
+            &how as *const OpenHow,
+            mem::size_of::<OpenHow>(),
+        )
+    };
+    let fd = Errno::result(fd)?;
+    Ok(unsafe { File::from_raw_fd(fd as RawFd) })
+}
+
+/// Opens an absolute path as O_PATH file descriptor without following any
+/// symlinks. Returns None if the path does not exist. On kernels without
+/// openat2, the path which the file descriptor refers to is compared to the
+/// requested path instead.
+pub fn open_no_symlinks(path: &Path) -> Result<Option<File>> {
+    if !path.is_absolute() {
+        bail!("{:?} is not an absolute path", path);
+    }
+
+    match openat2(libc::AT_FDCWD, path, OFlag::O_PATH, RESOLVE_NO_SYMLINKS) {
+        Ok(file) => Ok(Some(file)),
+        Err(Errno::ENOENT) => Ok(None),
+        Err(Errno::ELOOP) => bail!("{:?} must not contain symlinks", path),
+        Err(Errno::ENOSYS) => open_no_symlinks_fallback(path),
+        Err(err) => Err(err).with_context(|| format!("failed to open {:?}", path)),
+    }
+}
+
+fn open_no_symlinks_fallback(path: &Path) -> Result<Option<File>> {
+    let c_path = CString::new(path.as_os_str().as_bytes())?;
+    let flags = OFlag::O_PATH | OFlag::O_NOFOLLOW | OFlag::O_CLOEXEC;
+    let fd = unsafe { libc::open(c_path.as_ptr(), flags.bits()) };
+    let file = match Errno::result(fd) {
+        Ok(fd) => unsafe { File::from_raw_fd(fd) },
+        Err(Errno::ENOENT) => return Ok(None),
+        Err(err) => return Err(err).with_context(|| format!("failed to open {:?}", path)),
+    };
+
+    // a symlink in one of the components results in another path
+    let resolved = fs::read_link(proc_fd_path(&file))
+        .with_context(|| format!("failed to resolve {:?}", path))?;
+    if resolved != path || fs::symlink_metadata(&resolved)?.file_type().is_symlink() {
+        bail!("{:?} must not contain symlinks", path);
+    }
+
+    Ok(Some(file))
+}
+
+/// Resolves the path as if the root directory was the root of the filesystem,
+/// which is the case for the container process. Symlinks and ".." components
+/// can not escape the root, which is enforced by the kernel. Components which
+/// do not exist yet are appended to the resolved path of the longest existing
+/// prefix. Unlike a mount target, the resolved path may still be changed by
+/// other processes which have access to the root, e.g. if the rootfs is
+/// shared by several containers.
+pub fn resolve_in_root(root: &Path, path: &Path) -> Result<PathBuf> {
+    let root_dir = File::open(root).with_context(|| format!("failed to open {:?}", root))?;
+    let path = path.strip_prefix("/").unwrap_or(path);
+
+    for existing in path.ancestors() {
+        let existing = if existing.as_os_str().is_empty() {
+            Path::new(".")
+        } else {
+            existing
+        };
+
+        let file = match openat2(
+            root_dir.as_raw_fd(),
+            existing,
+            OFlag::O_PATH,
+            RESOLVE_IN_ROOT | RESOLVE_NO_MAGICLINKS,
+        ) {
+            Ok(file) => file,
+            Err(Errno::ENOENT) => continue,
+            // kernels before 5.6, the symlinks are resolved in user space
+            Err(Errno::ENOSYS) => return utils::secure_join(root, path),
+            Err(err) => {
+                return Err(err).with_context(|| format!("failed to resolve {:?}", existing))
+            }
+        };
+
+        let resolved = fs::read_link(proc_fd_path(&file))
+            .with_context(|| format!("failed to resolve {:?}", existing))?;
+        let missing = path
+            .strip_prefix(existing)
+            .unwrap_or_else(|_| Path::new(""));
+        if missing
+            .components()
+            .any(|component| !matches!(component, Component::Normal(_)))
+        {
+            // ".." after a missing component can not be resolved by the
+            // kernel, it is resolved lexically like in secure_join
+            return utils::secure_join(resolved.as_path(), missing);
+        }
+
+        return Ok(resolved.join(missing));
+    }
+
+    bail!("failed to resolve {:?} in {:?}", path, root)
+}
+
+/// Opens the path in the root as if the root directory was the root of the
+/// filesystem, like resolve_in_root. Unlike the resolved path, the file
+/// descriptor can not be redirected by replacing a component of the path with
+/// a symlink afterwards, so mounts are done onto it through proc_fd_path.
+/// Returns None if the path does not exist.
+pub fn open_in_root(root: &Path, path: &Path, flags: OFlag) -> Result<Option<File>> {
+    let root_dir = File::open(root).with_context(|| format!("failed to open {:?}", root))?;
+    let relative = match path.strip_prefix("/").unwrap_or(path) {
+        relative if relative.as_os_str().is_empty() => Path::new("."),
+        relative => relative,
+    };
+
+    match openat2(
+        root_dir.as_raw_fd(),
+        relative,
+        flags,
+        RESOLVE_IN_ROOT | RESOLVE_NO_MAGICLINKS,
+    ) {
+        Ok(file) => Ok(Some(file)),
+        Err(Errno::ENOENT) => Ok(None),
+        // kernels before 5.6, the symlinks are resolved in user space
+        Err(Errno::ENOSYS) => {
+            let resolved = utils::secure_join(root, path)?;
+            match open(
+                &resolved,
+                flags | OFlag::O_NOFOLLOW | OFlag::O_CLOEXEC,
+                Mode::empty(),
+            ) {
+                Ok(fd) => Ok(Some(unsafe { File::from_raw_fd(fd) })),
+                Err(Errno::ENOENT) => Ok(None),
+                Err(err) => Err(err).with_context(|| format!("failed to open {:?}", resolved)),
+            }
+        }
+        Err(err) => Err(err).with_context(|| format!("failed to open {:?} in {:?}", path, root)),
+    }
+}
+
+/// Returns the path in procfs, through which the file can be accessed, e.g.
+/// to use it as mount target
+pub fn proc_fd_path(file: &File) -> PathBuf {
+    PathBuf::from(format!("/proc/self/fd/{}", file.as_raw_fd()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::create_temp_dir;
+    use std::io::Read;
+    use std::os::unix::fs::symlink;
+
+    #[test]
+    fn test_open_no_symlinks() -> Result<()> {
+        let tmp = create_temp_dir("test_open_no_symlinks")?;
+        let dir = tmp.join("dir");
+        fs::create_dir_all(&dir)?;
+        fs::write(dir.join("file"), "")?;
+        symlink(&dir, tmp.join("link"))?;
+
+        let file = open_no_symlinks(&dir)?.expect("dir should exist");
+        assert_eq!(fs::read_link(proc_fd_path(&file))?, dir);
+        assert!(open_no_symlinks(&tmp.join("missing"))?.is_none());
+        assert!(open_no_symlinks(&tmp.join("link")).is_err());
+        assert!(open_no_symlinks(&tmp.join("link/file")).is_err());
+        assert!(open_no_symlinks(Path::new("relative")).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_resolve_in_root() -> Result<()> {
+        let tmp = create_temp_dir("test_resolve_in_root")?;
+        let rootfs = tmp.join("rootfs");
+        fs::create_dir_all(rootfs.join("etc"))?;
+        symlink("/etc", rootfs.join("absolute"))?;
+        symlink("../../../../etc", rootfs.join("etc/relative"))?;
+
+        assert_eq!(
+            resolve_in_root(&rootfs, Path::new("/etc"))?,
+            rootfs.join("etc")
+        );
+        // symlinks are resolved within the rootfs
+        assert_eq!(
+            resolve_in_root(&rootfs, Path::new("/absolute/hosts"))?,
+            rootfs.join("etc/hosts")
+        );
+        assert_eq!(
+            resolve_in_root(&rootfs, Path::new("/etc/relative/missing/dir"))?,
+            rootfs.join("etc/missing/dir")
+        );
+        assert_eq!(
+            resolve_in_root(&rootfs, Path::new("/../../etc"))?,
+            rootfs.join("etc")
+        );
+        assert_eq!(resolve_in_root(&rootfs, Path::new("/"))?, rootfs);
+        Ok(())
+    }
+
+    #[test]
+    fn test_open_in_root() -> Result<()> {
+        let tmp = create_temp_dir("test_open_in_root")?;
+        let rootfs = tmp.join("rootfs");
+        fs::create_dir_all(rootfs.join("etc"))?;
+        fs::create_dir_all(tmp.join("etc"))?;
+        fs::write(rootfs.join("etc/passwd"), "container")?;
+        fs::write(tmp.join("etc/passwd"), "host")?;
+        symlink("/etc", rootfs.join("absolute"))?;
+        symlink("../../etc", rootfs.join("etc/relative"))?;
+
+        let open = |path: &str| -> Result<Option<PathBuf>> {
+            match open_in_root(&rootfs, Path::new(path), OFlag::O_PATH)? {
+                Some(file) => Ok(Some(fs::read_link(proc_fd_path(&file))?)),
+                None => Ok(None),
+            }
+        };
+        assert_eq!(open("/etc")?, Some(rootfs.join("etc")));
+        // symlinks are resolved within the rootfs
+        assert_eq!(open("/absolute/passwd")?, Some(rootfs.join("etc/passwd")));
+        assert_eq!(
+            open("/etc/relative/passwd")?,
+            Some(rootfs.join("etc/passwd"))
+        );
+        assert_eq!(open("/../etc/passwd")?, Some(rootfs.join("etc/passwd")));
+        assert_eq!(open("/")?, Some(rootfs.clone()));
+        assert_eq!(open("/missing")?, None);
+
+        let mut file = open_in_root(&rootfs, Path::new("/absolute/passwd"), OFlag::O_RDONLY)?
+            .expect("passwd should exist");
+        let mut content = String::new();
+        file.read_to_string(&mut content)?;
+        assert_eq!(content, "container");
+        Ok(())
+    }
+}
@@ -0,0 +1,101 @@
+//! Execution domain of the container process (see personality(2)). Setting
+//! the LINUX32 domain allows 32-bit userlands to run on a 64-bit kernel, as
+//! e.g. `uname -m` will report a 32-bit architecture.
+use anyhow::{bail, Result};
+use nix::errno::Errno;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+// see include/uapi/linux/personality.h
+const PER_LINUX: libc::c_ulong = 0x0000;
+const PER_LINUX32: libc::c_ulong = 0x0008;
+
+/// Execution domains which are supported by youki
+pub const SUPPORTED_DOMAINS: &[PersonalityDomain] =
+    &[PersonalityDomain::Linux, PersonalityDomain::Linux32];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum PersonalityDomain {
+    #[serde(rename = "LINUX")]
+    Linux,
+    #[serde(rename = "LINUX32")]
+    Linux32,
+}
+
+impl fmt::Display for PersonalityDomain {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let print = match self {
+            Self::Linux => "LINUX",
+            Self::Linux32 => "LINUX32",
+        };
+
+        write!(f, "{}", print)
+    }
+}
+
+/// Personality of the container process as specified by `linux.personality`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Personality {
+    pub domain: PersonalityDomain,
+    /// Additional flags. The runtime spec does not define any flags yet.
+    #[serde(default)]
+    pub flags: Vec<String>,
+}
+
+impl Personality {
+    pub fn validate(&self) -> Result<()> {
+        if !self.flags.is_empty() {
+            bail!("personality flags are not supported: {:?}", self.flags);
+        }
+
+        Ok(())
+    }
+
+    fn persona(&self) -> libc::c_ulong {
+        match self.domain {
+            PersonalityDomain::Linux => PER_LINUX,
+            PersonalityDomain::Linux32 => PER_LINUX32,
+        }
+    }
+
+    /// Sets the execution domain of the calling process. It is inherited by
+    /// the payload of the container on exec.
+    pub fn apply(&self) -> Result<()> {
+        tracing::debug!("set personality {}", self.domain);
+        if unsafe { libc::personality(self.persona()) } == -1 {
+            bail!(
+                "failed to set personality {}: {}",
+                self.domain,
+                Errno::last()
+            );
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_parse_personality() -> Result<()> {
+        let personality: Personality = serde_json::from_value(json!({ "domain": "LINUX32" }))?;
+        assert_eq!(personality.domain, PersonalityDomain::Linux32);
+        assert_eq!(personality.persona(), PER_LINUX32);
+        assert!(personality.validate().is_ok());
+
+        assert!(serde_json::from_value::<Personality>(json!({ "domain": "SVR4" })).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_personality_flags_are_rejected() {
+        let personality = Personality {
+            domain: PersonalityDomain::Linux,
+            flags: vec!["ADDR_NO_RANDOMIZE".to_owned()],
+        };
+        assert!(personality.validate().is_err());
+    }
+}
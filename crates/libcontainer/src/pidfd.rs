@@ -0,0 +1,124 @@
+//! Process file descriptors (see pidfd_open(2)) refer to a specific process.
+//! Unlike a pid, which may be reused by another process as soon as the
+//! process has exited, a pidfd can be used to signal the process or to wait
+//! for its exit without races. Available since Linux 5.3.
+//!
+//! As a pidfd can not outlive the youki invocation which opened it, the start
+//! time of the container init process is recorded in the state of the
+//! container. It is compared to the start time of the process a pidfd has
+//! been opened for, to detect if the pid has been reused.
+use anyhow::{Context, Result};
+use nix::{
+    errno::Errno,
+    poll::{self, PollFd, PollFlags},
+    sys::signal::Signal,
+    unistd::{self, Pid},
+};
+use procfs::process::Process;
+use std::{convert::TryFrom, os::unix::io::RawFd, ptr, time::Duration};
+
+/// File descriptor referring to a process
+#[derive(Debug)]
+pub struct PidFd {
+    fd: RawFd,
+    pid: Pid,
+}
+
+impl PidFd {
+    /// Opens a pidfd for the process with the given pid. Fails with ESRCH if
+    /// the process does not exist and with ENOSYS if the kernel does not
+    /// support pidfds.
+    pub fn open(pid: Pid) -> nix::Result<Self> {
+        let fd = unsafe { libc::syscall(libc::SYS_pidfd_open, pid.as_raw(), 0) };
+        let fd = Errno::result(fd)?;
+        Ok(Self {
+            fd: fd as RawFd,
+            pid,
+        })
+    }
+
+    pub fn pid(&self) -> Pid {
+        self.pid
+    }
+
+    /// Sends a signal to the process. This fails with ESRCH if the process
+    /// has already exited, even if its pid has been reused in the meantime.
+    pub fn send_signal(&self, signal: Signal) -> nix::Result<()> {
+        let result = unsafe {
+            libc::syscall(
+                libc::SYS_pidfd_send_signal,
+                self.fd,
+                signal as libc::c_int,
+                ptr::null::<libc::siginfo_t>(),
+                0,
+            )
+        };
+        Errno::result(result).map(drop)
+    }
+
+    /// Waits until the process has exited or the timeout has expired.
+    /// Returns if the process has exited.
+    pub fn wait_for_exit(&self, timeout: Duration) -> Result<bool> {
+        let timeout = libc::c_int::try_from(timeout.as_millis()).unwrap_or(libc::c_int::MAX);
+        let mut fds = [PollFd::new(self.fd, PollFlags::POLLIN)];
+        loop {
+            match poll::poll(&mut fds, timeout) {
+                Ok(ready) => return Ok(ready > 0),
+                Err(Errno::EINTR) => continue,
+                Err(err) => {
+                    return Err(err)
+                        .with_context(|| format!("failed to wait for process {}", self.pid))
+                }
+            }
+        }
+    }
+
+    /// Returns if the process has already exited
+    pub fn has_exited(&self) -> Result<bool> {
+        self.wait_for_exit(Duration::ZERO)
+    }
+}
+
+impl Drop for PidFd {
+    fn drop(&mut self) {
+        let _ = unistd::close(self.fd);
+    }
+}
+
+/// Returns the start time of the process in clock ticks after system boot,
+/// which together with the pid identifies a process
+pub fn start_time(pid: Pid) -> Result<u64> {
+    let process =
+        Process::new(pid.as_raw()).with_context(|| format!("failed to inspect process {}", pid))?;
+    Ok(process.stat.starttime)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::process::Command;
+
+    #[test]
+    fn test_pidfd_of_exited_process() -> Result<()> {
+        let mut child = Command::new("sleep").arg("10").spawn()?;
+        let pid = Pid::from_raw(child.id() as i32);
+        let pidfd = match PidFd::open(pid) {
+            Ok(pidfd) => pidfd,
+            // pidfds are not supported by the kernel
+            Err(Errno::ENOSYS) => {
+                child.kill()?;
+                return Ok(());
+            }
+            Err(err) => return Err(err.into()),
+        };
+
+        assert!(start_time(pid)? > 0);
+        assert!(!pidfd.has_exited()?);
+        pidfd.send_signal(Signal::SIGKILL)?;
+        assert!(pidfd.wait_for_exit(Duration::from_secs(5))?);
+
+        child.wait()?;
+        assert_eq!(pidfd.send_signal(Signal::SIGKILL), Err(Errno::ESRCH));
+        Ok(())
+    }
+}
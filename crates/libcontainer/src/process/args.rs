@@ -3,7 +3,11 @@ use oci_spec::runtime::Spec;
 use std::os::unix::prelude::RawFd;
 use std::path::PathBuf;
 
+use crate::executor::Executor;
+use crate::log_driver::LogPipes;
+use crate::rootfs::IdmappedMounts;
 use crate::rootless::Rootless;
+use crate::spec_extensions::SpecExtensions;
 use crate::{container::Container, notify_socket::NotifyListener, syscall::Syscall};
 
 pub struct ContainerArgs<'a> {
@@ -21,10 +25,35 @@ pub struct ContainerArgs<'a> {
     pub notify_socket: NotifyListener,
     /// File descriptos preserved/passed to the container init process.
     pub preserve_fds: i32,
+    /// Sockets passed by systemd socket activation, which precede the
+    /// preserved fds
+    pub listen_fds: i32,
     /// Container state
     pub container: &'a Option<Container>,
     /// Options for rootless containers
-    pub rootless: &'a Option<Rootless<'a>>,
+    pub rootless: &'a Option<Rootless>,
     /// Cgroup Manager
     pub cgroup_manager: Box<dyn CgroupManager>,
+    /// Fail if resource restrictions cannot be applied because a controller
+    /// is not available
+    pub strict_resources: bool,
+    /// Settings of the spec which are not supported by oci-spec
+    pub spec_extensions: &'a SpecExtensions,
+    /// Create the working directory of the container process if it does
+    /// not exist
+    pub create_cwd: bool,
+    /// Detach the container processes from the session of the caller
+    pub detach: bool,
+    /// Keep the session keyring of the caller instead of creating a new one
+    pub no_new_keyring: bool,
+    /// Use MS_MOVE and chroot instead of pivot_root to change the root
+    pub no_pivot: bool,
+    /// Create missing sources of bind mounts
+    pub create_missing_bind_sources: bool,
+    /// Executes the payload of the container process
+    pub executor: &'a dyn Executor,
+    /// Pipes to the log driver, which replace stdout and stderr
+    pub log_pipes: Option<LogPipes>,
+    /// Idmapped mounts, which are moved into the rootfs by the init process
+    pub idmapped_mounts: Option<&'a IdmappedMounts>,
 }
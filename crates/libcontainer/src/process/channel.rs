@@ -1,4 +1,4 @@
-use crate::process::message::Message;
+use crate::process::message::{Message, ProcessError};
 use anyhow::{bail, Context, Result};
 use nix::{
     sys::{socket, uio},
@@ -34,7 +34,7 @@ impl MainSender {
     // requests the Main to write the id mappings for the intermediate process
     // this needs to be done from the parent see https://man7.org/linux/man-pages/man7/user_namespaces.7.html
     pub fn identifier_mapping_request(&mut self) -> Result<()> {
-        log::debug!("send identifier mapping request");
+        tracing::debug!("send identifier mapping request");
         self.sender.send(Message::WriteMapping)?;
 
         Ok(())
@@ -49,7 +49,7 @@ impl MainSender {
 
     pub fn intermediate_ready(&mut self, pid: Pid) -> Result<()> {
         // Send over the IntermediateReady follow by the pid.
-        log::debug!("sending init pid ({:?})", pid);
+        tracing::debug!("sending init pid ({:?})", pid);
         self.sender.send(Message::IntermediateReady(pid.as_raw()))?;
 
         Ok(())
@@ -61,6 +61,15 @@ impl MainSender {
         Ok(())
     }
 
+    /// Reports the failure of the intermediate or init process, which is
+    /// returned by the main process instead of the message it waits for
+    pub fn process_error(&mut self, err: &anyhow::Error) -> Result<()> {
+        self.sender
+            .send(Message::ProcessError(ProcessError::from(err)))?;
+
+        Ok(())
+    }
+
     pub fn close(&self) -> Result<()> {
         self.sender.close()
     }
@@ -81,6 +90,7 @@ impl MainReceiver {
 
         match msg {
             Message::IntermediateReady(pid) => Ok(Pid::from_raw(pid)),
+            Message::ProcessError(err) => Err(err.into()),
             _ => bail!(
                 "receive unexpected message {:?} waiting for intermediate ready",
                 msg
@@ -95,6 +105,7 @@ impl MainReceiver {
             .context("failed to wait for mapping request")?;
         match msg {
             Message::WriteMapping => Ok(()),
+            Message::ProcessError(err) => Err(err.into()),
             msg => bail!(
                 "receive unexpected message {:?} waiting for mapping request",
                 msg
@@ -116,6 +127,7 @@ impl MainReceiver {
                 };
                 Ok(fd)
             }
+            Message::ProcessError(err) => Err(err.into()),
             msg => bail!(
                 "receive unexpected message {:?} waiting for seccomp request",
                 msg
@@ -132,6 +144,7 @@ impl MainReceiver {
             .context("failed to wait for init ready")?;
         match msg {
             Message::InitReady => Ok(()),
+            Message::ProcessError(err) => Err(err.into()),
             msg => bail!(
                 "receive unexpected message {:?} waiting for init ready",
                 msg
@@ -158,7 +171,7 @@ pub struct IntermediateSender {
 
 impl IntermediateSender {
     pub fn mapping_written(&mut self) -> Result<()> {
-        log::debug!("identifier mapping written");
+        tracing::debug!("identifier mapping written");
         self.sender.send(Message::MappingWritten)?;
 
         Ok(())
@@ -176,7 +189,7 @@ pub struct IntermediateReceiver {
 impl IntermediateReceiver {
     // wait until the parent process has finished writing the id mappings
     pub fn wait_for_mapping_ack(&mut self) -> Result<()> {
-        log::debug!("waiting for mapping ack");
+        tracing::debug!("waiting for mapping ack");
         let msg = self
             .receiver
             .recv()
@@ -489,6 +502,36 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    #[serial]
+    fn test_channel_process_error() -> Result<()> {
+        let (sender, receiver) = &mut main_channel()?;
+        match unsafe { unistd::fork()? } {
+            unistd::ForkResult::Parent { child } => {
+                wait::waitpid(child, None)?;
+                let err = receiver
+                    .wait_for_init_ready()
+                    .expect_err("init should have failed");
+                receiver.close()?;
+                let err = err
+                    .downcast_ref::<ProcessError>()
+                    .expect("error of the init process");
+                assert_eq!(err.errno, Some(libc::EPERM));
+                assert!(err.message.starts_with("failed to set hostname"));
+            }
+            unistd::ForkResult::Child => {
+                let err = Err::<(), _>(nix::errno::Errno::EPERM)
+                    .context("failed to set hostname")
+                    .unwrap_err();
+                sender.process_error(&err)?;
+                sender.close()?;
+                std::process::exit(0);
+            }
+        };
+
+        Ok(())
+    }
+
     #[test]
     #[serial]
     fn test_channel_id_mapping_ack() -> Result<()> {
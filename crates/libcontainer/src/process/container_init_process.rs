@@ -2,21 +2,27 @@ use super::args::ContainerArgs;
 use crate::apparmor;
 use crate::syscall::Syscall;
 use crate::{
-    capabilities, hooks, namespaces::Namespaces, process::channel, rootfs::RootFS,
-    rootless::Rootless, seccomp, tty, utils,
+    capabilities, hooks, keyring, namespaces::Namespaces, openat2, process::channel,
+    rootfs::RootFS, rootless::Rootless, seccomp, sysctl, tty, utils,
 };
 use anyhow::{bail, Context, Result};
 use nix::mount::MsFlags;
 use nix::sched::CloneFlags;
 use nix::{
+    errno::Errno,
     fcntl,
+    sys::{
+        stat::{self, Mode, SFlag},
+        statvfs::{self, FsFlags},
+    },
     unistd::{self, Gid, Uid},
 };
-use oci_spec::runtime::{LinuxNamespaceType, Spec, User};
-use std::collections::HashMap;
+use oci_spec::runtime::{LinuxNamespaceType, Process, Spec, User};
 use std::{
-    env, fs,
-    path::{Path, PathBuf},
+    env,
+    fs::{self, DirBuilder},
+    os::unix::{fs::DirBuilderExt, io::RawFd},
+    path::Path,
 };
 
 // Get a list of open fds for the calling process.
@@ -49,14 +55,21 @@ fn get_open_fds() -> Result<Vec<i32>> {
 // Cleanup any extra file descriptors, so the new container process will not
 // leak a file descriptor from before execve gets executed. The first 3 fd will
 // stay open: stdio, stdout, and stderr. We would further preserve the next
-// "preserve_fds" number of fds. Set the rest of fd with CLOEXEC flag, so they
-// will be closed after execve into the container payload. We can't close the
-// fds immediatly since we at least still need it for the pipe used to wait on
+// "preserve_fds" number of fds, whose CLOEXEC flag is cleared in case the
+// caller has set it. Set the rest of fd with CLOEXEC flag, so they will be
+// closed after execve into the container payload. We can't close the fds
+// immediatly since we at least still need it for the pipe used to wait on
 // starting the container.
 fn cleanup_file_descriptors(preserve_fds: i32) -> Result<()> {
     let open_fds = get_open_fds().with_context(|| "Failed to obtain opened fds")?;
     // Include stdin, stdout, and stderr for fd 0, 1, and 2 respectively.
     let min_fd = preserve_fds + 3;
+    // the builder has ensured that the preserved fds are open
+    for fd in (3..min_fd).filter(|fd| open_fds.contains(fd)) {
+        fcntl::fcntl(fd, fcntl::F_SETFD(fcntl::FdFlag::empty()))
+            .with_context(|| format!("failed to preserve fd {}", fd))?;
+    }
+
     let to_be_cleaned_up_fds: Vec<i32> = open_fds
         .iter()
         .filter_map(|&fd| if fd >= min_fd { Some(fd) } else { None })
@@ -71,46 +84,38 @@ fn cleanup_file_descriptors(preserve_fds: i32) -> Result<()> {
     Ok(())
 }
 
-fn sysctl(kernel_params: &HashMap<String, String>) -> Result<()> {
-    let sys = PathBuf::from("/proc/sys");
-    for (kernel_param, value) in kernel_params {
-        let path = sys.join(kernel_param.replace(".", "/"));
-        log::debug!(
-            "apply value {} to kernel parameter {}.",
-            value,
-            kernel_param
-        );
-        fs::write(path, value.as_bytes())
-            .with_context(|| format!("failed to set sysctl {}={}", kernel_param, value))?;
-    }
-
-    Ok(())
-}
-
 // make a read only path
 // The first time we bind mount, other flags are ignored,
 // so we need to mount it once and then remount it with the necessary flags specified.
 // https://man7.org/linux/man-pages/man2/mount.2.html
+// The path is resolved without following symlinks and mounted over through its
+// file descriptor, so that the rootfs can not redirect the mount to another
+// location. The file descriptor still refers to the covered mount after the
+// bind mount, so the path is resolved again for the remount.
 fn readonly_path(path: &Path, syscall: &dyn Syscall) -> Result<()> {
-    if let Err(err) = syscall.mount(
-        Some(path),
-        path,
+    let file = match openat2::open_no_symlinks(path)
+        .with_context(|| format!("failed to resolve read only path {:?}", path))?
+    {
+        Some(file) => file,
+        // ignore error if path is not exist.
+        None => return Ok(()),
+    };
+    let fd_path = openat2::proc_fd_path(&file);
+    syscall.mount(
+        Some(&fd_path),
+        &fd_path,
         None,
         MsFlags::MS_BIND | MsFlags::MS_REC,
         None,
-    ) {
-        if let Some(errno) = err.downcast_ref() {
-            // ignore error if path is not exist.
-            if matches!(errno, nix::errno::Errno::ENOENT) {
-                return Ok(());
-            }
-        }
-        bail!(err)
-    }
+    )?;
 
+    let file = openat2::open_no_symlinks(path)
+        .with_context(|| format!("failed to resolve read only path {:?}", path))?
+        .with_context(|| format!("read only path {:?} disappeared", path))?;
+    let fd_path = openat2::proc_fd_path(&file);
     syscall.mount(
-        Some(path),
-        path,
+        Some(&fd_path),
+        &fd_path,
         None,
         MsFlags::MS_NOSUID
             | MsFlags::MS_NODEV
@@ -121,10 +126,53 @@ fn readonly_path(path: &Path, syscall: &dyn Syscall) -> Result<()> {
         None,
     )?;
 
-    log::debug!("readonly path {:?} mounted", path);
+    tracing::debug!("readonly path {:?} mounted", path);
     Ok(())
 }
 
+// Remounts the root of the container read only. Only the root mount itself is
+// affected, so /dev and the other mounts stay writable. The flags of the root
+// mount are kept, as they may be locked if the mount has been created in
+// another user namespace.
+fn readonly_rootfs(syscall: &dyn Syscall) -> Result<()> {
+    let root = Path::new("/");
+    let current = statvfs::statvfs(root).context("failed to get flags of the root mount")?;
+    let locked = [
+        (FsFlags::ST_NOSUID, MsFlags::MS_NOSUID),
+        (FsFlags::ST_NODEV, MsFlags::MS_NODEV),
+        (FsFlags::ST_NOEXEC, MsFlags::MS_NOEXEC),
+        (FsFlags::ST_NOATIME, MsFlags::MS_NOATIME),
+        (FsFlags::ST_NODIRATIME, MsFlags::MS_NODIRATIME),
+        (FsFlags::ST_RELATIME, MsFlags::MS_RELATIME),
+    ]
+    .iter()
+    .filter(|(fs_flag, _)| current.flags().contains(*fs_flag))
+    .fold(MsFlags::empty(), |flags, (_, ms_flag)| flags | *ms_flag);
+
+    syscall.mount(
+        None,
+        root,
+        None,
+        MsFlags::MS_RDONLY | MsFlags::MS_REMOUNT | MsFlags::MS_BIND | locked,
+        None,
+    )
+}
+
+// Changes the root of the process in the mount namespace of the container to
+// the rootfs. If pivot_root is not allowed, the rootfs is moved on top of the
+// original root instead.
+fn change_root(rootfs_path: &Path, no_pivot: bool, syscall: &dyn Syscall) -> Result<()> {
+    if no_pivot {
+        syscall
+            .move_rootfs(rootfs_path)
+            .with_context(|| format!("Failed to move root to {:?}", rootfs_path))
+    } else {
+        syscall
+            .pivot_rootfs(rootfs_path)
+            .with_context(|| format!("Failed to pivot root to {:?}", rootfs_path))
+    }
+}
+
 // For files, bind mounts /dev/null over the top of the specified path.
 // For directories, mounts read-only tmpfs over the top of the specified path.
 fn masked_path(path: &Path, mount_label: &Option<String>, syscall: &dyn Syscall) -> Result<()> {
@@ -137,7 +185,7 @@ fn masked_path(path: &Path, mount_label: &Option<String>, syscall: &dyn Syscall)
     ) {
         if let Some(errno) = e.downcast_ref() {
             if matches!(errno, nix::errno::Errno::ENOENT) {
-                log::warn!("masked path {:?} not exist", path);
+                tracing::warn!("masked path {:?} not exist", path);
             } else if matches!(errno, nix::errno::Errno::ENOTDIR) {
                 let label = match mount_label {
                     Some(l) => format!("context=\"{}\"", l),
@@ -150,6 +198,8 @@ fn masked_path(path: &Path, mount_label: &Option<String>, syscall: &dyn Syscall)
                     MsFlags::MS_RDONLY,
                     Some(label.as_str()),
                 )?;
+            } else {
+                bail!(e)
             }
         } else {
             bail!(e)
@@ -158,6 +208,113 @@ fn masked_path(path: &Path, mount_label: &Option<String>, syscall: &dyn Syscall)
     Ok(())
 }
 
+// Creates the missing directories of the working directory. They are owned by
+// the user of the container process, so that it can write to its working
+// directory as it would be the case for e.g. its home directory.
+fn create_cwd(cwd: &Path, user: &User, syscall: &dyn Syscall) -> Result<()> {
+    if !cwd.is_absolute() {
+        bail!("cwd {:?} is not an absolute path", cwd);
+    }
+
+    let missing: Vec<&Path> = cwd.ancestors().take_while(|dir| !dir.exists()).collect();
+    for dir in missing.into_iter().rev() {
+        tracing::debug!("creating cwd {:?}", dir);
+        DirBuilder::new()
+            .mode(0o755)
+            .create(dir)
+            .with_context(|| format!("failed to create directory {:?}", dir))?;
+        syscall
+            .chown(
+                dir,
+                Some(Uid::from_raw(user.uid())),
+                Some(Gid::from_raw(user.gid())),
+            )
+            .with_context(|| format!("failed to change owner of {:?}", dir))?;
+    }
+
+    Ok(())
+}
+
+// The stdio of the container process is inherited from the caller, e.g. the
+// pipes through which a container engine collects the output. They are owned
+// by the user who has created them, so they are handed over to the user of the
+// container process, which could otherwise not reopen them through e.g.
+// /dev/stdout. /dev/null is shared with the host and therefore left alone.
+fn fix_stdio_ownership(fds: &[RawFd], user: &User) -> Result<()> {
+    for &fd in fds {
+        let file_stat = match stat::fstat(fd) {
+            Ok(stat) => stat,
+            Err(Errno::EBADF) => continue,
+            Err(err) => return Err(err).with_context(|| format!("failed to stat fd {}", fd)),
+        };
+
+        let is_dev_null = SFlag::from_bits_truncate(file_stat.st_mode) & SFlag::S_IFMT
+            == SFlag::S_IFCHR
+            && stat::major(file_stat.st_rdev) == 1
+            && stat::minor(file_stat.st_rdev) == 3;
+        if is_dev_null || (file_stat.st_uid == user.uid() && file_stat.st_gid == user.gid()) {
+            continue;
+        }
+
+        match unistd::fchown(
+            fd,
+            Some(Uid::from_raw(user.uid())),
+            Some(Gid::from_raw(user.gid())),
+        ) {
+            Ok(()) => {}
+            // the user may not be mapped in the user namespace or the file
+            // may belong to a user namespace, in which youki is not privileged
+            Err(err @ (Errno::EINVAL | Errno::EPERM)) => {
+                tracing::debug!("could not change owner of fd {}: {}", fd, err)
+            }
+            Err(err) => {
+                return Err(err).with_context(|| format!("failed to change owner of fd {}", fd))
+            }
+        }
+    }
+
+    Ok(())
+}
+
+const OOM_SCORE_ADJ_PATH: &str = "/proc/self/oom_score_adj";
+const OOM_SCORE_ADJ_MIN: i32 = -1000;
+const OOM_SCORE_ADJ_MAX: i32 = 1000;
+
+/// Ensures that the oom score adjustment is accepted by the kernel
+pub fn validate_oom_score_adj(oom_score_adj: i32) -> Result<()> {
+    if !(OOM_SCORE_ADJ_MIN..=OOM_SCORE_ADJ_MAX).contains(&oom_score_adj) {
+        bail!(
+            "oom score adjustment {} is not in the range [{}, {}]",
+            oom_score_adj,
+            OOM_SCORE_ADJ_MIN,
+            OOM_SCORE_ADJ_MAX
+        );
+    }
+
+    Ok(())
+}
+
+// Adjusts the likelihood of the container process to be killed by the oom
+// killer, see https://dev.to/rrampage/surviving-the-linux-oom-killer-2ki9 for
+// more information. The value is inherited by the container processes.
+pub(crate) fn set_oom_score_adj(oom_score_adj: i32) -> Result<()> {
+    validate_oom_score_adj(oom_score_adj)?;
+    tracing::debug!("set oom score adjustment to {}", oom_score_adj);
+    fs::write(OOM_SCORE_ADJ_PATH, oom_score_adj.to_string())
+        .with_context(|| format!("failed to write {}", OOM_SCORE_ADJ_PATH))
+}
+
+fn set_umask(umask: u32) -> Result<()> {
+    let mode = match Mode::from_bits(umask) {
+        Some(mode) if umask <= 0o777 => mode,
+        _ => bail!("invalid umask {:#o}", umask),
+    };
+
+    tracing::debug!("set umask to {:#o}", umask);
+    stat::umask(mode);
+    Ok(())
+}
+
 // Enter into rest of namespace. Note, we already entered into user and pid
 // namespace. We also have to enter into mount namespace last since
 // namespace may be bind to /proc path. The /proc path will need to be
@@ -165,6 +322,7 @@ fn masked_path(path: &Path, mount_label: &Option<String>, syscall: &dyn Syscall)
 fn apply_rest_namespaces(
     namespaces: &Namespaces,
     spec: &Spec,
+    domainname: Option<&str>,
     syscall: &dyn Syscall,
 ) -> Result<()> {
     namespaces
@@ -173,21 +331,104 @@ fn apply_rest_namespaces(
         })
         .with_context(|| "failed to apply namespaces")?;
 
-    // Only set the host name if entering into a new uts namespace
+    // Only set the host and domain name if entering into a new uts namespace
     if let Some(uts_namespace) = namespaces.get(LinuxNamespaceType::Uts) {
         if uts_namespace.path().is_none() {
             if let Some(hostname) = spec.hostname() {
                 syscall.set_hostname(hostname)?;
             }
+            if let Some(domainname) = domainname {
+                syscall.set_domainname(domainname)?;
+            }
         }
     }
     Ok(())
 }
 
+/// Determines when the seccomp profile is loaded during the setup of the
+/// container process
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SeccompOrdering {
+    /// Without no_new_privs loading a seccomp filter requires CAP_SYS_ADMIN,
+    /// so the profile is loaded before the user is switched and the
+    /// capabilities are dropped. The profile has to allow the syscalls of the
+    /// remaining setup.
+    BeforePrivilegeDrop,
+    /// With no_new_privs an unprivileged process may load a seccomp filter,
+    /// so the profile is loaded as close to the execution of the payload as
+    /// possible to filter as few syscalls of the runtime as possible
+    BeforeExec,
+}
+
+impl SeccompOrdering {
+    pub fn for_process(process: &Process) -> Self {
+        match process.no_new_privileges() {
+            Some(true) => Self::BeforeExec,
+            _ => Self::BeforePrivilegeDrop,
+        }
+    }
+}
+
+#[tracing::instrument(level = "debug", skip_all)]
 pub fn container_init_process(
     args: &ContainerArgs,
     main_sender: &mut channel::MainSender,
     init_receiver: &mut channel::InitReceiver,
+) -> Result<()> {
+    // Failures during the setup of the container are reported to the main
+    // process, so that the creation of the container fails with the actual
+    // cause instead of a broken channel
+    if let Err(err) = setup_container(args, main_sender, init_receiver) {
+        if let Err(report_err) = main_sender.process_error(&err) {
+            tracing::warn!("failed to report error to the main process: {}", report_err);
+        }
+        return Err(err);
+    }
+
+    // Notify main process that the init process is ready to execute the
+    // payload.  Note, because we are already inside the pid namespace, the pid
+    // outside the pid namespace should be recorded by the intermediate process
+    // already.
+    main_sender.init_ready()?;
+    main_sender
+        .close()
+        .context("failed to close down main sender in init process")?;
+
+    // listing on the notify socket for container start command
+    let mut start_request = args.notify_socket.wait_for_container_start()?;
+
+    // Errors until the payload is executed are reported to the process which
+    // requested the start, instead of leaving it with a closed connection
+    let proc = args.spec.process().as_ref().context("no process in spec")?;
+    if let Err(err) = prepare_payload(args, proc) {
+        start_request.report_error(&err);
+        return Err(err);
+    }
+
+    // The connection is closed on exec. Executors which run the payload in
+    // this process confirm the start right away.
+    let mut start_request = Some(start_request);
+    if !args.executor.replaces_process() {
+        start_request = None;
+    }
+    if let Err(err) = args.executor.exec(args.spec) {
+        if let Some(start_request) = &mut start_request {
+            start_request.report_error(&err);
+        }
+        return Err(err);
+    }
+
+    // Only reached if the payload has been run by the executor in this
+    // process, which exits now
+    Ok(())
+}
+
+/// Sets up the container process up to the point where it waits for the
+/// container to be started
+fn setup_container(
+    args: &ContainerArgs,
+    main_sender: &mut channel::MainSender,
+    init_receiver: &mut channel::InitReceiver,
 ) -> Result<()> {
     let syscall = args.syscall;
     let spec = args.spec;
@@ -199,15 +440,32 @@ pub fn container_init_process(
     let container = args.container.as_ref();
     let namespaces = Namespaces::from(linux.namespaces().as_ref());
 
-    // set up tty if specified
-    if let Some(csocketfd) = args.console_socket {
-        tty::setup_console(&csocketfd).with_context(|| "Failed to set up tty")?;
-    }
-
-    apply_rest_namespaces(&namespaces, spec, syscall)?;
+    apply_rest_namespaces(
+        &namespaces,
+        spec,
+        args.spec_extensions.domainname.as_deref(),
+        syscall,
+    )?;
 
+    // no_new_privs is set before the user is switched and the seccomp
+    // profile is loaded, see SeccompOrdering
+    let seccomp_ordering = SeccompOrdering::for_process(proc);
     if let Some(true) = proc.no_new_privileges() {
-        let _ = prctl::set_no_new_privileges(true);
+        prctl::set_no_new_privileges(true)
+            .map_err(Errno::from_i32)
+            .context("failed to set no_new_privs")?;
+    }
+
+    // The container gets a session keyring of its own, so that it can not
+    // access the keys of the caller. This has to happen before seccomp is
+    // applied, as keyctl may be blocked by the profile.
+    if args.init && !args.no_new_keyring {
+        let name = match container {
+            Some(container) => format!("_ses.{}", container.id()),
+            None => "_ses".to_owned(),
+        };
+        keyring::join_session_keyring(&name, proc.selinux_label().as_deref())
+            .context("failed to create session keyring")?;
     }
 
     if args.init {
@@ -218,26 +476,39 @@ pub fn container_init_process(
                 .context("Failed to run create container hooks")?;
         }
 
-        let bind_service = namespaces.get(LinuxNamespaceType::User).is_some();
         let rootfs = RootFS::new();
         rootfs
             .prepare_rootfs(
                 spec,
                 rootfs_path,
-                bind_service,
+                namespaces.get(LinuxNamespaceType::User).is_some(),
                 namespaces.get(LinuxNamespaceType::Cgroup).is_some(),
+                args.create_missing_bind_sources,
+                container.map(|container| container.root.as_path()),
+                args.idmapped_mounts,
             )
             .with_context(|| "Failed to prepare rootfs")?;
 
+        // the idmapped mounts have been moved into the rootfs
+        if let Some(idmapped_mounts) = args.idmapped_mounts {
+            idmapped_mounts.close()?;
+        }
+
+        // The namespaces of the container have been entered at this point, so
+        // the sysctls only affect the container. This is done before pivot_root,
+        // as /proc/sys may be read only or masked inside of the container.
+        if let Some(kernel_params) = linux.sysctl() {
+            sysctl::apply(kernel_params)
+                .with_context(|| format!("Failed to sysctl: {:?}", kernel_params))?;
+        }
+
         // Entering into the rootfs jail. If mount namespace is specified, then
         // we use pivot_root, but if we are on the host mount namespace, we will
         // use simple chroot. Scary things will happen if you try to pivot_root
         // in the host mount namespace...
         if namespaces.get(LinuxNamespaceType::Mount).is_some() {
             // change the root of filesystem of the process to the rootfs
-            syscall
-                .pivot_rootfs(rootfs_path)
-                .with_context(|| format!("Failed to pivot root to {:?}", rootfs_path))?;
+            change_root(rootfs_path, args.no_pivot, syscall)?;
         } else {
             syscall
                 .chroot(rootfs_path)
@@ -247,11 +518,20 @@ pub fn container_init_process(
         rootfs
             .adjust_root_mount_propagation(linux)
             .context("Failed to set propagation type of root mount")?;
+    }
 
-        if let Some(kernel_params) = linux.sysctl() {
-            sysctl(kernel_params)
-                .with_context(|| format!("Failed to sysctl: {:?}", kernel_params))?;
-        }
+    if let Some(log_pipes) = &args.log_pipes {
+        log_pipes
+            .redirect()
+            .context("failed to redirect stdio to the log driver")?;
+    }
+
+    // The pty is allocated once the process has entered the mount namespace
+    // and the root of the container, so that it belongs to the devpts
+    // instance of the container
+    if let Some(csocketfd) = args.console_socket {
+        tty::setup_console(&csocketfd, args.init, proc.console_size().as_ref())
+            .with_context(|| "Failed to set up tty")?;
     }
 
     if let Some(profile) = proc.apparmor_profile() {
@@ -259,16 +539,20 @@ pub fn container_init_process(
             .with_context(|| format!("failed to apply apparmor profile {}", profile))?;
     }
 
+    // the cwd can not be created anymore once the rootfs or the paths leading
+    // to it have been remounted read only
+    if args.create_cwd && !proc.cwd().as_os_str().is_empty() {
+        create_cwd(proc.cwd(), proc.user(), syscall)
+            .with_context(|| format!("failed to create cwd {:?}", proc.cwd()))?;
+    }
+
     if let Some(true) = spec.root().as_ref().map(|r| r.readonly().unwrap_or(false)) {
-        syscall.mount(
-            None,
-            Path::new("/"),
-            None,
-            MsFlags::MS_RDONLY | MsFlags::MS_REMOUNT | MsFlags::MS_BIND,
-            None,
-        )?
+        readonly_rootfs(syscall).context("failed to remount rootfs read only")?;
     }
 
+    // the paths are resolved without following symlinks and mounted over
+    // through their file descriptor, so that the rootfs can not redirect the
+    // mounts to other locations
     if let Some(paths) = linux.readonly_paths() {
         // mount readonly path
         for path in paths {
@@ -280,7 +564,16 @@ pub fn container_init_process(
     if let Some(paths) = linux.masked_paths() {
         // mount masked path
         for path in paths {
-            masked_path(Path::new(path), linux.mount_label(), syscall)
+            let file = match openat2::open_no_symlinks(Path::new(path))
+                .with_context(|| format!("failed to resolve masked path {:?}", path))?
+            {
+                Some(file) => file,
+                None => {
+                    tracing::warn!("masked path {:?} not exist", path);
+                    continue;
+                }
+            };
+            masked_path(&openat2::proc_fd_path(&file), linux.mount_label(), syscall)
                 .with_context(|| format!("Failed to set masked path {:?}", path))?;
         }
     }
@@ -295,10 +588,38 @@ pub fn container_init_process(
         match unistd::chdir(proc.cwd()) {
             Ok(_) => false,
             Err(nix::Error::EPERM) => true,
+            Err(nix::Error::ENOENT) => {
+                bail!("cwd {:?} does not exist in the container", proc.cwd())
+            }
             Err(e) => bail!("failed to chdir: {}", e),
         }
     };
 
+    // Realtime policies require CAP_SYS_NICE and the realtime io class
+    // CAP_SYS_ADMIN, so they have to be set while the process is still
+    // privileged.
+    if let Some(scheduler) = &args.spec_extensions.scheduler {
+        scheduler.apply().context("failed to set scheduler")?;
+    }
+
+    if let Some(io_priority) = &args.spec_extensions.io_priority {
+        io_priority.apply().context("failed to set io priority")?;
+    }
+
+    if let (Some(seccomp), SeccompOrdering::BeforePrivilegeDrop) =
+        (linux.seccomp(), seccomp_ordering)
+    {
+        let notify_fd =
+            seccomp::initialize_seccomp(seccomp).context("failed to execute seccomp")?;
+        sync_seccomp(notify_fd, main_sender, init_receiver).context("failed to sync seccomp")?;
+    }
+
+    fix_stdio_ownership(
+        &[libc::STDIN_FILENO, libc::STDOUT_FILENO, libc::STDERR_FILENO],
+        proc.user(),
+    )
+    .context("failed to change owner of stdio")?;
+
     set_supplementary_gids(proc.user(), args.rootless, syscall)
         .context("failed to set supplementary gids")?;
 
@@ -309,62 +630,23 @@ pub fn container_init_process(
         )
         .context("failed to configure uid and gid")?;
 
-    // Without no new privileges, seccomp is a privileged operation. We have to
-    // do this before dropping capabilities. Otherwise, we should do it later,
-    // as close to exec as possible.
-    if let Some(seccomp) = linux.seccomp() {
-        if proc.no_new_privileges().is_none() {
-            let notify_fd =
-                seccomp::initialize_seccomp(seccomp).context("failed to execute seccomp")?;
-            sync_seccomp(notify_fd, main_sender, init_receiver)
-                .context("failed to sync seccomp")?;
-        }
-    }
-
     capabilities::reset_effective(syscall).context("Failed to reset effective capabilities")?;
     if let Some(caps) = proc.capabilities() {
         capabilities::drop_privileges(caps, syscall).context("Failed to drop capabilities")?;
     }
 
-    // Take care of LISTEN_FDS used for systemd-active-socket. If the value is
-    // not 0, then we have to preserve those fds as well, and set up the correct
-    // environment variables.
-    let preserve_fds: i32 = match env::var("LISTEN_FDS") {
-        Ok(listen_fds_str) => {
-            let listen_fds = match listen_fds_str.parse::<i32>() {
-                Ok(v) => v,
-                Err(error) => {
-                    log::warn!(
-                        "LISTEN_FDS entered is not a fd. Ignore the value. {:?}",
-                        error
-                    );
-
-                    0
-                }
-            };
-
-            // The LISTEN_FDS will have to be passed to container init process.
-            // The LISTEN_PID will be set to PID 1. Based on the spec, if
-            // LISTEN_FDS is 0, the variable should be unset, so we just ignore
-            // it here, if it is 0.
-            if listen_fds > 0 {
-                envs.append(&mut vec![
-                    format!("LISTEN_FDS={}", listen_fds),
-                    "LISTEN_PID=1".to_string(),
-                ]);
-            }
-
-            args.preserve_fds + listen_fds
+    // Take care of LISTEN_FDS used for systemd-active-socket. The sockets
+    // precede the preserved fds. LISTEN_PID has to refer to the container
+    // process, which is 1 in a new pid namespace. Based on the spec, if
+    // LISTEN_FDS is 0, the variables should be unset.
+    if args.listen_fds > 0 {
+        envs.push(format!("LISTEN_FDS={}", args.listen_fds));
+        envs.push(format!("LISTEN_PID={}", unistd::getpid()));
+        if let Ok(names) = env::var("LISTEN_FDNAMES") {
+            envs.push(format!("LISTEN_FDNAMES={}", names));
         }
-        Err(env::VarError::NotPresent) => args.preserve_fds,
-        Err(env::VarError::NotUnicode(value)) => {
-            log::warn!(
-                "LISTEN_FDS entered is malformed: {:?}. Ignore the value.",
-                &value
-            );
-            args.preserve_fds
-        }
-    };
+    }
+    let preserve_fds = args.preserve_fds + args.listen_fds;
 
     // Clean up and handle perserved fds. We only mark the fd as CLOSEXEC, so we
     // don't have to worry about when the fd will be closed.
@@ -384,44 +666,46 @@ pub fn container_init_process(
     // Initialize seccomp profile right before we are ready to execute the
     // payload so as few syscalls will happen between here and payload exec. The
     // notify socket will still need network related syscalls.
-    if let Some(seccomp) = linux.seccomp() {
-        if proc.no_new_privileges().is_some() {
-            let notify_fd =
-                seccomp::initialize_seccomp(seccomp).context("failed to execute seccomp")?;
-            sync_seccomp(notify_fd, main_sender, init_receiver)
-                .context("failed to sync seccomp")?;
-        }
+    if let (Some(seccomp), SeccompOrdering::BeforeExec) = (linux.seccomp(), seccomp_ordering) {
+        let notify_fd =
+            seccomp::initialize_seccomp(seccomp).context("failed to execute seccomp")?;
+        sync_seccomp(notify_fd, main_sender, init_receiver).context("failed to sync seccomp")?;
     }
 
-    // Notify main process that the init process is ready to execute the
-    // payload.  Note, because we are already inside the pid namespace, the pid
-    // outside the pid namespace should be recorded by the intermediate process
-    // already.
-    main_sender.init_ready()?;
-    main_sender
-        .close()
-        .context("failed to close down main sender in init process")?;
-
-    // listing on the notify socket for container start command
-    args.notify_socket.wait_for_container_start()?;
+    Ok(())
+}
 
-    // create_container hook needs to be called after the namespace setup, but
-    // before pivot_root is called. This runs in the container namespaces.
+/// Finishes the setup which has to happen once the container has been started,
+/// right before the payload is executed
+fn prepare_payload(args: &ContainerArgs, proc: &Process) -> Result<()> {
+    // start_container hooks are run after the container has been started,
+    // right before the payload is executed. This runs in the container
+    // namespaces.
     if args.init {
-        if let Some(hooks) = hooks {
-            hooks::run_hooks(hooks.start_container().as_ref(), container)?
+        if let Some(hooks) = args.spec.hooks() {
+            hooks::run_hooks(hooks.start_container().as_ref(), args.container.as_ref())
+                .context("failed to run start container hooks")?;
         }
     }
 
-    if let Some(args) = proc.args() {
-        utils::do_exec(&args[0], args)?;
-    } else {
-        bail!("on non-Windows, at least one process arg entry is required")
+    // Without a umask in the spec, the umask of youki is inherited
+    if let Some(umask) = proc.user().umask() {
+        set_umask(umask)?;
+    }
+
+    // The execution domain is inherited by the payload, so it is set as late as
+    // possible to not affect the setup of the container.
+    if let Some(personality) = &args.spec_extensions.personality {
+        personality.apply().context("failed to set personality")?;
+    }
+
+    if let Some(affinity) = &args.spec_extensions.exec_cpu_affinity {
+        affinity
+            .apply_final()
+            .context("failed to set final cpu affinity")?;
     }
 
-    // After do_exec is called, the process is replaced with the container
-    // payload through execvp, so it should never reach here.
-    unreachable!();
+    Ok(())
 }
 
 // Before 3.19 it was possible for an unprivileged user to enter an user namespace,
@@ -496,7 +780,7 @@ fn sync_seccomp(
     init_receiver: &mut channel::InitReceiver,
 ) -> Result<()> {
     if let Some(fd) = fd {
-        log::debug!("init process sync seccomp, notify fd: {}", fd);
+        tracing::debug!("init process sync seccomp, notify fd: {}", fd);
         main_sender.seccomp_notify_request(fd)?;
         init_receiver.wait_for_seccomp_request_done()?;
         // Once we are sure the seccomp notify fd is sent, we can safely close
@@ -511,20 +795,101 @@ fn sync_seccomp(
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::process::fork::container_fork;
     use crate::syscall::{
+        linux::LinuxSyscall,
         syscall::create_syscall,
         test::{ArgName, MountArgs, TestHelperSyscall},
     };
-    use nix::{fcntl, sys, unistd};
+    use crate::utils::create_temp_dir;
+    use nix::{fcntl, mount, sched, sys, unistd};
     use oci_spec::runtime::{LinuxNamespaceBuilder, SpecBuilder, UserBuilder};
     use serial_test::serial;
-    use std::{fs, os::unix::prelude::AsRawFd};
+    use std::{fs, os::unix::prelude::AsRawFd, path::PathBuf};
 
     // Note: We have to run these tests here as serial. The main issue is that
     // these tests has a dependency on the system state. The
     // cleanup_file_descriptors test is especially evil when running with other
     // tests because it would ran around close down different fds.
 
+    #[test]
+    fn test_seccomp_ordering() {
+        for (no_new_privileges, ordering) in [
+            (None, SeccompOrdering::BeforePrivilegeDrop),
+            (Some(false), SeccompOrdering::BeforePrivilegeDrop),
+            (Some(true), SeccompOrdering::BeforeExec),
+        ] {
+            let mut process = Process::default();
+            process.set_no_new_privileges(no_new_privileges);
+            assert_eq!(SeccompOrdering::for_process(&process), ordering);
+        }
+    }
+
+    #[test]
+    fn test_create_cwd() -> Result<()> {
+        let tmp = create_temp_dir("test_create_cwd")?;
+        let cwd = tmp.join("home/app");
+        let user = UserBuilder::default().uid(1000u32).gid(1000u32).build()?;
+        let syscall = create_syscall();
+        create_cwd(&cwd, &user, syscall.as_ref())?;
+        assert!(cwd.is_dir());
+
+        let got = syscall
+            .as_any()
+            .downcast_ref::<TestHelperSyscall>()
+            .unwrap()
+            .get_chown_args();
+        let paths: Vec<PathBuf> = got.iter().map(|args| args.path.clone()).collect();
+        assert_eq!(paths, vec![tmp.join("home"), cwd.clone()]);
+        assert_eq!(got[0].owner, Some(Uid::from_raw(1000)));
+
+        // existing directories are left untouched
+        create_cwd(&cwd, &user, syscall.as_ref())?;
+        assert!(create_cwd(Path::new("relative"), &user, syscall.as_ref()).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_fix_stdio_ownership() -> Result<()> {
+        if !Uid::effective().is_root() {
+            return Ok(());
+        }
+
+        let user = UserBuilder::default().uid(1000u32).gid(1000u32).build()?;
+        let (read, write) = unistd::pipe()?;
+        let dev_null = fs::File::open("/dev/null")?;
+        let dev_null_owner = stat::fstat(dev_null.as_raw_fd())?.st_uid;
+
+        fix_stdio_ownership(&[read, dev_null.as_raw_fd()], &user)?;
+
+        let stat = stat::fstat(read)?;
+        assert_eq!((stat.st_uid, stat.st_gid), (1000, 1000));
+        assert_eq!(stat::fstat(dev_null.as_raw_fd())?.st_uid, dev_null_owner);
+        unistd::close(read)?;
+        unistd::close(write)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_validate_oom_score_adj() {
+        assert!(validate_oom_score_adj(-1000).is_ok());
+        assert!(validate_oom_score_adj(1000).is_ok());
+        assert!(validate_oom_score_adj(-1001).is_err());
+        assert!(validate_oom_score_adj(1001).is_err());
+    }
+
+    #[test]
+    #[serial]
+    fn test_set_umask() -> Result<()> {
+        let original = stat::umask(Mode::from_bits_truncate(0o022));
+        set_umask(0o027)?;
+        let applied = stat::umask(original);
+        assert_eq!(applied.bits(), 0o027);
+
+        assert!(set_umask(0o1777).is_err());
+        Ok(())
+    }
+
     #[test]
     #[serial]
     fn test_get_open_fds() -> Result<()> {
@@ -568,38 +933,108 @@ mod tests {
 
     #[test]
     fn test_readonly_path() -> Result<()> {
+        let tmp_dir = create_temp_dir("test_readonly_path")?;
         let syscall = create_syscall();
-        readonly_path(Path::new("/proc/sys"), syscall.as_ref())?;
-
-        let want = vec![
-            MountArgs {
-                source: Some(PathBuf::from("/proc/sys")),
-                target: PathBuf::from("/proc/sys"),
-                fstype: None,
-                flags: MsFlags::MS_BIND | MsFlags::MS_REC,
-                data: None,
-            },
-            MountArgs {
-                source: Some(PathBuf::from("/proc/sys")),
-                target: PathBuf::from("/proc/sys"),
-                fstype: None,
-                flags: MsFlags::MS_NOSUID
-                    | MsFlags::MS_NODEV
-                    | MsFlags::MS_NOEXEC
-                    | MsFlags::MS_BIND
-                    | MsFlags::MS_REMOUNT
-                    | MsFlags::MS_RDONLY,
-                data: None,
-            },
-        ];
+        readonly_path(tmp_dir.path(), syscall.as_ref())?;
+        readonly_path(&tmp_dir.path().join("missing"), syscall.as_ref())?;
+
         let got = syscall
             .as_any()
             .downcast_ref::<TestHelperSyscall>()
             .unwrap()
             .get_mount_args();
-
-        assert_eq!(want, *got);
         assert_eq!(got.len(), 2);
+        assert_eq!(got[0].flags, MsFlags::MS_BIND | MsFlags::MS_REC);
+        assert_eq!(
+            got[1].flags,
+            MsFlags::MS_NOSUID
+                | MsFlags::MS_NODEV
+                | MsFlags::MS_NOEXEC
+                | MsFlags::MS_BIND
+                | MsFlags::MS_REMOUNT
+                | MsFlags::MS_RDONLY
+        );
+        for args in got {
+            assert_eq!(args.target, tmp_dir.path());
+            assert_eq!(args.source, Some(args.target.clone()));
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_readonly_path_flags() -> Result<()> {
+        if !unistd::geteuid().is_root() {
+            return Ok(());
+        }
+
+        let tmp_dir = create_temp_dir("test_readonly_path_flags")?;
+        let path = tmp_dir.path().to_path_buf();
+        // the mount is created in a separate mount namespace, so that it does
+        // not leak into the mount namespace of the tests
+        let child = container_fork(|| {
+            sched::unshare(CloneFlags::CLONE_NEWNS)?;
+            mount::mount(
+                None::<&str>,
+                "/",
+                None::<&str>,
+                MsFlags::MS_PRIVATE | MsFlags::MS_REC,
+                None::<&str>,
+            )?;
+            readonly_path(&path, &LinuxSyscall)?;
+
+            let flags = statvfs::statvfs(&path)?.flags();
+            let want = FsFlags::ST_RDONLY | FsFlags::ST_NOSUID | FsFlags::ST_NODEV;
+            if !flags.contains(want) {
+                bail!("unexpected flags of read only path: {:?}", flags);
+            }
+            Ok(())
+        })?;
+
+        match sys::wait::waitpid(child, None)? {
+            sys::wait::WaitStatus::Exited(_, 0) => Ok(()),
+            status => bail!("read only path has unexpected flags: {:?}", status),
+        }
+    }
+
+    #[test]
+    fn test_readonly_rootfs() -> Result<()> {
+        let syscall = create_syscall();
+        readonly_rootfs(syscall.as_ref())?;
+
+        let got = syscall
+            .as_any()
+            .downcast_ref::<TestHelperSyscall>()
+            .unwrap()
+            .get_mount_args();
+        assert_eq!(got.len(), 1);
+        assert_eq!(got[0].target, PathBuf::from("/"));
+        assert!(got[0]
+            .flags
+            .contains(MsFlags::MS_RDONLY | MsFlags::MS_REMOUNT | MsFlags::MS_BIND));
+        Ok(())
+    }
+
+    #[test]
+    fn test_change_root() -> Result<()> {
+        let rootfs = PathBuf::from("/var/lib/youki/rootfs");
+
+        let syscall = create_syscall();
+        change_root(&rootfs, false, syscall.as_ref())?;
+        let mocks = syscall
+            .as_any()
+            .downcast_ref::<TestHelperSyscall>()
+            .unwrap();
+        assert_eq!(mocks.get_pivot_rootfs_args(), vec![rootfs.clone()]);
+        assert!(mocks.get_move_rootfs_args().is_empty());
+
+        let syscall = create_syscall();
+        change_root(&rootfs, true, syscall.as_ref())?;
+        let mocks = syscall
+            .as_any()
+            .downcast_ref::<TestHelperSyscall>()
+            .unwrap();
+        assert_eq!(mocks.get_move_rootfs_args(), vec![rootfs]);
+        assert!(mocks.get_pivot_rootfs_args().is_empty());
         Ok(())
     }
 
@@ -617,16 +1052,20 @@ mod tests {
         ];
         let namespaces = Namespaces::from(Some(&linux_spaces));
 
-        apply_rest_namespaces(&namespaces, &spec, syscall.as_ref())?;
+        apply_rest_namespaces(&namespaces, &spec, Some("example.com"), syscall.as_ref())?;
 
-        let got_hostnames = syscall
+        let test_syscall = syscall
             .as_ref()
             .as_any()
             .downcast_ref::<TestHelperSyscall>()
-            .unwrap()
-            .get_hostname_args();
+            .unwrap();
+        let got_hostnames = test_syscall.get_hostname_args();
         assert_eq!(1, got_hostnames.len());
         assert_eq!("youki".to_string(), got_hostnames[0]);
+        assert_eq!(
+            vec!["example.com".to_string()],
+            test_syscall.get_domainname_args()
+        );
         Ok(())
     }
 
@@ -661,6 +1100,8 @@ mod tests {
                     newuidmap: None,
                     uid_mappings: None,
                     user_namespace: None,
+                    allow_setgroups: false,
+                    allow_single_mapping_fallback: false,
                 }),
                 vec![vec![Gid::from_raw(37), Gid::from_raw(38)]],
             ),
@@ -783,6 +1224,19 @@ mod tests {
         assert_eq!(want, got[0]);
     }
 
+    #[test]
+    fn test_masked_path_with_unknown_errno() {
+        let syscall = create_syscall();
+        let mocks = syscall
+            .as_any()
+            .downcast_ref::<TestHelperSyscall>()
+            .unwrap();
+        mocks.set_ret_err(ArgName::Mount, || bail!(nix::errno::Errno::EPERM));
+
+        assert!(masked_path(Path::new("/proc/kcore"), &None, syscall.as_ref()).is_err());
+        assert_eq!(0, mocks.get_mount_args().len());
+    }
+
     #[test]
     fn test_masked_path_with_unknown_error() {
         let syscall = create_syscall();
@@ -1,14 +1,23 @@
-use crate::{namespaces::Namespaces, process::channel, process::fork};
-use anyhow::{Context, Error, Result};
+use crate::{namespaces::Namespaces, process::channel, process::fork, rootless::Rootless};
+use anyhow::{bail, Context, Error, Result};
 use libcgroups::common::CgroupManager;
-use nix::unistd::{Gid, Pid, Uid};
+use nix::{
+    fcntl::{self, OFlag},
+    sys::stat::Mode,
+    unistd::{self, Gid, Pid, Uid},
+};
 use oci_spec::runtime::{LinuxNamespaceType, LinuxResources};
 use procfs::process::Process;
-use std::convert::From;
+use std::{collections::HashMap, convert::From};
 
 use super::args::ContainerArgs;
 use super::container_init_process::container_init_process;
 
+/// Annotation which requests that the cgroup of a container with its own user
+/// and cgroup namespace is handed over to the root user of the container
+pub const DELEGATE_CGROUP_ANNOTATION: &str = "run.youki.delegate-cgroup";
+
+#[tracing::instrument(level = "debug", skip_all)]
 pub fn container_intermediate_process(
     args: &ContainerArgs,
     intermediate_sender: &mut channel::IntermediateSender,
@@ -17,11 +26,77 @@ pub fn container_intermediate_process(
     init_receiver: &mut channel::InitReceiver,
     main_sender: &mut channel::MainSender,
 ) -> Result<()> {
+    let pid = match fork_init_process(
+        args,
+        intermediate_sender,
+        intermediate_receiver,
+        init_sender,
+        init_receiver,
+        main_sender,
+    ) {
+        Ok(pid) => pid,
+        Err(err) => {
+            // the main process waits for the pid of the init process, so it
+            // gets the actual cause instead of a broken channel
+            if let Err(report_err) = main_sender.process_error(&err) {
+                tracing::warn!("failed to report error to the main process: {}", report_err);
+            }
+            return Err(err);
+        }
+    };
+
+    // Once we fork the container init process, the job for intermediate process
+    // is done. We notify the container main process about the pid we just
+    // forked for container init process.
+    main_sender
+        .intermediate_ready(pid)
+        .context("failed to send child ready from intermediate process")?;
+
+    // Close unused senders here so we don't have lingering socket around.
+    main_sender
+        .close()
+        .context("failed to close unused main sender")?;
+    intermediate_sender
+        .close()
+        .context("failed to close sender in the intermediate process")?;
+    init_sender
+        .close()
+        .context("failed to close unused init sender")?;
+
+    Ok(())
+}
+
+/// Prepares the namespaces and the cgroup the init process is created in and
+/// forks it. Returns the pid of the init process.
+fn fork_init_process(
+    args: &ContainerArgs,
+    intermediate_sender: &mut channel::IntermediateSender,
+    intermediate_receiver: &mut channel::IntermediateReceiver,
+    init_sender: &mut channel::InitSender,
+    init_receiver: &mut channel::InitReceiver,
+    main_sender: &mut channel::MainSender,
+) -> Result<Pid> {
     let command = &args.syscall;
     let spec = &args.spec;
     let linux = spec.linux().as_ref().context("no linux in spec")?;
     let namespaces = Namespaces::from(linux.namespaces().as_ref());
 
+    // The intermediate process exits as soon as it has forked the init
+    // process. Together this is a double fork, so the init process is no
+    // session leader and can not reacquire a controlling terminal by accident.
+    if args.detach {
+        detach().context("failed to detach from the session of the caller")?;
+    }
+
+    // the initial affinity is set before joining the cgroup, so that it is
+    // narrowed down by the cpuset of the cgroup (see execCPUAffinity in the
+    // runtime spec)
+    if let Some(affinity) = &args.spec_extensions.exec_cpu_affinity {
+        affinity
+            .apply_initial()
+            .context("failed to set initial cpu affinity")?;
+    }
+
     // this needs to be done before we create the init process, so that the init
     // process will already be captured by the cgroup. It also needs to be done
     // before we enter the user namespace because if a privileged user starts a
@@ -35,10 +110,32 @@ pub fn container_intermediate_process(
     apply_cgroups(
         args.cgroup_manager.as_ref(),
         linux.resources().as_ref(),
+        spec.annotations().as_ref(),
+        args.strict_resources,
         args.init,
     )
     .context("failed to apply cgroups")?;
 
+    // with a private cgroup namespace the cgroup of the container becomes the
+    // root of its cgroup hierarchy. Handing it over to the root user of the
+    // container allows processes inside (e.g. systemd or nested runtimes) to
+    // manage sub-cgroups on their own. This has to happen before we enter the
+    // user namespace, as we will lose the privileges to change the owner. As
+    // this grants the container control over its resource limits, it has to
+    // be requested through an annotation.
+    if let (true, true, Some(cgroup_namespace), Some(rootless)) = (
+        args.init,
+        delegate_requested(spec.annotations().as_ref()),
+        namespaces.get(LinuxNamespaceType::Cgroup),
+        args.rootless,
+    ) {
+        if cgroup_namespace.path().is_none() {
+            if let Err(err) = delegate_cgroup(args.cgroup_manager.as_ref(), rootless) {
+                tracing::warn!("failed to delegate cgroup to the container: {:?}", err);
+            }
+        }
+    }
+
     // if new user is specified in specification, this will be true and new
     // namespace will be created, check
     // https://man7.org/linux/man-pages/man7/user_namespaces.7.html for more
@@ -48,7 +145,7 @@ pub fn container_intermediate_process(
             .unshare_or_setns(user_namespace)
             .with_context(|| format!("Failed to enter user namespace: {:?}", user_namespace))?;
         if user_namespace.path().is_none() {
-            log::debug!("creating new user namespace");
+            tracing::debug!("creating new user namespace");
             // child needs to be dumpable, otherwise the non root parent is not
             // allowed to write the uid/gid maps
             prctl::set_dumpable(true).unwrap();
@@ -76,6 +173,16 @@ pub fn container_intermediate_process(
         }
     }
 
+    // Like the pid namespace, the time namespace only applies to the children of
+    // the process that created or joined it, so it has to be entered before
+    // the fork as well. Offsets can only be written as long as no process
+    // has been created in the namespace.
+    if let Some(time_namespace) = &args.spec_extensions.time_namespace {
+        time_namespace
+            .enter()
+            .context("failed to enter time namespace")?;
+    }
+
     // Pid namespace requires an extra fork to enter, so we enter pid namespace now.
     if let Some(pid_namespace) = namespaces.get(LinuxNamespaceType::Pid) {
         namespaces
@@ -97,63 +204,96 @@ pub fn container_intermediate_process(
             .context("failed to close sender in the intermediate process")?;
         container_init_process(args, main_sender, init_receiver)
     })?;
-    // Once we fork the container init process, the job for intermediate process
-    // is done. We notify the container main process about the pid we just
-    // forked for container init process.
-    main_sender
-        .intermediate_ready(pid)
-        .context("failed to send child ready from intermediate process")?;
 
-    // Close unused senders here so we don't have lingering socket around.
-    main_sender
-        .close()
-        .context("failed to close unused main sender")?;
-    intermediate_sender
-        .close()
-        .context("failed to close sender in the intermediate process")?;
-    init_sender
-        .close()
-        .context("failed to close unused init sender")?;
+    Ok(pid)
+}
+
+/// Moves the process into a new session without a controlling terminal and
+/// ensures that the standard streams are open, so that no file opened later
+/// on is mistaken for one of them by the container process
+fn detach() -> Result<()> {
+    unistd::setsid().context("failed to create new session")?;
+
+    for stdio in 0..=2 {
+        if fcntl::fcntl(stdio, fcntl::F_GETFD).is_ok() {
+            continue;
+        }
+
+        let fd = fcntl::open("/dev/null", OFlag::O_RDWR, Mode::empty())
+            .context("failed to open /dev/null")?;
+        if fd != stdio {
+            unistd::dup2(fd, stdio).with_context(|| format!("failed to open fd {}", stdio))?;
+            unistd::close(fd)?;
+        }
+    }
 
     Ok(())
 }
 
+#[tracing::instrument(level = "debug", skip_all, name = "cgroups")]
 fn apply_cgroups<C: CgroupManager + ?Sized>(
     cmanager: &C,
     resources: Option<&LinuxResources>,
+    annotations: Option<&HashMap<String, String>>,
+    strict_resources: bool,
     init: bool,
 ) -> Result<(), Error> {
     let pid = Pid::from_raw(Process::myself()?.pid());
-    cmanager
-        .add_task(pid)
-        .with_context(|| format!("failed to add task {} to cgroup manager", pid))?;
-
-    if let Some(resources) = resources {
-        if init {
-            let controller_opt = libcgroups::common::ControllerOpt {
-                resources,
-                freezer_state: None,
-                oom_score_adj: None,
-                disable_oom_killer: false,
-            };
-
-            cmanager
-                .apply(&controller_opt)
-                .context("failed to apply resource limits to cgroup")?;
-        }
+    if !init {
+        return cmanager
+            .add_task(pid)
+            .with_context(|| format!("failed to add task {} to cgroup manager", pid));
     }
 
+    let controller_opt = resources.map(|resources| libcgroups::common::ControllerOpt {
+        resources,
+        freezer_state: None,
+        oom_score_adj: None,
+        disable_oom_killer: false,
+        annotations,
+        strict_resources,
+    });
+    cmanager
+        .add_init_task(pid, controller_opt.as_ref())
+        .with_context(|| {
+            format!(
+                "failed to add task {} to cgroup manager and apply resource limits",
+                pid
+            )
+        })?;
+
     Ok(())
 }
 
+fn delegate_requested(annotations: Option<&HashMap<String, String>>) -> bool {
+    annotations
+        .and_then(|a| a.get(DELEGATE_CGROUP_ANNOTATION))
+        .map_or(false, |value| value == "true")
+}
+
+fn delegate_cgroup<C: CgroupManager + ?Sized>(cmanager: &C, rootless: &Rootless) -> Result<()> {
+    let mappings = rootless.effective_mappings()?;
+    let (uid, gid) = match (mappings.host_uid_for(0), mappings.host_gid_for(0)) {
+        (Some(uid), Some(gid)) => (uid, gid),
+        _ => bail!("root of the container is not mapped to a host user"),
+    };
+
+    tracing::debug!("delegating cgroup to {}:{}", uid, gid);
+    cmanager
+        .delegate(Uid::from_raw(uid), Gid::from_raw(gid))
+        .context("failed to delegate cgroup")
+}
+
 #[cfg(test)]
 mod tests {
-    use super::apply_cgroups;
-    use anyhow::Result;
+    use super::{apply_cgroups, delegate_requested, detach, DELEGATE_CGROUP_ANNOTATION};
+    use crate::utils::test_utils::test_in_child_process;
+    use anyhow::{bail, Result};
     use libcgroups::test_manager::TestManager;
     use nix::unistd::Pid;
     use oci_spec::runtime::LinuxResources;
     use procfs::process::Process;
+    use std::collections::HashMap;
 
     #[test]
     fn apply_cgroup_init() -> Result<()> {
@@ -162,7 +302,7 @@ mod tests {
         let resources = LinuxResources::default();
 
         // act
-        apply_cgroups(&cmanager, Some(&resources), true)?;
+        apply_cgroups(&cmanager, Some(&resources), None, false, true)?;
 
         // assert
         assert!(cmanager.get_add_task_args().len() == 1);
@@ -181,7 +321,7 @@ mod tests {
         let resources = LinuxResources::default();
 
         // act
-        apply_cgroups(&cmanager, Some(&resources), false)?;
+        apply_cgroups(&cmanager, Some(&resources), None, false, false)?;
 
         // assert
         assert_eq!(
@@ -198,7 +338,7 @@ mod tests {
         let cmanager = TestManager::default();
 
         // act
-        apply_cgroups(&cmanager, None, true)?;
+        apply_cgroups(&cmanager, None, None, false, true)?;
         // assert
         assert_eq!(
             cmanager.get_add_task_args()[0],
@@ -207,4 +347,30 @@ mod tests {
         assert!(!cmanager.apply_called());
         Ok(())
     }
+
+    #[test]
+    fn test_detach() -> Result<()> {
+        test_in_child_process(|| {
+            nix::unistd::close(0)?;
+            detach()?;
+
+            if nix::unistd::getsid(None)? != nix::unistd::getpid() {
+                bail!("process is no session leader");
+            }
+            nix::fcntl::fcntl(0, nix::fcntl::F_GETFD)?;
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn test_delegate_requested() {
+        assert!(!delegate_requested(None));
+
+        let mut annotations = HashMap::new();
+        assert!(!delegate_requested(Some(&annotations)));
+        annotations.insert(DELEGATE_CGROUP_ANNOTATION.to_owned(), "false".to_owned());
+        assert!(!delegate_requested(Some(&annotations)));
+        annotations.insert(DELEGATE_CGROUP_ANNOTATION.to_owned(), "true".to_owned());
+        assert!(delegate_requested(Some(&annotations)));
+    }
 }
@@ -2,7 +2,7 @@ use crate::{
     container::ContainerProcessState,
     process::{args::ContainerArgs, channel, container_intermediate_process, fork},
     rootless::Rootless,
-    seccomp, utils,
+    seccomp,
 };
 use anyhow::{Context, Result};
 use nix::{
@@ -12,6 +12,7 @@ use nix::{
 use oci_spec::runtime;
 use std::path::Path;
 
+#[tracing::instrument(level = "debug", skip_all)]
 pub fn container_main_process(container_args: &ContainerArgs) -> Result<Pid> {
     // We use a set of channels to communicate between parent and child process.
     // Each channel is uni-directional. Because we will pass these channel to
@@ -22,7 +23,7 @@ pub fn container_main_process(container_args: &ContainerArgs) -> Result<Pid> {
     let (intermediate_sender, intermediate_receiver) = &mut channel::intermediate_channel()?;
     let (init_sender, init_receiver) = &mut channel::init_channel()?;
 
-    let intermediate_pid = fork::container_fork(|| {
+    let intermediate = || {
         container_intermediate_process::container_intermediate_process(
             container_args,
             intermediate_sender,
@@ -31,7 +32,32 @@ pub fn container_main_process(container_args: &ContainerArgs) -> Result<Pid> {
             init_receiver,
             main_sender,
         )
-    })?;
+    };
+
+    // If possible, the intermediate process is spawned directly into the cgroup
+    // of the container. Otherwise it moves itself into the cgroup before it
+    // creates the init process. The initial cpu affinity has to be set before
+    // the cgroup is joined, so it moves itself in that case as well.
+    let initial_affinity = container_args
+        .spec_extensions
+        .exec_cpu_affinity
+        .as_ref()
+        .map_or(false, |affinity| affinity.initial.is_some());
+    let cgroup = if initial_affinity {
+        None
+    } else {
+        match container_args.cgroup_manager.prepare_clone_into_cgroup() {
+            Ok(cgroup) => cgroup,
+            Err(err) => {
+                tracing::debug!("cannot spawn the process into its cgroup: {:?}", err);
+                None
+            }
+        }
+    };
+    let intermediate_pid = match cgroup {
+        Some(cgroup) => fork::container_fork_into_cgroup(&cgroup, intermediate)?,
+        None => fork::container_fork(intermediate)?,
+    };
     // Close down unused fds. The corresponding fds are duplicated to the
     // child process during fork.
     main_sender
@@ -40,8 +66,13 @@ pub fn container_main_process(container_args: &ContainerArgs) -> Result<Pid> {
 
     // If creating a rootless container, the intermediate process will ask
     // the main process to set up uid and gid mapping, once the intermediate
-    // process enters into a new user namespace.
-    if let Some(rootless) = &container_args.rootless {
+    // process enters into a new user namespace. An existing user namespace
+    // already has its mappings in place.
+    if let Some(rootless) = container_args
+        .rootless
+        .as_ref()
+        .filter(|r| !r.joins_existing_userns())
+    {
         main_receiver.wait_for_mapping_request()?;
         setup_mapping(rootless, intermediate_pid)?;
         intermediate_sender.mapping_written()?;
@@ -87,7 +118,7 @@ pub fn container_main_process(container_args: &ContainerArgs) -> Result<Pid> {
         .wait_for_init_ready()
         .context("failed to wait for init ready")?;
 
-    log::debug!("init pid is {:?}", init_pid);
+    tracing::debug!("init pid is {:?}", init_pid);
 
     Ok(init_pid)
 }
@@ -99,7 +130,7 @@ fn sync_seccomp(
     main_receiver: &mut channel::MainReceiver,
 ) -> Result<()> {
     if seccomp::is_notify(seccomp) {
-        log::debug!("main process waiting for sync seccomp");
+        tracing::debug!("main process waiting for sync seccomp");
         let seccomp_fd = main_receiver.wait_for_seccomp_request()?;
         let listener_path = seccomp
             .listener_path()
@@ -154,13 +185,7 @@ fn sync_seccomp_send_msg(listener_path: &Path, msg: &[u8], fd: i32) -> Result<()
 }
 
 fn setup_mapping(rootless: &Rootless, pid: Pid) -> Result<()> {
-    log::debug!("write mapping for pid {:?}", pid);
-    if !rootless.privileged {
-        // The main process is running as an unprivileged user and cannot write the mapping
-        // until "deny" has been written to setgroups. See CVE-2014-8989.
-        utils::write_file(format!("/proc/{}/setgroups", pid), "deny")?;
-    }
-
+    tracing::debug!("write mapping for pid {:?}", pid);
     rootless
         .write_uid_mapping(pid)
         .context(format!("failed to map uid of pid {}", pid))?;
@@ -192,9 +217,8 @@ mod tests {
             .container_id(0u32)
             .size(1u32)
             .build()?;
-        let uid_mappings = vec![uid_mapping];
         let rootless = Rootless {
-            uid_mappings: Some(&uid_mappings),
+            uid_mappings: Some(vec![uid_mapping]),
             privileged: true,
             ..Default::default()
         };
@@ -238,9 +262,8 @@ mod tests {
             .container_id(0u32)
             .size(1u32)
             .build()?;
-        let gid_mappings = vec![gid_mapping];
         let rootless = Rootless {
-            gid_mappings: Some(&gid_mappings),
+            gid_mappings: Some(vec![gid_mapping]),
             ..Default::default()
         };
         let (mut parent_sender, mut parent_receiver) = main_channel()?;
@@ -282,11 +305,11 @@ mod tests {
     #[test]
     #[serial]
     fn test_sync_seccomp() -> Result<()> {
+        use crate::utils::create_temp_dir;
         use std::io::Read;
         use std::os::unix::io::IntoRawFd;
         use std::os::unix::net::UnixListener;
         use std::thread;
-        use utils::create_temp_dir;
 
         let tmp_dir = create_temp_dir("test_sync_seccomp")?;
         let scmp_file = std::fs::OpenOptions::new()
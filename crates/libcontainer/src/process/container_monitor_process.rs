@@ -0,0 +1,235 @@
+//! Nobody waits for the init process of a detached container once the runtime
+//! which created it has exited, so its exit status would be lost. Instead, the
+//! container is created by a monitor process, which becomes the child
+//! subreaper of the container. The init process is re-parented to the monitor
+//! once the intermediate process has exited and the monitor records its exit
+//! status in the state of the container.
+use std::path::Path;
+
+use anyhow::{anyhow, bail, Context, Result};
+use nix::{
+    errno::Errno,
+    fcntl::OFlag,
+    sys::wait,
+    unistd::{self, Pid},
+};
+use std::os::unix::io::RawFd;
+
+use crate::container::{exit_code_of, set_child_subreaper, Container, State};
+use crate::process::fork;
+
+const PID_TAG: u8 = b'p';
+const ERROR_TAG: u8 = b'e';
+
+/// Monitor process as seen by the runtime
+#[derive(Debug)]
+pub struct Monitor {
+    pid: Pid,
+    ready_sender: RawFd,
+}
+
+impl Monitor {
+    pub fn pid(&self) -> Pid {
+        self.pid
+    }
+}
+
+impl Drop for Monitor {
+    // The monitor waits until the runtime has saved the state of the created
+    // container, otherwise the exit status could be overwritten by it
+    fn drop(&mut self) {
+        let _ = unistd::close(self.ready_sender);
+    }
+}
+
+/// Forks the monitor process, which creates the container by calling
+/// create_container. Returns the monitor and the pid of the init process. The
+/// monitor records the exit status once the returned monitor has been dropped.
+pub fn fork_monitor<F: FnOnce() -> Result<Pid>>(
+    container_root: &Path,
+    create_container: F,
+) -> Result<(Monitor, Pid)> {
+    let (result_receiver, result_sender) =
+        unistd::pipe2(OFlag::O_CLOEXEC).context("failed to create result pipe")?;
+    let (ready_receiver, ready_sender) =
+        unistd::pipe2(OFlag::O_CLOEXEC).context("failed to create ready pipe")?;
+
+    let pid = fork::container_fork(|| {
+        unistd::close(result_receiver)?;
+        unistd::close(ready_sender)?;
+        container_monitor_process(
+            container_root,
+            create_container,
+            result_sender,
+            ready_receiver,
+        )
+    });
+    let _ = unistd::close(result_sender);
+    let _ = unistd::close(ready_receiver);
+    let monitor = match pid {
+        Ok(pid) => Monitor { pid, ready_sender },
+        Err(err) => {
+            let _ = unistd::close(result_receiver);
+            let _ = unistd::close(ready_sender);
+            return Err(err).context("failed to fork monitor process");
+        }
+    };
+
+    let result = read_all(result_receiver);
+    let _ = unistd::close(result_receiver);
+    let init_pid = match result?.split_first() {
+        Some((&PID_TAG, pid)) if pid.len() == 4 => {
+            Pid::from_raw(i32::from_ne_bytes([pid[0], pid[1], pid[2], pid[3]]))
+        }
+        Some((&ERROR_TAG, err)) => bail!("{}", String::from_utf8_lossy(err)),
+        _ => bail!("monitor process exited without creating the container"),
+    };
+
+    Ok((monitor, init_pid))
+}
+
+fn container_monitor_process<F: FnOnce() -> Result<Pid>>(
+    container_root: &Path,
+    create_container: F,
+    result_sender: RawFd,
+    ready_receiver: RawFd,
+) -> Result<()> {
+    let init_pid = set_child_subreaper().and_then(|_| create_container());
+    let result = match &init_pid {
+        Ok(pid) => [&[PID_TAG][..], &pid.as_raw().to_ne_bytes()].concat(),
+        Err(err) => [&[ERROR_TAG][..], format!("{:?}", err).as_bytes()].concat(),
+    };
+    let sent = write_all(result_sender, &result);
+    let _ = unistd::close(result_sender);
+    let init_pid = init_pid?;
+    sent.context("failed to send init pid to the runtime")?;
+
+    // the monitor outlives the runtime, which must not wait for it
+    detach().context("failed to detach the monitor")?;
+
+    let exit_code = wait_for_exit(init_pid)?;
+    tracing::debug!("init process {} exited with {}", init_pid, exit_code);
+
+    // EOF once the runtime has saved the state or exited
+    read_all(ready_receiver)?;
+    let _ = unistd::close(ready_receiver);
+
+    // the container has been deleted or it could not be created
+    if !State::file_path(container_root).exists() {
+        return Ok(());
+    }
+
+    let mut container =
+        Container::load(container_root.to_path_buf()).context("failed to load container state")?;
+    container.save_exit_status(exit_code)
+}
+
+/// Waits for the init process and reaps all other children, which are the
+/// intermediate process and processes re-parented to the monitor
+fn wait_for_exit(init_pid: Pid) -> Result<i32> {
+    loop {
+        match wait::waitpid(None, None) {
+            Ok(status) if status.pid() == Some(init_pid) => {
+                if let Some(exit_code) = exit_code_of(status) {
+                    return Ok(exit_code);
+                }
+            }
+            Ok(status) => tracing::debug!("reaped process: {:?}", status),
+            Err(Errno::EINTR) => continue,
+            Err(err) => {
+                return Err(err)
+                    .with_context(|| format!("failed to wait for init process {}", init_pid))
+            }
+        }
+    }
+}
+
+/// Moves the monitor into a new session and replaces its stdio, which may
+/// belong to the caller of the runtime
+fn detach() -> Result<()> {
+    unistd::setsid().context("failed to create session")?;
+    let dev_null = nix::fcntl::open("/dev/null", OFlag::O_RDWR, nix::sys::stat::Mode::empty())
+        .context("failed to open /dev/null")?;
+    for fd in [libc::STDIN_FILENO, libc::STDOUT_FILENO, libc::STDERR_FILENO] {
+        unistd::dup2(dev_null, fd)?;
+    }
+    unistd::close(dev_null)?;
+    Ok(())
+}
+
+fn write_all(fd: RawFd, mut buf: &[u8]) -> Result<()> {
+    while !buf.is_empty() {
+        match unistd::write(fd, buf) {
+            Ok(written) => buf = &buf[written..],
+            Err(Errno::EINTR) => continue,
+            Err(err) => return Err(anyhow!(err)),
+        }
+    }
+
+    Ok(())
+}
+
+fn read_all(fd: RawFd) -> Result<Vec<u8>> {
+    let mut content = Vec::new();
+    let mut buf = [0u8; 4096];
+    loop {
+        match unistd::read(fd, &mut buf) {
+            Ok(0) => return Ok(content),
+            Ok(read) => content.extend_from_slice(&buf[..read]),
+            Err(Errno::EINTR) => continue,
+            Err(err) => return Err(err).context("failed to read from pipe"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::container::ContainerStatus;
+    use crate::utils::create_temp_dir;
+    use nix::sys::wait::WaitStatus;
+
+    #[test]
+    fn test_monitor_records_exit_status() -> Result<()> {
+        let tmp_dir = create_temp_dir("test_monitor_records_exit_status")?;
+        let container = Container::new(
+            "test_monitor",
+            ContainerStatus::Creating,
+            None,
+            tmp_dir.path(),
+            tmp_dir.path(),
+        )?;
+        container.save()?;
+
+        // the forked process stands in for the init process of the container
+        let (monitor, init_pid) = fork_monitor(tmp_dir.path(), || {
+            fork::container_fork(|| bail!("container process failed"))
+        })?;
+        let mut container = Container::load(tmp_dir.path().to_path_buf())?;
+        container
+            .set_status(ContainerStatus::Created)
+            .set_pid(init_pid.as_raw())
+            .save()?;
+        let monitor_pid = monitor.pid();
+        drop(monitor);
+
+        match wait::waitpid(monitor_pid, None)? {
+            WaitStatus::Exited(_, 0) => {}
+            status => bail!("monitor exited unexpectedly: {:?}", status),
+        }
+        let container = Container::load(tmp_dir.path().to_path_buf())?;
+        assert_eq!(container.status(), ContainerStatus::Stopped);
+        // container_fork exits with -1 if the callback fails
+        assert_eq!(container.exit_code(), Some(255));
+        Ok(())
+    }
+
+    #[test]
+    fn test_monitor_reports_error() -> Result<()> {
+        let tmp_dir = create_temp_dir("test_monitor_reports_error")?;
+        let err = fork_monitor(tmp_dir.path(), || bail!("failed to create container"))
+            .expect_err("monitor should report the error");
+        assert!(format!("{:?}", err).contains("failed to create container"));
+        Ok(())
+    }
+}
@@ -1,6 +1,33 @@
-use anyhow::Result;
+use std::os::unix::io::RawFd;
+use std::path::Path;
+
+use anyhow::{Context, Result};
+use nix::errno::Errno;
+use nix::fcntl::{self, OFlag};
+use nix::sys::stat::Mode;
 use nix::unistd;
 use nix::unistd::Pid;
+use procfs::process::Process;
+
+// Not yet exported by the libc crate
+const CLONE_INTO_CGROUP: u64 = 0x200000000;
+
+// struct clone_args as defined in linux/sched.h (CLONE_ARGS_SIZE_VER2)
+#[repr(C)]
+#[derive(Default)]
+struct CloneArgs {
+    flags: u64,
+    pidfd: u64,
+    child_tid: u64,
+    parent_tid: u64,
+    exit_signal: u64,
+    stack: u64,
+    stack_size: u64,
+    tls: u64,
+    set_tid: u64,
+    set_tid_size: u64,
+    cgroup: u64,
+}
 
 // Execute the cb in another process. Make the fork works more like thread_spawn
 // or clone, so it is easier to reason. Compared to clone call, fork is easier
@@ -13,7 +40,7 @@ pub fn container_fork<F: FnOnce() -> Result<()>>(cb: F) -> Result<Pid> {
         unistd::ForkResult::Parent { child } => Ok(child),
         unistd::ForkResult::Child => {
             let ret = if let Err(error) = cb() {
-                log::debug!("failed to run fork: {:?}", error);
+                tracing::debug!("failed to run fork: {:?}", error);
                 -1
             } else {
                 0
@@ -23,11 +50,94 @@ pub fn container_fork<F: FnOnce() -> Result<()>>(cb: F) -> Result<Pid> {
     }
 }
 
+/// Same as container_fork, but the child process is spawned directly into the
+/// cgroup with clone3(CLONE_INTO_CGROUP), so that it never runs in the cgroup of
+/// the parent, not even briefly. Falls back to a regular fork if the kernel does
+/// not support this (requires Linux 5.7 and cgroup v2), in which case the child
+/// has to move itself into the cgroup.
+///
+/// Unlike fork, the raw clone3 syscall does not run the fork handlers of the C
+/// library, which reset its locks (e.g. of the allocator) in the child. This is
+/// only safe as long as no other thread can hold such a lock, so a regular fork
+/// is used as well if the process is multi-threaded.
+pub fn container_fork_into_cgroup<F: FnOnce() -> Result<()>>(cgroup: &Path, cb: F) -> Result<Pid> {
+    if !is_single_threaded() {
+        tracing::debug!("process is multi-threaded, falling back to fork");
+        return container_fork(cb);
+    }
+
+    let cgroup_fd = fcntl::open(
+        cgroup,
+        OFlag::O_PATH | OFlag::O_DIRECTORY | OFlag::O_CLOEXEC,
+        Mode::empty(),
+    )
+    .with_context(|| format!("failed to open cgroup {:?}", cgroup))?;
+
+    let result = clone_into_cgroup(cgroup_fd);
+    match result {
+        Ok(0) => {
+            let _ = unistd::close(cgroup_fd);
+            let ret = if let Err(error) = cb() {
+                tracing::debug!("failed to run fork: {:?}", error);
+                -1
+            } else {
+                0
+            };
+            std::process::exit(ret);
+        }
+        Ok(child) => {
+            let _ = unistd::close(cgroup_fd);
+            Ok(Pid::from_raw(child))
+        }
+        Err(errno) => {
+            let _ = unistd::close(cgroup_fd);
+            tracing::debug!(
+                "failed to clone into cgroup {:?} ({}), falling back to fork",
+                cgroup,
+                errno
+            );
+            container_fork(cb)
+        }
+    }
+}
+
+fn is_single_threaded() -> bool {
+    match Process::myself() {
+        Ok(process) => process.stat.num_threads == 1,
+        Err(err) => {
+            tracing::debug!("failed to determine number of threads: {}", err);
+            false
+        }
+    }
+}
+
+fn clone_into_cgroup(cgroup_fd: RawFd) -> std::result::Result<i32, Errno> {
+    let mut args = CloneArgs {
+        flags: CLONE_INTO_CGROUP,
+        exit_signal: libc::SIGCHLD as u64,
+        cgroup: cgroup_fd as u64,
+        ..Default::default()
+    };
+
+    // Without CLONE_VM and a stack, clone3 behaves like fork, i.e. the child
+    // continues with a copy of the address space of the parent
+    let ret = unsafe {
+        libc::syscall(
+            libc::SYS_clone3,
+            &mut args as *mut CloneArgs,
+            std::mem::size_of::<CloneArgs>(),
+        )
+    };
+
+    Errno::result(ret).map(|pid| pid as i32)
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
     use anyhow::{bail, Result};
     use nix::sys::wait::{waitpid, WaitStatus};
+    use std::fs;
 
     #[test]
     fn test_container_fork() -> Result<()> {
@@ -54,4 +164,69 @@ mod test {
             _ => bail!("test failed"),
         }
     }
+
+    #[test]
+    fn test_container_fork_into_cgroup_fallback() -> Result<()> {
+        // a directory which is not a cgroup is rejected by clone3, so that the
+        // regular fork is used
+        let tmp = std::env::temp_dir();
+        let pid = container_fork_into_cgroup(&tmp, || Ok(()))?;
+        match waitpid(pid, None).expect("wait pid failed.") {
+            WaitStatus::Exited(p, status) => {
+                assert_eq!(pid, p);
+                assert_eq!(status, 0);
+                Ok(())
+            }
+            _ => bail!("test failed"),
+        }
+    }
+
+    #[test]
+    fn test_clone_into_cgroup() -> Result<()> {
+        if !unistd::geteuid().is_root() {
+            return Ok(());
+        }
+
+        // a cgroup v2 hierarchy is required, which may also be read-only
+        let cgroup = Path::new("/sys/fs/cgroup/youki_test_clone_into_cgroup");
+        if !Path::new("/sys/fs/cgroup/cgroup.controllers").exists()
+            || fs::create_dir(cgroup).is_err()
+        {
+            return Ok(());
+        }
+
+        // the test harness is multi-threaded, so clone3 is called from a
+        // single-threaded child
+        let pid = container_fork(|| {
+            let cgroup_fd = fcntl::open(
+                cgroup,
+                OFlag::O_PATH | OFlag::O_DIRECTORY | OFlag::O_CLOEXEC,
+                Mode::empty(),
+            )?;
+            let child = match clone_into_cgroup(cgroup_fd) {
+                Ok(0) => {
+                    let cgroups = fs::read_to_string("/proc/self/cgroup").unwrap_or_default();
+                    let in_cgroup = cgroups
+                        .lines()
+                        .any(|l| l == "0::/youki_test_clone_into_cgroup");
+                    std::process::exit(if in_cgroup { 0 } else { 1 });
+                }
+                Ok(child) => Pid::from_raw(child),
+                // not supported by the kernel
+                Err(_) => return Ok(()),
+            };
+
+            match waitpid(child, None)? {
+                WaitStatus::Exited(_, 0) => Ok(()),
+                status => bail!("child was not spawned into the cgroup: {:?}", status),
+            }
+        })?;
+        let status = waitpid(pid, None);
+        fs::remove_dir(cgroup)?;
+
+        match status? {
+            WaitStatus::Exited(_, 0) => Ok(()),
+            status => bail!("test failed: {:?}", status),
+        }
+    }
 }
@@ -1,5 +1,7 @@
 /// Used as a wrapper for messages to be sent between child and parent processes
+use nix::errno::Errno;
 use serde::{Deserialize, Serialize};
+use std::fmt;
 
 #[derive(Debug, Serialize, Deserialize)]
 pub enum Message {
@@ -9,4 +11,65 @@ pub enum Message {
     MappingWritten,
     SeccompNotify,
     SeccompNotifyDone,
+    ProcessError(ProcessError),
+}
+
+/// Failure of the intermediate or init process, which is reported to the main
+/// process instead of the message it waits for
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ProcessError {
+    /// Error code of the system call which has failed, if any
+    pub errno: Option<i32>,
+    /// Message of the error including its context
+    pub message: String,
+}
+
+impl From<&anyhow::Error> for ProcessError {
+    fn from(err: &anyhow::Error) -> Self {
+        let errno = err.chain().find_map(|cause| {
+            cause
+                .downcast_ref::<Errno>()
+                .map(|errno| *errno as i32)
+                .or_else(|| {
+                    cause
+                        .downcast_ref::<std::io::Error>()
+                        .and_then(|err| err.raw_os_error())
+                })
+        });
+
+        Self {
+            errno,
+            message: format!("{:#}", err),
+        }
+    }
+}
+
+impl fmt::Display for ProcessError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl std::error::Error for ProcessError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Context;
+
+    #[test]
+    fn test_process_error_from_error() {
+        let err = Err::<(), _>(Errno::ENOENT)
+            .context("failed to mount /proc")
+            .unwrap_err();
+        let process_error = ProcessError::from(&err);
+        assert_eq!(process_error.errno, Some(libc::ENOENT));
+        assert_eq!(
+            process_error.to_string(),
+            "failed to mount /proc: ENOENT: No such file or directory"
+        );
+
+        let err = anyhow::anyhow!("invalid capability");
+        assert_eq!(ProcessError::from(&err).errno, None);
+    }
 }
@@ -6,5 +6,6 @@ pub mod channel;
 pub mod container_init_process;
 pub mod container_intermediate_process;
 pub mod container_main_process;
+pub mod container_monitor_process;
 pub mod fork;
 pub mod message;
@@ -1,16 +1,23 @@
 use super::utils::to_sflag;
+use crate::openat2;
 use crate::syscall::{syscall::create_syscall, Syscall};
-use crate::utils::{self, PathBufExt};
+use crate::utils::PathBufExt;
 use anyhow::{bail, Context, Result};
 use nix::{
+    errno::Errno,
     fcntl::{open, OFlag},
     mount::MsFlags,
     sys::stat::{umask, Mode},
-    unistd::{close, Gid, Uid},
+    unistd::{Gid, Uid},
 };
-use oci_spec::runtime::LinuxDevice;
+use oci_spec::runtime::{LinuxDevice, LinuxDeviceType};
+use std::fs::File;
+use std::os::unix::io::FromRawFd;
 use std::path::{Path, PathBuf};
 
+/// Permissions of devices, which do not specify a file mode
+const DEFAULT_DEVICE_MODE: u32 = 0o666;
+
 pub struct Device {
     syscall: Box<dyn Syscall>,
 }
@@ -33,38 +40,60 @@ impl Device {
         I: IntoIterator<Item = &'a LinuxDevice>,
     {
         let old_mode = umask(Mode::from_bits_truncate(0o000));
-        devices
+        let result = devices
             .into_iter()
             .map(|dev| {
                 if !dev.path().starts_with("/dev") {
                     bail!("{} is not a valid device path", dev.path().display());
                 }
+                if dev.typ() == LinuxDeviceType::A {
+                    bail!("device {} must not be of type a", dev.path().display());
+                }
 
-                if bind {
+                // unlike device nodes, fifos can be created in user namespaces
+                if bind && dev.typ() != LinuxDeviceType::P {
                     self.bind_dev(rootfs, dev)
                 } else {
-                    self.mknod_dev(rootfs, dev)
+                    self.mknod_or_bind_dev(rootfs, dev)
                 }
             })
-            .collect::<Result<Vec<_>>>()?;
+            .collect::<Result<Vec<_>>>();
         umask(old_mode);
 
-        Ok(())
+        result.map(drop)
+    }
+
+    // Creating device nodes is not permitted in a user namespace, unless it
+    // is owned by the initial user namespace, so the device of the host is
+    // bind mounted instead
+    fn mknod_or_bind_dev(&self, rootfs: &Path, dev: &LinuxDevice) -> Result<()> {
+        match self.mknod_dev(rootfs, dev) {
+            Err(err) if matches!(err.downcast_ref::<Errno>(), Some(Errno::EPERM)) => {
+                tracing::debug!(
+                    "creating device {:?} is not permitted, bind mounting it",
+                    dev.path()
+                );
+                self.bind_dev(rootfs, dev)
+            }
+            result => result,
+        }
     }
 
     fn bind_dev(&self, rootfs: &Path, dev: &LinuxDevice) -> Result<()> {
-        let full_container_path = create_container_dev_path(rootfs, dev)
+        let (_dir, full_container_path) = create_container_dev_path(rootfs, dev)
             .with_context(|| format!("could not create container path for device {:?}", dev))?;
 
         let fd = open(
             &full_container_path,
-            OFlag::O_RDWR | OFlag::O_CREAT,
+            OFlag::O_RDWR | OFlag::O_CREAT | OFlag::O_NOFOLLOW | OFlag::O_CLOEXEC,
             Mode::from_bits_truncate(0o644),
         )?;
-        close(fd)?;
+        // the device is mounted over through the file descriptor of the
+        // created file
+        let file = unsafe { File::from_raw_fd(fd) };
         self.syscall.mount(
             Some(dev.path()),
-            &full_container_path,
+            &openat2::proc_fd_path(&file),
             Some("bind"),
             MsFlags::MS_BIND,
             None,
@@ -81,13 +110,13 @@ impl Device {
                 | ((major & !0xfff) << 32)) as u64
         }
 
-        let full_container_path = create_container_dev_path(rootfs, dev)
+        let (_dir, full_container_path) = create_container_dev_path(rootfs, dev)
             .with_context(|| format!("could not create container path for device {:?}", dev))?;
 
         self.syscall.mknod(
             &full_container_path,
             to_sflag(dev.typ()),
-            Mode::from_bits_truncate(dev.file_mode().unwrap_or(0)),
+            Mode::from_bits_truncate(dev.file_mode().unwrap_or(DEFAULT_DEVICE_MODE)),
             makedev(dev.major(), dev.minor()),
         )?;
         self.syscall.chown(
@@ -100,12 +129,15 @@ impl Device {
     }
 }
 
-fn create_container_dev_path(rootfs: &Path, dev: &LinuxDevice) -> Result<PathBuf> {
+/// Creates the parent directory of the device in the rootfs. The directory is
+/// returned together with the path of the device through its file descriptor,
+/// so that the device can not be created outside of the rootfs.
+fn create_container_dev_path(rootfs: &Path, dev: &LinuxDevice) -> Result<(File, PathBuf)> {
     let relative_dev_path = dev
         .path()
         .as_relative()
         .with_context(|| format!("could not convert {:?} to relative path", dev.path()))?;
-    let full_container_path = utils::secure_join(rootfs, relative_dev_path)
+    let full_container_path = openat2::resolve_in_root(rootfs, relative_dev_path)
         .with_context(|| format!("could not join {:?} with {:?}", rootfs, dev.path()))?;
 
     crate::utils::create_dir_all(
@@ -114,19 +146,30 @@ fn create_container_dev_path(rootfs: &Path, dev: &LinuxDevice) -> Result<PathBuf
             .unwrap_or_else(|| Path::new("")),
     )?;
 
-    Ok(full_container_path)
+    let file_name = relative_dev_path
+        .file_name()
+        .with_context(|| format!("device path {:?} has no file name", dev.path()))?;
+    let dir = openat2::open_in_root(
+        rootfs,
+        relative_dev_path.parent().unwrap_or_else(|| Path::new("")),
+        OFlag::O_PATH | OFlag::O_DIRECTORY,
+    )?
+    .with_context(|| format!("parent directory of {:?} does not exist", dev.path()))?;
+    let dev_path = openat2::proc_fd_path(&dir).join(file_name);
+
+    Ok((dir, dev_path))
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::syscall::test::{ChownArgs, MknodArgs, MountArgs, TestHelperSyscall};
+    use crate::syscall::test::{ArgName, ChownArgs, MknodArgs, MountArgs, TestHelperSyscall};
     use crate::utils::TempDir;
     use nix::{
         sys::stat::SFlag,
         unistd::{Gid, Uid},
     };
-    use oci_spec::runtime::{LinuxDeviceBuilder, LinuxDeviceType};
+    use oci_spec::runtime::LinuxDeviceBuilder;
     use std::path::PathBuf;
 
     #[test]
@@ -259,4 +302,75 @@ mod tests {
             .get_mknod_args()[0];
         assert_eq!(want, *got);
     }
+
+    #[test]
+    fn test_create_devices_falls_back_to_bind() -> Result<()> {
+        let tmp_dir = TempDir::new("/tmp/test_create_devices_falls_back_to_bind")?;
+        let device = Device::new();
+        let devices = vec![LinuxDeviceBuilder::default()
+            .path(PathBuf::from("/dev/null"))
+            .major(1)
+            .minor(3)
+            .typ(LinuxDeviceType::C)
+            .build()?];
+        let syscall = device
+            .syscall
+            .as_any()
+            .downcast_ref::<TestHelperSyscall>()
+            .unwrap();
+        syscall.set_ret_err(ArgName::Mknod, || bail!(Errno::EPERM));
+
+        device.create_devices(tmp_dir.path(), &devices, false)?;
+
+        assert!(syscall.get_mknod_args().is_empty());
+        let want = vec![MountArgs {
+            source: Some(PathBuf::from("/dev/null")),
+            target: tmp_dir.path().join("dev/null"),
+            fstype: Some("bind".to_string()),
+            flags: MsFlags::MS_BIND,
+            data: None,
+        }];
+        assert_eq!(want, syscall.get_mount_args());
+
+        syscall.set_ret_err(ArgName::Mknod, || bail!(Errno::EEXIST));
+        assert!(device
+            .create_devices(tmp_dir.path(), &devices, false)
+            .is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_create_fifo_with_bind() -> Result<()> {
+        let tmp_dir = TempDir::new("/tmp/test_create_fifo_with_bind")?;
+        let device = Device::new();
+        let devices = vec![LinuxDeviceBuilder::default()
+            .path(PathBuf::from("/dev/fifo"))
+            .typ(LinuxDeviceType::P)
+            .build()?];
+
+        device.create_devices(tmp_dir.path(), &devices, true)?;
+
+        let syscall = device
+            .syscall
+            .as_any()
+            .downcast_ref::<TestHelperSyscall>()
+            .unwrap();
+        assert!(syscall.get_mount_args().is_empty());
+        let want = vec![MknodArgs {
+            path: tmp_dir.path().join("dev/fifo"),
+            kind: SFlag::S_IFIFO,
+            perm: Mode::from_bits_truncate(0o666),
+            dev: 0,
+        }];
+        assert_eq!(want, syscall.get_mknod_args());
+
+        let devices = vec![LinuxDeviceBuilder::default()
+            .path(PathBuf::from("/dev/all"))
+            .typ(LinuxDeviceType::A)
+            .build()?];
+        assert!(device
+            .create_devices(tmp_dir.path(), &devices, false)
+            .is_err());
+        Ok(())
+    }
 }
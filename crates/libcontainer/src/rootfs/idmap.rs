@@ -0,0 +1,357 @@
+//! Idmapped bind mounts have to be created by the runtime before the container
+//! process is forked. Attaching an id mapping to a mount requires CAP_SYS_ADMIN
+//! in the user namespace which owns the mount, which the init process lacks
+//! once it has entered the user namespace of the container. The detached mounts
+//! are inherited by the init process, which moves them into the rootfs.
+use std::collections::HashMap;
+use std::fs::{canonicalize, File};
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Context, Result};
+use nix::{
+    fcntl::OFlag,
+    sched::{self, CloneFlags},
+    sys::{
+        signal::Signal,
+        wait::{waitpid, WaitStatus},
+    },
+    unistd::{self, Pid},
+};
+use oci_spec::runtime::{LinuxIdMapping, LinuxIdMappingBuilder, Mount, Spec};
+
+use crate::syscall::linux::LinuxSyscall;
+use crate::utils;
+
+/// Mount option which requests an idmapped mount. Without a value the id
+/// mappings of the container are used, otherwise the mappings are given as
+/// `idmap=uids=<container>-<host>-<size>[#...];gids=<container>-<host>-<size>[#...]`.
+pub const IDMAP_OPTION: &str = "idmap";
+
+/// Detached idmapped mounts keyed by the destination of the mount
+#[derive(Debug, Default)]
+pub struct IdmappedMounts {
+    mounts: HashMap<PathBuf, RawFd>,
+}
+
+impl IdmappedMounts {
+    /// Creates the idmapped mounts requested in the spec. Returns None if the
+    /// spec does not contain any idmapped mounts.
+    pub fn new(spec: &Spec) -> Result<Option<Self>> {
+        let mut idmapped = IdmappedMounts::default();
+        for mount in spec.mounts().iter().flatten() {
+            let value = match idmap_option(mount) {
+                Some(value) => value,
+                None => continue,
+            };
+            if mount.typ().as_deref() != Some("bind") {
+                bail!(
+                    "idmapped mounts are only supported for bind mounts: {:?}",
+                    mount.destination()
+                );
+            }
+
+            let (uid_mappings, gid_mappings) =
+                mount_id_mappings(value, spec).with_context(|| {
+                    format!("invalid id mappings for mount {:?}", mount.destination())
+                })?;
+            let source = mount.source().as_ref().context("no source in mount spec")?;
+            let source = canonicalize(source)
+                .with_context(|| format!("failed to canonicalize: {:?}", source))?;
+            let recursive = mount
+                .options()
+                .iter()
+                .flatten()
+                .any(|option| option.as_str() == "rbind");
+
+            let userns = create_userns(&uid_mappings, &gid_mappings)
+                .context("failed to create user namespace for idmapped mount")?;
+            let tree_fd = LinuxSyscall::open_idmapped_tree(&source, userns.as_raw_fd(), recursive)
+                .with_context(|| format!("failed to create idmapped mount of {:?}", source))?;
+            idmapped
+                .mounts
+                .insert(mount.destination().to_path_buf(), tree_fd);
+        }
+
+        if idmapped.mounts.is_empty() {
+            return Ok(None);
+        }
+
+        Ok(Some(idmapped))
+    }
+
+    /// Returns the detached mount for the destination
+    pub fn get(&self, destination: &Path) -> Option<RawFd> {
+        self.mounts.get(destination).copied()
+    }
+
+    /// Closes the detached mounts in the calling process
+    pub fn close(&self) -> Result<()> {
+        for fd in self.mounts.values() {
+            unistd::close(*fd).context("failed to close idmapped mount")?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Returns the value of the idmap option of the mount, which is empty if the
+/// option has no value
+pub fn idmap_option(mount: &Mount) -> Option<&str> {
+    mount.options().iter().flatten().find_map(|option| {
+        if option.as_str() == IDMAP_OPTION {
+            Some("")
+        } else {
+            option
+                .strip_prefix(IDMAP_OPTION)
+                .and_then(|value| value.strip_prefix('='))
+        }
+    })
+}
+
+fn mount_id_mappings(
+    value: &str,
+    spec: &Spec,
+) -> Result<(Vec<LinuxIdMapping>, Vec<LinuxIdMapping>)> {
+    if value.is_empty() {
+        let linux = spec.linux().as_ref().context("no linux in spec")?;
+        return match (linux.uid_mappings(), linux.gid_mappings()) {
+            (Some(uid_mappings), Some(gid_mappings)) => {
+                Ok((uid_mappings.clone(), gid_mappings.clone()))
+            }
+            _ => bail!(
+                "the container has no id mappings, they have to be given in the mount options"
+            ),
+        };
+    }
+
+    let mut uid_mappings = None;
+    let mut gid_mappings = None;
+    for part in value.split(';') {
+        match part.split_once('=') {
+            Some(("uids", mappings)) => uid_mappings = Some(parse_id_mappings(mappings)?),
+            Some(("gids", mappings)) => gid_mappings = Some(parse_id_mappings(mappings)?),
+            _ => bail!("unknown idmap option {}", part),
+        }
+    }
+
+    match (uid_mappings, gid_mappings) {
+        (Some(uid_mappings), Some(gid_mappings)) => Ok((uid_mappings, gid_mappings)),
+        _ => bail!("both uids and gids have to be given in the idmap option"),
+    }
+}
+
+fn parse_id_mappings(mappings: &str) -> Result<Vec<LinuxIdMapping>> {
+    mappings
+        .split('#')
+        .map(|mapping| {
+            let ids = mapping
+                .split('-')
+                .map(|id| id.parse::<u32>())
+                .collect::<Result<Vec<_>, _>>()
+                .with_context(|| format!("failed to parse id mapping {}", mapping))?;
+            match ids[..] {
+                [container_id, host_id, size] => Ok(LinuxIdMappingBuilder::default()
+                    .container_id(container_id)
+                    .host_id(host_id)
+                    .size(size)
+                    .build()?),
+                _ => bail!(
+                    "invalid id mapping {}, expected <container>-<host>-<size>",
+                    mapping
+                ),
+            }
+        })
+        .collect()
+}
+
+/// Creates a user namespace with the id mappings. The namespace is owned by a
+/// short lived child process, which exits once the namespace has been opened.
+fn create_userns(uid_mappings: &[LinuxIdMapping], gid_mappings: &[LinuxIdMapping]) -> Result<File> {
+    let (read_fd, write_fd) = unistd::pipe2(OFlag::O_CLOEXEC).context("failed to create pipe")?;
+
+    let mut stack = vec![0u8; 64 * 1024];
+    let child = sched::clone(
+        Box::new(|| {
+            let _ = unistd::close(write_fd);
+            // blocks until the parent closes the write end of the pipe
+            let mut buf = [0u8; 1];
+            let _ = unistd::read(read_fd, &mut buf);
+            0
+        }),
+        &mut stack,
+        CloneFlags::CLONE_NEWUSER,
+        Some(Signal::SIGCHLD as i32),
+    );
+    let _ = unistd::close(read_fd);
+    let child = match child {
+        Ok(child) => child,
+        Err(err) => {
+            let _ = unistd::close(write_fd);
+            bail!("failed to clone into new user namespace: {}", err);
+        }
+    };
+
+    let userns = open_userns(child, uid_mappings, gid_mappings);
+    let _ = unistd::close(write_fd);
+    match waitpid(child, None) {
+        Ok(WaitStatus::Exited(_, 0)) => {}
+        status => tracing::warn!("user namespace process exited unexpectedly: {:?}", status),
+    }
+
+    userns
+}
+
+fn open_userns(
+    pid: Pid,
+    uid_mappings: &[LinuxIdMapping],
+    gid_mappings: &[LinuxIdMapping],
+) -> Result<File> {
+    let proc_dir = PathBuf::from(format!("/proc/{}", pid));
+    utils::write_file(proc_dir.join("uid_map"), format_id_mappings(uid_mappings))
+        .context("failed to write uid mappings")?;
+    utils::write_file(proc_dir.join("gid_map"), format_id_mappings(gid_mappings))
+        .context("failed to write gid mappings")?;
+    File::open(proc_dir.join("ns/user")).context("failed to open user namespace")
+}
+
+fn format_id_mappings(mappings: &[LinuxIdMapping]) -> String {
+    mappings
+        .iter()
+        .map(|m| format!("{} {} {}\n", m.container_id(), m.host_id(), m.size()))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::process::fork::container_fork;
+    use crate::syscall::Syscall;
+    use crate::utils::create_temp_dir;
+    use nix::mount::{mount, MsFlags};
+    use nix::sys::stat::stat;
+    use oci_spec::runtime::{LinuxBuilder, MountBuilder, SpecBuilder};
+
+    fn mapping(container_id: u32, host_id: u32, size: u32) -> LinuxIdMapping {
+        LinuxIdMappingBuilder::default()
+            .container_id(container_id)
+            .host_id(host_id)
+            .size(size)
+            .build()
+            .unwrap()
+    }
+
+    #[test]
+    fn test_idmap_option() -> Result<()> {
+        let mount = MountBuilder::default()
+            .destination("/data")
+            .options(vec!["rbind".to_owned(), "idmap".to_owned()])
+            .build()?;
+        assert_eq!(idmap_option(&mount), Some(""));
+
+        let mount = MountBuilder::default()
+            .destination("/data")
+            .options(vec!["idmap=uids=0-1000-10;gids=0-1000-10".to_owned()])
+            .build()?;
+        assert_eq!(idmap_option(&mount), Some("uids=0-1000-10;gids=0-1000-10"));
+
+        let mount = MountBuilder::default()
+            .destination("/data")
+            .options(vec!["idmapped".to_owned()])
+            .build()?;
+        assert_eq!(idmap_option(&mount), None);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_mount_id_mappings() -> Result<()> {
+        let spec = SpecBuilder::default()
+            .linux(
+                LinuxBuilder::default()
+                    .uid_mappings(vec![mapping(0, 100000, 65536)])
+                    .gid_mappings(vec![mapping(0, 200000, 65536)])
+                    .build()?,
+            )
+            .build()?;
+
+        assert_eq!(
+            mount_id_mappings("", &spec)?,
+            (
+                vec![mapping(0, 100000, 65536)],
+                vec![mapping(0, 200000, 65536)]
+            )
+        );
+        assert_eq!(
+            mount_id_mappings("uids=0-1000-1#1-2000-10;gids=0-1000-1", &spec)?,
+            (
+                vec![mapping(0, 1000, 1), mapping(1, 2000, 10)],
+                vec![mapping(0, 1000, 1)]
+            )
+        );
+        assert!(mount_id_mappings("uids=0-1000-1", &spec).is_err());
+        assert!(mount_id_mappings("uids=0-1000;gids=0-1000-1", &spec).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_idmapped_mount() -> Result<()> {
+        if !nix::unistd::geteuid().is_root() {
+            return Ok(());
+        }
+
+        let tmp_dir = create_temp_dir("test_idmapped_mount")?;
+        let source = tmp_dir.path().join("source");
+        let target = tmp_dir.path().join("target");
+        std::fs::create_dir_all(&source)?;
+        std::fs::create_dir_all(&target)?;
+        std::fs::write(source.join("file"), "")?;
+
+        let spec = SpecBuilder::default()
+            .mounts(vec![MountBuilder::default()
+                .destination("/data")
+                .typ("bind")
+                .source(&source)
+                .options(vec![
+                    "rbind".to_owned(),
+                    "idmap=uids=0-1000-1;gids=0-2000-1".to_owned(),
+                ])
+                .build()?])
+            .build()?;
+
+        let idmapped = match IdmappedMounts::new(&spec) {
+            Ok(idmapped) => idmapped.unwrap(),
+            // idmapped mounts are not supported by the kernel or filesystem
+            Err(_) => return Ok(()),
+        };
+        let tree_fd = idmapped.get(Path::new("/data")).unwrap();
+
+        // the mount is attached in a separate mount namespace, so that it
+        // does not leak into the mount namespace of the tests
+        let child = container_fork(|| {
+            sched::unshare(CloneFlags::CLONE_NEWNS)?;
+            mount(
+                None::<&str>,
+                "/",
+                None::<&str>,
+                MsFlags::MS_PRIVATE | MsFlags::MS_REC,
+                None::<&str>,
+            )?;
+            LinuxSyscall.move_mount(tree_fd, &target)?;
+
+            let stat = stat(&target.join("file"))?;
+            if stat.st_uid != 1000 || stat.st_gid != 2000 {
+                bail!("unexpected owner {}:{}", stat.st_uid, stat.st_gid);
+            }
+
+            Ok(())
+        })?;
+        idmapped.close()?;
+
+        match waitpid(child, None)? {
+            WaitStatus::Exited(_, 0) => Ok(()),
+            status => bail!("idmapped mount has unexpected owner: {:?}", status),
+        }
+    }
+}
@@ -5,7 +5,11 @@
 pub(crate) mod rootfs;
 pub use rootfs::RootFS;
 
+pub mod idmap;
+pub use idmap::IdmappedMounts;
+
 pub(super) mod device;
 pub(super) mod mount;
+pub(super) mod overlay;
 pub(super) mod symlink;
 pub(super) mod utils;
@@ -1,9 +1,15 @@
 use super::{
+    idmap::{idmap_option, IdmappedMounts},
+    overlay::{prepare_overlay_data, CREATE_DIRS_OPTION},
     symlink::Symlink,
-    utils::{find_parent_mount, parse_mount},
+    utils::{
+        devpts_data, devpts_data_without_gid, find_parent_mount, parse_mount, parse_mount_attrs,
+        parse_mount_propagation,
+    },
 };
 use crate::utils::PathBufExt;
 use crate::{
+    openat2,
     syscall::{syscall::create_syscall, Syscall},
     utils,
 };
@@ -12,14 +18,20 @@ use libcgroups::common::{
     CgroupSetup::{Hybrid, Legacy, Unified},
     DEFAULT_CGROUP_ROOT,
 };
-use nix::{errno::Errno, mount::MsFlags};
+use nix::{
+    errno::Errno,
+    fcntl::OFlag,
+    mount::{umount2, MntFlags, MsFlags},
+    unistd,
+};
 use oci_spec::runtime::{Mount as SpecMount, MountBuilder as SpecMountBuilder};
 use procfs::process::{MountOptFields, Process};
 use std::borrow::Cow;
+use std::os::unix::io::RawFd;
 use std::path::{Path, PathBuf};
 use std::{
     collections::HashMap,
-    fs::{canonicalize, create_dir_all, OpenOptions},
+    fs::{canonicalize, create_dir_all, File, OpenOptions},
 };
 
 #[derive(Debug)]
@@ -27,6 +39,13 @@ pub struct MountOptions<'a> {
     pub root: &'a Path,
     pub label: Option<&'a str>,
     pub cgroup_ns: bool,
+    pub create_missing_bind_sources: bool,
+    /// Directory in which relative directories of overlay mounts are created
+    pub state_dir: Option<&'a Path>,
+    /// Whether the container has its own user namespace
+    pub user_ns: bool,
+    /// Detached idmapped mounts created by the runtime
+    pub idmapped_mounts: Option<&'a IdmappedMounts>,
 }
 
 pub struct Mount {
@@ -47,7 +66,7 @@ impl Mount {
     }
 
     pub fn setup_mount(&self, mount: &SpecMount, options: &MountOptions) -> Result<()> {
-        log::debug!("Mounting {:?}", mount);
+        tracing::debug!("Mounting {:?}", mount);
         let (flags, data) = parse_mount(mount);
 
         match mount.typ().as_deref() {
@@ -63,7 +82,28 @@ impl Mount {
                         .context("failed to mount cgroup v2")?,
                 }
             }
+            Some("overlay") => {
+                let create_dirs = mount
+                    .options()
+                    .as_ref()
+                    .map_or(false, |o| o.iter().any(|o| o == CREATE_DIRS_OPTION));
+                let data =
+                    prepare_overlay_data(&data, create_dirs, options.state_dir, options.user_ns)
+                        .with_context(|| format!("invalid overlay mount: {:?}", mount))?;
+                self.mount_into_container(mount, options.root, flags, &data, options.label, None)
+                    .with_context(|| match options.user_ns {
+                        true => format!(
+                            "failed to mount overlay in user namespace, which requires Linux 5.11: {:?}",
+                            mount
+                        ),
+                        false => format!("failed to mount: {:?}", mount),
+                    })?;
+            }
             _ => {
+                if mount.typ().as_deref() == Some("bind") && options.create_missing_bind_sources {
+                    create_missing_bind_source(mount, options.root)?;
+                }
+
                 if *mount.destination() == PathBuf::from("/dev") {
                     self.mount_into_container(
                         mount,
@@ -71,11 +111,22 @@ impl Mount {
                         flags & !MsFlags::MS_RDONLY,
                         &data,
                         options.label,
+                        None,
                     )
                     .with_context(|| format!("failed to mount /dev: {:?}", mount))?;
                 } else {
-                    self.mount_into_container(mount, options.root, flags, &data, options.label)
-                        .with_context(|| format!("failed to mount: {:?}", mount))?;
+                    let idmapped = options
+                        .idmapped_mounts
+                        .and_then(|idmapped| idmapped.get(mount.destination()));
+                    self.mount_into_container(
+                        mount,
+                        options.root,
+                        flags,
+                        &data,
+                        options.label,
+                        idmapped,
+                    )
+                    .with_context(|| format!("failed to mount: {:?}", mount))?;
                 }
             }
         }
@@ -83,7 +134,7 @@ impl Mount {
         Ok(())
     }
     fn mount_cgroup_v1(&self, cgroup_mount: &SpecMount, options: &MountOptions) -> Result<()> {
-        log::debug!("Mounting cgroup v1 filesystem");
+        tracing::debug!("Mounting cgroup v1 filesystem");
         // create tmpfs into which the cgroup subsystems will be mounted
         let tmpfs = SpecMountBuilder::default()
             .source("tmpfs")
@@ -107,7 +158,7 @@ impl Mount {
             .into_iter()
             .filter(|p| p.as_path().starts_with(DEFAULT_CGROUP_ROOT))
             .collect();
-        log::debug!("cgroup mounts: {:?}", host_mounts);
+        tracing::debug!("cgroup mounts: {:?}", host_mounts);
 
         // get process cgroups
         let process_cgroups: HashMap<String, String> = Process::myself()?
@@ -116,32 +167,29 @@ impl Mount {
             .into_iter()
             .map(|c| (c.controllers.join(","), c.pathname))
             .collect();
-        log::debug!("Process cgroups: {:?}", process_cgroups);
+        tracing::debug!("Process cgroups: {:?}", process_cgroups);
 
         let cgroup_root = options
             .root
             .join_safely(cgroup_mount.destination())
             .context("could not join rootfs path with cgroup mount destination")?;
-        log::debug!("cgroup root: {:?}", cgroup_root);
+        tracing::debug!("cgroup root: {:?}", cgroup_root);
 
         let symlink = Symlink::new();
 
         // setup cgroup mounts for container
         for host_mount in &host_mounts {
             if let Some(subsystem_name) = host_mount.file_name().and_then(|n| n.to_str()) {
+                // named hierarchies like name=systemd have no controllers
+                let named = is_named_hierarchy(subsystem_name, &process_cgroups);
                 if options.cgroup_ns {
-                    self.setup_namespaced_subsystem(
-                        cgroup_mount,
-                        options,
-                        subsystem_name,
-                        subsystem_name == "systemd",
-                    )?;
+                    self.setup_namespaced_subsystem(cgroup_mount, options, subsystem_name, named)?;
                 } else {
                     self.setup_emulated_subsystem(
                         cgroup_mount,
                         options,
                         subsystem_name,
-                        subsystem_name == "systemd",
+                        named,
                         host_mount,
                         &process_cgroups,
                     )?;
@@ -149,7 +197,7 @@ impl Mount {
 
                 symlink.setup_comount_symlinks(&cgroup_root, subsystem_name)?;
             } else {
-                log::warn!("could not get subsystem name from {:?}", host_mount);
+                tracing::warn!("could not get subsystem name from {:?}", host_mount);
             }
         }
 
@@ -165,7 +213,7 @@ impl Mount {
         subsystem_name: &str,
         named: bool,
     ) -> Result<()> {
-        log::debug!(
+        tracing::debug!(
             "Mounting (namespaced) {:?} cgroup subsystem",
             subsystem_name
         );
@@ -194,6 +242,7 @@ impl Mount {
             MsFlags::MS_NOEXEC | MsFlags::MS_NOSUID | MsFlags::MS_NODEV,
             &data,
             options.label,
+            None,
         )
         .with_context(|| format!("failed to mount {:?}", subsystem_mount))
     }
@@ -207,7 +256,7 @@ impl Mount {
         host_mount: &Path,
         process_cgroups: &HashMap<String, String>,
     ) -> Result<()> {
-        log::debug!("Mounting (emulated) {:?} cgroup subsystem", subsystem_name);
+        tracing::debug!("Mounting (emulated) {:?} cgroup subsystem", subsystem_name);
         let named_hierarchy: Cow<str> = if named {
             format!("name={}", subsystem_name).into()
         } else {
@@ -245,12 +294,12 @@ impl Mount {
                         .collect::<Vec<String>>(),
                 )
                 .build()?;
-            log::debug!("Mounting emulated cgroup subsystem: {:?}", emulated);
+            tracing::debug!("Mounting emulated cgroup subsystem: {:?}", emulated);
 
             self.setup_mount(&emulated, options)
                 .with_context(|| format!("failed to mount {} cgroup hierarchy", subsystem_name))?;
         } else {
-            log::warn!("Could not mount {:?} cgroup subsystem", subsystem_name);
+            tracing::warn!("Could not mount {:?} cgroup subsystem", subsystem_name);
         }
 
         Ok(())
@@ -263,7 +312,7 @@ impl Mount {
         flags: MsFlags,
         data: &str,
     ) -> Result<()> {
-        log::debug!("Mounting cgroup v2 filesystem");
+        tracing::debug!("Mounting cgroup v2 filesystem");
 
         let cgroup_mount = SpecMountBuilder::default()
             .typ("cgroup2")
@@ -271,42 +320,63 @@ impl Mount {
             .destination(cgroup_mount.destination())
             .options(Vec::new())
             .build()?;
-        log::debug!("{:?}", cgroup_mount);
-
-        if self
-            .mount_into_container(&cgroup_mount, options.root, flags, data, options.label)
-            .context("failed to mount into container")
-            .is_err()
-        {
-            let host_mount = libcgroups::v2::util::get_unified_mount_point()
-                .context("failed to get unified mount point")?;
-
-            let process_cgroup = Process::myself()?
-                .cgroups()
-                .context("failed to get process cgroups")?
-                .into_iter()
-                .find(|c| c.hierarchy == 0)
-                .map(|c| PathBuf::from(c.pathname))
-                .ok_or_else(|| anyhow!("failed to find unified process cgroup"))?;
-
-            let bind_mount = SpecMountBuilder::default()
-                .typ("bind")
-                .source(host_mount.join_safely(process_cgroup)?)
-                .destination(cgroup_mount.destination())
-                .options(Vec::new())
-                .build()
-                .context("failed to build cgroup bind mount")?;
-            log::debug!("{:?}", bind_mount);
-
-            self.mount_into_container(
-                &bind_mount,
+        tracing::debug!("{:?}", cgroup_mount);
+
+        // a new cgroup2 mount only shows the cgroup of the container and its
+        // descendants if the container has its own cgroup namespace. Otherwise
+        // the cgroup of the container is bind mounted read only, so that the
+        // container can neither see nor modify the hierarchy of the host.
+        let flags = if options.cgroup_ns {
+            match self.mount_into_container(
+                &cgroup_mount,
                 options.root,
-                flags | MsFlags::MS_BIND,
+                flags,
                 data,
                 options.label,
-            )
-            .context("failed to bind mount cgroup hierarchy")?;
-        }
+                None,
+            ) {
+                Ok(()) => return Ok(()),
+                Err(err) => {
+                    tracing::debug!(
+                        "failed to mount cgroup2, bind mounting it instead: {:#}",
+                        err
+                    );
+                    flags
+                }
+            }
+        } else {
+            flags | MsFlags::MS_RDONLY
+        };
+
+        let host_mount = libcgroups::v2::util::get_unified_mount_point()
+            .context("failed to get unified mount point")?;
+
+        let process_cgroup = Process::myself()?
+            .cgroups()
+            .context("failed to get process cgroups")?
+            .into_iter()
+            .find(|c| c.hierarchy == 0)
+            .map(|c| PathBuf::from(c.pathname))
+            .ok_or_else(|| anyhow!("failed to find unified process cgroup"))?;
+
+        let bind_mount = SpecMountBuilder::default()
+            .typ("bind")
+            .source(host_mount.join_safely(process_cgroup)?)
+            .destination(cgroup_mount.destination())
+            .options(Vec::new())
+            .build()
+            .context("failed to build cgroup bind mount")?;
+        tracing::debug!("{:?}", bind_mount);
+
+        self.mount_into_container(
+            &bind_mount,
+            options.root,
+            flags | MsFlags::MS_BIND,
+            data,
+            options.label,
+            None,
+        )
+        .context("failed to bind mount cgroup hierarchy")?;
 
         Ok(())
     }
@@ -335,6 +405,32 @@ impl Mount {
         Ok(())
     }
 
+    /// Creates a private mount for the staging directory of a tmpcopyup
+    /// mount. Otherwise the tmpfs could propagate to the host and could not
+    /// be moved if the parent mount is shared.
+    fn prepare_tmpcopyup_staging(&self) -> Result<TmpcopyupStaging> {
+        let root = std::env::temp_dir().join(format!("youki-tmpcopyup-{}", unistd::getpid()));
+        let tmpfs = root.join("tmpfs");
+        utils::create_dir_all(&tmpfs)?;
+
+        let staging = TmpcopyupStaging { root, tmpfs };
+        self.syscall
+            .mount(
+                Some(&staging.root),
+                &staging.root,
+                None,
+                MsFlags::MS_BIND,
+                None,
+            )
+            .and_then(|_| {
+                self.syscall
+                    .mount(None, &staging.root, None, MsFlags::MS_PRIVATE, None)
+            })
+            .with_context(|| format!("failed to prepare staging directory {:?}", staging.root))?;
+
+        Ok(staging)
+    }
+
     fn mount_into_container(
         &self,
         m: &SpecMount,
@@ -342,8 +438,14 @@ impl Mount {
         flags: MsFlags,
         data: &str,
         label: Option<&str>,
+        idmapped: Option<RawFd>,
     ) -> Result<()> {
         let typ = m.typ().as_deref();
+        let data: Cow<str> = if typ == Some("devpts") {
+            Cow::Owned(devpts_data(data))
+        } else {
+            Cow::Borrowed(data)
+        };
         let mut d = data.to_string();
 
         if let Some(l) = label {
@@ -355,7 +457,7 @@ impl Mount {
             }
         }
 
-        let dest_for_host = utils::secure_join(rootfs, m.destination())
+        let dest_for_host = openat2::resolve_in_root(rootfs, m.destination())
             .with_context(|| format!("failed to join {:?} with {:?}", rootfs, m.destination()))?;
 
         let dest = Path::new(&dest_for_host);
@@ -366,7 +468,9 @@ impl Mount {
         let src = if typ == Some("bind") {
             let src = canonicalize(source)
                 .with_context(|| format!("failed to canonicalize: {:?}", source))?;
-            let dir = if src.is_file() {
+            // anything but a directory, e.g. a device or a socket, is bind
+            // mounted onto a file
+            let dir = if !src.is_dir() {
                 Path::new(&dest).parent().unwrap()
             } else {
                 Path::new(&dest)
@@ -375,7 +479,7 @@ impl Mount {
             create_dir_all(&dir)
                 .with_context(|| format!("failed to create dir for bind mount: {:?}", dir))?;
 
-            if src.is_file() {
+            if !src.is_dir() && !dest.exists() {
                 OpenOptions::new()
                     .create(true)
                     .write(true)
@@ -391,43 +495,269 @@ impl Mount {
             PathBuf::from(source)
         };
 
-        if let Err(err) = self.syscall.mount(Some(&*src), dest, typ, flags, Some(&*d)) {
-            if let Some(errno) = err.downcast_ref() {
-                if !matches!(errno, Errno::EINVAL) {
+        // the destination is mounted over through its file descriptor, so that
+        // a component of the path can not be replaced with a symlink after it
+        // has been resolved
+        let dest_file = open_destination(rootfs, m)?;
+        let dest_fd_path = openat2::proc_fd_path(&dest_file);
+        let dest = dest_fd_path.as_path();
+
+        let attrs = parse_mount_attrs(m);
+        let propagation = parse_mount_propagation(m)?;
+        if !attrs.is_empty() && typ != Some("bind") {
+            bail!(
+                "recursive mount options are only supported for bind mounts: {:?}",
+                m.destination()
+            );
+        }
+
+        let has_option = |name: &str| {
+            m.options()
+                .as_ref()
+                .map_or(false, |options| options.iter().any(|o| o == name))
+        };
+        let idmap = idmap_option(m).is_some();
+        let tmpcopyup = has_option("tmpcopyup");
+        if has_option(CREATE_DIRS_OPTION) && typ != Some("overlay") {
+            bail!(
+                "{} is only supported for overlay mounts: {:?}",
+                CREATE_DIRS_OPTION,
+                m.destination()
+            );
+        }
+        if tmpcopyup && typ != Some("tmpfs") {
+            bail!(
+                "tmpcopyup is only supported for tmpfs mounts: {:?}",
+                m.destination()
+            );
+        }
+
+        // with tmpcopyup, the tmpfs is mounted to a staging directory first,
+        // so that the contents of the destination can be copied into it
+        let staging = if tmpcopyup {
+            Some(self.prepare_tmpcopyup_staging()?)
+        } else {
+            None
+        };
+        let mount_target = staging
+            .as_ref()
+            .map_or(dest, |staging| staging.tmpfs.as_path());
+
+        // the flags of the host instance are applied by remounting it
+        let mut host_fallback = false;
+        if idmap {
+            // the idmapped mount has been created by the runtime, as the id
+            // mapping can not be attached in the user namespace of the container
+            let tree_fd = idmapped.with_context(|| {
+                format!("idmapped mount of {:?} was not created", m.destination())
+            })?;
+            self.syscall
+                .move_mount(tree_fd, dest)
+                .with_context(|| format!("failed to move idmapped mount of {:?}", src))?;
+        } else if let Err(err) =
+            self.syscall
+                .mount(Some(&*src), mount_target, typ, flags, Some(&*d))
+        {
+            let errno = err.downcast_ref::<Errno>().copied();
+            match (errno, host_fallback_source(typ, m.destination())) {
+                // a new instance can not be mounted in a user namespace, which
+                // does not own the network or ipc namespace of the container
+                (Some(Errno::EPERM), Some(host_source)) => {
+                    tracing::debug!(
+                        "mounting {:?} is not permitted, bind mounting {:?}",
+                        m.destination(),
+                        host_source
+                    );
+                    self.syscall
+                        .mount(
+                            Some(host_source),
+                            mount_target,
+                            None,
+                            MsFlags::MS_BIND | MsFlags::MS_REC,
+                            None,
+                        )
+                        .with_context(|| {
+                            format!(
+                                "failed to bind mount {:?} to {:?}",
+                                host_source,
+                                m.destination()
+                            )
+                        })?;
+                    host_fallback = true;
+                }
+                (Some(errno), _) if errno != Errno::EINVAL => {
                     bail!("mount of {:?} failed. {}", m.destination(), errno);
                 }
+                _ => match self
+                    .syscall
+                    .mount(Some(&*src), mount_target, typ, flags, Some(&*data))
+                {
+                    // the group of ttys may not be mapped in the user namespace
+                    Err(err)
+                        if typ == Some("devpts")
+                            && matches!(err.downcast_ref::<Errno>(), Some(Errno::EINVAL)) =>
+                    {
+                        match devpts_data_without_gid(&data) {
+                            Some(data) => {
+                                tracing::warn!(
+                                    "failed to mount devpts with gid, mounting it without"
+                                );
+                                self.syscall.mount(
+                                    Some(&*src),
+                                    mount_target,
+                                    typ,
+                                    flags,
+                                    Some(data.as_str()),
+                                )
+                            }
+                            None => Err(err),
+                        }
+                    }
+                    result => result,
+                }
+                .with_context(|| format!("failed to mount {:?} to {:?}", src, m.destination()))?,
             }
+        }
 
+        if let Some(staging) = &staging {
+            utils::copy_dir_contents(dest, &staging.tmpfs).with_context(|| {
+                format!("failed to copy up the contents of {:?}", m.destination())
+            })?;
             self.syscall
-                .mount(Some(&*src), dest, typ, flags, Some(data))
-                .with_context(|| format!("failed to mount {:?} to {:?}", src, dest))?;
+                .mount(Some(&staging.tmpfs), dest, None, MsFlags::MS_MOVE, None)
+                .with_context(|| format!("failed to move tmpfs to {:?}", m.destination()))?;
         }
 
-        if typ == Some("bind")
-            && flags.intersects(
-                !(MsFlags::MS_REC
-                    | MsFlags::MS_REMOUNT
-                    | MsFlags::MS_BIND
-                    | MsFlags::MS_PRIVATE
-                    | MsFlags::MS_SHARED
-                    | MsFlags::MS_SLAVE),
-            )
+        // the file descriptor of the destination still refers to the covered
+        // directory, the new mount is only reached by opening it again
+        let mounted_file = open_destination(rootfs, m)?;
+        let mounted_fd_path = openat2::proc_fd_path(&mounted_file);
+        let dest = mounted_fd_path.as_path();
+
+        if host_fallback
+            || (typ == Some("bind")
+                && flags.intersects(
+                    !(MsFlags::MS_REC
+                        | MsFlags::MS_REMOUNT
+                        | MsFlags::MS_BIND
+                        | MsFlags::MS_PRIVATE
+                        | MsFlags::MS_SHARED
+                        | MsFlags::MS_SLAVE),
+                ))
         {
             self.syscall
-                .mount(Some(dest), dest, None, flags | MsFlags::MS_REMOUNT, None)
-                .with_context(|| format!("Failed to remount: {:?}", dest))?;
+                .mount(
+                    Some(dest),
+                    dest,
+                    None,
+                    flags | MsFlags::MS_BIND | MsFlags::MS_REMOUNT,
+                    None,
+                )
+                .with_context(|| format!("Failed to remount: {:?}", m.destination()))?;
+        }
+
+        if !attrs.is_empty() {
+            self.syscall
+                .mount_setattr(dest, attrs.attr_set, attrs.attr_clr, true)
+                .with_context(|| {
+                    format!(
+                        "failed to apply recursive mount options to {:?}",
+                        m.destination()
+                    )
+                })?;
+        }
+
+        if let Some(propagation) = propagation {
+            self.syscall
+                .mount(None, dest, None, propagation, None)
+                .with_context(|| {
+                    format!("failed to change propagation of {:?}", m.destination())
+                })?;
         }
 
         Ok(())
     }
 }
 
+/// Opens the destination of the mount in the rootfs, the mount is done onto the
+/// returned file descriptor
+fn open_destination(rootfs: &Path, m: &SpecMount) -> Result<File> {
+    openat2::open_in_root(rootfs, m.destination(), OFlag::O_PATH)
+        .with_context(|| format!("failed to open mount destination {:?}", m.destination()))?
+        .with_context(|| format!("mount destination {:?} does not exist", m.destination()))
+}
+
+/// Creates the source of a bind mount if it does not exist. It is created as
+/// an empty file if the destination is a file in the rootfs, otherwise as
+/// directory.
+fn create_missing_bind_source(m: &SpecMount, rootfs: &Path) -> Result<()> {
+    let source = match m.source() {
+        Some(source) if !source.exists() => source,
+        _ => return Ok(()),
+    };
+
+    let dest = openat2::resolve_in_root(rootfs, m.destination())?;
+    if dest.exists() && !dest.is_dir() {
+        if let Some(parent) = source.parent() {
+            utils::create_dir_all(parent)?;
+        }
+        OpenOptions::new()
+            .create(true)
+            .write(true)
+            .open(source)
+            .with_context(|| format!("failed to create bind mount source {:?}", source))?;
+    } else {
+        utils::create_dir_all(source)?;
+    }
+
+    tracing::debug!("created missing bind mount source {:?}", source);
+    Ok(())
+}
+
+/// Returns if the cgroup v1 hierarchy, which is mounted at the subsystem name,
+/// is a named hierarchy without controllers. The process cgroups are keyed by
+/// the controllers of the hierarchy or by name=<name> for named hierarchies.
+fn is_named_hierarchy(subsystem_name: &str, process_cgroups: &HashMap<String, String>) -> bool {
+    subsystem_name == "systemd" || process_cgroups.contains_key(&format!("name={}", subsystem_name))
+}
+
+/// Returns the instance of the host, which is bind mounted if a new instance
+/// of the filesystem can not be mounted
+fn host_fallback_source(typ: Option<&str>, destination: &Path) -> Option<&'static Path> {
+    match typ {
+        Some("sysfs") => Some(Path::new("/sys")),
+        Some("mqueue") => Some(Path::new("/dev/mqueue")),
+        Some("tmpfs") if destination == Path::new("/dev/shm") => Some(Path::new("/dev/shm")),
+        _ => None,
+    }
+}
+
+/// Staging directory of a tmpfs mount with the tmpcopyup option
+struct TmpcopyupStaging {
+    root: PathBuf,
+    tmpfs: PathBuf,
+}
+
+impl Drop for TmpcopyupStaging {
+    fn drop(&mut self) {
+        // the tmpfs is only still mounted if it could not be moved
+        let _ = umount2(&self.tmpfs, MntFlags::MNT_DETACH);
+        if let Err(err) = umount2(&self.root, MntFlags::MNT_DETACH) {
+            tracing::debug!("failed to unmount {:?}: {}", self.root, err);
+        }
+        if let Err(err) = std::fs::remove_dir_all(&self.root) {
+            tracing::warn!("failed to remove {:?}: {}", self.root, err);
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use std::fs;
 
     use super::*;
-    use crate::syscall::test::{MountArgs, TestHelperSyscall};
+    use crate::syscall::linux::{MOUNT_ATTR_NOSUID, MOUNT_ATTR_RDONLY};
+    use crate::syscall::test::{MountArgs, MountSetattrArgs, MoveMountArgs, TestHelperSyscall};
     use crate::utils::create_temp_dir;
     use anyhow::Result;
 
@@ -453,7 +783,7 @@ mod tests {
             let (flags, data) = parse_mount(mount);
 
             assert!(m
-                .mount_into_container(mount, tmp_dir.path(), flags, &data, Some("defaults"))
+                .mount_into_container(mount, tmp_dir.path(), flags, &data, Some("defaults"), None)
                 .is_ok());
 
             let want = vec![MountArgs {
@@ -491,7 +821,7 @@ mod tests {
                 .unwrap();
 
             assert!(m
-                .mount_into_container(mount, tmp_dir.path(), flags, &data, None)
+                .mount_into_container(mount, tmp_dir.path(), flags, &data, None, None)
                 .is_ok());
 
             let want = vec![
@@ -507,7 +837,7 @@ mod tests {
                     source: Some(tmp_dir.path().join("dev/null")),
                     target: tmp_dir.path().join("dev/null"),
                     fstype: None,
-                    flags: MsFlags::MS_RDONLY | MsFlags::MS_REMOUNT,
+                    flags: MsFlags::MS_RDONLY | MsFlags::MS_BIND | MsFlags::MS_REMOUNT,
                     data: None,
                 },
             ];
@@ -522,6 +852,418 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_idmapped_mount_to_container() -> Result<()> {
+        let tmp_dir = create_temp_dir("test_idmapped_mount_to_container")?;
+        let source = tmp_dir.path().join("data");
+        fs::create_dir_all(&source)?;
+
+        let m = Mount::new();
+        let mount = &SpecMountBuilder::default()
+            .destination(PathBuf::from("/data"))
+            .typ("bind")
+            .source(&source)
+            .options(vec!["rbind".to_string(), "idmap".to_string()])
+            .build()?;
+        let (flags, data) = parse_mount(mount);
+        assert!(data.is_empty());
+
+        // the idmapped mount has to be created by the runtime
+        assert!(m
+            .mount_into_container(mount, tmp_dir.path(), flags, &data, None, None)
+            .is_err());
+
+        m.mount_into_container(mount, tmp_dir.path(), flags, &data, None, Some(42))?;
+
+        let syscall = m
+            .syscall
+            .as_any()
+            .downcast_ref::<TestHelperSyscall>()
+            .unwrap();
+        let want = vec![MoveMountArgs {
+            from_fd: 42,
+            target: tmp_dir.path().join("data"),
+        }];
+        assert_eq!(want, syscall.get_move_mount_args());
+        assert!(syscall.get_mount_args().is_empty());
+
+        let mount = &SpecMountBuilder::default()
+            .destination(PathBuf::from("/tmp"))
+            .typ("tmpfs")
+            .source("tmpfs")
+            .options(vec!["idmap".to_string()])
+            .build()?;
+        let (flags, data) = parse_mount(mount);
+        assert!(m
+            .mount_into_container(mount, tmp_dir.path(), flags, &data, None, None)
+            .is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_recursive_attrs_mount_to_container() -> Result<()> {
+        let tmp_dir = create_temp_dir("test_recursive_attrs_mount_to_container")?;
+        let source = tmp_dir.path().join("data");
+        fs::create_dir_all(&source)?;
+
+        let m = Mount::new();
+        let mount = &SpecMountBuilder::default()
+            .destination(PathBuf::from("/data"))
+            .typ("bind")
+            .source(&source)
+            .options(vec![
+                "rbind".to_string(),
+                "rro".to_string(),
+                "rnosuid".to_string(),
+            ])
+            .build()?;
+        let (flags, data) = parse_mount(mount);
+        m.mount_into_container(mount, tmp_dir.path(), flags, &data, None, None)?;
+
+        let syscall = m
+            .syscall
+            .as_any()
+            .downcast_ref::<TestHelperSyscall>()
+            .unwrap();
+        let want = vec![MountArgs {
+            source: Some(canonicalize(&source)?),
+            target: tmp_dir.path().join("data"),
+            fstype: Some("bind".to_string()),
+            flags: MsFlags::MS_BIND | MsFlags::MS_REC,
+            data: Some("".to_string()),
+        }];
+        assert_eq!(want, syscall.get_mount_args());
+        let want = vec![MountSetattrArgs {
+            path: tmp_dir.path().join("data"),
+            attr_set: MOUNT_ATTR_RDONLY | MOUNT_ATTR_NOSUID,
+            attr_clr: 0,
+            recursive: true,
+        }];
+        assert_eq!(want, syscall.get_mount_setattr_args());
+
+        let mount = &SpecMountBuilder::default()
+            .destination(PathBuf::from("/tmp"))
+            .typ("tmpfs")
+            .source("tmpfs")
+            .options(vec!["rro".to_string()])
+            .build()?;
+        let (flags, data) = parse_mount(mount);
+        assert!(m
+            .mount_into_container(mount, tmp_dir.path(), flags, &data, None, None)
+            .is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_tmpcopyup_mount_to_container() -> Result<()> {
+        let tmp_dir = create_temp_dir("test_tmpcopyup_mount_to_container")?;
+        fs::create_dir_all(tmp_dir.path().join("etc"))?;
+        fs::write(tmp_dir.path().join("etc/hosts"), "127.0.0.1 localhost")?;
+
+        let m = Mount::new();
+        let mount = &SpecMountBuilder::default()
+            .destination(PathBuf::from("/etc"))
+            .typ("tmpfs")
+            .source("tmpfs")
+            .options(vec!["tmpcopyup".to_string(), "mode=755".to_string()])
+            .build()?;
+        let (flags, data) = parse_mount(mount);
+        assert_eq!(data, "mode=755");
+        m.mount_into_container(mount, tmp_dir.path(), flags, &data, None, None)?;
+
+        let staging = std::env::temp_dir().join(format!("youki-tmpcopyup-{}", unistd::getpid()));
+        let want = vec![
+            MountArgs {
+                source: Some(staging.clone()),
+                target: staging.clone(),
+                fstype: None,
+                flags: MsFlags::MS_BIND,
+                data: None,
+            },
+            MountArgs {
+                source: None,
+                target: staging.clone(),
+                fstype: None,
+                flags: MsFlags::MS_PRIVATE,
+                data: None,
+            },
+            MountArgs {
+                source: Some(PathBuf::from("tmpfs")),
+                target: staging.join("tmpfs"),
+                fstype: Some("tmpfs".to_string()),
+                flags: MsFlags::empty(),
+                data: Some("mode=755".to_string()),
+            },
+            MountArgs {
+                source: Some(staging.join("tmpfs")),
+                target: tmp_dir.path().join("etc"),
+                fstype: None,
+                flags: MsFlags::MS_MOVE,
+                data: None,
+            },
+        ];
+        let got = m
+            .syscall
+            .as_any()
+            .downcast_ref::<TestHelperSyscall>()
+            .unwrap()
+            .get_mount_args();
+        assert_eq!(want, got);
+        assert!(!staging.exists());
+
+        let mount = &SpecMountBuilder::default()
+            .destination(PathBuf::from("/data"))
+            .typ("bind")
+            .source(tmp_dir.path().join("etc"))
+            .options(vec!["tmpcopyup".to_string()])
+            .build()?;
+        let (flags, data) = parse_mount(mount);
+        assert!(m
+            .mount_into_container(mount, tmp_dir.path(), flags, &data, None, None)
+            .is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_devpts_mount_without_gid() -> Result<()> {
+        let tmp_dir = create_temp_dir("test_devpts_mount_without_gid")?;
+        let m = Mount::new();
+        let mount = &SpecMountBuilder::default()
+            .destination(PathBuf::from("/dev/pts"))
+            .typ("devpts")
+            .source(PathBuf::from("devpts"))
+            .options(vec!["nosuid".to_string(), "gid=5".to_string()])
+            .build()?;
+        let (flags, data) = parse_mount(mount);
+        let syscall = m
+            .syscall
+            .as_any()
+            .downcast_ref::<TestHelperSyscall>()
+            .unwrap();
+        syscall.set_ret_err(ArgName::Mount, || bail!(Errno::EINVAL));
+        syscall.set_ret_err_times(ArgName::Mount, 2);
+
+        m.mount_into_container(mount, tmp_dir.path(), flags, &data, None, None)?;
+
+        let want = vec![MountArgs {
+            source: Some(PathBuf::from("devpts")),
+            target: tmp_dir.path().join("dev/pts"),
+            fstype: Some("devpts".to_string()),
+            flags: MsFlags::MS_NOSUID,
+            data: Some("newinstance,ptmxmode=0666".to_string()),
+        }];
+        assert_eq!(want, syscall.get_mount_args());
+        Ok(())
+    }
+
+    #[test]
+    fn test_sysfs_mount_falls_back_to_bind() -> Result<()> {
+        let tmp_dir = create_temp_dir("test_sysfs_mount_falls_back_to_bind")?;
+        let m = Mount::new();
+        let mount = &SpecMountBuilder::default()
+            .destination(PathBuf::from("/sys"))
+            .typ("sysfs")
+            .source(PathBuf::from("sysfs"))
+            .options(vec!["nosuid".to_string(), "ro".to_string()])
+            .build()?;
+        let (flags, data) = parse_mount(mount);
+        let syscall = m
+            .syscall
+            .as_any()
+            .downcast_ref::<TestHelperSyscall>()
+            .unwrap();
+        syscall.set_ret_err(ArgName::Mount, || bail!(Errno::EPERM));
+
+        m.mount_into_container(mount, tmp_dir.path(), flags, &data, None, None)?;
+
+        let want = vec![
+            MountArgs {
+                source: Some(PathBuf::from("/sys")),
+                target: tmp_dir.path().join("sys"),
+                fstype: None,
+                flags: MsFlags::MS_BIND | MsFlags::MS_REC,
+                data: None,
+            },
+            MountArgs {
+                source: Some(tmp_dir.path().join("sys")),
+                target: tmp_dir.path().join("sys"),
+                fstype: None,
+                flags: MsFlags::MS_NOSUID
+                    | MsFlags::MS_RDONLY
+                    | MsFlags::MS_BIND
+                    | MsFlags::MS_REMOUNT,
+                data: None,
+            },
+        ];
+        assert_eq!(want, syscall.get_mount_args());
+
+        // other filesystems do not fall back
+        let mount = &SpecMountBuilder::default()
+            .destination(PathBuf::from("/proc"))
+            .typ("proc")
+            .source(PathBuf::from("proc"))
+            .build()?;
+        let (flags, data) = parse_mount(mount);
+        syscall.set_ret_err(ArgName::Mount, || bail!(Errno::EPERM));
+        assert!(m
+            .mount_into_container(mount, tmp_dir.path(), flags, &data, None, None)
+            .is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_create_missing_bind_source() -> Result<()> {
+        let tmp_dir = create_temp_dir("test_create_missing_bind_source")?;
+        let rootfs = tmp_dir.path().join("rootfs");
+        fs::create_dir_all(rootfs.join("etc"))?;
+        fs::write(rootfs.join("etc/resolv.conf"), "")?;
+
+        let file_source = tmp_dir.path().join("resolv.conf");
+        let mount = SpecMountBuilder::default()
+            .destination(PathBuf::from("/etc/resolv.conf"))
+            .typ("bind")
+            .source(&file_source)
+            .build()?;
+        create_missing_bind_source(&mount, &rootfs)?;
+        assert!(file_source.is_file());
+
+        let dir_source = tmp_dir.path().join("volumes/data");
+        let mount = SpecMountBuilder::default()
+            .destination(PathBuf::from("/data"))
+            .typ("bind")
+            .source(&dir_source)
+            .build()?;
+        create_missing_bind_source(&mount, &rootfs)?;
+        assert!(dir_source.is_dir());
+        Ok(())
+    }
+
+    #[test]
+    fn test_propagation_mount_to_container() -> Result<()> {
+        let tmp_dir = create_temp_dir("test_propagation_mount_to_container")?;
+        let source = tmp_dir.path().join("data");
+        fs::create_dir_all(&source)?;
+
+        let m = Mount::new();
+        let mount = &SpecMountBuilder::default()
+            .destination(PathBuf::from("/data"))
+            .typ("bind")
+            .source(&source)
+            .options(vec!["rbind".to_string(), "rshared".to_string()])
+            .build()?;
+        let (flags, data) = parse_mount(mount);
+        m.mount_into_container(mount, tmp_dir.path(), flags, &data, None, None)?;
+
+        let want = vec![
+            MountArgs {
+                source: Some(canonicalize(&source)?),
+                target: tmp_dir.path().join("data"),
+                fstype: Some("bind".to_string()),
+                flags: MsFlags::MS_BIND | MsFlags::MS_REC,
+                data: Some("".to_string()),
+            },
+            MountArgs {
+                source: None,
+                target: tmp_dir.path().join("data"),
+                fstype: None,
+                flags: MsFlags::MS_SHARED | MsFlags::MS_REC,
+                data: None,
+            },
+        ];
+        let got = m
+            .syscall
+            .as_any()
+            .downcast_ref::<TestHelperSyscall>()
+            .unwrap()
+            .get_mount_args();
+        assert_eq!(want, got);
+        Ok(())
+    }
+
+    #[test]
+    fn test_overlay_mount_in_user_ns() -> Result<()> {
+        let tmp_dir = create_temp_dir("test_overlay_mount_in_user_ns")?;
+        let rootfs = tmp_dir.path().join("rootfs");
+        let state_dir = tmp_dir.path().join("state");
+        let lower = tmp_dir.path().join("lower");
+        fs::create_dir_all(&rootfs)?;
+        fs::create_dir_all(&lower)?;
+
+        let m = Mount::new();
+        let mount = &SpecMountBuilder::default()
+            .destination(PathBuf::from("/data"))
+            .typ("overlay")
+            .source(PathBuf::from("overlay"))
+            .options(vec![
+                format!("lowerdir={}", lower.display()),
+                "upperdir=upper".to_string(),
+                "workdir=work".to_string(),
+                "createdirs".to_string(),
+            ])
+            .build()?;
+        let options = MountOptions {
+            root: &rootfs,
+            label: None,
+            cgroup_ns: false,
+            create_missing_bind_sources: false,
+            state_dir: Some(&state_dir),
+            user_ns: true,
+            idmapped_mounts: None,
+        };
+        m.setup_mount(mount, &options)?;
+
+        assert!(state_dir.join("upper").is_dir());
+        assert!(state_dir.join("work").is_dir());
+        let want = vec![MountArgs {
+            source: Some(PathBuf::from("overlay")),
+            target: rootfs.join("data"),
+            fstype: Some("overlay".to_string()),
+            flags: MsFlags::empty(),
+            data: Some(format!(
+                "lowerdir={},upperdir={},workdir={},userxattr",
+                lower.display(),
+                state_dir.join("upper").display(),
+                state_dir.join("work").display()
+            )),
+        }];
+        let got = m
+            .syscall
+            .as_any()
+            .downcast_ref::<TestHelperSyscall>()
+            .unwrap()
+            .get_mount_args();
+        assert_eq!(want, got);
+
+        let mount = &SpecMountBuilder::default()
+            .destination(PathBuf::from("/data"))
+            .typ("overlay")
+            .source(PathBuf::from("overlay"))
+            .options(vec!["upperdir=/upper".to_string()])
+            .build()?;
+        assert!(m.setup_mount(mount, &options).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_is_named_hierarchy() {
+        let process_cgroups: HashMap<String, String> = [
+            ("cpu,cpuacct", "/youki"),
+            ("name=systemd", "/youki"),
+            ("name=openrc", "/youki"),
+        ]
+        .iter()
+        .map(|(k, v)| (k.to_string(), v.to_string()))
+        .collect();
+
+        assert!(is_named_hierarchy("systemd", &process_cgroups));
+        assert!(is_named_hierarchy("openrc", &process_cgroups));
+        assert!(!is_named_hierarchy("cpu,cpuacct", &process_cgroups));
+        assert!(!is_named_hierarchy("memory", &process_cgroups));
+    }
+
     #[test]
     fn test_make_parent_mount_private() {
         let tmp_dir = create_temp_dir("test_make_parent_mount_private").unwrap();
@@ -566,6 +1308,10 @@ mod tests {
             root: tmp.path(),
             label: None,
             cgroup_ns: true,
+            create_missing_bind_sources: false,
+            state_dir: None,
+            user_ns: false,
+            idmapped_mounts: None,
         };
 
         let subsystem_name = "cpu";
@@ -617,6 +1363,10 @@ mod tests {
             root: tmp.path(),
             label: None,
             cgroup_ns: false,
+            create_missing_bind_sources: false,
+            state_dir: None,
+            user_ns: false,
+            idmapped_mounts: None,
         };
 
         let subsystem_name = "cpu";
@@ -674,6 +1424,10 @@ mod tests {
             root: tmp.path(),
             label: None,
             cgroup_ns: true,
+            create_missing_bind_sources: false,
+            state_dir: None,
+            user_ns: false,
+            idmapped_mounts: None,
         };
 
         let mounter = Mount::new();
@@ -743,6 +1497,10 @@ mod tests {
             root: tmp.path(),
             label: None,
             cgroup_ns: true,
+            create_missing_bind_sources: false,
+            state_dir: None,
+            user_ns: false,
+            idmapped_mounts: None,
         };
 
         let mounter = Mount::new();
@@ -774,4 +1532,47 @@ mod tests {
 
         Ok(())
     }
+
+    #[test]
+    fn test_mount_cgroup_v2_without_cgroup_ns() -> Result<()> {
+        // the cgroup of the process is only found on hosts with cgroup v2
+        if !matches!(libcgroups::common::get_cgroup_setup()?, Unified) {
+            return Ok(());
+        }
+
+        let tmp = create_temp_dir("test_mount_cgroup_v2_without_cgroup_ns")?;
+        let container_cgroup = PathBuf::from("/sys/fs/cgroup");
+        let spec_cgroup_mount = SpecMountBuilder::default()
+            .destination(&container_cgroup)
+            .source("cgroup")
+            .typ("cgroup")
+            .build()?;
+        let mount_opts = MountOptions {
+            root: tmp.path(),
+            label: None,
+            cgroup_ns: false,
+            create_missing_bind_sources: false,
+            state_dir: None,
+            user_ns: false,
+            idmapped_mounts: None,
+        };
+
+        let mounter = Mount::new();
+        let flags = MsFlags::MS_NOEXEC | MsFlags::MS_NOSUID | MsFlags::MS_NODEV;
+        mounter.mount_cgroup_v2(&spec_cgroup_mount, &mount_opts, flags, "")?;
+
+        let got = mounter
+            .syscall
+            .as_any()
+            .downcast_ref::<TestHelperSyscall>()
+            .unwrap()
+            .get_mount_args();
+        assert_eq!(got.len(), 2);
+        assert_eq!(got[0].fstype, Some("bind".to_owned()));
+        assert_eq!(got[0].target, tmp.join_safely(&container_cgroup)?);
+        assert!(got[1]
+            .flags
+            .contains(MsFlags::MS_RDONLY | MsFlags::MS_REMOUNT));
+        Ok(())
+    }
 }
@@ -0,0 +1,248 @@
+//! Mounts of the overlay filesystem, which combine one or more read only lower
+//! directories and an optional writable upper directory. The directories are
+//! passed to the kernel through the `lowerdir`, `upperdir` and `workdir`
+//! options of the mount (see Documentation/filesystems/overlayfs.rst).
+use anyhow::{bail, Context, Result};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+/// Mount option, which creates missing upper and work directories. Relative
+/// directories are created in the state directory of the container.
+pub const CREATE_DIRS_OPTION: &str = "createdirs";
+
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct OverlayOptions {
+    pub lowerdirs: Vec<PathBuf>,
+    pub upperdir: Option<PathBuf>,
+    pub workdir: Option<PathBuf>,
+    /// Options which are passed to the kernel as they are
+    pub other: Vec<String>,
+}
+
+impl OverlayOptions {
+    /// Parses the data of an overlay mount. Commas and colons in paths have
+    /// to be escaped with a backslash, like for the kernel.
+    pub fn parse(data: &str) -> Self {
+        let mut options = Self::default();
+        for option in split_escaped(data, ',') {
+            match option.split_once('=') {
+                Some(("lowerdir", dirs)) => {
+                    options.lowerdirs =
+                        split_escaped(dirs, ':').into_iter().map(unescape).collect();
+                }
+                Some(("upperdir", dir)) => options.upperdir = Some(unescape(dir)),
+                Some(("workdir", dir)) => options.workdir = Some(unescape(dir)),
+                _ if option.is_empty() => {}
+                _ => options.other.push(option.to_owned()),
+            }
+        }
+
+        options
+    }
+
+    /// Checks that the combination of directories can be mounted
+    pub fn validate(&self) -> Result<()> {
+        if self.lowerdirs.is_empty() || self.lowerdirs.iter().any(|dir| dir.as_os_str().is_empty())
+        {
+            bail!("overlay mounts require at least one lowerdir");
+        }
+
+        match (&self.upperdir, &self.workdir) {
+            (Some(upperdir), Some(workdir)) => {
+                if workdir.starts_with(upperdir) || upperdir.starts_with(workdir) {
+                    bail!(
+                        "workdir {:?} and upperdir {:?} of overlay mount must not be nested",
+                        workdir,
+                        upperdir
+                    );
+                }
+            }
+            (Some(_), None) => bail!("overlay mounts with upperdir require a workdir"),
+            (None, Some(_)) => bail!("overlay mounts with workdir require an upperdir"),
+            (None, None) => {}
+        }
+
+        Ok(())
+    }
+
+    /// Creates the upper and work directories if they do not exist. Relative
+    /// directories are resolved against the state directory.
+    pub fn create_dirs(&mut self, state_dir: Option<&Path>) -> Result<()> {
+        for dir in [&mut self.upperdir, &mut self.workdir]
+            .into_iter()
+            .flatten()
+        {
+            if dir.is_relative() {
+                let state_dir = state_dir.with_context(|| {
+                    format!("relative overlay directory {:?} requires a state dir", dir)
+                })?;
+                *dir = state_dir.join(&*dir);
+            }
+
+            fs::create_dir_all(&dir)
+                .with_context(|| format!("failed to create overlay directory {:?}", dir))?;
+        }
+
+        Ok(())
+    }
+
+    /// Formats the options as data of the mount
+    pub fn to_data(&self) -> String {
+        let mut data = Vec::new();
+        let lowerdirs: Vec<String> = self.lowerdirs.iter().map(|dir| escape(dir)).collect();
+        data.push(format!("lowerdir={}", lowerdirs.join(":")));
+        if let Some(upperdir) = &self.upperdir {
+            data.push(format!("upperdir={}", escape(upperdir)));
+        }
+        if let Some(workdir) = &self.workdir {
+            data.push(format!("workdir={}", escape(workdir)));
+        }
+        data.extend(self.other.iter().cloned());
+        data.join(",")
+    }
+}
+
+/// Prepares the data of an overlay mount. In a user namespace, the extended
+/// attributes of overlayfs are stored in the user namespace instead of the
+/// trusted one, which is only writable with CAP_SYS_ADMIN in the initial user
+/// namespace. Mounting overlayfs in a user namespace requires Linux 5.11.
+pub fn prepare_overlay_data(
+    data: &str,
+    create_dirs: bool,
+    state_dir: Option<&Path>,
+    user_ns: bool,
+) -> Result<String> {
+    let mut options = OverlayOptions::parse(data);
+    options.validate()?;
+    if create_dirs {
+        options.create_dirs(state_dir)?;
+    }
+
+    if user_ns && !options.other.iter().any(|option| option == "userxattr") {
+        options.other.push("userxattr".to_owned());
+    }
+
+    Ok(options.to_data())
+}
+
+/// Splits at the separator, unless it is escaped with a backslash
+fn split_escaped(s: &str, separator: char) -> Vec<&str> {
+    let mut parts = Vec::new();
+    let mut start = 0;
+    let mut escaped = false;
+    for (i, c) in s.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' => escaped = true,
+            c if c == separator => {
+                parts.push(&s[start..i]);
+                start = i + c.len_utf8();
+            }
+            _ => {}
+        }
+    }
+    parts.push(&s[start..]);
+    parts
+}
+
+fn unescape(s: &str) -> PathBuf {
+    let mut unescaped = String::with_capacity(s.len());
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => unescaped.extend(chars.next()),
+            c => unescaped.push(c),
+        }
+    }
+    PathBuf::from(unescaped)
+}
+
+fn escape(path: &Path) -> String {
+    let mut escaped = String::new();
+    for c in path.to_string_lossy().chars() {
+        if matches!(c, '\\' | ',' | ':') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::create_temp_dir;
+
+    #[test]
+    fn test_parse_overlay_options() {
+        let options =
+            OverlayOptions::parse(r"lowerdir=/a:/b\:c,upperdir=/up\,per,workdir=/work,index=off");
+        assert_eq!(
+            options,
+            OverlayOptions {
+                lowerdirs: vec![PathBuf::from("/a"), PathBuf::from("/b:c")],
+                upperdir: Some(PathBuf::from("/up,per")),
+                workdir: Some(PathBuf::from("/work")),
+                other: vec!["index=off".to_owned()],
+            }
+        );
+        assert_eq!(
+            options.to_data(),
+            r"lowerdir=/a:/b\:c,upperdir=/up\,per,workdir=/work,index=off"
+        );
+    }
+
+    #[test]
+    fn test_validate_overlay_options() -> Result<()> {
+        OverlayOptions::parse("lowerdir=/a").validate()?;
+        OverlayOptions::parse("lowerdir=/a,upperdir=/up,workdir=/work").validate()?;
+        assert!(OverlayOptions::parse("upperdir=/up,workdir=/work")
+            .validate()
+            .is_err());
+        assert!(OverlayOptions::parse("lowerdir=/a::/b").validate().is_err());
+        assert!(OverlayOptions::parse("lowerdir=/a,upperdir=/up")
+            .validate()
+            .is_err());
+        assert!(OverlayOptions::parse("lowerdir=/a,workdir=/work")
+            .validate()
+            .is_err());
+        assert!(
+            OverlayOptions::parse("lowerdir=/a,upperdir=/up,workdir=/up/work")
+                .validate()
+                .is_err()
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_prepare_overlay_data() -> Result<()> {
+        let tmp = create_temp_dir("test_prepare_overlay_data")?;
+        let data = prepare_overlay_data(
+            "lowerdir=/a,upperdir=upper,workdir=work",
+            true,
+            Some(&tmp),
+            true,
+        )?;
+        assert!(tmp.join("upper").is_dir());
+        assert!(tmp.join("work").is_dir());
+        assert_eq!(
+            data,
+            format!(
+                "lowerdir=/a,upperdir={},workdir={},userxattr",
+                tmp.join("upper").display(),
+                tmp.join("work").display()
+            )
+        );
+
+        assert!(
+            prepare_overlay_data("lowerdir=/a,upperdir=up,workdir=wd", true, None, false).is_err()
+        );
+        assert_eq!(
+            prepare_overlay_data("lowerdir=/a,userxattr", false, None, true)?,
+            "lowerdir=/a,userxattr"
+        );
+        Ok(())
+    }
+}
@@ -1,5 +1,6 @@
 use super::{
     device::Device,
+    idmap::IdmappedMounts,
     mount::{Mount, MountOptions},
     symlink::Symlink,
     utils::default_devices,
@@ -7,9 +8,21 @@ use super::{
 use crate::syscall::{syscall::create_syscall, Syscall};
 use anyhow::{bail, Context, Result};
 use nix::mount::MsFlags;
-use oci_spec::runtime::{Linux, Spec};
+use oci_spec::runtime::{Linux, LinuxDevice, Spec};
 use std::path::Path;
 
+/// Returns the devices of the container, which are the default devices and
+/// the devices of the spec. Devices of the spec replace default devices with
+/// the same path.
+fn container_devices(linux: &Linux) -> Vec<LinuxDevice> {
+    let added_devices = linux.devices().as_deref().unwrap_or_default();
+    default_devices()
+        .into_iter()
+        .filter(|default| added_devices.iter().all(|dev| dev.path() != default.path()))
+        .chain(added_devices.iter().cloned())
+        .collect()
+}
+
 /// Holds information about rootfs
 pub struct RootFS {
     syscall: Box<dyn Syscall>,
@@ -28,14 +41,18 @@ impl RootFS {
         }
     }
 
+    #[tracing::instrument(level = "debug", skip_all, name = "mounts")]
     pub fn prepare_rootfs(
         &self,
         spec: &Spec,
         rootfs: &Path,
-        bind_devices: bool,
+        user_ns: bool,
         cgroup_ns: bool,
+        create_missing_bind_sources: bool,
+        state_dir: Option<&Path>,
+        idmapped_mounts: Option<&IdmappedMounts>,
     ) -> Result<()> {
-        log::debug!("Prepare rootfs: {:?}", rootfs);
+        tracing::debug!("Prepare rootfs: {:?}", rootfs);
         let mut flags = MsFlags::MS_REC;
         let linux = spec.linux().as_ref().context("no linux in spec")?;
 
@@ -56,7 +73,7 @@ impl RootFS {
             .make_parent_mount_private(rootfs)
             .context("failed to change parent mount of rootfs private")?;
 
-        log::debug!("mount root fs {:?}", rootfs);
+        tracing::debug!("mount root fs {:?}", rootfs);
         self.syscall.mount(
             Some(rootfs),
             rootfs,
@@ -69,6 +86,10 @@ impl RootFS {
             root: rootfs,
             label: linux.mount_label().as_deref(),
             cgroup_ns,
+            create_missing_bind_sources,
+            state_dir,
+            user_ns,
+            idmapped_mounts,
         };
 
         if let Some(mounts) = spec.mounts() {
@@ -87,16 +108,11 @@ impl RootFS {
             .setup_default_symlinks(rootfs)
             .context("failed to setup default symlinks")?;
 
+        // devices are bind mounted if the container has a user namespace
         let devicer = Device::new();
-        if let Some(added_devices) = linux.devices() {
-            devicer.create_devices(
-                rootfs,
-                default_devices().iter().chain(added_devices),
-                bind_devices,
-            )
-        } else {
-            devicer.create_devices(rootfs, &default_devices(), bind_devices)
-        }?;
+        devicer
+            .create_devices(rootfs, &container_devices(linux), user_ns)
+            .context("failed to create devices")?;
 
         symlinker.setup_ptmx(rootfs)?;
         Ok(())
@@ -112,7 +128,7 @@ impl RootFS {
         };
 
         if let Some(flags) = flags {
-            log::debug!("make root mount {:?}", flags);
+            tracing::debug!("make root mount {:?}", flags);
             self.syscall
                 .mount(None, Path::new("/"), None, flags, None)?;
         }
@@ -120,3 +136,45 @@ impl RootFS {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use oci_spec::runtime::{LinuxBuilder, LinuxDeviceBuilder, LinuxDeviceType};
+    use std::path::PathBuf;
+
+    #[test]
+    fn test_container_devices() -> Result<()> {
+        let linux = LinuxBuilder::default().build()?;
+        assert_eq!(container_devices(&linux), default_devices());
+
+        let null = LinuxDeviceBuilder::default()
+            .path(PathBuf::from("/dev/null"))
+            .typ(LinuxDeviceType::C)
+            .major(1)
+            .minor(3)
+            .file_mode(0o666u32)
+            .build()?;
+        let fuse = LinuxDeviceBuilder::default()
+            .path(PathBuf::from("/dev/fuse"))
+            .typ(LinuxDeviceType::C)
+            .major(10)
+            .minor(229)
+            .build()?;
+        let linux = LinuxBuilder::default()
+            .devices(vec![null.clone(), fuse.clone()])
+            .build()?;
+
+        let devices = container_devices(&linux);
+        assert_eq!(devices.len(), default_devices().len() + 1);
+        assert_eq!(
+            devices
+                .iter()
+                .filter(|dev| dev.path() == Path::new("/dev/null"))
+                .collect::<Vec<_>>(),
+            vec![&null]
+        );
+        assert!(devices.contains(&fuse));
+        Ok(())
+    }
+}
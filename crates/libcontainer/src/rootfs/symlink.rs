@@ -57,7 +57,7 @@ impl Symlink {
     pub fn setup_kcore_symlink(&self, rootfs: &Path) -> Result<()> {
         if Path::new("/proc/kcore").exists() {
             self.syscall
-                .symlink(Path::new("/proc/kcore"), &rootfs.join("dev/kcore"))
+                .symlink(Path::new("/proc/kcore"), &rootfs.join("dev/core"))
                 .context("Failed to symlink kcore")?;
         }
         Ok(())
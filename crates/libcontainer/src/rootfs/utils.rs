@@ -1,9 +1,16 @@
-use anyhow::{anyhow, Result};
+use anyhow::{anyhow, bail, Result};
 use nix::{mount::MsFlags, sys::stat::SFlag, NixPath};
 use oci_spec::runtime::{LinuxDevice, LinuxDeviceBuilder, LinuxDeviceType, Mount};
 use procfs::process::MountInfo;
 use std::path::{Path, PathBuf};
 
+use super::{idmap::IDMAP_OPTION, overlay::CREATE_DIRS_OPTION};
+use crate::syscall::linux::{
+    MOUNT_ATTR_NOATIME, MOUNT_ATTR_NODEV, MOUNT_ATTR_NODIRATIME, MOUNT_ATTR_NOEXEC,
+    MOUNT_ATTR_NOSUID, MOUNT_ATTR_NOSYMFOLLOW, MOUNT_ATTR_RDONLY, MOUNT_ATTR_RELATIME,
+    MOUNT_ATTR_STRICTATIME, MOUNT_ATTR__ATIME,
+};
+
 pub fn default_devices() -> Vec<LinuxDevice> {
     vec![
         LinuxDeviceBuilder::default()
@@ -93,18 +100,22 @@ pub fn parse_mount(m: &Mount) -> (MsFlags, String) {
                 "nodiratime" => Some((false, MsFlags::MS_NODIRATIME)),
                 "bind" => Some((false, MsFlags::MS_BIND)),
                 "rbind" => Some((false, MsFlags::MS_BIND | MsFlags::MS_REC)),
-                "unbindable" => Some((false, MsFlags::MS_UNBINDABLE)),
-                "runbindable" => Some((false, MsFlags::MS_UNBINDABLE | MsFlags::MS_REC)),
-                "private" => Some((true, MsFlags::MS_PRIVATE)),
-                "rprivate" => Some((true, MsFlags::MS_PRIVATE | MsFlags::MS_REC)),
-                "shared" => Some((true, MsFlags::MS_SHARED)),
-                "rshared" => Some((true, MsFlags::MS_SHARED | MsFlags::MS_REC)),
-                "slave" => Some((true, MsFlags::MS_SLAVE)),
-                "rslave" => Some((true, MsFlags::MS_SLAVE | MsFlags::MS_REC)),
+                // the propagation type can only be changed by a separate mount
+                // call, see parse_mount_propagation
+                o if propagation_flags(o).is_some() => Some((false, MsFlags::empty())),
                 "relatime" => Some((true, MsFlags::MS_RELATIME)),
                 "norelatime" => Some((true, MsFlags::MS_RELATIME)),
                 "strictatime" => Some((true, MsFlags::MS_STRICTATIME)),
                 "nostrictatime" => Some((true, MsFlags::MS_STRICTATIME)),
+                // idmapped mounts are created before the container process, see
+                // idmap::IdmappedMounts
+                o if o.split('=').next() == Some(IDMAP_OPTION) => Some((false, MsFlags::empty())),
+                // handled separately, see Mount::mount_into_container
+                "tmpcopyup" => Some((false, MsFlags::empty())),
+                // handled separately, see overlay::prepare_overlay_data
+                CREATE_DIRS_OPTION => Some((false, MsFlags::empty())),
+                // handled separately through mount_setattr, see parse_mount_attrs
+                o if recursive_mount_attr(o).is_some() => Some((false, MsFlags::empty())),
                 _ => None,
             } {
                 if is_clear {
@@ -120,6 +131,146 @@ pub fn parse_mount(m: &Mount) -> (MsFlags, String) {
     (flags, data.join(","))
 }
 
+fn propagation_flags(option: &str) -> Option<MsFlags> {
+    match option {
+        "private" => Some(MsFlags::MS_PRIVATE),
+        "rprivate" => Some(MsFlags::MS_PRIVATE | MsFlags::MS_REC),
+        "shared" => Some(MsFlags::MS_SHARED),
+        "rshared" => Some(MsFlags::MS_SHARED | MsFlags::MS_REC),
+        "slave" => Some(MsFlags::MS_SLAVE),
+        "rslave" => Some(MsFlags::MS_SLAVE | MsFlags::MS_REC),
+        "unbindable" => Some(MsFlags::MS_UNBINDABLE),
+        "runbindable" => Some(MsFlags::MS_UNBINDABLE | MsFlags::MS_REC),
+        _ => None,
+    }
+}
+
+/// Parses the propagation type of the mount, which is applied after the
+/// mount has been created. A mount can only have one propagation type.
+pub fn parse_mount_propagation(m: &Mount) -> Result<Option<MsFlags>> {
+    let mut propagation = None;
+    if let Some(options) = m.options() {
+        for option in options {
+            let flags = match propagation_flags(option) {
+                Some(flags) => flags,
+                None => continue,
+            };
+
+            match propagation {
+                Some(current) if current != flags => bail!(
+                    "conflicting propagation options of mount {:?}: {:?}",
+                    m.destination(),
+                    options
+                ),
+                _ => propagation = Some(flags),
+            }
+        }
+    }
+
+    Ok(propagation)
+}
+
+/// Mount attributes which are applied to a mount and all of its submounts
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct MountAttrs {
+    pub attr_set: u64,
+    pub attr_clr: u64,
+}
+
+impl MountAttrs {
+    pub fn is_empty(&self) -> bool {
+        self.attr_set == 0 && self.attr_clr == 0
+    }
+}
+
+/// Returns if the recursive option clears the attribute and the attribute
+fn recursive_mount_attr(option: &str) -> Option<(bool, u64)> {
+    match option {
+        "rro" => Some((false, MOUNT_ATTR_RDONLY)),
+        "rrw" => Some((true, MOUNT_ATTR_RDONLY)),
+        "rnosuid" => Some((false, MOUNT_ATTR_NOSUID)),
+        "rsuid" => Some((true, MOUNT_ATTR_NOSUID)),
+        "rnodev" => Some((false, MOUNT_ATTR_NODEV)),
+        "rdev" => Some((true, MOUNT_ATTR_NODEV)),
+        "rnoexec" => Some((false, MOUNT_ATTR_NOEXEC)),
+        "rexec" => Some((true, MOUNT_ATTR_NOEXEC)),
+        "rnodiratime" => Some((false, MOUNT_ATTR_NODIRATIME)),
+        "rdiratime" => Some((true, MOUNT_ATTR_NODIRATIME)),
+        "rnosymfollow" => Some((false, MOUNT_ATTR_NOSYMFOLLOW)),
+        "rsymfollow" => Some((true, MOUNT_ATTR_NOSYMFOLLOW)),
+        // the atime modes are exclusive, setting one replaces the current
+        // mode. atime is the default mode of the kernel, which is relatime.
+        "ratime" | "rrelatime" => Some((false, MOUNT_ATTR_RELATIME)),
+        "rnoatime" => Some((false, MOUNT_ATTR_NOATIME)),
+        "rstrictatime" => Some((false, MOUNT_ATTR_STRICTATIME)),
+        _ => None,
+    }
+}
+
+/// Parses the recursive mount options (rro, rnosuid, rnoatime, ...) of the
+/// mount, which are applied with mount_setattr(2) and AT_RECURSIVE
+pub fn parse_mount_attrs(m: &Mount) -> MountAttrs {
+    let mut attrs = MountAttrs::default();
+    let options = match m.options() {
+        Some(options) => options,
+        None => return attrs,
+    };
+
+    for option in options {
+        let (is_clear, attr) = match recursive_mount_attr(option) {
+            Some(attr) => attr,
+            None => continue,
+        };
+
+        if matches!(
+            option.as_str(),
+            "ratime" | "rrelatime" | "rnoatime" | "rstrictatime"
+        ) {
+            attrs.attr_set = (attrs.attr_set & !MOUNT_ATTR__ATIME) | attr;
+            attrs.attr_clr |= MOUNT_ATTR__ATIME;
+        } else if is_clear {
+            attrs.attr_set &= !attr;
+            attrs.attr_clr |= attr;
+        } else {
+            attrs.attr_set |= attr;
+            attrs.attr_clr &= !attr;
+        }
+    }
+
+    attrs
+}
+
+/// Returns the data of a devpts mount. The instance is always private to the
+/// container and the ptmx device is accessible by everyone, unless the
+/// options of the spec specify otherwise.
+pub fn devpts_data(data: &str) -> String {
+    let mut options: Vec<&str> = data.split(',').filter(|o| !o.is_empty()).collect();
+    if !options.contains(&"newinstance") {
+        options.insert(0, "newinstance");
+    }
+    if !options.iter().any(|o| o.starts_with("ptmxmode=")) {
+        options.push("ptmxmode=0666");
+    }
+    options.join(",")
+}
+
+/// Returns the data of a devpts mount without the gid option, or None if it
+/// does not have one
+pub fn devpts_data_without_gid(data: &str) -> Option<String> {
+    let options: Vec<&str> = data.split(',').collect();
+    if !options.iter().any(|o| o.starts_with("gid=")) {
+        return None;
+    }
+
+    Some(
+        options
+            .into_iter()
+            .filter(|o| !o.starts_with("gid="))
+            .collect::<Vec<_>>()
+            .join(","),
+    )
+}
+
 /// Find parent mount of rootfs in given mount infos
 pub fn find_parent_mount<'a>(rootfs: &Path, mount_infos: &'a [MountInfo]) -> Result<&'a MountInfo> {
     // find the longest mount point
@@ -191,6 +342,107 @@ mod tests {
         assert_eq!(SFlag::S_IFIFO, to_sflag(LinuxDeviceType::P));
     }
 
+    #[test]
+    fn test_parse_mount_propagation() -> anyhow::Result<()> {
+        let mount = MountBuilder::default()
+            .destination(PathBuf::from("/data"))
+            .typ("bind")
+            .source(PathBuf::from("/data"))
+            .options(vec!["rbind".to_string(), "rprivate".to_string()])
+            .build()?;
+        assert_eq!(
+            (MsFlags::MS_BIND | MsFlags::MS_REC, "".to_string()),
+            parse_mount(&mount)
+        );
+        assert_eq!(
+            parse_mount_propagation(&mount)?,
+            Some(MsFlags::MS_PRIVATE | MsFlags::MS_REC)
+        );
+
+        let mount = MountBuilder::default()
+            .destination(PathBuf::from("/data"))
+            .options(vec!["rbind".to_string()])
+            .build()?;
+        assert_eq!(parse_mount_propagation(&mount)?, None);
+
+        let mount = MountBuilder::default()
+            .destination(PathBuf::from("/data"))
+            .options(vec!["shared".to_string(), "slave".to_string()])
+            .build()?;
+        assert!(parse_mount_propagation(&mount).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_devpts_data() {
+        assert_eq!(devpts_data(""), "newinstance,ptmxmode=0666");
+        assert_eq!(
+            devpts_data("newinstance,ptmxmode=0620,mode=0620,gid=5"),
+            "newinstance,ptmxmode=0620,mode=0620,gid=5"
+        );
+        assert_eq!(
+            devpts_data("mode=0620,gid=5"),
+            "newinstance,mode=0620,gid=5,ptmxmode=0666"
+        );
+        assert_eq!(
+            devpts_data_without_gid("newinstance,gid=5,mode=0620"),
+            Some("newinstance,mode=0620".to_string())
+        );
+        assert_eq!(devpts_data_without_gid("newinstance"), None);
+    }
+
+    #[test]
+    fn test_parse_mount_attrs() -> anyhow::Result<()> {
+        let mount = MountBuilder::default()
+            .destination(PathBuf::from("/data"))
+            .typ("bind")
+            .source(PathBuf::from("/data"))
+            .options(vec![
+                "rbind".to_string(),
+                "rro".to_string(),
+                "rnosuid".to_string(),
+                "rexec".to_string(),
+                "rnoatime".to_string(),
+            ])
+            .build()?;
+        assert_eq!(
+            (MsFlags::MS_BIND | MsFlags::MS_REC, "".to_string()),
+            parse_mount(&mount)
+        );
+        assert_eq!(
+            MountAttrs {
+                attr_set: MOUNT_ATTR_RDONLY | MOUNT_ATTR_NOSUID | MOUNT_ATTR_NOATIME,
+                attr_clr: MOUNT_ATTR_NOEXEC | MOUNT_ATTR__ATIME,
+            },
+            parse_mount_attrs(&mount)
+        );
+
+        // the last option wins
+        let mount = MountBuilder::default()
+            .destination(PathBuf::from("/data"))
+            .options(vec![
+                "rro".to_string(),
+                "rrw".to_string(),
+                "rstrictatime".to_string(),
+                "rrelatime".to_string(),
+            ])
+            .build()?;
+        assert_eq!(
+            MountAttrs {
+                attr_set: MOUNT_ATTR_RELATIME,
+                attr_clr: MOUNT_ATTR_RDONLY | MOUNT_ATTR__ATIME,
+            },
+            parse_mount_attrs(&mount)
+        );
+
+        let mount = MountBuilder::default()
+            .destination(PathBuf::from("/data"))
+            .options(vec!["ro".to_string(), "nosuid".to_string()])
+            .build()?;
+        assert!(parse_mount_attrs(&mount).is_empty());
+        Ok(())
+    }
+
     #[test]
     fn test_parse_mount() {
         assert_eq!(
@@ -335,7 +587,7 @@ mod tests {
                     | MsFlags::MS_NOATIME
                     | MsFlags::MS_NODIRATIME
                     | MsFlags::MS_BIND
-                    | MsFlags::MS_UNBINDABLE,
+                    | MsFlags::MS_REC,
                 "".to_string()
             ),
             parse_mount(
@@ -1,64 +1,228 @@
 use crate::{namespaces::Namespaces, utils};
 use anyhow::{bail, Context, Result};
-use nix::unistd::Pid;
-use oci_spec::runtime::{Linux, LinuxIdMapping, LinuxNamespace, LinuxNamespaceType, Mount, Spec};
-use std::fs;
+use caps::Capability;
+use nix::{
+    fcntl::{self, OFlag},
+    sched::{self, CloneFlags},
+    sys::{
+        stat::Mode,
+        wait::{waitpid, WaitStatus},
+    },
+    unistd::{self, ForkResult, Pid, User},
+};
+use oci_spec::runtime::{
+    Linux, LinuxIdMapping, LinuxIdMappingBuilder, LinuxNamespace, LinuxNamespaceBuilder,
+    LinuxNamespaceType, Mount, Spec,
+};
+use std::ffi::CString;
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{Read, Write};
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::MetadataExt;
+use std::os::unix::io::FromRawFd;
 use std::path::Path;
-use std::process::Command;
+use std::process::{Command, ExitStatus};
+use std::str::FromStr;
 use std::{env, path::PathBuf};
 
 #[derive(Debug, Clone, Default)]
-pub struct Rootless<'a> {
+pub struct Rootless {
     /// Location of the newuidmap binary
     pub newuidmap: Option<PathBuf>,
     /// Location of the newgidmap binary
     pub newgidmap: Option<PathBuf>,
     /// Mappings for user ids
-    pub(crate) uid_mappings: Option<&'a Vec<LinuxIdMapping>>,
+    pub(crate) uid_mappings: Option<Vec<LinuxIdMapping>>,
     /// Mappings for group ids
-    pub(crate) gid_mappings: Option<&'a Vec<LinuxIdMapping>>,
+    pub(crate) gid_mappings: Option<Vec<LinuxIdMapping>>,
     /// Info on the user namespaces
     pub user_namespace: Option<LinuxNamespace>,
     /// Is rootless container requested by a privileged user
     pub privileged: bool,
+    /// Keep setgroups set to "allow" if the gid mapping is written by
+    /// newgidmap. Direct writes by an unprivileged user always require "deny".
+    pub allow_setgroups: bool,
+    /// Collapse multiple mappings to a single mapping of the caller if
+    /// newuidmap/newgidmap are not available instead of failing
+    pub allow_single_mapping_fallback: bool,
 }
 
-impl<'a> Rootless<'a> {
-    pub fn new(spec: &'a Spec) -> Result<Option<Rootless<'a>>> {
+impl Rootless {
+    pub fn new(spec: &Spec, mode: RootlessMode) -> Result<Option<Rootless>> {
         let linux = spec.linux().as_ref().context("no linux in spec")?;
         let namespaces = Namespaces::from(linux.namespaces().as_ref());
         let user_namespace = namespaces.get(LinuxNamespaceType::User);
 
         // If conditions requires us to use rootless, we must either create a new
         // user namespace or enter an exsiting.
-        if rootless_required() && user_namespace.is_none() {
+        if mode.is_required() && user_namespace.is_none() {
             bail!("rootless container requires valid user namespace definition");
         }
 
-        if user_namespace.is_some() && user_namespace.unwrap().path().is_none() {
-            log::debug!("rootless container should be created");
+        if let Some(path) = user_namespace.and_then(|ns| ns.path().as_ref()) {
+            tracing::debug!("rootless container should join user namespace {:?}", path);
+
+            let rootless = Rootless::from_existing_userns(path)?;
+            check_issues(validate_existing_userns(spec, &rootless)).with_context(|| {
+                format!(
+                    "the existing user namespace {:?} is not compatible with the spec",
+                    path
+                )
+            })?;
+
+            Ok(Some(rootless))
+        } else if user_namespace.is_some() {
+            tracing::debug!("rootless container should be created");
 
-            validate(spec).context("The spec failed to comply to rootless requirement")?;
             let mut rootless = Rootless::from(linux);
-            if let Some((uid_binary, gid_binary)) = lookup_map_binaries(linux)? {
+            if rootless.uid_mappings.is_none() && rootless.gid_mappings.is_none() {
+                tracing::debug!("no id mappings in spec, deriving them from subordinate id files");
+                let (uid_mappings, gid_mappings) = derive_id_mappings()
+                    .context("failed to derive id mappings from /etc/subuid and /etc/subgid")?;
+                rootless.uid_mappings = Some(uid_mappings);
+                rootless.gid_mappings = Some(gid_mappings);
+            }
+
+            check_issues(validate(
+                spec,
+                rootless.uid_mappings.as_deref(),
+                rootless.gid_mappings.as_deref(),
+            ))
+            .context("The spec failed to comply to rootless requirement")?;
+            if let Some((uid_binary, gid_binary)) = lookup_map_binaries(
+                rootless.uid_mappings.as_deref(),
+                rootless.gid_mappings.as_deref(),
+            )? {
                 rootless.newuidmap = Some(uid_binary);
                 rootless.newgidmap = Some(gid_binary);
             }
 
             Ok(Some(rootless))
         } else {
-            log::debug!("This is NOT a rootless container");
+            tracing::debug!("This is NOT a rootless container");
             Ok(None)
         }
     }
 
+    /// Creates the rootless configuration for joining the existing user
+    /// namespace at path. The id mappings are read from the namespace itself.
+    pub fn from_existing_userns<P: AsRef<Path>>(path: P) -> Result<Rootless> {
+        let path = path.as_ref();
+        let (uid_mappings, gid_mappings) = read_userns_mappings(path)
+            .with_context(|| format!("failed to read id mappings of user namespace {:?}", path))?;
+        let user_namespace = LinuxNamespaceBuilder::default()
+            .typ(LinuxNamespaceType::User)
+            .path(path)
+            .build()?;
+
+        Ok(Rootless {
+            newuidmap: None,
+            newgidmap: None,
+            uid_mappings: Some(uid_mappings),
+            gid_mappings: Some(gid_mappings),
+            user_namespace: Some(user_namespace),
+            privileged: is_real_root(),
+            allow_setgroups: false,
+            allow_single_mapping_fallback: false,
+        })
+    }
+
+    /// Returns true if an existing user namespace is joined instead of a new
+    /// one being created. The id mappings of an existing user namespace are
+    /// already in place and must not be written again.
+    pub fn joins_existing_userns(&self) -> bool {
+        self.user_namespace
+            .as_ref()
+            .map(|ns| ns.path().is_some())
+            .unwrap_or(false)
+    }
+
+    /// Mappings for user ids
+    pub fn uid_mappings(&self) -> Option<&[LinuxIdMapping]> {
+        self.uid_mappings.as_deref()
+    }
+
+    /// Mappings for group ids
+    pub fn gid_mappings(&self) -> Option<&[LinuxIdMapping]> {
+        self.gid_mappings.as_deref()
+    }
+
+    /// Returns the id mappings that will actually be written for the container,
+    /// i.e. after a possible fallback to a single mapping has been applied
+    pub fn effective_mappings(&self) -> Result<EffectiveMappings> {
+        let uid = match &self.uid_mappings {
+            Some(mappings) => self
+                .mappings_or_fallback(
+                    mappings,
+                    self.newuidmap.as_deref(),
+                    nix::unistd::geteuid().as_raw(),
+                )?
+                .into_owned(),
+            None => Vec::new(),
+        };
+        let gid = match &self.gid_mappings {
+            Some(mappings) => self
+                .mappings_or_fallback(
+                    mappings,
+                    self.newgidmap.as_deref(),
+                    nix::unistd::getegid().as_raw(),
+                )?
+                .into_owned(),
+            None => Vec::new(),
+        };
+
+        Ok(EffectiveMappings { uid, gid })
+    }
+
+    /// Returns the host uid that the container uid is mapped to, if any
+    pub fn host_uid_for(&self, container_uid: u32) -> Result<Option<u32>> {
+        Ok(self.effective_mappings()?.host_uid_for(container_uid))
+    }
+
+    /// Returns the host gid that the container gid is mapped to, if any
+    pub fn host_gid_for(&self, container_gid: u32) -> Result<Option<u32>> {
+        Ok(self.effective_mappings()?.host_gid_for(container_gid))
+    }
+
+    /// Multiple mappings can only be written with the help of newuidmap and
+    /// newgidmap. If these are not available and the fallback is allowed, the
+    /// mappings are collapsed to a single mapping of the caller.
+    fn mappings_or_fallback<'m>(
+        &self,
+        mappings: &'m [LinuxIdMapping],
+        map_binary: Option<&Path>,
+        host_id: u32,
+    ) -> Result<Cow<'m, [LinuxIdMapping]>> {
+        if mappings.len() <= 1 || map_binary.is_some() || !self.allow_single_mapping_fallback {
+            return Ok(Cow::Borrowed(mappings));
+        }
+
+        tracing::warn!(
+            "newuidmap/newgidmap are not available, falling back to a single mapping of \
+            container id 0 to host id {} instead of {:?}",
+            host_id,
+            mappings
+        );
+        Ok(Cow::Owned(vec![LinuxIdMappingBuilder::default()
+            .container_id(0u32)
+            .host_id(host_id)
+            .size(1u32)
+            .build()?]))
+    }
+
     pub fn write_uid_mapping(&self, target_pid: Pid) -> Result<()> {
-        log::debug!("Write UID mapping for {:?}", target_pid);
-        if let Some(uid_mappings) = self.uid_mappings {
+        tracing::debug!("Write UID mapping for {:?}", target_pid);
+        if let Some(uid_mappings) = &self.uid_mappings {
+            let uid_mappings = self.mappings_or_fallback(
+                uid_mappings,
+                self.newuidmap.as_deref(),
+                nix::unistd::geteuid().as_raw(),
+            )?;
             write_id_mapping(
                 target_pid,
                 &format!("/proc/{}/uid_map", target_pid),
-                uid_mappings,
+                &uid_mappings,
                 self.newuidmap.as_deref(),
             )
         } else {
@@ -67,12 +231,25 @@ impl<'a> Rootless<'a> {
     }
 
     pub fn write_gid_mapping(&self, target_pid: Pid) -> Result<()> {
-        log::debug!("Write GID mapping for {:?}", target_pid);
-        if let Some(gid_mappings) = self.gid_mappings {
+        tracing::debug!("Write GID mapping for {:?}", target_pid);
+        if let Some(gid_mappings) = &self.gid_mappings {
+            let gid_mappings = self.mappings_or_fallback(
+                gid_mappings,
+                self.newgidmap.as_deref(),
+                nix::unistd::getegid().as_raw(),
+            )?;
+            let uses_newgidmap = self.newgidmap.is_some();
+            if !self.privileged && !(uses_newgidmap && self.allow_setgroups) {
+                // The main process is running as an unprivileged user and cannot write the mapping
+                // until "deny" has been written to setgroups. See CVE-2014-8989.
+                utils::write_file(format!("/proc/{}/setgroups", target_pid), "deny")
+                    .with_context(|| format!("failed to disable setgroups for {}", target_pid))?;
+            }
+
             return write_id_mapping(
                 target_pid,
                 &format!("/proc/{}/gid_map", target_pid),
-                gid_mappings,
+                &gid_mappings,
                 self.newgidmap.as_deref(),
             );
         } else {
@@ -81,18 +258,263 @@ impl<'a> Rootless<'a> {
     }
 }
 
-impl<'a> From<&'a Linux> for Rootless<'a> {
-    fn from(linux: &'a Linux) -> Self {
+impl From<&Linux> for Rootless {
+    fn from(linux: &Linux) -> Self {
         let namespaces = Namespaces::from(linux.namespaces().as_ref());
         let user_namespace = namespaces.get(LinuxNamespaceType::User);
         Self {
             newuidmap: None,
             newgidmap: None,
-            uid_mappings: linux.uid_mappings().as_ref(),
-            gid_mappings: linux.gid_mappings().as_ref(),
+            uid_mappings: linux.uid_mappings().clone(),
+            gid_mappings: linux.gid_mappings().clone(),
             user_namespace: user_namespace.cloned(),
-            privileged: nix::unistd::geteuid().is_root(),
+            privileged: is_real_root(),
+            allow_setgroups: false,
+            allow_single_mapping_fallback: false,
+        }
+    }
+}
+
+/// Resolved view of the id mappings of a rootless container. Files owned by
+/// a host id that is not mapped appear as the overflow id inside the container.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct EffectiveMappings {
+    /// Mappings for user ids
+    pub uid: Vec<LinuxIdMapping>,
+    /// Mappings for group ids
+    pub gid: Vec<LinuxIdMapping>,
+}
+
+impl EffectiveMappings {
+    /// Returns the host uid that the container uid is mapped to, if any
+    pub fn host_uid_for(&self, container_uid: u32) -> Option<u32> {
+        map_id(container_uid, &self.uid, |m| {
+            (m.container_id(), m.host_id())
+        })
+    }
+
+    /// Returns the host gid that the container gid is mapped to, if any
+    pub fn host_gid_for(&self, container_gid: u32) -> Option<u32> {
+        map_id(container_gid, &self.gid, |m| {
+            (m.container_id(), m.host_id())
+        })
+    }
+
+    /// Returns the uid inside the container that a file owned by the host uid
+    /// appears as, if the host uid is mapped
+    pub fn container_uid_for(&self, host_uid: u32) -> Option<u32> {
+        map_id(host_uid, &self.uid, |m| (m.host_id(), m.container_id()))
+    }
+
+    /// Returns the gid inside the container that a file owned by the host gid
+    /// appears as, if the host gid is mapped
+    pub fn container_gid_for(&self, host_gid: u32) -> Option<u32> {
+        map_id(host_gid, &self.gid, |m| (m.host_id(), m.container_id()))
+    }
+}
+
+/// Translates id from one side of the mappings to the other. The selector
+/// returns the (from, to) start of each mapping.
+fn map_id<F>(id: u32, mappings: &[LinuxIdMapping], selector: F) -> Option<u32>
+where
+    F: Fn(&LinuxIdMapping) -> (u32, u32),
+{
+    mappings.iter().find_map(|m| {
+        let (from, to) = selector(m);
+        let offset = id.checked_sub(from)?;
+        if offset < m.size() {
+            to.checked_add(offset)
+        } else {
+            None
         }
+    })
+}
+
+/// Builder for a rootless configuration that is not derived from a runtime spec
+/// # Example
+///
+/// ```no_run
+/// use libcontainer::rootless::RootlessBuilder;
+/// use oci_spec::runtime::LinuxIdMappingBuilder;
+///
+/// let mapping = LinuxIdMappingBuilder::default()
+///     .container_id(0u32)
+///     .host_id(1000u32)
+///     .size(1u32)
+///     .build()?;
+/// let rootless = RootlessBuilder::default()
+///     .with_uid_mappings(vec![mapping])
+///     .with_gid_mappings(vec![mapping])
+///     .build()?;
+/// # Ok::<(), anyhow::Error>(())
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct RootlessBuilder {
+    newuidmap: Option<PathBuf>,
+    newgidmap: Option<PathBuf>,
+    uid_mappings: Vec<LinuxIdMapping>,
+    gid_mappings: Vec<LinuxIdMapping>,
+    user_namespace: Option<LinuxNamespace>,
+    privileged: Option<bool>,
+    allow_setgroups: bool,
+    allow_single_mapping_fallback: bool,
+}
+
+impl RootlessBuilder {
+    /// Sets the location of the newuidmap binary. If multiple uid mappings
+    /// are specified and this is not set, the binary is looked up in PATH.
+    pub fn with_newuidmap<P: Into<PathBuf>>(mut self, path: P) -> Self {
+        self.newuidmap = Some(path.into());
+        self
+    }
+
+    /// Sets the location of the newgidmap binary. If multiple gid mappings
+    /// are specified and this is not set, the binary is looked up in PATH.
+    pub fn with_newgidmap<P: Into<PathBuf>>(mut self, path: P) -> Self {
+        self.newgidmap = Some(path.into());
+        self
+    }
+
+    /// Sets the mappings for user ids
+    pub fn with_uid_mappings(mut self, mappings: Vec<LinuxIdMapping>) -> Self {
+        self.uid_mappings = mappings;
+        self
+    }
+
+    /// Sets the mappings for group ids
+    pub fn with_gid_mappings(mut self, mappings: Vec<LinuxIdMapping>) -> Self {
+        self.gid_mappings = mappings;
+        self
+    }
+
+    /// Sets the user namespace. By default a new user namespace is created.
+    pub fn with_user_namespace(mut self, user_namespace: LinuxNamespace) -> Self {
+        self.user_namespace = Some(user_namespace);
+        self
+    }
+
+    /// Sets if the rootless container is requested by a privileged user. By
+    /// default this is determined from the effective user id.
+    pub fn with_privileged(mut self, privileged: bool) -> Self {
+        self.privileged = Some(privileged);
+        self
+    }
+
+    /// Sets if setgroups should be kept at "allow" when the gid mapping is
+    /// written by newgidmap
+    pub fn with_allow_setgroups(mut self, allow: bool) -> Self {
+        self.allow_setgroups = allow;
+        self
+    }
+
+    /// Sets if multiple mappings should be collapsed to a single mapping of
+    /// the caller if newuidmap/newgidmap are not available
+    pub fn with_single_mapping_fallback(mut self, allow: bool) -> Self {
+        self.allow_single_mapping_fallback = allow;
+        self
+    }
+
+    /// Creates the rootless configuration
+    pub fn build(self) -> Result<Rootless> {
+        if self.uid_mappings.is_empty() {
+            bail!("rootless containers require at least one uid mapping");
+        }
+
+        if self.gid_mappings.is_empty() {
+            bail!("rootless containers require at least one gid mapping");
+        }
+
+        validate_id_mappings("uid", &self.uid_mappings)?;
+        validate_id_mappings("gid", &self.gid_mappings)?;
+
+        let user_namespace = match self.user_namespace {
+            Some(ns) if ns.typ() != LinuxNamespaceType::User => {
+                bail!("expected a user namespace, but got {:?}", ns.typ())
+            }
+            Some(ns) => ns,
+            None => LinuxNamespaceBuilder::default()
+                .typ(LinuxNamespaceType::User)
+                .build()?,
+        };
+
+        let (mut newuidmap, mut newgidmap) = (self.newuidmap, self.newgidmap);
+        if newuidmap.is_none() || newgidmap.is_none() {
+            if let Some((uid_binary, gid_binary)) = lookup_map_binaries(
+                Some(self.uid_mappings.as_slice()),
+                Some(self.gid_mappings.as_slice()),
+            )? {
+                newuidmap = newuidmap.or(Some(uid_binary));
+                newgidmap = newgidmap.or(Some(gid_binary));
+            }
+        }
+
+        Ok(Rootless {
+            newuidmap,
+            newgidmap,
+            uid_mappings: Some(self.uid_mappings),
+            gid_mappings: Some(self.gid_mappings),
+            user_namespace: Some(user_namespace),
+            privileged: self.privileged.unwrap_or_else(is_real_root),
+            allow_setgroups: self.allow_setgroups,
+            allow_single_mapping_fallback: self.allow_single_mapping_fallback,
+        })
+    }
+}
+
+/// Determines if rootless semantics should be applied to a container
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RootlessMode {
+    /// Rootless mode is used if the runtime is not running as root or the
+    /// YOUKI_USE_ROOTLESS environment variable is set to true
+    Auto,
+    /// Rootless mode is always used, even if the runtime is running as root
+    Enabled,
+    /// Rootless mode is never used
+    Disabled,
+}
+
+impl Default for RootlessMode {
+    fn default() -> Self {
+        Self::Auto
+    }
+}
+
+impl RootlessMode {
+    /// Checks if rootless mode should be used
+    pub fn is_required(self) -> bool {
+        match self {
+            Self::Auto => rootless_required(),
+            Self::Enabled => true,
+            Self::Disabled => false,
+        }
+    }
+}
+
+impl FromStr for RootlessMode {
+    type Err = anyhow::Error;
+
+    fn from_str(mode: &str) -> Result<Self, Self::Err> {
+        match mode {
+            "auto" => Ok(Self::Auto),
+            "true" => Ok(Self::Enabled),
+            "false" => Ok(Self::Disabled),
+            _ => bail!(
+                "invalid rootless mode {}, expected one of auto, true or false",
+                mode
+            ),
+        }
+    }
+}
+
+impl fmt::Display for RootlessMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let print = match self {
+            Self::Auto => "auto",
+            Self::Enabled => "true",
+            Self::Disabled => "false",
+        };
+
+        write!(f, "{}", print)
     }
 }
 
@@ -102,9 +524,89 @@ pub fn rootless_required() -> bool {
         return true;
     }
 
+    // root inside of a user namespace (e.g. when running nested in a rootless
+    // podman container) has none of the privileges of the real root user
+    match environment() {
+        Ok(env) if env.in_user_namespace => {
+            tracing::debug!(
+                "running as root in a nested user namespace (uid mappings: {:?}, gid mappings: {:?})",
+                env.uid_mappings,
+                env.gid_mappings
+            );
+            return true;
+        }
+        Ok(_) => {}
+        Err(err) => tracing::warn!("failed to detect user namespace environment: {:?}", err),
+    }
+
     matches!(std::env::var("YOUKI_USE_ROOTLESS").as_deref(), Ok("true"))
 }
 
+/// Information about the user namespace youki itself is running in
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Environment {
+    /// True if youki is not running in the initial user namespace
+    pub in_user_namespace: bool,
+    /// Mappings for user ids of the current user namespace
+    pub uid_mappings: Vec<LinuxIdMapping>,
+    /// Mappings for group ids of the current user namespace
+    pub gid_mappings: Vec<LinuxIdMapping>,
+}
+
+impl Environment {
+    /// Returns true if the current process is the real root user, i.e. root
+    /// in the initial user namespace
+    pub fn is_real_root(&self) -> bool {
+        nix::unistd::geteuid().is_root() && !self.in_user_namespace
+    }
+}
+
+/// Probes the user namespace the current process is running in. The initial
+/// user namespace maps the complete id range onto itself, any other mapping
+/// means that the process is already running inside of a user namespace.
+pub fn environment() -> Result<Environment> {
+    let uid_map = Path::new("/proc/self/uid_map");
+    let gid_map = Path::new("/proc/self/gid_map");
+    if !uid_map.exists() {
+        // kernel has been built without user namespace support
+        return Ok(Environment {
+            in_user_namespace: false,
+            uid_mappings: Vec::new(),
+            gid_mappings: Vec::new(),
+        });
+    }
+
+    let uid_mappings = parse_id_map(
+        &fs::read_to_string(uid_map).with_context(|| format!("failed to read {:?}", uid_map))?,
+    )?;
+    let gid_mappings = parse_id_map(
+        &fs::read_to_string(gid_map).with_context(|| format!("failed to read {:?}", gid_map))?,
+    )?;
+
+    Ok(Environment {
+        in_user_namespace: !is_initial_id_map(&uid_mappings) || !is_initial_id_map(&gid_mappings),
+        uid_mappings,
+        gid_mappings,
+    })
+}
+
+/// Returns true if the runtime is running as root in the initial user
+/// namespace. Root in a nested user namespace lacks the privileges to e.g.
+/// write arbitrary id mappings, so it is treated like an unprivileged user.
+fn is_real_root() -> bool {
+    match environment() {
+        Ok(env) => env.is_real_root(),
+        Err(err) => {
+            tracing::warn!("failed to detect user namespace environment: {:?}", err);
+            nix::unistd::geteuid().is_root()
+        }
+    }
+}
+
+fn is_initial_id_map(mappings: &[LinuxIdMapping]) -> bool {
+    matches!(mappings, [m] if m.container_id() == 0 && m.host_id() == 0 && m.size() == u32::MAX)
+}
+
 pub fn unprivileged_user_ns_enabled() -> Result<bool> {
     let user_ns_sysctl = Path::new("/proc/sys/kernel/unprivileged_userns_clone");
     if !user_ns_sysctl.exists() {
@@ -121,87 +623,554 @@ pub fn unprivileged_user_ns_enabled() -> Result<bool> {
     }
 }
 
-/// Validates that the spec contains the required information for
-/// running in rootless mode
-fn validate(spec: &Spec) -> Result<()> {
-    let linux = spec.linux().as_ref().context("no linux in spec")?;
+pub(crate) const SUBUID_FILE: &str = "/etc/subuid";
+pub(crate) const SUBGID_FILE: &str = "/etc/subgid";
+
+/// Derives uid and gid mappings for the invoking user from /etc/subuid and
+/// /etc/subgid. Root inside the container is mapped to the current effective
+/// user (group) and all further ids are mapped onto the subordinate range.
+fn derive_id_mappings() -> Result<(Vec<LinuxIdMapping>, Vec<LinuxIdMapping>)> {
+    let uid = nix::unistd::geteuid();
+    let gid = nix::unistd::getegid();
+    let user_name = User::from_uid(uid)
+        .with_context(|| format!("failed to look up user {}", uid))?
+        .map(|u| u.name);
+
+    let uid_range =
+        lookup_subordinate_range(Path::new(SUBUID_FILE), user_name.as_deref(), uid.as_raw())?
+            .with_context(|| {
+                format!(
+                    "no subordinate uid range for user {} in {}",
+                    uid, SUBUID_FILE
+                )
+            })?;
+    let gid_range =
+        lookup_subordinate_range(Path::new(SUBGID_FILE), user_name.as_deref(), uid.as_raw())?
+            .with_context(|| {
+                format!(
+                    "no subordinate gid range for user {} in {}",
+                    uid, SUBGID_FILE
+                )
+            })?;
+
+    Ok((
+        build_id_mappings(uid.as_raw(), uid_range)?,
+        build_id_mappings(gid.as_raw(), gid_range)?,
+    ))
+}
+
+/// Maps container id 0 to the given host id and container ids starting
+/// from 1 to the subordinate range
+fn build_id_mappings(host_id: u32, (start, count): (u32, u32)) -> Result<Vec<LinuxIdMapping>> {
+    Ok(vec![
+        LinuxIdMappingBuilder::default()
+            .container_id(0u32)
+            .host_id(host_id)
+            .size(1u32)
+            .build()?,
+        LinuxIdMappingBuilder::default()
+            .container_id(1u32)
+            .host_id(start)
+            .size(count)
+            .build()?,
+    ])
+}
+
+/// Looks up the first subordinate id range of a user in a file with the
+/// format of /etc/subuid or /etc/subgid. Entries can either refer to the
+/// user by name or by uid.
+pub(crate) fn lookup_subordinate_range(
+    path: &Path,
+    user_name: Option<&str>,
+    uid: u32,
+) -> Result<Option<(u32, u32)>> {
+    if !path.exists() {
+        return Ok(None);
+    }
+
+    let content = fs::read_to_string(path).with_context(|| format!("failed to read {:?}", path))?;
+    Ok(parse_subordinate_ranges(&content, user_name, uid)?
+        .into_iter()
+        .find(|(_, count)| *count > 0))
+}
+
+fn parse_subordinate_ranges(
+    content: &str,
+    user_name: Option<&str>,
+    uid: u32,
+) -> Result<Vec<(u32, u32)>> {
+    let uid = uid.to_string();
+    let mut ranges = Vec::new();
+    for line in content.lines() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let parts: Vec<&str> = line.split(':').collect();
+        if parts.len() != 3 {
+            bail!("invalid subordinate id entry: {}", line);
+        }
+
+        if parts[0] != uid && Some(parts[0]) != user_name {
+            continue;
+        }
+
+        let start = parts[1]
+            .parse()
+            .with_context(|| format!("invalid subordinate id start in entry: {}", line))?;
+        let count = parts[2]
+            .parse()
+            .with_context(|| format!("invalid subordinate id count in entry: {}", line))?;
+        ranges.push((start, count));
+    }
+
+    Ok(ranges)
+}
+
+/// Reads the uid and gid mappings of the user namespace at path. If the path
+/// refers to the namespace of a process, the maps can be read from procfs
+/// directly. Otherwise a short lived child process joins the namespace and
+/// reads its own maps, which are reported relative to the parent namespace.
+fn read_userns_mappings(path: &Path) -> Result<(Vec<LinuxIdMapping>, Vec<LinuxIdMapping>)> {
+    let (uid_map, gid_map) = match proc_dir_of_userns(path) {
+        Some(proc_dir) => (
+            fs::read_to_string(proc_dir.join("uid_map"))
+                .with_context(|| format!("failed to read uid_map of {:?}", proc_dir))?,
+            fs::read_to_string(proc_dir.join("gid_map"))
+                .with_context(|| format!("failed to read gid_map of {:?}", proc_dir))?,
+        ),
+        None => read_userns_maps_in_child(path)?,
+    };
+
+    Ok((parse_id_map(&uid_map)?, parse_id_map(&gid_map)?))
+}
+
+fn proc_dir_of_userns(path: &Path) -> Option<&Path> {
+    if path.starts_with("/proc") && path.ends_with("ns/user") {
+        path.parent().and_then(|ns_dir| ns_dir.parent())
+    } else {
+        None
+    }
+}
+
+fn read_userns_maps_in_child(path: &Path) -> Result<(String, String)> {
+    let ns_fd = fcntl::open(path, OFlag::O_RDONLY | OFlag::O_CLOEXEC, Mode::empty())
+        .with_context(|| format!("failed to open user namespace {:?}", path))?;
+    let (reader, writer) = unistd::pipe()?;
+
+    match unsafe { unistd::fork()? } {
+        ForkResult::Child => {
+            let _ = unistd::close(reader);
+            let result = (|| -> Result<()> {
+                sched::setns(ns_fd, CloneFlags::CLONE_NEWUSER)?;
+                let uid_map = fs::read_to_string("/proc/self/uid_map")?;
+                let gid_map = fs::read_to_string("/proc/self/gid_map")?;
+                let mut pipe = unsafe { File::from_raw_fd(writer) };
+                write!(pipe, "{}\0{}", uid_map, gid_map)?;
+                Ok(())
+            })();
+            std::process::exit(if result.is_ok() { 0 } else { 1 });
+        }
+        ForkResult::Parent { child } => {
+            let _ = unistd::close(writer);
+            let _ = unistd::close(ns_fd);
+            let mut content = String::new();
+            unsafe { File::from_raw_fd(reader) }.read_to_string(&mut content)?;
+            match waitpid(child, None)? {
+                WaitStatus::Exited(_, 0) => {}
+                status => bail!("failed to join user namespace {:?}: {:?}", path, status),
+            }
+
+            let (uid_map, gid_map) = content
+                .split_once('\0')
+                .context("received malformed id maps from child")?;
+            Ok((uid_map.to_owned(), gid_map.to_owned()))
+        }
+    }
+}
+
+/// Parses the content of a uid_map or gid_map file
+fn parse_id_map(content: &str) -> Result<Vec<LinuxIdMapping>> {
+    content
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| {
+            let fields: Vec<u32> = line
+                .split_whitespace()
+                .map(|f| f.parse())
+                .collect::<Result<_, _>>()
+                .with_context(|| format!("invalid id map entry: {}", line))?;
+            if fields.len() != 3 {
+                bail!("invalid id map entry: {}", line);
+            }
+
+            Ok(LinuxIdMappingBuilder::default()
+                .container_id(fields[0])
+                .host_id(fields[1])
+                .size(fields[2])
+                .build()?)
+        })
+        .collect()
+}
+
+/// Validates that an existing user namespace provides the mappings that
+/// are requested by the spec. If the spec does not contain any mappings,
+/// at least the user and group of the container process need to be mapped.
+fn validate_existing_userns(spec: &Spec, rootless: &Rootless) -> Vec<RootlessIssue> {
+    let mut issues = Vec::new();
+    let uid_mappings = rootless.uid_mappings.as_deref().unwrap_or_default();
+    let gid_mappings = rootless.gid_mappings.as_deref().unwrap_or_default();
+
+    let linux = match spec.linux() {
+        Some(linux) => linux,
+        None => {
+            issues.push(RootlessIssue::error("no linux in spec"));
+            return issues;
+        }
+    };
+
+    for (requested, existing, kind) in [
+        (linux.uid_mappings(), uid_mappings, "uid"),
+        (linux.gid_mappings(), gid_mappings, "gid"),
+    ] {
+        for mapping in requested.iter().flatten() {
+            if !is_range_mapped(mapping, existing) {
+                issues.push(RootlessIssue::error(format!(
+                    "{} mapping {:?} is not provided by the user namespace, which maps {:?}",
+                    kind, mapping, existing
+                )));
+            }
+        }
+    }
+
+    if let Some(process) = spec.process() {
+        let uid = process.user().uid();
+        if !is_id_mapped(uid, uid_mappings) {
+            issues.push(RootlessIssue::error(format!(
+                "uid {} is not mapped in the user namespace",
+                uid
+            )));
+        }
+
+        let gid = process.user().gid();
+        if !is_id_mapped(gid, gid_mappings) {
+            issues.push(RootlessIssue::error(format!(
+                "gid {} is not mapped in the user namespace",
+                gid
+            )));
+        }
+    }
+
+    if let Some(mounts) = spec.mounts() {
+        validate_mounts(mounts, uid_mappings, gid_mappings, &mut issues);
+    }
+
+    issues
+}
+
+/// Checks if the complete range of mapping is mapped to the same host ids
+/// by one of the existing mappings
+fn is_range_mapped(mapping: &LinuxIdMapping, existing: &[LinuxIdMapping]) -> bool {
+    let start = u64::from(mapping.container_id());
+    let end = start + u64::from(mapping.size());
+    existing.iter().any(|m| {
+        let existing_start = u64::from(m.container_id());
+        let existing_end = existing_start + u64::from(m.size());
+        start >= existing_start
+            && end <= existing_end
+            && u64::from(m.host_id()) + (start - existing_start) == u64::from(mapping.host_id())
+    })
+}
+
+/// Severity of an issue found during the validation of a rootless container
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    /// The container can be created, but may not behave as expected
+    Warning,
+    /// The container cannot be created
+    Error,
+}
+
+/// A problem found during the validation of a rootless container
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RootlessIssue {
+    pub severity: Severity,
+    pub message: String,
+}
+
+impl RootlessIssue {
+    fn error<S: Into<String>>(message: S) -> Self {
+        Self {
+            severity: Severity::Error,
+            message: message.into(),
+        }
+    }
+
+    fn warning<S: Into<String>>(message: S) -> Self {
+        Self {
+            severity: Severity::Warning,
+            message: message.into(),
+        }
+    }
+
+    pub fn is_error(&self) -> bool {
+        self.severity == Severity::Error
+    }
+}
+
+impl fmt::Display for RootlessIssue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.severity {
+            Severity::Warning => write!(f, "warning: {}", self.message),
+            Severity::Error => write!(f, "error: {}", self.message),
+        }
+    }
+}
+
+/// Returned if the validation of a rootless container found fatal issues.
+/// Contains all fatal issues, not only the first one.
+#[derive(Debug)]
+pub struct RootlessValidationError {
+    pub issues: Vec<RootlessIssue>,
+}
+
+impl std::error::Error for RootlessValidationError {}
+
+impl fmt::Display for RootlessValidationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} rootless issue(s) found", self.issues.len())?;
+        for issue in &self.issues {
+            write!(f, "\n  {}", issue)?;
+        }
+        Ok(())
+    }
+}
+
+/// Logs all warnings and fails if any of the issues is an error
+fn check_issues(issues: Vec<RootlessIssue>) -> Result<()> {
+    let (errors, warnings): (Vec<_>, Vec<_>) = issues.into_iter().partition(|i| i.is_error());
+    for warning in warnings {
+        tracing::warn!("{}", warning.message);
+    }
+
+    if !errors.is_empty() {
+        return Err(RootlessValidationError { issues: errors }.into());
+    }
+
+    Ok(())
+}
+
+/// Validates the spec against the requirements of a rootless container and
+/// returns all issues that were found. Issues with a severity of warning do
+/// not prevent the creation of the container.
+pub fn validate(
+    spec: &Spec,
+    uid_mappings: Option<&[LinuxIdMapping]>,
+    gid_mappings: Option<&[LinuxIdMapping]>,
+) -> Vec<RootlessIssue> {
+    let mut issues = Vec::new();
+    let linux = match spec.linux() {
+        Some(linux) => linux,
+        None => {
+            issues.push(RootlessIssue::error("no linux in spec"));
+            return issues;
+        }
+    };
+
     let namespaces = Namespaces::from(linux.namespaces().as_ref());
     if namespaces.get(LinuxNamespaceType::User).is_none() {
-        bail!("rootless containers require the specification of a user namespace");
+        issues.push(RootlessIssue::error(
+            "rootless containers require the specification of a user namespace",
+        ));
     }
 
-    let gid_mappings = linux
-        .gid_mappings()
-        .as_ref()
-        .context("rootless containers require gidMappings in spec")?;
-    let uid_mappings = linux
-        .uid_mappings()
-        .as_ref()
-        .context("rootless containers require uidMappings in spec")?;
+    let uid_mappings: &[LinuxIdMapping] = match uid_mappings {
+        None => {
+            issues.push(RootlessIssue::error(
+                "rootless containers require uidMappings in spec",
+            ));
+            &[]
+        }
+        Some([]) => {
+            issues.push(RootlessIssue::error(
+                "rootless containers require at least one uid mapping",
+            ));
+            &[]
+        }
+        Some(mappings) => mappings,
+    };
+    let gid_mappings: &[LinuxIdMapping] = match gid_mappings {
+        None => {
+            issues.push(RootlessIssue::error(
+                "rootless containers require gidMappings in spec",
+            ));
+            &[]
+        }
+        Some([]) => {
+            issues.push(RootlessIssue::error(
+                "rootless containers require at least one gid mapping",
+            ));
+            &[]
+        }
+        Some(mappings) => mappings,
+    };
 
-    if uid_mappings.is_empty() {
-        bail!("rootless containers require at least one uid mapping");
+    for (kind, mappings) in [("uid", uid_mappings), ("gid", gid_mappings)] {
+        if let Err(err) = validate_id_mappings(kind, mappings) {
+            issues.push(RootlessIssue::error(err.to_string()));
+        }
     }
 
-    if gid_mappings.is_empty() {
-        bail!("rootless containers require at least one gid mapping")
+    match spec.mounts() {
+        Some(mounts) => validate_mounts(mounts, uid_mappings, gid_mappings, &mut issues),
+        None => issues.push(RootlessIssue::error("no mounts in spec")),
     }
 
-    validate_mounts(
-        spec.mounts().as_ref().context("no mounts in spec")?,
-        uid_mappings,
-        gid_mappings,
-    )?;
-
     if let Some(additional_gids) = spec
         .process()
         .as_ref()
         .and_then(|process| process.user().additional_gids().as_ref())
     {
-        let privileged = nix::unistd::geteuid().is_root();
+        let privileged = is_real_root();
 
         match (privileged, additional_gids.is_empty()) {
             (true, false) => {
                 for gid in additional_gids {
                     if !is_id_mapped(*gid, gid_mappings) {
-                        bail!("gid {} is specified as supplementary group, but is not mapped in the user namespace", gid);
+                        issues.push(RootlessIssue::error(format!(
+                            "gid {} is specified as supplementary group, but is not mapped in the user namespace",
+                            gid
+                        )));
                     }
                 }
             }
-            (false, false) => {
+            (false, false) => issues.push(RootlessIssue::error(format!(
+                "user is {} (unprivileged). Supplementary groups cannot be set in \
+                    a rootless container for this user due to CVE-2014-8989",
+                nix::unistd::geteuid()
+            ))),
+            _ => {}
+        }
+    }
+
+    issues
+}
+
+/// Maximum number of lines the kernel accepts in uid_map and gid_map (since Linux 4.15)
+const MAX_ID_MAPPINGS: usize = 340;
+
+/// Checks the mappings against the constraints the kernel enforces when they
+/// are written to uid_map or gid_map, which otherwise only fails with EINVAL.
+/// See https://man7.org/linux/man-pages/man7/user_namespaces.7.html
+fn validate_id_mappings(kind: &str, mappings: &[LinuxIdMapping]) -> Result<()> {
+    if mappings.len() > MAX_ID_MAPPINGS {
+        bail!(
+            "{} {} mappings are specified, but the kernel supports at most {}",
+            mappings.len(),
+            kind,
+            MAX_ID_MAPPINGS
+        );
+    }
+
+    for mapping in mappings {
+        if mapping.size() == 0 {
+            bail!("{} mapping {:?} has a size of zero", kind, mapping);
+        }
+
+        if u64::from(mapping.container_id()) + u64::from(mapping.size()) > u64::from(u32::MAX)
+            || u64::from(mapping.host_id()) + u64::from(mapping.size()) > u64::from(u32::MAX)
+        {
+            bail!(
+                "{} mapping {:?} exceeds the range of valid ids",
+                kind,
+                mapping
+            );
+        }
+    }
+
+    for (i, first) in mappings.iter().enumerate() {
+        for second in &mappings[i + 1..] {
+            if ranges_overlap(
+                (first.container_id(), first.size()),
+                (second.container_id(), second.size()),
+            ) {
+                bail!(
+                    "{} mappings {:?} and {:?} overlap in the container",
+                    kind,
+                    first,
+                    second
+                );
+            }
+
+            if ranges_overlap(
+                (first.host_id(), first.size()),
+                (second.host_id(), second.size()),
+            ) {
                 bail!(
-                    "user is {} (unprivileged). Supplementary groups cannot be set in \
-                        a rootless container for this user due to CVE-2014-8989",
-                    nix::unistd::geteuid()
-                )
+                    "{} mappings {:?} and {:?} overlap on the host",
+                    kind,
+                    first,
+                    second
+                );
             }
-            _ => {}
         }
     }
 
     Ok(())
 }
 
+fn ranges_overlap(
+    (first_start, first_size): (u32, u32),
+    (second_start, second_size): (u32, u32),
+) -> bool {
+    let first_end = u64::from(first_start) + u64::from(first_size);
+    let second_end = u64::from(second_start) + u64::from(second_size);
+    u64::from(first_start) < second_end && u64::from(second_start) < first_end
+}
+
 fn validate_mounts(
     mounts: &[Mount],
     uid_mappings: &[LinuxIdMapping],
     gid_mappings: &[LinuxIdMapping],
-) -> Result<()> {
+    issues: &mut Vec<RootlessIssue>,
+) {
     for mount in mounts {
-        if let Some(options) = mount.options() {
-            for opt in options {
-                if opt.starts_with("uid=") && !is_id_mapped(opt[4..].parse()?, uid_mappings) {
-                    bail!("Mount {:?} specifies option {} which is not mapped inside the rootless container", mount, opt);
-                }
+        // the ownership of a tmpfs is only cosmetic, an unmapped id shows up
+        // as the overflow id inside of the container
+        let severity = match mount.typ().as_deref() {
+            Some("tmpfs") => Severity::Warning,
+            _ => Severity::Error,
+        };
 
-                if opt.starts_with("gid=") && !is_id_mapped(opt[4..].parse()?, gid_mappings) {
-                    bail!("Mount {:?} specifies option {} which is not mapped inside the rootless container", mount, opt);
-                }
+        for opt in mount.options().iter().flatten() {
+            let (id, mappings) = if let Some(id) = opt.strip_prefix("uid=") {
+                (id, uid_mappings)
+            } else if let Some(id) = opt.strip_prefix("gid=") {
+                (id, gid_mappings)
+            } else {
+                continue;
+            };
+
+            match id.parse() {
+                Ok(id) if is_id_mapped(id, mappings) => {}
+                Ok(_) => issues.push(RootlessIssue {
+                    severity,
+                    message: format!(
+                        "Mount {:?} specifies option {} which is not mapped inside the rootless container",
+                        mount.destination(),
+                        opt
+                    ),
+                }),
+                Err(err) => issues.push(RootlessIssue::error(format!(
+                    "Mount {:?} specifies invalid option {}: {}",
+                    mount.destination(),
+                    opt,
+                    err
+                ))),
             }
         }
     }
-
-    Ok(())
 }
 
 fn is_id_mapped(id: u32, mappings: &[LinuxIdMapping]) -> bool {
@@ -212,9 +1181,15 @@ fn is_id_mapped(id: u32, mappings: &[LinuxIdMapping]) -> bool {
 
 /// Looks up the location of the newuidmap and newgidmap binaries which
 /// are required to write multiple user/group mappings
-pub fn lookup_map_binaries(spec: &Linux) -> Result<Option<(PathBuf, PathBuf)>> {
-    if let Some(uid_mappings) = spec.uid_mappings() {
-        if uid_mappings.len() == 1 && uid_mappings.len() == 1 {
+pub fn lookup_map_binaries(
+    uid_mappings: Option<&[LinuxIdMapping]>,
+    gid_mappings: Option<&[LinuxIdMapping]>,
+) -> Result<Option<(PathBuf, PathBuf)>> {
+    if let (Some(uid_mappings), Some(gid_mappings)) = (uid_mappings, gid_mappings) {
+        let privileged = is_real_root();
+        if !requires_map_binary(uid_mappings, nix::unistd::geteuid().as_raw(), privileged)
+            && !requires_map_binary(gid_mappings, nix::unistd::getegid().as_raw(), privileged)
+        {
             return Ok(None);
         }
 
@@ -222,14 +1197,32 @@ pub fn lookup_map_binaries(spec: &Linux) -> Result<Option<(PathBuf, PathBuf)>> {
         let gidmap = lookup_map_binary("newgidmap")?;
 
         match (uidmap, gidmap) {
-            (Some(newuidmap), Some(newgidmap)) => Ok(Some((newuidmap, newgidmap))),
-            _ => bail!("newuidmap/newgidmap binaries could not be found in path. This is required if multiple id mappings are specified"),
+            (Some(newuidmap), Some(newgidmap)) => {
+                validate_map_binary(&newuidmap, Capability::CAP_SETUID)?;
+                validate_map_binary(&newgidmap, Capability::CAP_SETGID)?;
+                Ok(Some((newuidmap, newgidmap)))
+            }
+            _ => {
+                tracing::debug!("newuidmap/newgidmap binaries could not be found in path");
+                Ok(None)
+            }
         }
     } else {
         Ok(None)
     }
 }
 
+/// Multiple mappings and, for unprivileged users, any mapping other than the
+/// one of the own id, e.g. to a subordinate id range, can only be written with
+/// newuidmap and newgidmap
+fn requires_map_binary(mappings: &[LinuxIdMapping], own_id: u32, privileged: bool) -> bool {
+    match mappings {
+        [] => false,
+        [mapping] => !privileged && (mapping.host_id() != own_id || mapping.size() != 1),
+        _ => true,
+    }
+}
+
 fn lookup_map_binary(binary: &str) -> Result<Option<PathBuf>> {
     let paths = env::var("PATH").context("could not find PATH")?;
     Ok(paths
@@ -238,24 +1231,128 @@ fn lookup_map_binary(binary: &str) -> Result<Option<PathBuf>> {
         .find(|p| p.exists()))
 }
 
+/// Error returned if newuidmap or newgidmap did not exit successfully
+#[derive(Debug)]
+pub struct IdMappingError {
+    /// Binary that was used to write the mapping
+    pub binary: PathBuf,
+    /// Process for which the mapping should have been written
+    pub pid: Pid,
+    /// Mapping arguments that were passed to the binary
+    pub args: Vec<String>,
+    /// Exit status of the binary
+    pub status: ExitStatus,
+    /// Captured stderr of the binary
+    pub stderr: String,
+}
+
+impl std::error::Error for IdMappingError {}
+
+impl fmt::Display for IdMappingError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:?} failed to write id mapping {:?} for pid {}: {}",
+            self.binary, self.args, self.pid, self.status
+        )?;
+        if !self.stderr.is_empty() {
+            write!(f, ": {}", self.stderr)?;
+        }
+
+        Ok(())
+    }
+}
+
+const SECURITY_CAPABILITY_XATTR: &str = "security.capability";
+
+/// Validates that a map binary is able to write the id mappings, which
+/// requires it to either be setuid root or to carry the required file
+/// capability (CAP_SETUID for newuidmap, CAP_SETGID for newgidmap)
+fn validate_map_binary(binary: &Path, required: Capability) -> Result<()> {
+    let metadata =
+        fs::metadata(binary).with_context(|| format!("failed to get metadata of {:?}", binary))?;
+    if metadata.mode() & libc::S_ISUID != 0 && metadata.uid() == 0 {
+        return Ok(());
+    }
+
+    if let Some(xattr) = read_security_capability(binary)? {
+        if has_permitted_file_capability(&xattr, required) {
+            return Ok(());
+        }
+    }
+
+    bail!(
+        "{:?} is neither setuid root nor has the {} file capability. It cannot be used to write \
+        id mappings. Reinstall it (e.g. from the uidmap or shadow-utils package) or run \
+        'setcap {}+ep {}'",
+        binary,
+        required,
+        required.to_string().to_lowercase(),
+        binary.display()
+    )
+}
+
+fn read_security_capability(path: &Path) -> Result<Option<Vec<u8>>> {
+    let c_path = CString::new(path.as_os_str().as_bytes())?;
+    let c_name = CString::new(SECURITY_CAPABILITY_XATTR)?;
+    let mut buf = [0u8; 64];
+    let size = unsafe {
+        libc::getxattr(
+            c_path.as_ptr(),
+            c_name.as_ptr(),
+            buf.as_mut_ptr() as *mut libc::c_void,
+            buf.len(),
+        )
+    };
+
+    if size < 0 {
+        return match nix::errno::Errno::last() {
+            nix::errno::Errno::ENODATA | nix::errno::Errno::EOPNOTSUPP => Ok(None),
+            errno => Err(errno)
+                .with_context(|| format!("failed to read file capabilities of {:?}", path)),
+        };
+    }
+
+    Ok(Some(buf[..size as usize].to_vec()))
+}
+
+/// Checks if the capability is part of the permitted set of the file
+/// capabilities, which are stored as struct vfs_cap_data in the
+/// security.capability extended attribute
+fn has_permitted_file_capability(xattr: &[u8], cap: Capability) -> bool {
+    // magic_etc followed by pairs of permitted and inheritable 32 bit masks
+    let index = cap.index() as usize;
+    let offset = 4 + (index / 32) * 8;
+    match xattr.get(offset..offset + 4) {
+        Some(permitted) => {
+            let permitted =
+                u32::from_le_bytes([permitted[0], permitted[1], permitted[2], permitted[3]]);
+            permitted & (1 << (index % 32)) != 0
+        }
+        None => false,
+    }
+}
+
 fn write_id_mapping(
     pid: Pid,
     map_file: &str,
     mappings: &[LinuxIdMapping],
     map_binary: Option<&Path>,
 ) -> Result<()> {
-    log::debug!("Write ID mapping: {:?}", mappings);
-
-    match mappings.len() {
-        0 => bail!("at least one id mapping needs to be defined"),
-        1 => {
-            let mapping = mappings
-                .first()
-                .and_then(|m| format!("{} {} {}", m.container_id(), m.host_id(), m.size()).into())
-                .unwrap();
+    tracing::debug!("Write ID mapping: {:?}", mappings);
+
+    match (mappings, map_binary) {
+        ([], _) => bail!("at least one id mapping needs to be defined"),
+        ([mapping], None) => {
+            let mapping = format!(
+                "{} {} {}",
+                mapping.container_id(),
+                mapping.host_id(),
+                mapping.size()
+            );
             utils::write_file(map_file, mapping)?;
         }
-        _ => {
+        (_, map_binary) => {
             let args: Vec<String> = mappings
                 .iter()
                 .flat_map(|m| {
@@ -267,13 +1364,473 @@ fn write_id_mapping(
                 })
                 .collect();
 
-            Command::new(map_binary.unwrap())
+            let map_binary = map_binary.with_context(|| {
+                format!(
+                    "newuidmap/newgidmap binaries could not be found in path. \
+                    This is required to write multiple id mappings to {}",
+                    map_file
+                )
+            })?;
+            let output = Command::new(map_binary)
                 .arg(pid.to_string())
-                .args(args)
+                .args(&args)
                 .output()
                 .with_context(|| format!("failed to execute {:?}", map_binary))?;
+
+            if !output.status.success() {
+                return Err(IdMappingError {
+                    binary: map_binary.to_path_buf(),
+                    pid,
+                    args,
+                    status: output.status,
+                    stderr: String::from_utf8_lossy(&output.stderr).trim().to_owned(),
+                }
+                .into());
+            }
         }
     }
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use oci_spec::runtime::{LinuxBuilder, MountBuilder, SpecBuilder};
+
+    #[test]
+    fn test_parse_subordinate_ranges() -> Result<()> {
+        let content = "# comment\nalice:100000:65536\n1000:200000:65536\nbob:300000:65536\n\nalice:400000:10\n";
+
+        assert_eq!(
+            parse_subordinate_ranges(content, Some("alice"), 1001)?,
+            vec![(100000, 65536), (400000, 10)]
+        );
+        assert_eq!(
+            parse_subordinate_ranges(content, Some("carol"), 1000)?,
+            vec![(200000, 65536)]
+        );
+        assert!(parse_subordinate_ranges(content, None, 1002)?.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_subordinate_ranges_invalid() {
+        assert!(parse_subordinate_ranges("alice:100000", Some("alice"), 1000).is_err());
+        assert!(parse_subordinate_ranges("alice:abc:65536", Some("alice"), 1000).is_err());
+    }
+
+    #[test]
+    fn test_lookup_subordinate_range() -> Result<()> {
+        let tmp = utils::create_temp_dir("test_lookup_subordinate_range")?;
+        let subuid = tmp.join("subuid");
+        fs::write(&subuid, "alice:0:0\nalice:100000:65536\n")?;
+
+        assert_eq!(
+            lookup_subordinate_range(&subuid, Some("alice"), 1000)?,
+            Some((100000, 65536))
+        );
+        assert_eq!(lookup_subordinate_range(&subuid, Some("bob"), 1001)?, None);
+        assert_eq!(
+            lookup_subordinate_range(&tmp.join("missing"), Some("alice"), 1000)?,
+            None
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_write_id_mapping_binary_failure() -> Result<()> {
+        let mappings = build_id_mappings(1000, (100000, 65536))?;
+        let result = write_id_mapping(
+            Pid::from_raw(1),
+            "/proc/1/uid_map",
+            &mappings,
+            Some(Path::new("false")),
+        );
+
+        let err = result.expect_err("newuidmap failure should be reported");
+        let err = err
+            .downcast_ref::<IdMappingError>()
+            .expect("error should be an id mapping error");
+        assert_eq!(err.pid, Pid::from_raw(1));
+        assert_eq!(err.args, vec!["0", "1000", "1", "1", "100000", "65536"]);
+        assert!(!err.status.success());
+        Ok(())
+    }
+
+    #[test]
+    fn test_write_id_mapping_requires_binary() -> Result<()> {
+        let mappings = build_id_mappings(1000, (100000, 65536))?;
+        assert!(write_id_mapping(Pid::from_raw(1), "/proc/1/uid_map", &mappings, None).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_requires_map_binary() -> Result<()> {
+        let own = build_id_mappings(1000, (100000, 65536))?;
+        assert!(!requires_map_binary(&own[..1], 1000, false));
+        assert!(requires_map_binary(&own[1..], 1000, false));
+        assert!(!requires_map_binary(&own[1..], 1000, true));
+        assert!(requires_map_binary(&own, 1000, true));
+        assert!(!requires_map_binary(&[], 1000, false));
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_id_map() -> Result<()> {
+        let mappings =
+            parse_id_map("         0       1000          1\n         1     100000      65536\n")?;
+        assert_eq!(mappings, build_id_mappings(1000, (100000, 65536))?);
+        assert!(parse_id_map("0 1000\n").is_err());
+        assert!(parse_id_map("0 1000 x\n").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_proc_dir_of_userns() {
+        assert_eq!(
+            proc_dir_of_userns(Path::new("/proc/42/ns/user")),
+            Some(Path::new("/proc/42"))
+        );
+        assert_eq!(proc_dir_of_userns(Path::new("/run/userns/foo")), None);
+    }
+
+    #[test]
+    fn test_is_range_mapped() -> Result<()> {
+        let existing = build_id_mappings(1000, (100000, 65536))?;
+        let mapping = |container_id: u32, host_id: u32, size: u32| {
+            LinuxIdMappingBuilder::default()
+                .container_id(container_id)
+                .host_id(host_id)
+                .size(size)
+                .build()
+        };
+
+        assert!(is_range_mapped(&mapping(0, 1000, 1)?, &existing));
+        assert!(is_range_mapped(&mapping(10, 100009, 100)?, &existing));
+        assert!(!is_range_mapped(&mapping(0, 1000, 2)?, &existing));
+        assert!(!is_range_mapped(&mapping(10, 100000, 100)?, &existing));
+        assert!(!is_range_mapped(&mapping(65530, 165529, 10)?, &existing));
+        Ok(())
+    }
+
+    #[test]
+    fn test_from_existing_userns() -> Result<()> {
+        let rootless = Rootless::from_existing_userns("/proc/self/ns/user")?;
+        assert!(rootless.joins_existing_userns());
+        assert!(!rootless.uid_mappings.unwrap_or_default().is_empty());
+        assert!(!rootless.gid_mappings.unwrap_or_default().is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_rootless_builder() -> Result<()> {
+        let mappings = build_id_mappings(1000, (100000, 65536))?;
+        let rootless = RootlessBuilder::default()
+            .with_uid_mappings(mappings[..1].to_vec())
+            .with_gid_mappings(mappings[..1].to_vec())
+            .with_privileged(false)
+            .build()?;
+
+        assert_eq!(rootless.uid_mappings(), Some(&mappings[..1]));
+        assert_eq!(rootless.gid_mappings(), Some(&mappings[..1]));
+        assert!(!rootless.privileged);
+        assert!(!rootless.joins_existing_userns());
+        assert_eq!(rootless.newuidmap, None);
+
+        let rootless = RootlessBuilder::default()
+            .with_uid_mappings(mappings.clone())
+            .with_gid_mappings(mappings.clone())
+            .with_newuidmap("/usr/bin/newuidmap")
+            .with_newgidmap("/usr/bin/newgidmap")
+            .build()?;
+        assert_eq!(
+            rootless.newuidmap.as_deref(),
+            Some(Path::new("/usr/bin/newuidmap"))
+        );
+        assert_eq!(
+            rootless.newgidmap.as_deref(),
+            Some(Path::new("/usr/bin/newgidmap"))
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_rootless_builder_requires_mappings() -> Result<()> {
+        let mappings = build_id_mappings(1000, (100000, 65536))?;
+        assert!(RootlessBuilder::default().build().is_err());
+        assert!(RootlessBuilder::default()
+            .with_uid_mappings(mappings)
+            .build()
+            .is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_has_permitted_file_capability() {
+        // VFS_CAP_REVISION_2 | VFS_CAP_FLAGS_EFFECTIVE with CAP_SETUID permitted
+        let xattr = [
+            0x01, 0x00, 0x00, 0x02, 0x80, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+            0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        ];
+        assert!(has_permitted_file_capability(
+            &xattr,
+            Capability::CAP_SETUID
+        ));
+        assert!(!has_permitted_file_capability(
+            &xattr,
+            Capability::CAP_SETGID
+        ));
+        assert!(!has_permitted_file_capability(
+            &xattr[..4],
+            Capability::CAP_SETUID
+        ));
+    }
+
+    #[test]
+    fn test_validate_map_binary_unprivileged() -> Result<()> {
+        let tmp = utils::create_temp_dir("test_validate_map_binary_unprivileged")?;
+        let binary = tmp.join("newuidmap");
+        fs::write(&binary, "")?;
+
+        let err = validate_map_binary(&binary, Capability::CAP_SETUID)
+            .expect_err("binary without privileges should be rejected");
+        assert!(err.to_string().contains(&binary.display().to_string()));
+        Ok(())
+    }
+
+    #[test]
+    fn test_rootless_mode() -> Result<()> {
+        assert_eq!(RootlessMode::from_str("auto")?, RootlessMode::Auto);
+        assert_eq!(RootlessMode::from_str("true")?, RootlessMode::Enabled);
+        assert_eq!(RootlessMode::from_str("false")?, RootlessMode::Disabled);
+        assert!(RootlessMode::from_str("yes").is_err());
+
+        assert!(RootlessMode::Enabled.is_required());
+        assert!(!RootlessMode::Disabled.is_required());
+        assert_eq!(RootlessMode::Auto.is_required(), rootless_required());
+        Ok(())
+    }
+
+    #[test]
+    fn test_single_mapping_fallback() -> Result<()> {
+        let mappings = build_id_mappings(1000, (100000, 65536))?;
+        let rootless = Rootless {
+            allow_single_mapping_fallback: true,
+            ..Default::default()
+        };
+
+        let got = rootless.mappings_or_fallback(&mappings, None, 1000)?;
+        assert_eq!(got.len(), 1);
+        assert_eq!(got[0].container_id(), 0);
+        assert_eq!(got[0].host_id(), 1000);
+        assert_eq!(got[0].size(), 1);
+
+        let got = rootless.mappings_or_fallback(
+            &mappings,
+            Some(Path::new("/usr/bin/newuidmap")),
+            1000,
+        )?;
+        assert_eq!(got.as_ref(), mappings.as_slice());
+
+        let rootless = Rootless {
+            allow_single_mapping_fallback: false,
+            ..rootless
+        };
+        let got = rootless.mappings_or_fallback(&mappings, None, 1000)?;
+        assert_eq!(got.as_ref(), mappings.as_slice());
+        Ok(())
+    }
+
+    #[test]
+    fn test_effective_mappings() -> Result<()> {
+        let uid_mappings = build_id_mappings(1000, (100000, 65536))?;
+        let gid_mappings = build_id_mappings(1000, (200000, 10))?;
+        let rootless = Rootless {
+            newuidmap: Some(PathBuf::from("/usr/bin/newuidmap")),
+            newgidmap: Some(PathBuf::from("/usr/bin/newgidmap")),
+            uid_mappings: Some(uid_mappings.clone()),
+            gid_mappings: Some(gid_mappings.clone()),
+            ..Default::default()
+        };
+
+        let effective = rootless.effective_mappings()?;
+        assert_eq!(effective.uid, uid_mappings);
+        assert_eq!(effective.gid, gid_mappings);
+
+        assert_eq!(effective.host_uid_for(0), Some(1000));
+        assert_eq!(effective.host_uid_for(1), Some(100000));
+        assert_eq!(effective.host_uid_for(65536), Some(165535));
+        assert_eq!(effective.host_uid_for(65537), None);
+        assert_eq!(effective.host_gid_for(10), Some(200009));
+        assert_eq!(effective.host_gid_for(11), None);
+
+        assert_eq!(effective.container_uid_for(1000), Some(0));
+        assert_eq!(effective.container_uid_for(100001), Some(2));
+        assert_eq!(effective.container_uid_for(999), None);
+        assert_eq!(effective.container_gid_for(200000), Some(1));
+
+        assert_eq!(rootless.host_uid_for(5)?, Some(100004));
+        assert_eq!(rootless.host_gid_for(0)?, Some(1000));
+        Ok(())
+    }
+
+    #[test]
+    fn test_effective_mappings_fallback() -> Result<()> {
+        let rootless = Rootless {
+            uid_mappings: Some(build_id_mappings(1000, (100000, 65536))?),
+            allow_single_mapping_fallback: true,
+            ..Default::default()
+        };
+
+        let effective = rootless.effective_mappings()?;
+        assert_eq!(effective.uid.len(), 1);
+        assert!(effective.gid.is_empty());
+        assert_eq!(
+            effective.host_uid_for(0),
+            Some(nix::unistd::geteuid().as_raw())
+        );
+        assert_eq!(effective.host_uid_for(1), None);
+        Ok(())
+    }
+
+    fn id_mapping(container_id: u32, host_id: u32, size: u32) -> LinuxIdMapping {
+        LinuxIdMappingBuilder::default()
+            .container_id(container_id)
+            .host_id(host_id)
+            .size(size)
+            .build()
+            .unwrap()
+    }
+
+    #[test]
+    fn test_validate_id_mappings() -> Result<()> {
+        validate_id_mappings("uid", &build_id_mappings(1000, (100000, 65536))?)?;
+        validate_id_mappings("uid", &[id_mapping(0, 1000, 1), id_mapping(1, 1001, 1)])?;
+
+        let max = (0..MAX_ID_MAPPINGS as u32)
+            .map(|i| id_mapping(i, 100000 + i, 1))
+            .collect::<Vec<_>>();
+        validate_id_mappings("uid", &max)?;
+
+        let too_many = (0..=MAX_ID_MAPPINGS as u32)
+            .map(|i| id_mapping(i, 100000 + i, 1))
+            .collect::<Vec<_>>();
+        assert!(validate_id_mappings("uid", &too_many).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_validate_id_mappings_invalid() {
+        // zero size
+        assert!(validate_id_mappings("uid", &[id_mapping(0, 1000, 0)]).is_err());
+        // overflowing range
+        assert!(validate_id_mappings("uid", &[id_mapping(u32::MAX, 1000, 2)]).is_err());
+        // overlap in the container
+        assert!(
+            validate_id_mappings("gid", &[id_mapping(0, 1000, 10), id_mapping(5, 100000, 10)])
+                .is_err()
+        );
+        // overlap on the host
+        assert!(validate_id_mappings(
+            "gid",
+            &[id_mapping(0, 100000, 10), id_mapping(10, 100005, 10)]
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_is_initial_id_map() -> Result<()> {
+        assert!(is_initial_id_map(&parse_id_map("0 0 4294967295\n")?));
+        assert!(!is_initial_id_map(&parse_id_map("0 1000 1\n")?));
+        assert!(!is_initial_id_map(&parse_id_map(
+            "0 1000 1\n1 100000 65536\n"
+        )?));
+        assert!(!is_initial_id_map(&[]));
+        Ok(())
+    }
+
+    #[test]
+    fn test_environment() -> Result<()> {
+        let env = environment()?;
+        if env.in_user_namespace {
+            assert!(!env.is_real_root());
+            assert!(rootless_required());
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_validate_collects_issues() -> Result<()> {
+        let user_ns = LinuxNamespaceBuilder::default()
+            .typ(LinuxNamespaceType::User)
+            .build()?;
+        let spec = SpecBuilder::default()
+            .linux(
+                LinuxBuilder::default()
+                    .namespaces(vec![user_ns.clone()])
+                    .build()?,
+            )
+            .mounts(vec![
+                MountBuilder::default()
+                    .destination("/dev/shm")
+                    .typ("tmpfs")
+                    .options(vec!["uid=5000".to_string()])
+                    .build()?,
+                MountBuilder::default()
+                    .destination("/dev/pts")
+                    .typ("devpts")
+                    .options(vec!["gid=5000".to_string()])
+                    .build()?,
+            ])
+            .build()?;
+
+        let uid_mappings = vec![id_mapping(0, 1000, 1)];
+        let issues = validate(&spec, Some(uid_mappings.as_slice()), Some(&[]));
+        let errors: Vec<_> = issues.iter().filter(|i| i.is_error()).collect();
+        let warnings: Vec<_> = issues.iter().filter(|i| !i.is_error()).collect();
+        assert_eq!(errors.len(), 2, "{:?}", issues);
+        assert!(errors[0].message.contains("at least one gid mapping"));
+        assert!(errors[1].message.contains("/dev/pts"));
+        assert_eq!(warnings.len(), 1, "{:?}", issues);
+        assert!(warnings[0].message.contains("/dev/shm"));
+
+        let err = check_issues(issues).expect_err("errors should fail the check");
+        let err = err
+            .downcast_ref::<RootlessValidationError>()
+            .expect("error should contain all issues");
+        assert_eq!(err.issues.len(), 2);
+
+        // only warnings remain if the devpts mount is dropped
+        let spec = SpecBuilder::default()
+            .linux(LinuxBuilder::default().namespaces(vec![user_ns]).build()?)
+            .mounts(vec![MountBuilder::default()
+                .destination("/dev/shm")
+                .typ("tmpfs")
+                .options(vec!["uid=5000".to_string()])
+                .build()?])
+            .build()?;
+        let issues = validate(
+            &spec,
+            Some(uid_mappings.as_slice()),
+            Some(uid_mappings.as_slice()),
+        );
+        assert_eq!(issues.len(), 1);
+        assert_eq!(issues[0].severity, Severity::Warning);
+        assert!(check_issues(issues).is_ok());
+        Ok(())
+    }
+
+    #[test]
+    fn test_build_id_mappings() -> Result<()> {
+        let mappings = build_id_mappings(1000, (100000, 65536))?;
+        assert_eq!(mappings.len(), 2);
+        assert_eq!(mappings[0].container_id(), 0);
+        assert_eq!(mappings[0].host_id(), 1000);
+        assert_eq!(mappings[0].size(), 1);
+        assert_eq!(mappings[1].container_id(), 1);
+        assert_eq!(mappings[1].host_id(), 100000);
+        assert_eq!(mappings[1].size(), 65536);
+        Ok(())
+    }
+}
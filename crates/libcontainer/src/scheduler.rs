@@ -0,0 +1,305 @@
+//! Scheduling attributes of the container process as specified by
+//! `process.scheduler` (see sched(7)). They are applied with sched_setattr(2)
+//! and inherited by the payload.
+use anyhow::{bail, Context, Result};
+use nix::errno::Errno;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::mem;
+
+// see include/uapi/linux/sched.h
+const SCHED_OTHER: u32 = 0;
+const SCHED_FIFO: u32 = 1;
+const SCHED_RR: u32 = 2;
+const SCHED_BATCH: u32 = 3;
+const SCHED_ISO: u32 = 4;
+const SCHED_IDLE: u32 = 5;
+const SCHED_DEADLINE: u32 = 6;
+
+const SCHED_FLAG_RESET_ON_FORK: u64 = 0x01;
+const SCHED_FLAG_RECLAIM: u64 = 0x02;
+const SCHED_FLAG_DL_OVERRUN: u64 = 0x04;
+const SCHED_FLAG_KEEP_POLICY: u64 = 0x08;
+const SCHED_FLAG_KEEP_PARAMS: u64 = 0x10;
+
+const MIN_NICE: i32 = -20;
+const MAX_NICE: i32 = 19;
+const MIN_RT_PRIORITY: u32 = 1;
+const MAX_RT_PRIORITY: u32 = 99;
+
+/// Scheduling policy of the container process
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SchedulerPolicy {
+    #[serde(rename = "SCHED_OTHER")]
+    Other,
+    #[serde(rename = "SCHED_FIFO")]
+    Fifo,
+    #[serde(rename = "SCHED_RR")]
+    Rr,
+    #[serde(rename = "SCHED_BATCH")]
+    Batch,
+    #[serde(rename = "SCHED_ISO")]
+    Iso,
+    #[serde(rename = "SCHED_IDLE")]
+    Idle,
+    #[serde(rename = "SCHED_DEADLINE")]
+    Deadline,
+}
+
+impl SchedulerPolicy {
+    fn value(&self) -> u32 {
+        match self {
+            Self::Other => SCHED_OTHER,
+            Self::Fifo => SCHED_FIFO,
+            Self::Rr => SCHED_RR,
+            Self::Batch => SCHED_BATCH,
+            Self::Iso => SCHED_ISO,
+            Self::Idle => SCHED_IDLE,
+            Self::Deadline => SCHED_DEADLINE,
+        }
+    }
+
+    fn is_realtime(&self) -> bool {
+        matches!(self, Self::Fifo | Self::Rr)
+    }
+}
+
+/// Flags which modify the behaviour of the scheduling policy
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SchedulerFlag {
+    #[serde(rename = "SCHED_FLAG_RESET_ON_FORK")]
+    ResetOnFork,
+    #[serde(rename = "SCHED_FLAG_RECLAIM")]
+    Reclaim,
+    #[serde(rename = "SCHED_FLAG_DL_OVERRUN")]
+    DlOverrun,
+    #[serde(rename = "SCHED_FLAG_KEEP_POLICY")]
+    KeepPolicy,
+    #[serde(rename = "SCHED_FLAG_KEEP_PARAMS")]
+    KeepParams,
+    #[serde(rename = "SCHED_FLAG_UTIL_CLAMP_MIN")]
+    UtilClampMin,
+    #[serde(rename = "SCHED_FLAG_UTIL_CLAMP_MAX")]
+    UtilClampMax,
+}
+
+/// Scheduling attributes as specified by `process.scheduler`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Scheduler {
+    pub policy: SchedulerPolicy,
+    /// Nice value for SCHED_OTHER and SCHED_BATCH
+    #[serde(default)]
+    pub nice: i32,
+    /// Static priority for SCHED_FIFO and SCHED_RR
+    #[serde(default)]
+    pub priority: u32,
+    #[serde(default)]
+    pub flags: Vec<SchedulerFlag>,
+    /// Runtime of SCHED_DEADLINE in nanoseconds
+    #[serde(default)]
+    pub runtime: u64,
+    /// Deadline of SCHED_DEADLINE in nanoseconds
+    #[serde(default)]
+    pub deadline: u64,
+    /// Period of SCHED_DEADLINE in nanoseconds
+    #[serde(default)]
+    pub period: u64,
+}
+
+// struct sched_attr of the kernel in the version without utilization clamping
+// (SCHED_ATTR_SIZE_VER0)
+#[repr(C)]
+#[derive(Debug, Default)]
+struct SchedAttr {
+    size: u32,
+    sched_policy: u32,
+    sched_flags: u64,
+    sched_nice: i32,
+    sched_priority: u32,
+    sched_runtime: u64,
+    sched_deadline: u64,
+    sched_period: u64,
+}
+
+impl Scheduler {
+    /// Ensures that the attributes are accepted by the kernel
+    pub fn validate(&self) -> Result<()> {
+        if !(MIN_NICE..=MAX_NICE).contains(&self.nice) {
+            bail!(
+                "nice value {} is not in the range [{}, {}]",
+                self.nice,
+                MIN_NICE,
+                MAX_NICE
+            );
+        }
+
+        if self.policy.is_realtime() {
+            if !(MIN_RT_PRIORITY..=MAX_RT_PRIORITY).contains(&self.priority) {
+                bail!(
+                    "priority of {:?} must be in the range [{}, {}], but is {}",
+                    self.policy,
+                    MIN_RT_PRIORITY,
+                    MAX_RT_PRIORITY,
+                    self.priority
+                );
+            }
+        } else if self.priority != 0 {
+            bail!("priority can only be set for SCHED_FIFO and SCHED_RR");
+        }
+
+        if self.policy == SchedulerPolicy::Deadline {
+            // a period of zero is treated as equal to the deadline by the kernel
+            let period = if self.period == 0 {
+                self.deadline
+            } else {
+                self.period
+            };
+            if self.runtime == 0 || self.runtime > self.deadline || self.deadline > period {
+                bail!(
+                    "SCHED_DEADLINE requires 0 < runtime ({}) <= deadline ({}) <= period ({})",
+                    self.runtime,
+                    self.deadline,
+                    self.period
+                );
+            }
+        } else if self.runtime != 0 || self.deadline != 0 || self.period != 0 {
+            bail!("runtime, deadline and period can only be set for SCHED_DEADLINE");
+        }
+
+        if let Some(flag) = self.flags.iter().find(|flag| {
+            matches!(
+                flag,
+                SchedulerFlag::UtilClampMin | SchedulerFlag::UtilClampMax
+            )
+        }) {
+            bail!(
+                "scheduler flag {:?} is not supported, as the spec does not define clamp values",
+                flag
+            );
+        }
+
+        Ok(())
+    }
+
+    fn attr(&self) -> SchedAttr {
+        let sched_flags = self
+            .flags
+            .iter()
+            .map(|flag| match flag {
+                SchedulerFlag::ResetOnFork => SCHED_FLAG_RESET_ON_FORK,
+                SchedulerFlag::Reclaim => SCHED_FLAG_RECLAIM,
+                SchedulerFlag::DlOverrun => SCHED_FLAG_DL_OVERRUN,
+                SchedulerFlag::KeepPolicy => SCHED_FLAG_KEEP_POLICY,
+                SchedulerFlag::KeepParams => SCHED_FLAG_KEEP_PARAMS,
+                // rejected by validate
+                SchedulerFlag::UtilClampMin | SchedulerFlag::UtilClampMax => 0,
+            })
+            .fold(0, |flags, flag| flags | flag);
+
+        SchedAttr {
+            size: mem::size_of::<SchedAttr>() as u32,
+            sched_policy: self.policy.value(),
+            sched_flags,
+            sched_nice: self.nice,
+            sched_priority: self.priority,
+            sched_runtime: self.runtime,
+            sched_deadline: self.deadline,
+            sched_period: self.period,
+        }
+    }
+
+    /// Applies the scheduling attributes to the calling process. Realtime and
+    /// deadline policies require CAP_SYS_NICE, so this has to happen before
+    /// the capabilities are dropped.
+    pub fn apply(&self) -> Result<()> {
+        tracing::debug!("set scheduler {:?}", self);
+        let attr = self.attr();
+        let flags: libc::c_uint = 0;
+        let result =
+            unsafe { libc::syscall(libc::SYS_sched_setattr, 0, &attr as *const SchedAttr, flags) };
+        if result == -1 {
+            bail!(
+                "failed to set scheduler {:?}: {}",
+                self.policy,
+                Errno::last()
+            );
+        }
+
+        Ok(())
+    }
+}
+
+/// Removes the scheduler from the raw process config, as it cannot be
+/// represented by the spec, and validates it
+pub(crate) fn extract_scheduler(process: &mut Value) -> Result<Option<Scheduler>> {
+    let scheduler = match process
+        .as_object_mut()
+        .and_then(|process| process.remove("scheduler"))
+    {
+        Some(scheduler) => scheduler,
+        None => return Ok(None),
+    };
+
+    let scheduler: Scheduler =
+        serde_json::from_value(scheduler).context("invalid process.scheduler")?;
+    scheduler.validate()?;
+    Ok(Some(scheduler))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_extract_scheduler() -> Result<()> {
+        let mut process = json!({
+            "args": ["sh"],
+            "scheduler": {
+                "policy": "SCHED_FIFO",
+                "priority": 10,
+                "flags": ["SCHED_FLAG_RESET_ON_FORK"],
+            },
+        });
+
+        let scheduler = extract_scheduler(&mut process)?.expect("scheduler");
+        assert_eq!(process, json!({ "args": ["sh"] }));
+
+        let attr = scheduler.attr();
+        assert_eq!(attr.size, 48);
+        assert_eq!(attr.sched_policy, SCHED_FIFO);
+        assert_eq!(attr.sched_priority, 10);
+        assert_eq!(attr.sched_flags, SCHED_FLAG_RESET_ON_FORK);
+
+        assert_eq!(extract_scheduler(&mut process)?, None);
+        Ok(())
+    }
+
+    #[test]
+    fn test_invalid_scheduler() {
+        for scheduler in vec![
+            json!({ "policy": "SCHED_UNKNOWN" }),
+            json!({ "policy": "SCHED_OTHER", "nice": 20 }),
+            json!({ "policy": "SCHED_OTHER", "priority": 1 }),
+            json!({ "policy": "SCHED_RR" }),
+            json!({ "policy": "SCHED_FIFO", "priority": 100 }),
+            json!({ "policy": "SCHED_BATCH", "runtime": 1 }),
+            json!({ "policy": "SCHED_DEADLINE", "runtime": 2, "deadline": 1 }),
+            json!({ "policy": "SCHED_DEADLINE", "runtime": 1, "deadline": 2, "period": 1 }),
+            json!({ "policy": "SCHED_OTHER", "flags": ["SCHED_FLAG_UTIL_CLAMP_MIN"] }),
+        ] {
+            let mut process = json!({ "scheduler": scheduler.clone() });
+            assert!(extract_scheduler(&mut process).is_err(), "{}", scheduler);
+        }
+    }
+
+    #[test]
+    fn test_deadline_scheduler() -> Result<()> {
+        let mut process = json!({
+            "scheduler": { "policy": "SCHED_DEADLINE", "runtime": 10, "deadline": 20 },
+        });
+        let scheduler = extract_scheduler(&mut process)?.expect("scheduler");
+        assert_eq!(scheduler.attr().sched_policy, SCHED_DEADLINE);
+        Ok(())
+    }
+}
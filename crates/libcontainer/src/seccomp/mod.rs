@@ -301,7 +301,7 @@ pub fn initialize_seccomp(seccomp: &LinuxSeccomp) -> Result<Option<io::RawFd>> {
             if action == default_action {
                 // When the action is the same as the default action, the rule is redundent. We can
                 // skip this here to avoid failing when we add the rules.
-                log::warn!(
+                tracing::warn!(
                     "Detect a seccomp action that is the same as the default action: {:?}",
                     syscall
                 );
@@ -314,7 +314,7 @@ pub fn initialize_seccomp(seccomp: &LinuxSeccomp) -> Result<Option<io::RawFd>> {
                     Err(_) => {
                         // If we failed to resolve the syscall by name, likely the kernel
                         // doeesn't support this syscall. So it is safe to skip...
-                        log::warn!(
+                        tracing::warn!(
                             "Failed to resolve syscall, likely kernel doesn't support this. {:?}",
                             name
                         );
@@ -0,0 +1,210 @@
+//! Parts of the runtime spec which are not supported by the version of
+//! oci-spec in use yet. They are extracted from the raw config before it is
+//! parsed and passed along with the spec.
+use anyhow::{bail, Context, Result};
+use oci_spec::runtime::{LinuxNamespaceType, Spec};
+use serde_json::Value;
+use std::{fs, path::Path};
+
+use crate::{
+    cpu_affinity::{self, ExecCpuAffinity},
+    io_priority::{self, IoPriority},
+    personality::Personality,
+    scheduler::{self, Scheduler},
+    time_namespace::{self, TimeNamespace},
+};
+
+/// Settings of the runtime spec which can not be represented by oci-spec
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SpecExtensions {
+    /// Time namespace which should be created or joined
+    pub time_namespace: Option<TimeNamespace>,
+    /// NIS domain name of the container
+    pub domainname: Option<String>,
+    /// Execution domain of the container process
+    pub personality: Option<Personality>,
+    /// Scheduling attributes of the container process
+    pub scheduler: Option<Scheduler>,
+    /// I/O priority of the container process
+    pub io_priority: Option<IoPriority>,
+    /// CPU affinity of the container process during setup and after exec
+    pub exec_cpu_affinity: Option<ExecCpuAffinity>,
+}
+
+impl SpecExtensions {
+    /// Ensures that the extensions can be applied together with the spec
+    pub fn validate(&self, spec: &Spec) -> Result<()> {
+        if let Some(domainname) = &self.domainname {
+            // like the hostname, the domainname is kept by a joined uts namespace
+            let has_uts = spec
+                .linux()
+                .as_ref()
+                .and_then(|l| l.namespaces().as_ref())
+                .map_or(false, |namespaces| {
+                    namespaces
+                        .iter()
+                        .any(|ns| ns.typ() == LinuxNamespaceType::Uts)
+                });
+            if !domainname.is_empty() && !has_uts {
+                bail!("cannot set domainname without a uts namespace");
+            }
+        }
+
+        if let Some(personality) = &self.personality {
+            personality.validate()?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Loads the runtime spec from the given path together with the settings
+/// that are not supported by oci-spec
+pub fn load_spec<P: AsRef<Path>>(path: P) -> Result<(Spec, SpecExtensions)> {
+    let path = path.as_ref();
+    let content =
+        fs::read_to_string(path).with_context(|| format!("failed to read spec {:?}", path))?;
+    let mut config: Value = serde_json::from_str(&content)
+        .with_context(|| format!("failed to parse spec {:?}", path))?;
+    let extensions = extract_extensions(&mut config)?;
+    let spec = serde_json::from_value(config)
+        .with_context(|| format!("failed to parse spec {:?}", path))?;
+    Ok((spec, extensions))
+}
+
+fn extract_extensions(config: &mut Value) -> Result<SpecExtensions> {
+    let domainname = match config.as_object_mut().and_then(|c| c.remove("domainname")) {
+        Some(Value::String(domainname)) => Some(domainname),
+        Some(Value::Null) | None => None,
+        Some(other) => bail!("invalid domainname {}", other),
+    };
+
+    let personality = match config
+        .get_mut("linux")
+        .and_then(Value::as_object_mut)
+        .and_then(|linux| linux.remove("personality"))
+    {
+        Some(personality) => {
+            Some(serde_json::from_value(personality).context("invalid linux.personality")?)
+        }
+        None => None,
+    };
+
+    let mut extensions = match config.get_mut("process") {
+        Some(process) => extract_process_extensions(process)?,
+        None => SpecExtensions::default(),
+    };
+    extensions.time_namespace = time_namespace::extract_time_namespace(config)?;
+    extensions.domainname = domainname;
+    extensions.personality = personality;
+    Ok(extensions)
+}
+
+/// Removes the settings of the raw process config that are not supported by
+/// oci-spec. This is also used for the process of a tenant container.
+pub(crate) fn extract_process_extensions(process: &mut Value) -> Result<SpecExtensions> {
+    Ok(SpecExtensions {
+        scheduler: scheduler::extract_scheduler(process)?,
+        io_priority: io_priority::extract_io_priority(process)?,
+        exec_cpu_affinity: cpu_affinity::extract_exec_cpu_affinity(process)?,
+        ..Default::default()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{io_priority::IoPriorityClass, personality::PersonalityDomain};
+    use oci_spec::runtime::{LinuxNamespace, LinuxNamespaceBuilder};
+    use serde_json::json;
+
+    fn spec_with_uts(uts: Option<LinuxNamespace>) -> Spec {
+        let mut spec = Spec::default();
+        let mut linux = spec.linux().clone().unwrap();
+        linux.set_namespaces(Some(uts.into_iter().collect()));
+        spec.set_linux(Some(linux));
+        spec
+    }
+
+    #[test]
+    fn test_extract_domainname() -> Result<()> {
+        let mut config = json!({ "hostname": "youki", "domainname": "example.com" });
+        let extensions = extract_extensions(&mut config)?;
+        assert_eq!(extensions.domainname, Some("example.com".to_owned()));
+        assert_eq!(extensions.time_namespace, None);
+        assert_eq!(config, json!({ "hostname": "youki" }));
+
+        assert!(extract_extensions(&mut json!({ "domainname": 1 })).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_extract_personality() -> Result<()> {
+        let mut config = json!({ "linux": { "personality": { "domain": "LINUX32" } } });
+        let extensions = extract_extensions(&mut config)?;
+        assert_eq!(
+            extensions.personality,
+            Some(Personality {
+                domain: PersonalityDomain::Linux32,
+                flags: vec![],
+            })
+        );
+        assert_eq!(config, json!({ "linux": {} }));
+
+        let mut config = json!({ "linux": { "personality": { "domain": "LINUX64" } } });
+        assert!(extract_extensions(&mut config).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_extract_scheduler() -> Result<()> {
+        let mut config = json!({
+            "process": { "args": ["sh"], "scheduler": { "policy": "SCHED_BATCH", "nice": 5 } },
+        });
+        let extensions = extract_extensions(&mut config)?;
+        assert_eq!(extensions.scheduler.map(|s| s.nice), Some(5));
+        assert_eq!(config, json!({ "process": { "args": ["sh"] } }));
+        Ok(())
+    }
+
+    #[test]
+    fn test_extract_io_priority() -> Result<()> {
+        let mut config = json!({
+            "process": { "ioPriority": { "class": "IOPRIO_CLASS_RT", "priority": 1 } },
+        });
+        let extensions = extract_extensions(&mut config)?;
+        assert_eq!(
+            extensions.io_priority,
+            Some(IoPriority {
+                class: IoPriorityClass::Realtime,
+                priority: 1,
+            })
+        );
+        assert_eq!(config, json!({ "process": {} }));
+        Ok(())
+    }
+
+    #[test]
+    fn test_domainname_requires_uts_namespace() {
+        let extensions = SpecExtensions {
+            domainname: Some("example.com".to_owned()),
+            ..Default::default()
+        };
+
+        let new_uts = LinuxNamespaceBuilder::default()
+            .typ(LinuxNamespaceType::Uts)
+            .build()
+            .unwrap();
+        assert!(extensions.validate(&spec_with_uts(Some(new_uts))).is_ok());
+
+        let joined_uts = LinuxNamespaceBuilder::default()
+            .typ(LinuxNamespaceType::Uts)
+            .path("/proc/1/ns/uts")
+            .build()
+            .unwrap();
+        assert!(extensions
+            .validate(&spec_with_uts(Some(joined_uts)))
+            .is_ok());
+        assert!(extensions.validate(&spec_with_uts(None)).is_err());
+    }
+}
@@ -1,8 +1,9 @@
 //! Implements Command trait for Linux systems
 #[cfg_attr(coverage, no_coverage)]
-use std::ffi::{CStr, OsStr};
+use std::ffi::{CStr, CString, OsStr};
 use std::os::unix::ffi::OsStrExt;
 use std::os::unix::fs::symlink;
+use std::os::unix::io::RawFd;
 use std::sync::Arc;
 use std::{any::Any, mem, path::Path, ptr};
 
@@ -20,10 +21,38 @@ use nix::{
 };
 
 use oci_spec::runtime::LinuxRlimit;
+use procfs::process::Process;
 
 use super::Syscall;
 use crate::capabilities;
 
+// Constants and types for the new mount api, which are not yet available in libc
+const OPEN_TREE_CLONE: libc::c_uint = 1;
+const AT_RECURSIVE: libc::c_uint = 0x8000;
+const MOUNT_ATTR_IDMAP: u64 = 0x0010_0000;
+const MOVE_MOUNT_F_EMPTY_PATH: libc::c_uint = 0x0000_0004;
+
+// Mount attributes of mount_setattr(2), see include/uapi/linux/mount.h
+pub const MOUNT_ATTR_RDONLY: u64 = 0x0000_0001;
+pub const MOUNT_ATTR_NOSUID: u64 = 0x0000_0002;
+pub const MOUNT_ATTR_NODEV: u64 = 0x0000_0004;
+pub const MOUNT_ATTR_NOEXEC: u64 = 0x0000_0008;
+/// Mask of the atime mode, which has to be cleared to change the mode
+pub const MOUNT_ATTR__ATIME: u64 = 0x0000_0070;
+pub const MOUNT_ATTR_RELATIME: u64 = 0x0000_0000;
+pub const MOUNT_ATTR_NOATIME: u64 = 0x0000_0010;
+pub const MOUNT_ATTR_STRICTATIME: u64 = 0x0000_0020;
+pub const MOUNT_ATTR_NODIRATIME: u64 = 0x0000_0080;
+pub const MOUNT_ATTR_NOSYMFOLLOW: u64 = 0x0020_0000;
+
+#[repr(C)]
+struct MountAttr {
+    attr_set: u64,
+    attr_clr: u64,
+    propagation: u64,
+    userns_fd: u64,
+}
+
 /// Empty structure to implement Command trait for
 #[derive(Clone)]
 pub struct LinuxSyscall;
@@ -36,6 +65,59 @@ impl LinuxSyscall {
         T::from(OsStr::from_bytes(CStr::from_ptr(p).to_bytes()))
     }
 
+    /// Creates a detached copy of the source mount and attaches the id mapping
+    /// of the user namespace to it. The returned file descriptor refers to the
+    /// detached mount, which can be attached to a target with move_mount.
+    /// See https://man7.org/linux/man-pages/man2/mount_setattr.2.html
+    pub(crate) fn open_idmapped_tree(
+        source: &Path,
+        userns_fd: RawFd,
+        recursive: bool,
+    ) -> Result<RawFd> {
+        let source = CString::new(source.as_os_str().as_bytes())?;
+        let empty = CString::default();
+        let recursive = if recursive { AT_RECURSIVE } else { 0 };
+
+        let tree_fd = unsafe {
+            libc::syscall(
+                libc::SYS_open_tree,
+                libc::AT_FDCWD,
+                source.as_ptr(),
+                OPEN_TREE_CLONE | libc::O_CLOEXEC as libc::c_uint | recursive,
+            )
+        };
+        let tree_fd = match Errno::result(tree_fd) {
+            Ok(fd) => fd as RawFd,
+            Err(Errno::ENOSYS) => {
+                bail!("idmapped mounts require open_tree(2), which is available since Linux 5.2")
+            }
+            Err(err) => bail!("failed to clone mount tree: {}", err),
+        };
+
+        let attr = MountAttr {
+            attr_set: MOUNT_ATTR_IDMAP,
+            attr_clr: 0,
+            propagation: 0,
+            userns_fd: userns_fd as u64,
+        };
+        let result = unsafe {
+            libc::syscall(
+                libc::SYS_mount_setattr,
+                tree_fd,
+                empty.as_ptr(),
+                libc::AT_EMPTY_PATH as libc::c_uint | recursive,
+                &attr as *const MountAttr,
+                mem::size_of::<MountAttr>(),
+            )
+        };
+        if let Err(err) = Errno::result(result) {
+            let _ = unistd::close(tree_fd);
+            bail!("failed to set id mapping on mount: {}", err);
+        }
+
+        Ok(tree_fd)
+    }
+
     /// Reads data from the `c_passwd` and returns it as a `User`.
     unsafe fn passwd_to_user(passwd: libc::passwd) -> Arc<OsStr> {
         let name: Arc<OsStr> = Self::from_raw_buf(passwd.pw_name);
@@ -85,6 +167,64 @@ impl Syscall for LinuxSyscall {
         Ok(())
     }
 
+    fn move_rootfs(&self, path: &Path) -> Result<()> {
+        // pivot_root fails if the current root is not a mount point, e.g. when
+        // running from an initramfs. Instead the new root is moved on top of
+        // the original root, which can not be unmounted this way, and the
+        // process is jailed with chroot. Unlike pivot_root this can be escaped
+        // with CAP_SYS_CHROOT, so it is only used if requested explicitly.
+        // Full procfs and sysfs mounts of the host stay reachable below the
+        // original root, so they are detached or masked before the move.
+        let mount_infos = Process::myself()?.mountinfo()?;
+        for mount_info in mount_infos.iter().filter(|m| {
+            m.root == "/"
+                && (m.fs_type == "proc" || m.fs_type == "sysfs")
+                && !m.mount_point.starts_with(path)
+        }) {
+            let mount_point = &mount_info.mount_point;
+            // Make the mount rslave to avoid propagating the unmount event to
+            // the host mount namespace, same as in pivot_rootfs.
+            match mount(
+                None::<&str>,
+                mount_point,
+                None::<&str>,
+                MsFlags::MS_SLAVE | MsFlags::MS_REC,
+                None::<&str>,
+            ) {
+                Ok(_) => {}
+                Err(Errno::ENOENT) => continue,
+                Err(err) => bail!("failed to make {:?} rslave: {}", mount_point, err),
+            }
+
+            match umount2(mount_point, MntFlags::MNT_DETACH) {
+                Ok(_) => {}
+                // without the privileges to unmount, e.g. in rootless mode,
+                // the mount is covered instead
+                Err(Errno::EINVAL) | Err(Errno::EPERM) => mount(
+                    Some("tmpfs"),
+                    mount_point,
+                    Some("tmpfs"),
+                    MsFlags::empty(),
+                    None::<&str>,
+                )
+                .map_err(|err| anyhow!("failed to mask {:?}: {}", mount_point, err))?,
+                Err(err) => bail!("failed to unmount {:?}: {}", mount_point, err),
+            }
+        }
+
+        unistd::chdir(path)?;
+        mount(
+            Some(path),
+            "/",
+            None::<&str>,
+            MsFlags::MS_MOVE,
+            None::<&str>,
+        )?;
+        unistd::chroot(".")?;
+        unistd::chdir("/")?;
+        Ok(())
+    }
+
     /// Set namespace for process
     fn set_ns(&self, rawfd: i32, nstype: CloneFlags) -> Result<()> {
         nix::sched::setns(rawfd, nstype)?;
@@ -136,7 +276,7 @@ impl Syscall for LinuxSyscall {
                         Capability::CAP_PERFMON
                         | Capability::CAP_CHECKPOINT_RESTORE
                         | Capability::CAP_BPF => {
-                            log::warn!("{:?} is not supported.", c);
+                            tracing::warn!("{:?} is not supported.", c);
                             continue;
                         }
                         _ => caps::drop(None, CapSet::Bounding, *c)?,
@@ -158,6 +298,19 @@ impl Syscall for LinuxSyscall {
         Ok(())
     }
 
+    /// Sets NIS domain name for process
+    fn set_domainname(&self, domainname: &str) -> Result<()> {
+        let c_domainname = CString::new(domainname)?;
+        if unsafe { libc::setdomainname(c_domainname.as_ptr(), domainname.len()) } != 0 {
+            bail!(
+                "Failed to set {} as domainname. {:?}",
+                domainname,
+                Errno::last()
+            )
+        }
+        Ok(())
+    }
+
     /// Sets resource limit for process
     fn set_rlimit(&self, rlimit: &LinuxRlimit) -> Result<()> {
         let rlim = &libc::rlimit {
@@ -225,6 +378,59 @@ impl Syscall for LinuxSyscall {
         }
     }
 
+    fn move_mount(&self, from_fd: RawFd, target: &Path) -> Result<()> {
+        let c_target = CString::new(target.as_os_str().as_bytes())?;
+        let empty = CString::default();
+        let result = unsafe {
+            libc::syscall(
+                libc::SYS_move_mount,
+                from_fd,
+                empty.as_ptr(),
+                libc::AT_FDCWD,
+                c_target.as_ptr(),
+                MOVE_MOUNT_F_EMPTY_PATH,
+            )
+        };
+        match Errno::result(result) {
+            Ok(_) => Ok(()),
+            Err(err) => bail!("failed to move mount to {:?}: {}", target, err),
+        }
+    }
+
+    fn mount_setattr(
+        &self,
+        path: &Path,
+        attr_set: u64,
+        attr_clr: u64,
+        recursive: bool,
+    ) -> Result<()> {
+        let path = CString::new(path.as_os_str().as_bytes())?;
+        let attr = MountAttr {
+            attr_set,
+            attr_clr,
+            propagation: 0,
+            userns_fd: 0,
+        };
+        let flags = if recursive { AT_RECURSIVE } else { 0 };
+        let result = unsafe {
+            libc::syscall(
+                libc::SYS_mount_setattr,
+                libc::AT_FDCWD,
+                path.as_ptr(),
+                flags,
+                &attr as *const MountAttr,
+                mem::size_of::<MountAttr>(),
+            )
+        };
+        match Errno::result(result) {
+            Ok(_) => Ok(()),
+            Err(Errno::ENOSYS) => bail!(
+                "recursive mount options require mount_setattr(2), which is available since Linux 5.12"
+            ),
+            Err(err) => Err(anyhow!(err)),
+        }
+    }
+
     fn symlink(&self, original: &Path, link: &Path) -> Result<()> {
         match symlink(original, link) {
             Ok(_) => Ok(()),
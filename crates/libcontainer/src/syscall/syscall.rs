@@ -1,7 +1,7 @@
 //! An interface trait so that rest of Youki can call
 //! necessary functions without having to worry about their
 //! implementation details
-use std::{any::Any, ffi::OsStr, path::Path, sync::Arc};
+use std::{any::Any, ffi::OsStr, os::unix::io::RawFd, path::Path, sync::Arc};
 
 use anyhow::Result;
 use caps::{CapSet, CapsHashSet};
@@ -21,12 +21,14 @@ use crate::syscall::{linux::LinuxSyscall, test::TestHelperSyscall};
 pub trait Syscall {
     fn as_any(&self) -> &dyn Any;
     fn pivot_rootfs(&self, path: &Path) -> Result<()>;
+    fn move_rootfs(&self, path: &Path) -> Result<()>;
     fn chroot(&self, path: &Path) -> Result<()>;
     fn set_ns(&self, rawfd: i32, nstype: CloneFlags) -> Result<()>;
     fn set_id(&self, uid: Uid, gid: Gid) -> Result<()>;
     fn unshare(&self, flags: CloneFlags) -> Result<()>;
     fn set_capability(&self, cset: CapSet, value: &CapsHashSet) -> Result<()>;
     fn set_hostname(&self, hostname: &str) -> Result<()>;
+    fn set_domainname(&self, domainname: &str) -> Result<()>;
     fn set_rlimit(&self, rlimit: &LinuxRlimit) -> Result<()>;
     fn get_pwuid(&self, uid: u32) -> Option<Arc<OsStr>>;
     fn mount(
@@ -37,6 +39,14 @@ pub trait Syscall {
         flags: MsFlags,
         data: Option<&str>,
     ) -> Result<()>;
+    fn move_mount(&self, from_fd: RawFd, target: &Path) -> Result<()>;
+    fn mount_setattr(
+        &self,
+        path: &Path,
+        attr_set: u64,
+        attr_clr: u64,
+        recursive: bool,
+    ) -> Result<()>;
     fn symlink(&self, original: &Path, link: &Path) -> Result<()>;
     fn mknod(&self, path: &Path, kind: SFlag, perm: Mode, dev: u64) -> Result<()>;
     fn chown(&self, path: &Path, owner: Option<Uid>, group: Option<Gid>) -> Result<()>;
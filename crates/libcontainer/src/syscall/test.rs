@@ -3,6 +3,8 @@ use std::{
     cell::{Ref, RefCell, RefMut},
     collections::HashMap,
     ffi::{OsStr, OsString},
+    fs,
+    os::unix::io::RawFd,
     path::{Path, PathBuf},
     sync::Arc,
 };
@@ -28,6 +30,20 @@ pub struct MountArgs {
     pub data: Option<String>,
 }
 
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct MoveMountArgs {
+    pub from_fd: RawFd,
+    pub target: PathBuf,
+}
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct MountSetattrArgs {
+    pub path: PathBuf,
+    pub attr_set: u64,
+    pub attr_clr: u64,
+    pub recursive: bool,
+}
+
 #[derive(Clone, PartialEq, Eq, Debug)]
 pub struct MknodArgs {
     pub path: PathBuf,
@@ -43,6 +59,22 @@ pub struct ChownArgs {
     pub group: Option<Gid>,
 }
 
+/// Paths are accessed through file descriptors in procfs to prevent symlink
+/// attacks. The mocks record the path, which the file descriptor refers to,
+/// as the file descriptor is usually closed once the call returns.
+fn resolve_fd_path(path: &Path) -> PathBuf {
+    let mut components = path.components();
+    let fd_path: PathBuf = components.by_ref().take(5).collect();
+    if fd_path.parent() != Some(Path::new("/proc/self/fd")) {
+        return path.to_path_buf();
+    }
+
+    match fs::read_link(&fd_path) {
+        Ok(resolved) => resolved.join(components.as_path()),
+        Err(_) => path.to_path_buf(),
+    }
+}
+
 #[derive(Default)]
 struct Mock {
     values: Vec<Box<dyn Any>>,
@@ -52,13 +84,18 @@ struct Mock {
 
 #[derive(PartialEq, Eq, Hash, Copy, Clone)]
 pub enum ArgName {
+    PivotRootfs,
+    MoveRootfs,
     Namespace,
     Unshare,
     Mount,
+    MoveMount,
+    MountSetattr,
     Symlink,
     Mknod,
     Chown,
     Hostname,
+    Domainname,
     Groups,
     Capability,
 }
@@ -66,13 +103,18 @@ pub enum ArgName {
 impl ArgName {
     fn iterator() -> impl Iterator<Item = ArgName> {
         [
+            ArgName::PivotRootfs,
+            ArgName::MoveRootfs,
             ArgName::Namespace,
             ArgName::Unshare,
             ArgName::Mount,
+            ArgName::MoveMount,
+            ArgName::MountSetattr,
             ArgName::Symlink,
             ArgName::Mknod,
             ArgName::Chown,
             ArgName::Hostname,
+            ArgName::Domainname,
             ArgName::Groups,
             ArgName::Capability,
         ]
@@ -143,8 +185,14 @@ impl Syscall for TestHelperSyscall {
         self
     }
 
-    fn pivot_rootfs(&self, _path: &Path) -> anyhow::Result<()> {
-        unimplemented!()
+    fn pivot_rootfs(&self, path: &Path) -> anyhow::Result<()> {
+        self.mocks
+            .act(ArgName::PivotRootfs, Box::new(path.to_path_buf()))
+    }
+
+    fn move_rootfs(&self, path: &Path) -> anyhow::Result<()> {
+        self.mocks
+            .act(ArgName::MoveRootfs, Box::new(path.to_path_buf()))
     }
 
     fn set_ns(&self, rawfd: i32, nstype: CloneFlags) -> anyhow::Result<()> {
@@ -170,6 +218,11 @@ impl Syscall for TestHelperSyscall {
             .act(ArgName::Hostname, Box::new(hostname.to_owned()))
     }
 
+    fn set_domainname(&self, domainname: &str) -> anyhow::Result<()> {
+        self.mocks
+            .act(ArgName::Domainname, Box::new(domainname.to_owned()))
+    }
+
     fn set_rlimit(&self, _rlimit: &LinuxRlimit) -> anyhow::Result<()> {
         todo!()
     }
@@ -193,8 +246,8 @@ impl Syscall for TestHelperSyscall {
         self.mocks.act(
             ArgName::Mount,
             Box::new(MountArgs {
-                source: source.map(|x| x.to_owned()),
-                target: target.to_owned(),
+                source: source.map(resolve_fd_path),
+                target: resolve_fd_path(target),
                 fstype: fstype.map(|x| x.to_owned()),
                 flags,
                 data: data.map(|x| x.to_owned()),
@@ -202,6 +255,34 @@ impl Syscall for TestHelperSyscall {
         )
     }
 
+    fn move_mount(&self, from_fd: RawFd, target: &Path) -> anyhow::Result<()> {
+        self.mocks.act(
+            ArgName::MoveMount,
+            Box::new(MoveMountArgs {
+                from_fd,
+                target: resolve_fd_path(target),
+            }),
+        )
+    }
+
+    fn mount_setattr(
+        &self,
+        path: &Path,
+        attr_set: u64,
+        attr_clr: u64,
+        recursive: bool,
+    ) -> anyhow::Result<()> {
+        self.mocks.act(
+            ArgName::MountSetattr,
+            Box::new(MountSetattrArgs {
+                path: resolve_fd_path(path),
+                attr_set,
+                attr_clr,
+                recursive,
+            }),
+        )
+    }
+
     fn symlink(&self, original: &Path, link: &Path) -> anyhow::Result<()> {
         self.mocks.act(
             ArgName::Symlink,
@@ -213,7 +294,7 @@ impl Syscall for TestHelperSyscall {
         self.mocks.act(
             ArgName::Mknod,
             Box::new(MknodArgs {
-                path: path.to_path_buf(),
+                path: resolve_fd_path(path),
                 kind,
                 perm,
                 dev,
@@ -224,7 +305,7 @@ impl Syscall for TestHelperSyscall {
         self.mocks.act(
             ArgName::Chown,
             Box::new(ChownArgs {
-                path: path.to_path_buf(),
+                path: resolve_fd_path(path),
                 owner,
                 group,
             }),
@@ -246,6 +327,24 @@ impl TestHelperSyscall {
         self.mocks.fetch_mut(name).ret_err_times = times;
     }
 
+    pub fn get_pivot_rootfs_args(&self) -> Vec<PathBuf> {
+        self.mocks
+            .fetch(ArgName::PivotRootfs)
+            .values
+            .iter()
+            .map(|x| x.downcast_ref::<PathBuf>().unwrap().clone())
+            .collect::<Vec<PathBuf>>()
+    }
+
+    pub fn get_move_rootfs_args(&self) -> Vec<PathBuf> {
+        self.mocks
+            .fetch(ArgName::MoveRootfs)
+            .values
+            .iter()
+            .map(|x| x.downcast_ref::<PathBuf>().unwrap().clone())
+            .collect::<Vec<PathBuf>>()
+    }
+
     pub fn get_setns_args(&self) -> Vec<(i32, CloneFlags)> {
         self.mocks
             .fetch(ArgName::Namespace)
@@ -282,6 +381,24 @@ impl TestHelperSyscall {
             .collect::<Vec<MountArgs>>()
     }
 
+    pub fn get_move_mount_args(&self) -> Vec<MoveMountArgs> {
+        self.mocks
+            .fetch(ArgName::MoveMount)
+            .values
+            .iter()
+            .map(|x| x.downcast_ref::<MoveMountArgs>().unwrap().clone())
+            .collect::<Vec<MoveMountArgs>>()
+    }
+
+    pub fn get_mount_setattr_args(&self) -> Vec<MountSetattrArgs> {
+        self.mocks
+            .fetch(ArgName::MountSetattr)
+            .values
+            .iter()
+            .map(|x| x.downcast_ref::<MountSetattrArgs>().unwrap().clone())
+            .collect::<Vec<MountSetattrArgs>>()
+    }
+
     pub fn get_symlink_args(&self) -> Vec<(PathBuf, PathBuf)> {
         self.mocks
             .fetch(ArgName::Symlink)
@@ -318,6 +435,15 @@ impl TestHelperSyscall {
             .collect::<Vec<String>>()
     }
 
+    pub fn get_domainname_args(&self) -> Vec<String> {
+        self.mocks
+            .fetch(ArgName::Domainname)
+            .values
+            .iter()
+            .map(|x| x.downcast_ref::<String>().unwrap().clone())
+            .collect::<Vec<String>>()
+    }
+
     pub fn get_groups_args(&self) -> Vec<Vec<Gid>> {
         self.mocks
             .fetch(ArgName::Groups)
@@ -0,0 +1,186 @@
+//! Kernel parameters (sysctls) of a container. Only sysctls which are
+//! namespaced by the kernel can be set, as all other ones would change the
+//! configuration of the host.
+use anyhow::{bail, Context, Result};
+use oci_spec::runtime::{LinuxNamespaceType, Spec};
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Component, Path, PathBuf},
+};
+
+const PROC_SYS: &str = "/proc/sys";
+
+// sysctls which belong to the ipc namespace
+const IPC_SYSCTLS: &[&str] = &[
+    "kernel.msgmax",
+    "kernel.msgmnb",
+    "kernel.msgmni",
+    "kernel.sem",
+    "kernel.shmall",
+    "kernel.shmmax",
+    "kernel.shmmni",
+    "kernel.shm_rmid_forced",
+];
+const IPC_SYSCTL_PREFIX: &str = "fs.mqueue.";
+const NET_SYSCTL_PREFIX: &str = "net.";
+// sysctls which belong to the uts namespace
+const UTS_SYSCTLS: &[&str] = &["kernel.domainname"];
+
+/// Ensures that all sysctls of the spec belong to a namespace which is
+/// owned by the container
+pub fn validate(spec: &Spec) -> Result<()> {
+    let linux = match spec.linux() {
+        Some(linux) => linux,
+        None => return Ok(()),
+    };
+    let sysctls = match linux.sysctl() {
+        Some(sysctls) => sysctls,
+        None => return Ok(()),
+    };
+
+    let has_namespace = |typ: LinuxNamespaceType| {
+        linux.namespaces().as_ref().map_or(false, |namespaces| {
+            namespaces.iter().any(|ns| ns.typ() == typ)
+        })
+    };
+
+    for key in sysctls.keys() {
+        sysctl_path(key)?;
+        let namespace = match namespace_of(key) {
+            Some(namespace) => namespace,
+            None if key == "kernel.hostname" => {
+                bail!(
+                    "sysctl {} is not allowed, use the hostname field instead",
+                    key
+                )
+            }
+            None => bail!(
+                "sysctl {} is not namespaced and would change the configuration of the host",
+                key
+            ),
+        };
+
+        if !has_namespace(namespace) {
+            bail!(
+                "sysctl {} requires a {:?} namespace, but the container uses the one of the host",
+                key,
+                namespace
+            );
+        }
+    }
+
+    Ok(())
+}
+
+/// Writes the sysctls to /proc/sys. This has to happen after the namespaces of
+/// the container have been entered, as the values are applied to the
+/// namespaces of the calling process.
+pub fn apply(kernel_params: &HashMap<String, String>) -> Result<()> {
+    for (kernel_param, value) in kernel_params {
+        let path = sysctl_path(kernel_param)?;
+        tracing::debug!(
+            "apply value {} to kernel parameter {}.",
+            value,
+            kernel_param
+        );
+        fs::write(path, value.as_bytes())
+            .with_context(|| format!("failed to set sysctl {}={}", kernel_param, value))?;
+    }
+
+    Ok(())
+}
+
+/// Returns the namespace the sysctl belongs to
+fn namespace_of(key: &str) -> Option<LinuxNamespaceType> {
+    if key.starts_with(NET_SYSCTL_PREFIX) {
+        return Some(LinuxNamespaceType::Network);
+    }
+
+    if IPC_SYSCTLS.contains(&key) || key.starts_with(IPC_SYSCTL_PREFIX) {
+        return Some(LinuxNamespaceType::Ipc);
+    }
+
+    if UTS_SYSCTLS.contains(&key) {
+        return Some(LinuxNamespaceType::Uts);
+    }
+
+    None
+}
+
+fn sysctl_path(key: &str) -> Result<PathBuf> {
+    let relative = PathBuf::from(key.replace('.', "/"));
+    if key.is_empty()
+        || relative
+            .components()
+            .any(|c| !matches!(c, Component::Normal(_)))
+    {
+        bail!("invalid sysctl {}", key);
+    }
+
+    Ok(Path::new(PROC_SYS).join(relative))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use oci_spec::runtime::LinuxNamespaceBuilder;
+
+    fn spec_with_sysctl(key: &str, namespaces: &[LinuxNamespaceType]) -> Spec {
+        let mut spec = Spec::default();
+        let mut linux = spec.linux().clone().unwrap();
+        linux
+            .set_sysctl(Some(HashMap::from([(key.to_owned(), "1".to_owned())])))
+            .set_namespaces(Some(
+                namespaces
+                    .iter()
+                    .map(|&typ| LinuxNamespaceBuilder::default().typ(typ).build().unwrap())
+                    .collect(),
+            ));
+        spec.set_linux(Some(linux));
+        spec
+    }
+
+    #[test]
+    fn test_namespaced_sysctls() {
+        let tests = [
+            ("net.ipv4.ip_forward", LinuxNamespaceType::Network),
+            ("kernel.shmmax", LinuxNamespaceType::Ipc),
+            ("fs.mqueue.msg_max", LinuxNamespaceType::Ipc),
+            ("kernel.domainname", LinuxNamespaceType::Uts),
+        ];
+
+        for (key, namespace) in tests {
+            assert!(validate(&spec_with_sysctl(key, &[namespace])).is_ok());
+            assert!(validate(&spec_with_sysctl(key, &[])).is_err());
+        }
+    }
+
+    #[test]
+    fn test_host_sysctls_are_rejected() {
+        let all = [
+            LinuxNamespaceType::Network,
+            LinuxNamespaceType::Ipc,
+            LinuxNamespaceType::Uts,
+        ];
+        for key in [
+            "kernel.hostname",
+            "vm.swappiness",
+            "kernel.msg",
+            "/net/ipv4",
+        ] {
+            assert!(validate(&spec_with_sysctl(key, &all)).is_err(), "{}", key);
+        }
+    }
+
+    #[test]
+    fn test_sysctl_path() -> Result<()> {
+        assert_eq!(
+            sysctl_path("net.ipv4.ip_forward")?,
+            PathBuf::from("/proc/sys/net/ipv4/ip_forward")
+        );
+        assert!(sysctl_path("").is_err());
+        assert!(sysctl_path("/etc/passwd").is_err());
+        Ok(())
+    }
+}
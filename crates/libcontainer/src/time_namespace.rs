@@ -0,0 +1,244 @@
+//! Time namespaces virtualize the values of the monotonic and boottime clocks
+//! (see time_namespaces(7)), so that e.g. the uptime of a container can start
+//! from zero or continue after it has been migrated to another host.
+//!
+//! The version of the runtime spec which is supported by oci-spec does not
+//! know about time namespaces yet. Therefore the time namespace and
+//! `linux.timeOffsets` are extracted from the raw config before it is parsed
+//! (see spec_extensions).
+//!
+//! Unlike other namespaces, unsharing the time namespace only affects the
+//! children of the calling process. The offsets have to be written to
+//! /proc/self/timens_offsets before the first process has been forked into
+//! the new namespace.
+
+use anyhow::{bail, Context, Result};
+use nix::{errno::Errno, fcntl, sched, sys::stat, unistd};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::{collections::HashMap, fs, os::unix::fs::MetadataExt, path::PathBuf};
+
+// not yet exposed by nix
+const CLONE_NEWTIME: libc::c_int = 0x80;
+const TIMENS_OFFSETS: &str = "/proc/self/timens_offsets";
+const NAMESPACE_TYPE: &str = "time";
+const CLOCKS: &[&str] = &["monotonic", "boottime"];
+
+/// Offset of a clock in a time namespace
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TimeOffset {
+    /// Offset in seconds
+    #[serde(default)]
+    pub secs: i64,
+    /// Additional offset in nanoseconds
+    #[serde(default)]
+    pub nanosecs: u32,
+}
+
+/// Time namespace of a container
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TimeNamespace {
+    /// Existing time namespace that should be joined. A new time namespace
+    /// is created if this is not set.
+    pub path: Option<PathBuf>,
+    /// Offsets of the clocks of a new time namespace keyed by clock
+    pub offsets: HashMap<String, TimeOffset>,
+}
+
+impl TimeNamespace {
+    /// Returns the time namespace of the process with the given pid if it
+    /// differs from the time namespace of the current process
+    pub fn of_process(pid: i32) -> Result<Option<Self>> {
+        let path = PathBuf::from(format!("/proc/{}/ns/{}", pid, NAMESPACE_TYPE));
+        // time namespaces are only supported since Linux 5.6
+        if !path.exists() {
+            return Ok(None);
+        }
+
+        let current = fs::metadata(format!("/proc/self/ns/{}", NAMESPACE_TYPE))
+            .context("failed to inspect time namespace of the current process")?;
+        let target = fs::metadata(&path)
+            .with_context(|| format!("failed to inspect time namespace {:?}", path))?;
+        if current.dev() == target.dev() && current.ino() == target.ino() {
+            return Ok(None);
+        }
+
+        Ok(Some(Self {
+            path: Some(path),
+            offsets: HashMap::new(),
+        }))
+    }
+
+    /// Creates or joins the time namespace. Only processes which are forked
+    /// afterwards will be part of the namespace.
+    pub fn enter(&self) -> Result<()> {
+        match &self.path {
+            None => {
+                tracing::debug!("creating new time namespace");
+                // SAFETY: unshare does not access any memory of the process
+                Errno::result(unsafe { libc::unshare(CLONE_NEWTIME) })
+                    .context("failed to unshare time namespace")?;
+                if !self.offsets.is_empty() {
+                    fs::write(TIMENS_OFFSETS, format_offsets(&self.offsets)).with_context(
+                        || format!("failed to write time offsets to {}", TIMENS_OFFSETS),
+                    )?;
+                }
+            }
+            Some(path) => {
+                tracing::debug!("joining time namespace {:?}", path);
+                let fd = fcntl::open(path, fcntl::OFlag::O_CLOEXEC, stat::Mode::empty())
+                    .with_context(|| format!("failed to open time namespace {:?}", path))?;
+                let result = sched::setns(fd, sched::CloneFlags::empty())
+                    .with_context(|| format!("failed to join time namespace {:?}", path));
+                let _ = unistd::close(fd);
+                result?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Removes the time namespace and the time offsets from the raw config, as
+/// they cannot be represented by the spec, and validates them
+pub(crate) fn extract_time_namespace(config: &mut Value) -> Result<Option<TimeNamespace>> {
+    let linux = match config.get_mut("linux").and_then(Value::as_object_mut) {
+        Some(linux) => linux,
+        None => return Ok(None),
+    };
+
+    let offsets: HashMap<String, TimeOffset> = match linux.remove("timeOffsets") {
+        Some(offsets) => serde_json::from_value(offsets).context("invalid linux.timeOffsets")?,
+        None => HashMap::new(),
+    };
+
+    let mut namespaces = Vec::new();
+    if let Some(Value::Array(entries)) = linux.get_mut("namespaces") {
+        entries.retain(|ns| {
+            if ns.get("type").and_then(Value::as_str) == Some(NAMESPACE_TYPE) {
+                namespaces.push(ns.get("path").and_then(Value::as_str).map(PathBuf::from));
+                return false;
+            }
+            true
+        });
+    }
+
+    if namespaces.len() > 1 {
+        bail!("time namespace is specified more than once");
+    }
+
+    validate_offsets(&offsets)?;
+    match namespaces.pop() {
+        Some(path) => {
+            let path = path.filter(|p| !p.as_os_str().is_empty());
+            if path.is_some() && !offsets.is_empty() {
+                bail!("time offsets can only be set if a new time namespace is created");
+            }
+
+            Ok(Some(TimeNamespace { path, offsets }))
+        }
+        None if !offsets.is_empty() => {
+            bail!("time offsets are set, but no time namespace is created")
+        }
+        None => Ok(None),
+    }
+}
+
+fn validate_offsets(offsets: &HashMap<String, TimeOffset>) -> Result<()> {
+    for (clock, offset) in offsets {
+        if !CLOCKS.contains(&clock.as_str()) {
+            bail!("time offsets can not be set for clock {}", clock);
+        }
+        if offset.nanosecs >= 1_000_000_000 {
+            bail!(
+                "nanoseconds of the {} time offset must be less than one second",
+                clock
+            );
+        }
+    }
+
+    Ok(())
+}
+
+fn format_offsets(offsets: &HashMap<String, TimeOffset>) -> String {
+    let mut clocks: Vec<_> = offsets.iter().collect();
+    clocks.sort_by_key(|(clock, _)| clock.as_str());
+    clocks
+        .into_iter()
+        .map(|(clock, offset)| format!("{} {} {}\n", clock, offset.secs, offset.nanosecs))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_extract_time_namespace() -> Result<()> {
+        let mut config = json!({
+            "linux": {
+                "namespaces": [{ "type": "pid" }, { "type": "time" }],
+                "timeOffsets": {
+                    "monotonic": { "secs": 3600 },
+                    "boottime": { "secs": -10, "nanosecs": 500 },
+                },
+            },
+        });
+
+        let time_namespace = extract_time_namespace(&mut config)?.expect("time namespace");
+        assert_eq!(time_namespace.path, None);
+        assert_eq!(
+            time_namespace.offsets["boottime"],
+            TimeOffset {
+                secs: -10,
+                nanosecs: 500
+            }
+        );
+        assert_eq!(
+            config,
+            json!({ "linux": { "namespaces": [{ "type": "pid" }] } })
+        );
+        assert_eq!(
+            format_offsets(&time_namespace.offsets),
+            "boottime -10 500\nmonotonic 3600 0\n"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_extract_without_time_namespace() -> Result<()> {
+        let mut config = json!({ "linux": { "namespaces": [{ "type": "pid" }] } });
+        assert_eq!(extract_time_namespace(&mut config)?, None);
+        Ok(())
+    }
+
+    #[test]
+    fn test_offsets_require_new_time_namespace() {
+        let mut config = json!({
+            "linux": { "timeOffsets": { "monotonic": { "secs": 1 } } },
+        });
+        assert!(extract_time_namespace(&mut config).is_err());
+
+        let mut config = json!({
+            "linux": {
+                "namespaces": [{ "type": "time", "path": "/proc/1/ns/time" }],
+                "timeOffsets": { "monotonic": { "secs": 1 } },
+            },
+        });
+        assert!(extract_time_namespace(&mut config).is_err());
+    }
+
+    #[test]
+    fn test_invalid_time_offsets() {
+        for offsets in vec![
+            json!({ "realtime": { "secs": 1 } }),
+            json!({ "monotonic": { "nanosecs": 1_000_000_000u32 } }),
+        ] {
+            let mut config = json!({
+                "linux": { "namespaces": [{ "type": "time" }], "timeOffsets": offsets },
+            });
+            assert!(extract_time_namespace(&mut config).is_err());
+        }
+    }
+}
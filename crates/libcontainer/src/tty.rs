@@ -1,6 +1,7 @@
 //! tty (teletype) for user-system interaction
 
-use std::os::unix::fs::symlink;
+use std::fs::OpenOptions;
+use std::os::unix::fs::{symlink, OpenOptionsExt};
 use std::os::unix::io::AsRawFd;
 use std::os::unix::prelude::RawFd;
 use std::path::Path;
@@ -8,15 +9,23 @@ use std::path::Path;
 use anyhow::Context;
 use anyhow::{bail, Result};
 use nix::errno::Errno;
+use nix::fcntl::{open, OFlag};
+use nix::mount::{mount, MsFlags};
+use nix::pty::{grantpt, posix_openpt, ptsname_r, unlockpt, Winsize};
 use nix::sys::socket;
+use nix::sys::stat::Mode;
 use nix::sys::uio;
 use nix::unistd::dup2;
 use nix::unistd::{close, setsid};
+use oci_spec::runtime::Box as ConsoleSize;
+use serde::{Deserialize, Serialize};
 
 const STDIN: i32 = 0;
 const STDOUT: i32 = 1;
 const STDERR: i32 = 2;
 
+const CONSOLE_PATH: &str = "/dev/console";
+
 // TODO: Handling when there isn't console-socket.
 pub fn setup_console_socket(
     container_dir: &Path,
@@ -47,34 +56,128 @@ pub fn setup_console_socket(
     Ok(csocketfd)
 }
 
-pub fn setup_console(console_fd: &RawFd) -> Result<()> {
-    // You can also access pty master, but it is better to use the API.
-    // ref. https://github.com/containerd/containerd/blob/261c107ffc4ff681bc73988f64e3f60c32233b37/vendor/github.com/containerd/go-runc/console.go#L139-L154
-    let openpty_result =
-        nix::pty::openpty(None, None).context("could not create pseudo terminal")?;
-    let pty_name: &[u8] = b"/dev/ptmx";
-    let iov = [uio::IoVec::from_slice(pty_name)];
-    let fds = [openpty_result.master];
-    let cmsg = socket::ControlMessage::ScmRights(&fds);
-    socket::sendmsg(
-        console_fd.as_raw_fd(),
-        &iov,
-        &[cmsg],
-        socket::MsgFlags::empty(),
-        None,
+/// Header of the message, with which the pty master is sent to the console
+/// socket. The master itself is attached as SCM_RIGHTS control message.
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct ConsoleMessage {
+    /// Path of the pty slave in the container
+    pub name: String,
+}
+
+/// Allocates a pty in the mount namespace of the container, which the
+/// container process must have entered already, and sends its master to the
+/// console socket. The slave becomes the controlling terminal and the stdio
+/// of the container process. If requested, the slave is bind mounted to
+/// /dev/console as well. The window size of the pty is initialized with the
+/// console size of the process, if there is one.
+pub fn setup_console(
+    console_fd: &RawFd,
+    mount_console: bool,
+    console_size: Option<&ConsoleSize>,
+) -> Result<()> {
+    let master = posix_openpt(OFlag::O_RDWR | OFlag::O_NOCTTY | OFlag::O_CLOEXEC)
+        .context("could not create pseudo terminal")?;
+    grantpt(&master).context("could not grant access to pty slave")?;
+    unlockpt(&master).context("could not unlock pty slave")?;
+    let slave_name = ptsname_r(&master).context("could not get name of pty slave")?;
+    let slave = open(
+        slave_name.as_str(),
+        OFlag::O_RDWR | OFlag::O_NOCTTY | OFlag::O_CLOEXEC,
+        Mode::empty(),
     )
-    .context("failed to send pty master")?;
+    .with_context(|| format!("could not open pty slave {}", slave_name))?;
+
+    if let Some(size) = console_size {
+        let size = Winsize {
+            ws_row: size.height().try_into().unwrap_or(u16::MAX),
+            ws_col: size.width().try_into().unwrap_or(u16::MAX),
+            ws_xpixel: 0,
+            ws_ypixel: 0,
+        };
+        set_window_size(slave, &size).context("could not set console size")?;
+    }
+
+    if mount_console {
+        bind_console(Path::new(&slave_name))
+            .with_context(|| format!("could not mount {} to /dev/console", slave_name))?;
+    }
+
+    send_master(console_fd.as_raw_fd(), master.as_raw_fd(), &slave_name)
+        .context("failed to send pty master")?;
+    // the master is only kept open by the receiver of the console socket
+    drop(master);
 
     setsid()?;
-    if unsafe { libc::ioctl(openpty_result.slave, libc::TIOCSCTTY) } < 0 {
-        log::warn!("could not TIOCSCTTY");
+    if unsafe { libc::ioctl(slave, libc::TIOCSCTTY) } < 0 {
+        tracing::warn!("could not TIOCSCTTY");
     };
-    let slave = openpty_result.slave;
     connect_stdio(&slave, &slave, &slave).context("could not dup tty to stderr")?;
+    close(slave).context("could not close pty slave")?;
     close(console_fd.as_raw_fd()).context("could not close console socket")?;
     Ok(())
 }
 
+fn send_master(console_fd: RawFd, master: RawFd, slave_name: &str) -> Result<()> {
+    let header = serde_json::to_vec(&ConsoleMessage {
+        name: slave_name.to_owned(),
+    })?;
+    let iov = [uio::IoVec::from_slice(&header)];
+    let fds = [master];
+    let cmsg = socket::ControlMessage::ScmRights(&fds);
+    socket::sendmsg(console_fd, &iov, &[cmsg], socket::MsgFlags::empty(), None)?;
+    Ok(())
+}
+
+// Like on a regular system, /dev/console refers to the terminal of the
+// container
+fn bind_console(slave: &Path) -> Result<()> {
+    let console = Path::new(CONSOLE_PATH);
+    if !console.exists() {
+        OpenOptions::new()
+            .create(true)
+            .write(true)
+            .mode(0o666)
+            .open(console)
+            .context("could not create /dev/console")?;
+    }
+
+    mount(
+        Some(slave),
+        console,
+        None::<&str>,
+        MsFlags::MS_BIND,
+        None::<&str>,
+    )?;
+    Ok(())
+}
+
+/// Returns the window size of the terminal
+pub fn window_size(fd: RawFd) -> Result<Winsize> {
+    let mut size = Winsize {
+        ws_row: 0,
+        ws_col: 0,
+        ws_xpixel: 0,
+        ws_ypixel: 0,
+    };
+    Errno::result(unsafe { libc::ioctl(fd, libc::TIOCGWINSZ, &mut size) })
+        .context("failed to get window size")?;
+    Ok(size)
+}
+
+/// Changes the window size of the terminal, which sends SIGWINCH to its
+/// foreground process group
+pub fn set_window_size(fd: RawFd, size: &Winsize) -> Result<()> {
+    Errno::result(unsafe { libc::ioctl(fd, libc::TIOCSWINSZ, size) })
+        .context("failed to set window size")?;
+    Ok(())
+}
+
+/// Applies the window size of one terminal to another, e.g. of the terminal
+/// of youki to the pty of the container once it has been resized
+pub fn copy_window_size(from: RawFd, to: RawFd) -> Result<()> {
+    set_window_size(to, &window_size(from)?)
+}
+
 fn connect_stdio(stdin: &RawFd, stdout: &RawFd, stderr: &RawFd) -> Result<()> {
     dup2(stdin.as_raw_fd(), STDIN)?;
     dup2(stdout.as_raw_fd(), STDOUT)?;
@@ -93,6 +196,8 @@ mod tests {
     use std::os::unix::net::UnixListener;
     use std::path::PathBuf;
 
+    use nix::pty::openpty;
+    use oci_spec::runtime::BoxBuilder;
     use serial_test::serial;
 
     use crate::utils::{create_temp_dir, TempDir};
@@ -147,14 +252,57 @@ mod tests {
 
     #[test]
     #[serial]
-    fn test_setup_console() {
-        let init = setup("test_setup_console");
-        assert!(init.is_ok());
-        let (testdir, rundir_path, socket_path) = init.unwrap();
-        let lis = UnixListener::bind(Path::join(&testdir, "console-socket"));
-        assert!(lis.is_ok());
-        let fd = setup_console_socket(&rundir_path, &socket_path, CONSOLE_SOCKET);
-        let status = setup_console(&fd.unwrap());
-        assert!(status.is_ok());
+    fn test_setup_console() -> Result<()> {
+        let (testdir, rundir_path, socket_path) = setup("test_setup_console")?;
+        let lis = UnixListener::bind(Path::join(&testdir, "console-socket"))?;
+        let fd = setup_console_socket(&rundir_path, &socket_path, CONSOLE_SOCKET)?;
+        let size = BoxBuilder::default().height(24u64).width(80u64).build()?;
+        setup_console(&fd, false, Some(&size))?;
+
+        let (conn, _) = lis.accept()?;
+        let mut buf = [0u8; 256];
+        let iov = [uio::IoVec::from_mut_slice(&mut buf)];
+        let mut cmsg_buf = nix::cmsg_space!([RawFd; 1]);
+        let msg = socket::recvmsg(
+            conn.as_raw_fd(),
+            &iov,
+            Some(&mut cmsg_buf),
+            socket::MsgFlags::empty(),
+        )?;
+        let master = msg
+            .cmsgs()
+            .find_map(|cmsg| match cmsg {
+                socket::ControlMessageOwned::ScmRights(fds) => fds.first().copied(),
+                _ => None,
+            })
+            .expect("pty master should be sent");
+        let message: ConsoleMessage = serde_json::from_slice(&buf[..msg.bytes])?;
+        assert!(message.name.starts_with("/dev/pts/"));
+        let size = window_size(master)?;
+        assert_eq!((size.ws_row, size.ws_col), (24, 80));
+        close(master)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_copy_window_size() -> Result<()> {
+        let from = openpty(None, None)?;
+        let to = openpty(None, None)?;
+        let size = Winsize {
+            ws_row: 50,
+            ws_col: 120,
+            ws_xpixel: 0,
+            ws_ypixel: 0,
+        };
+        set_window_size(from.slave, &size)?;
+
+        copy_window_size(from.master, to.master)?;
+
+        let copied = window_size(to.slave)?;
+        assert_eq!((copied.ws_row, copied.ws_col), (50, 120));
+        for fd in [from.master, from.slave, to.master, to.slave] {
+            close(fd)?;
+        }
+        Ok(())
     }
 }
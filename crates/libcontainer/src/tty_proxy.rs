@@ -0,0 +1,319 @@
+//! Relay between the stdio of youki and the terminal of a container, which is
+//! used if youki stays in the foreground and no console socket is given by the
+//! caller. The pty master of the container is received through a console
+//! socket of youki itself. Input is passed to the container unchanged, so the
+//! terminal of youki is put into raw mode and the pty of the container handles
+//! line editing and signal characters like ^C.
+use anyhow::{bail, Context, Result};
+use nix::{
+    errno::Errno,
+    fcntl::{fcntl, FcntlArg, OFlag},
+    sys::{
+        epoll::{
+            epoll_create1, epoll_ctl, epoll_wait, EpollCreateFlags, EpollEvent, EpollFlags, EpollOp,
+        },
+        socket::{recvmsg, ControlMessageOwned, MsgFlags},
+        termios::{self, SetArg, Termios},
+        uio::IoVec,
+    },
+    unistd::{self, close, isatty},
+};
+use std::{
+    fs,
+    os::unix::{io::AsRawFd, io::RawFd, net::UnixListener},
+    path::{Path, PathBuf},
+};
+
+use crate::tty::ConsoleMessage;
+
+const STDIN: RawFd = libc::STDIN_FILENO;
+const STDOUT: RawFd = libc::STDOUT_FILENO;
+
+/// End of transmission, which is the end of file for a terminal
+const EOT: u8 = 0x04;
+
+/// Console socket, to which the container process sends its pty master
+pub struct ConsoleSocket {
+    listener: UnixListener,
+    path: PathBuf,
+}
+
+impl ConsoleSocket {
+    pub fn new<P: Into<PathBuf>>(path: P) -> Result<Self> {
+        let path = path.into();
+        let listener = UnixListener::bind(&path)
+            .with_context(|| format!("failed to bind console socket {:?}", path))?;
+        Ok(Self { listener, path })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Waits for the pty master of the container process
+    pub fn receive_master(&self) -> Result<RawFd> {
+        let (stream, _) = self
+            .listener
+            .accept()
+            .context("failed to accept console connection")?;
+        let mut header = [0u8; 4096];
+        let iov = [IoVec::from_mut_slice(&mut header)];
+        let mut cmsg_buf = nix::cmsg_space!([RawFd; 1]);
+        let msg = recvmsg(
+            stream.as_raw_fd(),
+            &iov,
+            Some(&mut cmsg_buf),
+            MsgFlags::MSG_CMSG_CLOEXEC,
+        )
+        .context("failed to receive pty master")?;
+        let master = msg.cmsgs().find_map(|cmsg| match cmsg {
+            ControlMessageOwned::ScmRights(fds) => fds.first().copied(),
+            _ => None,
+        });
+        let bytes = msg.bytes;
+
+        let master = match master {
+            Some(master) => master,
+            None => bail!("console message does not contain a pty master"),
+        };
+        let message: ConsoleMessage =
+            serde_json::from_slice(&header[..bytes]).context("failed to parse console message")?;
+        tracing::debug!("received pty master of {}", message.name);
+        Ok(master)
+    }
+}
+
+impl Drop for ConsoleSocket {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+/// Terminal in raw mode, whose previous settings are restored on drop
+struct RawTerminal {
+    fd: RawFd,
+    original: Termios,
+}
+
+impl RawTerminal {
+    /// Returns None if the file descriptor does not refer to a terminal
+    fn new(fd: RawFd) -> Result<Option<Self>> {
+        if !isatty(fd).unwrap_or(false) {
+            return Ok(None);
+        }
+
+        let original = termios::tcgetattr(fd).context("failed to get terminal settings")?;
+        let mut raw = original.clone();
+        termios::cfmakeraw(&mut raw);
+        termios::tcsetattr(fd, SetArg::TCSANOW, &raw).context("failed to set raw mode")?;
+        Ok(Some(Self { fd, original }))
+    }
+}
+
+impl Drop for RawTerminal {
+    fn drop(&mut self) {
+        if let Err(err) = termios::tcsetattr(self.fd, SetArg::TCSANOW, &self.original) {
+            tracing::warn!("failed to restore terminal settings: {}", err);
+        }
+    }
+}
+
+/// Relays data between the stdio of youki and the pty master of the
+/// container, until a signal is available on the signalfd of the caller
+pub struct TtyProxy {
+    epoll: RawFd,
+    master: RawFd,
+    signal_fd: RawFd,
+    _terminal: Option<RawTerminal>,
+}
+
+impl TtyProxy {
+    pub fn new(master: RawFd, signal_fd: RawFd) -> Result<Self> {
+        let epoll = epoll_create1(EpollCreateFlags::EPOLL_CLOEXEC)
+            .context("failed to create epoll instance")?;
+        let mut proxy = Self {
+            epoll,
+            master,
+            signal_fd,
+            _terminal: None,
+        };
+
+        for fd in [signal_fd, master] {
+            proxy.watch(fd)?;
+        }
+        // regular files, like /dev/null, can not be watched, they are
+        // treated as if their end had already been reached
+        match proxy.watch(STDIN) {
+            Err(err) if matches!(err.downcast_ref::<Errno>(), Some(Errno::EPERM)) => {
+                tracing::debug!("stdin can not be relayed to the container")
+            }
+            result => result?,
+        }
+
+        proxy._terminal = RawTerminal::new(STDIN)?;
+        Ok(proxy)
+    }
+
+    /// Relays data until a signal is available on the signalfd
+    pub fn wait_for_signal(&mut self) -> Result<()> {
+        let mut events = [EpollEvent::empty(); 3];
+        loop {
+            let n = match epoll_wait(self.epoll, &mut events, -1) {
+                Ok(n) => n,
+                Err(Errno::EINTR) => continue,
+                Err(err) => return Err(err).context("failed to wait for tty events"),
+            };
+
+            let mut signaled = false;
+            for event in &events[..n] {
+                match event.data() as RawFd {
+                    fd if fd == self.signal_fd => signaled = true,
+                    fd if fd == self.master => {
+                        if !relay(self.master, STDOUT)? {
+                            self.unwatch(self.master)?;
+                        }
+                    }
+                    STDIN => {
+                        if !relay(STDIN, self.master)? {
+                            self.unwatch(STDIN)?;
+                            // the container sees the end of its input as well
+                            write_all(self.master, &[EOT])?;
+                        }
+                    }
+                    _ => {}
+                }
+            }
+
+            if signaled {
+                return Ok(());
+            }
+        }
+    }
+
+    /// Relays the output, which the container has written before it exited
+    pub fn flush(&mut self) -> Result<()> {
+        let flags = fcntl(self.master, FcntlArg::F_GETFL)?;
+        fcntl(
+            self.master,
+            FcntlArg::F_SETFL(OFlag::from_bits_truncate(flags) | OFlag::O_NONBLOCK),
+        )?;
+
+        // processes of the container may still have the pty open, so there
+        // may not be an end
+        let mut buf = [0u8; 4096];
+        loop {
+            match unistd::read(self.master, &mut buf) {
+                Ok(0) | Err(Errno::EIO | Errno::EAGAIN) => return Ok(()),
+                Ok(n) => write_all(STDOUT, &buf[..n])?,
+                Err(Errno::EINTR) => continue,
+                Err(err) => return Err(err).context("failed to read from pty master"),
+            }
+        }
+    }
+
+    fn watch(&self, fd: RawFd) -> Result<()> {
+        let mut event = EpollEvent::new(EpollFlags::EPOLLIN, fd as u64);
+        epoll_ctl(self.epoll, EpollOp::EpollCtlAdd, fd, &mut event)
+            .with_context(|| format!("failed to watch fd {}", fd))
+    }
+
+    fn unwatch(&self, fd: RawFd) -> Result<()> {
+        epoll_ctl(
+            self.epoll,
+            EpollOp::EpollCtlDel,
+            fd,
+            None::<&mut EpollEvent>,
+        )
+        .with_context(|| format!("failed to stop watching fd {}", fd))
+    }
+}
+
+impl Drop for TtyProxy {
+    fn drop(&mut self) {
+        let _ = close(self.epoll);
+    }
+}
+
+/// Copies the available data. Returns false once the end has been reached,
+/// which is reported as EIO by a pty master after all its slaves have been
+/// closed.
+fn relay(from: RawFd, to: RawFd) -> Result<bool> {
+    let mut buf = [0u8; 4096];
+    let n = match unistd::read(from, &mut buf) {
+        Ok(0) | Err(Errno::EIO) => return Ok(false),
+        Ok(n) => n,
+        Err(Errno::EAGAIN | Errno::EINTR) => return Ok(true),
+        Err(err) => return Err(err).with_context(|| format!("failed to read from fd {}", from)),
+    };
+
+    write_all(to, &buf[..n])?;
+    Ok(true)
+}
+
+fn write_all(fd: RawFd, mut buf: &[u8]) -> Result<()> {
+    while !buf.is_empty() {
+        match unistd::write(fd, buf) {
+            Ok(n) => buf = &buf[n..],
+            Err(Errno::EINTR) => continue,
+            Err(err) => return Err(err).with_context(|| format!("failed to write to fd {}", fd)),
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::create_temp_dir;
+    use nix::sys::socket::{sendmsg, ControlMessage};
+    use std::os::unix::net::UnixStream;
+
+    #[test]
+    fn test_relay() -> Result<()> {
+        let (from_read, from_write) = unistd::pipe()?;
+        let (to_read, to_write) = unistd::pipe()?;
+
+        unistd::write(from_write, b"hello")?;
+        assert!(relay(from_read, to_write)?);
+        let mut buf = [0u8; 16];
+        let n = unistd::read(to_read, &mut buf)?;
+        assert_eq!(&buf[..n], b"hello");
+
+        close(from_write)?;
+        assert!(!relay(from_read, to_write)?);
+        for fd in [from_read, to_read, to_write] {
+            close(fd)?;
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_receive_master() -> Result<()> {
+        let tmp = create_temp_dir("test_receive_master")?;
+        let console_socket = ConsoleSocket::new(tmp.join("console.sock"))?;
+        let (read, write) = unistd::pipe()?;
+
+        let stream = UnixStream::connect(console_socket.path())?;
+        let header = serde_json::to_vec(&ConsoleMessage {
+            name: "/dev/pts/0".to_owned(),
+        })?;
+        let fds = [read];
+        sendmsg(
+            stream.as_raw_fd(),
+            &[IoVec::from_slice(&header)],
+            &[ControlMessage::ScmRights(&fds)],
+            MsgFlags::empty(),
+            None,
+        )?;
+
+        let master = console_socket.receive_master()?;
+        unistd::write(write, b"x")?;
+        let mut buf = [0u8; 1];
+        assert_eq!(unistd::read(master, &mut buf)?, 1);
+        for fd in [read, write, master] {
+            close(fd)?;
+        }
+        Ok(())
+    }
+}
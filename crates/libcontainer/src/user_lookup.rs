@@ -0,0 +1,308 @@
+//! Resolves users and groups by name from the passwd and group databases of
+//! the root filesystem of a container (see passwd(5) and group(5)). The
+//! databases of the host must not be used, as the ids inside of the container
+//! may be assigned to entirely different users.
+use anyhow::{bail, Context, Result};
+use nix::fcntl::OFlag;
+use oci_spec::runtime::Spec;
+use std::{
+    io::{BufRead, Read},
+    path::Path,
+};
+
+use crate::openat2;
+
+const PASSWD_PATH: &str = "/etc/passwd";
+const GROUP_PATH: &str = "/etc/group";
+
+/// Entry of the passwd database
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PasswdEntry {
+    pub name: String,
+    pub uid: u32,
+    pub gid: u32,
+    pub home: String,
+    pub shell: String,
+}
+
+/// Entry of the group database
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GroupEntry {
+    pub name: String,
+    pub gid: u32,
+    pub members: Vec<String>,
+}
+
+/// User and groups a process of the container should run as
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ResolvedUser {
+    pub uid: u32,
+    pub gid: u32,
+    /// Groups the user is a member of, apart from the primary group
+    pub additional_gids: Vec<u32>,
+}
+
+/// Parses the passwd database. Malformed lines are skipped like libc does.
+pub fn parse_passwd<R: BufRead>(reader: R) -> Result<Vec<PasswdEntry>> {
+    let mut entries = Vec::new();
+    for line in reader.lines() {
+        let line = line.context("failed to read passwd database")?;
+        let fields: Vec<&str> = line.trim().split(':').collect();
+        if line.starts_with('#') || fields.len() < 7 {
+            continue;
+        }
+
+        if let (Ok(uid), Ok(gid)) = (fields[2].parse(), fields[3].parse()) {
+            entries.push(PasswdEntry {
+                name: fields[0].to_owned(),
+                uid,
+                gid,
+                home: fields[5].to_owned(),
+                shell: fields[6].to_owned(),
+            });
+        }
+    }
+
+    Ok(entries)
+}
+
+/// Parses the group database. Malformed lines are skipped like libc does.
+pub fn parse_group<R: BufRead>(reader: R) -> Result<Vec<GroupEntry>> {
+    let mut entries = Vec::new();
+    for line in reader.lines() {
+        let line = line.context("failed to read group database")?;
+        let fields: Vec<&str> = line.trim().split(':').collect();
+        if line.starts_with('#') || fields.len() < 4 {
+            continue;
+        }
+
+        if let Ok(gid) = fields[2].parse() {
+            entries.push(GroupEntry {
+                name: fields[0].to_owned(),
+                gid,
+                members: fields[3]
+                    .split(',')
+                    .filter(|member| !member.is_empty())
+                    .map(|member| member.to_owned())
+                    .collect(),
+            });
+        }
+    }
+
+    Ok(entries)
+}
+
+/// Resolves a user specification of the form `user[:group]`, where user and
+/// group are either names or numeric ids, against the given databases.
+/// Numeric ids do not need to exist in the databases.
+pub fn resolve_user(
+    user: &str,
+    passwd: &[PasswdEntry],
+    groups: &[GroupEntry],
+) -> Result<ResolvedUser> {
+    let (user, group) = match user.split_once(':') {
+        Some((user, group)) => (user, Some(group)),
+        None => (user, None),
+    };
+
+    let entry = passwd.iter().find(|entry| entry.name == user).or_else(|| {
+        user.parse::<u32>()
+            .ok()
+            .and_then(|uid| passwd.iter().find(|entry| entry.uid == uid))
+    });
+    let (uid, mut gid) = match (entry, user.parse::<u32>()) {
+        (Some(entry), _) => (entry.uid, entry.gid),
+        (None, Ok(uid)) => (uid, 0),
+        (None, Err(_)) => bail!("no user {} in the passwd database of the container", user),
+    };
+
+    if let Some(group) = group {
+        gid = match groups.iter().find(|entry| entry.name == group) {
+            Some(entry) => entry.gid,
+            None => group.parse().with_context(|| {
+                format!("no group {} in the group database of the container", group)
+            })?,
+        };
+    }
+
+    let mut additional_gids = Vec::new();
+    if let Some(entry) = entry {
+        for group in groups {
+            if group.gid != gid
+                && group.members.contains(&entry.name)
+                && !additional_gids.contains(&group.gid)
+            {
+                additional_gids.push(group.gid);
+            }
+        }
+    }
+
+    Ok(ResolvedUser {
+        uid,
+        gid,
+        additional_gids,
+    })
+}
+
+/// Reads a database from the root filesystem. A missing database is treated
+/// as an empty one.
+fn read_database(rootfs: &Path, path: &str) -> Result<Vec<u8>> {
+    let mut file = match openat2::open_in_root(rootfs, Path::new(path), OFlag::O_RDONLY)? {
+        Some(file) => file,
+        None => return Ok(Vec::new()),
+    };
+    let mut content = Vec::new();
+    file.read_to_end(&mut content)
+        .with_context(|| format!("failed to read {:?}", path))?;
+    Ok(content)
+}
+
+/// Returns the home directory of the user with the uid from the passwd
+/// database of the root filesystem, if there is such a user
+pub fn home_dir(rootfs: &Path, uid: u32) -> Result<Option<String>> {
+    let passwd = parse_passwd(read_database(rootfs, PASSWD_PATH)?.as_slice())?;
+    Ok(passwd
+        .into_iter()
+        .find(|entry| entry.uid == uid)
+        .map(|entry| entry.home)
+        .filter(|home| !home.is_empty()))
+}
+
+/// Resolves the user of the process by the username in `process.user`. The
+/// uid and gid of the spec are replaced and, unless they are given
+/// explicitly, the additional gids are derived from the group membership of
+/// the user.
+pub fn resolve_spec_user(spec: &mut Spec, rootfs: &Path) -> Result<()> {
+    let mut process = match spec.process() {
+        Some(process) => process.clone(),
+        None => return Ok(()),
+    };
+    let mut user = process.user().clone();
+    let username = match user.username() {
+        Some(username) if !username.is_empty() => username.clone(),
+        _ => return Ok(()),
+    };
+
+    let passwd = parse_passwd(read_database(rootfs, PASSWD_PATH)?.as_slice())?;
+    let groups = parse_group(read_database(rootfs, GROUP_PATH)?.as_slice())?;
+    let resolved = resolve_user(&username, &passwd, &groups)
+        .with_context(|| format!("failed to resolve user {}", username))?;
+    tracing::debug!("resolved user {} to {:?}", username, resolved);
+
+    user.set_uid(resolved.uid).set_gid(resolved.gid);
+    if user.additional_gids().is_none() {
+        user.set_additional_gids(Some(resolved.additional_gids));
+    }
+    process.set_user(user);
+    spec.set_process(Some(process));
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::create_temp_dir;
+    use oci_spec::runtime::{ProcessBuilder, UserBuilder};
+    use std::fs;
+
+    const PASSWD: &str = "root:x:0:0:root:/root:/bin/sh
+# comment
+daemon:x:1:1:daemon:/usr/sbin:/usr/sbin/nologin
+broken:x:abc:1::/:/bin/sh
+app:x:1000:1000:App:/home/app:/bin/bash
+";
+
+    const GROUP: &str = "root:x:0:
+daemon:x:1:app
+wheel:x:10:root,app
+app:x:1000:app
+audio:x:29:
+";
+
+    fn databases() -> Result<(Vec<PasswdEntry>, Vec<GroupEntry>)> {
+        Ok((
+            parse_passwd(PASSWD.as_bytes())?,
+            parse_group(GROUP.as_bytes())?,
+        ))
+    }
+
+    #[test]
+    fn test_parse_databases() -> Result<()> {
+        let (passwd, groups) = databases()?;
+        assert_eq!(passwd.len(), 3);
+        assert_eq!(
+            passwd[2],
+            PasswdEntry {
+                name: "app".to_owned(),
+                uid: 1000,
+                gid: 1000,
+                home: "/home/app".to_owned(),
+                shell: "/bin/bash".to_owned(),
+            }
+        );
+        assert_eq!(groups.len(), 5);
+        assert_eq!(groups[2].members, vec!["root", "app"]);
+        assert!(groups[4].members.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_resolve_user() -> Result<()> {
+        let (passwd, groups) = databases()?;
+
+        let user = resolve_user("app", &passwd, &groups)?;
+        assert_eq!(
+            user,
+            ResolvedUser {
+                uid: 1000,
+                gid: 1000,
+                additional_gids: vec![1, 10],
+            }
+        );
+
+        let user = resolve_user("1000:audio", &passwd, &groups)?;
+        assert_eq!((user.uid, user.gid), (1000, 29));
+        assert_eq!(user.additional_gids, vec![1, 10, 1000]);
+
+        let user = resolve_user("4242:42", &passwd, &groups)?;
+        assert_eq!((user.uid, user.gid), (4242, 42));
+        assert!(user.additional_gids.is_empty());
+
+        assert!(resolve_user("unknown", &passwd, &groups).is_err());
+        assert!(resolve_user("app:unknown", &passwd, &groups).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_resolve_spec_user() -> Result<()> {
+        let rootfs = create_temp_dir("test_resolve_spec_user")?;
+        fs::create_dir_all(rootfs.join("etc"))?;
+        fs::write(rootfs.join("etc/passwd"), PASSWD)?;
+        fs::write(rootfs.join("etc/group"), GROUP)?;
+
+        let mut spec = Spec::default();
+        spec.set_process(Some(
+            ProcessBuilder::default()
+                .user(UserBuilder::default().username("app").build()?)
+                .build()?,
+        ));
+        resolve_spec_user(&mut spec, &rootfs)?;
+
+        let user = spec.process().as_ref().unwrap().user();
+        assert_eq!((user.uid(), user.gid()), (1000, 1000));
+        assert_eq!(user.additional_gids(), &Some(vec![1, 10]));
+        Ok(())
+    }
+
+    #[test]
+    fn test_home_dir() -> Result<()> {
+        let rootfs = create_temp_dir("test_home_dir")?;
+        assert_eq!(home_dir(&rootfs, 1000)?, None);
+
+        fs::create_dir_all(rootfs.join("etc"))?;
+        fs::write(rootfs.join("etc/passwd"), PASSWD)?;
+        assert_eq!(home_dir(&rootfs, 1000)?.as_deref(), Some("/home/app"));
+        assert_eq!(home_dir(&rootfs, 4242)?, None);
+        Ok(())
+    }
+}
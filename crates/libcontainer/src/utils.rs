@@ -2,16 +2,17 @@
 
 use anyhow::Context;
 use anyhow::{bail, Result};
-use nix::sys::stat::Mode;
+use nix::errno::Errno;
+use nix::sys::stat::{self, Mode, SFlag};
 use nix::sys::statfs;
-use nix::unistd;
+use nix::unistd::{self, FchownatFlags, Gid, Uid};
 use std::collections::HashMap;
-use std::ffi::CString;
+use std::ffi::{CString, OsString};
 use std::fs::{self, DirBuilder, File};
 use std::io::ErrorKind;
 use std::ops::Deref;
 use std::os::linux::fs::MetadataExt;
-use std::os::unix::fs::DirBuilderExt;
+use std::os::unix::fs::{symlink, DirBuilderExt, PermissionsExt};
 use std::os::unix::prelude::{AsRawFd, OsStrExt};
 use std::path::{Path, PathBuf};
 
@@ -81,6 +82,28 @@ pub fn write_file<P: AsRef<Path>, C: AsRef<[u8]>>(path: P, contents: C) -> Resul
     Ok(())
 }
 
+/// Writes the contents to a temporary file next to the path, which is then
+/// renamed to the path. Readers either see the previous or the new contents,
+/// but never a partially written file.
+pub fn write_file_atomically<P: AsRef<Path>, C: AsRef<[u8]>>(path: P, contents: C) -> Result<()> {
+    let path = path.as_ref();
+    let file_name = path
+        .file_name()
+        .with_context(|| format!("{:?} is not a file path", path))?;
+    let mut tmp_name = OsString::from(".");
+    tmp_name.push(file_name);
+    tmp_name.push(".tmp");
+    let tmp_path = path.with_file_name(tmp_name);
+
+    write_file(&tmp_path, contents)?;
+    if let Err(err) = fs::rename(&tmp_path, path) {
+        let _ = fs::remove_file(&tmp_path);
+        return Err(err).with_context(|| format!("failed to rename {:?} to {:?}", tmp_path, path));
+    }
+
+    Ok(())
+}
+
 pub fn create_dir_all<P: AsRef<Path>>(path: P) -> Result<()> {
     let path = path.as_ref();
     fs::create_dir_all(path).with_context(|| format!("failed to create directory {:?}", path))
@@ -131,6 +154,139 @@ pub fn create_dir_all_with_mode<P: AsRef<Path>>(path: P, owner: u32, mode: Mode)
     }
 }
 
+/// Copies the contents of the source directory recursively into the
+/// destination directory. The ownership, permissions and extended attributes
+/// of the source directory and of all copied entries are preserved.
+pub fn copy_dir_contents<P: AsRef<Path>>(src: P, dst: P) -> Result<()> {
+    let src = src.as_ref();
+    let dst = dst.as_ref();
+    for entry in fs::read_dir(src).with_context(|| format!("failed to read {:?}", src))? {
+        let entry = entry.with_context(|| format!("failed to read entry of {:?}", src))?;
+        let src_path = entry.path();
+        let dst_path = dst.join(entry.file_name());
+        let metadata = fs::symlink_metadata(&src_path)
+            .with_context(|| format!("failed to get metadata of {:?}", src_path))?;
+
+        let file_type = metadata.file_type();
+        if file_type.is_dir() {
+            fs::create_dir(&dst_path)
+                .with_context(|| format!("failed to create directory {:?}", dst_path))?;
+            copy_dir_contents(&src_path, &dst_path)?;
+        } else if file_type.is_symlink() {
+            let target = fs::read_link(&src_path)
+                .with_context(|| format!("failed to read link {:?}", src_path))?;
+            symlink(&target, &dst_path)
+                .with_context(|| format!("failed to create symlink {:?}", dst_path))?;
+        } else if file_type.is_file() {
+            fs::copy(&src_path, &dst_path)
+                .with_context(|| format!("failed to copy {:?} to {:?}", src_path, dst_path))?;
+        } else {
+            // devices, fifos and sockets
+            stat::mknod(
+                &dst_path,
+                SFlag::from_bits_truncate(metadata.st_mode()),
+                Mode::from_bits_truncate(metadata.st_mode()),
+                metadata.st_rdev(),
+            )
+            .with_context(|| format!("failed to create {:?}", dst_path))?;
+        }
+
+        copy_metadata(&src_path, &dst_path, &metadata)?;
+    }
+
+    let metadata =
+        fs::metadata(src).with_context(|| format!("failed to get metadata of {:?}", src))?;
+    copy_metadata(src, dst, &metadata)
+}
+
+/// Copies the ownership, permissions and extended attributes of a file
+/// without following symlinks
+fn copy_metadata(src: &Path, dst: &Path, metadata: &fs::Metadata) -> Result<()> {
+    unistd::fchownat(
+        None,
+        dst,
+        Some(Uid::from_raw(metadata.st_uid())),
+        Some(Gid::from_raw(metadata.st_gid())),
+        FchownatFlags::NoFollowSymlink,
+    )
+    .with_context(|| format!("failed to change owner of {:?}", dst))?;
+
+    // the permissions of symlinks can not be changed. changing the owner
+    // clears the setuid and setgid bits, so they are set afterwards.
+    if !metadata.file_type().is_symlink() {
+        fs::set_permissions(dst, fs::Permissions::from_mode(metadata.st_mode()))
+            .with_context(|| format!("failed to change permissions of {:?}", dst))?;
+    }
+
+    copy_xattrs(src, dst).with_context(|| format!("failed to copy xattrs to {:?}", dst))
+}
+
+fn copy_xattrs(src: &Path, dst: &Path) -> Result<()> {
+    let c_src = CString::new(src.as_os_str().as_bytes())?;
+    let c_dst = CString::new(dst.as_os_str().as_bytes())?;
+
+    let names = match read_xattr_buf(|buf, len| unsafe {
+        libc::llistxattr(c_src.as_ptr(), buf as *mut libc::c_char, len)
+    }) {
+        Ok(names) => names,
+        // the filesystem does not support xattrs
+        Err(Errno::EOPNOTSUPP) => return Ok(()),
+        Err(err) => return Err(err).context("failed to list xattrs"),
+    };
+
+    for name in names.split(|b| *b == 0).filter(|name| !name.is_empty()) {
+        let c_name = CString::new(name)?;
+        let value = read_xattr_buf(|buf, len| unsafe {
+            libc::lgetxattr(
+                c_src.as_ptr(),
+                c_name.as_ptr(),
+                buf as *mut libc::c_void,
+                len,
+            )
+        })
+        .with_context(|| format!("failed to get xattr {:?}", c_name))?;
+        let result = unsafe {
+            libc::lsetxattr(
+                c_dst.as_ptr(),
+                c_name.as_ptr(),
+                value.as_ptr() as *const libc::c_void,
+                value.len(),
+                0,
+            )
+        };
+        match Errno::result(result) {
+            Ok(_) => {}
+            // the xattr namespace is not supported by the destination
+            Err(Errno::EOPNOTSUPP) => {
+                tracing::debug!("xattr {:?} is not supported on {:?}", c_name, dst)
+            }
+            Err(err) => {
+                return Err(err).with_context(|| format!("failed to set xattr {:?}", c_name))
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Calls an xattr function, which returns the size of the buffer it needs if
+/// it is called with an empty buffer
+fn read_xattr_buf<F: Fn(*mut u8, usize) -> isize>(f: F) -> nix::Result<Vec<u8>> {
+    loop {
+        let len = Errno::result(f(std::ptr::null_mut(), 0))? as usize;
+        let mut buf = vec![0u8; len];
+        match Errno::result(f(buf.as_mut_ptr(), buf.len())) {
+            Ok(len) => {
+                buf.truncate(len as usize);
+                return Ok(buf);
+            }
+            // the xattrs have changed in the meantime
+            Err(Errno::ERANGE) => continue,
+            Err(err) => return Err(err),
+        }
+    }
+}
+
 // Make sure a given path is on procfs. This is to avoid the security risk that
 // /proc path is mounted over. Ref: CVE-2019-16884
 pub fn ensure_procfs(path: &Path) -> Result<()> {
@@ -338,6 +494,42 @@ mod tests {
 
         Ok(())
     }
+
+    #[test]
+    fn test_write_file_atomically() -> Result<()> {
+        let tmp = create_temp_dir("test_write_file_atomically")?;
+        let path = tmp.join("container.pid");
+        write_file_atomically(&path, "42")?;
+        write_file_atomically(&path, "4242")?;
+
+        assert_eq!(fs::read_to_string(&path)?, "4242");
+        assert!(!tmp.join(".container.pid.tmp").exists());
+        Ok(())
+    }
+
+    #[test]
+    fn test_copy_dir_contents() -> Result<()> {
+        let tmp = create_temp_dir("test_copy_dir_contents")?;
+        let src = tmp.join("src");
+        let dst = tmp.join("dst");
+        fs::create_dir_all(src.join("dir"))?;
+        fs::create_dir_all(&dst)?;
+        fs::write(src.join("dir/file"), "content")?;
+        fs::set_permissions(src.join("dir/file"), fs::Permissions::from_mode(0o640))?;
+        fs::set_permissions(&src, fs::Permissions::from_mode(0o1777))?;
+        symlink("dir/file", src.join("link"))?;
+
+        copy_dir_contents(&src, &dst)?;
+
+        assert_eq!(fs::read_to_string(dst.join("dir/file"))?, "content");
+        assert_eq!(
+            fs::metadata(dst.join("dir/file"))?.st_mode() & 0o7777,
+            0o640
+        );
+        assert_eq!(fs::read_link(dst.join("link"))?, PathBuf::from("dir/file"));
+        assert_eq!(fs::metadata(&dst)?.st_mode() & 0o7777, 0o1777);
+        Ok(())
+    }
     #[test]
     fn test_secure_join() {
         assert_eq!(
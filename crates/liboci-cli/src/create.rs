@@ -18,6 +18,34 @@ pub struct Create {
     /// Pass N additional file descriptors to the container (stdio + $LISTEN_FDS + N in total)
     #[clap(long, default_value = "0")]
     pub preserve_fds: i32,
+    /// Fail if resource restrictions cannot be applied because a cgroup controller
+    /// is not available, instead of skipping them with a warning
+    #[clap(long)]
+    pub strict_resources: bool,
+    /// Join the network, ipc, pid and uts namespaces of the container with this id
+    #[clap(long)]
+    pub ns_from: Option<String>,
+    /// Fail if the working directory of the container process does not exist,
+    /// instead of creating it
+    #[clap(long)]
+    pub no_create_cwd: bool,
+    /// Keep the session keyring of youki instead of creating a new one for the container
+    #[clap(long)]
+    pub no_new_keyring: bool,
+    /// Do not use pivot_root to change the root of the container, e.g. when
+    /// running from a ramdisk
+    #[clap(long)]
+    pub no_pivot: bool,
+    /// Detach the container from the session and the controlling terminal of youki
+    #[clap(short, long)]
+    pub detach: bool,
+    /// Allocate unused subordinate id ranges to the user namespace of the
+    /// container if the spec does not contain any id mappings
+    #[clap(long)]
+    pub auto_userns: bool,
+    /// Number of uids and gids allocated with --auto-userns
+    #[clap(long, default_value = "65536")]
+    pub auto_userns_size: u32,
     /// name of the container instance to be started
     #[clap(forbid_empty_values = true, required = true)]
     pub container_id: String,
@@ -1,11 +1,16 @@
+use std::time::Duration;
+
 use clap::Parser;
 
+use crate::parse_duration;
+
 /// Show resource statistics for the container
 #[derive(Parser, Debug)]
 pub struct Events {
-    /// Sets the stats collection interval in seconds (default: 5s)
-    #[clap(long, default_value = "5")]
-    pub interval: u32,
+    /// Sets the stats collection interval, e.g. 5s, 500ms or 1m (default: 5s).
+    /// Together with --stats the stats are streamed continuously.
+    #[clap(long, parse(try_from_str = parse_duration))]
+    pub interval: Option<Duration>,
     /// Display the container stats only once
     #[clap(long)]
     pub stats: bool,
@@ -9,6 +9,7 @@ pub struct Exec {
     /// Unix socket (file) path , which will receive file descriptor of the writing end of the pseudoterminal
     #[clap(long)]
     pub console_socket: Option<PathBuf>,
+    /// Allocate a pseudoterminal for the process
     #[clap(short, long)]
     pub tty: bool,
     #[clap(long)]
@@ -20,15 +21,33 @@ pub struct Exec {
     /// Environment variables that should be set in the container
     #[clap(short, long, parse(try_from_str = parse_key_val), number_of_values = 1)]
     pub env: Vec<(String, String)>,
+    /// User of the process in the form of uid[:gid], instead of the user of
+    /// the container
+    #[clap(short, long)]
+    pub user: Option<String>,
+    /// Additional gids of the process, instead of those of the container
+    #[clap(short = 'g', long, number_of_values = 1)]
+    pub additional_gids: Vec<u32>,
+    /// Capabilities, which are granted to the process in addition to those
+    /// of the container
+    #[clap(short, long, number_of_values = 1)]
+    pub cap: Vec<String>,
     /// Prevent the process from gaining additional privileges
     #[clap(long)]
     pub no_new_privs: bool,
     /// Path to process.json
     #[clap(short, long)]
     pub process: Option<PathBuf>,
+    /// Pass N additional file descriptors to the container (stdio + $LISTEN_FDS + N in total)
+    #[clap(long, default_value = "0")]
+    pub preserve_fds: i32,
     /// Detach from the container process
     #[clap(short, long)]
     pub detach: bool,
+    /// Fail if the working directory of the container process does not exist,
+    /// instead of creating it
+    #[clap(long)]
+    pub no_create_cwd: bool,
     /// Identifier of the container
     #[clap(forbid_empty_values = true, required = true)]
     pub container_id: String,
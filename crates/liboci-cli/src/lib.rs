@@ -1,7 +1,9 @@
+use std::error::Error;
 use std::fmt::Debug;
 use std::path::PathBuf;
+use std::time::Duration;
 
-use clap::Parser;
+use clap::{ArgEnum, Parser};
 
 // Subcommands that are specified in https://github.com/opencontainers/runtime-tools/blob/master/docs/command-line-interface.md
 
@@ -22,10 +24,11 @@ mod ps;
 mod resume;
 mod run;
 mod spec;
+mod update;
 
 pub use {
     events::Events, exec::Exec, list::List, pause::Pause, ps::Ps, resume::Resume, run::Run,
-    spec::Spec,
+    spec::Spec, update::Update,
 };
 
 // Subcommands parsed by liboci-cli, based on the [OCI
@@ -41,6 +44,19 @@ pub enum StandardCmd {
     Delete(Delete),
 }
 
+impl StandardCmd {
+    /// Id of the container, to which the command is applied
+    pub fn container_id(&self) -> &str {
+        match self {
+            Self::Create(create) => &create.container_id,
+            Self::Start(start) => &start.container_id,
+            Self::State(state) => &state.container_id,
+            Self::Kill(kill) => &kill.container_id,
+            Self::Delete(delete) => &delete.container_id,
+        }
+    }
+}
+
 // Extra subcommands not documented in the OCI Command Line Interface,
 // but found in
 // [runc](https://github.com/opencontainers/runc/blob/master/man/runc.8.md)
@@ -56,6 +72,23 @@ pub enum CommonCmd {
     Resume(Resume),
     Run(Run),
     Spec(Spec),
+    Update(Update),
+}
+
+impl CommonCmd {
+    /// Id of the container, to which the command is applied, if any
+    pub fn container_id(&self) -> Option<&str> {
+        match self {
+            Self::Events(events) => Some(&events.container_id),
+            Self::Exec(exec) => Some(&exec.container_id),
+            Self::Pause(pause) => Some(&pause.container_id),
+            Self::Ps(ps) => Some(&ps.container_id),
+            Self::Resume(resume) => Some(&resume.container_id),
+            Self::Run(run) => Some(&run.container_id),
+            Self::Update(update) => Some(&update.container_id),
+            Self::List(_) | Self::Spec(_) => None,
+        }
+    }
 }
 
 // The OCI Command Line Interface document doesn't define any global
@@ -66,9 +99,12 @@ pub struct GlobalOpts {
     // Example in future : '--debug     change log level to debug. (default: "warn")'
     #[clap(long)]
     pub debug: bool,
+    /// append the logs of youki to this file instead of writing them to stderr
     #[clap(short, long)]
     pub log: Option<PathBuf>,
-    #[clap(long)]
+    /// format of the logs, json writes one record with level, msg, time and
+    /// id of the container per line (default: text)
+    #[clap(long, possible_values = &["text", "json"])]
     pub log_format: Option<String>,
     /// root directory to store container state
     #[clap(short, long)]
@@ -76,4 +112,37 @@ pub struct GlobalOpts {
     /// Enable systemd cgroup manager, rather then use the cgroupfs directly.
     #[clap(short, long)]
     pub systemd_cgroup: bool,
+    /// Enable or disable rootless mode
+    #[clap(long, arg_enum, default_value = "auto")]
+    pub rootless: Rootless,
+}
+
+/// Whether the container should be run in rootless mode
+#[derive(ArgEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Rootless {
+    /// Always run the container in rootless mode
+    True,
+    /// Never run the container in rootless mode
+    False,
+    /// Detect whether rootless mode is required
+    Auto,
+}
+
+/// Parses a duration with an optional unit, e.g. 5s, 500ms or 1m
+fn parse_duration(s: &str) -> Result<Duration, Box<dyn Error + Send + Sync + 'static>> {
+    let pos = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+    let value: u64 = s[..pos].parse()?;
+    let duration = match &s[pos..] {
+        "ms" => Duration::from_millis(value),
+        "" | "s" => Duration::from_secs(value),
+        "m" => Duration::from_secs(value * 60),
+        "h" => Duration::from_secs(value * 60 * 60),
+        unit => return Err(format!("invalid duration unit `{}` in `{}`", unit, s).into()),
+    };
+
+    if duration.is_zero() {
+        return Err(format!("duration `{}` must be greater than zero", s).into());
+    }
+
+    Ok(duration)
 }
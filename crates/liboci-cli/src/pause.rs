@@ -1,8 +1,15 @@
+use std::time::Duration;
+
 use clap::Parser;
 
+use crate::parse_duration;
+
 /// Suspend the processes within the container
 #[derive(Parser, Debug)]
 pub struct Pause {
+    /// Time to wait for the container to be frozen, e.g. 5s or 500ms (default: 10s)
+    #[clap(long, parse(try_from_str = parse_duration))]
+    pub timeout: Option<Duration>,
     #[clap(forbid_empty_values = true, required = true)]
     pub container_id: String,
 }
@@ -0,0 +1,62 @@
+use std::path::PathBuf;
+
+use clap::Parser;
+
+/// Update the resource restrictions of a running container
+#[derive(Parser, Debug)]
+pub struct Update {
+    /// Path to a file containing the resources in the format of the OCI
+    /// runtime-spec, use '-' to read them from stdin
+    #[clap(short, long)]
+    pub resources: Option<PathBuf>,
+    /// Relative weight of block io (10-1000)
+    #[clap(long)]
+    pub blkio_weight: Option<u16>,
+    /// CPU CFS period in microseconds
+    #[clap(long)]
+    pub cpu_period: Option<u64>,
+    /// CPU CFS quota in microseconds
+    #[clap(long)]
+    pub cpu_quota: Option<i64>,
+    /// CPU CFS burst in microseconds, which may not exceed the quota
+    #[clap(long)]
+    pub cpu_burst: Option<u64>,
+    /// CPU shares (relative weight)
+    #[clap(long)]
+    pub cpu_share: Option<u64>,
+    /// CPU realtime period in microseconds
+    #[clap(long)]
+    pub cpu_rt_period: Option<u64>,
+    /// CPU realtime runtime in microseconds
+    #[clap(long)]
+    pub cpu_rt_runtime: Option<i64>,
+    /// CPUs in which execution is allowed (e.g. 0-3, 0,1)
+    #[clap(long)]
+    pub cpuset_cpus: Option<String>,
+    /// Memory nodes in which execution is allowed (e.g. 0-3, 0,1)
+    #[clap(long)]
+    pub cpuset_mems: Option<String>,
+    /// Memory limit in bytes
+    #[clap(long)]
+    pub memory: Option<i64>,
+    /// Memory reservation or soft limit in bytes
+    #[clap(long)]
+    pub memory_reservation: Option<i64>,
+    /// Total memory usage (memory + swap) in bytes, use -1 for unlimited swap
+    #[clap(long, allow_hyphen_values = true)]
+    pub memory_swap: Option<i64>,
+    /// Maximum number of pids allowed in the container, use -1 for unlimited
+    #[clap(long, allow_hyphen_values = true)]
+    pub pids_limit: Option<i64>,
+    /// Device rule which allows access to devices, in the format of the
+    /// devices.allow file of cgroup v1 (e.g. 'c 1:3 rwm')
+    #[clap(long, number_of_values = 1)]
+    pub device_allow: Vec<String>,
+    /// Device rule which denies access to devices, in the same format as
+    /// --device-allow
+    #[clap(long, number_of_values = 1)]
+    pub device_deny: Vec<String>,
+    /// Identifier of the container
+    #[clap(forbid_empty_values = true, required = true)]
+    pub container_id: String,
+}
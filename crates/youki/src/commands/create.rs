@@ -2,7 +2,10 @@
 use anyhow::Result;
 use std::path::PathBuf;
 
-use libcontainer::{container::builder::ContainerBuilder, syscall::syscall::create_syscall};
+use libcontainer::{
+    auto_userns::AutoUserns, container::builder::ContainerBuilder, rootless::RootlessMode,
+    syscall::syscall::create_syscall,
+};
 use liboci_cli::Create;
 
 // One thing to note is that in the end, container is just another process in Linux
@@ -10,15 +13,34 @@ use liboci_cli::Create;
 // can be given impression that is is running on a complete system, but on the system which
 // it is running, it is just another process, and has attributes such as pid, file descriptors, etc.
 // associated with it like any other process.
-pub fn create(args: Create, root_path: PathBuf, systemd_cgroup: bool) -> Result<()> {
+pub fn create(
+    args: Create,
+    root_path: PathBuf,
+    systemd_cgroup: bool,
+    rootless_mode: RootlessMode,
+) -> Result<()> {
     let syscall = create_syscall();
     ContainerBuilder::new(args.container_id.clone(), syscall.as_ref())
         .with_pid_file(args.pid_file.as_ref())
         .with_console_socket(args.console_socket.as_ref())
         .with_root_path(root_path)
+        .with_rootless_mode(rootless_mode)
         .with_preserved_fds(args.preserve_fds)
+        .with_create_cwd(!args.no_create_cwd)
+        .with_detach(args.detach)
         .as_init(&args.bundle)
         .with_systemd(systemd_cgroup)
+        .with_strict_resources(args.strict_resources)
+        .with_namespaces_from(args.ns_from.clone())
+        .with_no_new_keyring(args.no_new_keyring)
+        .with_no_pivot(args.no_pivot)
+        // nobody waits for a created container, which is started by a
+        // separate invocation of the runtime
+        .with_exit_monitor(true)
+        .with_auto_userns(args.auto_userns.then(|| AutoUserns {
+            size: args.auto_userns_size,
+            ..Default::default()
+        }))
         .build()?;
 
     Ok(())
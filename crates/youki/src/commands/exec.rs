@@ -1,21 +1,129 @@
-use anyhow::Result;
-use std::path::PathBuf;
+use anyhow::{bail, Context, Result};
+use std::{
+    env, fs,
+    path::{Path, PathBuf},
+    process,
+};
 
-use libcontainer::{container::builder::ContainerBuilder, syscall::syscall::create_syscall};
+use libcontainer::{
+    container::{builder::ContainerBuilder, set_child_subreaper, Container, SuperviseOptions},
+    rootless::RootlessMode,
+    syscall::syscall::create_syscall,
+    tty_proxy::ConsoleSocket,
+};
 use liboci_cli::Exec;
+use nix::unistd::close;
+
+pub fn exec(args: Exec, root_path: PathBuf, rootless_mode: RootlessMode) -> Result<()> {
+    // in the foreground the process is re-parented to youki, so that it can
+    // wait for it
+    if !args.detach {
+        set_child_subreaper()?;
+    }
+
+    let terminal = match &args.process {
+        Some(process) => uses_terminal(process)?,
+        None => args.tty,
+    };
+    // without a console socket of the caller, youki relays between its own
+    // stdio and the terminal of the process while it is in the foreground
+    let console_socket = if !args.detach && args.console_socket.is_none() && terminal {
+        let path = env::temp_dir().join(format!("youki-console-{}.sock", process::id()));
+        Some(ConsoleSocket::new(path)?)
+    } else {
+        None
+    };
+    let console_socket_path = args.console_socket.clone().or_else(|| {
+        console_socket
+            .as_ref()
+            .map(|socket| socket.path().to_path_buf())
+    });
+    let (uid, gid) = match &args.user {
+        Some(user) => {
+            let (uid, gid) = parse_user(user)?;
+            (Some(uid), gid)
+        }
+        None => (None, None),
+    };
 
-pub fn exec(args: Exec, root_path: PathBuf) -> Result<()> {
     let syscall = create_syscall();
-    ContainerBuilder::new(args.container_id.clone(), syscall.as_ref())
-        .with_root_path(root_path)
-        .with_console_socket(args.console_socket.as_ref())
+    let pid = ContainerBuilder::new(args.container_id.clone(), syscall.as_ref())
+        .with_root_path(&root_path)
+        .with_rootless_mode(rootless_mode)
+        .with_console_socket(console_socket_path.as_ref())
         .with_pid_file(args.pid_file.as_ref())
+        .with_preserved_fds(args.preserve_fds)
+        .with_create_cwd(!args.no_create_cwd)
+        .with_detach(args.detach)
         .as_tenant()
         .with_cwd(args.cwd.as_ref())
         .with_env(args.env.clone().into_iter().collect())
         .with_process(args.process.as_ref())
         .with_no_new_privs(args.no_new_privs)
-        .with_process(args.process.as_ref())
+        .with_terminal(args.tty)
+        .with_user(uid, gid)
+        .with_additional_gids(Some(args.additional_gids.clone()).filter(|gids| !gids.is_empty()))
+        .with_capabilities(args.cap.clone())
         .with_container_args(args.command.clone())
-        .build()
+        .build()?;
+    let console = match console_socket {
+        Some(socket) => Some(socket.receive_master()?),
+        None => None,
+    };
+    if args.detach {
+        return Ok(());
+    }
+
+    let container = Container::load(root_path.join(&args.container_id))?;
+    let exit_code = container
+        .supervise_tenant(pid, SuperviseOptions::default().with_console(console))
+        .with_context(|| format!("failed to wait for process {}", pid))?;
+    if let Some(console) = console {
+        close(console)?;
+    }
+    if exit_code != 0 {
+        super::exit(exit_code);
+    }
+
+    Ok(())
+}
+
+fn uses_terminal(process: &Path) -> Result<bool> {
+    let process: serde_json::Value = serde_json::from_str(
+        &fs::read_to_string(process).with_context(|| format!("failed to read {:?}", process))?,
+    )?;
+    Ok(process["terminal"].as_bool().unwrap_or(false))
+}
+
+/// Parses a user in the form of uid[:gid]
+fn parse_user(user: &str) -> Result<(u32, Option<u32>)> {
+    let (uid, gid) = match user.split_once(':') {
+        Some((uid, gid)) => (uid, Some(gid)),
+        None => (user, None),
+    };
+    let uid = match uid.parse() {
+        Ok(uid) => uid,
+        Err(_) => bail!("invalid uid {:?} in user {:?}", uid, user),
+    };
+    let gid = match gid.map(str::parse).transpose() {
+        Ok(gid) => gid,
+        Err(_) => bail!("invalid gid in user {:?}", user),
+    };
+
+    Ok((uid, gid))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_user() -> Result<()> {
+        assert_eq!(parse_user("1000")?, (1000, None));
+        assert_eq!(parse_user("1000:100")?, (1000, Some(100)));
+        assert!(parse_user("app").is_err());
+        assert!(parse_user("1000:").is_err());
+        assert!(parse_user(":100").is_err());
+        Ok(())
+    }
 }
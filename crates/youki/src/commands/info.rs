@@ -3,7 +3,7 @@ use std::{collections::HashSet, fs, path::Path};
 
 use anyhow::Result;
 use clap::Parser;
-use libcontainer::rootless;
+use libcontainer::{personality, rootless};
 use procfs::{CpuInfo, Meminfo};
 
 use libcgroups::{common::CgroupSetup, v2::controller_type::ControllerType};
@@ -19,6 +19,7 @@ pub fn info(_: Info) -> Result<()> {
     print_hardware();
     print_cgroups();
     print_namespaces();
+    print_personality();
 
     Ok(())
 }
@@ -100,52 +101,48 @@ pub fn print_hardware() {
 
 /// Print cgroups info of system
 pub fn print_cgroups() {
-    let cgroup_setup = libcgroups::common::get_cgroup_setup();
-    if let Ok(cgroup_setup) = &cgroup_setup {
-        println!("{:<18}{}", "Cgroup setup", cgroup_setup);
-    }
+    let features = match libcgroups::common::get_cgroup_features() {
+        Ok(features) => features,
+        Err(err) => {
+            println!("{:<18}unknown ({:#})", "Cgroup setup", err);
+            return;
+        }
+    };
 
+    println!("{:<18}{}", "Cgroup setup", features.setup);
     println!("Cgroup mounts");
-    if let Ok(v1_mounts) = libcgroups::v1::util::list_supported_mount_points() {
-        let mut v1_mounts: Vec<String> = v1_mounts
-            .iter()
-            .map(|kv| format!("  {:<16}{}", kv.0.to_string(), kv.1.display()))
-            .collect();
-
-        v1_mounts.sort();
-        for cgroup_mount in v1_mounts {
-            println!("{}", cgroup_mount);
-        }
+    let mut v1_mounts: Vec<String> = features
+        .v1_controllers
+        .iter()
+        .map(|kv| format!("  {:<16}{}", kv.0.to_string(), kv.1.display()))
+        .collect();
+
+    v1_mounts.sort();
+    for cgroup_mount in v1_mounts {
+        println!("{}", cgroup_mount);
     }
 
-    let unified = libcgroups::v2::util::get_unified_mount_point();
-    if let Ok(mount_point) = &unified {
+    if let Some(mount_point) = &features.unified_mount {
         println!("  {:<16}{}", "unified", mount_point.display());
     }
 
-    if let Ok(cgroup_setup) = cgroup_setup {
-        if let Ok(unified) = &unified {
-            if matches!(cgroup_setup, CgroupSetup::Hybrid | CgroupSetup::Unified) {
-                if let Ok(controllers) = libcgroups::v2::util::get_available_controllers(unified) {
-                    println!("CGroup v2 controllers");
-                    let active_controllers: HashSet<ControllerType> =
-                        controllers.into_iter().collect();
-                    for controller in libcgroups::v2::controller_type::CONTROLLER_TYPES {
-                        let status = if active_controllers.contains(controller) {
-                            "attached"
-                        } else {
-                            "detached"
-                        };
-
-                        println!("  {:<16}{}", controller.to_string(), status);
-                    }
-                }
-
-                if let Some(config) = read_kernel_config() {
-                    let display = FeatureDisplay::with_status("device", "attached", "detached");
-                    print_feature_status(&config, "CONFIG_CGROUP_BPF", display);
-                }
-            }
+    if matches!(features.setup, CgroupSetup::Hybrid | CgroupSetup::Unified) {
+        println!("CGroup v2 controllers");
+        let active_controllers: HashSet<ControllerType> =
+            features.v2_controllers.into_iter().collect();
+        for controller in libcgroups::v2::controller_type::CONTROLLER_TYPES {
+            let status = if active_controllers.contains(controller) {
+                "attached"
+            } else {
+                "detached"
+            };
+
+            println!("  {:<16}{}", controller.to_string(), status);
+        }
+
+        if let Some(config) = read_kernel_config() {
+            let display = FeatureDisplay::with_status("device", "attached", "detached");
+            print_feature_status(&config, "CONFIG_CGROUP_BPF", display);
         }
     }
 }
@@ -181,6 +178,11 @@ pub fn print_namespaces() {
             _ => FeatureDisplay::new("user"),
         };
         print_feature_status(&content, "CONFIG_USER_NS", user_display);
+        if let Ok(env) = rootless::environment() {
+            if env.in_user_namespace {
+                println!("  {:<16}yes", "nested user ns");
+            }
+        }
         print_feature_status(&content, "CONFIG_PID_NS", FeatureDisplay::new("pid"));
         print_feature_status(&content, "CONFIG_NET_NS", FeatureDisplay::new("network"));
         // While the CONFIG_CGROUP_NS kernel feature exists, it is obsolete and should not be used. CGroup namespaces
@@ -189,6 +191,15 @@ pub fn print_namespaces() {
     }
 }
 
+/// Print the execution domains a container can be run with
+pub fn print_personality() {
+    let domains: Vec<String> = personality::SUPPORTED_DOMAINS
+        .iter()
+        .map(|domain| domain.to_string())
+        .collect();
+    println!("{:<18}{}", "Personality", domains.join(", "));
+}
+
 fn print_feature_status(config: &str, feature: &str, display: FeatureDisplay) {
     if let Some(status_flag) = find_parameter(config, feature) {
         let status = if status_flag == "y" {
@@ -10,5 +10,5 @@ use liboci_cli::Kill;
 pub fn kill(args: Kill, root_path: PathBuf) -> Result<()> {
     let mut container = load_container(root_path, &args.container_id)?;
     let signal: Signal = args.signal.as_str().try_into()?;
-    container.kill(signal)
+    container.kill(signal, args.all)
 }
@@ -17,6 +17,7 @@ pub mod run;
 pub mod spec_json;
 pub mod start;
 pub mod state;
+pub mod update;
 
 fn load_container<P: AsRef<Path>>(root_path: P, container_id: &str) -> Result<Container> {
     // resolves relative paths, symbolic links etc. and get complete path
@@ -31,3 +32,11 @@ fn load_container<P: AsRef<Path>>(root_path: P, container_id: &str) -> Result<Co
     Container::load(container_root)
         .with_context(|| format!("could not load state for container {}", container_id))
 }
+
+/// Exits the process with the exit code. The destructors in main are not run
+/// on exit, so the remaining spans are exported before.
+fn exit(code: i32) -> ! {
+    #[cfg(feature = "otlp")]
+    crate::tracer::shutdown();
+    std::process::exit(code)
+}
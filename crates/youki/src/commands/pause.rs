@@ -3,6 +3,7 @@ use crate::commands::load_container;
 use std::path::PathBuf;
 
 use anyhow::{Context, Result};
+use libcgroups::common::DEFAULT_FREEZER_TIMEOUT;
 
 use liboci_cli::Pause;
 
@@ -15,6 +16,6 @@ pub fn pause(args: Pause, root_path: PathBuf) -> Result<()> {
     log::debug!("start pausing container {}", args.container_id);
     let mut container = load_container(root_path, &args.container_id)?;
     container
-        .pause()
+        .pause_with_timeout(args.timeout.unwrap_or(DEFAULT_FREEZER_TIMEOUT))
         .with_context(|| format!("failed to pause container {}", args.container_id))
 }
@@ -1,6 +1,6 @@
 use anyhow::{bail, Context, Result};
 use libcgroups;
-use libcontainer::{container::Container, utils};
+use libcontainer::container::Container;
 use liboci_cli::Ps;
 use std::{path::PathBuf, process::Command};
 
@@ -11,17 +11,7 @@ pub fn ps(args: Ps, root_path: PathBuf) -> Result<()> {
     }
     let container = Container::load(container_root)?;
     if container.root.exists() {
-        let config_absolute_path = container.root.join("config.json");
-        log::debug!("load spec from {:?}", config_absolute_path);
-        let spec = oci_spec::runtime::Spec::load(config_absolute_path)?;
-        log::debug!("spec: {:?}", spec);
-        let cgroups_path = utils::get_cgroup_path(
-            spec.linux()
-                .as_ref()
-                .context("no linux in spec")?
-                .cgroups_path(),
-            container.id(),
-        );
+        let cgroups_path = container.cgroup_path()?;
         let systemd_cgroup = container
             .systemd()
             .context("could not determine cgroup manager")?;
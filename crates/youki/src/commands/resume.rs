@@ -2,6 +2,7 @@
 use std::path::PathBuf;
 
 use anyhow::{Context, Result};
+use libcgroups::common::DEFAULT_FREEZER_TIMEOUT;
 
 use crate::commands::load_container;
 
@@ -16,6 +17,6 @@ pub fn resume(args: Resume, root_path: PathBuf) -> Result<()> {
     log::debug!("start resuming container {}", args.container_id);
     let mut container = load_container(root_path, &args.container_id)?;
     container
-        .resume()
+        .resume_with_timeout(args.timeout.unwrap_or(DEFAULT_FREEZER_TIMEOUT))
         .with_context(|| format!("failed to resume container {}", args.container_id))
 }
@@ -1,21 +1,104 @@
-use std::path::PathBuf;
+use std::{
+    env,
+    path::{Path, PathBuf},
+    process,
+};
 
 use anyhow::{Context, Result};
-use libcontainer::{container::builder::ContainerBuilder, syscall::syscall::create_syscall};
+use libcontainer::{
+    auto_userns::AutoUserns,
+    container::{builder::ContainerBuilder, set_child_subreaper, SuperviseOptions},
+    rootless::RootlessMode,
+    spec_extensions,
+    syscall::syscall::create_syscall,
+    tty_proxy::ConsoleSocket,
+};
 use liboci_cli::Run;
+use nix::unistd::close;
+
+pub fn run(
+    args: Run,
+    root_path: PathBuf,
+    systemd_cgroup: bool,
+    rootless_mode: RootlessMode,
+) -> Result<()> {
+    // in the foreground the init process of the container is re-parented to
+    // youki, so that it can wait for it
+    if !args.detach {
+        set_child_subreaper()?;
+    }
+
+    // without a console socket of the caller, youki relays between its own
+    // stdio and the terminal of the container while it is in the foreground
+    let console_socket =
+        if !args.detach && args.console_socket.is_none() && uses_terminal(&args.bundle)? {
+            let path = env::temp_dir().join(format!("youki-console-{}.sock", process::id()));
+            Some(ConsoleSocket::new(path)?)
+        } else {
+            None
+        };
+    let console_socket_path = args.console_socket.clone().or_else(|| {
+        console_socket
+            .as_ref()
+            .map(|socket| socket.path().to_path_buf())
+    });
 
-pub fn run(args: Run, root_path: PathBuf, systemd_cgroup: bool) -> Result<()> {
     let syscall = create_syscall();
     let mut container = ContainerBuilder::new(args.container_id.clone(), syscall.as_ref())
         .with_pid_file(args.pid_file.as_ref())
-        .with_console_socket(args.console_socket.as_ref())
+        .with_console_socket(console_socket_path.as_ref())
         .with_root_path(root_path)
+        .with_rootless_mode(rootless_mode)
         .with_preserved_fds(args.preserve_fds)
+        .with_create_cwd(!args.no_create_cwd)
+        .with_detach(args.detach)
         .as_init(&args.bundle)
         .with_systemd(systemd_cgroup)
+        .with_strict_resources(args.strict_resources)
+        .with_namespaces_from(args.ns_from.clone())
+        .with_no_new_keyring(args.no_new_keyring)
+        .with_no_pivot(args.no_pivot)
+        .with_exit_monitor(args.detach)
+        .with_auto_userns(args.auto_userns.then(|| AutoUserns {
+            size: args.auto_userns_size,
+            ..Default::default()
+        }))
         .build()?;
+    let console = match console_socket {
+        Some(socket) => Some(socket.receive_master()?),
+        None => None,
+    };
 
     container
         .start()
-        .with_context(|| format!("failed to start container {}", args.container_id))
+        .with_context(|| format!("failed to start container {}", args.container_id))?;
+    if args.detach {
+        return Ok(());
+    }
+
+    let options = SuperviseOptions::default()
+        .with_console(console)
+        .with_forward_all_signals(args.forward_all_signals)
+        .with_signal_all(args.signal_all);
+    let exit_code = container
+        .supervise_with(options)
+        .with_context(|| format!("failed to wait for container {}", args.container_id))?;
+    if let Some(console) = console {
+        close(console)?;
+    }
+    if exit_code != 0 {
+        super::exit(exit_code);
+    }
+
+    Ok(())
+}
+
+fn uses_terminal(bundle: &Path) -> Result<bool> {
+    let (spec, _) =
+        spec_extensions::load_spec(bundle.join("config.json")).context("failed to load spec")?;
+    Ok(spec
+        .process()
+        .as_ref()
+        .and_then(|process| process.terminal())
+        .unwrap_or(false))
 }
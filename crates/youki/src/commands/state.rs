@@ -11,5 +11,5 @@ pub fn state(args: State, root_path: PathBuf) -> Result<()> {
     let container_root = root_path.join(&args.container_id);
     let container = Container::load(container_root)?;
     println!("{}", serde_json::to_string_pretty(&container.state)?);
-    std::process::exit(0);
+    super::exit(0);
 }
@@ -0,0 +1,222 @@
+//! Contains functionality of update container command
+use std::{
+    fs::File,
+    io::{self, Read},
+    path::{Path, PathBuf},
+};
+
+use anyhow::{bail, Context, Result};
+use serde_json::{json, Map, Value};
+
+use crate::commands::load_container;
+use liboci_cli::Update;
+
+pub fn update(args: Update, root_path: PathBuf) -> Result<()> {
+    log::debug!("start updating container {}", args.container_id);
+    let mut container = load_container(root_path, &args.container_id)?;
+
+    let resources = match &args.resources {
+        Some(path) => read_resources(path)?,
+        None => resources_from_flags(&args)?,
+    };
+
+    // resources are deserialized into the type expected by libcontainer
+    container
+        .update_resources(&serde_json::from_value(resources).context("failed to parse resources")?)
+        .with_context(|| format!("failed to update container {}", args.container_id))
+}
+
+fn read_resources(path: &Path) -> Result<Value> {
+    let mut content = String::new();
+    if path == Path::new("-") {
+        io::stdin()
+            .read_to_string(&mut content)
+            .context("failed to read resources from stdin")?;
+    } else {
+        File::open(path)
+            .and_then(|mut f| f.read_to_string(&mut content))
+            .with_context(|| format!("failed to read resources from {:?}", path))?;
+    }
+
+    serde_json::from_str(&content).context("resources are not valid json")
+}
+
+/// Converts the resource flags into the representation of the OCI runtime-spec
+fn resources_from_flags(args: &Update) -> Result<Value> {
+    let mut resources = Map::new();
+
+    let mut cpu = Map::new();
+    insert(&mut cpu, "shares", args.cpu_share);
+    insert(&mut cpu, "quota", args.cpu_quota);
+    insert(&mut cpu, "period", args.cpu_period);
+    insert(&mut cpu, "realtimeRuntime", args.cpu_rt_runtime);
+    insert(&mut cpu, "realtimePeriod", args.cpu_rt_period);
+    insert(&mut cpu, "cpus", args.cpuset_cpus.clone());
+    insert(&mut cpu, "mems", args.cpuset_mems.clone());
+    if !cpu.is_empty() {
+        resources.insert("cpu".to_owned(), Value::Object(cpu));
+    }
+
+    let mut memory = Map::new();
+    insert(&mut memory, "limit", args.memory);
+    insert(&mut memory, "reservation", args.memory_reservation);
+    insert(&mut memory, "swap", args.memory_swap);
+    if !memory.is_empty() {
+        resources.insert("memory".to_owned(), Value::Object(memory));
+    }
+
+    // the runtime spec has no field for the burst yet, libcgroups takes it
+    // from the unified resources on cgroup v1 and v2
+    if let Some(burst) = args.cpu_burst {
+        resources.insert(
+            "unified".to_owned(),
+            json!({ "cpu.max.burst": burst.to_string() }),
+        );
+    }
+
+    if let Some(limit) = args.pids_limit {
+        resources.insert("pids".to_owned(), json!({ "limit": limit }));
+    }
+
+    if let Some(weight) = args.blkio_weight {
+        resources.insert("blockIO".to_owned(), json!({ "weight": weight }));
+    }
+
+    let devices = args
+        .device_allow
+        .iter()
+        .map(|rule| parse_device_rule(rule, true))
+        .chain(
+            args.device_deny
+                .iter()
+                .map(|rule| parse_device_rule(rule, false)),
+        )
+        .collect::<Result<Vec<Value>>>()?;
+    if !devices.is_empty() {
+        resources.insert("devices".to_owned(), Value::Array(devices));
+    }
+
+    Ok(Value::Object(resources))
+}
+
+/// Parses a device rule in the format of cgroup v1, i.e. 'type major:minor
+/// access', in which major and minor may be '*' to match all numbers
+fn parse_device_rule(rule: &str, allow: bool) -> Result<Value> {
+    let mut device = Map::new();
+    device.insert("allow".to_owned(), allow.into());
+
+    let mut parts = rule.split_whitespace();
+    match parts.next() {
+        Some(typ @ ("a" | "b" | "c")) => device.insert("type".to_owned(), typ.into()),
+        _ => bail!("invalid device type in rule {:?}", rule),
+    };
+
+    if let Some(numbers) = parts.next() {
+        let (major, minor) = numbers
+            .split_once(':')
+            .with_context(|| format!("invalid device numbers in rule {:?}", rule))?;
+        for (key, number) in [("major", major), ("minor", minor)] {
+            if number != "*" {
+                let number: i64 = number
+                    .parse()
+                    .with_context(|| format!("invalid device number in rule {:?}", rule))?;
+                device.insert(key.to_owned(), number.into());
+            }
+        }
+    }
+
+    let access = parts.next().unwrap_or("rwm");
+    if access.is_empty() || !access.chars().all(|c| matches!(c, 'r' | 'w' | 'm')) {
+        bail!("invalid device access in rule {:?}", rule);
+    }
+    device.insert("access".to_owned(), access.into());
+
+    if parts.next().is_some() {
+        bail!("invalid device rule {:?}", rule);
+    }
+
+    Ok(Value::Object(device))
+}
+
+fn insert<T: Into<Value>>(map: &mut Map<String, Value>, key: &str, value: Option<T>) {
+    if let Some(value) = value {
+        map.insert(key.to_owned(), value.into());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::Parser;
+
+    #[test]
+    fn test_resources_from_flags() -> Result<()> {
+        let args = Update::parse_from([
+            "update",
+            "--cpu-quota",
+            "50000",
+            "--cpu-burst",
+            "10000",
+            "--cpuset-cpus",
+            "0-1",
+            "--memory-swap",
+            "-1",
+            "--pids-limit",
+            "100",
+            "container",
+        ]);
+
+        assert_eq!(
+            resources_from_flags(&args)?,
+            json!({
+                "cpu": { "quota": 50000, "cpus": "0-1" },
+                "memory": { "swap": -1 },
+                "pids": { "limit": 100 },
+                "unified": { "cpu.max.burst": "10000" },
+            })
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_device_rules_from_flags() -> Result<()> {
+        let args = Update::parse_from([
+            "update",
+            "--device-allow",
+            "c 10:200 rwm",
+            "--device-allow",
+            "b *:* m",
+            "--device-deny",
+            "c 1:3",
+            "container",
+        ]);
+
+        assert_eq!(
+            resources_from_flags(&args)?,
+            json!({
+                "devices": [
+                    { "allow": true, "type": "c", "major": 10, "minor": 200, "access": "rwm" },
+                    { "allow": true, "type": "b", "access": "m" },
+                    { "allow": false, "type": "c", "major": 1, "minor": 3, "access": "rwm" },
+                ],
+            })
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_invalid_device_rule() {
+        assert!(parse_device_rule("x 1:3 rwm", true).is_err());
+        assert!(parse_device_rule("c 1 rwm", true).is_err());
+        assert!(parse_device_rule("c 1:x rwm", true).is_err());
+        assert!(parse_device_rule("c 1:3 rwx", true).is_err());
+        assert!(parse_device_rule("c 1:3 rwm extra", true).is_err());
+    }
+
+    #[test]
+    fn test_no_flags() -> Result<()> {
+        let args = Update::parse_from(["update", "container"]);
+        assert_eq!(resources_from_flags(&args)?, json!({}));
+        Ok(())
+    }
+}
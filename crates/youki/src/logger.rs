@@ -29,25 +29,29 @@ const DEFAULT_LOG_LEVEL: &str = "warn";
 /// Initialize the logger, must be called before accessing the logger
 /// Multiple parts might call this at once, but the actual initialization
 /// is done only once due to use of OnceCell
+/// The log file is shared by all invocations of youki for a container, e.g.
+/// by a high-level runtime, so logs are appended to it
 pub fn init(
     log_debug_flag: bool,
     log_file: Option<PathBuf>,
     log_format: Option<String>,
+    container_id: Option<String>,
 ) -> Result<()> {
     let level = detect_log_level(log_debug_flag).context("failed to parse log level")?;
     let format = detect_log_format(log_format).context("failed to detect log format")?;
-    let _ = LOG_FILE.get_or_init(|| -> Option<File> {
-        log_file.map(|path| {
-            OpenOptions::new()
-                .create(true)
-                .write(true)
-                .truncate(false)
-                .open(path)
-                .expect("failed opening log file")
-        })
-    });
+    let _ = LOG_FILE.get_or_try_init(|| -> Result<Option<File>> {
+        log_file
+            .map(|path| {
+                OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(&path)
+                    .with_context(|| format!("failed to open log file {:?}", path))
+            })
+            .transpose()
+    })?;
 
-    let logger = YoukiLogger::new(level.to_level(), format);
+    let logger = YoukiLogger::new(level.to_level(), format, container_id);
     log::set_boxed_logger(Box::new(logger))
         .map(|()| log::set_max_level(level))
         .expect("set logger failed");
@@ -78,12 +82,18 @@ struct YoukiLogger {
     /// Indicates level up to which logs are to be printed
     level: Option<log::Level>,
     format: LogFormat,
+    /// Id of the container, which is added to json records
+    container_id: Option<String>,
 }
 
 impl YoukiLogger {
     /// Create new logger
-    pub fn new(level: Option<log::Level>, format: LogFormat) -> Self {
-        Self { level, format }
+    pub fn new(level: Option<log::Level>, format: LogFormat, container_id: Option<String>) -> Self {
+        Self {
+            level,
+            format,
+            container_id,
+        }
     }
 }
 
@@ -103,7 +113,7 @@ impl Log for YoukiLogger {
         if self.enabled(record.metadata()) {
             let log_msg = match self.format {
                 LogFormat::Text => text_format(record),
-                LogFormat::Json => json_format(record),
+                LogFormat::Json => json_format(record, self.container_id.as_deref()),
             };
             // if log file is set, write to it, else write to stderr
             if let Some(mut log_file) = LOG_FILE.get().unwrap().as_ref() {
@@ -124,13 +134,19 @@ impl Log for YoukiLogger {
     }
 }
 
-fn json_format(record: &log::Record) -> String {
-    serde_json::to_string(&serde_json::json!({
-        "level": record.level().to_string(),
+/// Formats a record like the json logs of runc, which are parsed by high-level
+/// runtimes like containerd
+fn json_format(record: &log::Record, container_id: Option<&str>) -> String {
+    let mut log_msg = serde_json::json!({
+        "level": record.level().as_str().to_lowercase(),
+        "msg": record.args().to_string(),
         "time": chrono::Local::now().to_rfc3339(),
-        "message": record.args(),
-    }))
-    .expect("serde::to_string with string keys will not fail")
+    });
+    if let Some(container_id) = container_id {
+        log_msg["id"] = container_id.into();
+    }
+
+    serde_json::to_string(&log_msg).expect("serde::to_string with string keys will not fail")
 }
 
 fn text_format(record: &log::Record) -> String {
@@ -214,7 +230,7 @@ mod tests {
         let temp_dir = create_temp_dir("logfile").expect("failed to create tempdir for logfile");
         let log_file = Path::join(temp_dir.path(), "test.log");
 
-        init(true, Some(log_file.to_owned()), None).expect("failed to initialize logger");
+        init(true, Some(log_file.to_owned()), None, None).expect("failed to initialize logger");
         assert!(
             log_file
                 .as_path()
@@ -237,4 +253,22 @@ mod tests {
             "some log should be written into the logfile"
         );
     }
+
+    #[test]
+    fn test_json_format() -> Result<()> {
+        let record = Record::builder()
+            .level(log::Level::Warn)
+            .args(format_args!("hello {}", "world"))
+            .build();
+
+        let log_msg: serde_json::Value = serde_json::from_str(&json_format(&record, Some("test")))?;
+        assert_eq!(log_msg["level"], "warn");
+        assert_eq!(log_msg["msg"], "hello world");
+        assert_eq!(log_msg["id"], "test");
+        assert!(log_msg["time"].is_string());
+
+        let log_msg: serde_json::Value = serde_json::from_str(&json_format(&record, None))?;
+        assert!(log_msg.get("id").is_none());
+        Ok(())
+    }
 }
@@ -3,6 +3,8 @@
 //! This crate provides a container runtime which can be used by a high-level container runtime to run containers.
 mod commands;
 mod logger;
+#[cfg(feature = "otlp")]
+mod tracer;
 
 use std::fs;
 use std::path::{Path, PathBuf};
@@ -13,13 +15,13 @@ use anyhow::Result;
 use clap::{crate_version, Parser};
 
 use crate::commands::info;
-use libcontainer::rootless::rootless_required;
+use libcontainer::rootless::RootlessMode;
 use libcontainer::utils;
 use libcontainer::utils::create_dir_all_with_mode;
 use nix::sys::stat::Mode;
 use nix::unistd::getuid;
 
-use liboci_cli::{CommonCmd, GlobalOpts, StandardCmd};
+use liboci_cli::{CommonCmd, GlobalOpts, Rootless, StandardCmd};
 
 // High-level commandline option definition
 // This takes global options as well as individual commands as specified in [OCI runtime-spec](https://github.com/opencontainers/runtime-spec/blob/master/runtime.md)
@@ -65,23 +67,42 @@ fn main() -> Result<()> {
 
     let opts = Opts::parse();
 
-    if let Err(e) = crate::logger::init(opts.global.debug, opts.global.log, opts.global.log_format)
-    {
+    let container_id = match &opts.subcmd {
+        SubCommand::Standard(cmd) => Some(cmd.container_id()),
+        SubCommand::Common(cmd) => cmd.container_id(),
+        SubCommand::Info(_) => None,
+    };
+    if let Err(e) = crate::logger::init(
+        opts.global.debug,
+        opts.global.log,
+        opts.global.log_format,
+        container_id.map(str::to_owned),
+    ) {
         eprintln!("log init failed: {:?}", e);
     }
+    #[cfg(feature = "otlp")]
+    let _tracer = crate::tracer::init().unwrap_or_else(|e| {
+        log::warn!("tracing init failed: {:?}", e);
+        None
+    });
 
     log::debug!(
         "started by user {} with {:?}",
         nix::unistd::geteuid(),
         std::env::args_os()
     );
-    let root_path = determine_root_path(opts.global.root)?;
+    let rootless_mode = match opts.global.rootless {
+        Rootless::True => RootlessMode::Enabled,
+        Rootless::False => RootlessMode::Disabled,
+        Rootless::Auto => RootlessMode::Auto,
+    };
+    let root_path = determine_root_path(opts.global.root, rootless_mode)?;
     let systemd_cgroup = opts.global.systemd_cgroup;
 
     match opts.subcmd {
         SubCommand::Standard(cmd) => match cmd {
             StandardCmd::Create(create) => {
-                commands::create::create(create, root_path, systemd_cgroup)
+                commands::create::create(create, root_path, systemd_cgroup, rootless_mode)
             }
             StandardCmd::Start(start) => commands::start::start(start, root_path),
             StandardCmd::Kill(kill) => commands::kill::kill(kill, root_path),
@@ -90,25 +111,28 @@ fn main() -> Result<()> {
         },
         SubCommand::Common(cmd) => match cmd {
             CommonCmd::Events(events) => commands::events::events(events, root_path),
-            CommonCmd::Exec(exec) => commands::exec::exec(exec, root_path),
+            CommonCmd::Exec(exec) => commands::exec::exec(exec, root_path, rootless_mode),
             CommonCmd::List(list) => commands::list::list(list, root_path),
             CommonCmd::Pause(pause) => commands::pause::pause(pause, root_path),
             CommonCmd::Ps(ps) => commands::ps::ps(ps, root_path),
             CommonCmd::Resume(resume) => commands::resume::resume(resume, root_path),
-            CommonCmd::Run(run) => commands::run::run(run, root_path, systemd_cgroup),
+            CommonCmd::Run(run) => {
+                commands::run::run(run, root_path, systemd_cgroup, rootless_mode)
+            }
             CommonCmd::Spec(spec) => commands::spec_json::spec(spec),
+            CommonCmd::Update(update) => commands::update::update(update, root_path),
         },
 
         SubCommand::Info(info) => commands::info::info(info),
     }
 }
 
-fn determine_root_path(root_path: Option<PathBuf>) -> Result<PathBuf> {
+fn determine_root_path(root_path: Option<PathBuf>, rootless_mode: RootlessMode) -> Result<PathBuf> {
     if let Some(path) = root_path {
         return Ok(path);
     }
 
-    if !rootless_required() {
+    if !rootless_mode.is_required() {
         let default = PathBuf::from("/run/youki");
         utils::create_dir_all(&default)?;
         return Ok(default);
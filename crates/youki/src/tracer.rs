@@ -0,0 +1,59 @@
+//! Export of tracing spans to an OTLP collector, which shows where the time
+//! to create, start and exec containers goes. Only built with the `otlp`
+//! feature and only enabled if the endpoint of the collector is set in
+//! YOUKI_OTLP_ENDPOINT, e.g. http://localhost:4318/v1/traces. The exporter
+//! runs in a thread of youki, so spans of the forked container processes are
+//! dropped, they are still written to the log.
+
+use anyhow::{Context, Result};
+use opentelemetry::{
+    sdk::{trace, Resource},
+    KeyValue,
+};
+use opentelemetry_otlp::WithExportConfig;
+use tracing_subscriber::prelude::*;
+
+const OTLP_ENDPOINT_ENV_NAME: &str = "YOUKI_OTLP_ENDPOINT";
+
+/// Exports the remaining spans on drop
+pub struct Tracer;
+
+impl Drop for Tracer {
+    fn drop(&mut self) {
+        shutdown();
+    }
+}
+
+/// Exports the remaining spans and stops the exporter. Has to be called before
+/// the process exits without returning from main.
+pub fn shutdown() {
+    opentelemetry::global::shutdown_tracer_provider();
+}
+
+/// Installs the exporter, if an endpoint is configured
+pub fn init() -> Result<Option<Tracer>> {
+    let endpoint = match std::env::var(OTLP_ENDPOINT_ENV_NAME) {
+        Ok(endpoint) => endpoint,
+        Err(_) => return Ok(None),
+    };
+
+    let tracer = opentelemetry_otlp::new_pipeline()
+        .tracing()
+        .with_exporter(
+            opentelemetry_otlp::new_exporter()
+                .http()
+                .with_endpoint(endpoint),
+        )
+        .with_trace_config(
+            trace::config()
+                .with_resource(Resource::new(vec![KeyValue::new("service.name", "youki")])),
+        )
+        .install_batch(opentelemetry::runtime::TokioCurrentThread)
+        .context("failed to install otlp exporter")?;
+    tracing_subscriber::registry()
+        .with(tracing_opentelemetry::layer().with_tracer(tracer))
+        .try_init()
+        .context("failed to set tracing subscriber")?;
+
+    Ok(Some(Tracer))
+}